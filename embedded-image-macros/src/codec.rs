@@ -0,0 +1,214 @@
+//! The QOI and BMP decoders at the byte level, shared with
+//! `embedded-image-macros`
+//!
+//! A proc-macro can't depend on the crate it expands into, so both crates
+//! have a copy of this file, and a test in the macros crate keeps them
+//! identical. It only uses `core` and `alloc`, and has its own error type.
+use alloc::{vec, vec::Vec};
+use core::ops::ControlFlow;
+
+/// Why a file couldn't be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Error {
+    /// Not the format, or truncated or corrupt
+    InvalidData,
+    /// A part of the format that isn't supported
+    Unsupported,
+}
+
+pub(crate) fn be32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+pub(crate) fn le32(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+pub(crate) fn le16(b: &[u8]) -> u16 {
+    u16::from_le_bytes([b[0], b[1]])
+}
+
+/// Longest run one QOI byte can encode
+pub(crate) const QOI_MAX_RUN: u64 = 62;
+
+/// Index of `px` in QOI's table of recent pixels
+pub(crate) fn qoi_hash(px: [u8; 4]) -> usize {
+    (px[0] as usize * 3 + px[1] as usize * 5 + px[2] as usize * 7 + px[3] as usize * 11) % 64
+}
+
+/// The parts of a QOI header the decoder needs
+pub(crate) struct QoiHeader {
+    pub(crate) res: (u32, u32),
+    pub(crate) channels: u8,
+    /// The linear colorspace flag
+    pub(crate) linear: bool,
+}
+
+/// Parse and check a QOI header
+pub(crate) fn qoi_header(data: &[u8]) -> Result<QoiHeader, Error> {
+    if data.len() < 14 || &data[..4] != b"qoif" {
+        return Err(Error::InvalidData);
+    }
+    let (w, h) = (be32(&data[4..]), be32(&data[8..]));
+    if w == 0 || h == 0 {
+        return Err(Error::InvalidData);
+    }
+    Ok(QoiHeader {
+        res: (w, h),
+        channels: if data[12] == 3 { 3 } else { 4 },
+        linear: data[13] == 1,
+    })
+}
+
+/// Decode a QOI image, calling `on_row` with each straight RGBA 8888 row
+/// and its index, until it returns [`ControlFlow::Break`]
+pub(crate) fn qoi_rows(
+    data: &[u8],
+    mut on_row: impl FnMut(u32, &[[u8; 4]]) -> ControlFlow<()>,
+) -> Result<QoiHeader, Error> {
+    let header = qoi_header(data)?;
+    let (w, h) = header.res;
+    // Even all runs can't fit more than this, don't allocate for garbage
+    if w as u64 * h as u64 > (data.len() - 14) as u64 * QOI_MAX_RUN {
+        return Err(Error::InvalidData);
+    }
+
+    let mut row = vec![[0u8; 4]; w as usize];
+    let mut index = [[0u8; 4]; 64];
+    let mut px = [0, 0, 0, 255u8];
+    let mut run = 0;
+    let mut bytes = data[14..].iter().copied();
+    let mut next = || bytes.next().ok_or(Error::InvalidData);
+
+    for y in 0..h {
+        for out in &mut row {
+            if run > 0 {
+                run -= 1;
+            } else {
+                let b = next()?;
+                match b {
+                    0xfe => px = [next()?, next()?, next()?, px[3]],
+                    0xff => px = [next()?, next()?, next()?, next()?],
+                    _ => match b >> 6 {
+                        0 => px = index[b as usize],
+                        1 => {
+                            let d = |s: u8| ((b >> s) & 3).wrapping_sub(2);
+                            px[0] = px[0].wrapping_add(d(4));
+                            px[1] = px[1].wrapping_add(d(2));
+                            px[2] = px[2].wrapping_add(d(0));
+                        }
+                        2 => {
+                            let dg = (b & 0x3f).wrapping_sub(32);
+                            let b2 = next()?;
+                            px[0] = px[0].wrapping_add(dg.wrapping_add((b2 >> 4).wrapping_sub(8)));
+                            px[1] = px[1].wrapping_add(dg);
+                            px[2] = px[2].wrapping_add(dg.wrapping_add((b2 & 0xf).wrapping_sub(8)));
+                        }
+                        _ => run = b & 0x3f,
+                    },
+                }
+                index[qoi_hash(px)] = px;
+            }
+            *out = px;
+        }
+        if on_row(y, &row).is_break() {
+            break;
+        }
+    }
+    Ok(header)
+}
+
+/// The parts of a BMP header the decoder needs
+pub(crate) struct BmpHeader {
+    pub(crate) res: (u32, u32),
+    /// Start of the pixel array
+    pub(crate) offset: usize,
+    pub(crate) top_down: bool,
+    pub(crate) alpha: bool,
+    /// Bytes per pixel
+    pub(crate) bytes: usize,
+}
+
+/// Parse and check a BMP header, without looking at the pixels
+pub(crate) fn bmp_header(data: &[u8]) -> Result<BmpHeader, Error> {
+    if data.len() < 54 || &data[..2] != b"BM" {
+        return Err(Error::InvalidData);
+    }
+    let offset = le32(&data[10..]) as usize;
+    let header = le32(&data[14..]) as usize;
+    let w = le32(&data[18..]) as i32;
+    let h = le32(&data[22..]) as i32;
+    let bpp = le16(&data[28..]);
+    let compression = le32(&data[30..]);
+    // BI_RGB, or BI_BITFIELDS with the usual BGRA masks
+    let alpha = match (bpp, compression) {
+        (24, 0) | (32, 0) => false,
+        (32, 3) if header >= 56 && data.len() >= 70 => {
+            let masks = &data[54..70];
+            if [le32(masks), le32(&masks[4..]), le32(&masks[8..])] != [0xff0000, 0xff00, 0xff] {
+                return Err(Error::Unsupported);
+            }
+            le32(&masks[12..]) == 0xff000000
+        }
+        _ => return Err(Error::Unsupported),
+    };
+    if w <= 0 || h == 0 {
+        return Err(Error::InvalidData);
+    }
+    Ok(BmpHeader {
+        res: (w as u32, h.unsigned_abs()),
+        offset,
+        top_down: h < 0,
+        alpha,
+        bytes: bpp as usize / 8,
+    })
+}
+
+/// Decode an uncompressed 24 or 32 bit BMP, calling `on_row` with each
+/// straight RGBA 8888 row and its index, top to bottom, until it returns
+/// [`ControlFlow::Break`]
+///
+/// If `salvage`, rows past the end of `data` are skipped, and only then is
+/// it an error.
+pub(crate) fn bmp_rows(
+    data: &[u8],
+    salvage: bool,
+    mut on_row: impl FnMut(u32, &[[u8; 4]]) -> ControlFlow<()>,
+) -> Result<BmpHeader, Error> {
+    let header = bmp_header(data)?;
+    let (w, h) = (header.res.0 as usize, header.res.1 as usize);
+    let row_bytes = w.checked_mul(header.bytes).ok_or(Error::InvalidData)?;
+    let stride = row_bytes.div_ceil(4).saturating_mul(4);
+    let offset = header.offset;
+    if !salvage && data.len() < offset.saturating_add(stride.saturating_mul(h)) {
+        return Err(Error::InvalidData);
+    }
+    // Not even one row is there, don't allocate for garbage
+    if data.len() < offset.saturating_add(row_bytes) {
+        return Err(Error::InvalidData);
+    }
+
+    let mut row: Vec<[u8; 4]> = vec![[0u8; 4]; w];
+    let mut missing = false;
+    for y in 0..h {
+        let src = if header.top_down { y } else { h - 1 - y };
+        let Some(src) = src
+            .checked_mul(stride)
+            .and_then(|s| data.get(s.checked_add(offset)?..))
+            .and_then(|d| d.get(..row_bytes))
+        else {
+            missing = true;
+            continue;
+        };
+        for (o, p) in row.iter_mut().zip(src.chunks_exact(header.bytes)) {
+            *o = [p[2], p[1], p[0], if header.alpha { p[3] } else { 255 }];
+        }
+        if on_row(y as u32, &row).is_break() {
+            break;
+        }
+    }
+    if missing {
+        return Err(Error::InvalidData);
+    }
+    Ok(header)
+}
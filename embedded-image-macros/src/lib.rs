@@ -0,0 +1,192 @@
+//! Compile time image embedding for `embedded-image`
+//!
+//! The decoders are a copy of the ones in `embedded-image`, since a
+//! proc-macro can't depend on the crate it expands into.
+extern crate alloc;
+
+use std::{fmt::Write, ops::ControlFlow, path::PathBuf};
+
+use proc_macro::{Literal, TokenStream, TokenTree};
+
+// Only the decoders are used here
+#[allow(dead_code)]
+mod codec;
+
+/// Straight RGBA 8888 pixels, resolution, and whether they're linear
+struct Decoded {
+    data: Vec<u8>,
+    res: (u32, u32),
+    linear: bool,
+}
+
+/// Decode `data` with the `embedded-image` decoder for the extension `ext`
+fn decode(data: &[u8], ext: Option<&str>) -> Result<Decoded, String> {
+    let mut out = Vec::new();
+    let on_row = |_, row: &[[u8; 4]]| {
+        out.extend(row.iter().flatten());
+        ControlFlow::Continue(())
+    };
+    let (name, header) = match ext {
+        Some("qoi") => (
+            "QOI",
+            codec::qoi_rows(data, on_row).map(|h| (h.res, h.linear)),
+        ),
+        Some("bmp") => (
+            "BMP",
+            codec::bmp_rows(data, false, on_row).map(|h| (h.res, false)),
+        ),
+        _ => return Err("unsupported format, expected .qoi or .bmp".into()),
+    };
+    let (res, linear) = header.map_err(|e| match e {
+        codec::Error::InvalidData => format!("not a valid {name} image, or truncated"),
+        codec::Error::Unsupported => format!("unsupported kind of {name} image"),
+    })?;
+    Ok(Decoded {
+        data: out,
+        res,
+        linear,
+    })
+}
+
+/// Parse a single string literal, without escapes
+fn parse_path(input: TokenStream) -> Result<String, String> {
+    let mut tokens = input.into_iter();
+    let lit = match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Literal(l)), None) => l,
+        _ => return Err("expected a single string literal path".into()),
+    };
+    let s = lit.to_string();
+    let s = s.strip_prefix('r').map_or(&*s, |s| s.trim_matches('#'));
+    match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(s) if !s.contains('\\') => Ok(s.into()),
+        _ => Err("expected a plain string literal path".into()),
+    }
+}
+
+/// The expansion of `include_image!` for `path`, relative to the crate
+/// being built
+fn expand(path: &str) -> Result<String, String> {
+    let full = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default()).join(path);
+    let data = std::fs::read(&full).map_err(|e| format!("couldn't read `{path}`: {e}"))?;
+    let ext = full
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    let img = decode(&data, ext.as_deref()).map_err(|e| format!("`{path}`: {e}"))?;
+
+    let mut bytes = String::with_capacity(img.data.len() * 4);
+    for b in &img.data {
+        let _ = write!(bytes, "{b},");
+    }
+    let color = if img.linear { "sRGBLinear" } else { "sRGB" };
+    // `include_bytes!` so cargo rebuilds when the file changes
+    Ok(format!(
+        "{{
+            const _: &[u8] = include_bytes!({file:?});
+            const DATA: [u8; {len}] = [{bytes}];
+            ::embedded_image::StaticImage {{
+                data: &DATA,
+                res: ({w}, {h}),
+                color: ::embedded_image::ColorSpace::{color},
+            }}
+        }}",
+        file = full.to_string_lossy(),
+        len = img.data.len(),
+        w = img.res.0,
+        h = img.res.1,
+    ))
+}
+
+/// Decode a QOI or BMP image at compile time into an
+/// `embedded_image::StaticImage`
+///
+/// The path is relative to the crate's `Cargo.toml`. Errors, like missing
+/// files or unsupported formats, are compile errors.
+///
+/// ```
+/// use embedded_image::{include_image, StaticImage};
+///
+/// const LOGO: StaticImage = include_image!("../assets/corners.qoi");
+/// assert_eq!(LOGO.res, (7, 5));
+/// ```
+///
+/// Missing files, unknown extensions, and anything but one string literal
+/// don't build:
+///
+/// ```compile_fail
+/// # use embedded_image::{include_image, StaticImage};
+/// const LOGO: StaticImage = include_image!("../assets/missing.qoi");
+/// ```
+///
+/// ```compile_fail
+/// # use embedded_image::{include_image, StaticImage};
+/// const LOGO: StaticImage = include_image!("Cargo.toml");
+/// ```
+///
+/// ```compile_fail
+/// # use embedded_image::{include_image, StaticImage};
+/// const LOGO: StaticImage = include_image!("../assets/corners.qoi", 2);
+/// ```
+#[proc_macro]
+pub fn include_image(input: TokenStream) -> TokenStream {
+    let out = parse_path(input)
+        .and_then(|path| expand(&path))
+        .unwrap_or_else(|e| format!("compile_error!({})", Literal::string(&e)));
+    out.parse().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codec_matches_embedded_image() {
+        let ours = include_str!("codec.rs");
+        let theirs = include_str!("../../src/formats/codec.rs");
+        assert!(
+            ours == theirs,
+            "copy src/formats/codec.rs over src/codec.rs"
+        );
+    }
+
+    /// A file in the temporary directory with `data`, by absolute path
+    fn temp(name: &str, data: &[u8]) -> String {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, data).unwrap();
+        path.to_string_lossy().into()
+    }
+
+    #[test]
+    fn expands_assets() {
+        for path in ["../assets/corners.qoi", "../assets/corners.bmp"] {
+            let out = expand(path).unwrap();
+            assert!(out.contains("res: (7, 5)"), "{out}");
+            assert!(out.contains("const DATA: [u8; 140] = [255,0,0,255,"));
+            assert!(out.contains("ColorSpace::sRGB,"));
+        }
+    }
+
+    #[test]
+    fn errors_name_the_file() {
+        let qoi = std::fs::read("../assets/corners.qoi").unwrap();
+        let bmp = std::fs::read("../assets/corners.bmp").unwrap();
+        let mut bad_bmp = bmp.clone();
+        // 16 bits per pixel
+        bad_bmp[28] = 16;
+        let cases = [
+            ("../assets/missing.qoi".into(), "couldn't read"),
+            ("Cargo.toml".into(), "unsupported format"),
+            (temp("eim-cut.qoi", &qoi[..40]), "not a valid QOI image"),
+            (temp("eim-cut.bmp", &bmp[..100]), "not a valid BMP image"),
+            (temp("eim-16.bmp", &bad_bmp), "unsupported kind of BMP"),
+        ];
+        for (path, want) in cases {
+            let e = expand(&path).unwrap_err();
+            assert!(
+                e.starts_with(&format!("`{path}`")) || e.contains(&path),
+                "{e}"
+            );
+            assert!(e.contains(want), "{e}");
+        }
+    }
+}
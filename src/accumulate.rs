@@ -0,0 +1,375 @@
+//! Averaging frames over time
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    composite::{from_linear_premul, to_linear_premul},
+    AlphaMode, CaptureInfo, ColorSpace, Image, ImageError, ResXY, WorkPixel, F32,
+};
+
+/// How [`Accumulator`] combines frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccumulateMode {
+    /// Equal weight average of every frame since the last reset
+    RunningMean,
+
+    /// Each new frame gets weight `alpha`, in `0..=1`, and the history the
+    /// rest
+    ///
+    /// Higher is more responsive but noisier. The first frame is taken as is.
+    ExponentialMovingAverage { alpha: f32 },
+
+    /// Like [`AccumulateMode::ExponentialMovingAverage`], weighting each
+    /// frame by the time since the last one, from [`CaptureInfo`], for
+    /// cameras with uneven frame rates
+    ///
+    /// A frame `dt` after the last gets weight `1 - e^(-dt / time_constant_us)`,
+    /// so history fades the same amount per second however often frames
+    /// come, and frames at an even rate are the same as a fixed `alpha`.
+    /// Frames at or before the last one add nothing.
+    TimedMovingAverage { time_constant_us: f32 },
+}
+
+/// Averages a stream of frames, like for denoising a camera preview
+///
+/// Averaging is done in linear light with premultiplied alpha, so brightness
+/// doesn't drift.
+#[derive(Debug, Clone)]
+pub struct Accumulator {
+    state: Vec<WorkPixel>,
+    res: ResXY,
+    color: ColorSpace,
+    mode: AccumulateMode,
+    count: u32,
+    /// Timestamp of the last frame, for [`AccumulateMode::TimedMovingAverage`]
+    last_us: Option<u64>,
+}
+
+impl Accumulator {
+    pub fn new(res: ResXY, color: ColorSpace, mode: AccumulateMode) -> Self {
+        Self {
+            state: vec![[0.; 4]; res.0 as usize * res.1 as usize],
+            res,
+            color,
+            mode,
+            count: 0,
+            last_us: None,
+        }
+    }
+
+    /// Add `frame` to the average
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::DimensionMismatch`] if `frame` is a different size
+    /// - [`ImageError::ColorSpaceMismatch`] if `frame` is in a different
+    ///   color space
+    /// - [`ImageError::InvalidArgument`] for
+    ///   [`AccumulateMode::TimedMovingAverage`], if `frame` has no
+    ///   [`CaptureInfo`]
+    pub fn add_frame(&mut self, frame: &Image) -> Result<(), ImageError> {
+        if frame.res != self.res {
+            return Err(ImageError::DimensionMismatch);
+        }
+        if frame.color != self.color {
+            return Err(ImageError::ColorSpaceMismatch);
+        }
+        let now = frame.meta.capture.map(|c| c.timestamp_us);
+        if let AccumulateMode::TimedMovingAverage { .. } = self.mode {
+            if now.is_none() {
+                return Err(ImageError::InvalidArgument);
+            }
+        }
+        self.count = self.count.saturating_add(1);
+        let last = self.last_us;
+        // Late frames don't move the clock back
+        self.last_us = now.max(last);
+        let weight = match self.mode {
+            _ if self.count == 1 => 1.,
+            AccumulateMode::RunningMean => 1. / self.count as f32,
+            AccumulateMode::ExponentialMovingAverage { alpha } => alpha.clamp(0., 1.),
+            AccumulateMode::TimedMovingAverage { time_constant_us } => {
+                let dt = match (now, last) {
+                    (Some(now), Some(last)) => now.saturating_sub(last),
+                    _ => 0,
+                };
+                if time_constant_us > 0. {
+                    1. - (-(dt as f32) / time_constant_us).exp()
+                } else {
+                    1.
+                }
+            }
+        };
+        let decode = self.color.transfer().map(|t| t.0);
+        for (s, p) in self.state.iter_mut().zip(&frame.data) {
+            let p = to_linear_premul(*p, decode, frame.alpha);
+            for c in 0..4 {
+                s[c] += (p[c] - s[c]) * weight;
+            }
+        }
+        Ok(())
+    }
+
+    /// The current average, with straight alpha
+    ///
+    /// Transparent black if no frames have been added.
+    pub fn current(&self) -> Image {
+        let encode = self.color.transfer().map(|t| t.1);
+        let data = self.state.iter();
+        let data = data.map(|p| from_linear_premul(*p, encode, AlphaMode::Straight));
+        Image::from_parts(data.collect(), self.res, self.color)
+    }
+
+    /// Number of frames added since the last reset
+    pub fn frames(&self) -> u32 {
+        self.count
+    }
+
+    /// Forget all frames
+    pub fn reset(&mut self) {
+        self.state.fill([0.; 4]);
+        self.count = 0;
+        self.last_us = None;
+    }
+}
+
+impl Image {
+    /// Per channel absolute difference from `other`, for motion detection
+    ///
+    /// This is on the stored color values, and the result is opaque.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::DimensionMismatch`] if the images are different sizes
+    /// - [`ImageError::ColorSpaceMismatch`] if the images have different
+    ///   color spaces
+    pub fn absolute_difference(&self, other: &Image) -> Result<Image, ImageError> {
+        self.check_blend(other)?;
+        let data = self.data.iter().zip(&other.data);
+        let data = data.map(|(a, b)| {
+            [
+                (a[0] - b[0]).abs(),
+                (a[1] - b[1]).abs(),
+                (a[2] - b[2]).abs(),
+                1.,
+            ]
+        });
+        Ok(Image::from_parts(data.collect(), self.res, self.color))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{max_diff, photo, solid},
+        transforms::rgb_to_srgb,
+    };
+
+    fn gray(res: ResXY, v: f32, color: ColorSpace) -> Image {
+        let mut img = solid(res, [v, v, v, 1.]);
+        img.color = color;
+        img
+    }
+
+    #[test]
+    fn identical_frames() {
+        let frame = photo((12, 9));
+        for mode in [
+            AccumulateMode::RunningMean,
+            AccumulateMode::ExponentialMovingAverage { alpha: 0.3 },
+        ] {
+            let mut acc = Accumulator::new((12, 9), ColorSpace::sRGB, mode);
+            for _ in 0..10 {
+                acc.add_frame(&frame).unwrap();
+            }
+            assert_eq!(acc.frames(), 10);
+            let diff = max_diff(acc.current().pixels(), frame.pixels());
+            assert!(diff < 1e-5, "{mode:?} {diff}");
+        }
+    }
+
+    #[test]
+    fn mean_in_linear_light() {
+        let mut acc = Accumulator::new((2, 2), ColorSpace::sRGB, AccumulateMode::RunningMean);
+        for v in [0., 1., 0., 1.] {
+            acc.add_frame(&gray((2, 2), v, ColorSpace::sRGB)).unwrap();
+        }
+        let want = rgb_to_srgb(0.5);
+        for p in acc.current().pixels() {
+            assert!((p[0] - want).abs() < 1e-5 && p[3] == 1.);
+        }
+    }
+
+    #[test]
+    fn ema_converges() {
+        let alpha = 0.25;
+        let mode = AccumulateMode::ExponentialMovingAverage { alpha };
+        let mut acc = Accumulator::new((3, 3), ColorSpace::sRGBLinear, mode);
+        acc.add_frame(&gray((3, 3), 0.2, ColorSpace::sRGBLinear))
+            .unwrap();
+        for k in 1..=12 {
+            acc.add_frame(&gray((3, 3), 1., ColorSpace::sRGBLinear))
+                .unwrap();
+            // What's left of the old scene shrinks by `1 - alpha` a frame
+            let want = 1. - 0.8 * (1. - alpha).powi(k);
+            let got = acc.current().pixels()[4][0];
+            assert!((got - want).abs() < 1e-5, "{k}: {got} {want}");
+        }
+    }
+
+    #[test]
+    fn errors_and_reset() {
+        let mut acc = Accumulator::new((4, 4), ColorSpace::sRGB, AccumulateMode::RunningMean);
+        assert!(acc.current().pixels().iter().all(|p| *p == [0.; 4]));
+        assert_eq!(
+            acc.add_frame(&photo((4, 5))),
+            Err(ImageError::DimensionMismatch)
+        );
+        assert_eq!(
+            acc.add_frame(&gray((4, 4), 0.5, ColorSpace::DisplayP3)),
+            Err(ImageError::ColorSpaceMismatch)
+        );
+        assert_eq!(acc.frames(), 0);
+        acc.add_frame(&photo((4, 4))).unwrap();
+        acc.reset();
+        assert_eq!(acc.frames(), 0);
+        assert!(acc.current().pixels().iter().all(|p| *p == [0.; 4]));
+        // The next frame starts afresh
+        acc.add_frame(&gray((4, 4), 0.5, ColorSpace::sRGB)).unwrap();
+        assert!(
+            max_diff(
+                acc.current().pixels(),
+                gray((4, 4), 0.5, ColorSpace::sRGB).pixels()
+            ) < 1e-5
+        );
+    }
+
+    #[test]
+    fn absolute_difference() {
+        let a = Image::from_parts(vec![[0.2, 0.9, 0.5, 0.3]], (1, 1), ColorSpace::sRGB);
+        let b = Image::from_parts(vec![[0.7, 0.4, 0.5, 1.]], (1, 1), ColorSpace::sRGB);
+        let d = a.absolute_difference(&b).unwrap().pixels()[0];
+        assert!(max_diff(&[d], &[[0.5, 0.5, 0., 1.]]) < 1e-6);
+        assert_eq!(
+            b.absolute_difference(&a).unwrap().pixels(),
+            a.absolute_difference(&b).unwrap().pixels()
+        );
+        assert_eq!(
+            a.absolute_difference(&photo((1, 2))).err(),
+            Some(ImageError::DimensionMismatch)
+        );
+        assert_eq!(
+            a.absolute_difference(&gray((1, 1), 0., ColorSpace::sRGBLinear))
+                .err(),
+            Some(ImageError::ColorSpaceMismatch)
+        );
+    }
+
+    /// `gray` captured at `timestamp_us`
+    fn at(v: f32, timestamp_us: u64) -> Image {
+        let mut img = gray((3, 3), v, ColorSpace::sRGBLinear);
+        img.metadata_mut().capture = Some(CaptureInfo {
+            timestamp_us,
+            exposure_us: 1000,
+            analog_gain: 1.,
+            temperature_c: None,
+        });
+        img
+    }
+
+    fn value(acc: &Accumulator) -> f32 {
+        acc.current().pixels()[0][0]
+    }
+
+    #[test]
+    fn timed_even_rate_is_a_fixed_alpha() {
+        let (tau, dt) = (40_000., 10_000);
+        let alpha = 1. - (-(dt as f32) / tau).exp();
+        let timed = AccumulateMode::TimedMovingAverage {
+            time_constant_us: tau,
+        };
+        let fixed = AccumulateMode::ExponentialMovingAverage { alpha };
+        let mut a = Accumulator::new((3, 3), ColorSpace::sRGBLinear, timed);
+        let mut b = Accumulator::new((3, 3), ColorSpace::sRGBLinear, fixed);
+        for i in 0..20 {
+            let v = if i % 3 == 0 { 0.9 } else { 0.1 };
+            a.add_frame(&at(v, 5_000 + i * dt)).unwrap();
+            b.add_frame(&at(v, 0)).unwrap();
+            assert!((value(&a) - value(&b)).abs() < 1e-5, "{i}");
+        }
+    }
+
+    #[test]
+    fn timed_uneven_rate_fades_by_time() {
+        let tau = 20_000.;
+        let mode = AccumulateMode::TimedMovingAverage {
+            time_constant_us: tau,
+        };
+        let mut acc = Accumulator::new((3, 3), ColorSpace::sRGBLinear, mode);
+        let mut want = 0.;
+        let mut last = 0;
+        for (i, t) in [0, 3_000, 4_000, 30_000, 31_000, 90_000]
+            .into_iter()
+            .enumerate()
+        {
+            let v = (i % 2) as f32;
+            acc.add_frame(&at(v, t)).unwrap();
+            let w = if i == 0 {
+                1.
+            } else {
+                1. - (-((t - last) as f32) / tau).exp()
+            };
+            want += (v - want) * w;
+            last = t;
+            assert!((value(&acc) - want).abs() < 1e-5, "{t}");
+        }
+        // Two frames half as far apart fade history as much as one
+        let steps = |dts: &[u64]| {
+            let mut acc = Accumulator::new((3, 3), ColorSpace::sRGBLinear, mode);
+            acc.add_frame(&at(1., 0)).unwrap();
+            let mut t = 0;
+            for dt in dts {
+                t += dt;
+                acc.add_frame(&at(0., t)).unwrap();
+            }
+            value(&acc)
+        };
+        assert!((steps(&[10_000, 10_000]) - steps(&[20_000])).abs() < 1e-6);
+    }
+
+    #[test]
+    fn timed_frames_out_of_order_add_nothing() {
+        let mode = AccumulateMode::TimedMovingAverage {
+            time_constant_us: 10_000.,
+        };
+        let mut acc = Accumulator::new((3, 3), ColorSpace::sRGBLinear, mode);
+        acc.add_frame(&at(0.5, 50_000)).unwrap();
+        acc.add_frame(&at(1., 50_000)).unwrap();
+        acc.add_frame(&at(1., 20_000)).unwrap();
+        assert_eq!(value(&acc), 0.5);
+        // And timing still runs from the latest
+        let mut ordered = Accumulator::new((3, 3), ColorSpace::sRGBLinear, mode);
+        ordered.add_frame(&at(0.5, 50_000)).unwrap();
+        ordered.add_frame(&at(1., 60_000)).unwrap();
+        acc.add_frame(&at(1., 60_000)).unwrap();
+        assert_eq!(value(&acc), value(&ordered));
+        // Untimed frames can't be weighted
+        assert_eq!(
+            acc.add_frame(&gray((3, 3), 1., ColorSpace::sRGBLinear)),
+            Err(ImageError::InvalidArgument)
+        );
+        assert_eq!(acc.frames(), 4);
+        // Reset forgets the last time, so the next frame is taken as is
+        acc.reset();
+        acc.add_frame(&at(0.25, 0)).unwrap();
+        assert_eq!(value(&acc), 0.25);
+        // No time constant is no history
+        let mode = AccumulateMode::TimedMovingAverage {
+            time_constant_us: 0.,
+        };
+        let mut acc = Accumulator::new((3, 3), ColorSpace::sRGBLinear, mode);
+        acc.add_frame(&at(0.5, 0)).unwrap();
+        acc.add_frame(&at(0.125, 1)).unwrap();
+        assert_eq!(value(&acc), 0.125);
+    }
+}
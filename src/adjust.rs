@@ -0,0 +1,742 @@
+//! Color adjustments
+use alloc::{vec, vec::Vec};
+
+use core::slice::from_mut;
+
+use crate::{
+    alpha_converter, convert_rows, luma::sobel, transforms::*, AlphaMode, ColorSpace, Image,
+    ImageError, F32, XY,
+};
+
+/// Oklab chroma below which colors count as gray and are never recolored,
+/// with a ramp up to twice this
+const ACHROMATIC: f32 = 0.02;
+
+/// Largest gain [`Image::auto_color_correct`] uses, and the inverse is the
+/// smallest
+const MAX_GAIN: f32 = 4.;
+
+/// Linear light above which [`Image::auto_color_correct`] fades gains out,
+/// reaching none at `1`
+const HIGHLIGHT: f32 = 0.95;
+
+/// How [`Image::estimate_illuminant`] guesses the color of the light
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IlluminantEstimator {
+    /// The scene averages to gray, like [`Image::auto_white_balance`]
+    GrayWorld,
+    /// The brightest colors are white, taking the 99th percentile of each
+    /// channel so a few hot pixels don't decide
+    MaxRgb,
+    /// Edges average to gray, from the mean Sobel gradient of each channel.
+    /// Large areas of one color have no edges inside, so this is fooled
+    /// much less than [`IlluminantEstimator::GrayWorld`] by them
+    #[default]
+    GrayEdge,
+}
+
+impl Image {
+    /// White balance the image so that the pixel at `xy` becomes neutral gray
+    ///
+    /// The sample is the average of the 3x3 neighborhood around `xy`,
+    /// to be a bit more forgiving of noise.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::OutOfBounds`] if `xy` is outside the image
+    /// - [`ImageError::InvalidArgument`] if the sample has a black channel,
+    ///   as there's no gain that can fix that
+    pub fn white_balance_from_point(&mut self, xy: XY) -> Result<(), ImageError> {
+        let (x, y) = xy;
+        if x >= self.width() || y >= self.height() {
+            return Err(ImageError::OutOfBounds);
+        }
+        let decode = self.color.transfer().map(|t| t.0);
+
+        let mut sum = [0f32; 3];
+        let mut count = 0.;
+        for y in y.saturating_sub(1)..=(y + 1).min(self.height() - 1) {
+            for x in x.saturating_sub(1)..=(x + 1).min(self.width() - 1) {
+                let p = self.get_pixel((x, y)).unwrap();
+                for c in 0..3 {
+                    sum[c] += decode.map_or(p[c], |f| f(p[c]));
+                }
+                count += 1.;
+            }
+        }
+        let avg = sum.map(|c| c / count);
+        if avg.iter().any(|c| *c <= f32::EPSILON) {
+            return Err(ImageError::InvalidArgument);
+        }
+
+        let gray = luminance(avg);
+        self.white_balance_gains(avg.map(|c| gray / c));
+        Ok(())
+    }
+
+    /// Multiply each color channel by `gains`, in linear light
+    ///
+    /// Results are clamped to `0..=1`, as are negative gains.
+    pub fn white_balance_gains(&mut self, gains: [f32; 3]) {
+        let gains = gains.map(|g| g.max(0.));
+        self.in_linear(|img| {
+            for p in &mut img.data {
+                for c in 0..3 {
+                    p[c] = (p[c] * gains[c]).clamp(0., 1.);
+                }
+            }
+        });
+    }
+
+    /// Gray-world automatic white balance
+    ///
+    /// Assumes the scene averages out to gray, and scales each channel so it
+    /// does, keeping the luminance of the average like
+    /// [`Image::white_balance_from_point`]. Images with a black channel on
+    /// average are left alone.
+    pub fn auto_white_balance(&mut self) {
+        let decode = self.color.transfer().map(|t| t.0);
+        let mut sum = [0f32; 3];
+        for p in &self.data {
+            for c in 0..3 {
+                sum[c] += decode.map_or(p[c], |f| f(p[c]));
+            }
+        }
+        let n = self.data.len() as f32;
+        let avg = sum.map(|c| c / n);
+        let gray = luminance(avg);
+        if avg.iter().any(|c| *c <= f32::EPSILON) {
+            return;
+        }
+        self.white_balance_gains(avg.map(|c| gray / c));
+    }
+
+    /// Shift hue by `dh` degrees, and saturation and lightness by `ds` and
+    /// `dl`, in HSL
+    ///
+    /// HSL is computed on encoded values, since that's what designers mean.
+    /// [`ColorSpace::sRGBLinear`] images are encoded to sRGB for it, anything
+    /// else is used as-is. Hue wraps, saturation and lightness clamp to
+    /// `0..=1`. Alpha is untouched.
+    pub fn adjust_hsl(&mut self, dh: f32, ds: f32, dl: f32) {
+        let linear = self.color == ColorSpace::sRGBLinear;
+        for p in &mut self.data {
+            let mut rgb = [p[0], p[1], p[2]];
+            if linear {
+                rgb = rgb.map(rgb_to_srgb);
+            }
+            let [h, s, l] = rgb_to_hsl(rgb);
+            let hsl = [
+                wrap_hue(h + dh),
+                (s + ds).clamp(0., 1.),
+                (l + dl).clamp(0., 1.),
+            ];
+            let mut rgb = hsl_to_rgb(hsl);
+            if linear {
+                rgb = rgb.map(srgb_to_rgb);
+            }
+            *p = [rgb[0], rgb[1], rgb[2], p[3]];
+        }
+    }
+
+    /// Stretch luminance so the darkest and brightest pixels become black and
+    /// white, ignoring `clip_percent` percent of pixels at each end
+    ///
+    /// This works in linear light, and scales RGB together to keep hue.
+    /// Results are clamped to `0..=1`. Solid images are left alone.
+    ///
+    /// A `clip_percent` of zero uses the exact darkest and brightest pixels.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `clip_percent` is not in `0..50`
+    pub fn auto_contrast(&mut self, clip_percent: f32) -> Result<(), ImageError> {
+        if !(0. ..50.).contains(&clip_percent) {
+            return Err(ImageError::InvalidArgument);
+        }
+        let decode = self.color.transfer().map(|t| t.0);
+        let luma = |p: &[f32; 4]| {
+            let rgb = [p[0], p[1], p[2]];
+            luminance(decode.map_or(rgb, |f| rgb.map(f)))
+        };
+        let (mut lo, mut hi) = (f32::MAX, f32::MIN);
+        for p in &self.data {
+            let y = luma(p);
+            lo = lo.min(y);
+            hi = hi.max(y);
+        }
+        if hi - lo <= f32::EPSILON {
+            return Ok(());
+        }
+
+        if clip_percent > 0. {
+            const BINS: usize = 1024;
+            let bin = |y: f32| (((y - lo) / (hi - lo)) * (BINS - 1) as f32) as usize;
+            let mut hist = vec![0usize; BINS];
+            for p in &self.data {
+                hist[bin(luma(p))] += 1;
+            }
+            let clip = (self.data.len() as f32 * clip_percent / 100.) as usize;
+            // First bin where more than `clip` pixels have been seen
+            fn edge<'a>(bins: impl Iterator<Item = (usize, &'a usize)>, clip: usize) -> usize {
+                let mut seen = 0;
+                for (i, n) in bins {
+                    seen += n;
+                    if seen > clip {
+                        return i;
+                    }
+                }
+                0
+            }
+            let (black, white) = (
+                edge(hist.iter().enumerate(), clip),
+                edge(hist.iter().enumerate().rev(), clip),
+            );
+            let (min, step) = (lo, (hi - lo) / (BINS - 1) as f32);
+            lo = min + step * black as f32;
+            hi = min + step * white as f32;
+            if hi - lo <= f32::EPSILON {
+                return Ok(());
+            }
+        }
+        if lo <= 0. && hi >= 1. {
+            return Ok(());
+        }
+
+        self.in_linear(|img| {
+            for p in &mut img.data {
+                let y = luminance([p[0], p[1], p[2]]);
+                if y <= 0. {
+                    continue;
+                }
+                let scale = ((y - lo) / (hi - lo)).max(0.) / y;
+                for c in &mut p[..3] {
+                    *c = (*c * scale).clamp(0., 1.);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Rotate the hue of colors near `from_hue` by `to_hue - from_hue`, in
+    /// Oklch, keeping their lightness and chroma
+    ///
+    /// Hues are in degrees, and ranges wrap around 360. Colors within
+    /// `tolerance` of `from_hue` are shifted fully, a color at `from_hue`
+    /// lands exactly on `to_hue`, and the shift fades out over the next
+    /// `feather` degrees. Grays and near grays are never touched. Results are
+    /// clamped to `0..=1`, for shifted colors that leave the gamut. Alpha is
+    /// untouched.
+    pub fn replace_hue_range(&mut self, from_hue: f32, tolerance: f32, to_hue: f32, feather: f32) {
+        let offset = (to_hue - from_hue).to_radians();
+        let (tolerance, feather) = (tolerance.max(0.), feather.max(0.));
+        let (color, alpha) = (self.color, self.alpha);
+        for i in 0..self.data.len() {
+            let [l, a, b, _] = self.to_oklab_alpha(self.data[i]);
+            let chroma = (a * a + b * b).sqrt();
+            let hue = libm::atan2f(b, a).to_degrees();
+            // Distance around the circle, `0..=180`
+            let d = wrap_hue(hue - from_hue);
+            let d = d.min(360. - d);
+            let range = if d <= tolerance {
+                1.
+            } else if d < tolerance + feather {
+                1. - (d - tolerance) / feather
+            } else {
+                0.
+            };
+            let weight = range * ((chroma - ACHROMATIC) / ACHROMATIC).clamp(0., 1.);
+            if weight <= 0. {
+                continue;
+            }
+            let (sin, cos) = ((offset * weight).sin(), (offset * weight).cos());
+            let rgb = oklab_to_linear_srgb([l, a * cos - b * sin, a * sin + b * cos]);
+            let p = &mut self.data[i];
+            let mut out = [rgb[0], rgb[1], rgb[2], p[3]];
+            convert_rows(from_mut(&mut out), ColorSpace::sRGBLinear, color);
+            out = out.map(|c| c.clamp(0., 1.));
+            *p = match alpha {
+                AlphaMode::Straight => out,
+                AlphaMode::Premultiplied => premultiply(out),
+            };
+        }
+        self.check();
+    }
+
+    /// Guess the color of the light in the scene with `method`, in linear
+    /// light, scaled to a luminance of 1
+    ///
+    /// This is the estimate [`Image::auto_color_correct`] uses, for
+    /// smoothing across frames and applying it with
+    /// [`Image::white_balance_gains`], as the inverse. Transparent pixels
+    /// are ignored. If there's nothing to go on, like a black image, it's
+    /// `[1., 1., 1.]`.
+    pub fn estimate_illuminant(&self, method: IlluminantEstimator) -> [f32; 3] {
+        let decode = self.color.transfer().map(|t| t.0);
+        let straight = alpha_converter(self.alpha, AlphaMode::Straight);
+        let linear: Vec<[f32; 4]> = self
+            .data
+            .iter()
+            .map(|p| {
+                let p = straight(*p);
+                let rgb = [p[0], p[1], p[2]].map(|c| decode.map_or(c, |f| f(c)));
+                [rgb[0], rgb[1], rgb[2], p[3].clamp(0., 1.)]
+            })
+            .collect();
+
+        let plane = |c: usize| linear.iter().map(|p| p[c]).collect::<Vec<f32>>();
+        let weight: f32 = linear.iter().map(|p| p[3]).sum();
+        let e = match method {
+            IlluminantEstimator::GrayWorld => {
+                [0, 1, 2].map(|c| linear.iter().map(|p| p[c] * p[3]).sum::<f32>() / weight)
+            }
+            IlluminantEstimator::MaxRgb => [0, 1, 2].map(|c| {
+                const BINS: usize = 1024;
+                let mut hist = vec![0f32; BINS];
+                for p in &linear {
+                    hist[(p[c].clamp(0., 1.) * (BINS - 1) as f32) as usize] += p[3];
+                }
+                // Highest bin with at least 1% of the weight above it
+                let mut above = 0.;
+                let bin = (0..BINS).rev().find(|i| {
+                    above += hist[*i];
+                    above >= weight * 0.01
+                });
+                bin.unwrap_or(0) as f32 / (BINS - 1) as f32
+            }),
+            IlluminantEstimator::GrayEdge => [0, 1, 2].map(|c| {
+                let edges = sobel(&plane(c), self.res);
+                edges
+                    .iter()
+                    .zip(&linear)
+                    .map(|(e, p)| e * p[3])
+                    .sum::<f32>()
+                    / weight
+            }),
+        };
+        // Also catches NaN, from no weight at all
+        if !e.iter().all(|c| *c > f32::EPSILON) {
+            return [1.; 3];
+        }
+        let y = luminance(e);
+        e.map(|c| c / y)
+    }
+
+    /// Remove a color cast, with gains from [`Image::estimate_illuminant`]
+    ///
+    /// Gains are applied in linear light, clamped to `0.25..=4`, and fade
+    /// out for pixels with a channel near clipping, so highlights that were
+    /// already white stay white. Results are clamped to `0..=1`.
+    pub fn auto_color_correct(&mut self, method: IlluminantEstimator) {
+        let e = self.estimate_illuminant(method);
+        let gains = e.map(|c| (1. / c).clamp(1. / MAX_GAIN, MAX_GAIN));
+        self.in_straight(|img| {
+            img.in_linear(|img| {
+                for p in &mut img.data {
+                    let peak = p[0].max(p[1]).max(p[2]);
+                    let fade = ((peak - HIGHLIGHT) / (1. - HIGHLIGHT)).clamp(0., 1.);
+                    for (c, g) in p.iter_mut().zip(gains) {
+                        *c = (*c * (g + (1. - g) * fade)).clamp(0., 1.);
+                    }
+                }
+            })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{max_diff, photo, solid};
+
+    /// Linear light color of `p` in `img`
+    fn linear(img: &Image, p: crate::WorkPixel) -> [f32; 3] {
+        let decode = img.color.transfer_function();
+        [p[0], p[1], p[2]].map(|c| decode.decode(c))
+    }
+
+    fn is_neutral(rgb: [f32; 3]) -> bool {
+        (rgb[0] - rgb[1]).abs() < 1e-4 && (rgb[1] - rgb[2]).abs() < 1e-4
+    }
+
+    #[test]
+    fn white_balance_neutral_is_noop() {
+        let mut img = photo((9, 9));
+        img.map_pixels_indexed(|(x, y), p| match x < 3 && y < 3 {
+            true => [0.6, 0.6, 0.6, 1.],
+            false => p,
+        });
+        let before = img.clone();
+        img.white_balance_from_point((1, 1)).unwrap();
+        assert!(max_diff(img.pixels(), before.pixels()) < 1e-5);
+    }
+
+    #[test]
+    fn white_balance_removes_tint() {
+        let mut img = solid((4, 4), [0.4, 0.45, 0.8, 1.]);
+        img.white_balance_from_point((3, 0)).unwrap();
+        assert!(img.pixels().iter().all(|p| is_neutral(linear(&img, *p))));
+
+        let mut img = solid((4, 4), [0.4, 0.45, 0.8, 1.]);
+        img.auto_white_balance();
+        assert!(img.pixels().iter().all(|p| is_neutral(linear(&img, *p))));
+    }
+
+    #[test]
+    fn gray_card_gains_agree() {
+        let card = solid((4, 4), [0.5, 0.4, 0.25, 1.]);
+        let mut point = card.clone();
+        point.white_balance_from_point((1, 1)).unwrap();
+        let mut auto = card.clone();
+        auto.auto_white_balance();
+        let mut corrected = card.clone();
+        corrected.auto_color_correct(IlluminantEstimator::GrayWorld);
+        assert!(max_diff(point.pixels(), auto.pixels()) < 1e-6);
+        assert!(max_diff(point.pixels(), corrected.pixels()) < 1e-5);
+
+        let p = point.pixels()[0];
+        assert!(is_neutral(linear(&point, p)));
+        let before = luminance(linear(&card, card.pixels()[0]));
+        assert!((luminance(linear(&point, p)) - before).abs() < 1e-5);
+    }
+
+    #[test]
+    fn white_balance_gains_clamp() {
+        let mut img = solid((2, 2), [0.5, 0.5, 0.5, 0.7]);
+        img.white_balance_gains([100., -3., 1.]);
+        for p in img.pixels() {
+            // Clamped in linear light, then encoded again
+            assert!((p[0] - 1.).abs() < 1e-6);
+            assert_eq!(p[1], 0.);
+            assert!((p[2] - 0.5).abs() < 1e-5);
+            assert_eq!(p[3], 0.7);
+        }
+    }
+
+    #[test]
+    fn white_balance_errors() {
+        let mut img = solid((4, 4), [0., 0.5, 0.5, 1.]);
+        assert_eq!(
+            img.white_balance_from_point((4, 0)),
+            Err(ImageError::OutOfBounds)
+        );
+        assert_eq!(
+            img.white_balance_from_point((0, 0)),
+            Err(ImageError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn adjust_hsl_components() {
+        let mut img = solid((2, 2), [1., 0., 0., 0.4]);
+        img.adjust_hsl(120., 0., 0.);
+        for p in img.pixels() {
+            assert!(max_diff(&[*p], &[[0., 1., 0., 0.4]]) < 1e-5, "{p:?}");
+        }
+        // Wraps the long way round, back to red
+        img.adjust_hsl(-480., 0., 0.);
+        assert!(max_diff(&img.pixels()[..1], &[[1., 0., 0., 0.4]]) < 1e-5);
+
+        // Lightness and saturation clamp
+        let mut img = solid((1, 1), [0.6, 0.4, 0.4, 1.]);
+        img.adjust_hsl(0., 5., 5.);
+        assert!(max_diff(img.pixels(), &[[1., 1., 1., 1.]]) < 1e-5);
+        let mut img = solid((1, 1), [0.6, 0.4, 0.4, 1.]);
+        img.adjust_hsl(0., -5., 0.);
+        let p = img.pixels()[0];
+        assert!(is_neutral([p[0], p[1], p[2]]) && (p[0] - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn adjust_hsl_encodes_linear() {
+        let mut img = solid((1, 1), [0.2, 0.2, 0.2, 1.]);
+        img.color = ColorSpace::sRGBLinear;
+        img.adjust_hsl(0., 0., 0.1);
+        let l = rgb_to_srgb(0.2) + 0.1;
+        assert!((img.pixels()[0][0] - srgb_to_rgb(l)).abs() < 1e-5);
+    }
+
+    /// Gray, from `lo` to `hi` left to right, encoded
+    fn gray_ramp(res: crate::ResXY, lo: f32, hi: f32) -> Image {
+        let mut img = solid(res, [0.; 4]);
+        let w = (res.0 - 1) as f32;
+        img.map_pixels_indexed(|(x, _), _| {
+            let v = lo + (hi - lo) * x as f32 / w;
+            [v, v, v, 1.]
+        });
+        img
+    }
+
+    #[test]
+    fn auto_contrast_full_range_unchanged() {
+        let mut img = photo((8, 8));
+        img.data[0] = [0., 0., 0., 1.];
+        img.data[1] = [1., 1., 1., 1.];
+        let before = img.clone();
+        img.auto_contrast(0.).unwrap();
+        assert_eq!(img.pixels(), before.pixels());
+    }
+
+    #[test]
+    fn auto_contrast_stretches() {
+        let mut img = gray_ramp((32, 2), 0.3, 0.6);
+        img.auto_contrast(0.).unwrap();
+        let row = img.row(0).unwrap();
+        assert!(row[0][0].abs() < 1e-5, "{:?}", row[0]);
+        assert!((row[31][0] - 1.).abs() < 1e-5, "{:?}", row[31]);
+        assert!(row.windows(2).all(|w| w[1][0] >= w[0][0]));
+        assert!(img.pixels().iter().all(|p| is_neutral([p[0], p[1], p[2]])));
+
+        // A lone outlier is clipped away, and everything else still stretches
+        let mut img = gray_ramp((50, 4), 0.3, 0.6);
+        img.data[0] = [1.; 4];
+        img.auto_contrast(1.).unwrap();
+        assert!(img.row(3).unwrap()[49][0] > 0.99);
+    }
+
+    #[test]
+    fn auto_contrast_keeps_hue() {
+        // Gray ends, and a color in the middle that won't clip
+        let mut img = solid((3, 1), [0.2, 0.2, 0.2, 1.]);
+        img.data[1] = [0.4, 0.5, 0.6, 1.];
+        img.data[2] = [0.8, 0.8, 0.8, 1.];
+        let before = linear(&img, img.data[1]);
+        img.auto_contrast(0.).unwrap();
+        let after = linear(&img, img.data[1]);
+        assert!(after[2] > before[2] && after[2] < 1.);
+        // Scaled together, so the ratios hold
+        assert!((after[0] / after[2] - before[0] / before[2]).abs() < 1e-4);
+        assert!((after[1] / after[2] - before[1] / before[2]).abs() < 1e-4);
+    }
+
+    #[test]
+    fn auto_contrast_solid_and_errors() {
+        let mut img = solid((5, 5), [0.4, 0.4, 0.4, 1.]);
+        img.auto_contrast(10.).unwrap();
+        assert!(img.pixels().iter().all(|p| *p == [0.4, 0.4, 0.4, 1.]));
+        img.auto_contrast(0.).unwrap();
+        assert!(img.pixels().iter().all(|p| *p == [0.4, 0.4, 0.4, 1.]));
+        for clip in [50., 75., -1., f32::NAN] {
+            assert_eq!(img.auto_contrast(clip), Err(ImageError::InvalidArgument));
+        }
+    }
+
+    /// Linear light sRGB pixel of Oklch `l`, `c`, and hue `h` in degrees
+    fn oklch(l: f32, c: f32, h: f32) -> crate::WorkPixel {
+        let h = h.to_radians();
+        let [r, g, b] = oklab_to_linear_srgb([l, c * h.cos(), c * h.sin()]);
+        [r, g, b, 1.]
+    }
+
+    /// Oklch lightness, chroma, and hue of each pixel
+    fn lch(img: &Image) -> alloc::vec::Vec<[f32; 3]> {
+        img.pixels()
+            .iter()
+            .map(|p| {
+                let [l, a, b, _] = img.to_oklab_alpha(*p);
+                let h = libm::atan2f(b, a).to_degrees();
+                [l, (a * a + b * b).sqrt(), wrap_hue(h)]
+            })
+            .collect()
+    }
+
+    /// `img` of `pixels` in a row, in linear light
+    fn row(pixels: &[crate::WorkPixel]) -> Image {
+        let mut img = solid((pixels.len() as u32, 1), [0.; 4]);
+        img.data.copy_from_slice(pixels);
+        img.color = ColorSpace::sRGBLinear;
+        img
+    }
+
+    fn hue_diff(a: f32, b: f32) -> f32 {
+        let d = wrap_hue(a - b);
+        d.min(360. - d)
+    }
+
+    #[test]
+    fn hue_range_shifts_fully() {
+        let blue = oklch(0.6, 0.08, 250.);
+        let mut img = row(&[blue, oklch(0.6, 0.08, 255.), oklch(0.6, 0.08, 200.)]);
+        img.replace_hue_range(250., 10., 190., 0.);
+        let out = lch(&img);
+        assert!(hue_diff(out[0][2], 190.) < 0.5, "{out:?}");
+        assert!(hue_diff(out[1][2], 195.) < 0.5, "{out:?}");
+        // Lightness and chroma kept
+        for p in &out[..2] {
+            assert!(
+                (p[0] - 0.6).abs() < 1e-3 && (p[1] - 0.08).abs() < 1e-3,
+                "{p:?}"
+            );
+        }
+        // Outside the range
+        assert_eq!(img.pixels()[2], oklch(0.6, 0.08, 200.));
+    }
+
+    #[test]
+    fn hue_range_skips_grays() {
+        let mut img = photo((8, 8));
+        img.map_pixels(|p| {
+            let v = (p[0] + p[1] + p[2]) / 3.;
+            [v, v, v, p[3]]
+        });
+        let before = img.clone();
+        for from in [0., 90., 250.] {
+            img.replace_hue_range(from, 180., from + 120., 0.);
+        }
+        assert_eq!(img.pixels(), before.pixels());
+        // Near gray too
+        let mut img = row(&[oklch(0.5, 0.015, 250.)]);
+        img.replace_hue_range(250., 10., 100., 0.);
+        assert_eq!(img.pixels(), [oklch(0.5, 0.015, 250.)]);
+    }
+
+    #[test]
+    fn hue_range_wraps() {
+        let pixels = [
+            oklch(0.6, 0.08, 350.),
+            oklch(0.6, 0.08, 20.),
+            oklch(0.6, 0.08, 60.),
+        ];
+        let mut img = row(&pixels);
+        img.replace_hue_range(5., 20., 125., 0.);
+        let out = lch(&img);
+        assert!(hue_diff(out[0][2], 110.) < 0.5, "{out:?}");
+        assert!(hue_diff(out[1][2], 140.) < 0.5, "{out:?}");
+        assert_eq!(img.pixels()[2], pixels[2]);
+        // And from the other side
+        let mut img = row(&pixels);
+        img.replace_hue_range(355., 30., 295., 0.);
+        let out = lch(&img);
+        assert!(hue_diff(out[0][2], 290.) < 0.5, "{out:?}");
+        assert!(hue_diff(out[1][2], 320.) < 0.5, "{out:?}");
+    }
+
+    #[test]
+    fn hue_range_feathers() {
+        // Fully in, halfway through the feather, and past it
+        let hues = [205., 220., 240.];
+        let mut img = row(&hues.map(|h| oklch(0.6, 0.08, h)));
+        img.replace_hue_range(200., 10., 160., 20.);
+        let out = lch(&img);
+        assert!(hue_diff(out[0][2], 165.) < 0.5, "{out:?}");
+        assert!(hue_diff(out[1][2], 200.) < 0.5, "{out:?}");
+        assert!(hue_diff(out[2][2], 240.) < 1e-3, "{out:?}");
+    }
+
+    const METHODS: [IlluminantEstimator; 3] = [
+        IlluminantEstimator::GrayWorld,
+        IlluminantEstimator::MaxRgb,
+        IlluminantEstimator::GrayEdge,
+    ];
+    const TINT: [f32; 3] = [1.3, 1., 0.7];
+
+    /// A textured gray scene in linear light, `0.05..=0.6`, times `tint`
+    fn tinted_gray(res: crate::ResXY, tint: [f32; 3]) -> Image {
+        let mut img = photo(res);
+        img.to_color(ColorSpace::sRGBLinear);
+        img.map_pixels(|p| {
+            let y = 0.05 + luminance([p[0], p[1], p[2]]) * 0.55;
+            [y * tint[0], y * tint[1], y * tint[2], 1.]
+        });
+        img
+    }
+
+    /// Largest relative difference between the channels of the pixels
+    /// `region` picks out of `img`
+    fn cast(img: &Image, region: impl Fn(XY) -> bool) -> f32 {
+        let mut worst = 0f32;
+        for y in 0..img.height() {
+            for x in (0..img.width()).filter(|x| region((*x, y))) {
+                let p = img.get_pixel((x, y)).unwrap();
+                let mean = (p[0] + p[1] + p[2]) / 3.;
+                for c in &p[..3] {
+                    worst = worst.max((c - mean).abs() / mean);
+                }
+            }
+        }
+        worst
+    }
+
+    #[test]
+    fn auto_color_correct_removes_cast() {
+        // Corrected to the luminance of the tinted scene
+        let y = luminance(TINT);
+        let neutral = tinted_gray((32, 24), [y; 3]);
+        for method in METHODS {
+            let mut img = tinted_gray((32, 24), TINT);
+            let e = img.estimate_illuminant(method);
+            for (e, t) in e.iter().zip(TINT) {
+                assert!((e - t / y).abs() < 5e-3, "{method:?} {e}");
+            }
+            img.auto_color_correct(method);
+            assert!(cast(&img, |_| true) < 1e-2, "{method:?}");
+            assert!(
+                max_diff(img.pixels(), neutral.pixels()) < 1e-2,
+                "{method:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn gray_edge_resists_dominant_colors() {
+        // Mostly flat green, with a textured gray strip on the right
+        let gray = tinted_gray((32, 24), TINT);
+        let strip = |(x, _): XY| x >= 24;
+        let mut img = gray.clone();
+        img.map_pixels_indexed(|xy, p| {
+            if strip(xy) {
+                p
+            } else {
+                [0.05 * TINT[0], 0.5 * TINT[1], 0.05 * TINT[2], 1.]
+            }
+        });
+        let error = |method| {
+            let mut img = img.clone();
+            img.auto_color_correct(method);
+            cast(&img, strip)
+        };
+        let (world, edge) = (
+            error(IlluminantEstimator::GrayWorld),
+            error(IlluminantEstimator::GrayEdge),
+        );
+        assert!(edge < world, "{edge} {world}");
+    }
+
+    #[test]
+    fn auto_color_correct_clamps_gains() {
+        let mut img = row(&[[0.5, 0.5, 0.001, 1.], [0.2, 0.2, 0.0004, 1.]]);
+        img.auto_color_correct(IlluminantEstimator::GrayWorld);
+        let p = img.pixels();
+        assert!((p[0][2] - 0.004).abs() < 1e-6, "{:?}", p[0]);
+        assert!((p[1][2] - 0.0016).abs() < 1e-6, "{:?}", p[1]);
+        // Red and green get the gain to keep the luminance
+        let e = luminance([0.5, 0.5, 0.001]) / 0.5;
+        assert!((p[0][0] - 0.5 * e).abs() < 1e-4, "{:?}", p[0]);
+    }
+
+    #[test]
+    fn auto_color_correct_protects_highlights() {
+        let mut img = tinted_gray((16, 16), TINT);
+        img.data[0] = [1., 1., 1., 1.];
+        img.auto_color_correct(IlluminantEstimator::GrayWorld);
+        assert_eq!(img.data[0], [1., 1., 1., 1.]);
+    }
+
+    #[test]
+    fn estimate_without_evidence() {
+        for method in METHODS {
+            assert_eq!(
+                solid((4, 4), [0., 0., 0., 1.]).estimate_illuminant(method),
+                [1.; 3]
+            );
+            assert_eq!(
+                solid((4, 4), [1., 0., 0., 0.]).estimate_illuminant(method),
+                [1.; 3]
+            );
+            assert_eq!(
+                solid((0, 0), [1., 0., 0., 1.]).estimate_illuminant(method),
+                [1.; 3]
+            );
+        }
+    }
+}
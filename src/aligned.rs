@@ -0,0 +1,131 @@
+//! Aligned exports for 2D accelerators, like the STM32 DMA2D
+use alloc::vec::Vec;
+
+use crate::{
+    alpha_converter,
+    layout::{argb_stride, prepare},
+    AlphaMode, Image, PixelFormat,
+};
+
+impl Image {
+    /// Export as [`PixelFormat::Argb8888`] with straight alpha into `out`,
+    /// each row padded to a multiple of `align` bytes, returning the stride
+    ///
+    /// See [`Image::to_argb8888_aligned_with_alpha`].
+    pub fn to_argb8888_aligned(&self, align: usize, out: &mut Vec<u8>) -> usize {
+        self.to_argb8888_aligned_with_alpha(align, AlphaMode::Straight, out)
+    }
+
+    /// [`Image::to_argb8888_aligned`], exporting with the [`AlphaMode`]
+    /// `alpha`
+    ///
+    /// `out` is replaced, keeping its capacity, so reusing it for every frame
+    /// doesn't allocate. Padding bytes are zero. The stride is
+    /// [`argb_stride`].
+    ///
+    /// # Panics
+    ///
+    /// - If the stride overflows
+    pub fn to_argb8888_aligned_with_alpha(
+        &self,
+        align: usize,
+        alpha: AlphaMode,
+        out: &mut Vec<u8>,
+    ) -> usize {
+        profile!(Export);
+        let format = PixelFormat::Argb8888;
+        let stride = argb_stride(self.width(), align);
+        let transfer = self.color.transfer();
+        let convert = alpha_converter(AlphaMode::Straight, alpha);
+        out.clear();
+        out.resize(stride * self.height() as usize, 0);
+
+        let width = self.width() as usize;
+        for (row, dst) in self
+            .data
+            .chunks_exact(width.max(1))
+            .zip(out.chunks_exact_mut(stride.max(1)))
+        {
+            for (p, o) in row.iter().zip(dst.chunks_exact_mut(4)) {
+                format.encode(convert(prepare(*p, format, self.alpha, transfer)), o);
+            }
+        }
+        stride
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorSpace;
+
+    fn image(res: crate::ResXY) -> Image {
+        let data: Vec<u8> = (0..res.0 * res.1)
+            .flat_map(|i| [200, 100, 50, [128, 255, 0][i as usize % 3]])
+            .collect();
+        Image::from_bytes(&data, res, ColorSpace::sRGB)
+    }
+
+    #[test]
+    fn strides() {
+        const STRIDE: usize = argb_stride(10, 32);
+        assert_eq!(STRIDE, 64);
+        for (width, align, stride) in [
+            (0, 4, 0),
+            (1, 0, 4),
+            (1, 1, 4),
+            (3, 4, 12),
+            (3, 8, 16),
+            (5, 16, 32),
+            (7, 64, 64),
+            (5, 3, 21),
+            (17, 32, 96),
+        ] {
+            assert_eq!(argb_stride(width, align), stride, "{width} {align}");
+        }
+    }
+
+    #[test]
+    fn padded_rows() {
+        let img = image((3, 4));
+        let mut out = alloc::vec![0xff; 7];
+        let stride = img.to_argb8888_aligned(32, &mut out);
+        assert_eq!(stride, 32);
+        assert_eq!(out.len(), 32 * 4);
+        for row in out.chunks_exact(stride) {
+            assert_eq!(row[12..], [0; 20]);
+        }
+        assert_eq!(
+            out[..12],
+            [128, 200, 100, 50, 255, 200, 100, 50, 0, 200, 100, 50]
+        );
+    }
+
+    #[test]
+    fn alpha_modes() {
+        let img = image((3, 1));
+        let mut out = Vec::new();
+        img.to_argb8888_aligned_with_alpha(1, AlphaMode::Premultiplied, &mut out);
+        assert_eq!(out, [128, 100, 50, 25, 255, 200, 100, 50, 0, 0, 0, 0]);
+        // The same from a premultiplied image
+        let mut premul = img.clone();
+        premul.to_alpha_mode(AlphaMode::Premultiplied);
+        let mut straight = Vec::new();
+        premul.to_argb8888_aligned(1, &mut straight);
+        assert_eq!(straight[..8], [128, 200, 100, 50, 255, 200, 100, 50]);
+        let mut again = Vec::new();
+        premul.to_argb8888_aligned_with_alpha(1, AlphaMode::Premultiplied, &mut again);
+        assert_eq!(again, out);
+    }
+
+    #[test]
+    fn reuses_capacity() {
+        let img = image((5, 5));
+        let mut out = Vec::with_capacity(1024);
+        let ptr = out.as_ptr();
+        for align in [4, 16, 64] {
+            img.to_argb8888_aligned(align, &mut out);
+            assert_eq!(out.as_ptr(), ptr);
+        }
+    }
+}
@@ -0,0 +1,637 @@
+//! Alpha channel utilities
+use crate::{AlphaMode, Image, ResXY, ScaleFilter, WorkPixel, F32, XY};
+
+impl Image {
+    /// Whether the pixel at `xy` has alpha above `alpha_threshold`
+    ///
+    /// Coordinates outside the image never hit.
+    pub fn hit_test(&self, xy: XY, alpha_threshold: f32) -> bool {
+        self.get_pixel(xy).is_some_and(|p| p[3] > alpha_threshold)
+    }
+
+    /// Tight bounding box of all pixels with alpha above `alpha_threshold`,
+    /// as `(origin, size)`
+    ///
+    /// Returns `None` if there are no such pixels.
+    pub fn opaque_bounds(&self, alpha_threshold: f32) -> Option<(XY, ResXY)> {
+        self.bounds_where(|p| p[3] > alpha_threshold)
+    }
+
+    /// Tight bounding box of all pixels matching `f`, as `(origin, size)`
+    pub(crate) fn bounds_where(&self, mut f: impl FnMut(WorkPixel) -> bool) -> Option<(XY, ResXY)> {
+        let width = self.width();
+        let (mut min_x, mut min_y) = (u32::MAX, u32::MAX);
+        let (mut max_x, mut max_y) = (0, 0);
+        for (i, p) in self.data.iter().enumerate() {
+            if f(*p) {
+                let (x, y) = (i as u32 % width, i as u32 / width);
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x);
+                max_y = max_y.max(y);
+            }
+        }
+        if min_x == u32::MAX {
+            return None;
+        }
+        Some(((min_x, min_y), (max_x - min_x + 1, max_y - min_y + 1)))
+    }
+
+    /// Crop away transparent borders, see [`Image::opaque_bounds`]
+    ///
+    /// Fully transparent images are left unchanged.
+    pub fn trim_transparent(&mut self, alpha_threshold: f32) {
+        if let Some((origin, size)) = self.opaque_bounds(alpha_threshold) {
+            // Always in bounds
+            let _ = self.crop(origin, size);
+        }
+    }
+
+    /// Average alpha, the fraction of the image covered
+    fn coverage(&self) -> f32 {
+        let sum: f32 = self.data.iter().map(|p| p[3]).sum();
+        sum / self.data.len().max(1) as f32
+    }
+
+    /// Fraction of pixels with alpha above `threshold`, what survives alpha
+    /// testing against it
+    ///
+    /// Empty images have no coverage.
+    pub fn alpha_coverage(&self, threshold: f32) -> f32 {
+        let above = self.data.iter().filter(|p| p[3] > threshold).count();
+        above as f32 / self.data.len().max(1) as f32
+    }
+
+    /// Downscale with [`ScaleFilter::Box`], then scale alpha so coverage
+    /// matches `target_coverage`, or the source's coverage
+    ///
+    /// Coverage is average alpha, `0..=1`. Area filtering keeps thin strokes
+    /// as faint alpha instead of dropping them, and the scale makes up for
+    /// alpha lost to clamping, the same trick as alpha to coverage mipmaps.
+    /// Targets above the fraction of pixels with any alpha can't be reached,
+    /// those all become opaque.
+    ///
+    /// # Panics
+    ///
+    /// - If `new` is zero in either dimension
+    pub fn scale_preserve_coverage(&mut self, new: ResXY, target_coverage: Option<f32>) {
+        let target = target_coverage
+            .unwrap_or_else(|| self.coverage())
+            .clamp(0., 1.);
+        self.scale_with(new, ScaleFilter::Box);
+
+        let covered = |s: f32| {
+            let sum: f32 = self.data.iter().map(|p| (p[3] * s).min(1.)).sum();
+            sum / self.data.len() as f32
+        };
+        // Coverage only grows with the scale, so binary search it
+        let (mut lo, mut hi) = (0f32, 1f32);
+        while covered(hi) < target && hi < 1e6 {
+            hi *= 2.;
+        }
+        for _ in 0..32 {
+            let mid = (lo + hi) / 2.;
+            if covered(mid) < target {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        self.scale_alpha(hi);
+    }
+
+    /// Multiply alpha by `s`, clamped to 1, keeping straight colors
+    pub(crate) fn scale_alpha(&mut self, s: f32) {
+        let premul = self.alpha == AlphaMode::Premultiplied;
+        for p in &mut self.data {
+            let a = (p[3] * s).min(1.);
+            if premul && p[3] > 0. {
+                let f = a / p[3];
+                p[0] *= f;
+                p[1] *= f;
+                p[2] *= f;
+            }
+            p[3] = a;
+        }
+    }
+
+    /// Downscale with [`ScaleFilter::Box`], weighting each pixel's color by
+    /// its alpha
+    ///
+    /// Unlike plain [`Image::scale_with`] on straight alpha, the color of
+    /// transparent pixels doesn't bleed into their neighbors. Areas that are
+    /// entirely transparent become transparent black.
+    ///
+    /// # Panics
+    ///
+    /// - If `new` is zero in either dimension
+    pub fn scale_alpha_weighted(&mut self, new: ResXY) {
+        let alpha = self.alpha;
+        self.to_alpha_mode(AlphaMode::Premultiplied);
+        self.scale_with(new, ScaleFilter::Box);
+        self.to_alpha_mode(alpha);
+    }
+
+    /// Recover alpha from an image composited over the solid `background`
+    ///
+    /// Each pixel is assumed to be `fg * a + background * (1 - a)`, and
+    /// solved for the smallest `a` that gives a valid foreground, like
+    /// GIMP's color to alpha. Pixels matching `background` become fully
+    /// transparent. This works in linear light, `background` is in the
+    /// image's color space.
+    pub fn unmatte(&mut self, background: impl Into<WorkPixel>) {
+        let background = background.into();
+        let decode = self.color.transfer().map(|t| t.0);
+        let bg = [0, 1, 2].map(|c| decode.map_or(background[c], |f| f(background[c])));
+        let premul = self.alpha == AlphaMode::Premultiplied;
+        if premul {
+            self.to_alpha_mode(AlphaMode::Straight);
+        }
+        self.in_linear(|img| {
+            for p in &mut img.data {
+                let a = (0..3)
+                    .map(|c| {
+                        let (v, b) = (p[c].clamp(0., 1.), bg[c]);
+                        if v > b {
+                            (v - b) / (1. - b)
+                        } else if v < b {
+                            (b - v) / b
+                        } else {
+                            0.
+                        }
+                    })
+                    .fold(0f32, f32::max)
+                    .clamp(0., 1.);
+                *p = if a <= 0. {
+                    [0.; 4]
+                } else {
+                    let fg = |c: usize| (bg[c] + (p[c].clamp(0., 1.) - bg[c]) / a).clamp(0., 1.);
+                    [fg(0), fg(1), fg(2), a * p[3]]
+                };
+            }
+        });
+        if premul {
+            self.to_alpha_mode(AlphaMode::Premultiplied);
+        }
+    }
+
+    /// Remove `old_matte` from an image whose alpha is coverage over it,
+    /// then composite over `new_matte`, or leave true straight alpha for
+    /// `None`
+    ///
+    /// Some tools export icons whose color was already blended over a matte,
+    /// usually white, so compositing them normally leaves fringes of it.
+    /// Each pixel is assumed to be `fg * a + old_matte * (1 - a)`, which is
+    /// solved for `fg`. The alpha is kept. Pixels with alpha too small to
+    /// solve become transparent black, or `new_matte`. This works in linear
+    /// light, the mattes are in the image's color space.
+    pub fn rematte(&mut self, old_matte: impl Into<WorkPixel>, new_matte: Option<WorkPixel>) {
+        let old_matte = old_matte.into();
+        // Below this the foreground is mostly rounding error
+        const MIN_ALPHA: f32 = 1. / 1024.;
+        let decode = self.color.transfer().map(|t| t.0);
+        let linear = |m: WorkPixel| [0, 1, 2].map(|c| decode.map_or(m[c], |f| f(m[c])));
+        let old = linear(old_matte);
+        let new = new_matte.map(linear);
+        let premul = self.alpha == AlphaMode::Premultiplied;
+        if premul {
+            self.to_alpha_mode(AlphaMode::Straight);
+        }
+        self.in_linear(|img| {
+            for p in &mut img.data {
+                let a = p[3].clamp(0., 1.);
+                let fg = if a < MIN_ALPHA {
+                    [0.; 3]
+                } else {
+                    [0, 1, 2].map(|c| ((p[c] - old[c] * (1. - a)) / a).clamp(0., 1.))
+                };
+                let rgb = match new {
+                    Some(m) => [0, 1, 2].map(|c| fg[c] * a + m[c] * (1. - a)),
+                    None => fg,
+                };
+                *p = [rgb[0], rgb[1], rgb[2], p[3]];
+            }
+        });
+        if premul {
+            self.to_alpha_mode(AlphaMode::Premultiplied);
+        }
+    }
+
+    /// Round the corners by multiplying alpha with an anti-aliased rounded
+    /// rectangle, with radii `[top_left, top_right, bottom_right,
+    /// bottom_left]`
+    ///
+    /// Only the corner squares are touched. Coverage of each pixel there is
+    /// estimated from its center's distance to the arc, a one pixel ramp.
+    /// Radii are clamped to half the smaller dimension, and zero leaves its
+    /// corner as is.
+    pub fn round_corners(&mut self, radii: [u32; 4]) {
+        let (w, h) = self.res;
+        let max = w.min(h) / 2;
+        for (corner, r) in radii.into_iter().enumerate() {
+            let r = r.min(max);
+            let rf = r as f32;
+            for dy in 0..r {
+                for dx in 0..r {
+                    // Offset from the arc's center, which is `r` in from the
+                    // corner
+                    let (ox, oy) = (rf - (dx as f32 + 0.5), rf - (dy as f32 + 0.5));
+                    let k = (rf + 0.5 - (ox * ox + oy * oy).sqrt()).clamp(0., 1.);
+                    if k >= 1. {
+                        continue;
+                    }
+                    let x = if corner == 0 || corner == 3 {
+                        dx
+                    } else {
+                        w - 1 - dx
+                    };
+                    let y = if corner < 2 { dy } else { h - 1 - dy };
+                    let p = &mut self.data[(y * w + x) as usize];
+                    match self.alpha {
+                        AlphaMode::Straight => p[3] *= k,
+                        AlphaMode::Premultiplied => *p = p.map(|c| c * k),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::{fixtures::max_diff, ColorSpace, Image};
+
+    /// `res` with a 3 pixel transparent margin around an opaque middle
+    fn sprite(res: (u32, u32)) -> Image {
+        let mut data = Vec::new();
+        for y in 0..res.1 {
+            for x in 0..res.0 {
+                let inside = (3..res.0 - 3).contains(&x) && (3..res.1 - 3).contains(&y);
+                data.extend([200, 100, 50, if inside { 255 } else { 0 }]);
+            }
+        }
+        Image::from_bytes(&data, res, ColorSpace::sRGB)
+    }
+
+    #[test]
+    fn trim_margin() {
+        let mut img = sprite((10, 8));
+        assert_eq!(img.opaque_bounds(0.5), Some(((3, 3), (4, 2))));
+        let original = img.clone();
+        img.trim_transparent(0.5);
+        assert_eq!(img.res, (4, 2));
+        assert!(img.pixels().iter().all(|p| p[3] == 1.));
+        assert_eq!(img.get_pixel((0, 0)), original.get_pixel((3, 3)));
+    }
+
+    #[test]
+    fn trim_fully_transparent() {
+        let mut img = sprite((6, 6));
+        assert_eq!(img.opaque_bounds(0.5), None);
+        let before = img.clone();
+        img.trim_transparent(0.5);
+        assert_eq!(img.res, (6, 6));
+        assert_eq!(max_diff(img.pixels(), before.pixels()), 0.);
+    }
+
+    #[test]
+    fn hit_test_boundaries() {
+        let img = sprite((10, 8));
+        let ((x, y), (w, h)) = img.opaque_bounds(0.5).unwrap();
+        // Every corner of the box hits, and just outside each doesn't
+        for (cx, cy) in [
+            (x, y),
+            (x + w - 1, y),
+            (x, y + h - 1),
+            (x + w - 1, y + h - 1),
+        ] {
+            assert!(img.hit_test((cx, cy), 0.5), "{cx}, {cy}");
+        }
+        assert!(!img.hit_test((x - 1, y), 0.5));
+        assert!(!img.hit_test((x, y - 1), 0.5));
+        assert!(!img.hit_test((x + w, y), 0.5));
+        assert!(!img.hit_test((x, y + h), 0.5));
+        // Outside the image never hits, and the threshold is exclusive
+        assert!(!img.hit_test((10, 0), -1.));
+        assert!(!img.hit_test((0, 8), -1.));
+        assert!(img.hit_test((0, 0), -1.));
+        assert!(!img.hit_test((4, 4), 1.));
+        // Consistent with the bounds for every pixel
+        for py in 0..8 {
+            for px in 0..10 {
+                let inside = (x..x + w).contains(&px) && (y..y + h).contains(&py);
+                assert_eq!(img.hit_test((px, py), 0.5), inside, "{px}, {py}");
+            }
+        }
+    }
+
+    #[test]
+    fn scale_empty() {
+        for res in [(0, 5), (5, 0)] {
+            let mut img = Image::from_bytes(&[], res, ColorSpace::sRGB);
+            img.scale_preserve_coverage((3, 4), None);
+            assert_eq!(img.res, (3, 4));
+            let mut img = Image::from_bytes(&[], res, ColorSpace::sRGB);
+            img.scale_alpha_weighted((3, 4));
+            assert!(img.pixels().iter().all(|p| *p == [0.; 4]));
+        }
+    }
+
+    /// A 1 pixel line on row 1 and a diagonal, on transparent
+    fn strokes() -> Image {
+        let mut data = Vec::new();
+        for y in 0..32 {
+            for x in 0..32 {
+                let on = y == 1 || x == y;
+                data.extend([255, 255, 255, if on { 255 } else { 0 }]);
+            }
+        }
+        Image::from_bytes(&data, (32, 32), ColorSpace::sRGB)
+    }
+
+    #[test]
+    fn thin_lines_survive() {
+        let img = strokes();
+        let mut nearest = img.clone();
+        nearest.scale_with((8, 8), crate::ScaleFilter::Nearest);
+        // Only the diagonal is sampled
+        assert!(nearest.row(0).unwrap()[1..].iter().all(|p| p[3] == 0.));
+
+        let mut kept = img.clone();
+        kept.scale_preserve_coverage((8, 8), None);
+        for i in 0..8 {
+            assert!(kept.get_pixel((i, 0)).unwrap()[3] > 0., "{i}");
+            assert!(kept.get_pixel((i, i)).unwrap()[3] > 0., "{i}");
+        }
+        let want = img.coverage();
+        assert!((kept.coverage() - want).abs() < want * 0.01);
+    }
+
+    #[test]
+    fn reaches_target() {
+        let mut img = strokes();
+        img.scale_preserve_coverage((8, 8), Some(0.1));
+        assert!((img.coverage() - 0.1).abs() < 0.001, "{}", img.coverage());
+        assert!(img.pixels().iter().all(|p| p[3] <= 1.));
+
+        // Past what the covered pixels can reach they're all opaque
+        let mut img = strokes();
+        img.scale_preserve_coverage((8, 8), Some(0.9));
+        for p in img.pixels() {
+            assert!(p[3] == 0. || p[3] == 1., "{p:?}");
+        }
+        assert_eq!(img.coverage(), 15. / 64.);
+    }
+
+    #[test]
+    fn premultiplied_color_follows_alpha() {
+        let mut straight = strokes();
+        let mut premul = strokes();
+        premul.to_alpha_mode(crate::AlphaMode::Premultiplied);
+        straight.scale_preserve_coverage((8, 8), Some(0.1));
+        premul.scale_preserve_coverage((8, 8), Some(0.1));
+        premul.to_alpha_mode(crate::AlphaMode::Straight);
+        for (s, p) in straight.pixels().iter().zip(premul.pixels()) {
+            assert!((s[3] - p[3]).abs() < 1e-6);
+            if s[3] > 0. {
+                assert!(max_diff(&[*s], &[*p]) < 1e-5, "{s:?} {p:?}");
+            }
+        }
+    }
+
+    /// Sprite colors with a channel at 0 or 1, so the least alpha that
+    /// explains them is their own, with straight alpha
+    fn sprite_colors() -> Image {
+        let colors = [
+            [1., 0.3, 0.6],
+            [0., 0.5, 0.2],
+            [0.7, 1., 0.1],
+            [0.25, 0.8, 0.],
+        ];
+        let data = (0..16)
+            .map(|i| {
+                let c: [f32; 3] = colors[i % 4];
+                [c[0], c[1], c[2], [0., 0.2, 0.6, 1.][i / 4]]
+            })
+            .collect();
+        Image::from_parts(data, (4, 4), ColorSpace::sRGB)
+    }
+
+    #[test]
+    fn unmatte_recovers_sprite() {
+        let sprite = sprite_colors();
+        // Strictly between black and white, so every color is reachable
+        for bg in [[0.5, 0.5, 0.5, 1.], [0.2, 0.6, 0.9, 1.]] {
+            let mut img = crate::fixtures::solid((4, 4), bg);
+            img.overlay(&sprite, (0, 0)).unwrap();
+            img.unmatte(bg);
+            for (got, want) in img.pixels().iter().zip(sprite.pixels()) {
+                if want[3] == 0. {
+                    assert_eq!(*got, [0.; 4]);
+                } else {
+                    assert!(
+                        max_diff(&[*got], &[*want]) < 1e-3,
+                        "{bg:?} {got:?} {want:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unmatte_background_is_transparent() {
+        let bg = [0.3, 0.7, 0.2, 1.];
+        let mut img = crate::fixtures::solid((5, 3), bg);
+        img.unmatte(bg);
+        assert!(img.pixels().iter().all(|p| *p == [0.; 4]));
+    }
+
+    #[test]
+    fn unmatte_clamps() {
+        let data = alloc::vec![[1.5, -0.5, 0.5, 1.], [2., 2., 2., 1.], [0.5, 0.5, 0.5, 0.5]];
+        let mut img = Image::from_parts(data, (3, 1), ColorSpace::sRGB);
+        img.to_alpha_mode(crate::AlphaMode::Premultiplied);
+        img.unmatte([0.5, 0.5, 0.5, 1.]);
+        assert_eq!(img.alpha, crate::AlphaMode::Premultiplied);
+        let p = img.pixels();
+        assert!(p.iter().flatten().all(|c| (0.0..=1.).contains(c)), "{p:?}");
+        // Clamped to white, fully explained by white
+        assert!((p[1][3] - 1.).abs() < 1e-6);
+        // Background at half alpha is still transparent
+        assert_eq!(p[2], [0.; 4]);
+    }
+
+    #[test]
+    fn weighted_downscale() {
+        // Opaque red on the left, transparent green on the right
+        let mut img = crate::fixtures::solid((8, 4), [0., 1., 0., 0.]);
+        img.map_pixels_indexed(|(x, _), p| if x < 4 { [1., 0., 0., 1.] } else { p });
+        let mut weighted = img.clone();
+        weighted.scale_alpha_weighted((2, 1));
+        assert_eq!(weighted.alpha, crate::AlphaMode::Straight);
+        assert_eq!(weighted.pixels(), [[1., 0., 0., 1.], [0.; 4]]);
+        weighted = img.clone();
+        weighted.scale_alpha_weighted((1, 1));
+        let p = weighted.pixels()[0];
+        assert!(
+            (p[0] - 1.).abs() < 1e-6 && p[1] == 0. && (p[3] - 0.5).abs() < 1e-6,
+            "{p:?}"
+        );
+        // Plain scaling lets the green in
+        img.scale_with((1, 1), crate::ScaleFilter::Box);
+        assert!(img.pixels()[0][1] > 0.4);
+
+        let mut clear = crate::fixtures::solid((4, 4), [0.3, 0.6, 0.9, 0.]);
+        clear.scale_alpha_weighted((1, 1));
+        assert_eq!(clear.pixels(), [[0.; 4]]);
+    }
+
+    const WHITE: crate::WorkPixel = [1.; 4];
+    const BLACK: crate::WorkPixel = [0., 0., 0., 1.];
+
+    /// A true straight alpha icon, alpha ramping up left to right
+    fn icon() -> Image {
+        let mut img = crate::fixtures::photo((17, 6));
+        img.map_pixels_indexed(|(x, _), p| [p[0], p[1], p[2], x as f32 / 16.]);
+        img
+    }
+
+    /// `img` composited over an opaque `matte`
+    fn over(img: &Image, matte: crate::WorkPixel) -> Image {
+        let mut bg = crate::fixtures::solid(img.res, matte);
+        bg.overlay(img, (0, 0)).unwrap();
+        bg
+    }
+
+    /// `icon` as some tools export it, blended over `matte` but still with
+    /// its alpha
+    fn matted(icon: &Image, matte: crate::WorkPixel) -> Image {
+        let mut img = over(icon, matte);
+        for (p, a) in img.data.iter_mut().zip(&icon.data) {
+            p[3] = a[3];
+        }
+        img
+    }
+
+    #[test]
+    fn rematte_removes_fringes() {
+        let icon = icon();
+        let mut img = matted(&icon, WHITE);
+        // Used as is, white shows through over black
+        assert!(max_diff(over(&img, BLACK).pixels(), over(&icon, BLACK).pixels()) > 0.3);
+        img.rematte(WHITE, None);
+        assert!(max_diff(over(&img, BLACK).pixels(), over(&icon, BLACK).pixels()) < 2e-3);
+        assert!(img
+            .pixels()
+            .iter()
+            .zip(icon.pixels())
+            .all(|(a, b)| a[3] == b[3]));
+    }
+
+    #[test]
+    fn rematte_onto_new_matte() {
+        let icon = icon();
+        for alpha in [crate::AlphaMode::Straight, crate::AlphaMode::Premultiplied] {
+            let mut img = matted(&icon, WHITE);
+            img.to_alpha_mode(alpha);
+            img.rematte(WHITE, Some(BLACK));
+            img.to_alpha_mode(crate::AlphaMode::Straight);
+            let want = over(&icon, BLACK);
+            for (a, b) in img.pixels().iter().zip(want.pixels()) {
+                assert!((0..3).all(|c| (a[c] - b[c]).abs() < 2e-3), "{a:?} {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn rematte_transparent_is_finite() {
+        let mut img = crate::fixtures::solid((3, 1), [1., 1., 1., 0.]);
+        img.data[1][3] = 1e-6;
+        img.rematte(WHITE, None);
+        assert!(img.pixels().iter().all(|p| p[..3] == [0.; 3]));
+        img.rematte(BLACK, Some([0.2, 0.4, 0.6, 1.]));
+        let close = |p: &crate::WorkPixel| (0..3).all(|c| (p[c] - [0.2, 0.4, 0.6][c]).abs() < 1e-5);
+        assert!(img.pixels().iter().all(close), "{:?}", img.pixels());
+    }
+
+    #[test]
+    fn round_corners_zero_is_a_no_op() {
+        let src = crate::fixtures::photo((13, 9));
+        let mut img = src.clone();
+        img.round_corners([0; 4]);
+        let bits = |img: &Image| {
+            img.pixels()
+                .iter()
+                .flatten()
+                .map(|c| c.to_bits())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(bits(&img), bits(&src));
+    }
+
+    #[test]
+    fn round_corners_circle() {
+        let mut img = crate::fixtures::solid((40, 40), WHITE);
+        img.round_corners([20; 4]);
+        let area: f32 = img.pixels().iter().map(|p| p[3]).sum();
+        let circle = core::f32::consts::PI * 400.;
+        assert!((area - circle).abs() < circle * 0.02, "{area} {circle}");
+        // Radii past half the size clamp
+        let mut big = crate::fixtures::solid((40, 40), WHITE);
+        big.round_corners([25, 100, 21, u32::MAX]);
+        assert_eq!(big.pixels(), img.pixels());
+    }
+
+    #[test]
+    fn round_corners_are_graded() {
+        let mut img = crate::fixtures::solid((30, 30), WHITE);
+        img.round_corners([10; 4]);
+        // Out along the diagonal, from inside to the corner
+        let diagonal: Vec<f32> = (0..10)
+            .rev()
+            .map(|i| img.get_pixel((i, i)).unwrap()[3])
+            .collect();
+        assert!(diagonal.windows(2).all(|w| w[1] <= w[0]), "{diagonal:?}");
+        assert_eq!((diagonal[0], diagonal[9]), (1., 0.));
+        // Partial coverage all along the arc, not a hard step
+        let partial = img
+            .pixels()
+            .iter()
+            .filter(|p| p[3] > 0. && p[3] < 1.)
+            .count();
+        assert!(partial >= 4 * 10, "{partial}");
+    }
+
+    #[test]
+    fn round_corners_asymmetric() {
+        let src = crate::fixtures::solid((30, 20), WHITE);
+        let mut img = src.clone();
+        img.round_corners([8, 0, 3, 0]);
+        img.map_pixels_indexed(|(x, y), p| {
+            let in_top_left = x < 8 && y < 8;
+            let in_bottom_right = x >= 27 && y >= 17;
+            if !in_top_left && !in_bottom_right {
+                assert_eq!(p, WHITE, "{x} {y}");
+            }
+            p
+        });
+        assert_eq!(img.get_pixel((0, 0)).unwrap()[3], 0.);
+        assert_eq!(img.get_pixel((29, 19)).unwrap()[3], 0.);
+        assert_eq!(img.get_pixel((29, 0)), Some(WHITE));
+        assert_eq!(img.get_pixel((0, 19)), Some(WHITE));
+    }
+
+    #[test]
+    fn round_corners_premultiplied() {
+        let mut straight = crate::fixtures::photo((12, 12));
+        let mut premul = straight.clone();
+        premul.to_alpha_mode(crate::AlphaMode::Premultiplied);
+        straight.round_corners([5, 4, 3, 2]);
+        premul.round_corners([5, 4, 3, 2]);
+        // Premultiplied, fully cut off pixels have no color left
+        straight.to_alpha_mode(crate::AlphaMode::Premultiplied);
+        assert!(max_diff(premul.pixels(), straight.pixels()) < 1e-5);
+    }
+}
@@ -0,0 +1,141 @@
+//! Text rendering of images, for serial consoles
+use alloc::string::String;
+use core::fmt::Write;
+
+use crate::{luma::pixel_luma, ColorSpace, Image, ScaleFilter, F32};
+
+/// How [`Image::to_ascii_art`] draws each character cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsciiCharset {
+    /// A ramp of plain ASCII characters, densest for black
+    Ascii,
+    /// Unicode shade blocks, ` ░▒▓█`, densest for black
+    Blocks,
+    /// Full blocks in 24 bit ANSI color
+    AnsiColor,
+    /// Full blocks in the 256 color ANSI palette, for older terminals
+    Ansi256,
+}
+
+/// ASCII ramp, from white to black
+const ASCII: &[u8] = b" .:-=+*#%@";
+
+/// Shade blocks, from white to black
+const BLOCKS: [char; 5] = [' ', '░', '▒', '▓', '█'];
+
+/// Nearest of the 6 levels of the 256 color cube
+fn cube(c: u8) -> u8 {
+    match c {
+        0..48 => 0,
+        48..115 => 1,
+        _ => (c - 35) / 40,
+    }
+}
+
+impl Image {
+    /// Draw the image as text, `columns` characters wide
+    ///
+    /// Character cells are assumed to be twice as tall as wide, so there
+    /// are about half as many rows as the aspect ratio would say. `columns`
+    /// is clamped to `1..=width`, one character per pixel at most. Alpha is
+    /// ignored. Every line ends with `\n`, and the ANSI charsets reset the
+    /// color before it.
+    pub fn to_ascii_art(&self, columns: u32, charset: AsciiCharset) -> String {
+        let (w, h) = self.res;
+        let mut s = String::new();
+        if w == 0 || h == 0 {
+            return s;
+        }
+        let cols = columns.clamp(1, w);
+        let rows = ((h as f32 * cols as f32 / w as f32 / 2.).round() as u32).max(1);
+        let mut img = self.clone();
+        if img.color != ColorSpace::AsIs {
+            img.to_color(ColorSpace::sRGB);
+        }
+        img.scale_with((cols, rows), ScaleFilter::Box);
+        let transfer = img.color.transfer();
+
+        for (row, bytes) in img
+            .data
+            .chunks_exact(cols as usize)
+            .zip(img.to_bytes().chunks_exact(cols as usize * 4))
+        {
+            for (p, b) in row.iter().zip(bytes.chunks_exact(4)) {
+                // 0 for white, 1 for black
+                let dark = 1. - pixel_luma(*p, img.alpha, transfer).clamp(0., 1.);
+                let level = |n: usize| ((dark * (n - 1) as f32).round() as usize).min(n - 1);
+                let _ = match charset {
+                    AsciiCharset::Ascii => write!(s, "{}", ASCII[level(ASCII.len())] as char),
+                    AsciiCharset::Blocks => write!(s, "{}", BLOCKS[level(BLOCKS.len())]),
+                    AsciiCharset::AnsiColor => {
+                        write!(s, "\x1b[38;2;{};{};{}m█", b[0], b[1], b[2])
+                    }
+                    AsciiCharset::Ansi256 => {
+                        let n = 16 + 36 * cube(b[0]) + 6 * cube(b[1]) + cube(b[2]);
+                        write!(s, "\x1b[38;5;{n}m█")
+                    }
+                };
+            }
+            if matches!(charset, AsciiCharset::AnsiColor | AsciiCharset::Ansi256) {
+                s.push_str("\x1b[0m");
+            }
+            s.push('\n');
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::fixtures::{photo, solid};
+
+    fn lines(s: &str) -> Vec<&str> {
+        assert!(s.ends_with('\n'));
+        s.lines().collect()
+    }
+
+    #[test]
+    fn geometry() {
+        let img = photo((40, 20));
+        for (columns, (cols, rows)) in [(20, (20, 5)), (0, (1, 1)), (1000, (40, 10)), (7, (7, 2))] {
+            for charset in [AsciiCharset::Ascii, AsciiCharset::Blocks] {
+                let art = img.to_ascii_art(columns, charset);
+                let lines = lines(&art);
+                assert_eq!(lines.len(), rows, "{columns}");
+                assert!(lines.iter().all(|l| l.chars().count() == cols), "{columns}");
+            }
+        }
+        let empty = Image::from_bytes(&[], (0, 4), ColorSpace::sRGB);
+        assert_eq!(empty.to_ascii_art(10, AsciiCharset::Ascii), "");
+    }
+
+    #[test]
+    fn black_is_densest() {
+        let black = solid((8, 8), [0., 0., 0., 1.]);
+        let white = solid((8, 8), [1., 1., 1., 1.]);
+        assert_eq!(black.to_ascii_art(4, AsciiCharset::Ascii), "@@@@\n@@@@\n");
+        assert_eq!(black.to_ascii_art(2, AsciiCharset::Blocks), "██\n");
+        assert_eq!(white.to_ascii_art(4, AsciiCharset::Ascii), "    \n    \n");
+        assert_eq!(white.to_ascii_art(2, AsciiCharset::Blocks), "  \n");
+    }
+
+    #[test]
+    fn ansi_escapes() {
+        let red = solid((6, 4), [1., 0., 0., 1.]);
+        let art = red.to_ascii_art(3, AsciiCharset::AnsiColor);
+        assert_eq!(art, "\x1b[38;2;255;0;0m█".repeat(3) + "\x1b[0m\n");
+        let art = red.to_ascii_art(3, AsciiCharset::Ansi256);
+        assert_eq!(art, "\x1b[38;5;196m█".repeat(3) + "\x1b[0m\n");
+
+        let art = photo((40, 20)).to_ascii_art(10, AsciiCharset::AnsiColor);
+        for line in lines(&art) {
+            assert!(line.starts_with("\x1b["));
+            assert!(line.ends_with("\x1b[0m"));
+            assert_eq!(line.matches("\x1b[38;2;").count(), 10);
+            assert_eq!(line.matches('█').count(), 10);
+        }
+    }
+}
@@ -0,0 +1,499 @@
+//! Average and dominant colors, and which pixels count towards statistics
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    alpha_converter, luma::pixel_luma, transforms::luminance, AlphaMode, Image, ImageError, ResXY,
+    WorkPixel, XY,
+};
+
+/// Which pixels of an image count towards masked statistics, like
+/// [`Image::average_color_masked`], and by how much
+#[derive(Debug, Clone, Copy)]
+pub enum StatsMask<'a> {
+    /// Only the rectangle at an origin of a size, each pixel in it counting
+    /// the same
+    Rect(XY, ResXY),
+    /// Each pixel counts by the luma of the same pixel in this image, as
+    /// stored, times its alpha, so opaque white is all of it and black or
+    /// transparent none of it
+    Image(&'a Image),
+}
+
+/// A color and its weight, for [`median_cut`]
+type Weighted = ([f32; 3], f32);
+
+/// Weighted mean of `items`, or `None` if they weigh nothing
+fn mean(items: &[Weighted]) -> Option<[f32; 3]> {
+    let total: f32 = items.iter().map(|(_, w)| w).sum();
+    if total <= 0. {
+        return None;
+    }
+    let mut sum = [0.; 3];
+    for (c, w) in items {
+        for i in 0..3 {
+            sum[i] += c[i] * w;
+        }
+    }
+    Some(sum.map(|s| s / total))
+}
+
+/// Split `items` into at most `n` boxes of similar colors, returning each
+/// box's mean and weight, heaviest first
+fn median_cut(items: Vec<Weighted>, n: usize) -> Vec<(WorkPixel, f32)> {
+    let total: f32 = items.iter().map(|(_, w)| w).sum();
+    if items.is_empty() || n == 0 || total <= 0. {
+        return Vec::new();
+    }
+    let mut boxes = vec![items];
+    while boxes.len() < n {
+        // The box with the widest channel, and that channel
+        let range = |b: &[Weighted], c: usize| {
+            let (lo, hi) = b.iter().fold((f32::MAX, f32::MIN), |(lo, hi), (p, _)| {
+                (lo.min(p[c]), hi.max(p[c]))
+            });
+            hi - lo
+        };
+        let Some((i, c, _)) = boxes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, b)| (0..3).map(move |c| (i, c, range(b, c))))
+            .filter(|(_, _, r)| *r > 0.)
+            .max_by(|a, b| a.2.total_cmp(&b.2))
+        else {
+            break;
+        };
+        let mut b = boxes.swap_remove(i);
+        b.sort_by(|x, y| x.0[c].total_cmp(&y.0[c]));
+        // Weighted median, keeping both halves non-empty
+        let half: f32 = b.iter().map(|(_, w)| w).sum::<f32>() / 2.;
+        let mut acc = 0.;
+        let mut split = b.len() - 1;
+        for (k, (_, w)) in b.iter().enumerate() {
+            acc += w;
+            if acc >= half {
+                split = k + 1;
+                break;
+            }
+        }
+        let split = split.clamp(1, b.len() - 1);
+        let rest = b.split_off(split);
+        boxes.push(b);
+        boxes.push(rest);
+    }
+    let mut out: Vec<(WorkPixel, f32)> = boxes
+        .iter()
+        .filter_map(|b| {
+            let w: f32 = b.iter().map(|(_, w)| w).sum();
+            mean(b).map(|[r, g, bl]| ([r, g, bl, 1.], w / total))
+        })
+        .collect();
+    out.sort_by(|a, b| b.1.total_cmp(&a.1));
+    out
+}
+
+impl Image {
+    /// Weight of each pixel under `mask`
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if a rectangle is empty
+    /// - [`ImageError::OutOfBounds`] if a rectangle isn't inside the image
+    /// - [`ImageError::DimensionMismatch`] if a mask image is a different
+    ///   size
+    pub(crate) fn mask_weights(&self, mask: StatsMask) -> Result<Vec<f32>, ImageError> {
+        match mask {
+            StatsMask::Rect(origin, size) => {
+                self.check_rect(origin, size)?;
+                let (x, y) = (origin.0..origin.0 + size.0, origin.1..origin.1 + size.1);
+                let w = self.width();
+                Ok((0..self.data.len() as u32)
+                    .map(|i| (x.contains(&(i % w)) && y.contains(&(i / w))) as u8 as f32)
+                    .collect())
+            }
+            StatsMask::Image(mask) => {
+                if mask.res != self.res {
+                    return Err(ImageError::DimensionMismatch);
+                }
+                let (alpha, transfer) = (mask.alpha, mask.color.transfer());
+                let to_straight = alpha_converter(alpha, AlphaMode::Straight);
+                Ok(mask
+                    .data
+                    .iter()
+                    .map(|p| {
+                        let w = pixel_luma(*p, alpha, transfer) * to_straight(*p)[3];
+                        if w.is_nan() {
+                            0.
+                        } else {
+                            w.clamp(0., 1.)
+                        }
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    /// Straight alpha pixels, decoded to linear light
+    fn linear_straight(&self) -> impl Iterator<Item = WorkPixel> + '_ {
+        let convert = alpha_converter(self.alpha, AlphaMode::Straight);
+        let decode = self.color.transfer().map(|t| t.0);
+        self.data.iter().map(move |p| {
+            let p = convert(*p);
+            match decode {
+                Some(f) => [f(p[0]), f(p[1]), f(p[2]), p[3]],
+                None => p,
+            }
+        })
+    }
+
+    /// Encode a linear `rgb` back to the image's color space
+    fn encode_rgb(&self, rgb: [f32; 3], a: f32) -> WorkPixel {
+        match self.color.transfer() {
+            Some((_, f)) => [f(rgb[0]), f(rgb[1]), f(rgb[2]), a],
+            None => [rgb[0], rgb[1], rgb[2], a],
+        }
+    }
+
+    /// Mean color, averaged in linear light, with straight alpha
+    ///
+    /// Every pixel counts the same, even fully transparent ones, see
+    /// [`Image::average_color_weighted`]. Empty images are transparent black.
+    pub fn average_color(&self) -> WorkPixel {
+        let n = self.data.len().max(1) as f32;
+        let mut sum = [0.; 4];
+        for p in self.linear_straight() {
+            for i in 0..4 {
+                sum[i] += p[i];
+            }
+        }
+        let [r, g, b, a] = sum.map(|s| s / n);
+        self.encode_rgb([r, g, b], a)
+    }
+
+    /// Like [`Image::average_color`], with each pixel counting by its
+    /// weight in `mask`, or `None` if nothing does
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if a rectangle is empty
+    /// - [`ImageError::OutOfBounds`] if a rectangle isn't inside the image
+    /// - [`ImageError::DimensionMismatch`] if a mask image is a different
+    ///   size
+    pub fn average_color_masked(&self, mask: StatsMask) -> Result<Option<WorkPixel>, ImageError> {
+        let weights = self.mask_weights(mask)?;
+        let (mut sum, mut total) = ([0.; 4], 0.);
+        for (p, w) in self.linear_straight().zip(weights).filter(|(_, w)| *w > 0.) {
+            for i in 0..4 {
+                sum[i] += p[i] * w;
+            }
+            total += w;
+        }
+        if total <= 0. {
+            return Ok(None);
+        }
+        let [r, g, b, a] = sum.map(|s| s / total);
+        Ok(Some(self.encode_rgb([r, g, b], a)))
+    }
+
+    /// Mean relative luminance, in linear light, from straight alpha color
+    ///
+    /// Alpha doesn't count, every pixel counts the same. Empty images are 0.
+    pub fn average_luminance(&self) -> f32 {
+        let n = self.data.len().max(1) as f32;
+        let sum: f32 = self
+            .linear_straight()
+            .map(|p| luminance([p[0], p[1], p[2]]))
+            .sum();
+        sum / n
+    }
+
+    /// Like [`Image::average_luminance`], with each pixel counting by its
+    /// weight in `mask`, or `None` if nothing does
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if a rectangle is empty
+    /// - [`ImageError::OutOfBounds`] if a rectangle isn't inside the image
+    /// - [`ImageError::DimensionMismatch`] if a mask image is a different
+    ///   size
+    pub fn average_luminance_masked(&self, mask: StatsMask) -> Result<Option<f32>, ImageError> {
+        let weights = self.mask_weights(mask)?;
+        let (mut sum, mut total) = (0., 0.);
+        for (p, w) in self.linear_straight().zip(weights).filter(|(_, w)| *w > 0.) {
+            sum += luminance([p[0], p[1], p[2]]) * w;
+            total += w;
+        }
+        Ok((total > 0.).then(|| sum / total))
+    }
+
+    /// Like [`Image::average_color`], but each pixel's color counts by its
+    /// alpha, so transparent areas don't leak into the result
+    ///
+    /// Alpha is still the plain mean. Fully transparent images are
+    /// transparent black.
+    pub fn average_color_weighted(&self) -> WorkPixel {
+        let items: Vec<Weighted> = self
+            .linear_straight()
+            .map(|p| ([p[0], p[1], p[2]], p[3]))
+            .collect();
+        let a = items.iter().map(|(_, w)| w).sum::<f32>() / items.len().max(1) as f32;
+        match mean(&items) {
+            Some(rgb) => self.encode_rgb(rgb, a),
+            None => [0.; 4],
+        }
+    }
+
+    /// Up to `n` representative colors by median cut, with the fraction of
+    /// the image each covers, largest first
+    ///
+    /// Colors are opaque, in the image's color space. Every pixel counts the
+    /// same, see [`Image::dominant_colors_weighted`].
+    pub fn dominant_colors(&self, n: usize) -> Vec<(WorkPixel, f32)> {
+        let items = self.linear_straight().map(|p| ([p[0], p[1], p[2]], 1.));
+        self.encode_palette(median_cut(items.collect(), n))
+    }
+
+    /// Like [`Image::dominant_colors`], but each pixel counts by its alpha,
+    /// so fully transparent pixels are ignored
+    ///
+    /// Fractions are of the total alpha. Fully transparent images have no
+    /// colors at all.
+    pub fn dominant_colors_weighted(&self, n: usize) -> Vec<(WorkPixel, f32)> {
+        let items = self
+            .linear_straight()
+            .filter(|p| p[3] > 0.)
+            .map(|p| ([p[0], p[1], p[2]], p[3]));
+        self.encode_palette(median_cut(items.collect(), n))
+    }
+
+    fn encode_palette(&self, palette: Vec<(WorkPixel, f32)>) -> Vec<(WorkPixel, f32)> {
+        palette
+            .into_iter()
+            .map(|(p, w)| (self.encode_rgb([p[0], p[1], p[2]], 1.), w))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{photo, solid},
+        ColorSpace,
+    };
+
+    /// Opaque red on the left, transparent green on the right
+    fn half_red() -> Image {
+        let mut img = solid((8, 4), [0., 1., 0., 0.]);
+        img.map_pixels_indexed(|(x, _), p| if x < 4 { [1., 0., 0., 1.] } else { p });
+        img
+    }
+
+    fn close(a: WorkPixel, b: WorkPixel) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5)
+    }
+
+    fn assert_close(a: WorkPixel, b: WorkPixel) {
+        assert!(close(a, b), "{a:?} {b:?}");
+    }
+
+    #[test]
+    fn weighted_average_ignores_transparent() {
+        let img = half_red();
+        assert_close(img.average_color_weighted(), [1., 0., 0., 0.5]);
+        let plain = img.average_color();
+        assert!(plain[1] > 0.5, "{plain:?}");
+        assert!((plain[3] - 0.5).abs() < 1e-6);
+        // In linear light
+        let half = solid((2, 1), [0.5, 0.5, 0.5, 0.5]);
+        assert_close(half.average_color_weighted(), [0.5; 4]);
+    }
+
+    #[test]
+    fn weighted_dominant_ignores_transparent() {
+        let img = half_red();
+        let colors = img.dominant_colors_weighted(4);
+        assert_eq!(colors.len(), 1);
+        assert_close(colors[0].0, [1., 0., 0., 1.]);
+        assert!((colors[0].1 - 1.).abs() < 1e-6);
+
+        let colors = img.dominant_colors(4);
+        assert_eq!(colors.len(), 2);
+        assert!(colors.iter().all(|(_, w)| (w - 0.5).abs() < 1e-6));
+        assert!(colors.iter().any(|(c, _)| c[1] > 0.99));
+    }
+
+    #[test]
+    fn dominant_fractions() {
+        let mut img = solid((4, 4), [0., 0., 1., 1.]);
+        img.map_pixels_indexed(|(x, _), p| if x > 1 { [1., 1., 0., 1.] } else { p });
+        for n in [2, 8] {
+            // Stops once every box is one color
+            let colors = img.dominant_colors(n);
+            assert_eq!(colors.len(), 2);
+            assert!(colors.iter().all(|(_, w)| (w - 0.5).abs() < 1e-6));
+            assert!(colors.iter().any(|(c, _)| close(*c, [1., 1., 0., 1.])));
+            assert!(colors.iter().any(|(c, _)| close(*c, [0., 0., 1., 1.])));
+        }
+        let colors = img.dominant_colors(1);
+        assert_close(colors[0].0, img.average_color());
+        assert_eq!(colors[0].1, 1.);
+        assert_eq!(img.dominant_colors(0), []);
+    }
+
+    #[test]
+    fn all_transparent() {
+        let img = solid((4, 4), [0.3, 0.6, 0.9, 0.]);
+        assert_eq!(img.average_color_weighted(), [0.; 4]);
+        assert_eq!(img.dominant_colors_weighted(3), []);
+        assert!(img.average_color().iter().all(|c| c.is_finite()));
+        let empty = solid((0, 0), [0.; 4]);
+        assert_eq!(empty.average_color(), [0.; 4]);
+        assert_eq!(empty.average_color_weighted(), [0.; 4]);
+        assert_eq!(empty.dominant_colors(2), []);
+    }
+
+    fn cropped(img: &Image, origin: XY, size: ResXY) -> Image {
+        let mut img = img.clone();
+        img.crop(origin, size).unwrap();
+        img
+    }
+
+    /// A linear mask of `res`, `left` on the left half and `right` on the
+    /// right
+    fn halves(res: ResXY, left: f32, right: f32) -> Image {
+        let mut mask = solid(res, [0.; 4]);
+        mask.color = ColorSpace::sRGBLinear;
+        let half = res.0 / 2;
+        mask.map_pixels_indexed(|(x, _), _| {
+            let v = if x < half { left } else { right };
+            [v, v, v, 1.]
+        });
+        mask
+    }
+
+    #[test]
+    fn rect_matches_crop() {
+        let img = photo((16, 12));
+        for (origin, size) in [((3, 2), (7, 5)), ((0, 0), (16, 12)), ((15, 11), (1, 1))] {
+            let mask = StatsMask::Rect(origin, size);
+            let crop = cropped(&img, origin, size);
+            let got = img.average_color_masked(mask).unwrap().unwrap();
+            assert_close(got, crop.average_color());
+            let got = img.average_luminance_masked(mask).unwrap().unwrap();
+            assert!((got - crop.average_luminance()).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn binary_mask_matches_the_included_half() {
+        let img = photo((16, 12));
+        let left = cropped(&img, (0, 0), (8, 12));
+        let right = cropped(&img, (8, 0), (8, 12));
+        for (mask, want) in [
+            (halves(img.res, 1., 0.), &left),
+            (halves(img.res, 0., 1.), &right),
+        ] {
+            let mask = StatsMask::Image(&mask);
+            assert_close(
+                img.average_color_masked(mask).unwrap().unwrap(),
+                want.average_color(),
+            );
+            let got = img.average_luminance_masked(mask).unwrap().unwrap();
+            assert!((got - want.average_luminance()).abs() < 1e-5);
+        }
+        // Transparent white counts for nothing, in either alpha mode
+        let mut mask = halves(img.res, 1., 1.);
+        mask.map_pixels_indexed(|(x, _), p| if x < 8 { p } else { [1., 1., 1., 0.] });
+        assert_close(
+            img.average_color_masked(StatsMask::Image(&mask))
+                .unwrap()
+                .unwrap(),
+            left.average_color(),
+        );
+        mask.to_alpha_mode(AlphaMode::Premultiplied);
+        assert_close(
+            img.average_color_masked(StatsMask::Image(&mask))
+                .unwrap()
+                .unwrap(),
+            left.average_color(),
+        );
+    }
+
+    #[test]
+    fn fractional_mask_interpolates() {
+        let mut img = photo((16, 12));
+        img.color = ColorSpace::sRGBLinear;
+        let (left, right) = (
+            cropped(&img, (0, 0), (8, 12)).average_color(),
+            cropped(&img, (8, 0), (8, 12)).average_color(),
+        );
+        for f in [0.25, 0.5, 0.75] {
+            let mask = halves(img.res, 1., f);
+            let got = img
+                .average_color_masked(StatsMask::Image(&mask))
+                .unwrap()
+                .unwrap();
+            let want: WorkPixel = core::array::from_fn(|c| (left[c] + f * right[c]) / (1. + f));
+            assert_close(got, want);
+            // Strictly between the halves, when they differ
+            for c in 0..3 {
+                let (lo, hi) = (left[c].min(right[c]), left[c].max(right[c]));
+                assert!((lo..=hi).contains(&got[c]));
+            }
+        }
+        // Scaling every weight changes nothing
+        let (a, b) = (halves(img.res, 1., 0.5), halves(img.res, 0.5, 0.25));
+        assert_close(
+            img.average_color_masked(StatsMask::Image(&a))
+                .unwrap()
+                .unwrap(),
+            img.average_color_masked(StatsMask::Image(&b))
+                .unwrap()
+                .unwrap(),
+        );
+    }
+
+    #[test]
+    fn zero_mask_is_none() {
+        let img = photo((16, 12));
+        let black = halves(img.res, 0., 0.);
+        let mask = StatsMask::Image(&black);
+        assert_eq!(img.average_color_masked(mask), Ok(None));
+        assert_eq!(img.average_luminance_masked(mask), Ok(None));
+        let mut nan = halves(img.res, 0., 0.);
+        nan.data[0] = [f32::NAN, 0., 0., 1.];
+        assert_eq!(img.average_color_masked(StatsMask::Image(&nan)), Ok(None));
+    }
+
+    #[test]
+    fn mask_errors() {
+        let img = photo((16, 12));
+        let err = |mask| img.average_color_masked(mask).err();
+        assert_eq!(
+            err(StatsMask::Rect((2, 2), (0, 3))),
+            Some(ImageError::InvalidArgument)
+        );
+        assert_eq!(
+            err(StatsMask::Rect((10, 2), (7, 3))),
+            Some(ImageError::OutOfBounds)
+        );
+        let small = halves((8, 12), 1., 1.);
+        assert_eq!(
+            err(StatsMask::Image(&small)),
+            Some(ImageError::DimensionMismatch)
+        );
+        assert_eq!(
+            img.average_luminance_masked(StatsMask::Image(&small)).err(),
+            Some(ImageError::DimensionMismatch)
+        );
+    }
+
+    #[test]
+    fn average_luminance() {
+        let img = solid((4, 4), [0.5, 0.5, 0.5, 0.25]);
+        let want = crate::transforms::srgb_to_rgb(0.5);
+        assert!((img.average_luminance() - want).abs() < 1e-5);
+        let empty = Image::from_bytes(&[], (0, 0), ColorSpace::sRGB);
+        assert_eq!(empty.average_luminance(), 0.);
+    }
+}
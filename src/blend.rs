@@ -0,0 +1,106 @@
+//! Which space drawing blends coverage in, see [`BlendSpace`]
+use crate::{ColorSpace, Image, Transfer};
+
+/// Where drawing primitives blend anti-aliased coverage and partial alpha
+///
+/// Blending encoded values makes dark on light text and shapes look too
+/// heavy, and light on dark too thin, since half encoded isn't half as
+/// bright. [`BlendSpace::Linear`] decodes the destination first, blends, and
+/// encodes again, which is correct but slower. Color spaces without a
+/// transfer function blend the same either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendSpace {
+    /// Blend the stored values directly
+    Encoded,
+    /// Blend in linear light
+    Linear,
+}
+
+impl BlendSpace {
+    /// The default for images in `color`, [`BlendSpace::Encoded`] for
+    /// [`ColorSpace::AsIs`], and [`BlendSpace::Linear`] otherwise
+    pub fn for_color(color: ColorSpace) -> Self {
+        match color {
+            ColorSpace::AsIs => BlendSpace::Encoded,
+            _ => BlendSpace::Linear,
+        }
+    }
+}
+
+impl Image {
+    /// The [`BlendSpace`] drawing uses when not given one, set with
+    /// [`Image::set_blend_space`] or [`BlendSpace::for_color`]
+    pub fn blend_space(&self) -> BlendSpace {
+        self.blend
+            .unwrap_or_else(|| BlendSpace::for_color(self.color))
+    }
+
+    /// Set the [`BlendSpace`] drawing uses when not given one, `None` for
+    /// [`BlendSpace::for_color`]
+    ///
+    /// This is kept by operations deriving new images, like cropping.
+    pub fn set_blend_space(&mut self, blend: Option<BlendSpace>) {
+        self.blend = blend;
+    }
+
+    /// Decode and encode functions for blending in `blend`
+    pub(crate) fn blend_transfer(&self, blend: BlendSpace) -> (Option<Transfer>, Option<Transfer>) {
+        match (blend, self.color.transfer()) {
+            (BlendSpace::Linear, Some((decode, encode))) => (Some(decode), Some(encode)),
+            _ => (None, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::solid;
+
+    /// Draw white over the left `cover` of a black pixel in `blend`
+    fn half(cover: f32, blend: BlendSpace) -> f32 {
+        let mut img = solid((1, 1), [0., 0., 0., 1.]);
+        let square = [(0., 0.), (cover, 0.), (cover, 1.), (0., 1.)];
+        img.fill_polygon_with(&square, [1., 1., 1., 1.], true, blend);
+        img.pixels()[0][0]
+    }
+
+    #[test]
+    fn coverage_blends_in_linear_light() {
+        let encode = ColorSpace::sRGB.transfer().unwrap().1;
+        let linear = half(0.5, BlendSpace::Linear);
+        assert!((linear - encode(0.5)).abs() < 1e-4, "{linear}");
+        assert!((linear - 0.735).abs() < 1e-3);
+        let encoded = half(0.5, BlendSpace::Encoded);
+        assert!((encoded - 0.5).abs() < 1e-4, "{encoded}");
+    }
+
+    #[test]
+    fn full_coverage_is_the_same() {
+        assert_eq!(half(1., BlendSpace::Linear), 1.);
+        assert_eq!(half(1., BlendSpace::Encoded), 1.);
+        assert_eq!(half(0., BlendSpace::Linear), 0.);
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(BlendSpace::for_color(ColorSpace::sRGB), BlendSpace::Linear);
+        assert_eq!(BlendSpace::for_color(ColorSpace::AsIs), BlendSpace::Encoded);
+        let mut img = solid((4, 4), [0., 0., 0., 1.]);
+        assert_eq!(img.blend_space(), BlendSpace::Linear);
+        img.set_blend_space(Some(BlendSpace::Encoded));
+        assert_eq!(img.blend_space(), BlendSpace::Encoded);
+        let square = [(0., 0.), (0.5, 0.), (0.5, 1.), (0., 1.)];
+        img.fill_polygon(&square, [1., 1., 1., 1.], true);
+        assert!((img.pixels()[0][0] - 0.5).abs() < 1e-4);
+        let derived = img.derive(img.data.clone(), img.res);
+        assert_eq!(derived.blend_space(), BlendSpace::Encoded);
+        img.set_blend_space(None);
+        assert_eq!(img.blend_space(), BlendSpace::Linear);
+        // Without a transfer function both are the same
+        let mut as_is = solid((1, 1), [0., 0., 0., 1.]);
+        as_is.color = ColorSpace::AsIs;
+        as_is.fill_polygon_with(&square, [1., 1., 1., 1.], true, BlendSpace::Linear);
+        assert!((as_is.pixels()[0][0] - 0.5).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,439 @@
+//! Converting and scaling between raw buffers without an [`Image`]
+//!
+//! [`Image`]: crate::Image
+use crate::{
+    layout::{prepare, validate_buffer},
+    scale::{corner_ratio, nearest},
+    AlphaMode, ColorSpace, Converter, ImageError, PixelFormat, ResXY, ScaleFilter, WorkPixel, F32,
+    XY,
+};
+
+/// Borrowed raw pixels with rows `stride` bytes apart, the source of
+/// [`convert_blit`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageRefRaw<'a> {
+    data: &'a [u8],
+    res: ResXY,
+    stride: usize,
+    format: PixelFormat,
+    color: ColorSpace,
+}
+
+impl<'a> ImageRefRaw<'a> {
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `stride` is smaller than a row
+    /// - [`ImageError::BufferSize`] if `data` is too small
+    pub fn new(
+        data: &'a [u8],
+        res: ResXY,
+        stride: usize,
+        format: PixelFormat,
+        color: ColorSpace,
+    ) -> Result<Self, ImageError> {
+        validate_buffer(data, format, res, stride)?;
+        Ok(Self {
+            data,
+            res,
+            stride,
+            format,
+            color,
+        })
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub const fn width(&self) -> u32 {
+        self.res.0
+    }
+
+    pub const fn height(&self) -> u32 {
+        self.res.1
+    }
+
+    pub const fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub const fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    pub const fn color(&self) -> ColorSpace {
+        self.color
+    }
+
+    /// Straight alpha pixel at `(x, y)`, which must be in bounds
+    fn get(&self, x: u32, y: u32) -> WorkPixel {
+        let bpp = self.format.bytes_per_pixel();
+        let i = y as usize * self.stride + x as usize * bpp;
+        self.format.decode(&self.data[i..i + bpp])
+    }
+}
+
+/// Mutably borrowed raw pixels with rows `stride` bytes apart, the
+/// destination of [`convert_blit`]
+#[derive(Debug, PartialEq, Eq)]
+pub struct ImageMutRaw<'a> {
+    data: &'a mut [u8],
+    res: ResXY,
+    stride: usize,
+    format: PixelFormat,
+    color: ColorSpace,
+}
+
+impl<'a> ImageMutRaw<'a> {
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `stride` is smaller than a row
+    /// - [`ImageError::BufferSize`] if `data` is too small
+    pub fn new(
+        data: &'a mut [u8],
+        res: ResXY,
+        stride: usize,
+        format: PixelFormat,
+        color: ColorSpace,
+    ) -> Result<Self, ImageError> {
+        validate_buffer(data, format, res, stride)?;
+        Ok(Self {
+            data,
+            res,
+            stride,
+            format,
+            color,
+        })
+    }
+
+    pub fn data(&mut self) -> &mut [u8] {
+        self.data
+    }
+
+    pub const fn width(&self) -> u32 {
+        self.res.0
+    }
+
+    pub const fn height(&self) -> u32 {
+        self.res.1
+    }
+
+    pub const fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub const fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    pub const fn color(&self) -> ColorSpace {
+        self.color
+    }
+}
+
+/// Pixels converted at once, on the stack
+const CHUNK: usize = 64;
+
+/// Whether the rectangle at `origin` of `size` is inside `res`
+fn contains(res: ResXY, (x, y): XY, (w, h): ResXY) -> bool {
+    x.checked_add(w).is_some_and(|x1| x1 <= res.0) && y.checked_add(h).is_some_and(|y1| y1 <= res.1)
+}
+
+/// Source index and weight of the next pixel for destination `d`, like
+/// [`ScaleFilter`] does it
+fn tap(filter: ScaleFilter, d: u32, src: u32, dst: u32) -> (u32, f32) {
+    match filter {
+        ScaleFilter::Bilinear => {
+            let s = d as f32 * corner_ratio(src, dst);
+            let start = (s.floor() as u32).min(src - 1);
+            let t = s - start as f32;
+            if start + 1 < src && t > 0. {
+                (start, t)
+            } else {
+                (start, 0.)
+            }
+        }
+        _ => (nearest(d, src, dst), 0.),
+    }
+}
+
+/// Copy the `src_rect` of `src`, or all of it, into the `dst_rect` of `dst`,
+/// converting the format and color space and scaling with `filter`
+///
+/// This works straight from the bytes of each, a few pixels at a time, and
+/// never allocates. The result is what [`Image::from_raw`],
+/// [`Image::scale_with`], [`Image::to_color`], and exporting to `dst`'s
+/// format would give. When the formats and color spaces are the same and
+/// the rectangles the same size, rows are copied as is.
+///
+/// Only [`ScaleFilter::Nearest`] and [`ScaleFilter::Bilinear`] are
+/// supported.
+///
+/// [`Image::from_raw`]: crate::Image::from_raw
+/// [`Image::scale_with`]: crate::Image::scale_with
+/// [`Image::to_color`]: crate::Image::to_color
+///
+/// # Errors
+///
+/// - [`ImageError::OutOfBounds`] if either rectangle isn't inside its image
+/// - [`ImageError::InvalidArgument`] if the source rectangle is empty but
+///   the destination isn't
+/// - [`ImageError::Unsupported`] for any other `filter`
+pub fn convert_blit(
+    src: ImageRefRaw,
+    dst: ImageMutRaw,
+    src_rect: Option<(XY, ResXY)>,
+    dst_rect: (XY, ResXY),
+    filter: ScaleFilter,
+) -> Result<(), ImageError> {
+    let (src_at, src_size) = src_rect.unwrap_or(((0, 0), src.res));
+    let (dst_at, dst_size) = dst_rect;
+    if !contains(src.res, src_at, src_size) || !contains(dst.res, dst_at, dst_size) {
+        return Err(ImageError::OutOfBounds);
+    }
+    if !matches!(filter, ScaleFilter::Nearest | ScaleFilter::Bilinear) {
+        return Err(ImageError::Unsupported);
+    }
+    if dst_size.0 == 0 || dst_size.1 == 0 {
+        return Ok(());
+    }
+    if src_size.0 == 0 || src_size.1 == 0 {
+        return Err(ImageError::InvalidArgument);
+    }
+
+    let (sbpp, dbpp) = (src.format.bytes_per_pixel(), dst.format.bytes_per_pixel());
+    if src.format == dst.format && src.color == dst.color && src_size == dst_size {
+        let len = src_size.0 as usize * sbpp;
+        for y in 0..src_size.1 as usize {
+            let s = (src_at.1 as usize + y) * src.stride + src_at.0 as usize * sbpp;
+            let d = (dst_at.1 as usize + y) * dst.stride + dst_at.0 as usize * dbpp;
+            dst.data[d..d + len].copy_from_slice(&src.data[s..s + len]);
+        }
+        return Ok(());
+    }
+
+    let converter = Converter::new(src.color, dst.color);
+    let transfer = dst.color.transfer();
+    let mut buf = [WorkPixel::default(); CHUNK];
+    let lerp = |a: WorkPixel, b: WorkPixel, t: f32| -> WorkPixel {
+        core::array::from_fn(|c| a[c] + (b[c] - a[c]) * t)
+    };
+    for dy in 0..dst_size.1 {
+        let (sy, ty) = tap(filter, dy, src_size.1, dst_size.1);
+        let sy = src_at.1 + sy;
+        let row = (dst_at.1 + dy) as usize * dst.stride + dst_at.0 as usize * dbpp;
+        for x0 in (0..dst_size.0).step_by(CHUNK) {
+            let n = (dst_size.0 - x0).min(CHUNK as u32);
+            for (dx, p) in (x0..x0 + n).zip(&mut buf) {
+                let (sx, tx) = tap(filter, dx, src_size.0, dst_size.0);
+                let sx = src_at.0 + sx;
+                let sample = |y: u32| {
+                    let a = src.get(sx, y);
+                    if tx > 0. {
+                        lerp(a, src.get(sx + 1, y), tx)
+                    } else {
+                        a
+                    }
+                };
+                let top = sample(sy);
+                *p = if ty > 0. {
+                    lerp(top, sample(sy + 1), ty)
+                } else {
+                    top
+                };
+            }
+            let chunk = &mut buf[..n as usize];
+            converter.convert_rows(chunk);
+            let out = &mut dst.data[row + x0 as usize * dbpp..][..n as usize * dbpp];
+            for (p, o) in chunk.iter().zip(out.chunks_exact_mut(dbpp)) {
+                dst.format
+                    .encode(prepare(*p, dst.format, AlphaMode::Straight, transfer), o);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{vec, vec::Vec};
+
+    use super::*;
+    use crate::{
+        fixtures::{noise, photo},
+        Image,
+    };
+
+    const SRC: ResXY = (17, 11);
+    const DST: ResXY = (20, 9);
+    const FILL: u8 = 0xa5;
+
+    /// `photo` as `format`, with `pad` bytes after every row
+    fn source(format: PixelFormat, color: ColorSpace, pad: usize) -> (Vec<u8>, usize) {
+        let mut img = photo(SRC);
+        img.map_pixels_indexed(|(x, _), p| [p[0], p[1], p[2], 0.4 + x as f32 / 30.]);
+        img.to_color(color);
+        let tight = img.to_raw(format);
+        let row = SRC.0 as usize * format.bytes_per_pixel();
+        let mut data = Vec::new();
+        for r in tight.chunks_exact(row) {
+            data.extend_from_slice(r);
+            data.extend((0..pad).map(|i| i as u8));
+        }
+        (data, row + pad)
+    }
+
+    /// The same through an [`Image`]
+    fn reference(
+        src: &ImageRefRaw,
+        src_rect: Option<(XY, ResXY)>,
+        size: ResXY,
+        filter: ScaleFilter,
+        format: PixelFormat,
+        color: ColorSpace,
+    ) -> Image {
+        let row = src.width() as usize * src.format().bytes_per_pixel();
+        let tight: Vec<u8> = src
+            .data()
+            .chunks(src.stride())
+            .flat_map(|r| &r[..row])
+            .copied()
+            .collect();
+        let mut img = Image::from_raw(&tight, src.res, src.format(), src.color()).unwrap();
+        if let Some((at, size)) = src_rect {
+            img.crop(at, size).unwrap();
+        }
+        img.scale_with(size, filter);
+        img.to_color(color);
+        Image::from_raw(&img.to_raw(format), size, format, color).unwrap()
+    }
+
+    #[test]
+    fn matches_the_image_pipeline() {
+        use PixelFormat::*;
+        let pairs = [
+            (Rgba8888, ColorSpace::sRGB, Rgb565Le, ColorSpace::sRGB),
+            (Rgb565Le, ColorSpace::sRGB, Rgb565Le, ColorSpace::sRGB),
+            (Bgr888, ColorSpace::sRGB, Rgba8888, ColorSpace::sRGBLinear),
+            (Gray8, ColorSpace::sRGB, Bgra8888, ColorSpace::sRGB),
+            (Rgba8888, ColorSpace::sRGBLinear, Rgba8888, ColorSpace::sRGB),
+        ];
+        let rects = [
+            (Some(((3, 2), (10, 7))), ((2, 1), (15, 5))),
+            (None, ((0, 0), DST)),
+            (Some(((16, 0), (1, 11))), ((5, 3), (4, 6))),
+        ];
+        for (sf, sc, df, dc) in pairs {
+            let (data, stride) = source(sf, sc, 3);
+            let src = ImageRefRaw::new(&data, SRC, stride, sf, sc).unwrap();
+            let dbpp = df.bytes_per_pixel();
+            let dstride = DST.0 as usize * dbpp + 5;
+            // One code of the coarsest channel
+            let tolerance = match df {
+                Rgb565Le => 1. / 31.,
+                _ => 1. / 255.,
+            } + 1e-5;
+            for filter in [ScaleFilter::Nearest, ScaleFilter::Bilinear] {
+                for (src_rect, (at, size)) in rects {
+                    let mut out = vec![FILL; dstride * DST.1 as usize];
+                    let dst = ImageMutRaw::new(&mut out, DST, dstride, df, dc).unwrap();
+                    convert_blit(src, dst, src_rect, (at, size), filter).unwrap();
+                    let want = reference(&src, src_rect, size, filter, df, dc);
+                    for (i, row) in out.chunks(dstride).enumerate() {
+                        let y = i as u32;
+                        for (x, px) in (0..DST.0).zip(row.chunks_exact(dbpp)) {
+                            let inside = (at.0..at.0 + size.0).contains(&x)
+                                && (at.1..at.1 + size.1).contains(&y);
+                            if !inside {
+                                assert!(px.iter().all(|b| *b == FILL));
+                                continue;
+                            }
+                            let (got, want) =
+                                (df.decode(px), want.get_pixel((x - at.0, y - at.1)).unwrap());
+                            for c in 0..4 {
+                                assert!(
+                                    (got[c] - want[c]).abs() <= tolerance,
+                                    "{sf:?} {df:?} {filter:?} {x} {y} {got:?} {want:?}"
+                                );
+                            }
+                        }
+                        assert!(row[DST.0 as usize * dbpp..].iter().all(|b| *b == FILL));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn same_format_is_copied() {
+        let mut seed = 9;
+        let stride = SRC.0 as usize * 2 + 2;
+        let data: Vec<u8> = (0..stride * SRC.1 as usize)
+            .map(|_| (noise(&mut seed) * 256.) as u8)
+            .collect();
+        let format = PixelFormat::Rgb565Be;
+        let src = ImageRefRaw::new(&data, SRC, stride, format, ColorSpace::sRGB).unwrap();
+        let mut out = vec![FILL; DST.0 as usize * 2 * DST.1 as usize];
+        let dst =
+            ImageMutRaw::new(&mut out, DST, DST.0 as usize * 2, format, ColorSpace::sRGB).unwrap();
+        let rect = ((4, 1), (9, 7));
+        convert_blit(
+            src,
+            dst,
+            Some(((8, 3), (9, 7))),
+            rect,
+            ScaleFilter::Bilinear,
+        )
+        .unwrap();
+        for y in 0..7 {
+            let s = (3 + y) * stride + 8 * 2;
+            let d = (1 + y) * DST.0 as usize * 2 + 4 * 2;
+            assert_eq!(out[d..d + 18], data[s..s + 18]);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let format = PixelFormat::Rgba8888;
+        let data = vec![0; 4 * 4 * 4];
+        let mut out = vec![0; 4 * 4 * 4];
+        assert_eq!(
+            ImageRefRaw::new(&data[1..], (4, 4), 16, format, ColorSpace::sRGB),
+            Err(ImageError::BufferSize {
+                expected: 64,
+                actual: 63
+            })
+        );
+        assert_eq!(
+            ImageRefRaw::new(&data, (4, 4), 15, format, ColorSpace::sRGB),
+            Err(ImageError::InvalidArgument)
+        );
+        let src = ImageRefRaw::new(&data, (4, 4), 16, format, ColorSpace::sRGB).unwrap();
+        let mut blit = |src_rect, dst_rect, filter| {
+            let dst = ImageMutRaw::new(&mut out, (4, 4), 16, format, ColorSpace::sRGB).unwrap();
+            convert_blit(src, dst, src_rect, dst_rect, filter)
+        };
+        let all = ((0, 0), (4, 4));
+        assert_eq!(
+            blit(Some(((1, 0), (4, 4))), all, ScaleFilter::Nearest),
+            Err(ImageError::OutOfBounds)
+        );
+        assert_eq!(
+            blit(None, ((3, 3), (2, 1)), ScaleFilter::Nearest),
+            Err(ImageError::OutOfBounds)
+        );
+        assert_eq!(
+            blit(None, ((u32::MAX, 0), (2, 1)), ScaleFilter::Nearest),
+            Err(ImageError::OutOfBounds)
+        );
+        assert_eq!(
+            blit(None, all, ScaleFilter::Box),
+            Err(ImageError::Unsupported)
+        );
+        assert_eq!(
+            blit(Some(((0, 0), (0, 2))), all, ScaleFilter::Nearest),
+            Err(ImageError::InvalidArgument)
+        );
+        assert_eq!(blit(None, ((1, 1), (0, 0)), ScaleFilter::Nearest), Ok(()));
+    }
+}
@@ -0,0 +1,522 @@
+//! Blurring and sharpening
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    alpha_converter,
+    border::Edge,
+    composite::{from_linear_premul, to_linear_premul},
+    fallible::try_with_capacity,
+    scale::Sample,
+    AlphaMode, Image, ImageError, ResXY, Scratch, WorkPixel, F32, XY,
+};
+
+/// Normalized Gaussian kernel for `sigma`, covering 3 sigma each side
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let radius = (sigma * 3.).ceil() as i64;
+    let mut k: Vec<f32> = (-radius..=radius)
+        .map(|i| (-((i * i) as f32) / (2. * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = k.iter().sum();
+    k.iter_mut().for_each(|w| *w /= sum);
+    k
+}
+
+/// Separable Gaussian blur of `data`, with edges clamped
+///
+/// `sigma` must be positive.
+pub(crate) fn gaussian_blur_buffer<T: Sample>(data: &[T], (w, h): ResXY, sigma: f32) -> Vec<T> {
+    profile!(Convolve);
+    let kernel = gaussian_kernel(sigma);
+    let radius = (kernel.len() / 2) as i64;
+    let (w, h) = (w as i64, h as i64);
+    let pass = |src: &[T], step: (i64, i64)| -> Vec<T> {
+        let mut out = vec![T::default(); src.len()];
+        for y in 0..h {
+            for x in 0..w {
+                let mut acc = T::default();
+                for (i, k) in kernel.iter().enumerate() {
+                    let o = i as i64 - radius;
+                    let sx = Edge::Replicate.index(x + o * step.0, w);
+                    let sy = Edge::Replicate.index(y + o * step.1, h);
+                    acc = acc.mul_add(src[(sy * w + sx) as usize], *k);
+                }
+                out[(y * w + x) as usize] = acc;
+            }
+        }
+        out
+    };
+    pass(&pass(data, (1, 0)), (0, 1))
+}
+
+impl Image {
+    /// Gaussian blur with standard deviation `sigma`, in pixels
+    ///
+    /// This works in linear light with premultiplied alpha, so transparent
+    /// pixels don't bleed their color. A `sigma` of zero or less does nothing.
+    pub fn gaussian_blur(&mut self, sigma: f32) {
+        if sigma <= 0. || self.data.is_empty() {
+            return;
+        }
+        let straight = self.alpha == AlphaMode::Straight;
+        self.in_linear(|img| {
+            if straight {
+                img.to_alpha_mode(AlphaMode::Premultiplied);
+            }
+            img.data = gaussian_blur_buffer(&img.data, img.res, sigma);
+            if straight {
+                img.to_alpha_mode(AlphaMode::Straight);
+            }
+        });
+    }
+
+    /// [`Image::gaussian_blur`], with its temporaries in `scratch`, or one
+    /// allocation the size of the image without
+    ///
+    /// With `scratch` the image is blurred in bands of full rows, as many
+    /// as fit, each reading the rows around it the kernel reaches. Only the
+    /// rows a band needs are ever held, so this works on images far larger
+    /// than `scratch`. The result is exactly that of
+    /// [`Image::gaussian_blur`].
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::BufferSize`] if `scratch` can't hold a band of one
+    ///   row, `width * (2 * ceil(3 * sigma) + 2) * 16` bytes or less for
+    ///   short images, with the smallest it could be as `expected`
+    /// - [`ImageError::OutOfMemory`] if there's no `scratch` and the
+    ///   allocation fails
+    pub fn gaussian_blur_tiled(
+        &mut self,
+        sigma: f32,
+        scratch: Option<&mut Scratch>,
+    ) -> Result<(), ImageError> {
+        if sigma <= 0. || self.data.is_empty() {
+            return Ok(());
+        }
+        profile!(Convolve);
+        let kernel = gaussian_kernel(sigma);
+        let radius = kernel.len() / 2;
+        let (w, h) = (self.width() as usize, self.height() as usize);
+        // Rows of the window, plus the row being read
+        let rows = |band: usize| (band + 2 * radius).min(h) + 1;
+
+        let mut owned;
+        let (pixels, band) = match scratch {
+            Some(scratch) => {
+                let pixels = scratch.pixels(rows(1) * w)?;
+                let band = (pixels.len() / w - 1)
+                    .saturating_sub(2 * radius)
+                    .clamp(1, h);
+                (pixels, band)
+            }
+            None => {
+                owned = try_with_capacity(rows(h) * w)?;
+                owned.resize(rows(h) * w, [0.; 4]);
+                (&mut owned[..], h)
+            }
+        };
+        let (window, row) = pixels.split_at_mut((rows(band) - 1) * w);
+        let row = &mut row[..w];
+
+        // The same steps as `gaussian_blur`, a pixel at a time
+        let transfer = self.color.transfer();
+        let (decode, encode) = (transfer.map(|t| t.0), transfer.map(|t| t.1));
+        let premultiply = alpha_converter(self.alpha, AlphaMode::Premultiplied);
+        let back = alpha_converter(AlphaMode::Premultiplied, self.alpha);
+        let tap = |i: usize, o: usize, len: usize| {
+            Edge::Replicate.index(i as i64 + o as i64 - radius as i64, len as i64) as usize
+        };
+
+        // Source row of the start of `window`, and how many rows it holds
+        let (mut top, mut filled) = (0, 0);
+        for y0 in (0..h).step_by(band) {
+            let y1 = (y0 + band).min(h);
+            let (start, end) = (y0.saturating_sub(radius), (y1 + radius).min(h));
+            let drop = start - top;
+            window.copy_within(drop * w..filled * w, 0);
+            (top, filled) = (start, filled - drop);
+
+            // Rows past the previous band haven't been written yet
+            while top + filled < end {
+                let src = &self.data[(top + filled) * w..][..w];
+                for (r, p) in row.iter_mut().zip(src) {
+                    let p = decode.map_or(*p, |f| [f(p[0]), f(p[1]), f(p[2]), p[3]]);
+                    *r = premultiply(p);
+                }
+                let out = &mut window[filled * w..][..w];
+                for (x, o) in out.iter_mut().enumerate() {
+                    let mut acc = WorkPixel::default();
+                    for (i, k) in kernel.iter().enumerate() {
+                        acc = acc.mul_add(row[tap(x, i, w)], *k);
+                    }
+                    *o = acc;
+                }
+                filled += 1;
+            }
+
+            for y in y0..y1 {
+                for x in 0..w {
+                    let mut acc = WorkPixel::default();
+                    for (i, k) in kernel.iter().enumerate() {
+                        acc = acc.mul_add(window[(tap(y, i, h) - top) * w + x], *k);
+                    }
+                    let p = back(acc);
+                    self.data[y * w + x] = encode.map_or(p, |f| [f(p[0]), f(p[1]), f(p[2]), p[3]]);
+                }
+            }
+        }
+        self.check();
+        Ok(())
+    }
+
+    /// Sharpen by adding back `amount` times the difference from a blurred
+    /// copy
+    ///
+    /// The copy is blurred with [`Image::gaussian_blur`] using `sigma`.
+    /// Channels where the difference is no more than `threshold` are left
+    /// exactly as they were, so noise in flat areas isn't amplified.
+    ///
+    /// This works in linear light, and sharpened channels are clamped to
+    /// `0..=1`. Alpha is untouched.
+    pub fn unsharp_mask(&mut self, sigma: f32, amount: f32, threshold: f32) {
+        if amount == 0. || sigma <= 0. || self.data.is_empty() {
+            return;
+        }
+        let transfer = self.color.transfer();
+        let mut linear = self.data.clone();
+        if let Some((decode, _)) = transfer {
+            crate::transforms::apply_transfer(&mut linear, decode);
+        }
+        let blurred = gaussian_blur_buffer(&linear, self.res, sigma);
+        for ((p, l), b) in self.data.iter_mut().zip(&linear).zip(&blurred) {
+            for c in 0..3 {
+                let d = l[c] - b[c];
+                if d.abs() > threshold {
+                    let v = (l[c] + d * amount).clamp(0., 1.);
+                    p[c] = transfer.map_or(v, |(_, encode)| encode(v));
+                }
+            }
+        }
+    }
+
+    /// Frosted glass panel, blur and dim the rectangle at `origin` of `size`
+    /// with rounded corners
+    ///
+    /// The blur is [`Image::gaussian_blur`] with `blur_sigma`, and reads
+    /// pixels around the rectangle too, so the edges look right. Color is
+    /// then multiplied by `1 - dim`. The effect is masked to a rounded
+    /// rectangle with `corner_radius`, anti-aliased, and pixels outside it are
+    /// left exactly as they were.
+    ///
+    /// The rectangle is clipped to the image, though the corners stay where
+    /// they would be unclipped.
+    pub fn frost_region(
+        &mut self,
+        origin: XY,
+        size: ResXY,
+        blur_sigma: f32,
+        dim: f32,
+        corner_radius: u32,
+    ) {
+        let (x0, y0) = origin;
+        let x1 = x0.saturating_add(size.0).min(self.width());
+        let y1 = y0.saturating_add(size.1).min(self.height());
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+        let transfer = self.color.transfer();
+        let (decode, encode) = (transfer.map(|t| t.0), transfer.map(|t| t.1));
+
+        // The rectangle plus enough around it for the kernel
+        let pad = if blur_sigma > 0. {
+            (blur_sigma * 3.).ceil() as u32
+        } else {
+            0
+        };
+        let (px0, py0) = (x0.saturating_sub(pad), y0.saturating_sub(pad));
+        let (px1, py1) = ((x1 + pad).min(self.width()), (y1 + pad).min(self.height()));
+        let pw = px1 - px0;
+        let mut patch = Vec::with_capacity((pw * (py1 - py0)) as usize);
+        for y in py0..py1 {
+            let row = &self.data[(y * self.width() + px0) as usize..][..pw as usize];
+            patch.extend(row.iter().map(|p| to_linear_premul(*p, decode, self.alpha)));
+        }
+        if blur_sigma > 0. {
+            patch = gaussian_blur_buffer(&patch, (pw, py1 - py0), blur_sigma);
+        }
+
+        let keep = 1. - dim.clamp(0., 1.);
+        let r = corner_radius.min(size.0 / 2).min(size.1 / 2) as f32;
+        let half = (size.0 as f32 / 2., size.1 as f32 / 2.);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                // Signed distance from the pixel center to the rounded rect
+                let qx = ((x - x0) as f32 + 0.5 - half.0).abs() - (half.0 - r);
+                let qy = ((y - y0) as f32 + 0.5 - half.1).abs() - (half.1 - r);
+                let outside = (qx.max(0.) * qx.max(0.) + qy.max(0.) * qy.max(0.)).sqrt();
+                let d = outside + qx.max(qy).min(0.) - r;
+                let cover = (0.5 - d).clamp(0., 1.);
+                if cover <= 0. {
+                    continue;
+                }
+
+                let i = (y * self.width() + x) as usize;
+                let b = patch[((y - py0) * pw + (x - px0)) as usize];
+                let b = [b[0] * keep, b[1] * keep, b[2] * keep, b[3]];
+                let p = if cover >= 1. {
+                    b
+                } else {
+                    let o = to_linear_premul(self.data[i], decode, self.alpha);
+                    o.lerp(b, cover)
+                };
+                self.data[i] = from_linear_premul(p, encode, self.alpha);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{noise, photo, solid},
+        ColorSpace,
+    };
+
+    /// Dark to light in the middle, blurred, in linear light
+    fn soft_edge() -> Image {
+        let mut img = solid((32, 4), [0.; 4]);
+        img.color = ColorSpace::sRGBLinear;
+        img.map_pixels_indexed(|(x, _), _| {
+            let v = if x < 16 { 0.2 } else { 0.8 };
+            [v, v, v, 0.5]
+        });
+        img.gaussian_blur(2.5);
+        img
+    }
+
+    /// Columns of row 0 between 10% and 90% of the way from dark to light
+    fn transition_width(img: &Image) -> usize {
+        img.row(0)
+            .unwrap()
+            .iter()
+            .filter(|p| p[0] > 0.26 && p[0] < 0.74)
+            .count()
+    }
+
+    #[test]
+    fn unsharp_steepens_edges() {
+        let mut img = soft_edge();
+        let before = transition_width(&img);
+        img.unsharp_mask(3., 1.5, 0.);
+        let after = transition_width(&img);
+        assert!(after < before, "{after} vs {before}");
+        assert!(img.pixels().iter().all(|p| p[3] == 0.5));
+    }
+
+    #[test]
+    fn unsharp_threshold_and_amount() {
+        // Flat with faint noise, all under the threshold
+        let mut seed = 3;
+        let mut img = solid((16, 16), [0.5; 4]);
+        img.map_pixels(|p| p.map(|c| c + (noise(&mut seed) - 0.5) * 0.004));
+        let before = img.clone();
+        img.unsharp_mask(2., 3., 0.01);
+        for (a, b) in img.pixels().iter().zip(before.pixels()) {
+            assert_eq!(a.map(f32::to_bits), b.map(f32::to_bits));
+        }
+
+        let mut img = photo((16, 16));
+        let before = img.clone();
+        img.unsharp_mask(2., 0., 0.);
+        assert_eq!(img.pixels(), before.pixels());
+    }
+
+    #[test]
+    fn unsharp_clamps() {
+        let mut img = solid((12, 12), [0.; 4]);
+        img.map_pixels_indexed(|(x, y), _| match (x + y) % 2 {
+            0 => [1., 0., 1., 1.],
+            _ => [0., 1., 0., 1.],
+        });
+        img.unsharp_mask(1., 10., 0.);
+        assert!(img
+            .pixels()
+            .iter()
+            .flatten()
+            .all(|c| (0. ..=1.).contains(c)));
+    }
+
+    #[test]
+    fn blur_keeps_flat() {
+        let mut img = solid((9, 7), [0.3, 0.6, 0.9, 1.]);
+        img.gaussian_blur(2.);
+        for p in img.pixels() {
+            for (a, b) in p.iter().zip([0.3, 0.6, 0.9, 1.]) {
+                assert!((a - b).abs() < 1e-5, "{p:?}");
+            }
+        }
+    }
+
+    const RECT: (XY, ResXY) = ((8, 6), (20, 14));
+
+    fn in_rect((x, y): XY, ((x0, y0), (w, h)): (XY, ResXY)) -> bool {
+        (x0..x0 + w).contains(&x) && (y0..y0 + h).contains(&y)
+    }
+
+    #[test]
+    fn frost_leaves_outside_alone() {
+        let img = photo((40, 30));
+        let mut out = img.clone();
+        out.frost_region(RECT.0, RECT.1, 2., 0.3, 5);
+        let w = img.width();
+        for (i, (a, b)) in img.data.iter().zip(&out.data).enumerate() {
+            let xy = (i as u32 % w, i as u32 / w);
+            if !in_rect(xy, RECT) {
+                assert_eq!(a, b, "{xy:?}");
+            }
+        }
+        // The corners of the rectangle are outside the rounding
+        for xy in [(8, 6), (27, 6), (8, 19), (27, 19), (9, 6), (8, 7)] {
+            assert_eq!(out.get_pixel(xy), img.get_pixel(xy), "{xy:?}");
+        }
+        assert_ne!(out.get_pixel((18, 13)), img.get_pixel((18, 13)));
+    }
+
+    #[test]
+    fn frost_blurs_and_dims() {
+        // A checkerboard, which blurs to its mean
+        let mut img = solid((40, 30), [0.; 4]);
+        img.map_pixels_indexed(|(x, y), _| {
+            let v = ((x + y) % 2) as f32;
+            [v, v, v, 1.]
+        });
+        img.to_color(ColorSpace::sRGBLinear);
+        img.frost_region(RECT.0, RECT.1, 2., 0.5, 5);
+        for y in 10..16 {
+            for x in 12..24 {
+                let p = img.get_pixel((x, y)).unwrap();
+                assert!((p[0] - 0.25).abs() < 0.01, "{x} {y} {p:?}");
+                assert!((p[3] - 1.).abs() < 1e-5);
+            }
+        }
+        // Untouched just outside
+        assert_eq!(img.get_pixel((7, 10)).unwrap()[0], 1.);
+    }
+
+    #[test]
+    fn frost_clips() {
+        let img = photo((40, 30));
+        let mut out = img.clone();
+        out.frost_region((30, 20), (20, 20), 3., 0.2, 4);
+        let w = img.width();
+        for (i, (a, b)) in img.data.iter().zip(&out.data).enumerate() {
+            let xy = (i as u32 % w, i as u32 / w);
+            if xy.0 < 30 || xy.1 < 20 {
+                assert_eq!(a, b, "{xy:?}");
+            }
+        }
+        // Corner of the clipped rectangle, nowhere near the rounding
+        assert_ne!(out.get_pixel((39, 29)), img.get_pixel((39, 29)));
+
+        let mut out = img.clone();
+        out.frost_region((40, 0), (5, 5), 3., 0.2, 0);
+        out.frost_region((0, 0), (0, 5), 3., 0.2, 0);
+        assert_eq!(out.pixels(), img.pixels());
+    }
+
+    /// `img` with noisy alpha, in `alpha`
+    fn translucent(res: ResXY, alpha: AlphaMode) -> Image {
+        let mut seed = 12;
+        let mut img = photo(res);
+        img.map_pixels(|p| [p[0], p[1], p[2], noise(&mut seed)]);
+        img.to_alpha_mode(alpha);
+        img
+    }
+
+    /// Bytes [`Image::gaussian_blur_tiled`] needs for a band of one row,
+    /// without alignment
+    fn band_bytes(res: ResXY, sigma: f32) -> usize {
+        let radius = gaussian_kernel(sigma).len() / 2;
+        let rows = (1 + 2 * radius).min(res.1 as usize) + 1;
+        rows * res.0 as usize * size_of::<WorkPixel>()
+    }
+
+    #[test]
+    fn tiled_blur_matches_whole() {
+        for (res, sigma) in [((23, 40), 1.5), ((17, 9), 3.), ((8, 3), 0.7), ((1, 30), 2.)] {
+            for alpha in [AlphaMode::Straight, AlphaMode::Premultiplied] {
+                let img = translucent(res, alpha);
+                let mut want = img.clone();
+                want.gaussian_blur(sigma);
+
+                let mut whole = img.clone();
+                whole.gaussian_blur_tiled(sigma, None).unwrap();
+                assert_eq!(whole.pixels(), want.pixels(), "{res:?} {sigma}");
+                let min = band_bytes(res, sigma);
+                // One row at a time, a few, and more than the whole image
+                for extra in [0, 1, 7, res.0 as usize * 16 * 3, 1 << 16] {
+                    let mut buf = vec![0xffu8; min + 16 + extra];
+                    let mut tiled = img.clone();
+                    let mut scratch = Scratch::new(&mut buf);
+                    tiled
+                        .gaussian_blur_tiled(sigma, Some(&mut scratch))
+                        .unwrap();
+                    assert_eq!(tiled.pixels(), want.pixels(), "{res:?} {sigma} {extra}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tiled_blur_reports_the_minimum() {
+        let img = photo((20, 30));
+        let mut buf = vec![0u8; 8192];
+        for len in [0, 100, 2000] {
+            let mut tiled = img.clone();
+            let mut scratch = Scratch::new(&mut buf[..len]);
+            let Err(ImageError::BufferSize { expected, actual }) =
+                tiled.gaussian_blur_tiled(2., Some(&mut scratch))
+            else {
+                panic!("{len}");
+            };
+            assert_eq!(actual, len);
+            assert!(expected >= band_bytes(img.res, 2.) && expected < band_bytes(img.res, 2.) + 16);
+            assert_eq!(tiled.pixels(), img.pixels());
+
+            // And that's enough
+            let mut scratch = Scratch::new(&mut buf[..expected]);
+            tiled.gaussian_blur_tiled(2., Some(&mut scratch)).unwrap();
+            let mut want = img.clone();
+            want.gaussian_blur(2.);
+            assert_eq!(tiled.pixels(), want.pixels());
+        }
+    }
+
+    #[test]
+    fn tiled_blur_misaligned_scratch() {
+        let img = translucent((13, 21), AlphaMode::Straight);
+        let mut want = img.clone();
+        want.gaussian_blur(1.2);
+        let mut buf = vec![0u8; band_bytes(img.res, 1.2) * 3 + 32];
+        for skip in 0..16 {
+            let mut tiled = img.clone();
+            let mut scratch = Scratch::new(&mut buf[skip..]);
+            tiled.gaussian_blur_tiled(1.2, Some(&mut scratch)).unwrap();
+            assert_eq!(tiled.pixels(), want.pixels(), "{skip}");
+        }
+    }
+
+    #[test]
+    fn tiled_blur_nothing_to_do() {
+        let img = photo((5, 5));
+        let mut tiled = img.clone();
+        let mut scratch = Scratch::new(&mut []);
+        assert!(scratch.is_empty());
+        tiled.gaussian_blur_tiled(0., Some(&mut scratch)).unwrap();
+        assert_eq!(tiled.pixels(), img.pixels());
+        let mut empty = Image::from_bytes(&[], (0, 4), ColorSpace::sRGB);
+        empty.gaussian_blur_tiled(2., Some(&mut scratch)).unwrap();
+        assert_eq!(empty.res, (0, 4));
+    }
+}
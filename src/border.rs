@@ -0,0 +1,231 @@
+//! Growing the canvas with a border
+use alloc::vec::Vec;
+
+use crate::{alpha_converter, AlphaMode, Image, WorkPixel};
+
+/// What [`Image::add_border`] fills the border with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BorderStyle {
+    /// A straight alpha color, in the image's color space
+    Solid(WorkPixel),
+    /// The nearest edge pixel, like convolution sees past the edge
+    Replicate,
+    /// The image reflected at its edges, with the edge pixels repeated
+    Mirror,
+    /// The image tiled, from the opposite edge
+    Wrap,
+}
+
+/// How pixels past the edge of an image are addressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Edge {
+    Replicate,
+    Mirror,
+    Wrap,
+}
+
+impl Edge {
+    /// The index in `0..len` that `i` reads from, `len` must be non zero
+    #[inline]
+    pub(crate) fn index(self, i: i64, len: i64) -> i64 {
+        match self {
+            Edge::Replicate => i.clamp(0, len - 1),
+            Edge::Mirror => {
+                let i = i.rem_euclid(2 * len);
+                if i < len {
+                    i
+                } else {
+                    2 * len - 1 - i
+                }
+            }
+            Edge::Wrap => i.rem_euclid(len),
+        }
+    }
+}
+
+impl Image {
+    /// Grow the canvas by `widths`, top, right, bottom, and left, filling
+    /// the new pixels with `style`
+    ///
+    /// The image moves to `(left, top)`. Empty images have nothing to
+    /// extend, so only [`BorderStyle::Solid`] fills them, the others give
+    /// transparent black.
+    ///
+    /// # Panics
+    ///
+    /// - If the new size doesn't fit in a `u32`
+    pub fn add_border(&mut self, widths: [u32; 4], style: BorderStyle) {
+        if widths == [0; 4] {
+            return;
+        }
+        let [top, right, bottom, left] = widths;
+        let (w, h) = self.res;
+        let grow = |len: u32, a: u32, b: u32| {
+            len.checked_add(a)
+                .and_then(|l| l.checked_add(b))
+                .expect("Border is too large")
+        };
+        let res = (grow(w, left, right), grow(h, top, bottom));
+
+        let edge = match style {
+            BorderStyle::Solid(_) => None,
+            BorderStyle::Replicate => Some(Edge::Replicate),
+            BorderStyle::Mirror => Some(Edge::Mirror),
+            BorderStyle::Wrap => Some(Edge::Wrap),
+        };
+        let fill = match style {
+            BorderStyle::Solid(p) => alpha_converter(AlphaMode::Straight, self.alpha)(p),
+            _ => WorkPixel::default(),
+        };
+        let (w, h) = (w as i64, h as i64);
+        let mut data = Vec::with_capacity(res.0 as usize * res.1 as usize);
+        for y in 0..res.1 as i64 {
+            let sy = y - top as i64;
+            for x in 0..res.0 as i64 {
+                let sx = x - left as i64;
+                let p = if (0..w).contains(&sx) && (0..h).contains(&sy) {
+                    self.data[(sy * w + sx) as usize]
+                } else {
+                    match edge {
+                        Some(e) if !self.data.is_empty() => {
+                            self.data[(e.index(sy, h) * w + e.index(sx, w)) as usize]
+                        }
+                        _ => fill,
+                    }
+                };
+                data.push(p);
+            }
+        }
+        self.data = data;
+        self.res = res;
+        self.check();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{photo, ramp};
+
+    const WIDTHS: [u32; 4] = [2, 3, 1, 4];
+    const STYLES: [BorderStyle; 4] = [
+        BorderStyle::Solid([1., 0., 0., 1.]),
+        BorderStyle::Replicate,
+        BorderStyle::Mirror,
+        BorderStyle::Wrap,
+    ];
+
+    fn at(img: &Image, x: u32, y: u32) -> WorkPixel {
+        img.get_pixel((x, y)).unwrap()
+    }
+
+    #[test]
+    fn grows_and_keeps_the_image() {
+        let src = ramp((5, 3));
+        for style in STYLES {
+            let mut img = src.clone();
+            img.add_border(WIDTHS, style);
+            assert_eq!(img.res, (5 + 3 + 4, 3 + 2 + 1), "{style:?}");
+            for y in 0..3 {
+                for x in 0..5 {
+                    assert_eq!(at(&img, x + 4, y + 2), at(&src, x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn solid_fills_the_border() {
+        let mut img = photo((5, 3));
+        img.to_alpha_mode(AlphaMode::Premultiplied);
+        let color = [1., 0.5, 0., 0.5];
+        img.add_border(WIDTHS, BorderStyle::Solid(color));
+        img.to_alpha_mode(AlphaMode::Straight);
+        for y in 0..6 {
+            for x in 0..12 {
+                let inside = (4..9).contains(&x) && (2..5).contains(&y);
+                assert_eq!(at(&img, x, y) == color, !inside, "{x} {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn replicate_extends_edges() {
+        let src = ramp((5, 3));
+        let mut img = src.clone();
+        img.add_border(WIDTHS, BorderStyle::Replicate);
+        let (w, h) = img.res;
+        // Corners are the nearest corner
+        for (corner, (x0, y0, x1, y1)) in [
+            ((0, 0), (0, 0, 4, 2)),
+            ((4, 0), (9, 0, w, 2)),
+            ((0, 2), (0, 5, 4, h)),
+            ((4, 2), (9, 5, w, h)),
+        ] {
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    assert_eq!(at(&img, x, y), at(&src, corner.0, corner.1));
+                }
+            }
+        }
+        // Sides are their edge pixel
+        for x in 0..5 {
+            assert_eq!(at(&img, x + 4, 0), at(&src, x, 0));
+            assert_eq!(at(&img, x + 4, 5), at(&src, x, 2));
+        }
+    }
+
+    #[test]
+    fn mirror_and_wrap() {
+        let src = ramp((5, 3));
+        let (mut mirror, mut wrap) = (src.clone(), src.clone());
+        mirror.add_border(WIDTHS, BorderStyle::Mirror);
+        wrap.add_border(WIDTHS, BorderStyle::Wrap);
+        // Left of x = 0 reads 0, 1, 2, 3 mirrored, and 4, 3, 2, 1 wrapped
+        for (i, (m, w)) in [(0, 4), (1, 3), (2, 2), (3, 1)].into_iter().enumerate() {
+            let x = 3 - i as u32;
+            assert_eq!(at(&mirror, x, 2), at(&src, m, 0));
+            assert_eq!(at(&wrap, x, 2), at(&src, w, 0));
+        }
+        // Right and above
+        assert_eq!(at(&mirror, 9, 2), at(&src, 4, 0));
+        assert_eq!(at(&mirror, 11, 1), at(&src, 2, 0));
+        assert_eq!(at(&wrap, 9, 2), at(&src, 0, 0));
+        assert_eq!(at(&wrap, 4, 0), at(&src, 0, 1));
+        assert_eq!(at(&mirror, 4, 0), at(&src, 0, 1));
+    }
+
+    #[test]
+    fn edge_indices() {
+        let got = |e: Edge| (-7..12).map(|i| e.index(i, 5)).collect::<Vec<_>>();
+        assert_eq!(
+            got(Edge::Replicate),
+            [0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 4, 4, 4, 4, 4, 4, 4]
+        );
+        assert_eq!(
+            got(Edge::Mirror),
+            [3, 4, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 4, 3, 2, 1, 0, 0, 1]
+        );
+        assert_eq!(
+            got(Edge::Wrap),
+            [3, 4, 0, 1, 2, 3, 4, 0, 1, 2, 3, 4, 0, 1, 2, 3, 4, 0, 1]
+        );
+    }
+
+    #[test]
+    fn zero_and_empty() {
+        let src = photo((5, 3));
+        for style in STYLES {
+            let mut img = src.clone();
+            img.add_border([0; 4], style);
+            assert_eq!((img.res, img.pixels()), (src.res, src.pixels()));
+        }
+        let mut empty = Image::from_bytes(&[], (0, 0), crate::ColorSpace::sRGB);
+        empty.add_border([1, 1, 1, 1], BorderStyle::Solid([1.; 4]));
+        assert_eq!(empty.res, (2, 2));
+        assert!(empty.pixels().iter().all(|p| *p == [1.; 4]));
+        let mut empty = Image::from_bytes(&[], (0, 0), crate::ColorSpace::sRGB);
+        empty.add_border([1, 0, 0, 1], BorderStyle::Mirror);
+        assert_eq!(empty.pixels(), [[0.; 4]]);
+    }
+}
@@ -0,0 +1,183 @@
+//! White points, primaries, and CIE xy chromaticity
+//!
+//! The built in color spaces build their matrices from the published
+//! primaries here, so they're the single source of truth.
+use nalgebra::{Matrix3, Vector3};
+
+/// CIE xy chromaticity coordinates
+pub type Xy = (f32, f32);
+
+/// A white point, as CIE xy chromaticity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WhitePoint {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl WhitePoint {
+    /// Noon daylight, the white of sRGB and Display P3
+    pub const D65: Self = Self::new(0.3127, 0.3290);
+
+    /// Horizon light, the white of ICC's connection space
+    pub const D50: Self = Self::new(0.3457, 0.3585);
+
+    /// Mid-morning daylight
+    pub const D55: Self = Self::new(0.3324, 0.3474);
+
+    /// Incandescent light
+    pub const A: Self = Self::new(0.44757, 0.40745);
+
+    /// Equal energy
+    pub const E: Self = Self::new(1. / 3., 1. / 3.);
+
+    pub const fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+
+    pub fn xy(self) -> Xy {
+        (self.x, self.y)
+    }
+
+    /// XYZ of the white point, with a luminance of `1`
+    pub fn xyz(self) -> [f32; 3] {
+        xy_to_xyz(self.x, self.y, 1.)
+    }
+}
+
+/// Red, green, and blue primaries of sRGB and Rec. 709
+pub const SRGB_PRIMARIES: [Xy; 3] = [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06)];
+
+/// Red, green, and blue primaries of Display P3, the same as DCI-P3
+pub const DISPLAY_P3_PRIMARIES: [Xy; 3] = [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060)];
+
+/// Red, green, and blue primaries of Adobe RGB (1998)
+pub const ADOBE_RGB_PRIMARIES: [Xy; 3] = [(0.64, 0.33), (0.21, 0.71), (0.15, 0.06)];
+
+/// XYZ of the chromaticity `(x, y)` with a luminance, `Y`, of `luminance`
+///
+/// A `y` of zero has no XYZ, and gives black.
+pub fn xy_to_xyz(x: f32, y: f32, luminance: f32) -> [f32; 3] {
+    if y == 0. {
+        return [0.; 3];
+    }
+    [x * luminance / y, luminance, (1. - x - y) * luminance / y]
+}
+
+/// Chromaticity of `xyz`, the inverse of [`xy_to_xyz`] apart from the
+/// luminance, which is just `xyz[1]`
+///
+/// Black has no chromaticity, and gives `(0, 0)`.
+pub fn xyz_to_xy(xyz: [f32; 3]) -> Xy {
+    let sum = xyz[0] + xyz[1] + xyz[2];
+    if sum == 0. {
+        return (0., 0.);
+    }
+    (xyz[0] / sum, xyz[1] / sum)
+}
+
+/// Matrix from linear RGB with the primaries `r_xy`, `g_xy`, and `b_xy` to
+/// XYZ, with RGB white at `white`
+///
+/// This is the standard derivation, the primaries' XYZ scaled so they sum
+/// to the white point. Degenerate primaries, all on one line, give zeros.
+pub fn primaries_to_rgb_xyz_matrix(
+    r_xy: Xy,
+    g_xy: Xy,
+    b_xy: Xy,
+    white: WhitePoint,
+) -> Matrix3<f32> {
+    // Twice the area of the gamut triangle, rounding can hide this from the
+    // inverse
+    let area = (g_xy.0 - r_xy.0) * (b_xy.1 - r_xy.1) - (b_xy.0 - r_xy.0) * (g_xy.1 - r_xy.1);
+    if area == 0. {
+        return Matrix3::zeros();
+    }
+    let [r, g, b] = [r_xy, g_xy, b_xy].map(|(x, y)| Vector3::from(xy_to_xyz(x, y, 1.)));
+    let m = Matrix3::from_columns(&[r, g, b]);
+    let Some(inv) = m.try_inverse() else {
+        return Matrix3::zeros();
+    };
+    let s = inv * Vector3::from(white.xyz());
+    Matrix3::from_columns(&[r * s[0], g * s[1], b * s[2]])
+}
+
+/// [`primaries_to_rgb_xyz_matrix`] for one of the `*_PRIMARIES`
+pub(crate) fn rgb_xyz_matrix(primaries: [Xy; 3], white: WhitePoint) -> Matrix3<f32> {
+    let [r, g, b] = primaries;
+    primaries_to_rgb_xyz_matrix(r, g, b, white)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_matrix(m: Matrix3<f32>, want: [[f32; 3]; 3]) {
+        for (r, row) in want.iter().enumerate() {
+            for (c, v) in row.iter().enumerate() {
+                assert!((m[(r, c)] - v).abs() < 1e-4, "{m} != {want:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_matrix_is_published() {
+        // IEC 61966-2-1
+        assert_matrix(
+            rgb_xyz_matrix(SRGB_PRIMARIES, WhitePoint::D65),
+            [
+                [0.4124, 0.3576, 0.1805],
+                [0.2126, 0.7152, 0.0722],
+                [0.0193, 0.1192, 0.9505],
+            ],
+        );
+        assert_matrix(
+            rgb_xyz_matrix(DISPLAY_P3_PRIMARIES, WhitePoint::D65),
+            [
+                [0.4866, 0.2657, 0.1982],
+                [0.2290, 0.6917, 0.0793],
+                [0.0000, 0.0451, 1.0439],
+            ],
+        );
+    }
+
+    #[test]
+    fn white_maps_to_white() {
+        for white in [
+            WhitePoint::D65,
+            WhitePoint::D50,
+            WhitePoint::A,
+            WhitePoint::E,
+        ] {
+            let m = rgb_xyz_matrix(SRGB_PRIMARIES, white);
+            let xyz = m * Vector3::new(1., 1., 1.);
+            let (x, y) = xyz_to_xy([xyz[0], xyz[1], xyz[2]]);
+            assert!((x - white.x).abs() < 1e-5 && (y - white.y).abs() < 1e-5);
+            assert!((xyz[1] - 1.).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn xy_round_trips() {
+        for (x, y) in [
+            WhitePoint::D65.xy(),
+            WhitePoint::A.xy(),
+            (0.64, 0.33),
+            (0.15, 0.06),
+        ] {
+            for luminance in [0.01, 0.5, 1., 80.] {
+                let xyz = xy_to_xyz(x, y, luminance);
+                assert!((xyz[1] - luminance).abs() < 1e-6);
+                let (x2, y2) = xyz_to_xy(xyz);
+                assert!((x2 - x).abs() < 1e-6 && (y2 - y).abs() < 1e-6, "{x} {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate() {
+        assert_eq!(xy_to_xyz(0.3, 0., 1.), [0.; 3]);
+        assert_eq!(xyz_to_xy([0.; 3]), (0., 0.));
+        let line = primaries_to_rgb_xyz_matrix((0.1, 0.1), (0.2, 0.2), (0.3, 0.3), WhitePoint::D65);
+        assert_eq!(line, Matrix3::zeros());
+    }
+}
@@ -0,0 +1,3 @@
+//! Color science building blocks, shared by the color spaces
+pub mod chromaticity;
+pub mod named;
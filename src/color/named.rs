@@ -0,0 +1,651 @@
+//! The CSS named colors, and parsing CSS colors
+//!
+//! The constants are straight alpha and sRGB encoded, as in CSS, so
+//! convert them with [`ColorSpace`](crate::ColorSpace) first to draw on
+//! linear images.
+use core::fmt;
+
+use crate::{ImageError, WorkPixel};
+
+/// Why [`parse_color`] rejected a string, see [`ImageError::InvalidColor`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorParseError {
+    /// There was nothing to parse
+    Empty,
+    /// A `#` color didn't have 3, 4, 6, or 8 digits, but this many
+    HexLength(usize),
+    /// A `#` color had something other than hex digits
+    HexDigit,
+    /// `rgb(...)` wasn't three components, separated by commas
+    Syntax,
+    /// A component of `rgb(...)` wasn't an integer in `0..=255`
+    Component,
+    /// Not a CSS color name
+    UnknownName,
+}
+
+impl fmt::Display for ColorParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorParseError::Empty => write!(f, "empty string"),
+            ColorParseError::HexLength(n) => {
+                write!(f, "hex color has {n} digits, expected 3, 4, 6, or 8")
+            }
+            ColorParseError::HexDigit => write!(f, "hex color has a non hex digit"),
+            ColorParseError::Syntax => write!(f, "expected rgb(r, g, b)"),
+            ColorParseError::Component => write!(f, "rgb component isn't an integer in 0..=255"),
+            ColorParseError::UnknownName => write!(f, "unknown color name"),
+        }
+    }
+}
+
+/// Parse a CSS color, `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`,
+/// `rgb(r, g, b)` with integer components, or one of the names here, in
+/// any case
+///
+/// The result is straight alpha and sRGB encoded, like the constants.
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidColor`] with what was wrong with `s`
+pub fn parse_color(s: &str) -> Result<WorkPixel, ImageError> {
+    parse(s.trim()).map_err(ImageError::InvalidColor)
+}
+
+fn parse(s: &str) -> Result<WorkPixel, ColorParseError> {
+    if s.is_empty() {
+        return Err(ColorParseError::Empty);
+    }
+    if let Some(hex) = s.strip_prefix('#') {
+        return parse_hex(hex);
+    }
+    if s.get(..4).is_some_and(|p| p.eq_ignore_ascii_case("rgb(")) {
+        return parse_rgb(&s[4..]);
+    }
+    let mut lower = [0u8; LONGEST];
+    let name = lower
+        .get_mut(..s.len())
+        .ok_or(ColorParseError::UnknownName)?;
+    name.copy_from_slice(s.as_bytes());
+    name.make_ascii_lowercase();
+    NAMES
+        .binary_search_by(|(n, _)| n.as_bytes().cmp(name))
+        .map(|i| NAMES[i].1)
+        .map_err(|_| ColorParseError::UnknownName)
+}
+
+fn parse_hex(hex: &str) -> Result<WorkPixel, ColorParseError> {
+    let mut digits = [0u8; 8];
+    let n = hex.len();
+    if ![3, 4, 6, 8].contains(&n) {
+        return Err(ColorParseError::HexLength(hex.chars().count()));
+    }
+    for (d, c) in digits.iter_mut().zip(hex.bytes()) {
+        *d = (c as char).to_digit(16).ok_or(ColorParseError::HexDigit)? as u8;
+    }
+    // Short forms repeat each digit, `f80` is `ff8800`
+    let channel = |i: usize| match n {
+        3 | 4 => digits[i] * 17,
+        _ => digits[i * 2] << 4 | digits[i * 2 + 1],
+    };
+    let a = if n == 4 || n == 8 { channel(3) } else { 255 };
+    Ok([channel(0), channel(1), channel(2), a].map(|c| c as f32 / 255.))
+}
+
+fn parse_rgb(args: &str) -> Result<WorkPixel, ColorParseError> {
+    let args = args.strip_suffix(')').ok_or(ColorParseError::Syntax)?;
+    let mut parts = args.split(',');
+    let mut rgb = [0.; 3];
+    for c in &mut rgb {
+        let part = parts.next().ok_or(ColorParseError::Syntax)?.trim();
+        let v: u8 = part.parse().map_err(|_| ColorParseError::Component)?;
+        *c = v as f32 / 255.;
+    }
+    if parts.next().is_some() {
+        return Err(ColorParseError::Syntax);
+    }
+    Ok([rgb[0], rgb[1], rgb[2], 1.])
+}
+
+const fn rgb(r: u8, g: u8, b: u8) -> WorkPixel {
+    [r as f32 / 255., g as f32 / 255., b as f32 / 255., 1.]
+}
+
+/// Fully transparent black, `rgba(0, 0, 0, 0)`
+pub const TRANSPARENT: WorkPixel = [0.; 4];
+
+/// `#f0f8ff`
+pub const ALICEBLUE: WorkPixel = rgb(240, 248, 255);
+/// `#faebd7`
+pub const ANTIQUEWHITE: WorkPixel = rgb(250, 235, 215);
+/// `#00ffff`
+pub const AQUA: WorkPixel = rgb(0, 255, 255);
+/// `#7fffd4`
+pub const AQUAMARINE: WorkPixel = rgb(127, 255, 212);
+/// `#f0ffff`
+pub const AZURE: WorkPixel = rgb(240, 255, 255);
+/// `#f5f5dc`
+pub const BEIGE: WorkPixel = rgb(245, 245, 220);
+/// `#ffe4c4`
+pub const BISQUE: WorkPixel = rgb(255, 228, 196);
+/// `#000000`
+pub const BLACK: WorkPixel = rgb(0, 0, 0);
+/// `#ffebcd`
+pub const BLANCHEDALMOND: WorkPixel = rgb(255, 235, 205);
+/// `#0000ff`
+pub const BLUE: WorkPixel = rgb(0, 0, 255);
+/// `#8a2be2`
+pub const BLUEVIOLET: WorkPixel = rgb(138, 43, 226);
+/// `#a52a2a`
+pub const BROWN: WorkPixel = rgb(165, 42, 42);
+/// `#deb887`
+pub const BURLYWOOD: WorkPixel = rgb(222, 184, 135);
+/// `#5f9ea0`
+pub const CADETBLUE: WorkPixel = rgb(95, 158, 160);
+/// `#7fff00`
+pub const CHARTREUSE: WorkPixel = rgb(127, 255, 0);
+/// `#d2691e`
+pub const CHOCOLATE: WorkPixel = rgb(210, 105, 30);
+/// `#ff7f50`
+pub const CORAL: WorkPixel = rgb(255, 127, 80);
+/// `#6495ed`
+pub const CORNFLOWERBLUE: WorkPixel = rgb(100, 149, 237);
+/// `#fff8dc`
+pub const CORNSILK: WorkPixel = rgb(255, 248, 220);
+/// `#dc143c`
+pub const CRIMSON: WorkPixel = rgb(220, 20, 60);
+/// `#00ffff`
+pub const CYAN: WorkPixel = rgb(0, 255, 255);
+/// `#00008b`
+pub const DARKBLUE: WorkPixel = rgb(0, 0, 139);
+/// `#008b8b`
+pub const DARKCYAN: WorkPixel = rgb(0, 139, 139);
+/// `#b8860b`
+pub const DARKGOLDENROD: WorkPixel = rgb(184, 134, 11);
+/// `#a9a9a9`
+pub const DARKGRAY: WorkPixel = rgb(169, 169, 169);
+/// `#006400`
+pub const DARKGREEN: WorkPixel = rgb(0, 100, 0);
+/// `#a9a9a9`
+pub const DARKGREY: WorkPixel = rgb(169, 169, 169);
+/// `#bdb76b`
+pub const DARKKHAKI: WorkPixel = rgb(189, 183, 107);
+/// `#8b008b`
+pub const DARKMAGENTA: WorkPixel = rgb(139, 0, 139);
+/// `#556b2f`
+pub const DARKOLIVEGREEN: WorkPixel = rgb(85, 107, 47);
+/// `#ff8c00`
+pub const DARKORANGE: WorkPixel = rgb(255, 140, 0);
+/// `#9932cc`
+pub const DARKORCHID: WorkPixel = rgb(153, 50, 204);
+/// `#8b0000`
+pub const DARKRED: WorkPixel = rgb(139, 0, 0);
+/// `#e9967a`
+pub const DARKSALMON: WorkPixel = rgb(233, 150, 122);
+/// `#8fbc8f`
+pub const DARKSEAGREEN: WorkPixel = rgb(143, 188, 143);
+/// `#483d8b`
+pub const DARKSLATEBLUE: WorkPixel = rgb(72, 61, 139);
+/// `#2f4f4f`
+pub const DARKSLATEGRAY: WorkPixel = rgb(47, 79, 79);
+/// `#2f4f4f`
+pub const DARKSLATEGREY: WorkPixel = rgb(47, 79, 79);
+/// `#00ced1`
+pub const DARKTURQUOISE: WorkPixel = rgb(0, 206, 209);
+/// `#9400d3`
+pub const DARKVIOLET: WorkPixel = rgb(148, 0, 211);
+/// `#ff1493`
+pub const DEEPPINK: WorkPixel = rgb(255, 20, 147);
+/// `#00bfff`
+pub const DEEPSKYBLUE: WorkPixel = rgb(0, 191, 255);
+/// `#696969`
+pub const DIMGRAY: WorkPixel = rgb(105, 105, 105);
+/// `#696969`
+pub const DIMGREY: WorkPixel = rgb(105, 105, 105);
+/// `#1e90ff`
+pub const DODGERBLUE: WorkPixel = rgb(30, 144, 255);
+/// `#b22222`
+pub const FIREBRICK: WorkPixel = rgb(178, 34, 34);
+/// `#fffaf0`
+pub const FLORALWHITE: WorkPixel = rgb(255, 250, 240);
+/// `#228b22`
+pub const FORESTGREEN: WorkPixel = rgb(34, 139, 34);
+/// `#ff00ff`
+pub const FUCHSIA: WorkPixel = rgb(255, 0, 255);
+/// `#dcdcdc`
+pub const GAINSBORO: WorkPixel = rgb(220, 220, 220);
+/// `#f8f8ff`
+pub const GHOSTWHITE: WorkPixel = rgb(248, 248, 255);
+/// `#ffd700`
+pub const GOLD: WorkPixel = rgb(255, 215, 0);
+/// `#daa520`
+pub const GOLDENROD: WorkPixel = rgb(218, 165, 32);
+/// `#808080`
+pub const GRAY: WorkPixel = rgb(128, 128, 128);
+/// `#008000`
+pub const GREEN: WorkPixel = rgb(0, 128, 0);
+/// `#adff2f`
+pub const GREENYELLOW: WorkPixel = rgb(173, 255, 47);
+/// `#808080`
+pub const GREY: WorkPixel = rgb(128, 128, 128);
+/// `#f0fff0`
+pub const HONEYDEW: WorkPixel = rgb(240, 255, 240);
+/// `#ff69b4`
+pub const HOTPINK: WorkPixel = rgb(255, 105, 180);
+/// `#cd5c5c`
+pub const INDIANRED: WorkPixel = rgb(205, 92, 92);
+/// `#4b0082`
+pub const INDIGO: WorkPixel = rgb(75, 0, 130);
+/// `#fffff0`
+pub const IVORY: WorkPixel = rgb(255, 255, 240);
+/// `#f0e68c`
+pub const KHAKI: WorkPixel = rgb(240, 230, 140);
+/// `#e6e6fa`
+pub const LAVENDER: WorkPixel = rgb(230, 230, 250);
+/// `#fff0f5`
+pub const LAVENDERBLUSH: WorkPixel = rgb(255, 240, 245);
+/// `#7cfc00`
+pub const LAWNGREEN: WorkPixel = rgb(124, 252, 0);
+/// `#fffacd`
+pub const LEMONCHIFFON: WorkPixel = rgb(255, 250, 205);
+/// `#add8e6`
+pub const LIGHTBLUE: WorkPixel = rgb(173, 216, 230);
+/// `#f08080`
+pub const LIGHTCORAL: WorkPixel = rgb(240, 128, 128);
+/// `#e0ffff`
+pub const LIGHTCYAN: WorkPixel = rgb(224, 255, 255);
+/// `#fafad2`
+pub const LIGHTGOLDENRODYELLOW: WorkPixel = rgb(250, 250, 210);
+/// `#d3d3d3`
+pub const LIGHTGRAY: WorkPixel = rgb(211, 211, 211);
+/// `#90ee90`
+pub const LIGHTGREEN: WorkPixel = rgb(144, 238, 144);
+/// `#d3d3d3`
+pub const LIGHTGREY: WorkPixel = rgb(211, 211, 211);
+/// `#ffb6c1`
+pub const LIGHTPINK: WorkPixel = rgb(255, 182, 193);
+/// `#ffa07a`
+pub const LIGHTSALMON: WorkPixel = rgb(255, 160, 122);
+/// `#20b2aa`
+pub const LIGHTSEAGREEN: WorkPixel = rgb(32, 178, 170);
+/// `#87cefa`
+pub const LIGHTSKYBLUE: WorkPixel = rgb(135, 206, 250);
+/// `#778899`
+pub const LIGHTSLATEGRAY: WorkPixel = rgb(119, 136, 153);
+/// `#778899`
+pub const LIGHTSLATEGREY: WorkPixel = rgb(119, 136, 153);
+/// `#b0c4de`
+pub const LIGHTSTEELBLUE: WorkPixel = rgb(176, 196, 222);
+/// `#ffffe0`
+pub const LIGHTYELLOW: WorkPixel = rgb(255, 255, 224);
+/// `#00ff00`
+pub const LIME: WorkPixel = rgb(0, 255, 0);
+/// `#32cd32`
+pub const LIMEGREEN: WorkPixel = rgb(50, 205, 50);
+/// `#faf0e6`
+pub const LINEN: WorkPixel = rgb(250, 240, 230);
+/// `#ff00ff`
+pub const MAGENTA: WorkPixel = rgb(255, 0, 255);
+/// `#800000`
+pub const MAROON: WorkPixel = rgb(128, 0, 0);
+/// `#66cdaa`
+pub const MEDIUMAQUAMARINE: WorkPixel = rgb(102, 205, 170);
+/// `#0000cd`
+pub const MEDIUMBLUE: WorkPixel = rgb(0, 0, 205);
+/// `#ba55d3`
+pub const MEDIUMORCHID: WorkPixel = rgb(186, 85, 211);
+/// `#9370db`
+pub const MEDIUMPURPLE: WorkPixel = rgb(147, 112, 219);
+/// `#3cb371`
+pub const MEDIUMSEAGREEN: WorkPixel = rgb(60, 179, 113);
+/// `#7b68ee`
+pub const MEDIUMSLATEBLUE: WorkPixel = rgb(123, 104, 238);
+/// `#00fa9a`
+pub const MEDIUMSPRINGGREEN: WorkPixel = rgb(0, 250, 154);
+/// `#48d1cc`
+pub const MEDIUMTURQUOISE: WorkPixel = rgb(72, 209, 204);
+/// `#c71585`
+pub const MEDIUMVIOLETRED: WorkPixel = rgb(199, 21, 133);
+/// `#191970`
+pub const MIDNIGHTBLUE: WorkPixel = rgb(25, 25, 112);
+/// `#f5fffa`
+pub const MINTCREAM: WorkPixel = rgb(245, 255, 250);
+/// `#ffe4e1`
+pub const MISTYROSE: WorkPixel = rgb(255, 228, 225);
+/// `#ffe4b5`
+pub const MOCCASIN: WorkPixel = rgb(255, 228, 181);
+/// `#ffdead`
+pub const NAVAJOWHITE: WorkPixel = rgb(255, 222, 173);
+/// `#000080`
+pub const NAVY: WorkPixel = rgb(0, 0, 128);
+/// `#fdf5e6`
+pub const OLDLACE: WorkPixel = rgb(253, 245, 230);
+/// `#808000`
+pub const OLIVE: WorkPixel = rgb(128, 128, 0);
+/// `#6b8e23`
+pub const OLIVEDRAB: WorkPixel = rgb(107, 142, 35);
+/// `#ffa500`
+pub const ORANGE: WorkPixel = rgb(255, 165, 0);
+/// `#ff4500`
+pub const ORANGERED: WorkPixel = rgb(255, 69, 0);
+/// `#da70d6`
+pub const ORCHID: WorkPixel = rgb(218, 112, 214);
+/// `#eee8aa`
+pub const PALEGOLDENROD: WorkPixel = rgb(238, 232, 170);
+/// `#98fb98`
+pub const PALEGREEN: WorkPixel = rgb(152, 251, 152);
+/// `#afeeee`
+pub const PALETURQUOISE: WorkPixel = rgb(175, 238, 238);
+/// `#db7093`
+pub const PALEVIOLETRED: WorkPixel = rgb(219, 112, 147);
+/// `#ffefd5`
+pub const PAPAYAWHIP: WorkPixel = rgb(255, 239, 213);
+/// `#ffdab9`
+pub const PEACHPUFF: WorkPixel = rgb(255, 218, 185);
+/// `#cd853f`
+pub const PERU: WorkPixel = rgb(205, 133, 63);
+/// `#ffc0cb`
+pub const PINK: WorkPixel = rgb(255, 192, 203);
+/// `#dda0dd`
+pub const PLUM: WorkPixel = rgb(221, 160, 221);
+/// `#b0e0e6`
+pub const POWDERBLUE: WorkPixel = rgb(176, 224, 230);
+/// `#800080`
+pub const PURPLE: WorkPixel = rgb(128, 0, 128);
+/// `#663399`
+pub const REBECCAPURPLE: WorkPixel = rgb(102, 51, 153);
+/// `#ff0000`
+pub const RED: WorkPixel = rgb(255, 0, 0);
+/// `#bc8f8f`
+pub const ROSYBROWN: WorkPixel = rgb(188, 143, 143);
+/// `#4169e1`
+pub const ROYALBLUE: WorkPixel = rgb(65, 105, 225);
+/// `#8b4513`
+pub const SADDLEBROWN: WorkPixel = rgb(139, 69, 19);
+/// `#fa8072`
+pub const SALMON: WorkPixel = rgb(250, 128, 114);
+/// `#f4a460`
+pub const SANDYBROWN: WorkPixel = rgb(244, 164, 96);
+/// `#2e8b57`
+pub const SEAGREEN: WorkPixel = rgb(46, 139, 87);
+/// `#fff5ee`
+pub const SEASHELL: WorkPixel = rgb(255, 245, 238);
+/// `#a0522d`
+pub const SIENNA: WorkPixel = rgb(160, 82, 45);
+/// `#c0c0c0`
+pub const SILVER: WorkPixel = rgb(192, 192, 192);
+/// `#87ceeb`
+pub const SKYBLUE: WorkPixel = rgb(135, 206, 235);
+/// `#6a5acd`
+pub const SLATEBLUE: WorkPixel = rgb(106, 90, 205);
+/// `#708090`
+pub const SLATEGRAY: WorkPixel = rgb(112, 128, 144);
+/// `#708090`
+pub const SLATEGREY: WorkPixel = rgb(112, 128, 144);
+/// `#fffafa`
+pub const SNOW: WorkPixel = rgb(255, 250, 250);
+/// `#00ff7f`
+pub const SPRINGGREEN: WorkPixel = rgb(0, 255, 127);
+/// `#4682b4`
+pub const STEELBLUE: WorkPixel = rgb(70, 130, 180);
+/// `#d2b48c`
+pub const TAN: WorkPixel = rgb(210, 180, 140);
+/// `#008080`
+pub const TEAL: WorkPixel = rgb(0, 128, 128);
+/// `#d8bfd8`
+pub const THISTLE: WorkPixel = rgb(216, 191, 216);
+/// `#ff6347`
+pub const TOMATO: WorkPixel = rgb(255, 99, 71);
+/// `#40e0d0`
+pub const TURQUOISE: WorkPixel = rgb(64, 224, 208);
+/// `#ee82ee`
+pub const VIOLET: WorkPixel = rgb(238, 130, 238);
+/// `#f5deb3`
+pub const WHEAT: WorkPixel = rgb(245, 222, 179);
+/// `#ffffff`
+pub const WHITE: WorkPixel = rgb(255, 255, 255);
+/// `#f5f5f5`
+pub const WHITESMOKE: WorkPixel = rgb(245, 245, 245);
+/// `#ffff00`
+pub const YELLOW: WorkPixel = rgb(255, 255, 0);
+/// `#9acd32`
+pub const YELLOWGREEN: WorkPixel = rgb(154, 205, 50);
+
+/// Length of the longest name
+const LONGEST: usize = 20;
+
+/// Every name, sorted, for [`parse_color`]
+const NAMES: [(&str, WorkPixel); 149] = [
+    ("aliceblue", ALICEBLUE),
+    ("antiquewhite", ANTIQUEWHITE),
+    ("aqua", AQUA),
+    ("aquamarine", AQUAMARINE),
+    ("azure", AZURE),
+    ("beige", BEIGE),
+    ("bisque", BISQUE),
+    ("black", BLACK),
+    ("blanchedalmond", BLANCHEDALMOND),
+    ("blue", BLUE),
+    ("blueviolet", BLUEVIOLET),
+    ("brown", BROWN),
+    ("burlywood", BURLYWOOD),
+    ("cadetblue", CADETBLUE),
+    ("chartreuse", CHARTREUSE),
+    ("chocolate", CHOCOLATE),
+    ("coral", CORAL),
+    ("cornflowerblue", CORNFLOWERBLUE),
+    ("cornsilk", CORNSILK),
+    ("crimson", CRIMSON),
+    ("cyan", CYAN),
+    ("darkblue", DARKBLUE),
+    ("darkcyan", DARKCYAN),
+    ("darkgoldenrod", DARKGOLDENROD),
+    ("darkgray", DARKGRAY),
+    ("darkgreen", DARKGREEN),
+    ("darkgrey", DARKGREY),
+    ("darkkhaki", DARKKHAKI),
+    ("darkmagenta", DARKMAGENTA),
+    ("darkolivegreen", DARKOLIVEGREEN),
+    ("darkorange", DARKORANGE),
+    ("darkorchid", DARKORCHID),
+    ("darkred", DARKRED),
+    ("darksalmon", DARKSALMON),
+    ("darkseagreen", DARKSEAGREEN),
+    ("darkslateblue", DARKSLATEBLUE),
+    ("darkslategray", DARKSLATEGRAY),
+    ("darkslategrey", DARKSLATEGREY),
+    ("darkturquoise", DARKTURQUOISE),
+    ("darkviolet", DARKVIOLET),
+    ("deeppink", DEEPPINK),
+    ("deepskyblue", DEEPSKYBLUE),
+    ("dimgray", DIMGRAY),
+    ("dimgrey", DIMGREY),
+    ("dodgerblue", DODGERBLUE),
+    ("firebrick", FIREBRICK),
+    ("floralwhite", FLORALWHITE),
+    ("forestgreen", FORESTGREEN),
+    ("fuchsia", FUCHSIA),
+    ("gainsboro", GAINSBORO),
+    ("ghostwhite", GHOSTWHITE),
+    ("gold", GOLD),
+    ("goldenrod", GOLDENROD),
+    ("gray", GRAY),
+    ("green", GREEN),
+    ("greenyellow", GREENYELLOW),
+    ("grey", GREY),
+    ("honeydew", HONEYDEW),
+    ("hotpink", HOTPINK),
+    ("indianred", INDIANRED),
+    ("indigo", INDIGO),
+    ("ivory", IVORY),
+    ("khaki", KHAKI),
+    ("lavender", LAVENDER),
+    ("lavenderblush", LAVENDERBLUSH),
+    ("lawngreen", LAWNGREEN),
+    ("lemonchiffon", LEMONCHIFFON),
+    ("lightblue", LIGHTBLUE),
+    ("lightcoral", LIGHTCORAL),
+    ("lightcyan", LIGHTCYAN),
+    ("lightgoldenrodyellow", LIGHTGOLDENRODYELLOW),
+    ("lightgray", LIGHTGRAY),
+    ("lightgreen", LIGHTGREEN),
+    ("lightgrey", LIGHTGREY),
+    ("lightpink", LIGHTPINK),
+    ("lightsalmon", LIGHTSALMON),
+    ("lightseagreen", LIGHTSEAGREEN),
+    ("lightskyblue", LIGHTSKYBLUE),
+    ("lightslategray", LIGHTSLATEGRAY),
+    ("lightslategrey", LIGHTSLATEGREY),
+    ("lightsteelblue", LIGHTSTEELBLUE),
+    ("lightyellow", LIGHTYELLOW),
+    ("lime", LIME),
+    ("limegreen", LIMEGREEN),
+    ("linen", LINEN),
+    ("magenta", MAGENTA),
+    ("maroon", MAROON),
+    ("mediumaquamarine", MEDIUMAQUAMARINE),
+    ("mediumblue", MEDIUMBLUE),
+    ("mediumorchid", MEDIUMORCHID),
+    ("mediumpurple", MEDIUMPURPLE),
+    ("mediumseagreen", MEDIUMSEAGREEN),
+    ("mediumslateblue", MEDIUMSLATEBLUE),
+    ("mediumspringgreen", MEDIUMSPRINGGREEN),
+    ("mediumturquoise", MEDIUMTURQUOISE),
+    ("mediumvioletred", MEDIUMVIOLETRED),
+    ("midnightblue", MIDNIGHTBLUE),
+    ("mintcream", MINTCREAM),
+    ("mistyrose", MISTYROSE),
+    ("moccasin", MOCCASIN),
+    ("navajowhite", NAVAJOWHITE),
+    ("navy", NAVY),
+    ("oldlace", OLDLACE),
+    ("olive", OLIVE),
+    ("olivedrab", OLIVEDRAB),
+    ("orange", ORANGE),
+    ("orangered", ORANGERED),
+    ("orchid", ORCHID),
+    ("palegoldenrod", PALEGOLDENROD),
+    ("palegreen", PALEGREEN),
+    ("paleturquoise", PALETURQUOISE),
+    ("palevioletred", PALEVIOLETRED),
+    ("papayawhip", PAPAYAWHIP),
+    ("peachpuff", PEACHPUFF),
+    ("peru", PERU),
+    ("pink", PINK),
+    ("plum", PLUM),
+    ("powderblue", POWDERBLUE),
+    ("purple", PURPLE),
+    ("rebeccapurple", REBECCAPURPLE),
+    ("red", RED),
+    ("rosybrown", ROSYBROWN),
+    ("royalblue", ROYALBLUE),
+    ("saddlebrown", SADDLEBROWN),
+    ("salmon", SALMON),
+    ("sandybrown", SANDYBROWN),
+    ("seagreen", SEAGREEN),
+    ("seashell", SEASHELL),
+    ("sienna", SIENNA),
+    ("silver", SILVER),
+    ("skyblue", SKYBLUE),
+    ("slateblue", SLATEBLUE),
+    ("slategray", SLATEGRAY),
+    ("slategrey", SLATEGREY),
+    ("snow", SNOW),
+    ("springgreen", SPRINGGREEN),
+    ("steelblue", STEELBLUE),
+    ("tan", TAN),
+    ("teal", TEAL),
+    ("thistle", THISTLE),
+    ("tomato", TOMATO),
+    ("transparent", TRANSPARENT),
+    ("turquoise", TURQUOISE),
+    ("violet", VIOLET),
+    ("wheat", WHEAT),
+    ("white", WHITE),
+    ("whitesmoke", WHITESMOKE),
+    ("yellow", YELLOW),
+    ("yellowgreen", YELLOWGREEN),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn px(r: u8, g: u8, b: u8, a: u8) -> WorkPixel {
+        [r, g, b, a].map(|c| c as f32 / 255.)
+    }
+
+    fn err(s: &str) -> ImageError {
+        parse_color(s).unwrap_err()
+    }
+
+    #[test]
+    fn formats_parse() {
+        let cases = [
+            ("#f80", px(255, 136, 0, 255)),
+            ("#f808", px(255, 136, 0, 136)),
+            ("#1a2b3c", px(0x1a, 0x2b, 0x3c, 255)),
+            ("#1A2B3C", px(0x1a, 0x2b, 0x3c, 255)),
+            ("#1a2b3c80", px(0x1a, 0x2b, 0x3c, 0x80)),
+            ("rgb(1,2,3)", px(1, 2, 3, 255)),
+            ("RGB( 255 , 0 ,128 )", px(255, 0, 128, 255)),
+            ("  #000  ", px(0, 0, 0, 255)),
+            ("red", px(255, 0, 0, 255)),
+            ("CornflowerBlue", px(100, 149, 237, 255)),
+            ("transparent", [0.; 4]),
+        ];
+        for (s, want) in cases {
+            assert_eq!(parse_color(s), Ok(want), "{s}");
+        }
+    }
+
+    #[test]
+    fn errors_name_the_problem() {
+        use ColorParseError::*;
+        let cases = [
+            ("", Empty),
+            ("   ", Empty),
+            ("#", HexLength(0)),
+            ("#12345", HexLength(5)),
+            ("#123456789", HexLength(9)),
+            ("#ggg", HexDigit),
+            ("#12 456", HexDigit),
+            ("rgb(1,2,3", Syntax),
+            ("rgb(1,2)", Syntax),
+            ("rgb(1,2,3,4)", Syntax),
+            ("rgb(1,2,256)", Component),
+            ("rgb(1,-2,3)", Component),
+            ("rgb(1,2.5,3)", Component),
+            ("rgb(1,,3)", Component),
+            ("notacolor", UnknownName),
+            ("lightgoldenrodyellowish", UnknownName),
+        ];
+        for (s, want) in cases {
+            assert_eq!(err(s), ImageError::InvalidColor(want), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn constants_match_css() {
+        assert_eq!(REBECCAPURPLE, px(0x66, 0x33, 0x99, 255));
+        assert_eq!(CORNFLOWERBLUE, px(0x64, 0x95, 0xed, 255));
+        assert_eq!(GOLD, px(0xff, 0xd7, 0x00, 255));
+        assert_eq!(AQUAMARINE, px(0x7f, 0xff, 0xd4, 255));
+        assert_eq!(WHITE, [1.; 4]);
+        assert_eq!(BLACK, [0., 0., 0., 1.]);
+        assert_eq!(GRAY, GREY);
+        assert_eq!(AQUA, CYAN);
+        assert_eq!(FUCHSIA, MAGENTA);
+    }
+
+    #[test]
+    fn every_name_parses() {
+        assert!(NAMES.windows(2).all(|w| w[0].0 < w[1].0), "sorted");
+        assert!(NAMES.iter().all(|(n, _)| n.len() <= LONGEST));
+        for (name, want) in NAMES {
+            assert_eq!(parse_color(name), Ok(want), "{name}");
+            let upper = alloc::string::String::from(name).to_ascii_uppercase();
+            assert_eq!(parse_color(&upper), Ok(want), "{upper}");
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! 4x5 affine color matrices, for [`Image::apply_color_matrix`]
+//!
+//! Like Android's `ColorMatrix` or SVG's `feColorMatrix`, each row gives one
+//! output channel from `[r, g, b, a, 1]`. Combine effects with [`compose`]
+//! so they cost one pass.
+use crate::{Image, WorkPixel, F32};
+
+/// Rows for R, G, B, and A, each weighting `[r, g, b, a, 1]`
+pub type ColorMatrix = [[f32; 5]; 4];
+
+/// Rec.709 luminance weights, like [`luminance`][crate::transforms::luminance]
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// Leaves colors untouched
+pub fn identity() -> ColorMatrix {
+    [
+        [1., 0., 0., 0., 0.],
+        [0., 1., 0., 0., 0.],
+        [0., 0., 1., 0., 0.],
+        [0., 0., 0., 1., 0.],
+    ]
+}
+
+/// Scale saturation by `s`, `0` is grayscale and `1` is unchanged
+///
+/// Gray is Rec.709 luminance, so on linear light `saturation(0.)` gives the
+/// same gray as [`LumaImage`][crate::LumaImage].
+pub fn saturation(s: f32) -> ColorMatrix {
+    let mut m = identity();
+    for (i, row) in m.iter_mut().take(3).enumerate() {
+        for (c, w) in LUMA.iter().enumerate() {
+            row[c] = w * (1. - s) + if i == c { s } else { 0. };
+        }
+    }
+    m
+}
+
+/// Rotate hue by `deg` degrees, the same as SVG and CSS `hue-rotate`
+pub fn hue_rotate(deg: f32) -> ColorMatrix {
+    let (sin, cos) = (deg.to_radians().sin(), deg.to_radians().cos());
+    [
+        [
+            0.213 + cos * 0.787 - sin * 0.213,
+            0.715 - cos * 0.715 - sin * 0.715,
+            0.072 - cos * 0.072 + sin * 0.928,
+            0.,
+            0.,
+        ],
+        [
+            0.213 - cos * 0.213 + sin * 0.143,
+            0.715 + cos * 0.285 + sin * 0.140,
+            0.072 - cos * 0.072 - sin * 0.283,
+            0.,
+            0.,
+        ],
+        [
+            0.213 - cos * 0.213 - sin * 0.787,
+            0.715 - cos * 0.715 + sin * 0.715,
+            0.072 + cos * 0.928 + sin * 0.072,
+            0.,
+            0.,
+        ],
+        [0., 0., 0., 1., 0.],
+    ]
+}
+
+/// Classic sepia tone, the same as CSS `sepia(1)`
+pub fn sepia() -> ColorMatrix {
+    [
+        [0.393, 0.769, 0.189, 0., 0.],
+        [0.349, 0.686, 0.168, 0., 0.],
+        [0.272, 0.534, 0.131, 0., 0.],
+        [0., 0., 0., 1., 0.],
+    ]
+}
+
+/// Add `offset` to each color channel
+pub fn brightness(offset: f32) -> ColorMatrix {
+    let mut m = identity();
+    m.iter_mut().take(3).for_each(|row| row[4] = offset);
+    m
+}
+
+/// Scale each color channel by `factor` around mid gray, `0.5`
+pub fn contrast(factor: f32) -> ColorMatrix {
+    let mut m = identity();
+    for (i, row) in m.iter_mut().take(3).enumerate() {
+        row[i] = factor;
+        row[4] = 0.5 * (1. - factor);
+    }
+    m
+}
+
+/// The 3x3 RGB matrix `m`, like a camera's color correction matrix, with
+/// alpha untouched
+pub fn from_rgb_matrix(m: [[f32; 3]; 3]) -> ColorMatrix {
+    let mut out = identity();
+    for (row, m) in out.iter_mut().zip(m) {
+        row[..3].copy_from_slice(&m);
+    }
+    out
+}
+
+/// A matrix doing `first` then `then`
+pub fn compose(first: &ColorMatrix, then: &ColorMatrix) -> ColorMatrix {
+    let mut m = [[0.; 5]; 4];
+    for (r, row) in m.iter_mut().enumerate() {
+        for (c, v) in row.iter_mut().enumerate() {
+            *v = (0..4).map(|k| then[r][k] * first[k][c]).sum();
+        }
+        // The implicit `1` row of `first`
+        row[4] += then[r][4];
+    }
+    m
+}
+
+impl Image {
+    /// Apply the color matrix `m` to every pixel, see [`color_matrix`]
+    ///
+    /// This is on the stored values *as is*, whatever the color space and
+    /// alpha mode. Convert first if it matters, for example to
+    /// [`ColorSpace::sRGBLinear`] for physically correct saturation, or
+    /// straight alpha so brightness doesn't add to transparent pixels.
+    /// Results are not clamped.
+    ///
+    /// [`color_matrix`]: crate::color_matrix
+    /// [`ColorSpace::sRGBLinear`]: crate::ColorSpace::sRGBLinear
+    pub fn apply_color_matrix(&mut self, m: &ColorMatrix) {
+        for p in &mut self.data {
+            *p = transform(m, *p);
+        }
+    }
+}
+
+/// `m` applied to `p`
+#[inline]
+pub(crate) fn transform(m: &ColorMatrix, p: WorkPixel) -> WorkPixel {
+    let v = [p[0], p[1], p[2], p[3], 1.];
+    m.map(|row| row.iter().zip(&v).map(|(w, c)| w * c).sum())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{max_diff, photo},
+        ColorSpace, LumaImage,
+    };
+
+    #[test]
+    fn identity_is_a_no_op() {
+        let img = photo((9, 7));
+        let mut out = img.clone();
+        out.apply_color_matrix(&identity());
+        assert_eq!(out.pixels(), img.pixels());
+        out.apply_color_matrix(&saturation(1.));
+        assert!(max_diff(out.pixels(), img.pixels()) < 1e-6);
+    }
+
+    #[test]
+    fn saturation_zero_is_luma() {
+        let mut img = photo((9, 7));
+        img.to_color(ColorSpace::sRGBLinear);
+        let gray = LumaImage::from_image(&img).to_image();
+        img.apply_color_matrix(&saturation(0.));
+        for (p, g) in img.pixels().iter().zip(gray.pixels()) {
+            for c in 0..3 {
+                assert!((p[c] - g[c]).abs() < 1e-5, "{p:?} {g:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn compose_is_sequential() {
+        let img = photo((9, 7));
+        let steps = [
+            sepia(),
+            hue_rotate(40.),
+            contrast(1.3),
+            brightness(0.1),
+            saturation(0.4),
+        ];
+        let mut one = img.clone();
+        let mut each = img.clone();
+        let mut m = identity();
+        for step in &steps {
+            m = compose(&m, step);
+            each.apply_color_matrix(step);
+        }
+        one.apply_color_matrix(&m);
+        assert!(max_diff(one.pixels(), each.pixels()) < 1e-5);
+    }
+
+    #[test]
+    fn operates_as_given() {
+        // Same stored values in either color space give the same result
+        let mut a = photo((4, 4));
+        let mut b = a.clone();
+        b.color = ColorSpace::sRGBLinear;
+        a.apply_color_matrix(&brightness(0.25));
+        b.apply_color_matrix(&brightness(0.25));
+        assert_eq!(a.pixels(), b.pixels());
+        assert_eq!(b.color, ColorSpace::sRGBLinear);
+        // Not clamped
+        assert!(a.pixels().iter().any(|p| p[0] > 1.));
+    }
+}
@@ -0,0 +1,504 @@
+//! Compositing
+use crate::{
+    alpha_converter, noise::Rng, transforms::*, AlphaMode, BlendSpace, Image, ImageError, Transfer,
+    WorkPixel, XY,
+};
+
+/// Pixels blended at once, with the `simd` feature
+#[cfg(feature = "simd")]
+const RUN: usize = 16;
+
+/// Decode `p` to linear, premultiplied alpha
+pub(crate) fn to_linear_premul(
+    p: WorkPixel,
+    decode: Option<Transfer>,
+    mode: AlphaMode,
+) -> WorkPixel {
+    let p = match mode {
+        AlphaMode::Straight => p,
+        AlphaMode::Premultiplied => unpremultiply(p),
+    };
+    let p = match decode {
+        Some(f) => [f(p[0]), f(p[1]), f(p[2]), p[3]],
+        None => p,
+    };
+    premultiply(p)
+}
+
+/// Inverse of [`to_linear_premul`]
+pub(crate) fn from_linear_premul(
+    p: WorkPixel,
+    encode: Option<Transfer>,
+    mode: AlphaMode,
+) -> WorkPixel {
+    let p = unpremultiply(p);
+    let p = match encode {
+        Some(f) => [f(p[0]), f(p[1]), f(p[2]), p[3]],
+        None => p,
+    };
+    match mode {
+        AlphaMode::Straight => p,
+        AlphaMode::Premultiplied => premultiply(p),
+    }
+}
+
+/// Porter-Duff source-over, on premultiplied pixels
+pub(crate) fn over(src: WorkPixel, dst: WorkPixel) -> WorkPixel {
+    let inv = 1. - src[3];
+    [
+        src[0] + dst[0] * inv,
+        src[1] + dst[1] * inv,
+        src[2] + dst[2] * inv,
+        src[3] + dst[3] * inv,
+    ]
+}
+
+impl Image {
+    /// Composite `src` over this image, with its top left corner at `at`
+    ///
+    /// This is done in linear light, respecting the [`AlphaMode`] of both
+    /// images. Parts of `src` outside this image are clipped.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::ColorSpaceMismatch`] if the images have different
+    ///   color spaces
+    pub fn overlay(&mut self, src: &Image, at: XY) -> Result<(), ImageError> {
+        self.overlay_weighted(src, at, BlendSpace::Linear, |_| 1.)
+    }
+
+    /// Composite `src` over this image like [`Image::overlay`], fading its
+    /// edges out over `feather` pixels so it doesn't look pasted on
+    ///
+    /// Coverage ramps up linearly from the edges of `src` inwards, and is
+    /// multiplied with its alpha. `feather` is clamped to half the smaller
+    /// dimension of `src`, and `0` is exactly the same as [`Image::overlay`].
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::ColorSpaceMismatch`] if the images have different
+    ///   color spaces
+    pub fn overlay_feathered(
+        &mut self,
+        src: &Image,
+        at: XY,
+        feather: u32,
+    ) -> Result<(), ImageError> {
+        let (w, h) = src.res;
+        let feather = feather.min(w.min(h) / 2);
+        let ramp = (feather + 1) as f32;
+        self.overlay_weighted(src, at, BlendSpace::Linear, |(x, y)| {
+            let d = x.min(y).min(w - 1 - x).min(h - 1 - y);
+            if d >= feather {
+                1.
+            } else {
+                (d + 1) as f32 / ramp
+            }
+        })
+    }
+
+    /// Composite `src` over this image in `blend`, scaling its coverage at
+    /// each pixel by `weight`
+    pub(crate) fn overlay_weighted(
+        &mut self,
+        src: &Image,
+        at: XY,
+        blend: BlendSpace,
+        weight: impl Fn(XY) -> f32,
+    ) -> Result<(), ImageError> {
+        if src.color != self.color {
+            return Err(ImageError::ColorSpaceMismatch);
+        }
+        let (x0, y0) = at;
+        let w = src.width().min(self.width().saturating_sub(x0));
+        let h = src.height().min(self.height().saturating_sub(y0));
+
+        #[cfg(feature = "simd")]
+        self.overlay_runs(src, at, (w, h), blend, weight);
+        #[cfg(not(feature = "simd"))]
+        self.overlay_pixels(src, at, (w, h), blend, weight);
+        Ok(())
+    }
+
+    /// [`Image::overlay_weighted`] of the `size` part of `src` that fits, a
+    /// pixel at a time
+    #[cfg(any(test, not(feature = "simd")))]
+    fn overlay_pixels(
+        &mut self,
+        src: &Image,
+        at: XY,
+        size: XY,
+        blend: BlendSpace,
+        weight: impl Fn(XY) -> f32,
+    ) {
+        let (decode, encode) = self.blend_transfer(blend);
+        let ((x0, y0), (w, h)) = (at, size);
+        for y in 0..h {
+            for x in 0..w {
+                let s = src.data[(y * src.width() + x) as usize];
+                let i = ((y0 + y) * self.width() + (x0 + x)) as usize;
+                let d = self.data[i];
+
+                let k = weight((x, y));
+                let s = to_linear_premul(s, decode, src.alpha).map(|c| c * k);
+                let d = to_linear_premul(d, decode, self.alpha);
+                self.data[i] = from_linear_premul(over(s, d), encode, self.alpha);
+            }
+        }
+    }
+
+    /// [`Image::overlay_weighted`] of the `size` part of `src` that fits,
+    /// on runs of a row at a time, so the transfer functions and
+    /// source-over are vectorized
+    #[cfg(feature = "simd")]
+    fn overlay_runs(
+        &mut self,
+        src: &Image,
+        at: XY,
+        size: XY,
+        blend: BlendSpace,
+        weight: impl Fn(XY) -> f32,
+    ) {
+        use crate::simd;
+
+        let tf = self
+            .blend_transfer(blend)
+            .0
+            .map(|_| self.color.transfer_function());
+        let straight = |p: WorkPixel, mode| match mode {
+            AlphaMode::Straight => p,
+            AlphaMode::Premultiplied => unpremultiply(p),
+        };
+        let ((x0, y0), (w, h)) = (at, size);
+        for y in 0..h {
+            for x in (0..w).step_by(RUN) {
+                let n = RUN.min((w - x) as usize);
+                let s = (y * src.width() + x) as usize;
+                let d = ((y0 + y) * self.width() + x0 + x) as usize;
+                let mut sr = [WorkPixel::default(); RUN];
+                let mut dr = [WorkPixel::default(); RUN];
+                let (sr, dr) = (&mut sr[..n], &mut dr[..n]);
+                for j in 0..n {
+                    sr[j] = straight(src.data[s + j], src.alpha);
+                    dr[j] = straight(self.data[d + j], self.alpha);
+                }
+                if let Some(tf) = tf {
+                    simd::transfer_pixels(sr, tf, false);
+                    simd::transfer_pixels(dr, tf, false);
+                }
+                for (j, (sp, dp)) in sr.iter_mut().zip(dr.iter_mut()).enumerate() {
+                    let k = weight((x + j as u32, y));
+                    *sp = premultiply(*sp).map(|c| c * k);
+                    *dp = unpremultiply(over(*sp, premultiply(*dp)));
+                }
+                if let Some(tf) = tf {
+                    simd::transfer_pixels(dr, tf, true);
+                }
+                for (p, o) in self.data[d..d + n].iter_mut().zip(dr) {
+                    *p = match self.alpha {
+                        AlphaMode::Straight => *o,
+                        AlphaMode::Premultiplied => premultiply(*o),
+                    };
+                }
+            }
+        }
+    }
+
+    /// Check `other` can be blended with this image
+    pub(crate) fn check_blend(&self, other: &Image) -> Result<(), ImageError> {
+        if other.res != self.res {
+            return Err(ImageError::DimensionMismatch);
+        }
+        if other.color != self.color {
+            return Err(ImageError::ColorSpaceMismatch);
+        }
+        Ok(())
+    }
+
+    /// Blend towards `other` by `t`, see [`Image::lerp_assign`]
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::DimensionMismatch`] if the images are different sizes
+    /// - [`ImageError::ColorSpaceMismatch`] if the images have different
+    ///   color spaces
+    pub fn lerp(&self, other: &Image, t: f32) -> Result<Image, ImageError> {
+        let mut img = self.clone();
+        img.lerp_assign(other, t)?;
+        Ok(img)
+    }
+
+    /// Blend towards `other` by `t`, in place
+    ///
+    /// This is done per channel in linear light, with premultiplied alpha.
+    /// `t` is clamped to `0..=1`, `0` leaves this image exactly as it is and
+    /// `1` makes it an exact copy of `other`.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::DimensionMismatch`] if the images are different sizes
+    /// - [`ImageError::ColorSpaceMismatch`] if the images have different
+    ///   color spaces
+    pub fn lerp_assign(&mut self, other: &Image, t: f32) -> Result<(), ImageError> {
+        self.check_blend(other)?;
+        let t = t.clamp(0., 1.);
+        if t == 0. {
+            return Ok(());
+        }
+        if t == 1. {
+            let convert = alpha_converter(other.alpha, self.alpha);
+            for (d, s) in self.data.iter_mut().zip(&other.data) {
+                *d = convert(*s);
+            }
+            return Ok(());
+        }
+        let transfer = self.color.transfer();
+        let (decode, encode) = (transfer.map(|t| t.0), transfer.map(|t| t.1));
+        for (d, s) in self.data.iter_mut().zip(&other.data) {
+            let a = to_linear_premul(*d, decode, self.alpha);
+            let b = to_linear_premul(*s, decode, other.alpha);
+            let p = core::array::from_fn(|c| a[c] + (b[c] - a[c]) * t);
+            *d = from_linear_premul(p, encode, self.alpha);
+        }
+        Ok(())
+    }
+
+    /// Switch pixels to `other` at random, with probability `t`
+    ///
+    /// For displays that can't blend, like 1 bit ones. The pattern only
+    /// depends on `seed`, and pixels that switch at some `t` also switch at
+    /// any larger `t`, so stepping `t` up with a fixed seed dissolves smoothly.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::DimensionMismatch`] if the images are different sizes
+    /// - [`ImageError::ColorSpaceMismatch`] if the images have different
+    ///   color spaces
+    pub fn dissolve(&mut self, other: &Image, t: f32, seed: u64) -> Result<(), ImageError> {
+        self.check_blend(other)?;
+        let convert = alpha_converter(other.alpha, self.alpha);
+        for (i, (d, s)) in self.data.iter_mut().zip(&other.data).enumerate() {
+            let z = Rng::nth(seed, i as u64);
+            if ((z >> 40) as f32 / (1u64 << 24) as f32) < t {
+                *d = convert(*s);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::{
+        fixtures::{max_diff, noise},
+        ColorSpace,
+    };
+
+    fn noisy(res: XY, seed: u64, alpha: AlphaMode) -> Image {
+        let mut seed = seed;
+        let data: Vec<u8> = (0..res.0 * res.1 * 4)
+            .map(|_| (noise(&mut seed) * 256.) as u8)
+            .collect();
+        let mut img = Image::from_bytes(&data, res, ColorSpace::sRGB);
+        img.to_alpha_mode(alpha);
+        img
+    }
+
+    #[test]
+    fn premultiplied_over_straight() {
+        let src = Image::from_bytes_with_alpha(
+            &[128, 0, 0, 128],
+            (1, 1),
+            ColorSpace::sRGBLinear,
+            AlphaMode::Premultiplied,
+        );
+        let mut dst = Image::from_bytes(&[255; 4], (1, 1), ColorSpace::sRGBLinear);
+        dst.overlay(&src, (0, 0)).unwrap();
+        assert_eq!(dst.alpha_mode(), AlphaMode::Straight);
+        let half = 128. / 255.;
+        let expected = [1., 1. - half, 1. - half, 1.];
+        for (a, b) in dst.pixels()[0].iter().zip(expected) {
+            assert!((a - b).abs() < 1e-6, "{:?}", dst.pixels()[0]);
+        }
+    }
+
+    #[test]
+    fn alpha_modes_composite_alike() {
+        for dst_alpha in [AlphaMode::Straight, AlphaMode::Premultiplied] {
+            let straight = noisy((9, 7), 3, AlphaMode::Straight);
+            let premul = noisy((9, 7), 3, AlphaMode::Premultiplied);
+            let mut a = noisy((12, 8), 5, dst_alpha);
+            let mut b = a.clone();
+            a.overlay(&straight, (2, 1)).unwrap();
+            b.overlay(&premul, (2, 1)).unwrap();
+            assert_eq!(a.alpha_mode(), dst_alpha);
+            assert!(max_diff(a.pixels(), b.pixels()) < 1e-5, "{dst_alpha:?}");
+        }
+    }
+
+    #[test]
+    fn lerp_endpoints() {
+        let (a, b) = (
+            noisy((7, 5), 1, AlphaMode::Straight),
+            noisy((7, 5), 2, AlphaMode::Straight),
+        );
+        assert_eq!(a.lerp(&b, 0.).unwrap().pixels(), a.pixels());
+        assert_eq!(a.lerp(&b, 1.).unwrap().pixels(), b.pixels());
+        // Clamped
+        assert_eq!(a.lerp(&b, -3.).unwrap().pixels(), a.pixels());
+        assert_eq!(a.lerp(&b, 7.).unwrap().pixels(), b.pixels());
+        let mut c = a.clone();
+        c.lerp_assign(&b, 1.).unwrap();
+        assert_eq!(c.pixels(), b.pixels());
+    }
+
+    #[test]
+    fn lerp_in_linear_light() {
+        let black = Image::from_bytes(&[0, 0, 0, 255], (1, 1), ColorSpace::sRGB);
+        let white = Image::from_bytes(&[255; 4], (1, 1), ColorSpace::sRGB);
+        let mid = black.lerp(&white, 0.5).unwrap().pixels()[0];
+        let want = rgb_to_srgb(0.5);
+        for c in &mid[..3] {
+            assert!((c - want).abs() < 1e-5, "{mid:?}");
+        }
+        assert_eq!(mid[3], 1.);
+    }
+
+    #[test]
+    fn lerp_errors() {
+        let a = noisy((3, 3), 1, AlphaMode::Straight);
+        let mut b = noisy((3, 3), 2, AlphaMode::Straight);
+        assert_eq!(
+            a.lerp(&noisy((3, 4), 2, AlphaMode::Straight), 0.5).err(),
+            Some(ImageError::DimensionMismatch)
+        );
+        b.color = ColorSpace::DisplayP3;
+        assert_eq!(a.lerp(&b, 0.5).err(), Some(ImageError::ColorSpaceMismatch));
+        let mut c = a.clone();
+        assert_eq!(c.dissolve(&b, 0.5, 1), Err(ImageError::ColorSpaceMismatch));
+    }
+
+    #[test]
+    fn dissolve_half() {
+        let black = Image::from_bytes(&[0; 64 * 64 * 4], (64, 64), ColorSpace::sRGB);
+        let white = Image::from_bytes(&[255; 64 * 64 * 4], (64, 64), ColorSpace::sRGB);
+        let dissolved = |t: f32, seed: u64| {
+            let mut img = black.clone();
+            img.dissolve(&white, t, seed).unwrap();
+            img
+        };
+        let half = dissolved(0.5, 42);
+        let flipped = half.pixels().iter().filter(|p| p[0] == 1.).count();
+        let share = flipped as f32 / (64. * 64.);
+        assert!((share - 0.5).abs() < 0.03, "{share}");
+        // Deterministic, and only more pixels switch as t rises
+        assert_eq!(dissolved(0.5, 42).pixels(), half.pixels());
+        assert_ne!(dissolved(0.5, 43).pixels(), half.pixels());
+        let more = dissolved(0.75, 42);
+        for (h, m) in half.pixels().iter().zip(more.pixels()) {
+            assert!(h[0] <= m[0]);
+        }
+        assert_eq!(dissolved(0., 42).pixels(), black.pixels());
+    }
+
+    /// A flat `v` gray opaque image, in linear light
+    fn flat(res: (u32, u32), v: f32) -> Image {
+        let data = alloc::vec![[v, v, v, 1.]; (res.0 * res.1) as usize];
+        Image::from_parts(data, res, ColorSpace::sRGBLinear)
+    }
+
+    #[test]
+    fn feather_zero_is_overlay() {
+        let src = noisy((9, 7), 3, AlphaMode::Straight);
+        let mut plain = noisy((16, 12), 4, AlphaMode::Straight);
+        let mut feathered = plain.clone();
+        plain.overlay(&src, (10, 8)).unwrap();
+        feathered.overlay_feathered(&src, (10, 8), 0).unwrap();
+        assert_eq!(plain.pixels(), feathered.pixels());
+    }
+
+    #[test]
+    fn feather_ramps_in() {
+        let mut img = flat((20, 20), 0.);
+        img.overlay_feathered(&flat((10, 10), 1.), (5, 5), 3)
+            .unwrap();
+        let row = img.row(10).unwrap();
+        assert_eq!(row[4][0], 0.);
+        // From the left edge in, then flat
+        let ramp: Vec<f32> = row[5..10].iter().map(|p| p[0]).collect();
+        assert!(
+            ramp.windows(2).all(|w| w[0] < w[1] || w[1] == 1.),
+            "{ramp:?}"
+        );
+        assert!((ramp[0] - 0.25).abs() < 1e-6);
+        assert_eq!(ramp[3..], [1., 1.]);
+        // Symmetric on the right
+        for x in 0..5 {
+            assert_eq!(row[5 + x], row[14 - x]);
+        }
+
+        // Clamped to half the size
+        let mut wide = flat((20, 20), 0.);
+        wide.overlay_feathered(&flat((10, 10), 1.), (5, 5), 100)
+            .unwrap();
+        let mut half = flat((20, 20), 0.);
+        half.overlay_feathered(&flat((10, 10), 1.), (5, 5), 5)
+            .unwrap();
+        assert_eq!(wide.pixels(), half.pixels());
+    }
+
+    #[test]
+    fn feather_respects_alpha() {
+        let mut src = flat((8, 8), 1.);
+        for p in &mut src.data {
+            p[3] = 0.5;
+        }
+        let mut img = flat((8, 8), 0.);
+        img.overlay_feathered(&src, (0, 0), 2).unwrap();
+        let row = img.row(4).unwrap();
+        assert!((row[0][0] - 0.5 / 3.).abs() < 1e-6);
+        assert!((row[4][0] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn feathered_seam_has_no_step() {
+        let (dark, light) = (0.2, 0.8);
+        let mut hard = flat((32, 16), dark);
+        hard.overlay(&flat((16, 16), light), (16, 0)).unwrap();
+        let mut soft = flat((32, 16), dark);
+        soft.overlay_feathered(&flat((16, 16), light), (16, 0), 6)
+            .unwrap();
+        let step = |img: &Image| {
+            let row = img.row(8).unwrap();
+            row.windows(2)
+                .map(|w| (w[1][0] - w[0][0]).abs())
+                .fold(0f32, f32::max)
+        };
+        assert!((step(&hard) - (light - dark)).abs() < 1e-6);
+        // Spread over the feather, a seventh of the step each
+        assert!(step(&soft) <= (light - dark) / 7. + 1e-6, "{}", step(&soft));
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn simd_matches_scalar() {
+        for alpha in [AlphaMode::Straight, AlphaMode::Premultiplied] {
+            for blend in [BlendSpace::Encoded, BlendSpace::Linear] {
+                let src = noisy((37, 9), 3, alpha);
+                let dst = noisy((40, 12), 5, alpha);
+                let weight = |(x, y): XY| (x + y) as f32 / 45.;
+                let (mut runs, mut pixels) = (dst.clone(), dst);
+                runs.overlay_runs(&src, (5, 2), (35, 9), blend, weight);
+                pixels.overlay_pixels(&src, (5, 2), (35, 9), blend, weight);
+                for (a, b) in runs.pixels().iter().zip(pixels.pixels()) {
+                    for c in 0..4 {
+                        assert!((a[c] - b[c]).abs() < 1e-6, "{a:?} != {b:?}");
+                    }
+                }
+            }
+        }
+    }
+}
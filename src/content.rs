@@ -0,0 +1,127 @@
+//! Cropping to content
+use alloc::vec::Vec;
+use core::slice::from_mut;
+
+use crate::{convert_rows, transforms::*, AlphaMode, ColorSpace, Image, ResXY, WorkPixel, F32, XY};
+
+impl Image {
+    /// Oklab and alpha of `p`, for perceptual comparisons
+    pub(crate) fn to_oklab_alpha(&self, mut p: WorkPixel) -> [f32; 4] {
+        if self.alpha == AlphaMode::Premultiplied {
+            p = unpremultiply(p);
+        }
+        convert_rows(from_mut(&mut p), self.color, ColorSpace::sRGBLinear);
+        let [l, a, b] = linear_srgb_to_oklab([p[0], p[1], p[2]]);
+        [l, a, b, p[3]]
+    }
+
+    /// Estimate the background color from the border pixels, as Oklab and
+    /// alpha
+    ///
+    /// This is the per channel median, so small amounts of content touching
+    /// the border don't throw it off.
+    fn border_background(&self) -> [f32; 4] {
+        let (w, h) = (self.width(), self.height());
+        let border: Vec<_> = (0..h)
+            .flat_map(|y| (0..w).map(move |x| (x, y)))
+            .filter(|&(x, y)| x == 0 || y == 0 || x == w - 1 || y == h - 1)
+            .filter_map(|xy| self.get_pixel(xy))
+            .map(|p| self.to_oklab_alpha(p))
+            .collect();
+        core::array::from_fn(|c| {
+            let mut v: Vec<f32> = border.iter().map(|p| p[c]).collect();
+            let mid = v.len() / 2;
+            *v.select_nth_unstable_by(mid, f32::total_cmp).1
+        })
+    }
+
+    /// Crop away borders matching the background color, returning the
+    /// `(origin, size)` used
+    ///
+    /// The background is estimated from the border pixels, and anything
+    /// further than `tolerance` from it, as distance in Oklab plus alpha,
+    /// counts as content. Around `0.02` is barely noticeable, and enough to
+    /// ignore mild compression noise.
+    ///
+    /// Images that are all background are left alone, returning the whole
+    /// image.
+    pub fn auto_crop(&mut self, tolerance: f32) -> (XY, ResXY) {
+        if self.data.is_empty() {
+            return ((0, 0), self.res);
+        }
+        let bg = self.border_background();
+        let bounds = self.bounds_where(|p| {
+            let p = self.to_oklab_alpha(p);
+            let d: f32 = (0..4).map(|c| (p[c] - bg[c]) * (p[c] - bg[c])).sum();
+            d.sqrt() > tolerance
+        });
+        match bounds {
+            Some((origin, size)) => {
+                // Always in bounds
+                let _ = self.crop(origin, size);
+                (origin, size)
+            }
+            None => ((0, 0), self.res),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{noise, solid};
+
+    /// A black `size` rectangle at `at` on white, `res` in all
+    fn receipt(res: ResXY, at: XY, size: ResXY) -> Image {
+        let mut img = solid(res, [1.; 4]);
+        img.map_pixels_indexed(|(x, y), p| {
+            let inside = (at.0..at.0 + size.0).contains(&x) && (at.1..at.1 + size.1).contains(&y);
+            if inside {
+                [0., 0., 0., 1.]
+            } else {
+                p
+            }
+        });
+        img
+    }
+
+    #[test]
+    fn crops_to_rectangle() {
+        for (res, at) in [((20, 12), (3, 4)), ((40, 40), (1, 30)), ((9, 30), (6, 0))] {
+            let mut img = receipt(res, at, (3, 5));
+            let original = img.clone();
+            let rect = img.auto_crop(0.02);
+            assert_eq!(rect, (at, (3, 5)), "{res:?}");
+            // The rect is what was cropped
+            let mut cropped = original;
+            cropped.crop(rect.0, rect.1).unwrap();
+            assert_eq!(img.res, rect.1);
+            assert_eq!(img.pixels(), cropped.pixels());
+            assert!(img.pixels().iter().all(|p| *p == [0., 0., 0., 1.]));
+        }
+    }
+
+    #[test]
+    fn ignores_mild_noise() {
+        let mut img = receipt((30, 20), (10, 5), (6, 4));
+        let mut seed = 7;
+        img.map_pixels(|p| {
+            // About 1/255 either way, like compression noise
+            let n = (noise(&mut seed) - 0.5) * 2. / 255.;
+            [p[0] - n.abs(), p[1] - n.abs(), p[2] - n.abs(), p[3]]
+        });
+        let mut tight = img.clone();
+        assert_eq!(img.auto_crop(0.02), ((10, 5), (6, 4)));
+        // Without tolerance the noise is content
+        assert_eq!(tight.auto_crop(0.), ((0, 0), (30, 20)));
+    }
+
+    #[test]
+    fn all_background_untouched() {
+        let mut img = solid((8, 5), [1.; 4]);
+        assert_eq!(img.auto_crop(0.02), ((0, 0), (8, 5)));
+        assert_eq!(img.res, (8, 5));
+        let mut empty = Image::from_bytes(&[], (0, 3), ColorSpace::sRGB);
+        assert_eq!(empty.auto_crop(0.02), ((0, 0), (0, 3)));
+    }
+}
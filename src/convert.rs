@@ -0,0 +1,541 @@
+//! Reusable color space conversions
+//!
+//! Every conversion is planned as the same few steps, decode the transfer
+//! function, one matrix between primaries, map to the gamut, and encode, so
+//! new color spaces only have to say what their primaries and transfer
+//! functions are.
+use nalgebra::Matrix3;
+
+use crate::{
+    color::chromaticity::{Xy, ADOBE_RGB_PRIMARIES, DISPLAY_P3_PRIMARIES, SRGB_PRIMARIES},
+    transforms::*,
+    AlphaMode, ColorSpace, Image, ImageError, Transfer, WorkPixel,
+};
+
+/// Primaries of `color`, or `None` for [`ColorSpace::AsIs`]
+pub(crate) fn primaries(color: ColorSpace) -> Option<[Xy; 3]> {
+    match color {
+        ColorSpace::sRGB | ColorSpace::sRGBLinear | ColorSpace::SimplesRGB => Some(SRGB_PRIMARIES),
+        ColorSpace::DisplayP3 => Some(DISPLAY_P3_PRIMARIES),
+        ColorSpace::AdobeRgb => Some(ADOBE_RGB_PRIMARIES),
+        ColorSpace::AsIs => None,
+    }
+}
+
+/// The steps to convert pixels from one color space to another
+///
+/// All the built in color spaces share the D65 white point, so no
+/// chromatic adaptation is ever needed between them.
+#[derive(Debug, Clone, Copy)]
+pub struct ConversionPlan {
+    from: ColorSpace,
+    to: ColorSpace,
+    intent: Option<GamutMap>,
+    decode: Option<Transfer>,
+    matrix: Option<Matrix3<f32>>,
+    encode: Option<Transfer>,
+}
+
+impl ConversionPlan {
+    /// Plan a conversion from `from` to `to`, bringing out of gamut colors
+    /// back in with `intent`, if any
+    ///
+    /// Without an intent, converting to the same color space, or to or from
+    /// [`ColorSpace::AsIs`], is the identity. With one, colors are always
+    /// mapped, in linear light unless [`ColorSpace::AsIs`] is involved.
+    pub fn new(from: ColorSpace, to: ColorSpace, intent: Option<GamutMap>) -> Self {
+        let convert = from != ColorSpace::AsIs && to != ColorSpace::AsIs;
+        let convert = convert && (from != to || intent.is_some());
+        let matrix = match (primaries(from), primaries(to)) {
+            (Some(a), Some(b)) if a != b => Some(primaries_matrix(a, b)),
+            _ => None,
+        };
+        Self {
+            from,
+            to,
+            intent,
+            decode: from.transfer().map(|t| t.0).filter(|_| convert),
+            matrix: matrix.filter(|_| convert),
+            encode: to.transfer().map(|t| t.1).filter(|_| convert),
+        }
+    }
+
+    /// [`ConversionPlan::new`], reusing recent plans with the `plan-cache`
+    /// feature
+    ///
+    /// Targets without atomic compare and swap always plan it.
+    pub fn cached(from: ColorSpace, to: ColorSpace, intent: Option<GamutMap>) -> Self {
+        #[cfg(all(feature = "plan-cache", target_has_atomic = "8"))]
+        return cache::get(from, to, intent);
+        #[cfg(not(all(feature = "plan-cache", target_has_atomic = "8")))]
+        Self::new(from, to, intent)
+    }
+
+    pub fn from(&self) -> ColorSpace {
+        self.from
+    }
+
+    pub fn to(&self) -> ColorSpace {
+        self.to
+    }
+
+    pub fn intent(&self) -> Option<GamutMap> {
+        self.intent
+    }
+
+    /// Whether this leaves pixels exactly as they are
+    pub fn is_identity(&self) -> bool {
+        self.decode.is_none()
+            && self.matrix.is_none()
+            && self.intent.is_none()
+            && self.encode.is_none()
+    }
+
+    /// Convert `rows`, which must be in [`ConversionPlan::from`]
+    pub fn apply(&self, rows: &mut [WorkPixel]) {
+        if self.is_identity() {
+            return;
+        }
+        #[cfg(feature = "simd")]
+        return self.apply_simd(rows);
+        #[cfg(not(feature = "simd"))]
+        self.apply_scalar(rows);
+    }
+
+    /// [`ConversionPlan::apply`], a pixel at a time
+    #[cfg(any(test, not(feature = "simd")))]
+    fn apply_scalar(&self, rows: &mut [WorkPixel]) {
+        for p in rows {
+            let mut rgb = [p[0], p[1], p[2]];
+            if let Some(f) = self.decode {
+                rgb = rgb.map(f);
+            }
+            if let Some(m) = &self.matrix {
+                let [r, g, b] = rgb;
+                rgb = [
+                    m[(0, 0)] * r + m[(0, 1)] * g + m[(0, 2)] * b,
+                    m[(1, 0)] * r + m[(1, 1)] * g + m[(1, 2)] * b,
+                    m[(2, 0)] * r + m[(2, 1)] * g + m[(2, 2)] * b,
+                ];
+            }
+            if let Some(intent) = self.intent {
+                rgb = map_to_gamut(rgb, intent);
+            }
+            if let Some(f) = self.encode {
+                rgb = rgb.map(f);
+            }
+            *p = [rgb[0], rgb[1], rgb[2], p[3]];
+        }
+    }
+
+    /// [`ConversionPlan::apply`], a stage at a time over runs of pixels
+    #[cfg(feature = "simd")]
+    fn apply_simd(&self, rows: &mut [WorkPixel]) {
+        for run in rows.chunks_mut(256) {
+            if self.decode.is_some() {
+                crate::simd::transfer_pixels(run, self.from.transfer_function(), false);
+            }
+            if let Some(m) = &self.matrix {
+                crate::simd::matrix(run, m);
+            }
+            if let Some(intent) = self.intent {
+                for p in run.iter_mut() {
+                    let [r, g, b] = map_to_gamut([p[0], p[1], p[2]], intent);
+                    *p = [r, g, b, p[3]];
+                }
+            }
+            if self.encode.is_some() {
+                crate::simd::transfer_pixels(run, self.to.transfer_function(), true);
+            }
+        }
+    }
+}
+
+/// The last few plans, so converting between the same spaces again skips
+/// planning
+#[cfg(all(feature = "plan-cache", target_has_atomic = "8"))]
+mod cache {
+    use super::ConversionPlan;
+    use crate::{lock::TryLock, ColorSpace, GamutMap};
+
+    const SIZE: usize = 8;
+
+    type Key = (ColorSpace, ColorSpace, Option<GamutMap>);
+
+    /// Plans, most recently used first
+    static CACHE: TryLock<[Option<(Key, ConversionPlan)>; SIZE]> = TryLock::new([None; SIZE]);
+
+    /// The cached plan for `from`, `to`, and `intent`, planning it if needed
+    ///
+    /// If the cache is busy this just plans it, never waiting.
+    pub(super) fn get(
+        from: ColorSpace,
+        to: ColorSpace,
+        intent: Option<GamutMap>,
+    ) -> ConversionPlan {
+        let key = (from, to, intent);
+        let hit = CACHE.try_with(|c| {
+            let i = c.iter().position(|e| e.is_some_and(|(k, _)| k == key))?;
+            c[..=i].rotate_right(1);
+            c[0].map(|(_, plan)| plan)
+        });
+        if let Some(Some(plan)) = hit {
+            return plan;
+        }
+        let plan = ConversionPlan::new(from, to, intent);
+        CACHE.try_with(|c| {
+            c.rotate_right(1);
+            c[0] = Some((key, plan));
+        });
+        plan
+    }
+}
+
+/// A conversion between two color spaces, set up once
+///
+/// For converting lots of small images, like sprites from an atlas, this
+/// works out the steps and builds the gamut matrix just once.
+/// [`Image::to_color`] and [`convert_rows`](crate::convert_rows) use this,
+/// so the results are identical.
+#[derive(Debug, Clone)]
+pub struct Converter {
+    plan: ConversionPlan,
+}
+
+impl Converter {
+    /// A conversion from `from` to `to`
+    ///
+    /// Converting to the same color space, or to or from
+    /// [`ColorSpace::AsIs`], doesn't touch the pixels.
+    pub fn new(from: ColorSpace, to: ColorSpace) -> Self {
+        Self {
+            plan: ConversionPlan::cached(from, to, None),
+        }
+    }
+
+    pub fn from(&self) -> ColorSpace {
+        self.plan.from
+    }
+
+    pub fn to(&self) -> ColorSpace {
+        self.plan.to
+    }
+
+    /// Convert `rows`, which must be in [`Converter::from`]
+    pub fn convert_rows(&self, rows: &mut [WorkPixel]) {
+        self.plan.apply(rows)
+    }
+
+    /// Convert `img`, like [`Image::to_color`]
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::ColorSpaceMismatch`] if `img` isn't in
+    ///   [`Converter::from`]
+    pub fn convert(&self, img: &mut Image) -> Result<(), ImageError> {
+        if img.color != self.plan.from {
+            return Err(ImageError::ColorSpaceMismatch);
+        }
+        self.apply(img);
+        Ok(())
+    }
+
+    /// Convert every image in `imgs`, see [`Converter::convert`]
+    ///
+    /// All the images are checked first, so on error none are converted.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::ColorSpaceMismatch`] if any image isn't in
+    ///   [`Converter::from`]
+    pub fn convert_many(&self, imgs: &mut [Image]) -> Result<(), ImageError> {
+        if imgs.iter().any(|img| img.color != self.plan.from) {
+            return Err(ImageError::ColorSpaceMismatch);
+        }
+        for img in imgs {
+            self.apply(img);
+        }
+        Ok(())
+    }
+
+    /// Convert `img`, which must be in [`Converter::from`]
+    pub(crate) fn apply(&self, img: &mut Image) {
+        apply_plan(&self.plan, img)
+    }
+}
+
+/// Convert `img` with `plan`, which must be from its color space
+pub(crate) fn apply_plan(plan: &ConversionPlan, img: &mut Image) {
+    profile!(ToColor);
+    apply_alpha(plan, &mut img.data, img.alpha);
+    img.color = plan.to;
+    img.check();
+}
+
+/// Convert `rows` of an image with `alpha` using `plan`
+///
+/// Transfer functions only make sense on straight color, so premultiplied
+/// pixels are converted unpremultiplied.
+pub(crate) fn apply_alpha(plan: &ConversionPlan, rows: &mut [WorkPixel], alpha: AlphaMode) {
+    if alpha == AlphaMode::Straight || plan.is_identity() {
+        return plan.apply(rows);
+    }
+    for p in rows.iter_mut() {
+        *p = unpremultiply(*p);
+    }
+    plan.apply(rows);
+    for p in rows.iter_mut() {
+        *p = premultiply(*p);
+    }
+}
+
+/// Convert `rows` of an image with `alpha` from `from` to `to`, like
+/// [`convert_rows`](crate::convert_rows) but minding premultiplied alpha
+pub(crate) fn convert_image_rows(
+    rows: &mut [WorkPixel],
+    alpha: AlphaMode,
+    from: ColorSpace,
+    to: ColorSpace,
+) {
+    apply_alpha(&ConversionPlan::cached(from, to, None), rows, alpha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{max_diff, photo},
+        PixelFormat,
+    };
+
+    fn pixel(alpha: AlphaMode) -> Image {
+        let mut img = Image::from_raw(
+            &[200, 100, 50, 128],
+            (1, 1),
+            PixelFormat::Rgba8888,
+            ColorSpace::sRGB,
+        )
+        .unwrap();
+        img.to_alpha_mode(alpha);
+        img
+    }
+
+    fn assert_close(a: &Image, b: &Image) {
+        for (a, b) in a.pixels().iter().zip(b.pixels()) {
+            for c in 0..4 {
+                assert!((a[c] - b[c]).abs() < 1e-5, "{a:?} != {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn premultiplied_matches_straight() {
+        let mut straight = pixel(AlphaMode::Straight);
+        straight.to_color(ColorSpace::sRGBLinear);
+        assert!((straight.pixels()[0][0] - 0.578).abs() < 1e-3);
+
+        let mut premul = pixel(AlphaMode::Premultiplied);
+        premul.to_color(ColorSpace::sRGBLinear);
+        premul.to_alpha_mode(AlphaMode::Straight);
+        assert_close(&premul, &straight);
+    }
+
+    #[test]
+    fn converter_minds_alpha() {
+        let mut straight = pixel(AlphaMode::Straight);
+        straight.to_color(ColorSpace::DisplayP3);
+
+        let converter = Converter::new(ColorSpace::sRGB, ColorSpace::DisplayP3);
+        let mut one = pixel(AlphaMode::Premultiplied);
+        converter.convert(&mut one).unwrap();
+        one.to_alpha_mode(AlphaMode::Straight);
+        assert_close(&one, &straight);
+
+        let mut many = [pixel(AlphaMode::Premultiplied), pixel(AlphaMode::Straight)];
+        converter.convert_many(&mut many).unwrap();
+        for img in &mut many {
+            img.to_alpha_mode(AlphaMode::Straight);
+            assert_close(img, &straight);
+        }
+    }
+
+    const SPACES: [ColorSpace; 5] = [
+        ColorSpace::sRGB,
+        ColorSpace::sRGBLinear,
+        ColorSpace::SimplesRGB,
+        ColorSpace::DisplayP3,
+        ColorSpace::AsIs,
+    ];
+
+    /// How conversions worked before plans, one arm per pair of spaces
+    fn by_arms(rows: &mut [WorkPixel], from: ColorSpace, to: ColorSpace) {
+        if from == to || from == ColorSpace::AsIs || to == ColorSpace::AsIs {
+            return;
+        }
+        let p3 = |c| c == ColorSpace::DisplayP3;
+        if let Some((decode, _)) = from.transfer() {
+            apply_transfer(rows, decode);
+        }
+        match (p3(from), p3(to)) {
+            (false, true) => apply_matrix3(rows, &srgb_to_p3_matrix()),
+            (true, false) => apply_matrix3(rows, &p3_to_srgb_matrix()),
+            _ => (),
+        }
+        if let Some((_, encode)) = to.transfer() {
+            apply_transfer(rows, encode);
+        }
+    }
+
+    /// How gamut mapped conversions worked before plans
+    fn by_arms_gamut(rows: &mut [WorkPixel], from: ColorSpace, to: ColorSpace, gamut: GamutMap) {
+        let convert = from != ColorSpace::AsIs && to != ColorSpace::AsIs;
+        if convert {
+            by_arms(rows, from, ColorSpace::sRGBLinear);
+            if to == ColorSpace::DisplayP3 {
+                apply_matrix3(rows, &srgb_to_p3_matrix());
+            }
+        }
+        for p in rows.iter_mut() {
+            let [r, g, b] = map_to_gamut([p[0], p[1], p[2]], gamut);
+            *p = [r, g, b, p[3]];
+        }
+        if let Some((_, encode)) = to.transfer().filter(|_| convert) {
+            apply_transfer(rows, encode);
+        }
+    }
+
+    #[test]
+    fn plans_match_arms() {
+        let src = photo((16, 8)).data;
+        for from in SPACES {
+            for to in SPACES {
+                let mut want = src.clone();
+                by_arms(&mut want, from, to);
+                let mut got = src.clone();
+                ConversionPlan::new(from, to, None).apply(&mut got);
+                assert!(max_diff(&got, &want) < 1e-6, "{from:?} to {to:?}");
+
+                for gamut in [GamutMap::Clip, GamutMap::SoftClip, GamutMap::ChromaReduce] {
+                    let mut want = src.clone();
+                    by_arms_gamut(&mut want, from, to, gamut);
+                    let mut got = src.clone();
+                    ConversionPlan::new(from, to, Some(gamut)).apply(&mut got);
+                    assert!(
+                        max_diff(&got, &want) < 1e-5,
+                        "{from:?} to {to:?} with {gamut:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn identity() {
+        for from in SPACES {
+            assert!(ConversionPlan::new(from, from, None).is_identity());
+            assert!(ConversionPlan::new(from, ColorSpace::AsIs, None).is_identity());
+            assert!(!ConversionPlan::new(from, from, Some(GamutMap::Clip)).is_identity());
+        }
+        let plan = ConversionPlan::new(ColorSpace::sRGB, ColorSpace::sRGB, None);
+        let mut rows = [[f32::NAN, -1., 2., 0.5]];
+        plan.apply(&mut rows);
+        assert_eq!(
+            rows[0].map(f32::to_bits),
+            [f32::NAN, -1., 2., 0.5].map(f32::to_bits)
+        );
+    }
+
+    #[test]
+    fn cached_matches_new() {
+        for _ in 0..2 {
+            for from in SPACES {
+                for to in SPACES {
+                    let new = ConversionPlan::new(from, to, Some(GamutMap::SoftClip));
+                    let cached = ConversionPlan::cached(from, to, Some(GamutMap::SoftClip));
+                    assert_eq!(alloc::format!("{new:?}"), alloc::format!("{cached:?}"));
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn simd_matches_scalar() {
+        let mut seed = 11;
+        let src: alloc::vec::Vec<WorkPixel> = (0..1001)
+            .map(|_| [(); 4].map(|_| crate::fixtures::noise(&mut seed) * 1.2 - 0.1))
+            .collect();
+        for from in SPACES {
+            for to in SPACES {
+                for intent in [None, Some(GamutMap::SoftClip), Some(GamutMap::ChromaReduce)] {
+                    let plan = ConversionPlan::new(from, to, intent);
+                    let (mut simd, mut scalar) = (src.clone(), src.clone());
+                    plan.apply(&mut simd);
+                    plan.apply_scalar(&mut scalar);
+                    assert!(max_diff(&simd, &scalar) < 1e-6, "{plan:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn converter_checks_color() {
+        let converter = Converter::new(ColorSpace::DisplayP3, ColorSpace::sRGB);
+        let mut img = pixel(AlphaMode::Straight);
+        assert_eq!(
+            converter.convert(&mut img),
+            Err(ImageError::ColorSpaceMismatch)
+        );
+    }
+
+    #[test]
+    fn converter_matches_to_color() {
+        for from in SPACES {
+            for to in SPACES {
+                let mut src = photo((8, 8));
+                src.color = from;
+                let mut want = src.clone();
+                want.to_color(to);
+                let mut got = src.clone();
+                Converter::new(from, to).convert(&mut got).unwrap();
+                assert_eq!(got.color, to);
+                assert_eq!(got.pixels(), want.pixels(), "{from:?} to {to:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn converter_is_built_once() {
+        // The plan, matrix and all, is made in `new`, converting only reads it
+        let converter = Converter::new(ColorSpace::sRGB, ColorSpace::DisplayP3);
+        let sprites: alloc::vec::Vec<Image> = (0..100)
+            .map(|i| {
+                let mut img = photo((4, 4));
+                img.map_pixels(|p| p.map(|c| (c + i as f32 / 100.) % 1.));
+                img
+            })
+            .collect();
+        let mut many = sprites.clone();
+        converter.convert_many(&mut many).unwrap();
+        for (got, mut want) in many.into_iter().zip(sprites) {
+            want.to_color(ColorSpace::DisplayP3);
+            assert_eq!(got.pixels(), want.pixels());
+        }
+    }
+
+    #[test]
+    fn convert_many_checks_all_first() {
+        let converter = Converter::new(ColorSpace::sRGB, ColorSpace::sRGBLinear);
+        let mut imgs = [photo((4, 4)), photo((4, 4))];
+        imgs[1].color = ColorSpace::DisplayP3;
+        let before = imgs.clone();
+        assert_eq!(
+            converter.convert_many(&mut imgs),
+            Err(ImageError::ColorSpaceMismatch)
+        );
+        for (a, b) in imgs.iter().zip(&before) {
+            assert_eq!(a.color, b.color);
+            assert_eq!(a.pixels(), b.pixels());
+        }
+    }
+}
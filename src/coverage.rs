@@ -0,0 +1,176 @@
+//! Coverage masks, for hardware that blends by subsample coverage instead of
+//! alpha
+use alloc::vec::Vec;
+
+use crate::{dither::ThresholdMap, ColorSpace, Image, ImageError, ResXY, F32};
+
+/// Bytes per mask for `samples`, or an error for unsupported counts
+fn mask_bytes(samples: u8) -> Result<usize, ImageError> {
+    match samples {
+        2 | 4 | 8 => Ok(1),
+        16 => Ok(2),
+        _ => Err(ImageError::InvalidArgument),
+    }
+}
+
+/// Sample `k` of `samples`, in bit reversed order so the first few are
+/// spread out
+fn sample_bit(k: u32, samples: u8) -> u32 {
+    let bits = samples.trailing_zeros();
+    k.reverse_bits() >> (32 - bits)
+}
+
+impl Image {
+    /// Convert each pixel's alpha to a coverage bitmask of `samples` bits
+    ///
+    /// Which samples are set depends on the pixel's position in a 4x4 Bayer
+    /// pattern, so flat areas of partial alpha don't all pick the same ones
+    /// and band. The number set is rounded, dithered by the same pattern.
+    ///
+    /// Masks are one byte each, or two little endian bytes for 16 samples,
+    /// in row order.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `samples` isn't 2, 4, 8, or 16
+    pub fn to_coverage_masks(&self, samples: u8) -> Result<Vec<u8>, ImageError> {
+        let bytes = mask_bytes(samples)?;
+        let map = ThresholdMap::bayer4();
+        let s = samples as u32;
+        let mut out = Vec::with_capacity(self.data.len() * bytes);
+        for (i, p) in self.data.iter().enumerate() {
+            let xy = (
+                (i % self.width() as usize) as u32,
+                (i / self.width() as usize) as u32,
+            );
+            let a = if p[3].is_nan() {
+                0.
+            } else {
+                p[3].clamp(0., 1.)
+            };
+            let n = ((a * s as f32 + map.threshold(xy, (0, 0))).round() as u32).min(s);
+            // The top bits of the pattern, so neighbors differ even for 2
+            let rotate = map.values()[((xy.1 % 4) * 4 + xy.0 % 4) as usize] * s / 16;
+            let mask = (0..n).fold(0u16, |m, k| m | 1 << sample_bit((k + rotate) % s, samples));
+            out.extend_from_slice(&mask.to_le_bytes()[..bytes]);
+        }
+        Ok(out)
+    }
+
+    /// Create a white image with alpha from coverage masks, the inverse of
+    /// [`Image::to_coverage_masks`]
+    ///
+    /// Alpha is the fraction of the `samples` bits set.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `samples` isn't 2, 4, 8, or 16
+    /// - [`ImageError::BufferSize`] if `masks` is the wrong size for `res`
+    pub fn from_coverage_masks(masks: &[u8], res: ResXY, samples: u8) -> Result<Image, ImageError> {
+        let bytes = mask_bytes(samples)?;
+        let expected = res.0 as usize * res.1 as usize * bytes;
+        if masks.len() != expected {
+            return Err(ImageError::BufferSize {
+                expected,
+                actual: masks.len(),
+            });
+        }
+        let valid = (1u32 << samples) - 1;
+        let data = masks
+            .chunks_exact(bytes)
+            .map(|m| {
+                let m = m.iter().rev().fold(0u32, |v, b| v << 8 | *b as u32) & valid;
+                [1., 1., 1., m.count_ones() as f32 / samples as f32]
+            })
+            .collect();
+        Ok(Image::from_parts(data, res, ColorSpace::sRGB))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::solid;
+
+    const SAMPLES: [u8; 4] = [2, 4, 8, 16];
+
+    /// The coverage masks of `img`, as numbers
+    fn masks(img: &Image, samples: u8) -> Vec<u32> {
+        let bytes = mask_bytes(samples).unwrap();
+        let masks = img.to_coverage_masks(samples).unwrap();
+        assert_eq!(masks.len(), img.pixels().len() * bytes);
+        masks
+            .chunks_exact(bytes)
+            .map(|m| m.iter().rev().fold(0, |v, b| v << 8 | *b as u32))
+            .collect()
+    }
+
+    #[test]
+    fn full_and_empty() {
+        for samples in SAMPLES {
+            let full = (1u32 << samples) - 1;
+            let opaque = solid((8, 8), [0.2, 0.4, 0.6, 1.]);
+            assert!(masks(&opaque, samples).iter().all(|m| *m == full));
+            let clear = solid((8, 8), [0.2, 0.4, 0.6, 0.]);
+            assert!(masks(&clear, samples).iter().all(|m| *m == 0));
+        }
+    }
+
+    #[test]
+    fn half_alpha_is_half_the_bits() {
+        let img = solid((8, 8), [1., 1., 1., 0.5]);
+        for samples in SAMPLES {
+            let masks = masks(&img, samples);
+            let bits: u32 = masks.iter().map(|m| m.count_ones()).sum();
+            assert_eq!(bits, 64 * samples as u32 / 2, "{samples}");
+            // Neighbors pick different samples
+            for (a, b) in masks.iter().zip(&masks[1..]).step_by(2) {
+                assert_ne!(a, b, "{samples}");
+            }
+            assert_ne!(masks[0], masks[8]);
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        for samples in SAMPLES {
+            let mut img = solid((4, 4), [1.; 4]);
+            let s = samples as u32;
+            img.map_pixels_indexed(|(x, y), p| {
+                [p[0], p[1], p[2], ((x + y * 4) % (s + 1)) as f32 / s as f32]
+            });
+            let back = Image::from_coverage_masks(
+                &img.to_coverage_masks(samples).unwrap(),
+                (4, 4),
+                samples,
+            )
+            .unwrap();
+            assert_eq!(back.pixels(), img.pixels(), "{samples}");
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let img = solid((2, 2), [1.; 4]);
+        for samples in [0, 1, 3, 32, 255] {
+            assert_eq!(
+                img.to_coverage_masks(samples),
+                Err(ImageError::InvalidArgument)
+            );
+            assert_eq!(
+                Image::from_coverage_masks(&[0; 4], (2, 2), samples).err(),
+                Some(ImageError::InvalidArgument)
+            );
+        }
+        assert_eq!(
+            Image::from_coverage_masks(&[0; 4], (2, 2), 16).err(),
+            Some(ImageError::BufferSize {
+                expected: 8,
+                actual: 4
+            })
+        );
+        // Bits past the sample count are ignored
+        let img = Image::from_coverage_masks(&[0xff], (1, 1), 4).unwrap();
+        assert_eq!(img.pixels(), [[1.; 4]]);
+    }
+}
@@ -0,0 +1,221 @@
+//! Color vision deficiency simulation
+use nalgebra::Matrix3;
+
+use crate::{transforms::*, Image};
+
+/// Kinds of dichromacy for [`Image::simulate_cvd`] and [`Image::daltonize`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CvdKind {
+    /// Missing or anomalous long wavelength, red, cones
+    Protanopia,
+
+    /// Missing or anomalous medium wavelength, green, cones
+    Deuteranopia,
+
+    /// Missing or anomalous short wavelength, blue, cones
+    Tritanopia,
+}
+
+impl CvdKind {
+    /// Machado, Oliveira, and Fernandes 2009 simulation matrix for full
+    /// severity, on linear sRGB
+    fn matrix(self) -> Matrix3<f32> {
+        match self {
+            CvdKind::Protanopia => Matrix3::new(
+                0.152286, 1.052583, -0.204868, //
+                0.114503, 0.786281, 0.099216, //
+                -0.003882, -0.048116, 1.051998,
+            ),
+            CvdKind::Deuteranopia => Matrix3::new(
+                0.367322, 0.860646, -0.227968, //
+                0.280085, 0.672501, 0.047413, //
+                -0.011820, 0.042940, 0.968881,
+            ),
+            CvdKind::Tritanopia => Matrix3::new(
+                1.255528, -0.076749, -0.178779, //
+                -0.078411, 0.930809, 0.147602, //
+                0.004733, 0.691367, 0.303900,
+            ),
+        }
+    }
+
+    /// Simulation matrix for `severity`, `0..=1`
+    ///
+    /// Interpolated linearly from the identity, rather than using the
+    /// paper's table of intermediate matrices.
+    fn matrix_with_severity(self, severity: f32) -> Matrix3<f32> {
+        let s = severity.clamp(0., 1.);
+        Matrix3::identity() * (1. - s) + self.matrix() * s
+    }
+
+    /// Where the error [`Image::daltonize`] shifts goes
+    fn shift(self) -> Matrix3<f32> {
+        match self {
+            // Red and green are confused, move the lost information to blue
+            // and green
+            CvdKind::Protanopia | CvdKind::Deuteranopia => Matrix3::new(
+                0., 0., 0., //
+                0.7, 1., 0., //
+                0.7, 0., 1.,
+            ),
+            // Blue is lost, move it to red and green
+            CvdKind::Tritanopia => Matrix3::new(
+                1., 0., 0.7, //
+                0., 1., 0.7, //
+                0., 0., 0.,
+            ),
+        }
+    }
+}
+
+impl Image {
+    /// Simulate how the image looks with the color vision deficiency `kind`
+    ///
+    /// `severity` is `0..=1`, `0` leaves the image untouched and `1` is full
+    /// dichromacy. This uses the Machado et al. 2009 model in linear light,
+    /// which assumes sRGB primaries. Alpha is untouched.
+    pub fn simulate_cvd(&mut self, kind: CvdKind, severity: f32) {
+        if severity <= 0. {
+            return;
+        }
+        let m = kind.matrix_with_severity(severity);
+        self.in_linear(|img| apply_matrix3(&mut img.data, &m));
+    }
+
+    /// Recolor the image so differences lost to the color vision deficiency
+    /// `kind` become visible again
+    ///
+    /// The difference from a full severity simulation is shifted into
+    /// channels that can still be seen, scaled by `strength`. `0` leaves the
+    /// image untouched. This is done in linear light and clamped to `0..=1`.
+    /// Alpha is untouched.
+    pub fn daltonize(&mut self, kind: CvdKind, strength: f32) {
+        if strength == 0. {
+            return;
+        }
+        let sim = kind.matrix();
+        let shift = kind.shift() * strength;
+        // out = p + shift * (p - sim * p)
+        let m = Matrix3::identity() + shift * (Matrix3::identity() - sim);
+        self.in_linear(|img| {
+            apply_matrix3(&mut img.data, &m);
+            for p in &mut img.data {
+                for c in &mut p[..3] {
+                    *c = c.clamp(0., 1.);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{
+        fixtures::{max_diff, photo},
+        ColorSpace,
+    };
+
+    const KINDS: [CvdKind; 3] = [
+        CvdKind::Protanopia,
+        CvdKind::Deuteranopia,
+        CvdKind::Tritanopia,
+    ];
+
+    /// Red, green, and blue, in linear light
+    fn primaries(alpha: f32) -> Image {
+        let data = vec![
+            [1., 0., 0., alpha],
+            [0., 1., 0., alpha],
+            [0., 0., 1., alpha],
+        ];
+        Image::from_parts(data, (3, 1), ColorSpace::sRGBLinear)
+    }
+
+    #[test]
+    fn full_severity_primaries() {
+        for kind in KINDS {
+            let mut img = primaries(1.);
+            img.simulate_cvd(kind, 1.);
+            let m = kind.matrix();
+            // Each primary picks out a column
+            for (i, p) in img.pixels().iter().enumerate() {
+                for c in 0..3 {
+                    assert!((p[c] - m[(c, i)]).abs() < 1e-6, "{kind:?} {i} {p:?}");
+                }
+            }
+        }
+        let mut img = primaries(1.);
+        img.simulate_cvd(CvdKind::Protanopia, 1.);
+        let red = img.pixels()[0];
+        assert!((red[0] - 0.152286).abs() < 1e-6);
+        assert!((red[1] - 0.114503).abs() < 1e-6);
+        assert!((red[2] + 0.003882).abs() < 1e-6);
+    }
+
+    #[test]
+    fn severity_interpolates() {
+        let orig = primaries(1.);
+        let mut full = orig.clone();
+        full.simulate_cvd(CvdKind::Deuteranopia, 1.);
+        let mut half = orig.clone();
+        half.simulate_cvd(CvdKind::Deuteranopia, 0.5);
+        for ((h, f), o) in half.pixels().iter().zip(full.pixels()).zip(orig.pixels()) {
+            for c in 0..3 {
+                assert!((h[c] - (f[c] + o[c]) / 2.).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_is_identity() {
+        let img = photo((16, 16));
+        for kind in KINDS {
+            let mut sim = img.clone();
+            sim.simulate_cvd(kind, 0.);
+            assert_eq!(sim.pixels(), img.pixels());
+            let mut fixed = img.clone();
+            fixed.daltonize(kind, 0.);
+            assert_eq!(fixed.pixels(), img.pixels());
+        }
+    }
+
+    #[test]
+    fn alpha_untouched() {
+        for kind in KINDS {
+            let mut sim = primaries(0.25);
+            sim.simulate_cvd(kind, 1.);
+            let mut fixed = primaries(0.25);
+            fixed.daltonize(kind, 1.);
+            for p in sim.pixels().iter().chain(fixed.pixels()) {
+                assert_eq!(p[3], 0.25);
+            }
+        }
+    }
+
+    #[test]
+    fn daltonize_separates_confused_colors() {
+        // Red and green look much alike to a protanope
+        let seen = |img: &Image| {
+            let mut img = img.clone();
+            img.simulate_cvd(CvdKind::Protanopia, 1.);
+            img
+        };
+        let orig = Image::from_parts(
+            vec![[0.8, 0.2, 0.1, 1.], [0.3, 0.5, 0.1, 1.]],
+            (2, 1),
+            ColorSpace::sRGBLinear,
+        );
+        let mut fixed = orig.clone();
+        fixed.daltonize(CvdKind::Protanopia, 1.);
+        let diff = |img: &Image| max_diff(&img.pixels()[..1], &img.pixels()[1..]);
+        assert!(diff(&seen(&fixed)) > diff(&seen(&orig)));
+        assert!(fixed
+            .pixels()
+            .iter()
+            .flatten()
+            .all(|c| (0.0..=1.).contains(c)));
+    }
+}
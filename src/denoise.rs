@@ -0,0 +1,267 @@
+//! Edge preserving noise reduction
+use alloc::{vec, vec::Vec};
+
+use crate::{layout::quantize, AlphaMode, Image};
+
+impl Image {
+    /// Run `f` on straight alpha pixels, restoring the alpha mode after
+    pub(crate) fn in_straight(&mut self, f: impl FnOnce(&mut Self)) {
+        let alpha = self.alpha;
+        self.to_alpha_mode(AlphaMode::Straight);
+        f(self);
+        self.to_alpha_mode(alpha);
+    }
+
+    /// Replace each color channel with its median over the square of
+    /// `radius` around it
+    ///
+    /// This removes specks, like salt and pepper noise, while keeping edges
+    /// sharp. Medians are found from sliding 8 bit histograms, so the cost
+    /// per pixel barely grows with the radius, and results are to 8 bit
+    /// precision. Edges are clamped, alpha is untouched, and a `radius` of
+    /// zero does nothing.
+    pub fn median_filter(&mut self, radius: u32) {
+        if radius == 0 || self.data.is_empty() {
+            return;
+        }
+        self.in_straight(|img| {
+            let (w, h) = (img.res.0 as i64, img.res.1 as i64);
+            let r = radius as i64;
+            let half = ((2 * r + 1) * (2 * r + 1) / 2) as u32;
+            let bins: Vec<[u8; 3]> = img
+                .data
+                .iter()
+                .map(|p| [0, 1, 2].map(|c| quantize(p[c], 255.) as u8))
+                .collect();
+            let at = |x: i64, y: i64| &bins[(y.clamp(0, h - 1) * w + x.clamp(0, w - 1)) as usize];
+
+            let mut hist = vec![[0u32; 256]; 3];
+            for y in 0..h {
+                hist.iter_mut().for_each(|h| h.fill(0));
+                let column = |hist: &mut [[u32; 256]], x: i64, add: bool| {
+                    for dy in -r..=r {
+                        for (c, v) in at(x, y + dy).iter().enumerate() {
+                            if add {
+                                hist[c][*v as usize] += 1;
+                            } else {
+                                hist[c][*v as usize] -= 1;
+                            }
+                        }
+                    }
+                };
+                for x in -r..=r {
+                    column(&mut hist, x, true);
+                }
+                for x in 0..w {
+                    if x > 0 {
+                        column(&mut hist, x - r - 1, false);
+                        column(&mut hist, x + r, true);
+                    }
+                    let p = &mut img.data[(y * w + x) as usize];
+                    for (c, hist) in hist.iter().enumerate() {
+                        let mut seen = 0;
+                        let median = hist
+                            .iter()
+                            .position(|n| {
+                                seen += n;
+                                seen > half
+                            })
+                            .unwrap_or(255);
+                        p[c] = median as f32 / 255.;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Kuwahara filter, replacing each pixel with the mean of whichever of
+    /// the four `radius + 1` square quadrants around it varies least
+    ///
+    /// This smooths flat areas while keeping edges, for a painterly look.
+    /// Variance is summed over the color channels, and means are taken in
+    /// linear light. Edges are clamped, alpha is untouched, and a `radius`
+    /// of zero does nothing.
+    pub fn kuwahara(&mut self, radius: u32) {
+        if radius == 0 || self.data.is_empty() {
+            return;
+        }
+        self.in_straight(|img| {
+            img.in_linear(|img| {
+                let (w, h) = (img.res.0 as i64, img.res.1 as i64);
+                let r = radius as i64;
+                // Summed area tables of the color and its square, over the
+                // image padded by `r` with its edges
+                let (pw, ph) = (w + 2 * r, h + 2 * r);
+                let stride = (pw + 1) as usize;
+                let mut sum = vec![[0f64; 3]; stride * (ph + 1) as usize];
+                let mut sq = vec![0f64; sum.len()];
+                for y in 0..ph {
+                    let sy = (y - r).clamp(0, h - 1);
+                    let mut row = [0f64; 3];
+                    let mut row_sq = 0f64;
+                    for x in 0..pw {
+                        let sx = (x - r).clamp(0, w - 1);
+                        let p = img.data[(sy * w + sx) as usize];
+                        for c in 0..3 {
+                            row[c] += p[c] as f64;
+                            row_sq += p[c] as f64 * p[c] as f64;
+                        }
+                        let (i, above) = (
+                            (y as usize + 1) * stride + x as usize + 1,
+                            y as usize * stride + x as usize + 1,
+                        );
+                        sum[i] = [0, 1, 2].map(|c| sum[above][c] + row[c]);
+                        sq[i] = sq[above] + row_sq;
+                    }
+                }
+                // Sums over padded `x0..x1` and `y0..y1`
+                let rect = |x0: i64, y0: i64, x1: i64, y1: i64| {
+                    let i = |x: i64, y: i64| y as usize * stride + x as usize;
+                    let (a, b, c, d) = (i(x0, y0), i(x1, y0), i(x0, y1), i(x1, y1));
+                    let s = [0, 1, 2].map(|ch| sum[d][ch] - sum[b][ch] - sum[c][ch] + sum[a][ch]);
+                    (s, sq[d] - sq[b] - sq[c] + sq[a])
+                };
+
+                let n = ((r + 1) * (r + 1)) as f64;
+                let mut out = img.data.clone();
+                for y in 0..h {
+                    for x in 0..w {
+                        // This pixel in the padded image
+                        let (px, py) = (x + r, y + r);
+                        let mut best = (f64::INFINITY, [0f64; 3]);
+                        for (qx, qy) in [(px - r, py - r), (px, py - r), (px - r, py), (px, py)] {
+                            let (s, s2) = rect(qx, qy, qx + r + 1, qy + r + 1);
+                            let mean = s.map(|v| v / n);
+                            let var = s2 / n - mean.iter().map(|m| m * m).sum::<f64>();
+                            if var < best.0 {
+                                best = (var, mean);
+                            }
+                        }
+                        let p = &mut out[(y * w + x) as usize];
+                        for (c, mean) in p.iter_mut().zip(best.1) {
+                            *c = mean as f32;
+                        }
+                    }
+                }
+                img.data = out;
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{noise, photo, solid},
+        WorkPixel,
+    };
+
+    /// Dark left half, light right half, in whole 8 bit steps
+    fn step() -> Image {
+        let mut img = solid((20, 10), [0.; 4]);
+        img.map_pixels_indexed(|(x, _), _| {
+            let v = if x < 10 { 51. } else { 204. } / 255.;
+            [v, v, v, 1.]
+        });
+        img
+    }
+
+    /// Each channel's variance
+    fn variance(img: &Image) -> f32 {
+        let n = img.data.len() as f32;
+        (0..3)
+            .map(|c| {
+                let mean = img.data.iter().map(|p| p[c]).sum::<f32>() / n;
+                img.data.iter().map(|p| (p[c] - mean).powi(2)).sum::<f32>() / n
+            })
+            .sum()
+    }
+
+    #[test]
+    fn median_removes_specks() {
+        let clean = step();
+        let mut img = clean.clone();
+        for (i, (x, y)) in [(3, 2), (7, 7), (12, 4), (16, 1), (9, 5), (10, 8)]
+            .into_iter()
+            .enumerate()
+        {
+            let v = (i % 2) as f32;
+            img.data[y * 20 + x] = [v, v, v, 1.];
+        }
+        img.median_filter(1);
+        assert_eq!(img.pixels(), clean.pixels());
+    }
+
+    #[test]
+    fn median_matches_naive() {
+        let src = photo((13, 9));
+        for radius in [1, 2, 5] {
+            let mut img = src.clone();
+            img.median_filter(radius);
+            let (w, h, r) = (13i64, 9i64, radius as i64);
+            for y in 0..h {
+                for x in 0..w {
+                    for c in 0..3 {
+                        let mut window: Vec<u8> = (-r..=r)
+                            .flat_map(|dy| (-r..=r).map(move |dx| (dx, dy)))
+                            .map(|(dx, dy)| {
+                                let (sx, sy) = ((x + dx).clamp(0, w - 1), (y + dy).clamp(0, h - 1));
+                                quantize(src.data[(sy * w + sx) as usize][c], 255.) as u8
+                            })
+                            .collect();
+                        window.sort();
+                        let want = window[window.len() / 2] as f32 / 255.;
+                        assert_eq!(img.data[(y * w + x) as usize][c], want, "{x} {y} {radius}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn kuwahara_smooths_and_keeps_edges() {
+        let mut seed = 4;
+        let mut img = solid((24, 24), [0.; 4]);
+        img.map_pixels(|_| {
+            let v = 0.5 + (noise(&mut seed) - 0.5) * 0.2;
+            [v, v, v, 1.]
+        });
+        let before = variance(&img);
+        img.kuwahara(2);
+        assert!(variance(&img) < before / 2., "{} {before}", variance(&img));
+
+        let clean = step();
+        let mut img = clean.clone();
+        img.kuwahara(3);
+        assert!(crate::fixtures::max_diff(img.pixels(), clean.pixels()) < 1e-5);
+    }
+
+    #[test]
+    fn alpha_is_kept() {
+        let mut src = photo((12, 8));
+        src.map_pixels_indexed(|(x, y), p| [p[0], p[1], p[2], ((x + y) % 5) as f32 / 4.]);
+        let alphas = |img: &Image| img.pixels().iter().map(|p| p[3]).collect::<Vec<_>>();
+        for filter in [Image::median_filter, Image::kuwahara] {
+            for alpha in [AlphaMode::Straight, AlphaMode::Premultiplied] {
+                let mut img = src.clone();
+                img.to_alpha_mode(alpha);
+                filter(&mut img, 2);
+                assert_eq!(img.alpha, alpha);
+                assert_eq!(alphas(&img), alphas(&src));
+            }
+        }
+    }
+
+    #[test]
+    fn radius_zero() {
+        let src = photo((6, 5));
+        let mut img = src.clone();
+        img.median_filter(0);
+        img.kuwahara(0);
+        assert_eq!(img.pixels(), src.pixels());
+        let mut empty = solid((0, 3), WorkPixel::default());
+        empty.median_filter(2);
+        empty.kuwahara(2);
+    }
+}
@@ -0,0 +1,288 @@
+//! Straightening slightly rotated scans
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    alpha_converter, luma::sobel, scale::sample_bilinear, scale::scale_buffer, AlphaMode, Image,
+    ResXY, ScaleFilter, WorkPixel, F32,
+};
+
+/// Largest skew looked for, in degrees
+const MAX_SKEW: f32 = 15.;
+
+/// Largest dimension the edge map is downsampled to
+const ANALYSIS_SIZE: u32 = 512;
+
+/// Skews smaller than this, in degrees, aren't worth resampling for
+const MIN_SKEW: f32 = 0.05;
+
+/// Edge pixels, as positions from the center and their weight
+///
+/// Positions are in downsampled pixels, but with the source's aspect ratio,
+/// so rounding the downsampled size doesn't bend angles.
+fn edge_points(img: &Image) -> Vec<(f32, f32, f32)> {
+    let (w, h) = img.res;
+    let scale = (ANALYSIS_SIZE as f32 / w.max(h) as f32).min(1.);
+    let small = (
+        ((w as f32 * scale) as u32).max(1),
+        ((h as f32 * scale) as u32).max(1),
+    );
+    let mut luma = img.lumas();
+    scale_buffer(&mut luma, img.res, small, ScaleFilter::Box);
+    let edges = sobel(&luma, small);
+
+    // Weak edges are mostly noise, and skipping them is much faster
+    let max = edges.iter().fold(0f32, |m, e| m.max(*e));
+    let cutoff = max * 0.1;
+    let (sx, sy) = (
+        w as f32 * scale / small.0 as f32,
+        h as f32 * scale / small.1 as f32,
+    );
+    let (cx, cy) = (small.0 as f32 / 2., small.1 as f32 / 2.);
+    edges
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| max > 0. && **e > cutoff)
+        .map(|(i, e)| {
+            let (x, y) = ((i as u32 % small.0) as f32, (i as u32 / small.0) as f32);
+            ((x + 0.5 - cx) * sx, (y + 0.5 - cy) * sy, *e)
+        })
+        .collect()
+}
+
+/// Projection profile bins per pixel
+///
+/// Linear splatting can't tell apart points spread within one bin, so with
+/// whole pixel bins every angle within a pixel's worth of spread looks the
+/// same.
+const BINS: usize = 4;
+
+/// How sharply `points` line up across the direction at `angle` degrees,
+/// as the sum of squares of their projection profile
+fn profile_energy(points: &[(f32, f32, f32)], extent: f32, angle: f32) -> f32 {
+    let (sin, cos) = (angle.to_radians().sin(), angle.to_radians().cos());
+    let mut bins = vec![0f32; (extent as usize + 2) * BINS * 2];
+    for (x, y, e) in points {
+        // Lines at `angle` have a constant `r`, split between two bins
+        let r = (x * sin + y * cos + extent + 1.) * BINS as f32;
+        let i = r as usize;
+        let t = r - i as f32;
+        bins[i] += e * (1. - t);
+        bins[i + 1] += e * t;
+    }
+    // Spread each point over a pixel's width, the area it actually covers.
+    // Points in a row all land on the same bins when the angle is a
+    // multiple of 90, which otherwise makes those angles look sharper than
+    // the real one.
+    bins.windows(BINS + 2)
+        .map(|b| {
+            let v = (b[0] + b[BINS + 1]) / 2. + b[1..=BINS].iter().sum::<f32>();
+            v * v
+        })
+        .sum()
+}
+
+/// The angle in `start..=end`, `step` apart, with the most energy, and its
+/// neighbors' energies
+fn best_angle(
+    points: &[(f32, f32, f32)],
+    extent: f32,
+    (start, end, step): (f32, f32, f32),
+) -> (f32, [f32; 3]) {
+    let angle = |i: u32| start + i as f32 * step;
+    let count = ((end - start) / step).round() as u32 + 1;
+    let energy: Vec<f32> = (0..count)
+        .map(|i| profile_energy(points, extent, angle(i)))
+        .collect();
+    let best = (0..count as usize)
+        .max_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+        .unwrap_or(0);
+    let at = |i: usize| energy[i.clamp(0, count as usize - 1)];
+    let around = [at(best.saturating_sub(1)), at(best), at(best + 1)];
+    (angle(best as u32), around)
+}
+
+impl Image {
+    /// Estimate how far the image is rotated, in degrees counterclockwise,
+    /// within ±15
+    ///
+    /// This finds the angle where the image's edges line up best into
+    /// parallel lines, like lines of text or the edges of a page, by
+    /// maximizing the variance of their projection. Edges are found on a
+    /// downsampled copy for speed. Images without edges are 0.
+    pub fn detect_skew(&self) -> f32 {
+        let points = edge_points(self);
+        if points.is_empty() {
+            return 0.;
+        }
+        let extent = points
+            .iter()
+            .fold(0f32, |m, (x, y, _)| m.max((x * x + y * y).sqrt()))
+            + 1.;
+
+        let (coarse, _) = best_angle(&points, extent, (-MAX_SKEW, MAX_SKEW, 0.5));
+        let step = 0.05;
+        let (fine, [a, b, c]) = best_angle(
+            &points,
+            extent,
+            (
+                (coarse - 0.5).max(-MAX_SKEW),
+                (coarse + 0.5).min(MAX_SKEW),
+                step,
+            ),
+        );
+        // Fit a parabola through the peak and its neighbors
+        let denom = a - 2. * b + c;
+        let offset = if denom < 0. {
+            (0.5 * (a - c) / denom).clamp(-0.5, 0.5)
+        } else {
+            0.
+        };
+        (fine + offset * step).clamp(-MAX_SKEW, MAX_SKEW)
+    }
+
+    /// Straighten the image by rotating it back by [`Image::detect_skew`]
+    ///
+    /// The size stays the same, and the corners that rotate in from outside
+    /// are filled with the straight alpha `background`. Pixels are sampled
+    /// bilinearly. Images that are already straight are left untouched.
+    pub fn deskew(&mut self, background: impl Into<WorkPixel>) {
+        let background = background.into();
+        let angle = self.detect_skew();
+        if angle.abs() < MIN_SKEW {
+            return;
+        }
+        self.data = self.rotated(angle, background);
+        self.check();
+    }
+
+    /// The image with its content rotated clockwise by `angle` degrees,
+    /// around its center, with `background` outside
+    fn rotated(&self, angle: f32, background: WorkPixel) -> Vec<WorkPixel> {
+        let (w, h): ResXY = self.res;
+        let (sin, cos) = (angle.to_radians().sin(), angle.to_radians().cos());
+        let (cx, cy) = (w as f32 / 2., h as f32 / 2.);
+        let background = alpha_converter(AlphaMode::Straight, self.alpha)(background);
+        let mut data = Vec::with_capacity(self.data.len());
+        for y in 0..h {
+            for x in 0..w {
+                let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+                // Undo the clockwise rotation to find the source
+                let sx = dx * cos + dy * sin + cx;
+                let sy = -dx * sin + dy * cos + cy;
+                if sx < 0. || sy < 0. || sx > w as f32 || sy > h as f32 {
+                    data.push(background);
+                } else {
+                    data.push(sample_bilinear(&self.data, self.res, (sx - 0.5, sy - 0.5)));
+                }
+            }
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::solid, ColorSpace};
+
+    const RES: ResXY = (200, 150);
+    const RED: WorkPixel = [1., 0., 0., 1.];
+
+    /// A page of dark lines of text rotated `angle` degrees
+    /// counterclockwise, supersampled
+    fn page(angle: f32) -> Image {
+        let (sin, cos) = (angle.to_radians().sin(), angle.to_radians().cos());
+        let (cx, cy) = (RES.0 as f32 / 2., RES.1 as f32 / 2.);
+        let mut img = solid(RES, [1.; 4]);
+        img.map_pixels_indexed(|(x, y), _| {
+            let mut ink = 0.;
+            for s in 0..16 {
+                let dx = x as f32 + (s % 4) as f32 / 4. + 0.125 - cx;
+                let dy = y as f32 + (s / 4) as f32 / 4. + 0.125 - cy;
+                // Across and along the lines
+                let across = dx * sin + dy * cos + 1000.;
+                let along = dx * cos - dy * sin;
+                if across % 10. < 3. && along.abs() < 70. && (-50.0..50.).contains(&dy) {
+                    ink += 1. / 16.;
+                }
+            }
+            let v = 1. - ink * 0.9;
+            [v, v, v, 1.]
+        });
+        img
+    }
+
+    #[test]
+    fn detects_skew() {
+        for angle in [3.7, -2.3, 0.8, 11.] {
+            let skew = page(angle).detect_skew();
+            assert!((skew - angle).abs() < 0.3, "{angle} {skew}");
+        }
+    }
+
+    #[test]
+    fn deskews_back() {
+        let mut img = page(3.7);
+        img.deskew([1.; 4]);
+        let left = img.detect_skew();
+        assert!(left.abs() < 0.5, "{left}");
+    }
+
+    #[test]
+    fn straight_is_unchanged() {
+        let img = page(0.);
+        assert!(img.detect_skew().abs() < MIN_SKEW);
+        let mut straight = img.clone();
+        straight.deskew(RED);
+        assert_eq!(straight.pixels(), img.pixels());
+    }
+
+    #[test]
+    fn background_only_in_corners() {
+        let mut img = page(3.7);
+        let angle = img.detect_skew();
+        img.deskew(RED);
+        let (sin, cos) = (angle.to_radians().sin(), angle.to_radians().cos());
+        let (w, h) = (RES.0 as f32, RES.1 as f32);
+        let mut exposed = 0;
+        for y in 0..RES.1 {
+            for x in 0..RES.0 {
+                let (dx, dy) = (x as f32 + 0.5 - w / 2., y as f32 + 0.5 - h / 2.);
+                let sx = dx * cos + dy * sin + w / 2.;
+                let sy = -dx * sin + dy * cos + h / 2.;
+                let p = img.get_pixel((x, y)).unwrap();
+                if sx < 0. || sy < 0. || sx > w || sy > h {
+                    assert_eq!(p, RED, "{x} {y}");
+                    exposed += 1;
+                } else {
+                    assert_eq!(p[0], p[1], "{x} {y}");
+                }
+            }
+        }
+        // All four corners
+        for (x, y) in [
+            (0, 0),
+            (RES.0 - 1, 0),
+            (0, RES.1 - 1),
+            (RES.0 - 1, RES.1 - 1),
+        ] {
+            assert_eq!(img.get_pixel((x, y)), Some(RED));
+        }
+        assert!(exposed > 100);
+    }
+
+    #[test]
+    fn deskew_empty() {
+        for res in [(0, 5), (5, 0), (0, 0)] {
+            let mut img = Image::from_bytes(&[], res, ColorSpace::sRGB);
+            assert_eq!(img.detect_skew(), 0.);
+            img.deskew([1.; 4]);
+            assert_eq!(img.res, res);
+        }
+        // No edges
+        let mut flat = solid((20, 20), [0.5, 0.5, 0.5, 1.]);
+        assert_eq!(flat.detect_skew(), 0.);
+        flat.deskew(RED);
+        assert!(flat.pixels().iter().all(|p| *p == [0.5, 0.5, 0.5, 1.]));
+    }
+}
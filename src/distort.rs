@@ -0,0 +1,131 @@
+//! Lens distortion correction
+use alloc::vec::Vec;
+
+use crate::{scale::sample_bilinear, FloatXY, Image, ResXY};
+
+impl Image {
+    /// Correct radial lens distortion, returning an image of `out_size`, or
+    /// the same size as this one
+    ///
+    /// This uses the standard polynomial model directly on the destination,
+    /// each output pixel is read from the source at
+    /// `center + d * (1 + k1 * r^2 + k2 * r^4)`, where `d` is its offset
+    /// from `center` and `r` is `d` normalized so that `1` is half the larger
+    /// source dimension. `center` is in source pixels. Negative `k1` corrects
+    /// barrel distortion, positive pincushion.
+    ///
+    /// Samples are bilinear, and ones outside the source are clamped to its
+    /// edge. With `k1` and `k2` of zero, and no `out_size`, this is an exact
+    /// copy.
+    pub fn undistort(&self, k1: f32, k2: f32, center: FloatXY, out_size: Option<ResXY>) -> Image {
+        let (w, h) = self.res;
+        let (ow, oh) = out_size.unwrap_or(self.res);
+        let norm = w.max(h) as f32 / 2.;
+        let (sx, sy) = (w as f32 / ow as f32, h as f32 / oh as f32);
+
+        let mut data = Vec::with_capacity(ow as usize * oh as usize);
+        for y in 0..oh {
+            for x in 0..ow {
+                // Output pixel position in source pixels
+                let px = (x as f32 + 0.5) * sx - 0.5;
+                let py = (y as f32 + 0.5) * sy - 0.5;
+                let (dx, dy) = (px - center.0, py - center.1);
+                let r2 = (dx * dx + dy * dy) / (norm * norm);
+                let f = 1. + k1 * r2 + k2 * r2 * r2;
+                let src = (center.0 + dx * f, center.1 + dy * f);
+                data.push(sample_bilinear(&self.data, self.res, src));
+            }
+        }
+        self.derive(data, (ow, oh))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{noise, ramp},
+        ColorSpace, F32,
+    };
+
+    const RES: ResXY = (32, 32);
+    const CENTER: FloatXY = (15.5, 15.5);
+
+    /// Factor [`Image::undistort`] scales offsets from `CENTER` by
+    fn factor(k1: f32, (dx, dy): FloatXY) -> f32 {
+        1. + k1 * (dx * dx + dy * dy) / (16. * 16.)
+    }
+
+    /// What a camera with distortion `k1` would see looking at a grid that
+    /// stores its own coordinates, `x` in red and `y` in green
+    fn distorted_grid(k1: f32) -> Image {
+        let mut data = Vec::new();
+        for y in 0..RES.1 {
+            for x in 0..RES.0 {
+                // Invert the model, for the undistorted offset that lands here
+                let q = (x as f32 - CENTER.0, y as f32 - CENTER.1);
+                let mut d = q;
+                for _ in 0..50 {
+                    let f = factor(k1, d);
+                    d = (q.0 / f, q.1 / f);
+                }
+                // Kept in range, corners land outside the grid
+                let u = (CENTER.0 + d.0, CENTER.1 + d.1);
+                data.push([u.0 / 64. + 0.25, u.1 / 64. + 0.25, 0., 1.]);
+            }
+        }
+        Image::from_parts(data, RES, ColorSpace::AsIs)
+    }
+
+    /// Furthest any pixel is from where the grid says it should be
+    fn bend(img: &Image) -> f32 {
+        let mut worst = 0f32;
+        for (i, p) in img.pixels().iter().enumerate() {
+            let (x, y) = ((i as u32 % RES.0) as f32, (i as u32 / RES.0) as f32);
+            let u = ((p[0] - 0.25) * 64., (p[1] - 0.25) * 64.);
+            worst = worst.max((u.0 - x).abs()).max((u.1 - y).abs());
+        }
+        worst
+    }
+
+    #[test]
+    fn identity() {
+        let mut seed = 5;
+        let data = (0..32 * 32)
+            .map(|_| [noise(&mut seed), noise(&mut seed), noise(&mut seed), 1.])
+            .collect();
+        let img = Image::from_parts(data, RES, ColorSpace::sRGB);
+        assert_eq!(img.undistort(0., 0., CENTER, None).pixels(), img.pixels());
+        // Off center too
+        assert_eq!(
+            img.undistort(0., 0., (3., 20.), None).pixels(),
+            img.pixels()
+        );
+    }
+
+    #[test]
+    fn straightens_grid() {
+        let k1 = -0.1;
+        let seen = distorted_grid(k1);
+        assert!(bend(&seen) > 3., "{}", bend(&seen));
+        let fixed = seen.undistort(k1, 0., CENTER, None);
+        assert!(bend(&fixed) < 1., "{}", bend(&fixed));
+        // And k2 does the same further out
+        let fixed = seen.undistort(k1 * 0.8, k1 * 0.2, CENTER, None);
+        assert!(bend(&fixed) < bend(&seen));
+    }
+
+    #[test]
+    fn clamps_to_edges() {
+        let img = ramp(RES);
+        let out = img.undistort(2., 0., CENTER, Some((8, 8)));
+        assert_eq!(out.res, (8, 8));
+        let p = |i: &Image, (x, y): (u32, u32)| i.pixels()[(y * i.res.0 + x) as usize];
+        // Far outside the source, each corner reads its corner
+        assert_eq!(p(&out, (0, 0)), p(&img, (0, 0)));
+        assert_eq!(p(&out, (7, 0)), p(&img, (31, 0)));
+        assert_eq!(p(&out, (0, 7)), p(&img, (0, 31)));
+        assert_eq!(p(&out, (7, 7)), p(&img, (31, 31)));
+        assert!(out.pixels().iter().all(|p| p.iter().all(|c| c.is_finite())));
+    }
+}
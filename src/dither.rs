@@ -0,0 +1,840 @@
+//! Quantizing to lower bit depths and fixed palettes
+use alloc::{vec, vec::Vec};
+use core::slice::from_mut;
+
+use crate::{
+    convert_rows,
+    noise::Rng,
+    transforms::{linear_srgb_to_oklab, unpremultiply},
+    AlphaMode, ColorSpace, Image, ImageError, ResXY, WorkPixel, F32, XY,
+};
+
+/// Dithering algorithms for [`Image::dither_to_depth`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DitherAlgorithm {
+    /// Floyd–Steinberg error diffusion
+    #[default]
+    FloydSteinberg,
+
+    /// Atkinson error diffusion
+    ///
+    /// Only diffuses 3/4 of the error, higher contrast but loses detail in
+    /// highlights and shadows.
+    Atkinson,
+
+    /// Ordered dithering with an 8x8 Bayer matrix
+    ///
+    /// No error is carried between pixels, so output is stable between frames.
+    /// See [`Image::dither_ordered`] to use other matrices.
+    Bayer,
+
+    /// Floyd–Steinberg error diffusion within each `block`, with no error
+    /// carried between blocks
+    ///
+    /// Blocks can be dithered in any order, or at the same time, with
+    /// [`dither_rows_blocked`], and always give the same result. To hide
+    /// the seams each block goes back and forth along its rows, error that
+    /// would leave the block goes to the pixels in it instead, and the top
+    /// row, where no error has built up yet, is offset by blue noise so
+    /// neighboring blocks don't start the same pattern.
+    Blocked { block: ResXY },
+}
+
+/// The 7 colors of ACeP e-paper panels, in sRGB, in the order their
+/// controllers index them
+///
+/// Black, white, green, blue, red, yellow, and orange. These are the nominal
+/// colors, real panels are much duller, so a palette measured from yours
+/// will dither better.
+pub const ACEP_PALETTE: [WorkPixel; 7] = [
+    [0., 0., 0., 1.],
+    [1., 1., 1., 1.],
+    [0., 1., 0., 1.],
+    [0., 0., 1., 1.],
+    [1., 0., 0., 1.],
+    [1., 1., 0., 1.],
+    [1., 128. / 255., 0., 1.],
+];
+
+/// How much mixing two far apart colors is penalized, so a gray mixes black
+/// and white instead of, say, blue and yellow
+const MIX_PENALTY: f32 = 0.01;
+
+/// `(dx, dy, weight)`
+type Kernel = &'static [(i64, i64, f32)];
+
+const FLOYD_STEINBERG: Kernel = &[
+    (1, 0, 7. / 16.),
+    (-1, 1, 3. / 16.),
+    (0, 1, 5. / 16.),
+    (1, 1, 1. / 16.),
+];
+
+/// Side of the blue noise tile offsetting block edges for
+/// [`DitherAlgorithm::Blocked`]
+const EDGE_NOISE: u32 = 16;
+
+/// Seed of that tile, fixed so the output never changes
+const EDGE_NOISE_SEED: u64 = 0x5eed;
+
+const ATKINSON: Kernel = &[
+    (1, 0, 1. / 8.),
+    (2, 0, 1. / 8.),
+    (-1, 1, 1. / 8.),
+    (0, 1, 1. / 8.),
+    (1, 1, 1. / 8.),
+    (0, 2, 1. / 8.),
+];
+
+/// A square matrix of thresholds for ordered dithering
+///
+/// Holds each of `0..size * size` exactly once, which sets the order pixels
+/// turn on in as the value rises.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThresholdMap {
+    size: u32,
+    data: Vec<u32>,
+}
+
+impl ThresholdMap {
+    /// Create a map from the `size * size` values in `data`, in row order
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `size` is zero, `data` is the
+    ///   wrong length, or isn't a permutation of `0..size * size`
+    pub fn new(size: u32, data: Vec<u32>) -> Result<Self, ImageError> {
+        let n = size as usize * size as usize;
+        if n == 0 || data.len() != n {
+            return Err(ImageError::InvalidArgument);
+        }
+        let mut seen = vec![false; n];
+        for &v in &data {
+            match seen.get_mut(v as usize) {
+                Some(s) if !*s => *s = true,
+                _ => return Err(ImageError::InvalidArgument),
+            }
+        }
+        Ok(Self { size, data })
+    }
+
+    /// The 2x2 Bayer matrix
+    pub fn bayer2() -> Self {
+        Self::bayer(2)
+    }
+
+    /// The 4x4 Bayer matrix
+    pub fn bayer4() -> Self {
+        Self::bayer(4)
+    }
+
+    /// The 8x8 Bayer matrix
+    pub fn bayer8() -> Self {
+        Self::bayer(8)
+    }
+
+    /// Bayer matrix of `size`, a power of two, built by recursively
+    /// tiling the 2x2 one
+    fn bayer(size: u32) -> Self {
+        let mut data = vec![0];
+        let mut n = 1;
+        while n < size {
+            let m = n * 2;
+            let mut next = vec![0; (m * m) as usize];
+            for y in 0..m {
+                for x in 0..m {
+                    let base = 4 * data[((y % n) * n + x % n) as usize];
+                    next[(y * m + x) as usize] =
+                        base + [0, 2, 3, 1][((y / n) * 2 + x / n) as usize];
+                }
+            }
+            data = next;
+            n = m;
+        }
+        Self { size, data }
+    }
+
+    /// Width and height of the map
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The raw values, in row order
+    pub fn values(&self) -> &[u32] {
+        &self.data
+    }
+
+    /// Threshold offset for the pixel at `xy`, centered on zero in
+    /// `-0.5..0.5`
+    ///
+    /// The map is tiled, and moved by `offset`, so changing it between
+    /// frames rotates the pattern.
+    pub fn threshold(&self, (x, y): XY, offset: XY) -> f32 {
+        let n = self.size;
+        let (x, y) = ((x % n + offset.0 % n) % n, (y % n + offset.1 % n) % n);
+        let v = self.data[(y * n + x) as usize];
+        (v as f32 + 0.5) / (n * n) as f32 - 0.5
+    }
+}
+
+/// A color as linear sRGB and Oklab, for matching against a palette
+#[derive(Clone, Copy)]
+struct Match {
+    linear: [f32; 3],
+    lab: [f32; 3],
+}
+
+impl Match {
+    fn new(img: &Image, mut p: WorkPixel) -> Self {
+        if img.alpha == AlphaMode::Premultiplied {
+            p = unpremultiply(p);
+        }
+        convert_rows(from_mut(&mut p), img.color, ColorSpace::sRGBLinear);
+        let linear = [p[0], p[1], p[2]];
+        Self {
+            linear,
+            lab: linear_srgb_to_oklab(linear),
+        }
+    }
+}
+
+fn distance2(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum()
+}
+
+/// Index of the perceptually nearest color in `palette`
+fn nearest(palette: &[Match], lab: [f32; 3]) -> usize {
+    (0..palette.len())
+        .min_by(|&a, &b| distance2(palette[a].lab, lab).total_cmp(&distance2(palette[b].lab, lab)))
+        .unwrap_or(0)
+}
+
+/// The two colors `a` and `b` in `palette` that mix best to `p`, and how
+/// much of `b`
+///
+/// The mix is done in linear light, as the eye blends dithered pixels, and
+/// judged in Oklab. A palette color that's near enough on its own is used
+/// as is.
+fn best_mix(palette: &[Match], p: Match) -> (usize, usize, f32) {
+    let n = nearest(palette, p.lab);
+    let mut best = (n, n, 0.);
+    let mut best_err = distance2(p.lab, palette[n].lab);
+    for (a, pa) in palette.iter().enumerate() {
+        for (b, pb) in palette.iter().enumerate().skip(a + 1) {
+            let d: [f32; 3] = core::array::from_fn(|c| pb.linear[c] - pa.linear[c]);
+            let len2: f32 = d.iter().map(|d| d * d).sum();
+            if len2 == 0. {
+                continue;
+            }
+            let r = ((0..3)
+                .map(|c| (p.linear[c] - pa.linear[c]) * d[c])
+                .sum::<f32>()
+                / len2)
+                .clamp(0., 1.);
+            let mix = linear_srgb_to_oklab(core::array::from_fn(|c| pa.linear[c] + d[c] * r));
+            let err = distance2(p.lab, mix)
+                + distance2(pa.lab, pb.lab) * MIX_PENALTY * ((r - 0.5).abs() + 0.5);
+            if err < best_err {
+                best = (a, b, r);
+                best_err = err;
+            }
+        }
+    }
+    best
+}
+
+fn quantize(v: f32, levels: f32) -> f32 {
+    (v.clamp(0., 1.) * levels).round() / levels
+}
+
+/// The `2^bits - 1` steps of each channel
+fn depth_levels(bits: [u8; 4]) -> [f32; 4] {
+    // Past 24 bits f32 can't tell the levels apart anyway
+    bits.map(|b| ((1u32 << b.min(24)) - 1) as f32)
+}
+
+/// Quantize `rows`, rows `first_row..` of an image `width` wide, to `bits`
+/// bits per channel like [`DitherAlgorithm::Blocked`] with `block`
+///
+/// This is for dithering an image in parts, on separate threads for example.
+/// Chunks that start on a row of blocks and, except for the last, end on
+/// one, like `rows.chunks_mut(width * block.1 * n)`, give exactly the same
+/// result as dithering the whole image with [`Image::dither_to_depth`].
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidArgument`] if any of `bits`, `width`, or `block`
+///   is zero, or `first_row` isn't on a row of blocks
+/// - [`ImageError::DimensionMismatch`] if `rows` isn't whole rows
+pub fn dither_rows_blocked(
+    rows: &mut [WorkPixel],
+    width: u32,
+    first_row: u32,
+    bits: [u8; 4],
+    block: ResXY,
+) -> Result<(), ImageError> {
+    if bits.contains(&0) || width == 0 || block.0 == 0 || block.1 == 0 {
+        return Err(ImageError::InvalidArgument);
+    }
+    if !first_row.is_multiple_of(block.1) {
+        return Err(ImageError::InvalidArgument);
+    }
+    if !rows.len().is_multiple_of(width as usize) {
+        return Err(ImageError::DimensionMismatch);
+    }
+    let levels = depth_levels(bits);
+    let noise = Image::blue_noise_mask((EDGE_NOISE, EDGE_NOISE), EDGE_NOISE_SEED);
+    // A quarter of a step of noise, moved around the tile for every block
+    // so blocks the tile fits into evenly don't start alike
+    let edge = |x: u32, y: u32, shift: u64| {
+        let (x, y) = (
+            x.wrapping_add(shift as u32),
+            y.wrapping_add((shift >> 32) as u32),
+        );
+        (noise.data[((y % EDGE_NOISE) * EDGE_NOISE + x % EDGE_NOISE) as usize][0] - 0.5) / 4.
+    };
+    let height = (rows.len() / width as usize) as u32;
+    let w = width as usize;
+
+    for by in (0..height).step_by(block.1 as usize) {
+        let bh = block.1.min(height - by);
+        for bx in (0..width).step_by(block.0 as usize) {
+            let bw = block.0.min(width - bx);
+            let shift = Rng::nth(
+                EDGE_NOISE_SEED,
+                (u64::from(first_row + by) << 32) | u64::from(bx),
+            );
+            for r in 0..bh {
+                // Every other row goes right to left, with the kernel mirrored
+                let dir = if r % 2 == 0 { 1 } else { -1 };
+                for i in 0..bw {
+                    let x = if dir == 1 { i } else { bw - 1 - i };
+                    let at = (by + r) as usize * w + (bx + x) as usize;
+                    let old = rows[at];
+                    let t = if r == 0 {
+                        edge(bx + x, first_row + by, shift)
+                    } else {
+                        0.
+                    };
+                    let new = core::array::from_fn(|c| quantize(old[c] + t / levels[c], levels[c]));
+                    rows[at] = new;
+                    // Error that would leave the block goes to the rest of
+                    // the kernel instead, so only the bottom row loses any
+                    let inside = |&&(dx, dy, _): &&(i64, i64, f32)| {
+                        let (nx, ny) = (x as i64 + dx * dir, r as i64 + dy);
+                        nx >= 0 && nx < bw as i64 && ny < bh as i64
+                    };
+                    let total: f32 = FLOYD_STEINBERG.iter().filter(inside).map(|k| k.2).sum();
+                    for &(dx, dy, weight) in FLOYD_STEINBERG.iter().filter(inside) {
+                        let (nx, ny) = ((x as i64 + dx * dir) as u32, r + dy as u32);
+                        let n = &mut rows[(by + ny) as usize * w + (bx + nx) as usize];
+                        for c in 0..4 {
+                            n[c] += (old[c].clamp(0., 1.) - new[c]) * weight / total;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Image {
+    /// Quantize each channel to `bits` bits, in place, using `algorithm`
+    ///
+    /// Values stay `f32`, but are snapped to the `2^bits` levels representable
+    /// at that depth, so exporting with [`Image::to_raw`] at that depth
+    /// doesn't band. For example `[5, 6, 5, 8]` for
+    /// [`PixelFormat::Rgb565Le`][crate::PixelFormat::Rgb565Le].
+    ///
+    /// This works on the stored values, in the image's color space.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if any of `bits` is zero, or the
+    ///   block size of [`DitherAlgorithm::Blocked`] is
+    pub fn dither_to_depth(
+        &mut self,
+        bits: [u8; 4],
+        algorithm: DitherAlgorithm,
+    ) -> Result<(), ImageError> {
+        if bits.contains(&0) {
+            return Err(ImageError::InvalidArgument);
+        }
+        let levels = depth_levels(bits);
+        let (w, h) = (self.width() as i64, self.height() as i64);
+
+        let kernel = match algorithm {
+            DitherAlgorithm::FloydSteinberg => FLOYD_STEINBERG,
+            DitherAlgorithm::Atkinson => ATKINSON,
+            DitherAlgorithm::Bayer => {
+                return self.dither_ordered(bits, &ThresholdMap::bayer8(), (0, 0));
+            }
+            DitherAlgorithm::Blocked { block } => {
+                if self.data.is_empty() {
+                    return Ok(());
+                }
+                return dither_rows_blocked(&mut self.data, self.res.0, 0, bits, block);
+            }
+        };
+
+        for y in 0..h {
+            for x in 0..w {
+                let i = (y * w + x) as usize;
+                let old = self.data[i];
+                let new = core::array::from_fn(|c| quantize(old[c], levels[c]));
+                self.data[i] = new;
+                for &(dx, dy, weight) in kernel {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || nx >= w || ny >= h {
+                        continue;
+                    }
+                    let n = &mut self.data[(ny * w + nx) as usize];
+                    for c in 0..4 {
+                        n[c] += (old[c].clamp(0., 1.) - new[c]) * weight;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Quantize each channel to `bits` bits, in place, with ordered
+    /// dithering using `map` shifted by `offset`
+    ///
+    /// Like [`DitherAlgorithm::Bayer`] but with any map. Moving `offset`
+    /// each frame gives temporal dithering, which pixels round up changes
+    /// but, over whole tiles of the map, not how many.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if any of `bits` is zero
+    pub fn dither_ordered(
+        &mut self,
+        bits: [u8; 4],
+        map: &ThresholdMap,
+        offset: XY,
+    ) -> Result<(), ImageError> {
+        if bits.contains(&0) {
+            return Err(ImageError::InvalidArgument);
+        }
+        let levels = depth_levels(bits);
+        let w = self.width();
+        for (i, p) in self.data.iter_mut().enumerate() {
+            let (x, y) = (i as u32 % w, i as u32 / w);
+            let t = map.threshold((x, y), offset);
+            for c in 0..4 {
+                p[c] = quantize(p[c] + t / levels[c], levels[c]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Dither to the colors in `palette`, in place, with ordered dithering
+    /// using `map`
+    ///
+    /// Each pixel picks between the two palette colors that mix best to it,
+    /// with the threshold from `map` deciding which, so small palettes like
+    /// [`ACEP_PALETTE`] for e-paper keep their colors clean instead of
+    /// muddy. `strength` from 0 to 1 pulls pixels toward their nearest
+    /// palette color first, at 0 this is just the nearest color.
+    ///
+    /// `palette` is in the image's color space. Pixels are replaced by the
+    /// palette entries exactly, alpha included, see
+    /// [`Image::to_palette_indices`] to export them.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `palette` is empty or `strength`
+    ///   isn't between 0 and 1
+    pub fn dither_to_palette_ordered(
+        &mut self,
+        palette: &[WorkPixel],
+        map: &ThresholdMap,
+        strength: f32,
+    ) -> Result<(), ImageError> {
+        if palette.is_empty() || !(0. ..=1.).contains(&strength) {
+            return Err(ImageError::InvalidArgument);
+        }
+        let matches: Vec<_> = palette.iter().map(|&p| Match::new(self, p)).collect();
+        let w = self.width();
+        for i in 0..self.data.len() {
+            let (x, y) = (i as u32 % w, i as u32 / w);
+            let mut p = Match::new(self, self.data[i]);
+            if strength < 1. {
+                let n = matches[nearest(&matches, p.lab)].linear;
+                p.linear = core::array::from_fn(|c| n[c] + (p.linear[c] - n[c]) * strength);
+                p.lab = linear_srgb_to_oklab(p.linear);
+            }
+            let (a, b, r) = best_mix(&matches, p);
+            let t = map.threshold((x, y), (0, 0)) + 0.5;
+            self.data[i] = palette[if t < r { b } else { a }];
+        }
+        Ok(())
+    }
+
+    /// Index of the nearest color in `palette` for every pixel, in row order
+    ///
+    /// After [`Image::dither_to_palette_ordered`] with the same palette
+    /// these are exact, ready for a panel controller.
+    ///
+    /// # Panics
+    ///
+    /// - If `palette` is empty or has more than 256 colors
+    pub fn to_palette_indices(&self, palette: &[WorkPixel]) -> Vec<u8> {
+        assert!(
+            !palette.is_empty() && palette.len() <= 256,
+            "Palette must have 1 to 256 colors"
+        );
+        let matches: Vec<_> = palette.iter().map(|&p| Match::new(self, p)).collect();
+        self.data
+            .iter()
+            .map(|&p| nearest(&matches, Match::new(self, p).lab) as u8)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::solid;
+
+    const ALGORITHMS: [DitherAlgorithm; 3] = [
+        DitherAlgorithm::FloydSteinberg,
+        DitherAlgorithm::Atkinson,
+        DitherAlgorithm::Bayer,
+    ];
+
+    /// Left to right from 0 to 1 in every channel, alpha included
+    fn gradient(res: ResXY) -> Image {
+        let mut img = solid(res, [0.; 4]);
+        let w = (res.0 - 1) as f32;
+        img.map_pixels_indexed(|(x, _), _| [x as f32 / w; 4]);
+        img
+    }
+
+    /// Whether every channel of every pixel is one of `levels + 1` levels
+    fn on_levels(img: &Image, levels: [f32; 4]) -> bool {
+        img.pixels().iter().all(|p| {
+            (0..4).all(|c| {
+                let v = p[c] * levels[c];
+                (0. ..=levels[c]).contains(&v) && (v - v.round()).abs() < 1e-3
+            })
+        })
+    }
+
+    /// Mean over columns of how far their average is from the original,
+    /// what the eye sees from a distance
+    fn column_error(img: &Image, original: &Image) -> f32 {
+        let (w, h) = img.res;
+        let mut total = 0.;
+        for x in 0..w {
+            let mean: f32 = (0..h)
+                .map(|y| img.get_pixel((x, y)).unwrap()[0])
+                .sum::<f32>();
+            total += (mean / h as f32 - original.get_pixel((x, 0)).unwrap()[0]).abs();
+        }
+        total / w as f32
+    }
+
+    #[test]
+    fn only_representable_levels() {
+        for algorithm in ALGORITHMS {
+            let mut img = gradient((100, 16));
+            img.dither_to_depth([5, 6, 5, 5], algorithm).unwrap();
+            assert!(on_levels(&img, [31., 63., 31., 31.]), "{algorithm:?}");
+            let mut distinct: Vec<u32> = img.pixels().iter().map(|p| (p[0] * 31.) as u32).collect();
+            distinct.sort_unstable();
+            distinct.dedup();
+            assert_eq!(distinct.len(), 32, "{algorithm:?}");
+        }
+    }
+
+    #[test]
+    fn dither_beats_rounding() {
+        let original = gradient((64, 32));
+        let mut rounded = original.clone();
+        rounded.map_pixels(|p| p.map(|c| quantize(c, 7.)));
+        let rounding = column_error(&rounded, &original);
+        for algorithm in ALGORITHMS {
+            let mut img = original.clone();
+            img.dither_to_depth([3; 4], algorithm).unwrap();
+            let error = column_error(&img, &original);
+            assert!(error < rounding, "{algorithm:?}: {error} vs {rounding}");
+        }
+    }
+
+    #[test]
+    fn deep_and_zero_bits() {
+        let mut img = gradient((300, 2));
+        img.dither_to_depth([8; 4], DitherAlgorithm::FloydSteinberg)
+            .unwrap();
+        assert!(on_levels(&img, [255.; 4]));
+        assert_eq!(img.to_raw(crate::PixelFormat::Rgba8888).len(), 300 * 2 * 4);
+
+        // Deeper than f32 can tell apart is still fine
+        let mut deep = gradient((300, 2));
+        deep.dither_to_depth([32; 4], DitherAlgorithm::Bayer)
+            .unwrap();
+        assert!(crate::fixtures::max_diff(deep.pixels(), gradient((300, 2)).pixels()) < 1e-6);
+
+        for bits in [[0, 8, 8, 8], [8, 8, 8, 0]] {
+            for algorithm in ALGORITHMS {
+                assert_eq!(
+                    img.dither_to_depth(bits, algorithm),
+                    Err(ImageError::InvalidArgument)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bayer_matrices() {
+        assert_eq!(ThresholdMap::bayer2().values(), [0, 2, 3, 1]);
+        assert_eq!(
+            ThresholdMap::bayer4().values(),
+            [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5]
+        );
+        let eight = ThresholdMap::bayer8();
+        assert_eq!(eight.size(), 8);
+        assert_eq!(eight.values()[..8], [0, 32, 8, 40, 2, 34, 10, 42]);
+        assert!(ThresholdMap::new(8, eight.values().to_vec()).is_ok());
+    }
+
+    #[test]
+    fn invalid_maps() {
+        for (size, data) in [
+            (0, vec![]),
+            (2, vec![0, 1, 2]),
+            (2, vec![0, 1, 2, 3, 4]),
+            (2, vec![0, 1, 1, 3]),
+            (2, vec![0, 1, 2, 4]),
+        ] {
+            assert_eq!(
+                ThresholdMap::new(size, data.clone()),
+                Err(ImageError::InvalidArgument),
+                "{size} {data:?}"
+            );
+        }
+        assert!(ThresholdMap::new(1, vec![0]).is_ok());
+    }
+
+    #[test]
+    fn thresholds_centered() {
+        let map = ThresholdMap::bayer4();
+        let t: Vec<f32> = (0..16)
+            .map(|i| map.threshold((i % 4, i / 4), (0, 0)))
+            .collect();
+        assert_eq!(t[0], -0.5 + 0.5 / 16.);
+        assert_eq!(t[12], 0.5 - 0.5 / 16.);
+        assert!(t.iter().sum::<f32>().abs() < 1e-6);
+        // Tiled, and moved by the offset
+        assert_eq!(map.threshold((5, 9), (0, 0)), t[4 + 1]);
+        assert_eq!(map.threshold((0, 0), (1, 2)), t[2 * 4 + 1]);
+        assert_eq!(
+            map.threshold((3, 3), (u32::MAX, 0)),
+            map.threshold((2, 3), (0, 0))
+        );
+    }
+
+    #[test]
+    fn offset_moves_pattern_not_coverage() {
+        let on = |map: &ThresholdMap, offset| {
+            let mut img = solid((16, 16), [0.3, 0.3, 0.3, 1.]);
+            img.dither_ordered([1; 4], map, offset).unwrap();
+            img.pixels().iter().map(|p| p[0] == 1.).collect::<Vec<_>>()
+        };
+        let custom = ThresholdMap::new(3, vec![4, 0, 7, 2, 8, 5, 6, 3, 1]).unwrap();
+        for map in [ThresholdMap::bayer4(), ThresholdMap::bayer8(), custom] {
+            let first = on(&map, (0, 0));
+            let count = |v: &[bool]| v.iter().filter(|&&b| b).count();
+            for offset in [(1, 0), (0, 1), (2, 3)] {
+                let next = on(&map, offset);
+                assert_ne!(first, next, "{offset:?}");
+                // Whole tiles for the power of two maps
+                if map.size() != 3 {
+                    assert_eq!(count(&first), count(&next));
+                }
+            }
+        }
+        let mut img = solid((4, 4), [0.5; 4]);
+        assert_eq!(
+            img.dither_ordered([1, 0, 1, 1], &ThresholdMap::bayer2(), (0, 0)),
+            Err(ImageError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn palette_members_only() {
+        let mut img = crate::fixtures::photo((24, 16));
+        img.dither_to_palette_ordered(&ACEP_PALETTE, &ThresholdMap::bayer4(), 1.)
+            .unwrap();
+        let indices = img.to_palette_indices(&ACEP_PALETTE);
+        for (p, i) in img.pixels().iter().zip(indices) {
+            assert_eq!(*p, ACEP_PALETTE[i as usize]);
+        }
+    }
+
+    #[test]
+    fn palette_gradient_is_monotonic() {
+        let palette = [[0., 0., 0., 1.], [1., 1., 1., 1.]];
+        let mut img = gradient((64, 4));
+        img.map_pixels(|p| [p[0], p[1], p[2], 1.]);
+        img.dither_to_palette_ordered(&palette, &ThresholdMap::bayer4(), 1.)
+            .unwrap();
+        let indices = img.to_palette_indices(&palette);
+        // White per 4x4 tile of the map
+        let whites: Vec<u32> = (0..16)
+            .map(|t| {
+                let tile = indices.chunks(64).flat_map(|row| &row[t * 4..t * 4 + 4]);
+                tile.map(|&i| i as u32).sum()
+            })
+            .collect();
+        assert!(whites.windows(2).all(|w| w[0] <= w[1]), "{whites:?}");
+        assert_eq!((whites[0], whites[15]), (0, 16), "{whites:?}");
+        assert!(whites.iter().any(|&n| n > 0 && n < 16), "{whites:?}");
+    }
+
+    #[test]
+    fn palette_strength_zero_is_nearest() {
+        let mut img = crate::fixtures::photo((24, 16));
+        let nearest = img.to_palette_indices(&ACEP_PALETTE);
+        img.dither_to_palette_ordered(&ACEP_PALETTE, &ThresholdMap::bayer8(), 0.)
+            .unwrap();
+        assert_eq!(img.to_palette_indices(&ACEP_PALETTE), nearest);
+    }
+
+    #[test]
+    fn palette_errors() {
+        let mut img = solid((4, 4), [0.5; 4]);
+        let map = ThresholdMap::bayer2();
+        for (palette, strength) in [
+            (&[][..], 1.),
+            (&ACEP_PALETTE[..], 1.5),
+            (&ACEP_PALETTE[..], -0.1),
+        ] {
+            assert_eq!(
+                img.dither_to_palette_ordered(palette, &map, strength),
+                Err(ImageError::InvalidArgument)
+            );
+        }
+    }
+
+    const BLOCK: ResXY = (16, 8);
+
+    /// A smooth diagonal ramp
+    fn ramp(res: ResXY) -> Image {
+        let mut img = solid(res, [0.; 4]);
+        let span = res.0 as f32 + res.1 as f32 * 0.3;
+        img.map_pixels_indexed(|(x, y), _| [(x as f32 + y as f32 * 0.3) / span; 4]);
+        img
+    }
+
+    /// How far the mean of every 4x4 square is from the original, with its
+    /// top left corner
+    fn window_errors(img: &Image, original: &Image) -> Vec<(XY, f32)> {
+        let (w, h) = img.res;
+        let mut out = Vec::new();
+        for y in 0..=h - 4 {
+            for x in 0..=w - 4 {
+                let mut d = 0.;
+                for i in (y..y + 4).flat_map(|yy| (x..x + 4).map(move |xx| (yy * w + xx) as usize))
+                {
+                    d += img.data[i][0] - original.data[i][0];
+                }
+                out.push(((x, y), (d / 16.).abs()));
+            }
+        }
+        out
+    }
+
+    fn mean(errors: impl Iterator<Item = f32>) -> f32 {
+        let (sum, n) = errors.fold((0., 0), |(s, n), e| (s + e, n + 1));
+        sum / n as f32
+    }
+
+    #[test]
+    fn blocked_in_parts_is_identical() {
+        let original = ramp((100, 37));
+        let mut whole = original.clone();
+        whole
+            .dither_to_depth([3, 4, 5, 2], DitherAlgorithm::Blocked { block: BLOCK })
+            .unwrap();
+        assert!(on_levels(&whole, [7., 15., 31., 3.]));
+        // Chunks of one and three rows of blocks, done in reverse, like
+        // threads finishing in any order
+        for blocks in [1, 3] {
+            let mut data = original.data.clone();
+            let rows = 100 * BLOCK.1 as usize * blocks;
+            let mut chunks: Vec<(u32, &mut [WorkPixel])> = (0..)
+                .step_by(BLOCK.1 as usize * blocks)
+                .zip(data.chunks_mut(rows))
+                .collect();
+            chunks.reverse();
+            for (first_row, chunk) in chunks {
+                dither_rows_blocked(chunk, 100, first_row, [3, 4, 5, 2], BLOCK).unwrap();
+            }
+            assert_eq!(data, whole.data, "{blocks}");
+        }
+    }
+
+    #[test]
+    fn blocked_is_close_to_floyd_steinberg() {
+        let original = ramp((128, 64));
+        let error = |algorithm| {
+            let mut img = original.clone();
+            img.dither_to_depth([3; 4], algorithm).unwrap();
+            window_errors(&img, &original)
+        };
+        let fs = mean(
+            error(DitherAlgorithm::FloydSteinberg)
+                .into_iter()
+                .map(|e| e.1),
+        );
+        let blocked = error(DitherAlgorithm::Blocked { block: BLOCK });
+        let all = mean(blocked.iter().map(|e| e.1));
+        assert!(all <= fs * 1.1, "{all} vs {fs}");
+
+        // Squares across a block edge aren't much worse than inside
+        let seam =
+            |&((x, y), _): &(XY, f32)| x % BLOCK.0 > BLOCK.0 - 4 || y % BLOCK.1 > BLOCK.1 - 4;
+        let seams = mean(blocked.iter().filter(|e| seam(e)).map(|e| e.1));
+        let inside = mean(blocked.iter().filter(|e| !seam(e)).map(|e| e.1));
+        assert!(seams <= inside * 1.25, "{seams} vs {inside}");
+        let worst = blocked.iter().fold(0f32, |m, e| m.max(e.1));
+        assert!(worst <= 1. / 7. / 4., "{worst}");
+    }
+
+    #[test]
+    fn blocked_errors() {
+        let mut img = ramp((20, 10));
+        for block in [(0, 4), (4, 0)] {
+            assert_eq!(
+                img.dither_to_depth([4; 4], DitherAlgorithm::Blocked { block }),
+                Err(ImageError::InvalidArgument)
+            );
+        }
+        let mut rows = img.data.clone();
+        assert_eq!(
+            dither_rows_blocked(&mut rows, 20, 3, [4; 4], (4, 4)),
+            Err(ImageError::InvalidArgument)
+        );
+        assert_eq!(
+            dither_rows_blocked(&mut rows[1..], 20, 0, [4; 4], (4, 4)),
+            Err(ImageError::DimensionMismatch)
+        );
+        assert_eq!(
+            dither_rows_blocked(&mut rows, 0, 0, [4; 4], (4, 4)),
+            Err(ImageError::InvalidArgument)
+        );
+        // Blocks of one pixel just round, and empty images are fine
+        img.dither_to_depth([4; 4], DitherAlgorithm::Blocked { block: (1, 1) })
+            .unwrap();
+        assert!(on_levels(&img, [15.; 4]));
+        let mut empty = solid((0, 0), [0.; 4]);
+        assert_eq!(
+            empty.dither_to_depth([4; 4], DitherAlgorithm::Blocked { block: BLOCK }),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,267 @@
+//! Gray e-ink updates, old and new levels of each pixel together for
+//! controllers that pick a waveform per pixel
+use alloc::vec::Vec;
+
+use crate::{
+    alpha_converter, dither::ThresholdMap, transforms::luminance, AlphaMode, Image, ImageError, F32,
+};
+
+/// How [`Image::to_eink_update`] lays out the old and new gray codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EinkPacking {
+    /// A byte per pixel, the new code in the high nibble and the old in the
+    /// low one
+    Nibbles,
+    /// The old codes then the new, each as their own buffer of rows packed
+    /// tightly at the code size, leftmost pixel in the high bits
+    ///
+    /// Rows are padded out to whole bytes with zeros.
+    Planar,
+}
+
+/// Bits per code for `levels` grays
+fn code_bits(levels: u8) -> Result<u32, ImageError> {
+    match levels {
+        4 => Ok(2),
+        16 => Ok(4),
+        _ => Err(ImageError::InvalidArgument),
+    }
+}
+
+/// Gray code of every pixel of `img`, 0 black to `max` white, from the luma
+/// in linear light, ordered dithered with `map` if any
+fn codes(img: &Image, max: u8, map: Option<&ThresholdMap>) -> Vec<u8> {
+    let decode = img.color.transfer().map(|t| t.0);
+    let to_straight = alpha_converter(img.alpha, AlphaMode::Straight);
+    let w = img.width();
+    let steps = max as f32;
+    img.data
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let p = to_straight(*p);
+            let y = luminance([p[0], p[1], p[2]].map(|c| decode.map_or(c, |f| f(c))));
+            let t = map.map_or(0., |m| m.threshold((i as u32 % w, i as u32 / w), (0, 0)));
+            let y = if y.is_nan() { 0. } else { y + t / steps };
+            (y.clamp(0., 1.) * steps).round() as u8
+        })
+        .collect()
+}
+
+/// `codes` of `bits` each packed into rows of `width` pixels
+fn pack_plane(codes: &[u8], width: usize, bits: u32, out: &mut Vec<u8>) {
+    let per_byte = 8 / bits as usize;
+    for row in codes.chunks(width) {
+        for byte in row.chunks(per_byte) {
+            let b = byte
+                .iter()
+                .enumerate()
+                .fold(0u8, |b, (k, c)| b | c << (8 - bits as usize * (k + 1)));
+            out.push(b);
+        }
+    }
+}
+
+impl Image {
+    /// Export this image as a partial update from `previous` for a gray
+    /// e-ink panel, with both frames' gray codes for each pixel laid out by
+    /// `packing`
+    ///
+    /// Each frame is quantized to `levels` grays, 4 or 16 for 2 or 4 bit
+    /// codes, from 0 for black up. This uses the luma in linear light,
+    /// straight alpha, with alpha ignored. Pixels that didn't change have
+    /// the same old and new code.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `levels` isn't 4 or 16
+    /// - [`ImageError::DimensionMismatch`] if `previous` is a different size
+    /// - [`ImageError::ColorSpaceMismatch`] if `previous` has a different
+    ///   color space
+    pub fn to_eink_update(
+        &self,
+        previous: &Image,
+        levels: u8,
+        packing: EinkPacking,
+    ) -> Result<Vec<u8>, ImageError> {
+        self.eink_update(previous, levels, packing, None)
+    }
+
+    /// Like [`Image::to_eink_update`], but ordered dithered with `map`
+    ///
+    /// Both frames use the map in the same place, so areas that didn't
+    /// change still keep their codes.
+    ///
+    /// # Errors
+    ///
+    /// - See [`Image::to_eink_update`]
+    pub fn to_eink_update_dithered(
+        &self,
+        previous: &Image,
+        levels: u8,
+        packing: EinkPacking,
+        map: &ThresholdMap,
+    ) -> Result<Vec<u8>, ImageError> {
+        self.eink_update(previous, levels, packing, Some(map))
+    }
+
+    fn eink_update(
+        &self,
+        previous: &Image,
+        levels: u8,
+        packing: EinkPacking,
+        map: Option<&ThresholdMap>,
+    ) -> Result<Vec<u8>, ImageError> {
+        let bits = code_bits(levels)?;
+        self.check_blend(previous)?;
+        let (old, new) = (
+            codes(previous, levels - 1, map),
+            codes(self, levels - 1, map),
+        );
+        Ok(match packing {
+            EinkPacking::Nibbles => old.iter().zip(&new).map(|(o, n)| n << 4 | o).collect(),
+            EinkPacking::Planar => {
+                let width = self.width() as usize;
+                let row = width.div_ceil(8 / bits as usize);
+                let mut out = Vec::with_capacity(row * self.height() as usize * 2);
+                pack_plane(&old, width, bits, &mut out);
+                pack_plane(&new, width, bits, &mut out);
+                out
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{photo, solid},
+        ColorSpace,
+    };
+
+    /// Black 5x3, with white at `at`
+    fn marked(at: (u32, u32)) -> Image {
+        let mut img = solid((5, 3), [0., 0., 0., 1.]);
+        img.map_pixels_indexed(|xy, p| if xy == at { [1.; 4] } else { p });
+        img
+    }
+
+    /// Codes of `img` the long way, its luma in linear light quantized by
+    /// [`Image::dither_ordered`]
+    fn reference(img: &Image, levels: u8, map: &ThresholdMap) -> Vec<u8> {
+        let mut gray = img.clone();
+        gray.to_color(ColorSpace::sRGBLinear);
+        gray.map_pixels(|p| {
+            let y = luminance([p[0], p[1], p[2]]);
+            [y, y, y, 1.]
+        });
+        let bits = code_bits(levels).unwrap() as u8;
+        gray.dither_ordered([bits; 4], map, (0, 0)).unwrap();
+        let max = (levels - 1) as f32;
+        gray.pixels()
+            .iter()
+            .map(|p| (p[0] * max).round() as u8)
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_frames_keep_their_codes() {
+        let img = photo((9, 7));
+        for levels in [4, 16] {
+            let bits = code_bits(levels).unwrap() as usize;
+            for map in [None, Some(ThresholdMap::bayer4())] {
+                let update = |packing| match &map {
+                    None => img.to_eink_update(&img, levels, packing),
+                    Some(m) => img.to_eink_update_dithered(&img, levels, packing, m),
+                };
+                let nibbles = update(EinkPacking::Nibbles).unwrap();
+                assert_eq!(nibbles.len(), 9 * 7);
+                assert!(nibbles.iter().all(|b| b >> 4 == b & 0xf));
+                let planar = update(EinkPacking::Planar).unwrap();
+                let plane = (9 * bits).div_ceil(8) * 7;
+                assert_eq!(planar.len(), plane * 2);
+                assert_eq!(planar[..plane], planar[plane..]);
+            }
+        }
+    }
+
+    #[test]
+    fn levels_match_quantizing_the_luma() {
+        let img = photo((16, 12));
+        let black = solid(img.res, [0., 0., 0., 1.]);
+        // A map of one value doesn't dither at all
+        let flat = ThresholdMap::new(1, alloc::vec![0]).unwrap();
+        for levels in [4, 16] {
+            let codes: Vec<u8> = img
+                .to_eink_update(&black, levels, EinkPacking::Nibbles)
+                .unwrap()
+                .iter()
+                .map(|b| b >> 4)
+                .collect();
+            assert_eq!(codes, reference(&img, levels, &flat), "{levels}");
+            // Not all one level
+            assert!(codes.iter().any(|c| *c != codes[0]));
+            let map = ThresholdMap::bayer4();
+            let dithered: Vec<u8> = img
+                .to_eink_update_dithered(&black, levels, EinkPacking::Nibbles, &map)
+                .unwrap()
+                .iter()
+                .map(|b| b >> 4)
+                .collect();
+            assert_eq!(dithered, reference(&img, levels, &map), "{levels}");
+            assert_ne!(dithered, codes);
+        }
+    }
+
+    #[test]
+    fn marked_pixels_land_by_hand() {
+        // New white at (3, 1), old white at (0, 2), black elsewhere
+        let (new, old) = (marked((3, 1)), marked((0, 2)));
+        let only = |len: usize, set: &[(usize, u8)]| {
+            let mut want = alloc::vec![0; len];
+            for (i, b) in set {
+                want[*i] = *b;
+            }
+            want
+        };
+        let update = |levels, packing| new.to_eink_update(&old, levels, packing).unwrap();
+
+        // A byte a pixel, new high
+        let nibbles = update(4, EinkPacking::Nibbles);
+        assert_eq!(nibbles, only(15, &[(5 + 3, 0x30), (10, 0x03)]));
+        let nibbles = update(16, EinkPacking::Nibbles);
+        assert_eq!(nibbles, only(15, &[(5 + 3, 0xf0), (10, 0x0f)]));
+
+        // 2 bit rows are 2 bytes, so planes of 6, (3, 1) is the lowest bits
+        // of byte 2 and (0, 2) the highest of byte 4
+        let planar = update(4, EinkPacking::Planar);
+        assert_eq!(planar, only(12, &[(4, 0b1100_0000), (6 + 2, 0b0000_0011)]));
+        // 4 bit rows are 3 bytes, so planes of 9, (3, 1) is the low nibble
+        // of byte 4 and (0, 2) the high nibble of byte 6
+        let planar = update(16, EinkPacking::Planar);
+        assert_eq!(planar, only(18, &[(6, 0xf0), (9 + 4, 0x0f)]));
+    }
+
+    #[test]
+    fn errors() {
+        let img = marked((0, 0));
+        for levels in [0, 2, 8, 255] {
+            assert_eq!(
+                img.to_eink_update(&img, levels, EinkPacking::Nibbles),
+                Err(ImageError::InvalidArgument)
+            );
+        }
+        let small = solid((5, 2), [0., 0., 0., 1.]);
+        assert_eq!(
+            img.to_eink_update(&small, 4, EinkPacking::Planar),
+            Err(ImageError::DimensionMismatch)
+        );
+        let mut linear = img.clone();
+        linear.color = ColorSpace::sRGBLinear;
+        assert_eq!(
+            img.to_eink_update(&linear, 4, EinkPacking::Nibbles),
+            Err(ImageError::ColorSpaceMismatch)
+        );
+    }
+}
@@ -0,0 +1,367 @@
+//! Images embedded in the binary
+use alloc::string::String;
+use core::fmt::Write;
+
+use crate::{layout::validate_exact, ColorSpace, Image, ImageError, PixelFormat, ResXY};
+
+/// Straight RGBA 8888 pixel data in static memory, as made by
+/// `include_image!` with the `macros` feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaticImage {
+    pub data: &'static [u8],
+    pub res: ResXY,
+    pub color: ColorSpace,
+}
+
+impl StaticImage {
+    pub const fn width(&self) -> u32 {
+        self.res.0
+    }
+
+    pub const fn height(&self) -> u32 {
+        self.res.1
+    }
+
+    /// Convert to an [`Image`], see [`Image::from_bytes`]
+    pub fn to_image(&self) -> Image {
+        Image::from_bytes(self.data, self.res, self.color)
+    }
+}
+
+/// Borrowed raw pixel data in any [`PixelFormat`], like a buffer from
+/// `include_bytes!`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageRef<'a> {
+    data: &'a [u8],
+    res: ResXY,
+    format: PixelFormat,
+    color: ColorSpace,
+}
+
+impl<'a> ImageRef<'a> {
+    /// # Errors
+    ///
+    /// - [`ImageError::BufferSize`] if `data` is not exactly
+    ///   `width * height * format.bytes_per_pixel()` in size
+    pub fn new(
+        data: &'a [u8],
+        res: ResXY,
+        format: PixelFormat,
+        color: ColorSpace,
+    ) -> Result<Self, ImageError> {
+        validate_exact(data, format, res)?;
+        Ok(Self {
+            data,
+            res,
+            format,
+            color,
+        })
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub const fn width(&self) -> u32 {
+        self.res.0
+    }
+
+    pub const fn height(&self) -> u32 {
+        self.res.1
+    }
+
+    pub const fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    pub const fn color(&self) -> ColorSpace {
+        self.color
+    }
+
+    /// Convert to an [`Image`], see [`Image::from_raw`]
+    pub fn to_image(&self) -> Image {
+        // Checked in `new`
+        Image::from_raw(self.data, self.res, self.format, self.color).unwrap()
+    }
+}
+
+impl StaticImage {
+    /// Borrow as an [`ImageRef`]
+    pub fn as_image_ref(&self) -> ImageRef<'static> {
+        ImageRef {
+            data: self.data,
+            res: self.res,
+            format: PixelFormat::Rgba8888,
+            color: self.color,
+        }
+    }
+}
+
+impl Image {
+    /// Rust source embedding this image in `format`, 16 bytes per line, see
+    /// [`Image::to_rust_source_with`]
+    pub fn to_rust_source(&self, name: &str, format: PixelFormat) -> String {
+        self.to_rust_source_with(name, format, 16)
+    }
+
+    /// Rust source embedding this image in `format`, for build steps that
+    /// generate `.rs` files
+    ///
+    /// This is a `pub const NAME: &[u8]` of the pixel data from
+    /// [`Image::to_raw`], with `bytes_per_line` bytes on each line so diffs
+    /// stay readable, and `NAME_WIDTH`, `NAME_HEIGHT`, and `NAME_FORMAT`.
+    /// `name` is uppercased. Load it back with [`Image::from_const`].
+    ///
+    /// The color space is not included, it's whatever this image is in.
+    pub fn to_rust_source_with(
+        &self,
+        name: &str,
+        format: PixelFormat,
+        bytes_per_line: usize,
+    ) -> String {
+        let name = name.to_ascii_uppercase();
+        let data = self.to_raw(format);
+        let mut s = String::new();
+        // Writing to a String can't fail
+        let _ = writeln!(s, "pub const {name}: &[u8] = &[");
+        for line in data.chunks(bytes_per_line.max(1)) {
+            s.push_str("   ");
+            for b in line {
+                let _ = write!(s, " {b},");
+            }
+            s.push('\n');
+        }
+        let _ = writeln!(s, "];");
+        let _ = writeln!(s, "pub const {name}_WIDTH: u32 = {};", self.width());
+        let _ = writeln!(s, "pub const {name}_HEIGHT: u32 = {};", self.height());
+        let _ = writeln!(
+            s,
+            "pub const {name}_FORMAT: embedded_image::PixelFormat = \
+             embedded_image::PixelFormat::{format:?};"
+        );
+        s
+    }
+
+    /// C header embedding this image in `format`, 16 bytes per line, see
+    /// [`Image::to_c_header_with`]
+    pub fn to_c_header(&self, name: &str, format: PixelFormat) -> String {
+        self.to_c_header_with(name, format, 16)
+    }
+
+    /// C header embedding this image in `format`, for firmware that isn't
+    /// all Rust
+    ///
+    /// Like [`Image::to_rust_source_with`], this is a
+    /// `static const uint8_t name[]` of the pixel data from
+    /// [`Image::to_raw`], with `NAME_WIDTH`, `NAME_HEIGHT`, `NAME_STRIDE` in
+    /// bytes, and `NAME_FORMAT` as a string of the [`PixelFormat`], all in
+    /// an include guard. Characters in `name` that can't be in a C
+    /// identifier become `_`, and one starting with a digit gets an `img_`
+    /// prefix.
+    pub fn to_c_header_with(
+        &self,
+        name: &str,
+        format: PixelFormat,
+        bytes_per_line: usize,
+    ) -> String {
+        let mut ident: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+            ident.insert_str(0, "img_");
+        }
+        let upper = ident.to_ascii_uppercase();
+        let data = self.to_raw(format);
+        let stride = format.row_bytes(self.width()).unwrap_or(0);
+        let mut s = String::new();
+        // Writing to a String can't fail
+        let _ = writeln!(s, "#ifndef {upper}_H");
+        let _ = writeln!(s, "#define {upper}_H");
+        let _ = writeln!(s);
+        let _ = writeln!(s, "#include <stdint.h>");
+        let _ = writeln!(s);
+        let _ = writeln!(s, "#define {upper}_WIDTH {}", self.width());
+        let _ = writeln!(s, "#define {upper}_HEIGHT {}", self.height());
+        let _ = writeln!(s, "#define {upper}_STRIDE {stride}");
+        let _ = writeln!(s, "#define {upper}_FORMAT \"{format:?}\"");
+        let _ = writeln!(s);
+        let _ = writeln!(s, "static const uint8_t {ident}[] = {{");
+        for line in data.chunks(bytes_per_line.max(1)) {
+            s.push_str("   ");
+            for b in line {
+                let _ = write!(s, " 0x{b:02x},");
+            }
+            s.push('\n');
+        }
+        let _ = writeln!(s, "}};");
+        let _ = writeln!(s);
+        let _ = writeln!(s, "#endif");
+        s
+    }
+
+    /// Read an Image from constants made by [`Image::to_rust_source`]
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::BufferSize`] if `data` is the wrong size, see
+    ///   [`Image::from_raw`]
+    pub fn from_const(
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+        color: ColorSpace,
+    ) -> Result<Self, ImageError> {
+        Self::from_raw(data, (width, height), format, color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::fixtures::photo;
+
+    fn tiny() -> Image {
+        let data = [
+            255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 128, 10, 20, 30, 0,
+        ];
+        Image::from_bytes(&data, (2, 2), ColorSpace::sRGB)
+    }
+
+    /// The bytes of the array in `source`
+    fn parse(source: &str) -> Vec<u8> {
+        let start = source.find("= &[").unwrap() + 4;
+        let end = source.find("];").unwrap();
+        source[start..end]
+            .split(',')
+            .map(str::trim)
+            .filter(|b| !b.is_empty())
+            .map(|b| b.parse().unwrap())
+            .collect()
+    }
+
+    /// The value of the constant `name` in `source`
+    fn constant<'a>(source: &'a str, name: &str) -> &'a str {
+        let line = source
+            .lines()
+            .find(|l| l.starts_with(&alloc::format!("pub const {name}:")))
+            .unwrap();
+        line[line.find(" = ").unwrap() + 3..].trim_end_matches(';')
+    }
+
+    #[test]
+    fn rust_source_exact() {
+        let source = tiny().to_rust_source_with("logo", PixelFormat::Rgba8888, 8);
+        assert_eq!(
+            source,
+            "pub const LOGO: &[u8] = &[\n    \
+             255, 0, 0, 255, 0, 255, 0, 255,\n    \
+             0, 0, 255, 128, 10, 20, 30, 0,\n\
+             ];\n\
+             pub const LOGO_WIDTH: u32 = 2;\n\
+             pub const LOGO_HEIGHT: u32 = 2;\n\
+             pub const LOGO_FORMAT: embedded_image::PixelFormat = \
+             embedded_image::PixelFormat::Rgba8888;\n"
+        );
+        // 16 a line by default, and at least one
+        assert_eq!(
+            tiny()
+                .to_rust_source("logo", PixelFormat::Rgba8888)
+                .lines()
+                .count(),
+            6
+        );
+        let one = tiny().to_rust_source_with("logo", PixelFormat::Rgb565Le, 0);
+        assert_eq!(one.lines().count(), 4 * 2 + 5);
+    }
+
+    #[test]
+    fn rust_source_round_trips() {
+        let img = photo((13, 7));
+        for format in [
+            PixelFormat::Rgba8888,
+            PixelFormat::Rgb565Le,
+            PixelFormat::Gray8,
+        ] {
+            let source = img.to_rust_source("asset", format);
+            let width = constant(&source, "ASSET_WIDTH").parse().unwrap();
+            let height = constant(&source, "ASSET_HEIGHT").parse().unwrap();
+            assert_eq!((width, height), (13, 7));
+            let tag = alloc::format!("embedded_image::PixelFormat::{format:?}");
+            assert_eq!(constant(&source, "ASSET_FORMAT"), tag);
+
+            let back = Image::from_const(&parse(&source), width, height, format, ColorSpace::sRGB)
+                .unwrap();
+            assert_eq!(back.to_raw(format), img.to_raw(format), "{format:?}");
+        }
+        assert_eq!(
+            Image::from_const(&[0; 15], 2, 2, PixelFormat::Rgba8888, ColorSpace::sRGB).err(),
+            Some(ImageError::BufferSize {
+                expected: 16,
+                actual: 15
+            })
+        );
+    }
+
+    #[test]
+    fn c_header_exact() {
+        let img = Image::from_bytes(&[255, 0, 0, 255, 0, 0, 255, 255], (2, 1), ColorSpace::sRGB);
+        let want = "\
+#ifndef LOGO_H
+#define LOGO_H
+
+#include <stdint.h>
+
+#define LOGO_WIDTH 2
+#define LOGO_HEIGHT 1
+#define LOGO_STRIDE 4
+#define LOGO_FORMAT \"Rgb565Le\"
+
+static const uint8_t logo[] = {
+    0x00, 0xf8, 0x1f, 0x00,
+};
+
+#endif
+";
+        assert_eq!(img.to_c_header("logo", PixelFormat::Rgb565Le), want);
+    }
+
+    #[test]
+    fn c_header_identifiers() {
+        let img = tiny();
+        for (name, ident) in [
+            ("my-logo 2", "my_logo_2"),
+            ("2nd", "img_2nd"),
+            ("", "img_"),
+            ("é", "_"),
+        ] {
+            let header = img.to_c_header(name, PixelFormat::Gray8);
+            let upper = ident.to_ascii_uppercase();
+            assert!(
+                header.starts_with(&alloc::format!("#ifndef {upper}_H\n")),
+                "{header}"
+            );
+            assert!(
+                header.contains(&alloc::format!("uint8_t {ident}[] = {{")),
+                "{header}"
+            );
+            assert!(
+                header.contains(&alloc::format!("#define {upper}_WIDTH 2\n")),
+                "{header}"
+            );
+        }
+    }
+
+    #[test]
+    fn c_header_line_width() {
+        let img = tiny();
+        let header = img.to_c_header_with("tiny", PixelFormat::Rgba8888, 3);
+        let lines: Vec<&str> = header.lines().filter(|l| l.starts_with("    0x")).collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "    0xff, 0x00, 0x00,");
+        assert_eq!(lines[5], "    0x00,");
+    }
+}
@@ -0,0 +1,255 @@
+//! Variants of the allocating operations that report allocation failure
+//! instead of aborting
+use alloc::vec::Vec;
+
+use crate::{
+    alpha_converter, layout, AlphaMode, ColorSpace, Image, ImageError, PixelFormat, ResXY,
+    ScaleFilter, WorkPixel,
+};
+
+/// An empty `Vec` with room for exactly `len` items, or
+/// [`ImageError::OutOfMemory`]
+///
+/// A size that overflows `usize` reports `usize::MAX` bytes.
+pub(crate) fn try_with_capacity<T>(len: usize) -> Result<Vec<T>, ImageError> {
+    let requested_bytes = len.saturating_mul(size_of::<T>());
+    let mut v = Vec::new();
+    v.try_reserve_exact(len)
+        .map_err(|_| ImageError::OutOfMemory { requested_bytes })?;
+    Ok(v)
+}
+
+/// `len` copies of `value`, or [`ImageError::OutOfMemory`]
+fn try_filled<T: Clone>(len: usize, value: T) -> Result<Vec<T>, ImageError> {
+    let mut v = try_with_capacity(len)?;
+    v.resize(len, value);
+    Ok(v)
+}
+
+/// Number of pixels in `res`, or [`ImageError::OutOfMemory`] if that
+/// doesn't fit in memory at all
+pub(crate) fn pixels(res: ResXY) -> Result<usize, ImageError> {
+    (res.0 as usize)
+        .checked_mul(res.1 as usize)
+        .ok_or(ImageError::OutOfMemory {
+            requested_bytes: usize::MAX,
+        })
+}
+
+impl Image {
+    /// [`Image::from_bytes`], reporting a failed allocation as an error
+    /// instead of aborting
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::BufferSize`] if `data` is not exactly
+    ///   `width * height * 4` in size
+    /// - [`ImageError::OutOfMemory`] if the pixels couldn't be allocated
+    pub fn try_from_bytes_fallible(
+        data: &[u8],
+        res: ResXY,
+        color: ColorSpace,
+    ) -> Result<Self, ImageError> {
+        let expected = pixels(res)?.saturating_mul(4);
+        if data.len() != expected {
+            return Err(ImageError::BufferSize {
+                expected,
+                actual: data.len(),
+            });
+        }
+        let mut pixels = try_with_capacity(data.len() / 4)?;
+        pixels.extend(
+            data.chunks_exact(4)
+                .map(|p| [p[0], p[1], p[2], p[3]].map(|c| c as f32 / 255.)),
+        );
+        Ok(Self::from_parts(pixels, res, color))
+    }
+
+    /// [`Clone::clone`], reporting a failed allocation as an error instead
+    /// of aborting
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::OutOfMemory`] if the pixels couldn't be allocated
+    pub fn try_clone(&self) -> Result<Self, ImageError> {
+        let mut data = try_with_capacity(self.data.len())?;
+        data.extend_from_slice(&self.data);
+        Ok(self.derive(data, self.res))
+    }
+
+    /// [`Image::scale_with`], reporting a failed allocation as an error
+    /// instead of aborting
+    ///
+    /// The result is identical, but never done in place, the output is
+    /// allocated up front like [`Image::scale_job`]. On error the image is
+    /// unchanged.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `new` is zero in either
+    ///   dimension
+    /// - [`ImageError::OutOfMemory`] if the output couldn't be allocated
+    pub fn try_scale_fallible(
+        &mut self,
+        new: ResXY,
+        filter: ScaleFilter,
+    ) -> Result<(), ImageError> {
+        if new.0 == 0 || new.1 == 0 {
+            return Err(ImageError::InvalidArgument);
+        }
+        let out = try_filled(pixels(new)?, WorkPixel::default())?;
+        let img = self.scale_job_into(new, filter, out).finish();
+        *self = img;
+        Ok(())
+    }
+
+    /// [`Image::to_bytes`], reporting a failed allocation as an error
+    /// instead of aborting
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::OutOfMemory`] if the bytes couldn't be allocated
+    pub fn try_to_bytes(&self) -> Result<Vec<u8>, ImageError> {
+        let convert = alpha_converter(self.alpha, AlphaMode::Straight);
+        let mut out = try_with_capacity(self.data.len().saturating_mul(4))?;
+        out.extend(
+            self.data
+                .iter()
+                .flat_map(|p| convert(*p).map(|c| layout::quantize(c, 255.) as u8)),
+        );
+        Ok(out)
+    }
+
+    /// [`Image::to_raw`], reporting a failed allocation as an error instead
+    /// of aborting
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::OutOfMemory`] if the bytes couldn't be allocated
+    pub fn try_to_raw(&self, format: PixelFormat) -> Result<Vec<u8>, ImageError> {
+        let bpp = format.bytes_per_pixel();
+        let transfer = self.color.transfer();
+        let mut out = try_filled(self.data.len().saturating_mul(bpp), 0)?;
+        for (p, o) in self.data.iter().zip(out.chunks_exact_mut(bpp)) {
+            format.encode(layout::prepare(*p, format, self.alpha, transfer), o);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+
+    use super::*;
+    use crate::fixtures::{photo, ramp};
+
+    /// Pixels for half of `isize::MAX`, so no allocator can say yes
+    const HUGE: ResXY = (1 << 30, 1 << 29);
+    const HUGE_BYTES: usize = 1 << 63;
+
+    #[test]
+    fn failures_report_the_size() {
+        assert_eq!(
+            try_with_capacity::<WorkPixel>(1 << 59).err(),
+            Some(ImageError::OutOfMemory {
+                requested_bytes: HUGE_BYTES
+            })
+        );
+        // Overflowing sizes saturate
+        assert_eq!(
+            try_with_capacity::<WorkPixel>(usize::MAX / 2).err(),
+            Some(ImageError::OutOfMemory {
+                requested_bytes: usize::MAX
+            })
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                ImageError::OutOfMemory {
+                    requested_bytes: 12
+                }
+            ),
+            "failed to allocate 12 bytes"
+        );
+    }
+
+    #[test]
+    fn failed_scale_leaves_the_image() {
+        let mut img = photo((9, 7));
+        let before = img.clone();
+        assert_eq!(
+            img.try_scale_fallible(HUGE, ScaleFilter::Bilinear),
+            Err(ImageError::OutOfMemory {
+                requested_bytes: HUGE_BYTES
+            })
+        );
+        assert_eq!(
+            img.try_scale_fallible((u32::MAX, u32::MAX), ScaleFilter::Nearest),
+            Err(ImageError::OutOfMemory {
+                requested_bytes: usize::MAX
+            })
+        );
+        assert_eq!(
+            img.try_scale_fallible((0, 3), ScaleFilter::Nearest),
+            Err(ImageError::InvalidArgument)
+        );
+        assert_eq!(img.res, before.res);
+        assert_eq!(img.pixels(), before.pixels());
+    }
+
+    #[test]
+    fn from_bytes_checks_size() {
+        assert_eq!(
+            Image::try_from_bytes_fallible(&[0; 8], HUGE, ColorSpace::sRGB).err(),
+            Some(ImageError::BufferSize {
+                expected: HUGE_BYTES / 4,
+                actual: 8
+            })
+        );
+        assert_eq!(
+            Image::try_from_bytes_fallible(&[0; 8], (3, 1), ColorSpace::sRGB).err(),
+            Some(ImageError::BufferSize {
+                expected: 12,
+                actual: 8
+            })
+        );
+    }
+
+    #[test]
+    fn same_as_infallible() {
+        let src = ramp((7, 5));
+        let bytes = src.to_bytes();
+        let img = Image::try_from_bytes_fallible(&bytes, (7, 5), ColorSpace::sRGB).unwrap();
+        assert_eq!(img.pixels(), src.pixels());
+
+        let mut img = photo((9, 7));
+        img.to_alpha_mode(AlphaMode::Premultiplied);
+        let copy = img.try_clone().unwrap();
+        assert_eq!(copy.pixels(), img.pixels());
+        assert_eq!(
+            (copy.res, copy.alpha, copy.color),
+            (img.res, img.alpha, img.color)
+        );
+        assert_eq!(img.try_to_bytes().unwrap(), img.to_bytes());
+        for format in [
+            PixelFormat::Rgb565Le,
+            PixelFormat::Bgra8888,
+            PixelFormat::Gray8,
+        ] {
+            assert_eq!(img.try_to_raw(format).unwrap(), img.to_raw(format));
+        }
+
+        for (new, filter) in [
+            ((4, 3), ScaleFilter::Bilinear),
+            ((18, 14), ScaleFilter::Nearest),
+            ((13, 5), ScaleFilter::CATMULL_ROM),
+        ] {
+            let (mut a, mut b) = (img.clone(), img.clone());
+            a.scale_with(new, filter);
+            b.try_scale_fallible(new, filter).unwrap();
+            assert_eq!(a.res, b.res);
+            assert_eq!(a.pixels(), b.pixels(), "{new:?} {filter:?}");
+        }
+    }
+}
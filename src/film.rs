@@ -0,0 +1,222 @@
+//! Film negatives
+use alloc::vec::Vec;
+
+use crate::{
+    transforms::{luminance, premultiply, unpremultiply},
+    AlphaMode, Image, ImageError, WorkPixel, F32,
+};
+
+/// Smallest film base channel, and negative value relative to it, that's
+/// divided by
+const MIN_BASE: f32 = 1e-4;
+
+/// Side of the blocks [`Image::estimate_film_base`] looks at
+const BLOCK: u32 = 8;
+
+impl Image {
+    /// Turn a scanned color negative into a positive
+    ///
+    /// `base` is the color of the unexposed film, with its orange mask, in
+    /// the image's color space, see [`Image::estimate_film_base`]. Each
+    /// linear channel is divided by the base, which removes the mask, then
+    /// inverted as `n^(-1 / gamma)`, so denser film is brighter, then
+    /// stretched so each channel's darkest and brightest values become `0`
+    /// and `1`. `gamma` is the film's contrast per channel, commonly around
+    /// `0.6`. Alpha is untouched.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if a `base` channel is about zero,
+    ///   or a `gamma` isn't positive
+    pub fn invert_negative(
+        &mut self,
+        base: impl Into<WorkPixel>,
+        gamma: [f32; 3],
+    ) -> Result<(), ImageError> {
+        let base = base.into();
+        let decode = self.color.transfer().map(|t| t.0);
+        let base: [f32; 3] = core::array::from_fn(|c| decode.map_or(base[c], |f| f(base[c])));
+        let bad_base = base.iter().any(|b| b.is_nan() || *b < MIN_BASE);
+        if bad_base || gamma.iter().any(|g| g.is_nan() || *g <= 0.) {
+            return Err(ImageError::InvalidArgument);
+        }
+        let alpha = self.alpha;
+        self.in_linear(|img| {
+            let (mut lo, mut hi) = ([f32::MAX; 3], [f32::MIN; 3]);
+            for p in &mut img.data {
+                let mut s = match alpha {
+                    AlphaMode::Straight => *p,
+                    AlphaMode::Premultiplied => unpremultiply(*p),
+                };
+                for c in 0..3 {
+                    let n = (s[c] / base[c]).clamp(MIN_BASE, 1.);
+                    s[c] = n.powf(-1. / gamma[c]);
+                    lo[c] = lo[c].min(s[c]);
+                    hi[c] = hi[c].max(s[c]);
+                }
+                *p = s;
+            }
+            for p in &mut img.data {
+                for c in 0..3 {
+                    let range = hi[c] - lo[c];
+                    p[c] = if range > 0. {
+                        ((p[c] - lo[c]) / range).clamp(0., 1.)
+                    } else {
+                        0.
+                    };
+                }
+                if alpha == AlphaMode::Premultiplied {
+                    *p = premultiply(*p);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Estimate the film base color of a negative, for
+    /// [`Image::invert_negative`]
+    ///
+    /// This is the mean of the brightest of the flattest 8x8 blocks, the
+    /// flattest half being those with the least variation, which is usually
+    /// the unexposed edge of the film. The result is opaque, in the image's
+    /// color space. Empty images are black.
+    pub fn estimate_film_base(&self) -> WorkPixel {
+        let (w, h) = self.res;
+        let size = BLOCK.min(w).min(h);
+        if size == 0 {
+            return [0., 0., 0., 1.];
+        }
+        let transfer = self.color.transfer();
+        let linear = |p: WorkPixel| {
+            let p = match self.alpha {
+                AlphaMode::Straight => p,
+                AlphaMode::Premultiplied => unpremultiply(p),
+            };
+            let rgb = [p[0], p[1], p[2]];
+            transfer.map_or(rgb, |(decode, _)| rgb.map(decode))
+        };
+        // Mean and variance of each block
+        let mut blocks: Vec<([f32; 3], f32)> = Vec::new();
+        for by in 0..h / size {
+            for bx in 0..w / size {
+                let (mut sum, mut sq) = ([0f32; 3], [0f32; 3]);
+                for y in by * size..(by + 1) * size {
+                    for x in bx * size..(bx + 1) * size {
+                        let rgb = linear(self.data[(y * w + x) as usize]);
+                        for c in 0..3 {
+                            sum[c] += rgb[c];
+                            sq[c] += rgb[c] * rgb[c];
+                        }
+                    }
+                }
+                let n = (size * size) as f32;
+                let mean = sum.map(|s| s / n);
+                let var = (0..3).map(|c| sq[c] / n - mean[c] * mean[c]).sum();
+                blocks.push((mean, var));
+            }
+        }
+        blocks.sort_by(|a, b| a.1.total_cmp(&b.1));
+        blocks.truncate(blocks.len().div_ceil(2));
+        let (mean, _) = blocks
+            .into_iter()
+            .max_by(|a, b| luminance(a.0).total_cmp(&luminance(b.0)))
+            .unwrap_or(([0.; 3], 0.));
+        let [r, g, b] = transfer.map_or(mean, |(_, encode)| mean.map(encode));
+        [r, g, b, 1.]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{max_diff, noise, solid},
+        ColorSpace,
+    };
+
+    const BASE: [f32; 3] = [0.8, 0.45, 0.2];
+    const GAMMA: [f32; 3] = [0.6, 0.7, 0.5];
+
+    /// A positive, in linear light, black down the unexposed left edge and
+    /// with each channel reaching `1`
+    fn positive() -> Image {
+        let mut seed = 3;
+        let mut img = solid((32, 16), [0.; 4]);
+        img.color = ColorSpace::sRGBLinear;
+        img.map_pixels_indexed(|(x, y), _| match (x, y) {
+            (0..8, _) => [0., 0., 0., 1.],
+            (20, 5) => [1.; 4],
+            _ => {
+                let [r, g, b] = [(); 3].map(|_| noise(&mut seed) * 0.9);
+                [r, g, b, 1.]
+            }
+        });
+        img
+    }
+
+    /// Film that `invert_negative` with `BASE` and `GAMMA` turns back into
+    /// `positive`
+    fn negative(positive: &Image) -> Image {
+        let mut img = positive.clone();
+        img.map_pixels(|p| {
+            let c = |c: usize| BASE[c] * (1. + 3. * p[c]).powf(-GAMMA[c]);
+            [c(0), c(1), c(2), p[3]]
+        });
+        img
+    }
+
+    #[test]
+    fn negative_inverts_back() {
+        let positive = positive();
+        let mut img = negative(&positive);
+        let base = img.estimate_film_base();
+        for c in 0..3 {
+            assert!((base[c] - BASE[c]).abs() < 1e-5, "{base:?}");
+        }
+        img.invert_negative(base, GAMMA).unwrap();
+        assert!(max_diff(img.pixels(), positive.pixels()) < 1e-3);
+    }
+
+    #[test]
+    fn bad_arguments() {
+        let mut img = negative(&positive());
+        let before = img.clone();
+        for base in [
+            [0., 0.5, 0.5, 1.],
+            [0.5, f32::NAN, 0.5, 1.],
+            [0.5, 0.5, -1., 1.],
+        ] {
+            assert_eq!(
+                img.invert_negative(base, GAMMA),
+                Err(ImageError::InvalidArgument)
+            );
+        }
+        for gamma in [[0., 0.6, 0.6], [0.6, -1., 0.6], [0.6, 0.6, f32::NAN]] {
+            assert_eq!(
+                img.invert_negative([0.5; 4], gamma),
+                Err(ImageError::InvalidArgument)
+            );
+        }
+        assert_eq!(img.pixels(), before.pixels());
+    }
+
+    #[test]
+    fn clear_film_stays_finite() {
+        let mut img = negative(&positive());
+        img.data[40] = [0., 0., 0., 1.];
+        img.data[41] = [2., 2., 2., 1.];
+        img.invert_negative([BASE[0], BASE[1], BASE[2], 1.], GAMMA)
+            .unwrap();
+        assert!(img
+            .pixels()
+            .iter()
+            .flatten()
+            .all(|c| (0.0..=1.).contains(c)));
+    }
+
+    #[test]
+    fn estimate_empty() {
+        let img = solid((0, 4), [0.5; 4]);
+        assert_eq!(img.estimate_film_base(), [0., 0., 0., 1.]);
+    }
+}
@@ -0,0 +1,155 @@
+//! Thumbnail strips
+use alloc::vec;
+
+use crate::{alpha_converter, AlphaMode, Image, ImageError, ResXY, ScaleFilter, WorkPixel};
+
+/// Size of `src` scaled to fit in `frame`, keeping its aspect ratio
+fn fit((sw, sh): ResXY, (fw, fh): ResXY) -> ResXY {
+    let s = (fw as f32 / sw as f32).min(fh as f32 / sh as f32);
+    let d = |v: u32, max: u32| ((v as f32 * s + 0.5) as u32).clamp(1, max);
+    (d(sw, fw), d(sh, fh))
+}
+
+impl Image {
+    /// `count` evenly spaced frames from `frames`, each scaled to fit
+    /// `frame_size` with `filter`, laid out left to right
+    ///
+    /// The first and last frames are always included. With fewer frames than
+    /// `count` every frame is used once, so the strip is narrower. Frames are
+    /// centered in their cell, and the bars around ones with a different
+    /// aspect ratio are `pad`, a straight alpha pixel. The result has
+    /// straight alpha.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if there are no frames, `count` is
+    ///   zero, or `frame_size` is zero in either dimension
+    /// - [`ImageError::ColorSpaceMismatch`] if the frames have different
+    ///   color spaces
+    pub fn filmstrip(
+        frames: &[Image],
+        count: usize,
+        frame_size: ResXY,
+        filter: ScaleFilter,
+        pad: impl Into<WorkPixel>,
+    ) -> Result<Image, ImageError> {
+        let pad = pad.into();
+        let (fw, fh) = frame_size;
+        if frames.is_empty() || count == 0 || fw == 0 || fh == 0 {
+            return Err(ImageError::InvalidArgument);
+        }
+        let color = frames[0].color;
+        if frames.iter().any(|f| f.color != color) {
+            return Err(ImageError::ColorSpaceMismatch);
+        }
+        let count = count.min(frames.len());
+        let width = fw
+            .checked_mul(count as u32)
+            .ok_or(ImageError::InvalidArgument)?;
+        let mut data = vec![pad; width as usize * fh as usize];
+
+        for i in 0..count {
+            let index = match count {
+                1 => 0,
+                _ => (i * (frames.len() - 1) + (count - 1) / 2) / (count - 1),
+            };
+            let mut frame = frames[index].clone();
+            let size = fit(frame.res, frame_size);
+            frame.scale_with(size, filter);
+            let convert = alpha_converter(frame.alpha, AlphaMode::Straight);
+            let x0 = i as u32 * fw + (fw - size.0) / 2;
+            let y0 = (fh - size.1) / 2;
+            for (y, row) in frame.data.chunks_exact(size.0 as usize).enumerate() {
+                let start = (y0 as usize + y) * width as usize + x0 as usize;
+                for (o, p) in data[start..start + row.len()].iter_mut().zip(row) {
+                    *o = convert(*p);
+                }
+            }
+        }
+        Ok(Image::from_parts(data, (width, fh), color))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorSpace;
+
+    #[test]
+    fn empty_frames() {
+        let frames = [
+            Image::from_bytes(&[], (0, 5), ColorSpace::sRGB),
+            Image::from_bytes(&[], (5, 0), ColorSpace::sRGB),
+        ];
+        for filter in [ScaleFilter::Nearest, ScaleFilter::Box] {
+            let strip = Image::filmstrip(&frames, 2, (3, 3), filter, [0., 0., 0., 1.]).unwrap();
+            assert_eq!(strip.res, (6, 3));
+        }
+    }
+
+    /// `n` wide frames, each a different gray, frame `i` at `i / 16`
+    fn frames(n: usize) -> alloc::vec::Vec<Image> {
+        (0..n)
+            .map(|i| {
+                let v = i as f32 / 16.;
+                crate::fixtures::solid((16, 8), [v, v, v, 1.])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn layout_and_selection() {
+        let pad = [1., 0., 1., 0.5];
+        let strip = Image::filmstrip(&frames(10), 4, (12, 12), ScaleFilter::Box, pad).unwrap();
+        assert_eq!(strip.res, (4 * 12, 12));
+        // Evenly spaced, first and last included
+        for (cell, index) in [0, 3, 6, 9].into_iter().enumerate() {
+            let center = strip.get_pixel((cell as u32 * 12 + 6, 6)).unwrap();
+            assert_eq!(center[0], index as f32 / 16., "cell {cell}");
+        }
+        // 2:1 frames in square cells, bars above and below
+        for (i, p) in strip.pixels().iter().enumerate() {
+            let y = i as u32 / strip.width();
+            if !(3..9).contains(&y) {
+                assert_eq!(*p, pad, "{i}");
+            } else {
+                assert_eq!(p[3], 1.);
+            }
+        }
+    }
+
+    #[test]
+    fn fewer_frames_than_asked() {
+        let strip = Image::filmstrip(&frames(3), 5, (8, 4), ScaleFilter::Nearest, [0.; 4]).unwrap();
+        assert_eq!(strip.res, (3 * 8, 4));
+        for cell in 0..3 {
+            let p = strip.get_pixel((cell * 8 + 4, 2)).unwrap();
+            assert_eq!(p[0], cell as f32 / 16.);
+        }
+        let one = Image::filmstrip(&frames(6), 1, (8, 4), ScaleFilter::Nearest, [0.; 4]).unwrap();
+        assert_eq!(one.res, (8, 4));
+        assert_eq!(one.get_pixel((4, 2)).unwrap()[0], 0.);
+    }
+
+    #[test]
+    fn errors() {
+        let f = frames(2);
+        let pad = [0.; 4];
+        for (frames, count, size) in [
+            (&f[..0], 2, (4, 4)),
+            (&f[..], 0, (4, 4)),
+            (&f[..], 2, (0, 4)),
+        ] {
+            assert_eq!(
+                Image::filmstrip(frames, count, size, ScaleFilter::Box, pad).err(),
+                Some(ImageError::InvalidArgument)
+            );
+        }
+        let mut mixed = frames(2);
+        mixed[1].color = ColorSpace::sRGBLinear;
+        assert_eq!(
+            Image::filmstrip(&mixed, 2, (4, 4), ScaleFilter::Box, pad).err(),
+            Some(ImageError::ColorSpaceMismatch)
+        );
+    }
+}
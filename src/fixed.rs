@@ -0,0 +1,226 @@
+//! Integer only color conversion, for cores without an FPU
+//!
+//! Linear values are 13 bit, `0..=8191`, enough to keep every 8 bit sRGB
+//! code distinct. Decoding uses 256 entry `u16` tables, 512 bytes each, and
+//! encoding uses 8192 entry `u8` tables, 8 KiB each, built at compile time.
+use crate::{ColorSpace, ImageError};
+
+/// Largest 13 bit linear value
+pub const LINEAR_MAX: u16 = 8191;
+
+/// 8 bit sRGB to 13 bit linear
+pub static SRGB_TO_LINEAR: [u16; 256] = [
+    0, 2, 5, 7, 10, 12, 15, 17, 20, 22, 25, 27, 30, 33, 36, 39, 42, 46, 50, 53, 57, 61, 66, 70, 75,
+    80, 85, 90, 95, 101, 106, 112, 118, 125, 131, 138, 145, 152, 159, 166, 174, 182, 190, 198, 206,
+    215, 224, 233, 242, 252, 261, 271, 281, 292, 302, 313, 324, 335, 347, 358, 370, 382, 395, 407,
+    420, 433, 446, 460, 473, 487, 502, 516, 531, 546, 561, 576, 592, 608, 624, 640, 657, 674, 691,
+    709, 726, 744, 762, 781, 799, 818, 837, 857, 877, 897, 917, 937, 958, 979, 1000, 1022, 1044,
+    1066, 1088, 1111, 1134, 1157, 1181, 1204, 1228, 1253, 1277, 1302, 1327, 1353, 1378, 1404, 1431,
+    1457, 1484, 1511, 1538, 1566, 1594, 1622, 1651, 1680, 1709, 1738, 1768, 1798, 1828, 1859, 1890,
+    1921, 1953, 1985, 2017, 2049, 2082, 2115, 2148, 2182, 2216, 2250, 2284, 2319, 2354, 2390, 2426,
+    2462, 2498, 2535, 2572, 2609, 2647, 2685, 2723, 2762, 2801, 2840, 2879, 2919, 2959, 3000, 3041,
+    3082, 3123, 3165, 3207, 3250, 3293, 3336, 3379, 3423, 3467, 3511, 3556, 3601, 3647, 3692, 3738,
+    3785, 3832, 3879, 3926, 3974, 4022, 4070, 4119, 4168, 4218, 4267, 4318, 4368, 4419, 4470, 4522,
+    4573, 4626, 4678, 4731, 4784, 4838, 4892, 4946, 5001, 5056, 5111, 5167, 5223, 5279, 5336, 5393,
+    5450, 5508, 5566, 5625, 5684, 5743, 5802, 5862, 5923, 5983, 6044, 6106, 6167, 6229, 6292, 6355,
+    6418, 6482, 6545, 6610, 6674, 6739, 6805, 6871, 6937, 7003, 7070, 7137, 7205, 7273, 7341, 7410,
+    7479, 7549, 7619, 7689, 7759, 7830, 7902, 7973, 8046, 8118, 8191,
+];
+
+/// 8 bit gamma 2.2, [`ColorSpace::SimplesRGB`], to 13 bit linear
+pub static GAMMA_TO_LINEAR: [u16; 256] = [
+    0, 0, 0, 0, 1, 1, 2, 3, 4, 5, 7, 8, 10, 12, 14, 16, 19, 21, 24, 27, 30, 34, 37, 41, 45, 49, 54,
+    59, 63, 69, 74, 79, 85, 91, 97, 104, 110, 117, 124, 132, 139, 147, 155, 163, 172, 180, 189,
+    198, 208, 217, 227, 237, 248, 258, 269, 280, 292, 303, 315, 327, 340, 352, 365, 378, 391, 405,
+    419, 433, 447, 462, 477, 492, 507, 523, 539, 555, 571, 588, 605, 622, 639, 657, 675, 693, 712,
+    731, 750, 769, 789, 808, 828, 849, 870, 890, 912, 933, 955, 977, 999, 1022, 1045, 1068, 1091,
+    1115, 1139, 1163, 1187, 1212, 1237, 1263, 1288, 1314, 1340, 1367, 1394, 1421, 1448, 1476, 1503,
+    1532, 1560, 1589, 1618, 1647, 1677, 1707, 1737, 1767, 1798, 1829, 1860, 1892, 1924, 1956, 1989,
+    2022, 2055, 2088, 2122, 2156, 2190, 2224, 2259, 2294, 2330, 2366, 2402, 2438, 2475, 2512, 2549,
+    2586, 2624, 2662, 2701, 2740, 2779, 2818, 2858, 2897, 2938, 2978, 3019, 3060, 3102, 3143, 3186,
+    3228, 3271, 3314, 3357, 3400, 3444, 3489, 3533, 3578, 3623, 3669, 3714, 3760, 3807, 3853, 3900,
+    3948, 3995, 4043, 4091, 4140, 4189, 4238, 4288, 4337, 4387, 4438, 4489, 4540, 4591, 4643, 4695,
+    4747, 4800, 4853, 4906, 4960, 5013, 5068, 5122, 5177, 5232, 5288, 5344, 5400, 5456, 5513, 5570,
+    5627, 5685, 5743, 5802, 5860, 5919, 5979, 6038, 6098, 6159, 6219, 6280, 6342, 6403, 6465, 6528,
+    6590, 6653, 6716, 6780, 6844, 6908, 6973, 7037, 7103, 7168, 7234, 7300, 7367, 7434, 7501, 7568,
+    7636, 7704, 7773, 7842, 7911, 7980, 8050, 8120, 8191,
+];
+
+/// 13 bit linear to 8 bit sRGB
+pub static LINEAR_TO_SRGB: [u8; 8192] = invert(&SRGB_TO_LINEAR);
+
+/// 13 bit linear to 8 bit gamma 2.2, [`ColorSpace::SimplesRGB`]
+pub static LINEAR_TO_GAMMA: [u8; 8192] = invert(&GAMMA_TO_LINEAR);
+
+/// Invert a decoding table, picking the code with the nearest linear value
+const fn invert(decode: &[u16; 256]) -> [u8; 8192] {
+    let mut out = [0; 8192];
+    let mut code = 0;
+    let mut v = 0;
+    while v < out.len() {
+        // Both are monotonic, so only ever step forward
+        while code < 255 && ((decode[code] + decode[code + 1]) as usize) < v * 2 {
+            code += 1;
+        }
+        out[v] = code as u8;
+        v += 1;
+    }
+    out
+}
+
+/// 8 bit `color` to 13 bit linear, `None` if that needs more than a transfer
+/// function
+fn decoder(color: ColorSpace) -> Option<fn(u8) -> u16> {
+    match color {
+        ColorSpace::sRGB => Some(|c| SRGB_TO_LINEAR[c as usize]),
+        ColorSpace::SimplesRGB => Some(|c| GAMMA_TO_LINEAR[c as usize]),
+        ColorSpace::sRGBLinear => Some(|c| ((c as u32 * LINEAR_MAX as u32 + 127) / 255) as u16),
+        _ => None,
+    }
+}
+
+/// 13 bit linear to 8 bit `color`, see [`decoder`]
+fn encoder(color: ColorSpace) -> Option<fn(u16) -> u8> {
+    match color {
+        ColorSpace::sRGB => Some(|v| LINEAR_TO_SRGB[v as usize]),
+        ColorSpace::SimplesRGB => Some(|v| LINEAR_TO_GAMMA[v as usize]),
+        ColorSpace::sRGBLinear => {
+            Some(|v| ((v as u32 * 255 + LINEAR_MAX as u32 / 2) / LINEAR_MAX as u32) as u8)
+        }
+        _ => None,
+    }
+}
+
+/// Convert RGBA 8888 `src` from `from` to `to` into `dst`, using only integer
+/// math
+///
+/// Only conversions between transfer functions are supported, that is
+/// between [`ColorSpace::sRGB`], [`ColorSpace::sRGBLinear`], and
+/// [`ColorSpace::SimplesRGB`]. Same space conversions, and to or from
+/// [`ColorSpace::AsIs`], just copy. Results are within one code value of the
+/// floating point path. Alpha is copied.
+///
+/// # Errors
+///
+/// - [`ImageError::DimensionMismatch`] if `src` and `dst` are different
+///   lengths, or not whole pixels
+/// - [`ImageError::Unsupported`] if the conversion needs a matrix, like to or
+///   from [`ColorSpace::DisplayP3`]. Use [`Image::to_color`][crate::Image::to_color]
+///   for those.
+pub fn convert_bytes_fixed(
+    src: &[u8],
+    dst: &mut [u8],
+    from: ColorSpace,
+    to: ColorSpace,
+) -> Result<(), ImageError> {
+    if src.len() != dst.len() || !src.len().is_multiple_of(4) {
+        return Err(ImageError::DimensionMismatch);
+    }
+    if from == to || from == ColorSpace::AsIs || to == ColorSpace::AsIs {
+        dst.copy_from_slice(src);
+        return Ok(());
+    }
+    let (Some(decode), Some(encode)) = (decoder(from), encoder(to)) else {
+        return Err(ImageError::Unsupported);
+    };
+    for (s, d) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+        for c in 0..3 {
+            d[c] = encode(decode(s[c]));
+        }
+        d[3] = s[3];
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{vec, vec::Vec};
+
+    use super::*;
+    use crate::Image;
+
+    const SPACES: [ColorSpace; 3] = [
+        ColorSpace::sRGB,
+        ColorSpace::sRGBLinear,
+        ColorSpace::SimplesRGB,
+    ];
+
+    /// Every code value, in each channel, with a varying alpha
+    fn all_codes() -> Vec<u8> {
+        (0..=255u8).flat_map(|c| [c, c, 255 - c, c / 2]).collect()
+    }
+
+    #[test]
+    fn table_sizes() {
+        assert_eq!(core::mem::size_of_val(&SRGB_TO_LINEAR), 512);
+        assert_eq!(core::mem::size_of_val(&GAMMA_TO_LINEAR), 512);
+        assert_eq!(core::mem::size_of_val(&LINEAR_TO_SRGB), 8192);
+        assert_eq!(core::mem::size_of_val(&LINEAR_TO_GAMMA), 8192);
+        assert_eq!(LINEAR_TO_SRGB.len(), LINEAR_MAX as usize + 1);
+        for t in [&SRGB_TO_LINEAR, &GAMMA_TO_LINEAR] {
+            assert_eq!((t[0], t[255]), (0, LINEAR_MAX));
+            assert!(t.windows(2).all(|w| w[0] <= w[1]));
+        }
+    }
+
+    #[test]
+    fn matches_float_path() {
+        let src = all_codes();
+        for from in SPACES {
+            for to in SPACES {
+                let mut dst = vec![0; src.len()];
+                convert_bytes_fixed(&src, &mut dst, from, to).unwrap();
+                let mut img = Image::from_bytes(&src, (256, 1), from);
+                img.to_color(to);
+                let want = img.to_bytes();
+                for (i, (d, w)) in dst.iter().zip(&want).enumerate() {
+                    if i % 4 == 3 {
+                        assert_eq!(d, &src[i]);
+                    } else {
+                        assert!(d.abs_diff(*w) <= 1, "{from:?} -> {to:?} at {i}: {d} vs {w}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_round_trips_exactly() {
+        let src = all_codes();
+        let mut linear = vec![0; src.len()];
+        let mut back = vec![0; src.len()];
+        // 8 bit linear loses dark codes, the 13 bit tables don't
+        for c in 0..=255u8 {
+            assert_eq!(LINEAR_TO_SRGB[SRGB_TO_LINEAR[c as usize] as usize], c);
+        }
+        convert_bytes_fixed(&src, &mut linear, ColorSpace::sRGB, ColorSpace::sRGBLinear).unwrap();
+        convert_bytes_fixed(&linear, &mut back, ColorSpace::sRGBLinear, ColorSpace::sRGB).unwrap();
+        assert_ne!(back, src);
+        convert_bytes_fixed(&src, &mut back, ColorSpace::AsIs, ColorSpace::sRGB).unwrap();
+        assert_eq!(back, src);
+    }
+
+    #[test]
+    fn errors() {
+        let src = all_codes();
+        let mut short = vec![0; src.len() - 4];
+        assert_eq!(
+            convert_bytes_fixed(&src, &mut short, ColorSpace::sRGB, ColorSpace::sRGBLinear),
+            Err(ImageError::DimensionMismatch)
+        );
+        let mut odd = [0; 3];
+        assert_eq!(
+            convert_bytes_fixed(&[0; 3], &mut odd, ColorSpace::sRGB, ColorSpace::sRGBLinear),
+            Err(ImageError::DimensionMismatch)
+        );
+        let mut dst = vec![0; src.len()];
+        assert_eq!(
+            convert_bytes_fixed(&src, &mut dst, ColorSpace::sRGB, ColorSpace::DisplayP3),
+            Err(ImageError::Unsupported)
+        );
+        assert_eq!(
+            convert_bytes_fixed(&src, &mut dst, ColorSpace::DisplayP3, ColorSpace::sRGB),
+            Err(ImageError::Unsupported)
+        );
+    }
+}
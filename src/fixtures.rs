@@ -0,0 +1,74 @@
+//! Images and comparisons shared by the unit tests
+use alloc::{vec, vec::Vec};
+
+use crate::{ColorSpace, Image, ResXY, WorkPixel, F32};
+
+/// Deterministic noise in `0..1`, a step of a 64 bit LCG
+pub(crate) fn noise(seed: &mut u64) -> f32 {
+    *seed = seed
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+    (*seed >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Every pixel `p`, in sRGB
+pub(crate) fn solid(res: ResXY, p: WorkPixel) -> Image {
+    let img = Image::from_bytes(&[], (0, 0), ColorSpace::sRGB);
+    img.derive(vec![p; res.0 as usize * res.1 as usize], res)
+}
+
+/// Every byte counting up, wrapping, so neighbors differ
+pub(crate) fn ramp(res: ResXY) -> Image {
+    let data: Vec<u8> = (0..res.0 * res.1 * 4).map(|i| (i * 7) as u8).collect();
+    Image::from_bytes(&data, res, ColorSpace::sRGB)
+}
+
+/// Something like a photo, smooth shapes with a little grain, opaque
+pub(crate) fn photo(res: ResXY) -> Image {
+    let (w, h) = (res.0 as f32, res.1 as f32);
+    let mut seed = 1;
+    let mut data = Vec::with_capacity(res.0 as usize * res.1 as usize * 4);
+    for y in 0..res.1 {
+        for x in 0..res.0 {
+            let (u, v) = (x as f32 / w, y as f32 / h);
+            let bump = (u * 7.).sin() * (v * 5.).cos() * 0.25;
+            let grain = (noise(&mut seed) - 0.5) * 0.04;
+            let c = |base: f32| ((base + bump + grain).clamp(0., 1.) * 255.).round() as u8;
+            data.extend([
+                c(0.3 + 0.4 * u),
+                c(0.5 - 0.2 * v),
+                c(0.2 + 0.3 * u * v),
+                255,
+            ]);
+        }
+    }
+    Image::from_bytes(&data, res, ColorSpace::sRGB)
+}
+
+/// Largest difference of any channel of any pixel
+pub(crate) fn max_diff(a: &[WorkPixel], b: &[WorkPixel]) -> f32 {
+    assert_eq!(a.len(), b.len(), "Different sizes");
+    a.iter()
+        .zip(b)
+        .flat_map(|(a, b)| (0..4).map(move |c| (a[c] - b[c]).abs()))
+        .fold(0., f32::max)
+}
+
+/// Peak signal to noise ratio of the 8 bit values, in dB
+pub(crate) fn psnr(a: &Image, b: &Image) -> f32 {
+    let (a, b) = (a.to_bytes(), b.to_bytes());
+    assert_eq!(a.len(), b.len(), "Different sizes");
+    let se: f64 = a
+        .iter()
+        .zip(&b)
+        .map(|(a, b)| {
+            let d = *a as f64 - *b as f64;
+            d * d
+        })
+        .sum();
+    let mse = se / a.len() as f64;
+    if mse == 0. {
+        return f32::INFINITY;
+    }
+    (10. * libm::log10(255. * 255. / mse)) as f32
+}
@@ -0,0 +1,296 @@
+//! Bitmap fonts
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::{ImageError, ResXY};
+
+/// Tab stops are every this many character cells
+const TAB_CELLS: u32 = 4;
+
+/// How lines of text line up, see [`TextLayout`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// How to lay out text, for
+/// [`FramebufferTarget::draw_text_with`][crate::FramebufferTarget::draw_text_with]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextLayout {
+    /// Each glyph pixel is drawn as a square this big, at least one
+    pub scale: u32,
+    /// Where each line goes within the width of the block
+    pub align: TextAlign,
+    /// Wrap lines to fit this many pixels, see [`BitmapFont::measure`]
+    pub max_width: Option<u32>,
+}
+
+impl Default for TextLayout {
+    fn default() -> Self {
+        Self {
+            scale: 1,
+            align: TextAlign::Left,
+            max_width: None,
+        }
+    }
+}
+
+/// Size and lines of a block of text, from [`BitmapFont::measure`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextMetrics {
+    /// Width of the longest line, in pixels
+    pub width: u32,
+    /// Height of all the lines, in pixels
+    pub height: u32,
+    /// Byte range of each line in the text, without the whitespace wrapped
+    /// at
+    pub lines: Vec<Range<usize>>,
+}
+
+/// The column after `c` at `col`, counting cells
+pub(crate) fn advance(col: u32, c: char) -> u32 {
+    match c {
+        '\t' => (col / TAB_CELLS + 1) * TAB_CELLS,
+        _ => col + 1,
+    }
+}
+
+/// Width of `line` in cells
+pub(crate) fn line_cells(line: &str) -> u32 {
+    line.chars().fold(0, advance)
+}
+
+/// Wrap `para`, which starts at byte `base` of the text, to `max` cells,
+/// pushing its lines to `out`
+fn wrap(para: &str, base: usize, max: Option<u32>, out: &mut Vec<Range<usize>>) {
+    let chars: Vec<(usize, char)> = para.char_indices().collect();
+    let byte = |i: usize| base + chars.get(i).map_or(para.len(), |c| c.0);
+    let mut start = 0;
+    loop {
+        let (mut col, mut space, mut end) = (0, None, chars.len());
+        for (j, &(_, c)) in chars.iter().enumerate().skip(start) {
+            let next = advance(col, c);
+            // Always take one character, so long ones still make progress
+            if max.is_some_and(|max| next > max) && j > start {
+                end = match space {
+                    Some(s) if !c.is_whitespace() => s,
+                    _ => j,
+                };
+                break;
+            }
+            if c.is_whitespace() && j > start {
+                space = Some(j);
+            }
+            col = next;
+        }
+        let mut trimmed = end;
+        while trimmed > start && end < chars.len() && chars[trimmed - 1].1.is_whitespace() {
+            trimmed -= 1;
+        }
+        out.push(byte(start)..byte(trimmed));
+        if end == chars.len() {
+            return;
+        }
+        start = end;
+        while start < chars.len() && chars[start].1.is_whitespace() {
+            start += 1;
+        }
+        if start == chars.len() {
+            return;
+        }
+    }
+}
+
+/// A fixed size 1 bit font, over user supplied glyph data
+///
+/// Glyphs are stored one after another for consecutive characters starting
+/// at `first`. Each glyph is `height` rows, each row padded to whole bytes,
+/// most significant bit leftmost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitmapFont<'a> {
+    data: &'a [u8],
+    glyph: ResXY,
+    first: char,
+}
+
+impl<'a> BitmapFont<'a> {
+    /// Create a font from `data` with glyphs of `glyph_size`, the first being
+    /// for `first`
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `glyph_size` is zero
+    /// - [`ImageError::DimensionMismatch`] if `data` isn't whole glyphs
+    pub fn new(data: &'a [u8], glyph_size: ResXY, first: char) -> Result<Self, ImageError> {
+        if glyph_size.0 == 0 || glyph_size.1 == 0 {
+            return Err(ImageError::InvalidArgument);
+        }
+        let font = Self {
+            data,
+            glyph: glyph_size,
+            first,
+        };
+        if !data.len().is_multiple_of(font.glyph_bytes()) {
+            return Err(ImageError::DimensionMismatch);
+        }
+        Ok(font)
+    }
+
+    /// Size of each glyph, in pixels
+    pub fn glyph_size(&self) -> ResXY {
+        self.glyph
+    }
+
+    fn row_bytes(&self) -> usize {
+        self.glyph.0.div_ceil(8) as usize
+    }
+
+    fn glyph_bytes(&self) -> usize {
+        self.row_bytes() * self.glyph.1 as usize
+    }
+
+    /// Glyph data for `c`, or `None` if the font doesn't have it
+    fn glyph(&self, c: char) -> Option<&'a [u8]> {
+        let i = (c as u32).checked_sub(self.first as u32)? as usize;
+        let len = self.glyph_bytes();
+        self.data.get(i * len..(i + 1) * len)
+    }
+
+    /// Lay out `text` with glyphs `scale` times their size, wrapping lines
+    /// wider than `max_width` pixels
+    ///
+    /// `'\n'` starts a new line, and tabs go to the next multiple of four
+    /// characters. Lines wrap at the last whitespace that fits, which is
+    /// dropped, or mid word if there is none. Every line, even an empty
+    /// one, is a glyph tall. Drawing with the same settings uses exactly
+    /// this layout.
+    pub fn measure(&self, text: &str, scale: u32, max_width: Option<u32>) -> TextMetrics {
+        let (cw, ch) = (self.glyph.0 * scale.max(1), self.glyph.1 * scale.max(1));
+        let max = max_width.map(|w| w / cw);
+        let mut lines = Vec::new();
+        let mut base = 0;
+        for para in text.split('\n') {
+            wrap(para, base, max, &mut lines);
+            base += para.len() + 1;
+        }
+        let cells = lines
+            .iter()
+            .map(|l| line_cells(&text[l.clone()]))
+            .max()
+            .unwrap_or(0);
+        TextMetrics {
+            width: cells.saturating_mul(cw),
+            height: (lines.len() as u32).saturating_mul(ch),
+            lines,
+        }
+    }
+
+    /// Whether pixel `(x, y)` of the glyph for `c` is set
+    ///
+    /// Characters the font doesn't have are blank.
+    pub fn pixel(&self, c: char, (x, y): (u32, u32)) -> bool {
+        if x >= self.glyph.0 || y >= self.glyph.1 {
+            return false;
+        }
+        self.glyph(c).is_some_and(|g| {
+            let byte = g[y as usize * self.row_bytes() + x as usize / 8];
+            byte & (0x80 >> (x % 8)) != 0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    /// Solid 3x4 glyphs for `'!'` to `'~'`, so space is blank
+    const BLOCKS: [u8; 94 * 4] = [0b1110_0000; 94 * 4];
+
+    fn blocks() -> BitmapFont<'static> {
+        BitmapFont::new(&BLOCKS, (3, 4), '!').unwrap()
+    }
+
+    fn lines<'a>(text: &'a str, m: &TextMetrics) -> Vec<&'a str> {
+        m.lines.iter().map(|l| &text[l.clone()]).collect()
+    }
+
+    #[test]
+    fn measure_single_lines() {
+        let font = blocks();
+        let m = font.measure("hello", 1, None);
+        assert_eq!(
+            (m.width, m.height, lines("hello", &m)),
+            (15, 4, vec!["hello"])
+        );
+        let m = font.measure("hello", 2, None);
+        assert_eq!((m.width, m.height), (30, 8));
+        let m = font.measure("", 1, None);
+        assert_eq!((m.width, m.height, lines("", &m)), (0, 4, vec![""]));
+    }
+
+    #[test]
+    fn measure_newlines_and_tabs() {
+        let font = blocks();
+        let text = "ab\n\ncdef\n";
+        let m = font.measure(text, 1, None);
+        assert_eq!(lines(text, &m), ["ab", "", "cdef", ""]);
+        assert_eq!((m.width, m.height), (12, 16));
+        // Tab stops every four cells
+        for (text, cells) in [("\t", 4), ("a\tb", 5), ("abcd\tb", 9), ("abc\t\t", 8)] {
+            assert_eq!(font.measure(text, 1, None).width, cells * 3, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn measure_wraps() {
+        let font = blocks();
+        // Six cells
+        for (text, want) in [
+            ("hello world", &["hello", "world"][..]),
+            ("ab cd ef gh", &["ab cd", "ef gh"]),
+            ("ab  cd", &["ab  cd"]),
+            ("ab   cd", &["ab", "cd"]),
+            ("abcdefghijklm", &["abcdef", "ghijkl", "m"]),
+            ("a bcdefghij", &["a", "bcdefg", "hij"]),
+            ("abcdef ghi", &["abcdef", "ghi"]),
+            ("a\tbcde", &["a", "bcde"]),
+            ("hi\nhello world", &["hi", "hello", "world"]),
+        ] {
+            let m = font.measure(text, 1, Some(18));
+            assert_eq!(lines(text, &m), want, "{text:?}");
+            let widest = want.iter().map(|l| line_cells(l)).max().unwrap();
+            assert_eq!(m.width, widest * 3);
+            assert_eq!(m.height, want.len() as u32 * 4);
+            assert!(m.width <= 18);
+        }
+        // Narrower than a glyph still takes one per line
+        let m = font.measure("abc", 2, Some(4));
+        assert_eq!(lines("abc", &m), ["a", "b", "c"]);
+        assert_eq!((m.width, m.height), (6, 24));
+        // Scale counts too
+        let m = font.measure("hello world", 2, Some(36));
+        assert_eq!(lines("hello world", &m), ["hello", "world"]);
+    }
+
+    #[test]
+    fn font_errors() {
+        assert_eq!(
+            BitmapFont::new(&BLOCKS, (0, 4), '!').err(),
+            Some(ImageError::InvalidArgument)
+        );
+        assert_eq!(
+            BitmapFont::new(&BLOCKS[1..], (3, 4), '!').err(),
+            Some(ImageError::DimensionMismatch)
+        );
+        let font = blocks();
+        assert!(font.pixel('a', (2, 3)));
+        assert!(!font.pixel('a', (3, 0)));
+        assert!(!font.pixel(' ', (0, 0)));
+        assert!(!font.pixel('é', (0, 0)));
+    }
+}
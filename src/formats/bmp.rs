@@ -0,0 +1,327 @@
+//! Windows BMP
+use alloc::{vec, vec::Vec};
+use core::ops::ControlFlow;
+
+use super::{
+    codec::{bmp_header, bmp_rows, BmpHeader},
+    decode_all, decode_all_with, DecodeMode, DecodeWarnings, FileFormat, ImageInfo, ProbeInfo,
+    RowSink, WriteError,
+};
+use crate::{ColorSpace, Image, ImageError, PixelFormat, RawPixel, ResXY, WorkPixel};
+
+/// Size of the file header plus `BITMAPV4HEADER`
+const HEADER: usize = 14 + 108;
+
+/// `LCS_sRGB`
+const SRGB: u32 = u32::from_be_bytes(*b"sRGB");
+
+/// Inches per meter
+const INCH: f32 = 39.370_08;
+
+/// Encode `img` as a 32 bit BMP with alpha
+///
+/// Pixels are written as stored, convert to [`ColorSpace::sRGB`] first for
+/// the file to look right elsewhere. The resolution fields come from
+/// [`Metadata::dpi`], defaulting to 72 DPI.
+///
+/// [`ColorSpace::sRGB`]: crate::ColorSpace::sRGB
+/// [`Metadata::dpi`]: crate::Metadata::dpi
+pub fn encode(img: &Image) -> Vec<u8> {
+    let pixels = img.to_raw(PixelFormat::Bgra8888);
+    let mut out = Vec::with_capacity(HEADER + pixels.len());
+    out.extend_from_slice(&file_header(img.res, img.metadata().dpi));
+    out.extend_from_slice(&pixels);
+    out
+}
+
+/// The headers of a 32 bit top down BMP of `res` at `dpi`, 72 by default
+fn file_header((w, h): ResXY, dpi: Option<(f32, f32)>) -> Vec<u8> {
+    let pixels = w as usize * h as usize * 4;
+    let size = HEADER + pixels;
+    let (dpi_x, dpi_y) = dpi.unwrap_or((72., 72.));
+    let ppm = |dpi: f32| (dpi * INCH + 0.5) as u32;
+
+    let mut out = Vec::with_capacity(HEADER);
+    let u16 = |out: &mut Vec<u8>, v: u16| out.extend_from_slice(&v.to_le_bytes());
+    let u32 = |out: &mut Vec<u8>, v: u32| out.extend_from_slice(&v.to_le_bytes());
+    // File header
+    out.extend_from_slice(b"BM");
+    u32(&mut out, size as u32);
+    u32(&mut out, 0);
+    u32(&mut out, HEADER as u32);
+    // BITMAPV4HEADER
+    u32(&mut out, 108);
+    u32(&mut out, w);
+    // Negative for top down rows
+    u32(&mut out, (h as i32).wrapping_neg() as u32);
+    u16(&mut out, 1);
+    u16(&mut out, 32);
+    // BI_BITFIELDS
+    u32(&mut out, 3);
+    u32(&mut out, pixels as u32);
+    u32(&mut out, ppm(dpi_x));
+    u32(&mut out, ppm(dpi_y));
+    u32(&mut out, 0);
+    u32(&mut out, 0);
+    for mask in [0xff0000, 0xff00, 0xff, 0xff000000] {
+        u32(&mut out, mask);
+    }
+    u32(&mut out, SRGB);
+    // Endpoints and gamma, unused for sRGB
+    out.extend_from_slice(&[0; 48]);
+    debug_assert_eq!(out.len(), HEADER);
+    out
+}
+
+/// Writes a BMP a row at a time, like [`encode`], to a sink that takes
+/// each piece of the file in order
+///
+/// Only one encoded row is held at a time. Rows are straight alpha, and
+/// written as stored, as BMP has nowhere to put a color space, at 72 DPI.
+pub struct RowWriter<F, E> {
+    rows: RowSink<F, E>,
+}
+
+impl<F: FnMut(&[u8]) -> Result<(), E>, E> RowWriter<F, E> {
+    /// Start a BMP of `res`, sending the headers to `sink` right away
+    ///
+    /// # Errors
+    ///
+    /// - [`WriteError::Sink`] if `sink` fails
+    pub fn new(res: ResXY, sink: F) -> Result<Self, WriteError<E>> {
+        let mut rows = RowSink::new(res, sink);
+        rows.send(&file_header(res, None))?;
+        Ok(Self { rows })
+    }
+
+    /// Write the next row, top to bottom
+    ///
+    /// # Errors
+    ///
+    /// - [`WriteError::Image`] if `row` is the wrong width, or every row
+    ///   has been written
+    /// - [`WriteError::Sink`] if the sink fails
+    pub fn write_row(&mut self, row: &[WorkPixel]) -> Result<(), WriteError<E>> {
+        self.rows.send_row(row, PixelFormat::Bgra8888)
+    }
+
+    /// End the file, which BMP needs nothing more for
+    ///
+    /// # Errors
+    ///
+    /// - [`WriteError::Image`] if not every row was written
+    pub fn finish(self) -> Result<(), WriteError<E>> {
+        self.rows.finish()
+    }
+}
+
+fn info(header: &BmpHeader) -> ImageInfo {
+    ImageInfo {
+        res: header.res,
+        color: ColorSpace::sRGB,
+    }
+}
+
+/// Read the header, see [`probe`](super::probe)
+pub(super) fn probe(data: &[u8]) -> Result<ProbeInfo, ImageError> {
+    let h = bmp_header(data)?;
+    ProbeInfo::new(FileFormat::Bmp, info(&h), 8, if h.alpha { 4 } else { 3 })
+}
+
+/// Decode an uncompressed 24 or 32 bit BMP, calling `on_row` with each row
+/// and its index
+///
+/// Rows are delivered top to bottom, even for bottom up files. Returning
+/// [`ControlFlow::Break`] stops decoding early, which isn't an error. The
+/// color space is always [`ColorSpace::sRGB`].
+///
+/// 32 bit files have alpha if they're `BI_BITFIELDS` with an alpha mask,
+/// like the ones from [`encode`].
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidData`] if `data` isn't a BMP, or is truncated
+/// - [`ImageError::Unsupported`] for other bit depths, compression, or
+///   channel masks
+pub fn decode_rows(
+    data: &[u8],
+    on_row: impl FnMut(u32, &[RawPixel]) -> ControlFlow<()>,
+) -> Result<ImageInfo, ImageError> {
+    rows(data, false, on_row)
+}
+
+/// [`decode_rows`], skipping the rows past the end of `data` if `salvage`,
+/// and only then failing
+fn rows(
+    data: &[u8],
+    salvage: bool,
+    on_row: impl FnMut(u32, &[RawPixel]) -> ControlFlow<()>,
+) -> Result<ImageInfo, ImageError> {
+    Ok(info(&bmp_rows(data, salvage, on_row)?))
+}
+
+/// Decode a whole BMP, see [`decode_rows`]
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidData`] if `data` isn't a BMP, or is truncated
+/// - [`ImageError::Unsupported`] for other bit depths, compression, or
+///   channel masks
+pub fn decode(data: &[u8]) -> Result<Image, ImageError> {
+    decode_all(data, |d, f| decode_rows(d, f))
+}
+
+/// Decode a whole BMP, or with [`DecodeMode::Salvage`] as much of a
+/// damaged one as possible
+///
+/// Files stored bottom up keep their bottom rows, which come first.
+///
+/// # Errors
+///
+/// - Like [`decode`], unless salvaging decoded at least one row
+/// - [`ImageError::OutOfMemory`] if salvaging and the size in the header
+///   can't be allocated
+pub fn decode_with(data: &[u8], mode: DecodeMode) -> Result<(Image, DecodeWarnings), ImageError> {
+    decode_all_with(data, mode, probe, |d, salvage, f| rows(d, salvage, f))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::formats::{codec::le32, DecodeWarning};
+
+    fn gradient(res: ResXY) -> Image {
+        let data: Vec<u8> = (0..res.0 * res.1 * 4).map(|i| (i * 7) as u8).collect();
+        Image::from_bytes(&data, res, ColorSpace::sRGB)
+    }
+
+    #[test]
+    fn salvage_fills_missing_rows() {
+        let img = gradient((5, 4));
+        let file = encode(&img);
+        // Top down, so cutting the end loses the bottom row
+        let cut = &file[..file.len() - 5 * 4];
+        assert_eq!(decode(cut).err(), Some(ImageError::InvalidData));
+
+        let fill = [1, 2, 3, 4];
+        let (got, warnings) = decode_with(cut, DecodeMode::Salvage { fill }).unwrap();
+        assert!(matches!(
+            warnings.warnings(),
+            [DecodeWarning::MissingRows { rows: 1, .. }]
+        ));
+        let want = img.to_bytes();
+        let got = got.to_bytes();
+        let last = 3 * 5 * 4;
+        assert_eq!(got[..last], want[..last]);
+        assert!(got[last..].chunks_exact(4).all(|p| p == fill));
+    }
+
+    #[test]
+    fn salvage_bottom_up_fills_the_top() {
+        let img = gradient((5, 4));
+        let mut file = encode(&img);
+        let offset = le32(&file[10..]) as usize;
+        file[22..26].copy_from_slice(&4i32.to_le_bytes());
+        let rows: Vec<Vec<u8>> = file[offset..]
+            .chunks(5 * 4)
+            .rev()
+            .map(<[u8]>::to_vec)
+            .collect();
+        file[offset..].copy_from_slice(&rows.concat());
+        // Bottom up, so cutting the end loses the top row
+        let cut = &file[..file.len() - 5 * 4];
+        let fill = [9, 8, 7, 6];
+        let (got, warnings) = decode_with(cut, DecodeMode::Salvage { fill }).unwrap();
+        assert!(matches!(
+            warnings.warnings(),
+            [DecodeWarning::MissingRows {
+                rows: 1,
+                error: ImageError::InvalidData
+            }]
+        ));
+        let (want, got) = (img.to_bytes(), got.to_bytes());
+        assert!(got[..5 * 4].chunks_exact(4).all(|p| p == fill));
+        assert_eq!(got[5 * 4..], want[5 * 4..]);
+    }
+
+    #[test]
+    fn salvage_rejects_huge_header() {
+        let mut file = encode(&gradient((4, 4)));
+        file[18..22].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+        file[22..26].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+        let fill = [0; 4];
+        assert_eq!(decode(&file).err(), Some(ImageError::InvalidData));
+        assert_eq!(
+            decode_with(&file, DecodeMode::Salvage { fill }).err(),
+            Some(ImageError::InvalidData)
+        );
+    }
+
+    #[test]
+    fn resolution_from_dpi() {
+        let fields = |file: &[u8]| (le32(&file[38..]), le32(&file[42..]));
+        let mut img = gradient((3, 2));
+        // 72 DPI by default
+        assert_eq!(fields(&encode(&img)), (2835, 2835));
+        img.metadata_mut().dpi = Some((300., 96.));
+        assert_eq!(fields(&encode(&img)), (11811, 3780));
+        // And it's still a BMP that decodes
+        assert_eq!(decode(&encode(&img)).unwrap().to_bytes(), img.to_bytes());
+    }
+
+    /// Rows from [`decode_rows`], with their indices, stopping after `stop`
+    fn collect(data: &[u8], stop: u32) -> (Result<ImageInfo, ImageError>, Vec<u32>, Vec<u8>) {
+        let (mut ys, mut bytes) = (Vec::new(), Vec::new());
+        let info = decode_rows(data, |y, row| {
+            ys.push(y);
+            bytes.extend(row.iter().flatten());
+            if y + 1 == stop {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        (info, ys, bytes)
+    }
+
+    #[test]
+    fn rows_match_decode() {
+        let img = gradient((5, 4));
+        let file = encode(&img);
+        let (info, ys, bytes) = collect(&file, u32::MAX);
+        let info = info.unwrap();
+        assert_eq!((info.res, info.color), ((5, 4), ColorSpace::sRGB));
+        assert_eq!(ys, [0, 1, 2, 3]);
+        assert_eq!(bytes, img.to_bytes());
+        assert_eq!(bytes, decode(&file).unwrap().to_bytes());
+    }
+
+    #[test]
+    fn bottom_up_rows_come_top_down() {
+        let img = gradient((5, 4));
+        let mut file = encode(&img);
+        let offset = le32(&file[10..]) as usize;
+        file[22..26].copy_from_slice(&4i32.to_le_bytes());
+        let rows: Vec<Vec<u8>> = file[offset..]
+            .chunks(5 * 4)
+            .rev()
+            .map(<[u8]>::to_vec)
+            .collect();
+        file[offset..].copy_from_slice(&rows.concat());
+        let (info, ys, bytes) = collect(&file, u32::MAX);
+        assert!(info.is_ok());
+        assert_eq!(ys, [0, 1, 2, 3]);
+        assert_eq!(bytes, img.to_bytes());
+    }
+
+    #[test]
+    fn rows_break_early() {
+        let img = gradient((5, 4));
+        let (info, ys, bytes) = collect(&encode(&img), 2);
+        assert_eq!(info.unwrap().res, (5, 4));
+        assert_eq!(ys, [0, 1]);
+        assert_eq!(bytes, img.to_bytes()[..2 * 5 * 4]);
+    }
+}
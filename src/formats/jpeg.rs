@@ -0,0 +1,766 @@
+//! Baseline JPEG, with the `jpeg` feature
+//!
+//! Only sequential Huffman coded files with 8 bit samples and all components
+//! in one scan are supported, which is nearly every camera and JFIF file.
+//! Progressive, arithmetic coded, lossless, and CMYK files are
+//! [`ImageError::Unsupported`].
+use alloc::{vec, vec::Vec};
+use core::{f32::consts::PI, ops::ControlFlow};
+
+use super::{
+    decode_all, decode_all_with, DecodeMode, DecodeWarnings, FileFormat, ImageInfo, ProbeInfo,
+};
+use crate::{ColorSpace, Image, ImageError, RawPixel, F32};
+
+/// Natural order index of each zigzag position
+const ZIGZAG: [u8; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Reads big endian values and segments, never past the end
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Result<u8, ImageError> {
+        let b = *self.data.get(self.pos).ok_or(ImageError::InvalidData)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn u16(&mut self) -> Result<u16, ImageError> {
+        Ok(u16::from_be_bytes([self.u8()?, self.u8()?]))
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], ImageError> {
+        let b = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or(ImageError::InvalidData)?;
+        self.pos += n;
+        Ok(b)
+    }
+
+    /// The body of a segment, after its length
+    fn segment(&mut self) -> Result<Reader<'a>, ImageError> {
+        let len = self.u16()? as usize;
+        let data = self.bytes(len.checked_sub(2).ok_or(ImageError::InvalidData)?)?;
+        Ok(Reader { data, pos: 0 })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+/// Canonical Huffman table
+struct Huffman {
+    /// Largest code of each length, or -1
+    max_code: [i32; 17],
+    /// Offset from a code of each length to its index in `values`
+    offset: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl Huffman {
+    fn new(counts: &[u8], values: &[u8]) -> Self {
+        let (mut max_code, mut offset) = ([-1; 17], [0; 17]);
+        let (mut code, mut k) = (0i32, 0i32);
+        for len in 1..=16 {
+            let n = counts[len - 1] as i32;
+            offset[len] = k - code;
+            code += n;
+            k += n;
+            if n > 0 {
+                max_code[len] = code - 1;
+            }
+            code <<= 1;
+        }
+        Self {
+            max_code,
+            offset,
+            values: values.into(),
+        }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u8, ImageError> {
+        let mut code = 0;
+        for len in 1..=16 {
+            code = (code << 1) | bits.bit()? as i32;
+            if code <= self.max_code[len] {
+                let i = (code + self.offset[len]) as usize;
+                return self.values.get(i).copied().ok_or(ImageError::InvalidData);
+            }
+        }
+        Err(ImageError::InvalidData)
+    }
+}
+
+/// Entropy coded data, with byte stuffing removed
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    acc: u32,
+    n: u32,
+    /// Hit a marker, which reads as zeros like libjpeg
+    marker: bool,
+}
+
+impl Bits<'_> {
+    fn bit(&mut self) -> Result<u32, ImageError> {
+        if self.n == 0 {
+            let b = match (self.marker, self.data.get(self.pos)) {
+                (true, _) => 0,
+                (_, Some(0xff)) => match self.data.get(self.pos + 1) {
+                    Some(0) => {
+                        self.pos += 2;
+                        0xff
+                    }
+                    Some(_) => {
+                        self.marker = true;
+                        0
+                    }
+                    None => return Err(ImageError::InvalidData),
+                },
+                (_, Some(b)) => {
+                    self.pos += 1;
+                    *b
+                }
+                // Truncated
+                (_, None) => return Err(ImageError::InvalidData),
+            };
+            self.acc = b as u32;
+            self.n = 8;
+        }
+        self.n -= 1;
+        Ok((self.acc >> self.n) & 1)
+    }
+
+    /// `count` bits as a signed coefficient, F.2.2.1
+    fn receive_extend(&mut self, count: u8) -> Result<i32, ImageError> {
+        if count == 0 {
+            return Ok(0);
+        }
+        if count > 16 {
+            return Err(ImageError::InvalidData);
+        }
+        let mut v = 0;
+        for _ in 0..count {
+            v = (v << 1) | self.bit()? as i32;
+        }
+        Ok(if v < 1 << (count - 1) {
+            v - (1 << count) + 1
+        } else {
+            v
+        })
+    }
+
+    /// Skip to after the next restart marker
+    fn restart(&mut self) -> Result<(), ImageError> {
+        self.n = 0;
+        self.marker = false;
+        while self.data.get(self.pos) == Some(&0xff) {
+            self.pos += 1;
+        }
+        match self.data.get(self.pos) {
+            Some(0xd0..=0xd7) => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(ImageError::InvalidData),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quant: usize,
+    dc: usize,
+    ac: usize,
+}
+
+/// `cos` terms of the inverse DCT, `[x][u]`, with the normalization
+fn idct_table() -> [[f32; 8]; 8] {
+    let mut t = [[0.; 8]; 8];
+    for (x, row) in t.iter_mut().enumerate() {
+        for (u, c) in row.iter_mut().enumerate() {
+            let scale = if u == 0 { 0.5f32.sqrt() } else { 1. };
+            *c = scale / 2. * (((2 * x + 1) * u) as f32 * PI / 16.).cos();
+        }
+    }
+    t
+}
+
+/// Inverse DCT of `coef`, in natural order, into 8 bit samples at `out` with
+/// rows `stride` apart
+fn idct(coef: &[i32; 64], table: &[[f32; 8]; 8], out: &mut [u8], stride: usize) {
+    let store = |v: f32| (v + 128.).round().clamp(0., 255.) as u8;
+    if coef[1..].iter().all(|c| *c == 0) {
+        let v = store(coef[0] as f32 / 8.);
+        for row in out.chunks_mut(stride).take(8) {
+            row[..8].fill(v);
+        }
+        return;
+    }
+    // Rows, then columns
+    let mut tmp = [0f32; 64];
+    for v in 0..8 {
+        for x in 0..8 {
+            tmp[v * 8 + x] = (0..8).map(|u| coef[v * 8 + u] as f32 * table[x][u]).sum();
+        }
+    }
+    for (y, row) in out.chunks_mut(stride).take(8).enumerate() {
+        for (x, o) in row[..8].iter_mut().enumerate() {
+            *o = store((0..8).map(|v| tmp[v * 8 + x] * table[y][v]).sum());
+        }
+    }
+}
+
+/// Frame and table state gathered from the header segments
+#[derive(Default)]
+struct Decoder {
+    quant: [Option<[u16; 64]>; 4],
+    dc: [Option<Huffman>; 4],
+    ac: [Option<Huffman>; 4],
+    res: (u32, u32),
+    components: Vec<Component>,
+    restart: u16,
+    /// From an Adobe segment, whether 3 components are YCbCr
+    adobe_ycc: Option<bool>,
+}
+
+impl Decoder {
+    fn quant_tables(&mut self, mut s: Reader) -> Result<(), ImageError> {
+        while !s.is_empty() {
+            let pq_tq = s.u8()?;
+            let id = (pq_tq & 15) as usize;
+            let mut table = [0; 64];
+            for q in &mut table {
+                *q = match pq_tq >> 4 {
+                    0 => s.u8()? as u16,
+                    1 => s.u16()?,
+                    _ => return Err(ImageError::InvalidData),
+                };
+            }
+            *self.quant.get_mut(id).ok_or(ImageError::InvalidData)? = Some(table);
+        }
+        Ok(())
+    }
+
+    fn huffman_tables(&mut self, mut s: Reader) -> Result<(), ImageError> {
+        while !s.is_empty() {
+            let tc_th = s.u8()?;
+            let id = (tc_th & 15) as usize;
+            let counts = s.bytes(16)?;
+            let total = counts.iter().map(|n| *n as usize).sum();
+            if total > 256 {
+                return Err(ImageError::InvalidData);
+            }
+            let table = Some(Huffman::new(counts, s.bytes(total)?));
+            let tables = match tc_th >> 4 {
+                0 => &mut self.dc,
+                1 => &mut self.ac,
+                _ => return Err(ImageError::InvalidData),
+            };
+            *tables.get_mut(id).ok_or(ImageError::InvalidData)? = table;
+        }
+        Ok(())
+    }
+
+    fn frame(&mut self, mut s: Reader) -> Result<(), ImageError> {
+        if s.u8()? != 8 {
+            return Err(ImageError::Unsupported);
+        }
+        let h = s.u16()? as u32;
+        let w = s.u16()? as u32;
+        // Zero height means a DNL segment later, which nothing uses
+        if w == 0 || h == 0 {
+            return Err(ImageError::Unsupported);
+        }
+        let n = s.u8()?;
+        if n != 1 && n != 3 {
+            return Err(ImageError::Unsupported);
+        }
+        self.res = (w, h);
+        self.components.clear();
+        for _ in 0..n {
+            let id = s.u8()?;
+            let hv = s.u8()?;
+            let quant = s.u8()? as usize;
+            let (h, v) = ((hv >> 4) as usize, (hv & 15) as usize);
+            if !(1..=4).contains(&h) || !(1..=4).contains(&v) || quant > 3 {
+                return Err(ImageError::InvalidData);
+            }
+            self.components.push(Component {
+                id,
+                h,
+                v,
+                quant,
+                dc: 0,
+                ac: 0,
+            });
+        }
+        if n == 1 {
+            // A single component is never interleaved, its MCU is one block
+            self.components[0].h = 1;
+            self.components[0].v = 1;
+        }
+        let h_max = self.components.iter().map(|c| c.h).max().unwrap_or(1);
+        let v_max = self.components.iter().map(|c| c.v).max().unwrap_or(1);
+        if self
+            .components
+            .iter()
+            .any(|c| h_max % c.h != 0 || v_max % c.v != 0)
+        {
+            return Err(ImageError::Unsupported);
+        }
+        Ok(())
+    }
+
+    /// Whether the 3 components are YCbCr rather than RGB
+    fn is_ycc(&self) -> bool {
+        let rgb = self.components.iter().map(|c| c.id).eq(*b"RGB");
+        self.adobe_ycc.unwrap_or(!rgb)
+    }
+
+    /// Decode the scan starting at `r`, calling `on_row` with each row
+    fn scan(
+        &mut self,
+        mut s: Reader,
+        data: &[u8],
+        on_row: &mut impl FnMut(u32, &[RawPixel]) -> ControlFlow<()>,
+    ) -> Result<(), ImageError> {
+        let n = s.u8()? as usize;
+        if n != self.components.len() {
+            // Sequential files can split components over scans, but that
+            // needs the whole image buffered
+            return Err(ImageError::Unsupported);
+        }
+        for _ in 0..n {
+            let id = s.u8()?;
+            let tables = s.u8()?;
+            let c = self
+                .components
+                .iter_mut()
+                .find(|c| c.id == id)
+                .ok_or(ImageError::InvalidData)?;
+            (c.dc, c.ac) = ((tables >> 4) as usize, (tables & 15) as usize);
+        }
+        let (ss, se, a) = (s.u8()?, s.u8()?, s.u8()?);
+        if (ss, se, a) != (0, 63, 0) {
+            return Err(ImageError::Unsupported);
+        }
+
+        let comps = self.components.clone();
+        let quant = comps
+            .iter()
+            .map(|c| self.quant[c.quant].ok_or(ImageError::InvalidData))
+            .collect::<Result<Vec<_>, _>>()?;
+        let tables = comps
+            .iter()
+            .map(|c| {
+                let dc = self.dc.get(c.dc).and_then(Option::as_ref);
+                let ac = self.ac.get(c.ac).and_then(Option::as_ref);
+                dc.zip(ac).ok_or(ImageError::InvalidData)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (w, h) = (self.res.0 as usize, self.res.1 as usize);
+        let h_max = comps.iter().map(|c| c.h).max().unwrap_or(1);
+        let v_max = comps.iter().map(|c| c.v).max().unwrap_or(1);
+        let (mcu_w, mcu_h) = (h_max * 8, v_max * 8);
+        let (mcus_x, mcus_y) = (w.div_ceil(mcu_w), h.div_ceil(mcu_h));
+        // One MCU row of each component
+        let strides: Vec<usize> = comps.iter().map(|c| mcus_x * c.h * 8).collect();
+        let mut planes: Vec<Vec<u8>> = comps
+            .iter()
+            .zip(&strides)
+            .map(|(c, s)| vec![0; s * c.v * 8])
+            .collect();
+        let mut preds = vec![0i32; n];
+        let table = idct_table();
+        let ycc = n == 3 && self.is_ycc();
+        let mut row = vec![[0, 0, 0, 255]; w];
+        let mut bits = Bits {
+            data,
+            pos: 0,
+            acc: 0,
+            n: 0,
+            marker: false,
+        };
+
+        let mut count = 0;
+        for my in 0..mcus_y {
+            for mx in 0..mcus_x {
+                if self.restart > 0 && count > 0 && count % self.restart as usize == 0 {
+                    bits.restart()?;
+                    preds.fill(0);
+                }
+                count += 1;
+                for (i, c) in comps.iter().enumerate() {
+                    let (dc, ac) = tables[i];
+                    for by in 0..c.v {
+                        for bx in 0..c.h {
+                            let coef = block(&mut bits, dc, ac, &mut preds[i], &quant[i])?;
+                            let x = (mx * c.h + bx) * 8;
+                            let out = &mut planes[i][by * 8 * strides[i] + x..];
+                            idct(&coef, &table, out, strides[i]);
+                        }
+                    }
+                }
+            }
+
+            for ly in 0..mcu_h {
+                let y = my * mcu_h + ly;
+                if y >= h {
+                    break;
+                }
+                let sample = |i: usize, x: usize| {
+                    let c = &comps[i];
+                    let (sx, sy) = (x * c.h / h_max, ly * c.v / v_max);
+                    planes[i][sy * strides[i] + sx]
+                };
+                for (x, o) in row.iter_mut().enumerate() {
+                    *o = match (n, ycc) {
+                        (1, _) => {
+                            let l = sample(0, x);
+                            [l, l, l, 255]
+                        }
+                        (_, true) => ycc_to_rgb(sample(0, x), sample(1, x), sample(2, x)),
+                        _ => [sample(0, x), sample(1, x), sample(2, x), 255],
+                    };
+                }
+                if on_row(y as u32, &row).is_break() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// JFIF full range YCbCr to RGB
+fn ycc_to_rgb(y: u8, cb: u8, cr: u8) -> RawPixel {
+    let (y, cb, cr) = (y as f32, cb as f32 - 128., cr as f32 - 128.);
+    let c = |v: f32| v.round().clamp(0., 255.) as u8;
+    [
+        c(y + 1.402 * cr),
+        c(y - 0.344_136 * cb - 0.714_136 * cr),
+        c(y + 1.772 * cb),
+        255,
+    ]
+}
+
+/// Decode one block's coefficients, dequantized and in natural order
+fn block(
+    bits: &mut Bits,
+    dc: &Huffman,
+    ac: &Huffman,
+    pred: &mut i32,
+    quant: &[u16; 64],
+) -> Result<[i32; 64], ImageError> {
+    let mut coef = [0; 64];
+    let t = dc.decode(bits)?;
+    *pred = pred.wrapping_add(bits.receive_extend(t)?);
+    coef[0] = pred.wrapping_mul(quant[0] as i32);
+    let mut k = 1;
+    while k < 64 {
+        let rs = ac.decode(bits)?;
+        let (r, s) = ((rs >> 4) as usize, rs & 15);
+        if s == 0 {
+            if r != 15 {
+                break;
+            }
+            k += 16;
+            continue;
+        }
+        k += r;
+        if k > 63 {
+            return Err(ImageError::InvalidData);
+        }
+        coef[ZIGZAG[k] as usize] = bits.receive_extend(s)? * quant[k] as i32;
+        k += 1;
+    }
+    Ok(coef)
+}
+
+/// Read the header, up to the frame, see [`probe`](super::probe)
+pub(super) fn probe(data: &[u8]) -> Result<ProbeInfo, ImageError> {
+    let mut r = Reader { data, pos: 0 };
+    if r.u16()? != 0xffd8 {
+        return Err(ImageError::InvalidData);
+    }
+    loop {
+        if r.u8()? != 0xff {
+            return Err(ImageError::InvalidData);
+        }
+        let mut marker = r.u8()?;
+        while marker == 0xff {
+            marker = r.u8()?;
+        }
+        match marker {
+            0xc0 | 0xc1 => {
+                let mut dec = Decoder::default();
+                dec.frame(r.segment()?)?;
+                let info = ImageInfo {
+                    res: dec.res,
+                    color: ColorSpace::sRGB,
+                };
+                return ProbeInfo::new(FileFormat::Jpeg, info, 8, dec.components.len() as u8);
+            }
+            0xc2..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                return Err(ImageError::Unsupported)
+            }
+            0xda | 0xd9 => return Err(ImageError::InvalidData),
+            0x01 | 0xd0..=0xd7 => {}
+            _ => {
+                r.segment()?;
+            }
+        }
+    }
+}
+
+/// Decode a baseline JPEG, calling `on_row` with each row and its index
+///
+/// Rows are produced a block row at a time, so only 8 or 16 rows of each
+/// component are buffered. Subsampled chroma is upsampled by replication.
+/// Returning [`ControlFlow::Break`] stops decoding early, which isn't an
+/// error. The color space is always [`ColorSpace::sRGB`], and alpha opaque.
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidData`] if `data` isn't a JPEG, or is corrupt or
+///   truncated
+/// - [`ImageError::Unsupported`] for progressive, arithmetic coded,
+///   lossless, 12 bit, and CMYK files, or components split over scans
+pub fn decode_rows(
+    data: &[u8],
+    mut on_row: impl FnMut(u32, &[RawPixel]) -> ControlFlow<()>,
+) -> Result<ImageInfo, ImageError> {
+    let mut r = Reader { data, pos: 0 };
+    if r.u16()? != 0xffd8 {
+        return Err(ImageError::InvalidData);
+    }
+    let mut dec = Decoder::default();
+    loop {
+        if r.u8()? != 0xff {
+            return Err(ImageError::InvalidData);
+        }
+        let mut marker = r.u8()?;
+        // Fill bytes
+        while marker == 0xff {
+            marker = r.u8()?;
+        }
+        match marker {
+            0xc0 | 0xc1 => dec.frame(r.segment()?)?,
+            0xc2..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                return Err(ImageError::Unsupported)
+            }
+            0xc4 => dec.huffman_tables(r.segment()?)?,
+            0xdb => dec.quant_tables(r.segment()?)?,
+            0xdd => {
+                let mut s = r.segment()?;
+                dec.restart = s.u16()?;
+            }
+            0xee => {
+                let mut s = r.segment()?;
+                if s.bytes(5).ok() == Some(b"Adobe") {
+                    // Version, flags0, flags1, then the transform
+                    s.bytes(6)?;
+                    dec.adobe_ycc = Some(s.u8()? != 0);
+                }
+            }
+            0xda => {
+                if dec.components.is_empty() {
+                    return Err(ImageError::InvalidData);
+                }
+                let s = r.segment()?;
+                let info = ImageInfo {
+                    res: dec.res,
+                    color: ColorSpace::sRGB,
+                };
+                dec.scan(s, &data[r.pos..], &mut on_row)?;
+                return Ok(info);
+            }
+            0xd9 => return Err(ImageError::InvalidData),
+            // Standalone markers
+            0x01 | 0xd0..=0xd7 => {}
+            _ => {
+                r.segment()?;
+            }
+        }
+    }
+}
+
+/// Decode a whole baseline JPEG, see [`decode_rows`]
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidData`] if `data` isn't a JPEG, or is corrupt or
+///   truncated
+/// - [`ImageError::Unsupported`] for files [`decode_rows`] doesn't support
+pub fn decode(data: &[u8]) -> Result<Image, ImageError> {
+    decode_all(data, |d, f| decode_rows(d, f))
+}
+
+/// Decode a whole baseline JPEG, or with [`DecodeMode::Salvage`] as much of
+/// a damaged one as possible, the rows up to where its scan broke off
+///
+/// # Errors
+///
+/// - Like [`decode`], unless salvaging decoded at least one row
+/// - [`ImageError::OutOfMemory`] if salvaging and the size in the header
+///   can't be allocated
+pub fn decode_with(data: &[u8], mode: DecodeMode) -> Result<(Image, DecodeWarnings), ImageError> {
+    decode_all_with(data, mode, probe, |d, _, f| decode_rows(d, f))
+}
+
+impl Image {
+    /// Decode a baseline JPEG, see [`decode`]
+    ///
+    /// # Errors
+    ///
+    /// - Like [`decode`]
+    pub fn from_jpeg(data: &[u8]) -> Result<Image, ImageError> {
+        decode(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::DecodeMode;
+
+    /// A tiny file from `testdata/jpeg.py`, with the RGBA of a float decode
+    /// of its coefficients
+    type Fixture = (&'static str, &'static [u8], &'static [u8], (u32, u32));
+
+    const FIXTURES: [Fixture; 4] = [
+        (
+            "gray",
+            include_bytes!("testdata/gray.jpg"),
+            include_bytes!("testdata/gray.rgba"),
+            (11, 9),
+        ),
+        (
+            "4:4:4",
+            include_bytes!("testdata/444.jpg"),
+            include_bytes!("testdata/444.rgba"),
+            (16, 8),
+        ),
+        (
+            "4:2:2",
+            include_bytes!("testdata/422.jpg"),
+            include_bytes!("testdata/422.rgba"),
+            (13, 8),
+        ),
+        (
+            "4:2:0 with restarts",
+            include_bytes!("testdata/420.jpg"),
+            include_bytes!("testdata/420.rgba"),
+            (19, 21),
+        ),
+    ];
+
+    const PROGRESSIVE: &[u8] = include_bytes!("testdata/progressive.jpg");
+
+    #[test]
+    fn decodes_fixtures() {
+        for (name, file, want, res) in FIXTURES {
+            let img = decode(file).unwrap_or_else(|e| panic!("{name}: {e:?}"));
+            assert_eq!(img.res, res, "{name}");
+            assert_eq!(img.color, ColorSpace::sRGB);
+            let got = img.to_bytes();
+            assert_eq!(got.len(), want.len(), "{name}");
+            let worst = got.iter().zip(want).map(|(a, b)| a.abs_diff(*b)).max();
+            assert!(worst <= Some(2), "{name} is off by {worst:?}");
+        }
+    }
+
+    #[test]
+    fn rows_match_whole() {
+        let (_, file, _, res) = FIXTURES[3];
+        let whole = decode(file).unwrap().to_bytes();
+        let mut rows: Vec<u8> = Vec::new();
+        let mut next = 0;
+        let info = decode_rows(file, |y, row| {
+            assert_eq!(y, next);
+            next += 1;
+            rows.extend(row.iter().flatten());
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert_eq!(info.res, res);
+        assert_eq!(rows, whole);
+
+        let mut seen = 0;
+        decode_rows(file, |_, _| {
+            seen += 1;
+            ControlFlow::Break(())
+        })
+        .unwrap();
+        assert_eq!(seen, 1);
+    }
+
+    #[test]
+    fn progressive_is_unsupported() {
+        assert_eq!(decode(PROGRESSIVE).err(), Some(ImageError::Unsupported));
+        assert_eq!(probe(PROGRESSIVE).err(), Some(ImageError::Unsupported));
+    }
+
+    #[test]
+    fn truncated() {
+        for (name, file, _, _) in FIXTURES {
+            // Everything but the end of image marker is needed
+            for n in 0..file.len() - 2 {
+                assert_eq!(
+                    decode(&file[..n]).err(),
+                    Some(ImageError::InvalidData),
+                    "{name} cut to {n}"
+                );
+            }
+        }
+        for n in 0..PROGRESSIVE.len() {
+            assert!(decode(&PROGRESSIVE[..n]).is_err());
+        }
+    }
+
+    #[test]
+    fn corrupt() {
+        for (_, file, _, _) in FIXTURES {
+            for i in 0..file.len() {
+                let mut file = file.to_vec();
+                file[i] ^= 0x5a;
+                // Anything but a panic
+                let _ = decode(&file);
+            }
+        }
+    }
+
+    #[test]
+    fn salvage_truncated() {
+        let (_, file, want, (w, h)) = FIXTURES[3];
+        let cut = &file[..file.len() - 40];
+        let fill = [1, 2, 3, 4];
+        let (img, warnings) = decode_with(cut, DecodeMode::Salvage { fill }).unwrap();
+        assert!(!warnings.is_empty());
+        assert_eq!(img.res, (w, h));
+        let got = img.to_bytes();
+        // The first MCU row is all there
+        let row = w as usize * 4 * 16;
+        let worst = got[..row]
+            .iter()
+            .zip(want)
+            .map(|(a, b)| a.abs_diff(*b))
+            .max();
+        assert!(worst <= Some(2));
+        assert!(got.ends_with(&fill));
+    }
+}
@@ -0,0 +1,555 @@
+//! Image file formats
+//!
+//! Each format has a `decode_rows` that hands straight RGBA 8888 rows to a
+//! callback, top to bottom, so they can go straight into a framebuffer
+//! without ever holding the whole image. Only one row is buffered.
+use alloc::{vec, vec::Vec};
+use core::{marker::PhantomData, ops::ControlFlow};
+
+use self::codec::{be32, le16, le32};
+use crate::{
+    fallible::{pixels, try_with_capacity},
+    ColorSpace, Image, ImageError, PixelFormat, RawPixel, ResXY, WorkPixel,
+};
+
+pub mod bmp;
+mod codec;
+#[cfg(feature = "jpeg")]
+pub mod jpeg;
+pub mod ppm;
+pub mod qoi;
+pub mod wave;
+
+/// What a decoder found in the file header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub res: ResXY,
+    pub color: ColorSpace,
+}
+
+/// How the `decode_with` of each format handles damaged files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecodeMode {
+    /// Any damage is an error, like `decode`
+    #[default]
+    Strict,
+
+    /// Keep what could be decoded of a damaged file, filling the rest with
+    /// the RGBA 8888 `fill`
+    ///
+    /// The header has to be intact, and at least one row decodable.
+    Salvage { fill: RawPixel },
+}
+
+/// Something wrong with a file [`DecodeMode::Salvage`] decoded anyway
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecodeWarning {
+    /// `rows` rows couldn't be decoded, from `error`, and were filled
+    MissingRows { rows: u32, error: ImageError },
+}
+
+/// What was wrong with a salvaged file, empty if nothing was
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DecodeWarnings(Vec<DecodeWarning>);
+
+impl DecodeWarnings {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn warnings(&self) -> &[DecodeWarning] {
+        &self.0
+    }
+}
+
+/// Why a streaming `RowWriter`, like [`bmp::RowWriter`], failed
+///
+/// The file is incomplete after either.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteError<E> {
+    /// [`ImageError::InvalidArgument`] for a row of the wrong width or
+    /// finishing before the last row, [`ImageError::OutOfBounds`] for rows
+    /// past the last
+    Image(ImageError),
+    /// The sink failed
+    Sink(E),
+}
+
+impl<E> From<ImageError> for WriteError<E> {
+    fn from(e: ImageError) -> Self {
+        WriteError::Image(e)
+    }
+}
+
+impl<E: core::fmt::Display> core::fmt::Display for WriteError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            WriteError::Image(e) => write!(f, "{e}"),
+            WriteError::Sink(e) => write!(f, "writing failed, {e}"),
+        }
+    }
+}
+
+/// Sends the bytes of a streaming `RowWriter` to its sink, and checks each
+/// row is the next one
+struct RowSink<F, E> {
+    sink: F,
+    res: ResXY,
+    /// Rows written so far
+    rows: u32,
+    /// One encoded row, reused
+    buf: Vec<u8>,
+    _error: PhantomData<fn() -> E>,
+}
+
+impl<F: FnMut(&[u8]) -> Result<(), E>, E> RowSink<F, E> {
+    fn new(res: ResXY, sink: F) -> Self {
+        Self {
+            sink,
+            res,
+            rows: 0,
+            buf: Vec::new(),
+            _error: PhantomData,
+        }
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> Result<(), WriteError<E>> {
+        (self.sink)(bytes).map_err(WriteError::Sink)
+    }
+
+    /// Count `row`, if it's the width of the image and there's room for it
+    fn next_row(&mut self, row: &[WorkPixel]) -> Result<(), WriteError<E>> {
+        if row.len() != self.res.0 as usize {
+            return Err(ImageError::InvalidArgument.into());
+        }
+        if self.rows == self.res.1 {
+            return Err(ImageError::OutOfBounds.into());
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Count `row` and send it as straight alpha `format`, which mustn't be
+    /// gray
+    fn send_row(&mut self, row: &[WorkPixel], format: PixelFormat) -> Result<(), WriteError<E>> {
+        self.next_row(row)?;
+        let bpp = format.bytes_per_pixel();
+        self.buf.resize(row.len() * bpp, 0);
+        for (p, o) in row.iter().zip(self.buf.chunks_exact_mut(bpp)) {
+            format.encode(*p, o);
+        }
+        (self.sink)(&self.buf).map_err(WriteError::Sink)
+    }
+
+    /// Check every row was written
+    fn finish(&self) -> Result<(), WriteError<E>> {
+        if self.rows != self.res.1 {
+            return Err(ImageError::InvalidArgument.into());
+        }
+        Ok(())
+    }
+}
+
+/// The formats [`probe`] knows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Bmp,
+    Qoi,
+    /// PPM or PGM
+    Ppm,
+    #[cfg(feature = "jpeg")]
+    Jpeg,
+    /// [`wave`]
+    Wave,
+}
+
+/// What [`probe`] found in a file header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeInfo {
+    pub format: FileFormat,
+    pub info: ImageInfo,
+
+    /// Bits per channel in the file
+    pub bit_depth: u8,
+
+    /// Channels in the file, 1 for gray up to 4 with alpha
+    pub channels: u8,
+
+    /// Size of the pixels of the decoded [`Image`], in bytes
+    ///
+    /// Decoding with `decode_rows` needs only a row instead, and decoding
+    /// whole also briefly holds the file's pixels as RGBA 8888.
+    pub estimated_work_bytes: usize,
+}
+
+impl ProbeInfo {
+    fn new(
+        format: FileFormat,
+        info: ImageInfo,
+        bit_depth: u8,
+        channels: u8,
+    ) -> Result<Self, ImageError> {
+        let estimated_work_bytes = (info.res.0 as usize)
+            .checked_mul(info.res.1 as usize)
+            .and_then(|n| n.checked_mul(size_of::<WorkPixel>()))
+            .ok_or(ImageError::InvalidData)?;
+        Ok(Self {
+            format,
+            info,
+            bit_depth,
+            channels,
+            estimated_work_bytes,
+        })
+    }
+}
+
+/// Identify the format of `data` from its magic bytes and read just the
+/// header, to check an untrusted file is small enough before decoding it
+///
+/// The pixel data isn't looked at, so the file may still fail to decode.
+///
+/// # Errors
+///
+/// - [`ImageError::UnknownFormat`] if `data` doesn't start like any of the
+///   [`FileFormat`]s
+/// - [`ImageError::InvalidData`] if the header is truncated or invalid, or
+///   the decoded size would overflow `usize`
+/// - [`ImageError::Unsupported`] for headers the decoder doesn't support
+/// - [`ImageError::UnknownColorSpace`] for [`wave`] files with unknown
+///   color spaces
+pub fn probe(data: &[u8]) -> Result<ProbeInfo, ImageError> {
+    match data {
+        [b'B', b'M', ..] => bmp::probe(data),
+        [b'q', b'o', b'i', b'f', ..] => qoi::probe(data),
+        [b'P', b'2' | b'3' | b'5' | b'6', ..] => ppm::probe(data),
+        #[cfg(feature = "jpeg")]
+        [0xff, 0xd8, ..] => jpeg::probe(data),
+        [b'w', b'a', b'v', b'e', ..] => wave::probe(data),
+        _ => Err(ImageError::UnknownFormat),
+    }
+}
+
+impl From<codec::Error> for ImageError {
+    fn from(e: codec::Error) -> Self {
+        match e {
+            codec::Error::InvalidData => ImageError::InvalidData,
+            codec::Error::Unsupported => ImageError::Unsupported,
+        }
+    }
+}
+
+/// Collect all the rows from `decode_rows` into an [`Image`]
+fn decode_all(
+    data: &[u8],
+    decode_rows: impl FnOnce(
+        &[u8],
+        &mut dyn FnMut(u32, &[RawPixel]) -> ControlFlow<()>,
+    ) -> Result<ImageInfo, ImageError>,
+) -> Result<Image, ImageError> {
+    let mut pixels = Vec::new();
+    let info = decode_rows(data, &mut |_, row| {
+        pixels.extend_from_slice(row.as_flattened());
+        ControlFlow::Continue(())
+    })?;
+    Ok(Image::from_bytes(&pixels, info.res, info.color))
+}
+
+/// [`decode_all`], keeping what decoded with [`DecodeMode::Salvage`]
+///
+/// `decode_rows` gets whether it's salvaging, so formats that check the
+/// size up front can skip rows that aren't there instead. Rows may be
+/// skipped at the start, for files stored bottom up, or the end, but not
+/// between.
+fn decode_all_with(
+    data: &[u8],
+    mode: DecodeMode,
+    probe: fn(&[u8]) -> Result<ProbeInfo, ImageError>,
+    decode_rows: impl FnOnce(
+        &[u8],
+        bool,
+        &mut dyn FnMut(u32, &[RawPixel]) -> ControlFlow<()>,
+    ) -> Result<ImageInfo, ImageError>,
+) -> Result<(Image, DecodeWarnings), ImageError> {
+    let fill = match mode {
+        DecodeMode::Strict => {
+            let img = decode_all(data, |d, f| decode_rows(d, false, f))?;
+            return Ok((img, DecodeWarnings::default()));
+        }
+        DecodeMode::Salvage { fill } => fill,
+    };
+    let info = probe(data)?.info;
+    let (w, h) = (info.res.0 as usize, info.res.1 as usize);
+    let total = pixels(info.res)?;
+    let (mut first, mut rows) = (None, 0);
+    let mut pixels = Vec::new();
+    let result = decode_rows(data, true, &mut |y, row| {
+        first.get_or_insert(y as usize);
+        pixels.extend_from_slice(row);
+        rows += 1;
+        ControlFlow::Continue(())
+    });
+    let mut warnings = DecodeWarnings::default();
+    match (result, first) {
+        (Ok(_), _) => (),
+        (Err(e), None) => return Err(e),
+        (Err(error), Some(first)) => {
+            warnings.0.push(DecodeWarning::MissingRows {
+                rows: (h - rows) as u32,
+                error,
+            });
+            // The header is all that says how big this is, so it may well
+            // not fit
+            let mut all = try_with_capacity(total)?;
+            all.resize(first * w, fill);
+            all.extend_from_slice(&pixels);
+            all.resize(total, fill);
+            pixels = all;
+        }
+    }
+    let img = Image::try_from_bytes_fallible(pixels.as_flattened(), info.res, info.color)?;
+    Ok((img, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::photo;
+
+    /// A file, its decoder, and the expected bits per channel and channels
+    type File = (
+        FileFormat,
+        Vec<u8>,
+        fn(&[u8]) -> Result<Image, ImageError>,
+        u8,
+        u8,
+    );
+
+    /// Each format's file of `img`
+    fn files(img: &Image) -> Vec<File> {
+        let qoi = qoi::encode(img).unwrap();
+        let channels = qoi[12];
+        let mut gray16 = b"P5\n7 5\n1000\n".to_vec();
+        gray16.extend(core::iter::repeat_n(0x01, 7 * 5 * 2));
+        vec![
+            (FileFormat::Bmp, bmp::encode(img), bmp::decode, 8, 4),
+            (FileFormat::Qoi, qoi, qoi::decode, 8, channels),
+            (FileFormat::Ppm, ppm::encode(img), ppm::decode, 8, 3),
+            (FileFormat::Ppm, gray16, ppm::decode, 16, 1),
+            (FileFormat::Wave, wave::encode(img, 80), wave::decode, 8, 4),
+        ]
+    }
+
+    #[test]
+    fn probe_headers() {
+        let mut img = photo((7, 5));
+        img.color = ColorSpace::sRGBLinear;
+        for (format, data, decode, bit_depth, channels) in files(&img) {
+            let info = probe(&data).unwrap();
+            assert_eq!(info.format, format);
+            assert_eq!(
+                (info.bit_depth, info.channels),
+                (bit_depth, channels),
+                "{format:?}"
+            );
+            assert_eq!(info.info.res, (7, 5));
+            // And what decoding it allocates
+            let decoded = decode(&data).unwrap();
+            assert_eq!(info.info.color, decoded.color, "{format:?}");
+            assert_eq!(
+                info.estimated_work_bytes,
+                decoded.data.capacity() * size_of::<WorkPixel>(),
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn probe_truncated() {
+        for (format, data, ..) in files(&photo((7, 5))) {
+            // Enough to recognize, not enough for the header
+            for len in [4, 9] {
+                let data = &data[..len];
+                assert_eq!(
+                    probe(data),
+                    Err(ImageError::InvalidData),
+                    "{format:?} {len}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn probe_unknown() {
+        for data in [
+            &b""[..],
+            b"GIF89a\x01\x00",
+            b"P",
+            b"P7\n",
+            b"B",
+            &[0x89, b'P', b'N', b'G'],
+        ] {
+            assert_eq!(probe(data), Err(ImageError::UnknownFormat), "{data:?}");
+        }
+    }
+
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn probe_jpeg() {
+        let data = include_bytes!("testdata/422.jpg");
+        let info = probe(data).unwrap();
+        assert_eq!(
+            (info.format, info.bit_depth, info.channels),
+            (FileFormat::Jpeg, 8, 3)
+        );
+        let decoded = jpeg::decode(data).unwrap();
+        assert_eq!(info.info.res, decoded.res);
+        assert_eq!(
+            info.estimated_work_bytes,
+            decoded.data.capacity() * size_of::<WorkPixel>()
+        );
+        assert_eq!(probe(&data[..20]), Err(ImageError::InvalidData));
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    enum Writer {
+        Bmp,
+        Ppm,
+        Qoi,
+    }
+
+    const WRITERS: [Writer; 3] = [Writer::Bmp, Writer::Ppm, Writer::Qoi];
+
+    /// What a streaming writer sent, each piece on its own
+    type Streamed = Result<Vec<Vec<u8>>, WriteError<usize>>;
+
+    /// `img` written a row at a time by `writer`, with a sink that fails on
+    /// its call numbered `fail`, and `rows` choosing which rows are written
+    fn stream(writer: Writer, img: &Image, fail: usize, rows: &[u32]) -> Streamed {
+        let mut pieces = Vec::new();
+        let sink = |b: &[u8]| {
+            if pieces.len() == fail {
+                return Err(fail);
+            }
+            pieces.push(b.to_vec());
+            Ok(())
+        };
+        let w = img.width() as usize;
+        let row = |y: u32| &img.data[y as usize * w..][..w];
+        match writer {
+            Writer::Bmp => {
+                let mut out = bmp::RowWriter::new(img.res, sink)?;
+                rows.iter().try_for_each(|y| out.write_row(row(*y)))?;
+                out.finish()?;
+            }
+            Writer::Ppm => {
+                let mut out = ppm::RowWriter::new(img.res, sink)?;
+                rows.iter().try_for_each(|y| out.write_row(row(*y)))?;
+                out.finish()?;
+            }
+            Writer::Qoi => {
+                let mut out = qoi::RowWriter::new(img.res, img.color, sink)?;
+                rows.iter().try_for_each(|y| out.write_row(row(*y)))?;
+                out.finish()?;
+            }
+        }
+        Ok(pieces)
+    }
+
+    fn encode(writer: Writer, img: &Image) -> Vec<u8> {
+        match writer {
+            Writer::Bmp => bmp::encode(img),
+            Writer::Ppm => ppm::encode(img),
+            Writer::Qoi => qoi::encode(img).unwrap(),
+        }
+    }
+
+    /// Test images, flat, noisy, with runs past QOI's longest, and one pixel
+    fn images() -> Vec<Image> {
+        let mut linear = photo((7, 5));
+        linear.color = ColorSpace::sRGBLinear;
+        let mut flat = crate::fixtures::solid((100, 3), [0.2, 0.4, 0.6, 0.8]);
+        flat.map_pixels_indexed(|(x, _), p| if x == 70 { [1.; 4] } else { p });
+        vec![
+            photo((31, 17)),
+            linear,
+            flat,
+            crate::fixtures::solid((1, 1), [0.; 4]),
+        ]
+    }
+
+    #[test]
+    fn row_writers_match_encode() {
+        for img in images() {
+            let all: Vec<u32> = (0..img.height()).collect();
+            for writer in WRITERS {
+                let pieces = stream(writer, &img, usize::MAX, &all).unwrap();
+                assert_eq!(
+                    pieces.concat(),
+                    encode(writer, &img),
+                    "{writer:?} {:?}",
+                    img.res
+                );
+                // Nothing bigger than a header or a row at a time
+                let row = img.width() as usize * 5;
+                assert!(pieces.iter().all(|p| p.len() <= row.max(122)), "{writer:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn row_writers_check_rows() {
+        let img = photo((7, 5));
+        for writer in WRITERS {
+            // Short
+            assert_eq!(
+                stream(writer, &img, usize::MAX, &[0, 1, 2]).err(),
+                Some(WriteError::Image(ImageError::InvalidArgument)),
+                "{writer:?}"
+            );
+            // Too many
+            assert_eq!(
+                stream(writer, &img, usize::MAX, &[0, 1, 2, 3, 4, 0]).err(),
+                Some(WriteError::Image(ImageError::OutOfBounds)),
+                "{writer:?}"
+            );
+        }
+        // The wrong width
+        let sink = |_: &[u8]| Ok::<_, ()>(());
+        let mut bmp = bmp::RowWriter::new((7, 5), sink).unwrap();
+        assert_eq!(
+            bmp.write_row(&img.data[..6]),
+            Err(WriteError::Image(ImageError::InvalidArgument))
+        );
+        let mut ppm = ppm::RowWriter::new((7, 5), sink).unwrap();
+        assert_eq!(
+            ppm.write_row(&img.data[..8]),
+            Err(WriteError::Image(ImageError::InvalidArgument))
+        );
+        let mut qoi = qoi::RowWriter::new((7, 5), ColorSpace::sRGB, sink).unwrap();
+        assert_eq!(
+            qoi.write_row(&[]),
+            Err(WriteError::Image(ImageError::InvalidArgument))
+        );
+        assert!(matches!(
+            qoi::RowWriter::new((0, 5), ColorSpace::sRGB, sink),
+            Err(WriteError::Image(ImageError::InvalidArgument))
+        ));
+    }
+
+    #[test]
+    fn row_writers_pass_on_sink_errors() {
+        let img = photo((7, 5));
+        let all = [0, 1, 2, 3, 4];
+        for writer in WRITERS {
+            let calls = stream(writer, &img, usize::MAX, &all).unwrap().len();
+            // In the header, a row, and whatever finishing sends
+            for fail in 0..calls {
+                assert_eq!(
+                    stream(writer, &img, fail, &all).err(),
+                    Some(WriteError::Sink(fail)),
+                    "{writer:?}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Binary Netpbm, PPM (`P6`) and PGM (`P5`)
+use alloc::{format, string::String, vec, vec::Vec};
+use core::ops::ControlFlow;
+
+use super::{
+    decode_all, decode_all_with, DecodeMode, DecodeWarnings, FileFormat, ImageInfo, ProbeInfo,
+    RowSink, WriteError,
+};
+use crate::{ColorSpace, Image, ImageError, PixelFormat, RawPixel, ResXY, WorkPixel};
+
+/// Encode `img` as an 8 bit binary PPM, `P6`
+///
+/// PPM has no alpha, so it's dropped. Pixels are written as stored, like
+/// [`bmp::encode`](super::bmp::encode).
+pub fn encode(img: &Image) -> Vec<u8> {
+    let mut out = file_header(img.res).into_bytes();
+    out.extend_from_slice(&img.to_raw(PixelFormat::Rgb888));
+    out
+}
+
+fn file_header((w, h): ResXY) -> String {
+    format!("P6\n{w} {h}\n255\n")
+}
+
+/// Writes a PPM a row at a time, like [`encode`], to a sink that takes each
+/// piece of the file in order
+///
+/// Only one encoded row is held at a time. Rows are written as stored, and
+/// their alpha is dropped.
+pub struct RowWriter<F, E> {
+    rows: RowSink<F, E>,
+}
+
+impl<F: FnMut(&[u8]) -> Result<(), E>, E> RowWriter<F, E> {
+    /// Start a PPM of `res`, sending the header to `sink` right away
+    ///
+    /// # Errors
+    ///
+    /// - [`WriteError::Sink`] if `sink` fails
+    pub fn new(res: ResXY, sink: F) -> Result<Self, WriteError<E>> {
+        let mut rows = RowSink::new(res, sink);
+        rows.send(file_header(res).as_bytes())?;
+        Ok(Self { rows })
+    }
+
+    /// Write the next row, top to bottom
+    ///
+    /// # Errors
+    ///
+    /// - [`WriteError::Image`] if `row` is the wrong width, or every row
+    ///   has been written
+    /// - [`WriteError::Sink`] if the sink fails
+    pub fn write_row(&mut self, row: &[WorkPixel]) -> Result<(), WriteError<E>> {
+        self.rows.send_row(row, PixelFormat::Rgb888)
+    }
+
+    /// End the file, which PPM needs nothing more for
+    ///
+    /// # Errors
+    ///
+    /// - [`WriteError::Image`] if not every row was written
+    pub fn finish(self) -> Result<(), WriteError<E>> {
+        self.rows.finish()
+    }
+}
+
+/// Parse the next header number from `data` at `*pos`, skipping whitespace
+/// and comments
+fn number(data: &[u8], pos: &mut usize) -> Result<u32, ImageError> {
+    loop {
+        match data.get(*pos) {
+            Some(b) if b.is_ascii_whitespace() => *pos += 1,
+            Some(b'#') => {
+                while data.get(*pos).is_some_and(|b| *b != b'\n') {
+                    *pos += 1;
+                }
+            }
+            _ => break,
+        }
+    }
+    let start = *pos;
+    let mut n: u32 = 0;
+    while let Some(d) = data.get(*pos).filter(|b| b.is_ascii_digit()) {
+        n = n
+            .checked_mul(10)
+            .and_then(|n| n.checked_add((d - b'0') as u32))
+            .ok_or(ImageError::InvalidData)?;
+        *pos += 1;
+    }
+    if *pos == start {
+        return Err(ImageError::InvalidData);
+    }
+    Ok(n)
+}
+
+/// The parts of a PPM or PGM header [`decode_rows`] needs
+struct Header {
+    info: ImageInfo,
+    channels: usize,
+    /// Largest sample value
+    max: u32,
+    /// Start of the samples
+    start: usize,
+}
+
+/// Parse and check the header, without looking at the samples
+fn header(data: &[u8]) -> Result<Header, ImageError> {
+    let channels = match data.get(..2) {
+        Some(b"P6") => 3,
+        Some(b"P5") => 1,
+        Some(b"P3" | b"P2") => return Err(ImageError::Unsupported),
+        _ => return Err(ImageError::InvalidData),
+    };
+    let mut pos = 2;
+    let w = number(data, &mut pos)?;
+    let h = number(data, &mut pos)?;
+    let max = number(data, &mut pos)?;
+    if w == 0 || h == 0 || max == 0 || max > u16::MAX as u32 {
+        return Err(ImageError::InvalidData);
+    }
+    // Exactly one whitespace byte before the samples
+    if !data.get(pos).is_some_and(u8::is_ascii_whitespace) {
+        return Err(ImageError::InvalidData);
+    }
+    Ok(Header {
+        info: ImageInfo {
+            res: (w, h),
+            color: ColorSpace::sRGB,
+        },
+        channels,
+        max,
+        start: pos + 1,
+    })
+}
+
+/// Read the header, see [`probe`](super::probe)
+pub(super) fn probe(data: &[u8]) -> Result<ProbeInfo, ImageError> {
+    let h = header(data)?;
+    let depth = if h.max > 255 { 16 } else { 8 };
+    ProbeInfo::new(FileFormat::Ppm, h.info, depth, h.channels as u8)
+}
+
+/// Decode a binary PPM or PGM, calling `on_row` with each row and its index
+///
+/// Samples are scaled to 8 bits from any max value, including 16 bit ones.
+/// Returning [`ControlFlow::Break`] stops decoding early, which isn't an
+/// error. The color space is always [`ColorSpace::sRGB`], and alpha opaque.
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidData`] if `data` isn't a PPM or PGM, or is
+///   truncated
+/// - [`ImageError::Unsupported`] for the ASCII variants
+pub fn decode_rows(
+    data: &[u8],
+    on_row: impl FnMut(u32, &[RawPixel]) -> ControlFlow<()>,
+) -> Result<ImageInfo, ImageError> {
+    rows(data, false, on_row)
+}
+
+/// [`decode_rows`], stopping at the end of `data` if `salvage`, and only
+/// then failing
+fn rows(
+    data: &[u8],
+    salvage: bool,
+    mut on_row: impl FnMut(u32, &[RawPixel]) -> ControlFlow<()>,
+) -> Result<ImageInfo, ImageError> {
+    let Header {
+        info,
+        channels,
+        max,
+        start: pos,
+    } = header(data)?;
+    let (w, h) = info.res;
+    let size = if max > 255 { 2 } else { 1 };
+    let stride = (w as usize)
+        .checked_mul(channels * size)
+        .ok_or(ImageError::InvalidData)?;
+    let complete = (data.len() - pos) / stride >= h as usize;
+    if !salvage && !complete {
+        return Err(ImageError::InvalidData);
+    }
+    // Not even one row is there, don't allocate for garbage
+    if data.len() - pos < stride {
+        return Err(ImageError::InvalidData);
+    }
+    let sample = |b: &[u8]| {
+        let v = if size == 2 {
+            u16::from_be_bytes([b[0], b[1]]) as u32
+        } else {
+            b[0] as u32
+        };
+        ((v.min(max) * 255 + max / 2) / max) as u8
+    };
+    let mut row = vec![[0u8; 4]; w as usize];
+    for (y, src) in data[pos..]
+        .chunks_exact(stride)
+        .take(h as usize)
+        .enumerate()
+    {
+        for (o, p) in row.iter_mut().zip(src.chunks_exact(channels * size)) {
+            *o = match channels {
+                3 => [sample(p), sample(&p[size..]), sample(&p[size * 2..]), 255],
+                _ => {
+                    let v = sample(p);
+                    [v, v, v, 255]
+                }
+            };
+        }
+        if on_row(y as u32, &row).is_break() {
+            return Ok(info);
+        }
+    }
+    if !complete {
+        return Err(ImageError::InvalidData);
+    }
+    Ok(info)
+}
+
+/// Decode a whole PPM or PGM, see [`decode_rows`]
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidData`] if `data` isn't a PPM or PGM, or is
+///   truncated
+/// - [`ImageError::Unsupported`] for the ASCII variants
+pub fn decode(data: &[u8]) -> Result<Image, ImageError> {
+    decode_all(data, |d, f| decode_rows(d, f))
+}
+
+/// Decode a whole PPM or PGM, or with [`DecodeMode::Salvage`] as much of a
+/// damaged one as possible, the rows up to where it stops
+///
+/// # Errors
+///
+/// - Like [`decode`], unless salvaging decoded at least one row
+/// - [`ImageError::OutOfMemory`] if salvaging and the size in the header
+///   can't be allocated
+pub fn decode_with(data: &[u8], mode: DecodeMode) -> Result<(Image, DecodeWarnings), ImageError> {
+    decode_all_with(data, mode, probe, |d, salvage, f| rows(d, salvage, f))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::fixtures::photo;
+
+    #[test]
+    fn salvage_rejects_huge_header() {
+        let mut file = b"P6\n999999999 999999999\n255\n".to_vec();
+        file.extend_from_slice(&[0; 64]);
+        let fill = [0; 4];
+        assert_eq!(decode(&file).err(), Some(ImageError::InvalidData));
+        assert_eq!(
+            decode_with(&file, DecodeMode::Salvage { fill }).err(),
+            Some(ImageError::InvalidData)
+        );
+    }
+
+    /// Rows from [`decode_rows`], with their indices, stopping after `stop`
+    fn collect(data: &[u8], stop: u32) -> (Result<ImageInfo, ImageError>, Vec<u32>, Vec<u8>) {
+        let (mut ys, mut bytes) = (Vec::new(), Vec::new());
+        let info = decode_rows(data, |y, row| {
+            ys.push(y);
+            bytes.extend(row.iter().flatten());
+            if y + 1 == stop {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        (info, ys, bytes)
+    }
+
+    #[test]
+    fn rows_match_decode() {
+        let img = photo((6, 4));
+        let file = encode(&img);
+        let (info, ys, bytes) = collect(&file, u32::MAX);
+        assert_eq!(info.unwrap().res, (6, 4));
+        assert_eq!(ys, [0, 1, 2, 3]);
+        assert_eq!(bytes, img.to_bytes());
+        assert_eq!(bytes, decode(&file).unwrap().to_bytes());
+    }
+
+    #[test]
+    fn rows_break_early() {
+        let img = photo((6, 4));
+        let (info, ys, bytes) = collect(&encode(&img), 3);
+        assert!(info.is_ok());
+        assert_eq!(ys, [0, 1, 2]);
+        assert_eq!(bytes, img.to_bytes()[..3 * 6 * 4]);
+    }
+
+    #[test]
+    fn gray_and_16_bit() {
+        let mut file = b"P5\n2 1\n65535\n".to_vec();
+        file.extend_from_slice(&[0xff, 0xff, 0x80, 0x00]);
+        let (info, ys, bytes) = collect(&file, u32::MAX);
+        assert_eq!(info.unwrap().res, (2, 1));
+        assert_eq!(ys, [0]);
+        assert_eq!(bytes, [255, 255, 255, 255, 128, 128, 128, 255]);
+    }
+}
@@ -0,0 +1,337 @@
+//! QOI, <https://qoiformat.org/qoi-specification.pdf>
+use alloc::{vec, vec::Vec};
+use core::{convert::Infallible, ops::ControlFlow};
+
+use super::{
+    codec::{qoi_hash, qoi_header, qoi_rows, QoiHeader, QOI_MAX_RUN},
+    decode_all, decode_all_with, DecodeMode, DecodeWarnings, FileFormat, ImageInfo, ProbeInfo,
+    RowSink, WriteError,
+};
+use crate::{
+    alpha_converter, AlphaMode, ColorSpace, Image, ImageError, PixelFormat, RawPixel, ResXY,
+    WorkPixel,
+};
+
+/// Encode `img` as a 4 channel QOI
+///
+/// Pixels are written as stored, with straight alpha, and
+/// [`ColorSpace::sRGBLinear`] sets the linear colorspace flag. See
+/// [`RowWriter`] to write one a row at a time instead.
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidArgument`] if `img` is empty, which QOI can't
+///   store
+pub fn encode(img: &Image) -> Result<Vec<u8>, ImageError> {
+    let mut out = Vec::new();
+    let sink = |b: &[u8]| {
+        out.extend_from_slice(b);
+        Ok::<_, Infallible>(())
+    };
+    let to_image = |e: WriteError<Infallible>| match e {
+        WriteError::Image(e) => e,
+        WriteError::Sink(e) => match e {},
+    };
+    let mut writer = RowWriter::new(img.res, img.color, sink).map_err(to_image)?;
+    let to_straight = alpha_converter(img.alpha, AlphaMode::Straight);
+    let mut row = Vec::with_capacity(img.width() as usize);
+    for src in img.data.chunks_exact(img.width() as usize) {
+        row.clear();
+        row.extend(src.iter().map(|p| to_straight(*p)));
+        writer.write_row(&row).map_err(to_image)?;
+    }
+    writer.finish().map_err(to_image)?;
+    Ok(out)
+}
+
+/// Writes a QOI a row at a time, like [`encode`], to a sink that takes each
+/// piece of the file in order
+///
+/// Only one encoded row is held at a time. Runs and the table of recent
+/// pixels carry on from row to row, as QOI doesn't know about rows. Rows are
+/// straight alpha, and written as stored.
+pub struct RowWriter<F, E> {
+    rows: RowSink<F, E>,
+    index: [RawPixel; 64],
+    prev: RawPixel,
+    /// Pixels the same as `prev` not written yet
+    run: u8,
+}
+
+impl<F: FnMut(&[u8]) -> Result<(), E>, E> RowWriter<F, E> {
+    /// Start a QOI of `res` in `color`, sending the header to `sink` right
+    /// away
+    ///
+    /// [`ColorSpace::sRGBLinear`] sets the linear colorspace flag, anything
+    /// else is tagged sRGB.
+    ///
+    /// # Errors
+    ///
+    /// - [`WriteError::Image`] with [`ImageError::InvalidArgument`] if
+    ///   `res` is empty, which QOI can't store
+    /// - [`WriteError::Sink`] if `sink` fails
+    pub fn new(res: ResXY, color: ColorSpace, sink: F) -> Result<Self, WriteError<E>> {
+        if res.0 == 0 || res.1 == 0 {
+            return Err(ImageError::InvalidArgument.into());
+        }
+        let mut rows = RowSink::new(res, sink);
+        let mut header = [0; 14];
+        header[..4].copy_from_slice(b"qoif");
+        header[4..8].copy_from_slice(&res.0.to_be_bytes());
+        header[8..12].copy_from_slice(&res.1.to_be_bytes());
+        header[12] = 4;
+        header[13] = (color == ColorSpace::sRGBLinear) as u8;
+        rows.send(&header)?;
+        Ok(Self {
+            rows,
+            index: [[0; 4]; 64],
+            prev: [0, 0, 0, 255],
+            run: 0,
+        })
+    }
+
+    /// Write the next row, top to bottom
+    ///
+    /// A run still going at the end of the row is held for the next one.
+    ///
+    /// # Errors
+    ///
+    /// - [`WriteError::Image`] if `row` is the wrong width, or every row
+    ///   has been written
+    /// - [`WriteError::Sink`] if the sink fails
+    pub fn write_row(&mut self, row: &[WorkPixel]) -> Result<(), WriteError<E>> {
+        self.rows.next_row(row)?;
+        let mut buf = core::mem::take(&mut self.rows.buf);
+        buf.clear();
+        for p in row {
+            let mut px = [0; 4];
+            PixelFormat::Rgba8888.encode(*p, &mut px);
+            self.push(px, &mut buf);
+        }
+        let sent = self.rows.send(&buf);
+        self.rows.buf = buf;
+        sent
+    }
+
+    /// Encode `px` onto `out`
+    fn push(&mut self, px: RawPixel, out: &mut Vec<u8>) {
+        let prev = core::mem::replace(&mut self.prev, px);
+        if px == prev {
+            self.run += 1;
+            if self.run as u64 == QOI_MAX_RUN {
+                out.push(0xc0 | (self.run - 1));
+                self.run = 0;
+            }
+            return;
+        }
+        if self.run > 0 {
+            out.push(0xc0 | (self.run - 1));
+            self.run = 0;
+        }
+        let h = qoi_hash(px);
+        if self.index[h] == px {
+            out.push(h as u8);
+            return;
+        }
+        self.index[h] = px;
+        if px[3] != prev[3] {
+            out.extend_from_slice(&[0xff, px[0], px[1], px[2], px[3]]);
+            return;
+        }
+        let d = |c: usize| px[c].wrapping_sub(prev[c]) as i8;
+        let (dr, dg, db) = (d(0), d(1), d(2));
+        let (dr_dg, db_dg) = (dr.wrapping_sub(dg), db.wrapping_sub(dg));
+        let small = |d: i8| (-2..=1).contains(&d);
+        let luma = |d: i8| (-8..=7).contains(&d);
+        if small(dr) && small(dg) && small(db) {
+            out.push(0x40 | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8);
+        } else if (-32..=31).contains(&dg) && luma(dr_dg) && luma(db_dg) {
+            out.push(0x80 | (dg + 32) as u8);
+            out.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+        } else {
+            out.extend_from_slice(&[0xfe, px[0], px[1], px[2]]);
+        }
+    }
+
+    /// End the file, with the last run and the end marker
+    ///
+    /// # Errors
+    ///
+    /// - [`WriteError::Image`] if not every row was written
+    /// - [`WriteError::Sink`] if the sink fails
+    pub fn finish(mut self) -> Result<(), WriteError<E>> {
+        self.rows.finish()?;
+        let mut end = [0; 9];
+        let n = if self.run > 0 {
+            end[0] = 0xc0 | (self.run - 1);
+            1
+        } else {
+            0
+        };
+        end[n + 7] = 1;
+        self.rows.send(&end[..n + 8])
+    }
+}
+
+/// Parse and check the header, returning the channel count too
+fn header(data: &[u8]) -> Result<(ImageInfo, u8), ImageError> {
+    let header = qoi_header(data)?;
+    Ok((info(&header), header.channels))
+}
+
+fn info(header: &QoiHeader) -> ImageInfo {
+    let color = match header.linear {
+        true => ColorSpace::sRGBLinear,
+        false => ColorSpace::sRGB,
+    };
+    ImageInfo {
+        res: header.res,
+        color,
+    }
+}
+
+/// Read the header, see [`probe`](super::probe)
+pub(super) fn probe(data: &[u8]) -> Result<ProbeInfo, ImageError> {
+    let (info, channels) = header(data)?;
+    ProbeInfo::new(FileFormat::Qoi, info, 8, channels)
+}
+
+/// Decode a QOI image, calling `on_row` with each row and its index
+///
+/// Returning [`ControlFlow::Break`] stops decoding early, which isn't an
+/// error. QOI's linear colorspace flag gives [`ColorSpace::sRGBLinear`],
+/// otherwise [`ColorSpace::sRGB`].
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidData`] if `data` isn't a QOI image, or is
+///   truncated
+pub fn decode_rows(
+    data: &[u8],
+    on_row: impl FnMut(u32, &[RawPixel]) -> ControlFlow<()>,
+) -> Result<ImageInfo, ImageError> {
+    Ok(info(&qoi_rows(data, on_row)?))
+}
+
+/// Decode a whole QOI image, see [`decode_rows`]
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidData`] if `data` isn't a QOI image, or is
+///   truncated
+pub fn decode(data: &[u8]) -> Result<Image, ImageError> {
+    decode_all(data, |d, f| decode_rows(d, f))
+}
+
+/// Decode a whole QOI image, or with [`DecodeMode::Salvage`] as much of a
+/// damaged one as possible, the rows up to where it broke off
+///
+/// # Errors
+///
+/// - Like [`decode`], unless salvaging decoded at least one row
+/// - [`ImageError::OutOfMemory`] if salvaging and the size in the header
+///   can't be allocated
+pub fn decode_with(data: &[u8], mode: DecodeMode) -> Result<(Image, DecodeWarnings), ImageError> {
+    decode_all_with(data, mode, probe, |d, _, f| decode_rows(d, f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::ramp, formats::DecodeWarning};
+
+    /// Rows from [`decode_rows`], with their indices, stopping after `stop`
+    fn collect(data: &[u8], stop: u32) -> (Result<ImageInfo, ImageError>, Vec<u32>, Vec<u8>) {
+        let (mut ys, mut bytes) = (Vec::new(), Vec::new());
+        let info = decode_rows(data, |y, row| {
+            ys.push(y);
+            bytes.extend(row.iter().flatten());
+            if y + 1 == stop {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        (info, ys, bytes)
+    }
+
+    #[test]
+    fn rows_match_decode() {
+        let mut img = ramp((7, 5));
+        img.color = ColorSpace::sRGBLinear;
+        let file = encode(&img).unwrap();
+        let (info, ys, bytes) = collect(&file, u32::MAX);
+        let info = info.unwrap();
+        assert_eq!((info.res, info.color), ((7, 5), ColorSpace::sRGBLinear));
+        assert_eq!(ys, [0, 1, 2, 3, 4]);
+        assert_eq!(bytes, img.to_bytes());
+        assert_eq!(bytes, decode(&file).unwrap().to_bytes());
+    }
+
+    #[test]
+    fn rows_break_early() {
+        let img = ramp((7, 5));
+        let file = encode(&img).unwrap();
+        let (info, ys, bytes) = collect(&file, 1);
+        assert_eq!(info.unwrap().res, (7, 5));
+        assert_eq!(ys, [0]);
+        assert_eq!(bytes, img.to_bytes()[..7 * 4]);
+        // Breaking early doesn't need the rest of the file
+        let (info, ys, _) = collect(&file[..file.len() / 2], 1);
+        assert!(info.is_ok());
+        assert_eq!(ys, [0]);
+    }
+
+    #[test]
+    fn salvage_keeps_the_decoded_prefix() {
+        let img = ramp((7, 5));
+        let file = encode(&img).unwrap();
+        let cut = &file[..file.len() / 2];
+        assert_eq!(decode(cut).err(), Some(ImageError::InvalidData));
+
+        let fill = [1, 2, 3, 4];
+        let (got, warnings) = decode_with(cut, DecodeMode::Salvage { fill }).unwrap();
+        let [DecodeWarning::MissingRows { rows, error }] = warnings.warnings() else {
+            panic!("{warnings:?}");
+        };
+        assert_eq!(*error, ImageError::InvalidData);
+        assert!((1..5).contains(rows), "{rows}");
+        assert_eq!((got.res, got.color), (img.res, img.color));
+        let kept = (5 - *rows as usize) * 7 * 4;
+        let (want, got) = (img.to_bytes(), got.to_bytes());
+        assert_eq!(got[..kept], want[..kept]);
+        assert!(got[kept..].chunks_exact(4).all(|p| p == fill));
+    }
+
+    #[test]
+    fn salvage_of_a_whole_file_has_no_warnings() {
+        let img = ramp((7, 5));
+        let file = encode(&img).unwrap();
+        let fill = [0; 4];
+        let (got, warnings) = decode_with(&file, DecodeMode::Salvage { fill }).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(got.to_bytes(), img.to_bytes());
+    }
+
+    #[test]
+    fn salvage_needs_the_header() {
+        let file = encode(&ramp((7, 5))).unwrap();
+        let fill = [0; 4];
+        let mut magic = file.clone();
+        magic[0] = b'x';
+        let mut empty = file.clone();
+        empty[4..8].copy_from_slice(&0u32.to_be_bytes());
+        for bad in [&magic[..], &empty, &file[..10]] {
+            assert_eq!(decode(bad).err(), Some(ImageError::InvalidData));
+            assert_eq!(
+                decode_with(bad, DecodeMode::Salvage { fill }).err(),
+                Some(ImageError::InvalidData)
+            );
+        }
+        // Intact header, but not one row
+        assert_eq!(
+            decode_with(&file[..15], DecodeMode::Salvage { fill }).err(),
+            Some(ImageError::InvalidData)
+        );
+    }
+}
@@ -0,0 +1,372 @@
+#!/usr/bin/env python3
+"""Writes the tiny JPEG fixtures for the jpeg tests, and their reference RGBA
+
+Nothing but the standard library, so it runs anywhere. The encoder is a
+plain baseline one with the Annex K Huffman tables and the quality 75
+tables, and the progressive file is the same coefficients split into a DC
+scan and an AC scan per component. The reference is a float decode of the
+coefficients, so it doesn't depend on the decoder under test.
+"""
+import math
+import os
+
+ZIGZAG = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27,
+    20, 13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58,
+    59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+]
+
+# Annex K.1, in zigzag order here after scaling
+LUMA_Q = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69,
+    56, 14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104,
+    113, 92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+]
+CHROMA_Q = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99,
+    99, 47, 66, 99, 99, 99, 99, 99, 99,
+] + [99] * 32
+
+# Annex K.3
+DC_LUMA = ([0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0], list(range(12)))
+DC_CHROMA = ([0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0], list(range(12)))
+AC_LUMA = (
+    [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7D],
+    bytes.fromhex(
+        "01020300041105122131410613516107227114328191a1082342b1c11552d1f02433627282090a161718191a"
+        "25262728292a3435363738393a434445464748494a535455565758595a636465666768696a73747576777879"
+        "7a838485868788898a92939495969798999aa2a3a4a5a6a7a8a9aab2b3b4b5b6b7b8b9bac2c3c4c5c6c7c8c9"
+        "cad2d3d4d5d6d7d8d9dae1e2e3e4e5e6e7e8e9eaf1f2f3f4f5f6f7f8f9fa"
+    ),
+)
+AC_CHROMA = (
+    [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77],
+    bytes.fromhex(
+        "000102031104052131061241510761711322328108144291a1b1c109233352f0156272d10a162434e125f1"
+        "1718191a262728292a35363738393a434445464748494a535455565758595a636465666768696a7374757677"
+        "78797a82838485868788898a92939495969798999aa2a3a4a5a6a7a8a9aab2b3b4b5b6b7b8b9bac2c3c4c5c6"
+        "c7c8c9cad2d3d4d5d6d7d8d9dae2e3e4e5e6e7e8e9eaf2f3f4f5f6f7f8f9fa"
+    ),
+)
+
+
+def quality(table, q=75):
+    scale = 5000 // q if q < 50 else 200 - 2 * q
+    return [min(max((t * scale + 50) // 100, 1), 255) for t in table]
+
+
+def codes(table):
+    counts, values = table
+    out, code, k = {}, 0, 0
+    for length, n in enumerate(counts, 1):
+        for _ in range(n):
+            out[values[k]] = (code, length)
+            code += 1
+            k += 1
+        code <<= 1
+    return out
+
+
+class Bits:
+    def __init__(self):
+        self.out, self.acc, self.n = bytearray(), 0, 0
+
+    def put(self, value, length):
+        for i in reversed(range(length)):
+            self.acc = self.acc << 1 | (value >> i) & 1
+            self.n += 1
+            if self.n == 8:
+                self.out.append(self.acc)
+                if self.acc == 0xFF:
+                    self.out.append(0)
+                self.acc, self.n = 0, 0
+
+    def flush(self):
+        while self.n:
+            self.put(1, 1)
+
+
+def category(v):
+    return 0 if v == 0 else int(abs(v)).bit_length()
+
+
+def put_value(bits, v):
+    s = category(v)
+    bits.put(v if v >= 0 else v + (1 << s) - 1, s)
+
+
+def fdct(block):
+    out = [0.0] * 64
+    for v in range(8):
+        for u in range(8):
+            cu = math.sqrt(0.5) if u == 0 else 1
+            cv = math.sqrt(0.5) if v == 0 else 1
+            s = sum(
+                block[y * 8 + x]
+                * math.cos((2 * x + 1) * u * math.pi / 16)
+                * math.cos((2 * y + 1) * v * math.pi / 16)
+                for y in range(8)
+                for x in range(8)
+            )
+            out[v * 8 + u] = cu * cv * s / 4
+    return out
+
+
+def idct(coef):
+    out = []
+    for y in range(8):
+        for x in range(8):
+            s = 0.0
+            for v in range(8):
+                for u in range(8):
+                    cu = math.sqrt(0.5) if u == 0 else 1
+                    cv = math.sqrt(0.5) if v == 0 else 1
+                    s += (
+                        cu * cv * coef[v * 8 + u]
+                        * math.cos((2 * x + 1) * u * math.pi / 16)
+                        * math.cos((2 * y + 1) * v * math.pi / 16)
+                    )
+            out.append(min(max(round(s / 4 + 128), 0), 255))
+    return out
+
+
+def clamp8(v):
+    return min(max(int(math.floor(v + 0.5)), 0), 255)
+
+
+class Jpeg:
+    """Quantized coefficients of `pixels`, `w` by `h` RGB, with `sampling`
+    the luma factors, or `None` for gray"""
+
+    def __init__(self, pixels, w, h, sampling):
+        self.w, self.h = w, h
+        if sampling is None:
+            planes = [[0.299 * r + 0.587 * g + 0.114 * b for r, g, b in pixels]]
+            self.factors = [(1, 1)]
+        else:
+            planes = [
+                [0.299 * r + 0.587 * g + 0.114 * b for r, g, b in pixels],
+                [-0.168736 * r - 0.331264 * g + 0.5 * b + 128 for r, g, b in pixels],
+                [0.5 * r - 0.418688 * g - 0.081312 * b + 128 for r, g, b in pixels],
+            ]
+            self.factors = [sampling, (1, 1), (1, 1)]
+        self.hmax = max(f[0] for f in self.factors)
+        self.vmax = max(f[1] for f in self.factors)
+        self.mcus = (-(-w // (8 * self.hmax)), -(-h // (8 * self.vmax)))
+        self.quant = [quality(LUMA_Q), quality(CHROMA_Q)]
+        # Blocks of each component, rows of them over the padded MCU grid
+        self.blocks = []
+        for i, plane in enumerate(planes):
+            fh, fv = self.factors[i]
+            bw, bh = self.mcus[0] * fh, self.mcus[1] * fv
+            # Box filter down, then pad by repeating the edge
+            sx, sy = self.hmax // fh, self.vmax // fv
+            cw, ch = -(-w // sx), -(-h // sy)
+
+            def sample(x, y):
+                x, y = min(x, cw - 1), min(y, ch - 1)
+                vals = [
+                    plane[min(y * sy + j, h - 1) * w + min(x * sx + k, w - 1)]
+                    for j in range(sy)
+                    for k in range(sx)
+                ]
+                return sum(vals) / len(vals)
+
+            q = self.quant[min(i, 1)]
+            grid = []
+            for by in range(bh):
+                row = []
+                for bx in range(bw):
+                    block = [
+                        sample(bx * 8 + x, by * 8 + y) - 128 for y in range(8) for x in range(8)
+                    ]
+                    coef = fdct(block)
+                    row.append([round(coef[ZIGZAG[k]] / q[k]) for k in range(64)])
+                grid.append(row)
+            self.blocks.append((grid, (cw, ch)))
+
+    def reference(self):
+        """The RGBA a float decoder gets, with chroma repeated"""
+        planes = []
+        for i, (grid, _) in enumerate(self.blocks):
+            q = self.quant[min(i, 1)]
+            stride = len(grid[0]) * 8
+            plane = [0] * (stride * len(grid) * 8)
+            for by, row in enumerate(grid):
+                for bx, zz in enumerate(row):
+                    coef = [0] * 64
+                    for k in range(64):
+                        coef[ZIGZAG[k]] = zz[k] * q[k]
+                    for j, v in enumerate(idct(coef)):
+                        plane[(by * 8 + j // 8) * stride + bx * 8 + j % 8] = v
+            planes.append((plane, stride))
+        out = bytearray()
+        for y in range(self.h):
+            for x in range(self.w):
+                s = []
+                for i, (plane, stride) in enumerate(planes):
+                    fh, fv = self.factors[i]
+                    s.append(plane[(y * fv // self.vmax) * stride + x * fh // self.hmax])
+                if len(s) == 1:
+                    out += bytes([s[0]] * 3 + [255])
+                    continue
+                l, cb, cr = s[0], s[1] - 128, s[2] - 128
+                out += bytes(
+                    [
+                        clamp8(l + 1.402 * cr),
+                        clamp8(l - 0.344136 * cb - 0.714136 * cr),
+                        clamp8(l + 1.772 * cb),
+                        255,
+                    ]
+                )
+        return bytes(out)
+
+    def header(self, sof, tables):
+        out = bytearray(b"\xff\xd8")
+        out += segment(0xE0, b"JFIF\x00\x01\x01\x00\x00\x01\x00\x01\x00\x00")
+        for i, q in enumerate(self.quant[: min(len(self.blocks), 2)]):
+            out += segment(0xDB, bytes([i] + q))
+        frame = bytes([8]) + self.h.to_bytes(2, "big") + self.w.to_bytes(2, "big")
+        frame += bytes([len(self.blocks)])
+        for i, (fh, fv) in enumerate(self.factors):
+            frame += bytes([i + 1, fh << 4 | fv, min(i, 1)])
+        out += segment(sof, frame)
+        for tc_th, (counts, values) in tables:
+            out += segment(0xC4, bytes([tc_th] + counts) + bytes(values))
+        return out
+
+    def order(self):
+        """Component and block of each block in interleaved order"""
+        single = len(self.blocks) == 1
+        for my in range(self.mcus[1]):
+            for mx in range(self.mcus[0]):
+                yield my, mx, [
+                    (i, (my * fv + j, mx * fh + k))
+                    for i, (fh, fv) in enumerate((1, 1) if single else f for f in self.factors)
+                    for j in range(fv)
+                    for k in range(fh)
+                ]
+
+    def baseline(self, restart=0):
+        tables = [(0x00, DC_LUMA), (0x10, AC_LUMA), (0x01, DC_CHROMA), (0x11, AC_CHROMA)]
+        out = self.header(0xC0, tables[: 2 * min(len(self.blocks), 2)])
+        if restart:
+            out += segment(0xDD, restart.to_bytes(2, "big"))
+        out += scan(self.blocks, [(i, min(i, 1) * 0x11) for i in range(len(self.blocks))], 0, 63)
+        dc = [codes(DC_LUMA), codes(DC_CHROMA)]
+        ac = [codes(AC_LUMA), codes(AC_CHROMA)]
+        bits, preds, count = Bits(), [0] * 3, 0
+        for _, _, blocks in self.order():
+            if restart and count and count % restart == 0:
+                bits.flush()
+                bits.out += bytes([0xFF, 0xD0 + (count // restart - 1) % 8])
+                preds = [0] * 3
+            count += 1
+            for i, (by, bx) in blocks:
+                zz = self.blocks[i][0][by][bx]
+                t = min(i, 1)
+                encode_dc(bits, dc[t], zz[0] - preds[i])
+                preds[i] = zz[0]
+                encode_ac(bits, ac[t], zz[1:])
+        bits.flush()
+        return bytes(out + bits.out + b"\xff\xd9")
+
+    def progressive(self):
+        """A DC scan of every component, then one AC scan of each"""
+        tables = [(0x00, DC_LUMA), (0x10, AC_LUMA)]
+        out = self.header(0xC2, tables)
+        dc, ac = codes(DC_LUMA), codes(AC_LUMA)
+        comps = len(self.blocks)
+        out += scan(self.blocks, [(i, 0x00) for i in range(comps)], 0, 0)
+        bits, preds = Bits(), [0] * 3
+        for _, _, blocks in self.order():
+            for i, (by, bx) in blocks:
+                zz = self.blocks[i][0][by][bx]
+                encode_dc(bits, dc, zz[0] - preds[i])
+                preds[i] = zz[0]
+        bits.flush()
+        out += bits.out
+        for i, (grid, (cw, ch)) in enumerate(self.blocks):
+            out += scan(self.blocks, [(i, 0x00)], 1, 63)
+            bits = Bits()
+            # Non interleaved, so just the blocks covering the component
+            for by in range(-(-ch // 8)):
+                for bx in range(-(-cw // 8)):
+                    encode_ac(bits, ac, grid[by][bx][1:])
+            bits.flush()
+            out += bits.out
+        return bytes(out + b"\xff\xd9")
+
+
+def segment(marker, body):
+    return bytes([0xFF, marker]) + (len(body) + 2).to_bytes(2, "big") + body
+
+
+def scan(blocks, comps, ss, se):
+    body = bytes([len(comps)])
+    for i, tables in comps:
+        body += bytes([i + 1, tables])
+    return segment(0xDA, body + bytes([ss, se, 0]))
+
+
+def encode_dc(bits, table, diff):
+    code, length = table[category(diff)]
+    bits.put(code, length)
+    put_value(bits, diff)
+
+
+def encode_ac(bits, table, ac):
+    run = 0
+    last = max((k for k, v in enumerate(ac) if v), default=-1)
+    for k, v in enumerate(ac[: last + 1]):
+        if v == 0:
+            run += 1
+            continue
+        while run > 15:
+            bits.put(*table[0xF0])
+            run -= 16
+        code, length = table[run << 4 | category(v)]
+        bits.put(code, length)
+        put_value(bits, v)
+        run = 0
+    if last < 62:
+        bits.put(*table[0x00])
+
+
+def picture(w, h):
+    """Soft diagonal gradients, with one sharp edge so the AC matters"""
+    out = []
+    for y in range(h):
+        for x in range(w):
+            edge = 60 if x > w // 2 else 0
+            out.append(
+                (
+                    min(255, 40 + 180 * x // max(w - 1, 1) + edge // 2),
+                    60 + 120 * y // max(h - 1, 1),
+                    min(255, 200 - 150 * (x + y) // max(w + h - 2, 1) + edge),
+                )
+            )
+    return out
+
+
+def main():
+    here = os.path.dirname(os.path.abspath(__file__))
+
+    def write(name, data):
+        with open(os.path.join(here, name), "wb") as f:
+            f.write(data)
+
+    for name, (w, h), sampling, restart in [
+        ("gray", (11, 9), None, 0),
+        ("444", (16, 8), (1, 1), 0),
+        ("422", (13, 8), (2, 1), 0),
+        ("420", (19, 21), (2, 2), 1),
+    ]:
+        jpeg = Jpeg(picture(w, h), w, h, sampling)
+        write(f"{name}.jpg", jpeg.baseline(restart))
+        write(f"{name}.rgba", jpeg.reference())
+    write("progressive.jpg", Jpeg(picture(16, 16), 16, 16, (2, 2)).progressive())
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,685 @@
+//! Drawing directly into framebuffers
+use core::slice::from_raw_parts_mut;
+
+use crate::{
+    alpha_converter,
+    composite::{from_linear_premul, over, to_linear_premul},
+    font::{advance, line_cells, BitmapFont, TextAlign, TextLayout},
+    layout::{prepare, validate_buffer},
+    AlphaMode, ColorSpace, Image, ImageError, Orientation, PixelFormat, ResXY, WorkPixel, XY,
+};
+
+/// A byte buffer, like a GOP framebuffer, used as a drawing target
+///
+/// Pixels are converted as they're written, nothing here allocates. All
+/// drawing is clipped to the target, and nothing is ever written outside
+/// `res`, including any padding at the end of rows.
+#[derive(Debug)]
+pub struct FramebufferTarget<'a> {
+    data: &'a mut [u8],
+    res: ResXY,
+    stride: usize,
+    format: PixelFormat,
+    color: ColorSpace,
+}
+
+impl<'a> FramebufferTarget<'a> {
+    /// Draw into `data`, of `res` pixels in the format `format`, with rows
+    /// `stride` bytes apart
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `stride` is smaller than a row
+    /// - [`ImageError::BufferSize`] if `data` is too small
+    pub fn new(
+        data: &'a mut [u8],
+        res: ResXY,
+        stride: usize,
+        format: PixelFormat,
+        color: ColorSpace,
+    ) -> Result<Self, ImageError> {
+        validate_buffer(data, format, res, stride)?;
+        Ok(Self {
+            data,
+            res,
+            stride,
+            format,
+            color,
+        })
+    }
+
+    /// Like [`FramebufferTarget::new`], but for `len` bytes at `ptr`, such as
+    /// a memory mapped framebuffer
+    ///
+    /// Writes are ordinary, not volatile, which is fine for framebuffers
+    /// but not for device registers.
+    ///
+    /// # Safety
+    ///
+    /// - `ptr` must be non-null, and valid for reads and writes of `len` bytes
+    ///   for all of `'a`
+    /// - Nothing else may access that memory during `'a`, except for the
+    ///   display hardware reading it
+    ///
+    /// # Errors
+    ///
+    /// See [`FramebufferTarget::new`]
+    pub unsafe fn from_raw_parts(
+        ptr: *mut u8,
+        len: usize,
+        res: ResXY,
+        stride: usize,
+        format: PixelFormat,
+        color: ColorSpace,
+    ) -> Result<Self, ImageError> {
+        // Safety: Upheld by the caller
+        let data = unsafe { from_raw_parts_mut(ptr, len) };
+        Self::new(data, res, stride, format, color)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.res.0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.res.1
+    }
+
+    pub fn color(&self) -> ColorSpace {
+        self.color
+    }
+
+    /// Byte offset of the pixel at `xy`, which must be in bounds
+    fn offset(&self, (x, y): XY) -> usize {
+        y as usize * self.stride + x as usize * self.format.bytes_per_pixel()
+    }
+
+    /// Clip the rectangle at `origin` of `size` to the target, as
+    /// `(x, y)` ranges
+    fn clip(&self, (x, y): XY, (w, h): ResXY) -> (core::ops::Range<u32>, core::ops::Range<u32>) {
+        let x1 = x.saturating_add(w).min(self.res.0);
+        let y1 = y.saturating_add(h).min(self.res.1);
+        (x.min(x1)..x1, y.min(y1)..y1)
+    }
+
+    /// Encode the straight alpha `p` into `out`
+    fn encode(&self, p: WorkPixel, out: &mut [u8]) {
+        let p = prepare(p, self.format, AlphaMode::Straight, self.color.transfer());
+        self.format.encode(p, out);
+    }
+
+    /// Set the pixel at `xy` to the straight alpha `p`, ignoring it if out
+    /// of bounds
+    pub fn set_pixel(&mut self, xy: XY, p: impl Into<WorkPixel>) {
+        let p = p.into();
+        if xy.0 < self.res.0 && xy.1 < self.res.1 {
+            let (i, bpp) = (self.offset(xy), self.format.bytes_per_pixel());
+            let mut bytes = [0; 8];
+            self.encode(p, &mut bytes[..bpp]);
+            self.data[i..i + bpp].copy_from_slice(&bytes[..bpp]);
+        }
+    }
+
+    /// Fill the rectangle at `origin` of `size` with the straight alpha `p`
+    ///
+    /// Pixels are replaced, not blended.
+    pub fn fill_rect(&mut self, origin: XY, size: ResXY, p: impl Into<WorkPixel>) {
+        let p = p.into();
+        let (xs, ys) = self.clip(origin, size);
+        let bpp = self.format.bytes_per_pixel();
+        let mut bytes = [0; 8];
+        self.encode(p, &mut bytes[..bpp]);
+        for y in ys {
+            let start = self.offset((xs.start, y));
+            let end = self.offset((xs.end, y));
+            for o in self.data[start..end].chunks_exact_mut(bpp) {
+                o.copy_from_slice(&bytes[..bpp]);
+            }
+        }
+    }
+
+    /// Draw `text` with its top left corner at `at`, setting glyph pixels to
+    /// the straight alpha `p`
+    ///
+    /// `'\n'` starts a new line, and tabs go to the next multiple of four
+    /// characters. Characters missing from `font` are blank. See
+    /// [`FramebufferTarget::draw_text_with`] for scaling and wrapping.
+    pub fn draw_text(&mut self, text: &str, at: XY, font: &BitmapFont, p: impl Into<WorkPixel>) {
+        let p = p.into();
+        self.draw_text_with(text, at, font, p, &TextLayout::default())
+    }
+
+    /// [`FramebufferTarget::draw_text`], laid out with `layout`
+    ///
+    /// Lines are exactly those of [`BitmapFont::measure`] with the same
+    /// scale and width, so the text fits in the measured size. They're
+    /// aligned within `layout.max_width`, or the measured width without one,
+    /// rounding down when centering.
+    pub fn draw_text_with(
+        &mut self,
+        text: &str,
+        at: XY,
+        font: &BitmapFont,
+        p: impl Into<WorkPixel>,
+        layout: &TextLayout,
+    ) {
+        let p = p.into();
+        let scale = layout.scale.max(1);
+        let metrics = font.measure(text, scale, layout.max_width);
+        let (gw, gh) = font.glyph_size();
+        let (cw, ch) = (gw * scale, gh * scale);
+        let block = layout.max_width.unwrap_or(0).max(metrics.width);
+        let mut y = at.1;
+        for line in metrics.lines {
+            let line = &text[line];
+            let width = line_cells(line) * cw;
+            let mut x = at.0.saturating_add(match layout.align {
+                TextAlign::Left => 0,
+                TextAlign::Center => (block - width) / 2,
+                TextAlign::Right => block - width,
+            });
+            let mut col = 0;
+            for c in line.chars() {
+                let next = advance(col, c);
+                // Tabs are only space
+                for gy in (0..gh).filter(|_| c != '\t') {
+                    for gx in 0..gw {
+                        if font.pixel(c, (gx, gy)) {
+                            let origin =
+                                (x.saturating_add(gx * scale), y.saturating_add(gy * scale));
+                            self.fill_rect(origin, (scale, scale), p);
+                        }
+                    }
+                }
+                x = x.saturating_add((next - col) * cw);
+                col = next;
+            }
+            y = y.saturating_add(ch);
+        }
+    }
+
+    /// Copy `src` into the target in `orientation`, with its top left
+    /// corner at `at`, which may be negative
+    ///
+    /// Pixels are replaced, not blended. `src` is remapped as it's
+    /// written, so it's never rotated in memory, and the result is exactly
+    /// that of drawing it after [`Image::flip_horizontal`] and
+    /// [`Image::rotate`], see [`Orientation`].
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::ColorSpaceMismatch`] if `src` has a different color
+    ///   space
+    pub fn blit(
+        &mut self,
+        src: &Image,
+        at: (i32, i32),
+        orientation: Orientation,
+    ) -> Result<(), ImageError> {
+        self.draw(src, (at.0.into(), at.1.into()), orientation, false)
+    }
+
+    /// Composite `src` over the target, with its top left corner at `at`,
+    /// like [`Image::overlay`]
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::ColorSpaceMismatch`] if `src` has a different color
+    ///   space
+    pub fn overlay(&mut self, src: &Image, at: XY) -> Result<(), ImageError> {
+        self.draw(src, (at.0.into(), at.1.into()), Orientation::IDENTITY, true)
+    }
+
+    /// [`FramebufferTarget::overlay`], in `orientation`, at an `at` which
+    /// may be negative, like [`FramebufferTarget::blit`]
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::ColorSpaceMismatch`] if `src` has a different color
+    ///   space
+    pub fn overlay_oriented(
+        &mut self,
+        src: &Image,
+        at: (i32, i32),
+        orientation: Orientation,
+    ) -> Result<(), ImageError> {
+        self.draw(src, (at.0.into(), at.1.into()), orientation, true)
+    }
+
+    /// Draw `src` in `orientation` at `at`, compositing it over the target
+    /// if `blend`, and replacing pixels otherwise
+    fn draw(
+        &mut self,
+        src: &Image,
+        at: (i64, i64),
+        orientation: Orientation,
+        blend: bool,
+    ) -> Result<(), ImageError> {
+        if src.color != self.color {
+            return Err(ImageError::ColorSpaceMismatch);
+        }
+        let transfer = self.color.transfer();
+        let (decode, encode) = (transfer.map(|t| t.0), transfer.map(|t| t.1));
+        let to_straight = alpha_converter(src.alpha, AlphaMode::Straight);
+        let bpp = self.format.bytes_per_pixel();
+        let (w, h) = orientation.oriented_res(src.res);
+        // Inside the target, and relative to `at`
+        let clip = |at: i64, len: u32, end: u32| {
+            let start = at.clamp(0, end.into());
+            (start as u32)..((at + i64::from(len)).clamp(start, end.into()) as u32)
+        };
+        let (xs, ys) = (clip(at.0, w, self.res.0), clip(at.1, h, self.res.1));
+        for y in ys {
+            for x in xs.clone() {
+                let local = ((x as i64 - at.0) as u32, (y as i64 - at.1) as u32);
+                let (sx, sy) = orientation.source(local, src.res);
+                let s = src.data[(sy * src.width() + sx) as usize];
+                let i = self.offset((x, y));
+                let p = if blend {
+                    let d = self.format.decode(&self.data[i..i + bpp]);
+                    let s = to_linear_premul(s, decode, src.alpha);
+                    let d = to_linear_premul(d, decode, AlphaMode::Straight);
+                    from_linear_premul(over(s, d), encode, AlphaMode::Straight)
+                } else {
+                    to_straight(s)
+                };
+                let mut bytes = [0; 8];
+                self.encode(p, &mut bytes[..bpp]);
+                self.data[i..i + bpp].copy_from_slice(&bytes[..bpp]);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{vec, vec::Vec};
+
+    use super::*;
+    use crate::fixtures::{noise, photo};
+
+    const RES: ResXY = (6, 5);
+    /// Two pixels of padding on every row
+    const STRIDE: usize = 6 * 4 + 8;
+    const PAD: u8 = 0xee;
+    const RED: [f32; 4] = [1., 0., 0., 1.];
+
+    /// A padded buffer of `RES` filled with `PAD`
+    fn buffer() -> Vec<u8> {
+        vec![PAD; STRIDE * RES.1 as usize]
+    }
+
+    fn target(buf: &mut [u8]) -> FramebufferTarget<'_> {
+        FramebufferTarget::new(buf, RES, STRIDE, PixelFormat::Rgba8888, ColorSpace::sRGB).unwrap()
+    }
+
+    /// Which pixels of `buf` are red
+    fn red(buf: &[u8]) -> Vec<Vec<bool>> {
+        (0..RES.1 as usize)
+            .map(|y| {
+                (0..RES.0 as usize)
+                    .map(|x| buf[y * STRIDE + x * 4..][..4] == [255, 0, 0, 255])
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Exactly the pixels right of and below `corner` are red
+    fn check_red_from(buf: &[u8], corner: (usize, usize)) {
+        for (y, row) in red(buf).iter().enumerate() {
+            for (x, &r) in row.iter().enumerate() {
+                assert_eq!(r, x >= corner.0 && y >= corner.1, "{x}, {y}");
+            }
+        }
+    }
+
+    /// The padding is still untouched
+    fn check_padding(buf: &[u8]) {
+        for row in buf.chunks(STRIDE) {
+            assert!(row[RES.0 as usize * 4..].iter().all(|&b| b == PAD));
+        }
+    }
+
+    #[test]
+    fn construction() {
+        let mut buf = buffer();
+        let short = STRIDE * 4 + 6 * 4 - 1;
+        assert_eq!(
+            FramebufferTarget::new(
+                &mut buf[..short],
+                RES,
+                STRIDE,
+                PixelFormat::Rgba8888,
+                ColorSpace::sRGB
+            )
+            .err(),
+            Some(ImageError::BufferSize {
+                expected: short + 1,
+                actual: short
+            })
+        );
+        assert_eq!(
+            FramebufferTarget::new(&mut buf, RES, 23, PixelFormat::Rgba8888, ColorSpace::sRGB)
+                .err(),
+            Some(ImageError::InvalidArgument)
+        );
+        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+        // Safety: `buf` outlives the target, and isn't used until it's gone
+        let mut fb = unsafe {
+            FramebufferTarget::from_raw_parts(
+                ptr,
+                len,
+                RES,
+                STRIDE,
+                PixelFormat::Rgba8888,
+                ColorSpace::sRGB,
+            )
+        }
+        .unwrap();
+        assert_eq!((fb.width(), fb.height()), RES);
+        fb.set_pixel((1, 1), RED);
+        assert!(red(&buf)[1][1]);
+    }
+
+    #[test]
+    fn fill_clips_at_every_edge() {
+        let mut buf = buffer();
+        let mut fb = target(&mut buf);
+        // Hanging off the right and bottom
+        fb.fill_rect((4, 3), (10, 10), RED);
+        // Huge, from the origin, then empty and fully outside
+        let mut other = buffer();
+        let mut full = target(&mut other);
+        full.fill_rect((0, 0), (u32::MAX, u32::MAX), RED);
+        full.fill_rect((6, 0), (3, 3), [0., 1., 0., 1.]);
+        full.fill_rect((0, 5), (3, 3), [0., 1., 0., 1.]);
+        full.fill_rect((2, 2), (0, 0), [0., 1., 0., 1.]);
+        full.set_pixel((6, 0), [0., 1., 0., 1.]);
+        full.set_pixel((0, 5), [0., 1., 0., 1.]);
+
+        check_red_from(&buf, (4, 3));
+        assert!(red(&other).iter().flatten().all(|&r| r));
+        check_padding(&buf);
+        check_padding(&other);
+        // Only the filled area was written
+        assert_eq!(buf[..4], [PAD; 4]);
+    }
+
+    #[test]
+    fn text_clips() {
+        // One solid glyph
+        let glyphs = [0xff; 8];
+        let font = BitmapFont::new(&glyphs, (8, 8), 'A').unwrap();
+        let mut buf = buffer();
+        let mut fb = target(&mut buf);
+        fb.draw_text("A", (3, 2), &font, RED);
+        fb.draw_text("AA", (100, 100), &font, RED);
+        check_red_from(&buf, (3, 2));
+        check_padding(&buf);
+    }
+
+    #[test]
+    fn overlay_matches_image() {
+        let bg = photo(RES);
+        let mut seed = 3;
+        let data = (0..12)
+            .map(|_| {
+                let a = noise(&mut seed);
+                [noise(&mut seed), noise(&mut seed), noise(&mut seed), a]
+            })
+            .collect();
+        let mut sprite = Image::from_parts(data, (4, 3), ColorSpace::sRGB);
+
+        for alpha in [AlphaMode::Straight, AlphaMode::Premultiplied] {
+            sprite.to_alpha_mode(alpha);
+            // Hanging off the bottom right
+            let at = (3, 3);
+            let mut want = bg.clone();
+            want.overlay(&sprite, at).unwrap();
+            let want = want.to_bytes();
+
+            let mut buf = buffer();
+            for (row, src) in buf.chunks_mut(STRIDE).zip(bg.to_bytes().chunks(6 * 4)) {
+                row[..6 * 4].copy_from_slice(src);
+            }
+            target(&mut buf).overlay(&sprite, at).unwrap();
+            check_padding(&buf);
+            for (row, want) in buf.chunks(STRIDE).zip(want.chunks(6 * 4)) {
+                for (g, w) in row.iter().zip(want) {
+                    assert!(g.abs_diff(*w) <= 1, "{alpha:?} {g} {w}");
+                }
+            }
+        }
+
+        let linear = Image::from_parts(vec![RED; 4], (2, 2), ColorSpace::sRGBLinear);
+        let mut buf = buffer();
+        assert_eq!(
+            target(&mut buf).overlay(&linear, (0, 0)),
+            Err(ImageError::ColorSpaceMismatch)
+        );
+        assert!(buf.iter().all(|&b| b == PAD));
+    }
+
+    /// Every orientation
+    fn orientations() -> impl Iterator<Item = Orientation> {
+        use crate::Rotation::*;
+        [R0, R90, R180, R270]
+            .into_iter()
+            .flat_map(|r| [false, true].map(|m| Orientation::new(r, m)))
+    }
+
+    /// `img` flipped and rotated into `o`, the slow way
+    fn oriented(img: &Image, o: Orientation) -> Image {
+        let mut img = img.clone();
+        if o.mirror {
+            img.flip_horizontal();
+        }
+        img.rotate(o.rotation);
+        img
+    }
+
+    /// A 4x3 sprite of patterned opaque pixels
+    fn sprite() -> Image {
+        let data: Vec<u8> = (0..12u8)
+            .flat_map(|i| [i * 20, 255 - i * 20, i * 7, 255])
+            .collect();
+        Image::from_bytes(&data, (4, 3), ColorSpace::sRGB)
+    }
+
+    #[test]
+    fn blit_matches_rotated_copies() {
+        let src = sprite();
+        for o in orientations() {
+            let copy = oriented(&src, o);
+            let bytes = copy.to_bytes();
+            let (w, h) = (copy.width() as i32, copy.height() as i32);
+            // Inside, hanging off every edge, and fully outside
+            for at in [(1, 1), (-2, -1), (4, 3), (-1, 3), (2, -2), (6, 0), (-4, -4)] {
+                let mut buf = buffer();
+                target(&mut buf).blit(&src, at, o).unwrap();
+                check_padding(&buf);
+                for y in 0..RES.1 as i32 {
+                    for x in 0..RES.0 as i32 {
+                        let (sx, sy) = (x - at.0, y - at.1);
+                        let want = if (0..w).contains(&sx) && (0..h).contains(&sy) {
+                            let i = (sy * w + sx) as usize * 4;
+                            bytes[i..i + 4].try_into().unwrap()
+                        } else {
+                            [PAD; 4]
+                        };
+                        let i = y as usize * STRIDE + x as usize * 4;
+                        assert_eq!(buf[i..i + 4], want, "{o:?} {at:?} {x} {y}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn overlay_oriented_matches_rotated_copies() {
+        let mut seed = 5;
+        let data = (0..12)
+            .map(|_| {
+                let a = noise(&mut seed);
+                [noise(&mut seed), noise(&mut seed), noise(&mut seed), a]
+            })
+            .collect();
+        let src = Image::from_parts(data, (4, 3), ColorSpace::sRGB);
+        let bg = photo(RES);
+        let fill = |buf: &mut [u8]| {
+            for (row, src) in buf.chunks_mut(STRIDE).zip(bg.to_bytes().chunks(6 * 4)) {
+                row[..6 * 4].copy_from_slice(src);
+            }
+        };
+        for o in orientations() {
+            let copy = oriented(&src, o);
+            for at in [(0, 0), (3, 2), (-1, -2)] {
+                let (mut got, mut want) = (buffer(), buffer());
+                fill(&mut got);
+                fill(&mut want);
+                target(&mut got).overlay_oriented(&src, at, o).unwrap();
+                target(&mut want)
+                    .overlay_oriented(&copy, at, Orientation::IDENTITY)
+                    .unwrap();
+                assert_eq!(got, want, "{o:?} {at:?}");
+                if at.0 >= 0 && at.1 >= 0 {
+                    let mut plain = buffer();
+                    fill(&mut plain);
+                    target(&mut plain)
+                        .overlay(&copy, (at.0 as u32, at.1 as u32))
+                        .unwrap();
+                    assert_eq!(got, plain, "{o:?} {at:?}");
+                }
+            }
+        }
+        let linear = Image::from_parts(vec![RED; 4], (2, 2), ColorSpace::sRGBLinear);
+        let mut buf = buffer();
+        assert_eq!(
+            target(&mut buf).blit(&linear, (0, 0), Orientation::IDENTITY),
+            Err(ImageError::ColorSpaceMismatch)
+        );
+        assert!(buf.iter().all(|&b| b == PAD));
+    }
+
+    /// Solid 3x4 glyphs for `'!'` to `'~'`, so space is blank
+    const BLOCKS: [u8; 94 * 4] = [0b1110_0000; 94 * 4];
+    const PANEL: ResXY = (41, 30);
+
+    /// Which pixels `text` sets, drawn at `at` in a `PANEL`
+    fn render(text: &str, at: XY, layout: &TextLayout) -> Vec<bool> {
+        let font = BitmapFont::new(&BLOCKS, (3, 4), '!').unwrap();
+        let mut buf = vec![0; PANEL.0 as usize * PANEL.1 as usize * 4];
+        let stride = PANEL.0 as usize * 4;
+        let mut fb = FramebufferTarget::new(
+            &mut buf,
+            PANEL,
+            stride,
+            PixelFormat::Rgba8888,
+            ColorSpace::sRGB,
+        )
+        .unwrap();
+        fb.draw_text_with(text, at, &font, RED, layout);
+        buf.chunks_exact(4).map(|p| p == [255, 0, 0, 255]).collect()
+    }
+
+    /// Bounding box of set pixels, as origin and size
+    fn bbox(set: &[bool]) -> (XY, ResXY) {
+        let w = PANEL.0;
+        let (mut lo, mut hi) = ((u32::MAX, u32::MAX), (0, 0));
+        for (i, _) in (0u32..).zip(set).filter(|(_, s)| **s) {
+            let (x, y) = (i % w, i / w);
+            lo = (lo.0.min(x), lo.1.min(y));
+            hi = (hi.0.max(x + 1), hi.1.max(y + 1));
+        }
+        (lo, (hi.0 - lo.0, hi.1 - lo.1))
+    }
+
+    #[test]
+    fn text_fills_its_measured_box() {
+        let font = BitmapFont::new(&BLOCKS, (3, 4), '!').unwrap();
+        for (text, scale, max_width) in [
+            ("Hello", 1, None),
+            ("two\nlines!", 1, None),
+            ("a\tb", 1, None),
+            ("hello world", 1, Some(18)),
+            ("wrapped mid-wordsarelong", 1, Some(20)),
+            ("big", 3, None),
+            ("xy z", 2, Some(12)),
+        ] {
+            let layout = TextLayout {
+                scale,
+                max_width,
+                ..TextLayout::default()
+            };
+            let m = font.measure(text, scale, max_width);
+            let (origin, size) = bbox(&render(text, (2, 1), &layout));
+            assert_eq!(origin, (2, 1), "{text:?}");
+            assert_eq!(size, (m.width, m.height), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn text_wraps_like_measure() {
+        let font = BitmapFont::new(&BLOCKS, (3, 4), '!').unwrap();
+        let text = "ab cdefghijk l\tm\n\nno pq";
+        for align in [TextAlign::Left, TextAlign::Center, TextAlign::Right] {
+            let layout = TextLayout {
+                scale: 1,
+                align,
+                max_width: Some(20),
+            };
+            let m = font.measure(text, 1, Some(20));
+            let mut want = vec![false; PANEL.0 as usize * PANEL.1 as usize];
+            for (i, line) in (0u32..).zip(&m.lines) {
+                let line = &text[line.clone()];
+                let slack = 20 - line_cells(line) * 3;
+                let x0 = match align {
+                    TextAlign::Left => 0,
+                    TextAlign::Center => slack / 2,
+                    TextAlign::Right => slack,
+                };
+                let mut col = 0;
+                for c in line.chars() {
+                    for y in (i * 4..i * 4 + 4).filter(|_| !c.is_whitespace()) {
+                        for x in x0 + col * 3..x0 + col * 3 + 3 {
+                            want[(y * PANEL.0 + x) as usize] = true;
+                        }
+                    }
+                    col = advance(col, c);
+                }
+            }
+            assert_eq!(render(text, (0, 0), &layout), want, "{align:?}");
+        }
+    }
+
+    #[test]
+    fn text_centers_odd_widths() {
+        // Lines of 1, 2, and 3 glyphs in 11 pixels
+        let layout = TextLayout {
+            scale: 1,
+            align: TextAlign::Center,
+            max_width: Some(11),
+        };
+        let set = render("a\nbb\nccc", (5, 0), &layout);
+        let row = |y: u32| bbox(&set[(y * PANEL.0) as usize..][..PANEL.0 as usize]);
+        assert_eq!(row(0), ((5 + 4, 0), (3, 1)));
+        assert_eq!(row(4), ((5 + 2, 0), (6, 1)));
+        assert_eq!(row(8), ((5 + 1, 0), (9, 1)));
+        // Without a width, within the widest line
+        let layout = TextLayout {
+            max_width: None,
+            ..layout
+        };
+        let set = render("a\nccc", (0, 0), &layout);
+        let row = |y: u32| bbox(&set[(y * PANEL.0) as usize..][..PANEL.0 as usize]);
+        assert_eq!(row(0), ((3, 0), (3, 1)));
+        assert_eq!(row(4), ((0, 0), (9, 1)));
+        let right = TextLayout {
+            align: TextAlign::Right,
+            ..layout
+        };
+        let set = render("a\nccc", (0, 0), &right);
+        assert_eq!(bbox(&set[..PANEL.0 as usize]), ((6, 0), (3, 1)));
+    }
+}
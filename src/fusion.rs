@@ -0,0 +1,234 @@
+//! Exposure fusion
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    composite::{from_linear_premul, to_linear_premul},
+    ColorSpace, Image, ImageError, ResXY, F32,
+};
+
+/// How [`Image::exposure_fuse_with`] blends frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FusionBlend {
+    /// Weighted average per pixel
+    ///
+    /// Fast, but sharp changes in weight can leave halos and seams.
+    #[default]
+    SingleScale,
+
+    /// Blend each level of a Laplacian pyramid of up to this many levels,
+    /// as in the paper
+    ///
+    /// Seamless, but slower and uses more memory.
+    Pyramid(usize),
+}
+
+/// Mertens weight of each pixel, from contrast, saturation, and how well
+/// exposed it is
+///
+/// This is on the encoded values, clamped to `0..=1`, which is closer to
+/// what looks well exposed.
+fn weights(img: &Image) -> Vec<f32> {
+    const SIGMA: f32 = 0.2;
+    let (w, h) = (img.width() as i64, img.height() as i64);
+    let gray: Vec<f32> = img
+        .data
+        .iter()
+        .map(|p| (p[0] + p[1] + p[2]).clamp(0., 3.) / 3.)
+        .collect();
+    let g = |x: i64, y: i64| gray[(y.clamp(0, h - 1) * w + x.clamp(0, w - 1)) as usize];
+
+    let mut out = Vec::with_capacity(gray.len());
+    for y in 0..h {
+        for x in 0..w {
+            let contrast =
+                (4. * g(x, y) - g(x - 1, y) - g(x + 1, y) - g(x, y - 1) - g(x, y + 1)).abs();
+            let p = img.data[(y * w + x) as usize];
+            let rgb = [p[0], p[1], p[2]].map(|c| c.clamp(0., 1.));
+            let mean = (rgb[0] + rgb[1] + rgb[2]) / 3.;
+            let saturation = (rgb.iter().map(|c| (c - mean) * (c - mean)).sum::<f32>() / 3.).sqrt();
+            let exposed: f32 = rgb
+                .iter()
+                .map(|c| (-(c - 0.5) * (c - 0.5) / (2. * SIGMA * SIGMA)).exp())
+                .product();
+            // Never quite zero, so flat frames still average
+            out.push(contrast * saturation * exposed + 1e-12);
+        }
+    }
+    out
+}
+
+impl Image {
+    /// Fuse bracketed exposures into one well exposed image, see
+    /// [`Image::exposure_fuse_with`]
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `frames` is empty
+    /// - [`ImageError::DimensionMismatch`] if the frames are different sizes
+    /// - [`ImageError::ColorSpaceMismatch`] if the frames have different
+    ///   color spaces
+    pub fn exposure_fuse(frames: &[&Image]) -> Result<Image, ImageError> {
+        Self::exposure_fuse_with(frames, FusionBlend::SingleScale)
+    }
+
+    /// Fuse bracketed exposures, like from a camera burst, into one well
+    /// exposed image using Mertens exposure fusion
+    ///
+    /// Each pixel of each frame is weighted by local contrast, saturation,
+    /// and how close it is to mid gray, then the frames are blended with the
+    /// normalized weights according to `blend`, in linear light. This isn't
+    /// HDR, the result is a normal image in the frames color space, with the
+    /// metadata and alpha mode of the first.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `frames` is empty
+    /// - [`ImageError::DimensionMismatch`] if the frames are different sizes
+    /// - [`ImageError::ColorSpaceMismatch`] if the frames have different
+    ///   color spaces
+    pub fn exposure_fuse_with(frames: &[&Image], blend: FusionBlend) -> Result<Image, ImageError> {
+        let (first, rest) = frames.split_first().ok_or(ImageError::InvalidArgument)?;
+        for f in rest {
+            first.check_blend(f)?;
+        }
+        if frames.len() == 1 {
+            return Ok((*first).clone());
+        }
+        let mut weights: Vec<Vec<f32>> = frames.iter().map(|f| weights(f)).collect();
+        for i in 0..first.data.len() {
+            let sum: f32 = weights.iter().map(|w| w[i]).sum();
+            weights.iter_mut().for_each(|w| w[i] /= sum);
+        }
+
+        match blend {
+            FusionBlend::SingleScale => {
+                let decode = first.color.transfer().map(|t| t.0);
+                let encode = first.color.transfer().map(|t| t.1);
+                let mut out = vec![[0f32; 4]; first.data.len()];
+                for (f, w) in frames.iter().zip(&weights) {
+                    for ((o, p), w) in out.iter_mut().zip(&f.data).zip(w) {
+                        let p = to_linear_premul(*p, decode, f.alpha);
+                        for c in 0..4 {
+                            o[c] += p[c] * w;
+                        }
+                    }
+                }
+                let out = out
+                    .iter()
+                    .map(|p| from_linear_premul(*p, encode, first.alpha));
+                Ok(first.derive(out.collect(), first.res))
+            }
+            FusionBlend::Pyramid(levels) => {
+                let mut out: Vec<Image> = Vec::new();
+                for (f, w) in frames.iter().zip(weights) {
+                    let lap = f.laplacian_pyramid(levels);
+                    // The weights as an image, so they get the same pyramid
+                    let w: Vec<_> = w.into_iter().map(|w| [w, w, w, 1.]).collect();
+                    let w = Image::from_parts(w, first.res, ColorSpace::AsIs);
+                    let gauss = w.gaussian_pyramid(levels);
+                    if out.is_empty() {
+                        out = lap
+                            .iter()
+                            .map(|l| l.derive(vec![[0.; 4]; l.data.len()], l.res))
+                            .collect();
+                    }
+                    for ((o, l), g) in out.iter_mut().zip(&lap).zip(&gauss) {
+                        for ((o, l), g) in o.data.iter_mut().zip(&l.data).zip(&g.data) {
+                            for c in 0..4 {
+                                o[c] += l[c] * g[0];
+                            }
+                        }
+                    }
+                }
+                Ok(Image::collapse(&out))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{max_diff, photo};
+
+    const BLENDS: [FusionBlend; 2] = [FusionBlend::SingleScale, FusionBlend::Pyramid(3)];
+
+    /// Orange stripes a column wide, `dark` on the left half and `bright` on
+    /// the right, each a pair of encoded levels
+    fn stripes(dark: [f32; 2], bright: [f32; 2]) -> Image {
+        let res = (16, 8);
+        let data = (0..res.0 * res.1)
+            .map(|i| {
+                let x = i % res.0;
+                let v = if x < res.0 / 2 { dark } else { bright }[x as usize % 2];
+                [v, v * 0.8, v * 0.6, 1.]
+            })
+            .collect();
+        Image::from_parts(data, res, ColorSpace::sRGB)
+    }
+
+    /// Mean difference between neighboring columns of red, in `0..8` or
+    /// `8..16`, away from the edges of the half
+    fn detail(img: &Image, xs: core::ops::Range<u32>) -> f32 {
+        let red = |x: u32, y: u32| img.data[(y * img.width() + x) as usize][0];
+        let mut sum = 0.;
+        for y in 0..img.height() {
+            for x in xs.start + 1..xs.end - 2 {
+                sum += (red(x, y) - red(x + 1, y)).abs();
+            }
+        }
+        sum / (img.height() * (xs.len() as u32 - 3)) as f32
+    }
+
+    #[test]
+    fn identical_frames() {
+        let img = photo((16, 12));
+        for blend in BLENDS {
+            let out = Image::exposure_fuse_with(&[&img, &img, &img], blend).unwrap();
+            assert!(max_diff(out.pixels(), img.pixels()) < 1e-4, "{blend:?}");
+        }
+    }
+
+    #[test]
+    fn single_frame() {
+        let img = photo((16, 12));
+        for blend in BLENDS {
+            let out = Image::exposure_fuse_with(&[&img], blend).unwrap();
+            assert_eq!(out.pixels(), img.pixels());
+        }
+    }
+
+    #[test]
+    fn recovers_shadows_and_highlights() {
+        let under = stripes([0.02, 0.05], [0.4, 0.6]);
+        let over = stripes([0.35, 0.6], [1., 1.]);
+        for blend in BLENDS {
+            let out = Image::exposure_fuse_with(&[&under, &over], blend).unwrap();
+            let (dark, bright) = (detail(&out, 0..8), detail(&out, 8..16));
+            assert!(dark > 0.5 * detail(&over, 0..8), "{blend:?} {dark}");
+            assert!(bright > 0.5 * detail(&under, 8..16), "{blend:?} {bright}");
+            assert!(dark > 3. * detail(&under, 0..8));
+            assert_eq!(detail(&over, 8..16), 0.);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let img = photo((4, 4));
+        let small = photo((4, 3));
+        let mut linear = img.clone();
+        linear.color = ColorSpace::sRGBLinear;
+        assert_eq!(
+            Image::exposure_fuse(&[]).err(),
+            Some(ImageError::InvalidArgument)
+        );
+        assert_eq!(
+            Image::exposure_fuse(&[&img, &small]).err(),
+            Some(ImageError::DimensionMismatch)
+        );
+        assert_eq!(
+            Image::exposure_fuse(&[&img, &linear]).err(),
+            Some(ImageError::ColorSpaceMismatch)
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Per channel display gamma
+use crate::{ColorSpace, Image, F32};
+
+/// Entries in each [`Lut`], past the first
+const STEPS: usize = 1024;
+
+/// `c.powf(exp)`, interpolated from a table
+///
+/// The table is indexed by `sqrt(c)`, so it has more entries near black,
+/// where `1 / 2.2` style exponents are steepest.
+struct Lut {
+    table: [f32; STEPS + 1],
+    exp: f32,
+}
+
+impl Lut {
+    fn new(exp: f32) -> Self {
+        let mut table = [0.; STEPS + 1];
+        for (i, v) in table.iter_mut().enumerate() {
+            let t = i as f32 / STEPS as f32;
+            *v = (t * t).powf(exp);
+        }
+        Self { table, exp }
+    }
+
+    /// Values outside `0..=1` are computed exactly, sign preserving like
+    /// [`crate::transforms`]
+    fn get(&self, c: f32) -> f32 {
+        if !(0. ..=1.).contains(&c) {
+            return c.abs().powf(self.exp).copysign(c);
+        }
+        let t = c.sqrt() * STEPS as f32;
+        let i = (t as usize).min(STEPS - 1);
+        let f = t - i as f32;
+        self.table[i] + (self.table[i + 1] - self.table[i]) * f
+    }
+}
+
+impl Image {
+    /// Pre-compensate for a display with its own gamma per channel
+    ///
+    /// The image is converted to linear light, then each of R, G, and B is
+    /// raised to `1 / gamma` of that channel. The result is tagged
+    /// [`ColorSpace::AsIs`], ready to send to the panel as is.
+    pub fn encode_for_display(&mut self, gammas: [f32; 3]) {
+        self.to_color(ColorSpace::sRGBLinear);
+        self.apply_gammas(gammas.map(|g| 1. / g));
+        self.color = ColorSpace::AsIs;
+    }
+
+    /// Undo [`Image::encode_for_display`], treating the image as encoded for
+    /// a display with `gammas`, and converting it to `color`
+    pub fn decode_from_display(&mut self, gammas: [f32; 3], color: ColorSpace) {
+        self.apply_gammas(gammas);
+        self.color = ColorSpace::sRGBLinear;
+        self.to_color(color);
+    }
+
+    /// Raise each color channel to its exponent in `exps`
+    fn apply_gammas(&mut self, exps: [f32; 3]) {
+        let luts = exps.map(Lut::new);
+        for p in &mut self.data {
+            for (c, lut) in p.iter_mut().zip(&luts) {
+                *c = lut.get(*c);
+            }
+        }
+        self.check();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{max_diff, photo, solid},
+        transforms::rgb_to_gamma,
+    };
+
+    #[test]
+    fn lut_accuracy() {
+        for exp in [1. / 2.2, 2.2, 1. / 2.35, 1.] {
+            let lut = Lut::new(exp);
+            for i in 0..=4096 {
+                let c = i as f32 / 4096.;
+                assert!((lut.get(c) - c.powf(exp)).abs() < 1e-3, "{exp} {c}");
+            }
+            assert_eq!(lut.get(0.), 0.);
+            assert!((lut.get(1.) - 1.).abs() < 1e-6);
+            assert_eq!(lut.get(1.5), 1.5f32.powf(exp));
+            assert_eq!(lut.get(-0.25), -(0.25f32.powf(exp)));
+        }
+    }
+
+    #[test]
+    fn uniform_gamma_matches_simple_srgb() {
+        let img = photo((16, 12));
+        let mut display = img.clone();
+        display.encode_for_display([2.2; 3]);
+        assert_eq!(display.color, ColorSpace::AsIs);
+        let mut simple = img.clone();
+        simple.to_color(ColorSpace::sRGBLinear);
+        simple.map_pixels(|p| {
+            [
+                rgb_to_gamma(p[0]),
+                rgb_to_gamma(p[1]),
+                rgb_to_gamma(p[2]),
+                p[3],
+            ]
+        });
+        assert!(max_diff(display.pixels(), simple.pixels()) < 1e-3);
+
+        display.decode_from_display([2.2; 3], ColorSpace::sRGB);
+        assert_eq!(display.color, ColorSpace::sRGB);
+        assert!(max_diff(display.pixels(), img.pixels()) < 2e-3);
+    }
+
+    #[test]
+    fn per_channel_gammas() {
+        let gammas = [2.15, 2.22, 2.35];
+        let mut img = solid((2, 2), [0.5, 0.5, 0.5, 0.25]);
+        img.encode_for_display(gammas);
+        let p = img.pixels()[0];
+        // Higher gamma, brighter pre-compensation below white
+        assert!(p[0] < p[1] && p[1] < p[2], "{p:?}");
+        assert_eq!(p[3], 0.25);
+        img.decode_from_display(gammas, ColorSpace::sRGB);
+        for c in &img.pixels()[0][..3] {
+            assert!((c - 0.5).abs() < 1e-3);
+        }
+    }
+}
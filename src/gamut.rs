@@ -0,0 +1,156 @@
+//! Gamut diagnostics
+use crate::{convert::convert_image_rows, ColorSpace, Image, ResXY, WorkPixel, XY};
+
+/// How far outside `0..=1` a channel may be and still count as in gamut, so
+/// rounding in the conversion isn't reported
+const EPSILON: f32 = 1e-5;
+
+/// Out of gamut pixels found by [`Image::gamut_report`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GamutReport {
+    /// Pixels with a color channel outside `0..=1`
+    pub out_of_gamut: usize,
+    pub total: usize,
+    /// Furthest each of R, G, and B got outside `0..=1`, in either
+    /// direction
+    pub max_excursion: [f32; 3],
+    /// Bounding box of the out of gamut pixels, as `(origin, size)`
+    pub bounds: Option<(XY, ResXY)>,
+}
+
+impl GamutReport {
+    /// Out of gamut pixels as a percentage of all of them
+    pub fn percent(&self) -> f32 {
+        if self.total == 0 {
+            return 0.;
+        }
+        self.out_of_gamut as f32 / self.total as f32 * 100.
+    }
+
+    pub fn is_in_gamut(&self) -> bool {
+        self.out_of_gamut == 0
+    }
+}
+
+/// How far `c` is outside `0..=1`
+fn excursion(c: f32) -> f32 {
+    (-c).max(c - 1.).max(0.)
+}
+
+fn out_of_gamut(p: WorkPixel) -> bool {
+    p[..3].iter().any(|c| excursion(*c) > EPSILON || c.is_nan())
+}
+
+impl Image {
+    /// Which pixels can't be shown in `target`, without changing the image
+    ///
+    /// Pixels are converted like [`Image::to_color`], and any with a color
+    /// channel outside `0..=1` are out of gamut.
+    pub fn gamut_report(&self, target: ColorSpace) -> GamutReport {
+        let converted = self.gamut_converted(target);
+        let mut report = GamutReport {
+            out_of_gamut: 0,
+            total: self.data.len(),
+            max_excursion: [0.; 3],
+            bounds: converted.bounds_where(out_of_gamut),
+        };
+        for p in converted.data.iter().filter(|p| out_of_gamut(**p)) {
+            report.out_of_gamut += 1;
+            for (m, c) in report.max_excursion.iter_mut().zip(p) {
+                *m = m.max(excursion(*c));
+            }
+        }
+        report
+    }
+
+    /// A copy with pixels that can't be shown in `target` replaced with
+    /// `marker`, see [`Image::gamut_report`]
+    pub fn highlight_out_of_gamut(
+        &self,
+        target: ColorSpace,
+        marker: impl Into<WorkPixel>,
+    ) -> Image {
+        let marker = marker.into();
+        let converted = self.gamut_converted(target);
+        let data = self
+            .data
+            .iter()
+            .zip(&converted.data)
+            .map(|(p, c)| if out_of_gamut(*c) { marker } else { *p })
+            .collect();
+        self.derive(data, self.res)
+    }
+
+    /// A copy converted to `target`, out of range values and all
+    fn gamut_converted(&self, target: ColorSpace) -> Image {
+        let mut img = self.clone();
+        convert_image_rows(&mut img.data, self.alpha, self.color, target);
+        img
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{photo, solid};
+
+    /// Gray Display P3 with a patch of its saturated green
+    fn p3_patch() -> Image {
+        let mut img = solid((12, 8), [0.5, 0.5, 0.5, 1.]);
+        img.color = ColorSpace::DisplayP3;
+        img.map_pixels_indexed(|(x, y), p| {
+            if (5..9).contains(&x) && (3..5).contains(&y) {
+                [0., 1., 0., 1.]
+            } else {
+                p
+            }
+        });
+        img
+    }
+
+    #[test]
+    fn srgb_is_in_gamut() {
+        let img = photo((16, 12));
+        for target in [
+            ColorSpace::sRGB,
+            ColorSpace::sRGBLinear,
+            ColorSpace::DisplayP3,
+        ] {
+            let report = img.gamut_report(target);
+            assert!(report.is_in_gamut(), "{target:?} {report:?}");
+            assert_eq!(report.bounds, None);
+            assert_eq!(report.percent(), 0.);
+            assert_eq!(report.total, 16 * 12);
+        }
+    }
+
+    #[test]
+    fn p3_green_is_not_srgb() {
+        let img = p3_patch();
+        let report = img.gamut_report(ColorSpace::sRGB);
+        assert_eq!(report.out_of_gamut, 8);
+        assert_eq!(report.bounds, Some(((5, 3), (4, 2))));
+        assert!((report.percent() - 800. / 96.).abs() < 1e-4);
+        // Negative red and blue, and green past 1
+        assert!(report.max_excursion.iter().all(|e| *e > 0.01), "{report:?}");
+        assert!(report.max_excursion[0] > report.max_excursion[1]);
+        assert!(img.gamut_report(ColorSpace::DisplayP3).is_in_gamut());
+        assert_eq!(img.color, ColorSpace::DisplayP3);
+    }
+
+    #[test]
+    fn highlight() {
+        let img = p3_patch();
+        let marker = [1., 0., 1., 1.];
+        let out = img.highlight_out_of_gamut(ColorSpace::sRGB, marker);
+        assert_eq!(out.color, ColorSpace::DisplayP3);
+        for (i, (o, p)) in out.pixels().iter().zip(img.pixels()).enumerate() {
+            let (x, y) = (i as u32 % 12, i as u32 / 12);
+            if (5..9).contains(&x) && (3..5).contains(&y) {
+                assert_eq!(*o, marker);
+            } else {
+                assert_eq!(o, p);
+            }
+        }
+    }
+}
@@ -0,0 +1,435 @@
+//! Gradient generators
+use alloc::vec::Vec;
+use core::f32::consts::TAU;
+
+use crate::{
+    convert_rows, transforms::*, AlphaMode, ColorSpace, FloatXY, Image, ImageError, ResXY,
+    WorkPixel, F32,
+};
+
+/// What gradient stops are blended in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GradientSpace {
+    /// Premultiplied linear light, physically correct and what browsers do
+    #[default]
+    Linear,
+
+    /// Premultiplied Oklab, for more even looking ramps between very
+    /// different hues
+    Oklab,
+}
+
+/// Stops decoded into the space they're blended in
+struct Ramp {
+    /// Position and the stop, premultiplied in the blend space
+    stops: Vec<(f32, WorkPixel)>,
+    /// The original stops, returned as is when sampled exactly
+    exact: Vec<WorkPixel>,
+    space: GradientSpace,
+}
+
+impl Ramp {
+    fn new(stops: &[(f32, WorkPixel)], space: GradientSpace) -> Result<Self, ImageError> {
+        let in_range = |t: f32| (0. ..=1.).contains(&t);
+        if stops.is_empty()
+            || !stops.iter().all(|(t, _)| in_range(*t))
+            || stops.windows(2).any(|w| w[1].0 < w[0].0)
+        {
+            return Err(ImageError::InvalidArgument);
+        }
+        let decode = |p: WorkPixel| {
+            let mut rgb = [p[0], p[1], p[2]].map(srgb_to_rgb);
+            if space == GradientSpace::Oklab {
+                rgb = linear_srgb_to_oklab(rgb);
+            }
+            [rgb[0] * p[3], rgb[1] * p[3], rgb[2] * p[3], p[3]]
+        };
+        Ok(Self {
+            stops: stops.iter().map(|(t, p)| (*t, decode(*p))).collect(),
+            exact: stops.iter().map(|(_, p)| *p).collect(),
+            space,
+        })
+    }
+
+    /// The color at `t`, clamped to the end stops
+    fn sample(&self, t: f32) -> WorkPixel {
+        let last = self.stops.len() - 1;
+        // First stop past `t`, so `t` on a stop takes the later of its
+        // colors, for hard edges
+        let i = self.stops.partition_point(|(p, _)| *p <= t);
+        if i == 0 {
+            return self.exact[0];
+        }
+        if i > last {
+            return self.exact[last];
+        }
+        let (t0, t1) = (self.stops[i - 1].0, self.stops[i].0);
+        if t == t0 {
+            return self.exact[i - 1];
+        }
+        self.blend(i - 1, i, (t - t0) / (t1 - t0))
+    }
+
+    /// Stop `i` to stop `j` by `f`, encoded back to straight sRGB
+    fn blend(&self, i: usize, j: usize, f: f32) -> WorkPixel {
+        let (a, b) = (self.stops[i].1, self.stops[j].1);
+        if a == b {
+            return self.exact[i];
+        }
+        let p: [f32; 4] = core::array::from_fn(|c| a[c] + (b[c] - a[c]) * f);
+        let alpha = p[3];
+        if alpha <= 0. {
+            return [0., 0., 0., 0.];
+        }
+        let mut rgb = [p[0] / alpha, p[1] / alpha, p[2] / alpha];
+        if self.space == GradientSpace::Oklab {
+            rgb = oklab_to_linear_srgb(rgb);
+        }
+        let [r, g, b] = rgb.map(|c| rgb_to_srgb(c.clamp(0., 1.)));
+        [r, g, b, alpha.clamp(0., 1.)]
+    }
+
+    /// The color at `t`, going round from the last stop back to the first
+    fn sample_wrapped(&self, t: f32) -> WorkPixel {
+        let last = self.stops.len() - 1;
+        let (first_t, last_t) = (self.stops[0].0, self.stops[last].0);
+        if t >= first_t && t < last_t {
+            return self.sample(t);
+        }
+        // Across the seam
+        let gap = first_t + 1. - last_t;
+        if gap <= 0. {
+            return self.exact[if t < first_t { 0 } else { last }];
+        }
+        let from_last = if t >= last_t {
+            t - last_t
+        } else {
+            t + 1. - last_t
+        };
+        if from_last == 0. {
+            return self.exact[last];
+        }
+        self.blend(last, 0, from_last / gap)
+    }
+}
+
+impl Image {
+    /// Image of `res` with each pixel from its position
+    fn generate(res: ResXY, mut f: impl FnMut(f32, f32) -> WorkPixel) -> Image {
+        let mut data = Vec::with_capacity(res.0 as usize * res.1 as usize);
+        for y in 0..res.1 {
+            for x in 0..res.0 {
+                data.push(f(x as f32, y as f32));
+            }
+        }
+        Image::from_parts(data, res, ColorSpace::sRGB)
+    }
+
+    /// Radial gradient around `center`, from the stop at 0 there to the stop
+    /// at 1 at `radius`
+    ///
+    /// See [`Image::radial_gradient_with`], this blends in
+    /// [`GradientSpace::Linear`].
+    ///
+    /// # Errors
+    ///
+    /// - The errors of [`Image::radial_gradient_with`]
+    pub fn radial_gradient(
+        res: ResXY,
+        center: FloatXY,
+        radius: f32,
+        stops: &[(f32, WorkPixel)],
+    ) -> Result<Image, ImageError> {
+        Self::radial_gradient_with(res, center, radius, stops, GradientSpace::Linear)
+    }
+
+    /// [`Image::radial_gradient`], blending stops in `space`
+    ///
+    /// `stops` are positions in `0..=1` and straight sRGB colors, and the
+    /// image is tagged [`ColorSpace::sRGB`]. `center` is in pixels, where
+    /// pixel `(x, y)` is at `(x, y)`. Pixels before the first stop or past the
+    /// last get their colors, and pixels exactly on a stop get it exactly.
+    /// Two stops at the same position make a hard edge.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `stops` is empty, unsorted, has
+    ///   positions outside `0..=1`, or `radius` isn't positive and finite
+    pub fn radial_gradient_with(
+        res: ResXY,
+        center: FloatXY,
+        radius: f32,
+        stops: &[(f32, WorkPixel)],
+        space: GradientSpace,
+    ) -> Result<Image, ImageError> {
+        if !radius.is_finite() || radius <= 0. {
+            return Err(ImageError::InvalidArgument);
+        }
+        let ramp = Ramp::new(stops, space)?;
+        Ok(Self::generate(res, |x, y| {
+            let (dx, dy) = (x - center.0, y - center.1);
+            ramp.sample((dx * dx + dy * dy).sqrt() / radius)
+        }))
+    }
+
+    /// Conic, or angular, gradient around `center`, going clockwise from
+    /// `start_angle`
+    ///
+    /// See [`Image::conic_gradient_with`], this blends in
+    /// [`GradientSpace::Linear`].
+    ///
+    /// # Errors
+    ///
+    /// - The errors of [`Image::conic_gradient_with`]
+    pub fn conic_gradient(
+        res: ResXY,
+        center: FloatXY,
+        start_angle: f32,
+        stops: &[(f32, WorkPixel)],
+    ) -> Result<Image, ImageError> {
+        Self::conic_gradient_with(res, center, start_angle, stops, GradientSpace::Linear)
+    }
+
+    /// [`Image::conic_gradient`], blending stops in `space`
+    ///
+    /// A full turn is 0 to 1, `start_angle` is in radians clockwise from the
+    /// positive x axis, since y points down. Between the last stop and the
+    /// first the colors blend round through the start angle, so there's only
+    /// a seam there if stops are at both 0 and 1 with different colors.
+    /// Stops and `center` are as in [`Image::radial_gradient_with`].
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `stops` is empty, unsorted, has
+    ///   positions outside `0..=1`, or `start_angle` isn't finite
+    pub fn conic_gradient_with(
+        res: ResXY,
+        center: FloatXY,
+        start_angle: f32,
+        stops: &[(f32, WorkPixel)],
+        space: GradientSpace,
+    ) -> Result<Image, ImageError> {
+        if !start_angle.is_finite() {
+            return Err(ImageError::InvalidArgument);
+        }
+        let ramp = Ramp::new(stops, space)?;
+        Ok(Self::generate(res, |x, y| {
+            let turns = (libm::atan2f(y - center.1, x - center.0) - start_angle) / TAU;
+            // Tiny negative turns can round up to exactly 1
+            let t = (turns - turns.floor()).min(1. - f32::EPSILON / 2.);
+            ramp.sample_wrapped(t)
+        }))
+    }
+
+    /// Recolor by luminance through the gradient `stops`, like a duotone
+    ///
+    /// See [`Image::gradient_map_with`], this blends in
+    /// [`GradientSpace::Linear`].
+    ///
+    /// # Errors
+    ///
+    /// - The errors of [`Image::gradient_map_with`]
+    pub fn gradient_map(&mut self, stops: &[(f32, WorkPixel)]) -> Result<(), ImageError> {
+        self.gradient_map_with(stops, GradientSpace::Linear)
+    }
+
+    /// Recolor by luminance through the gradient `stops`, blended in `space`
+    ///
+    /// Each pixel's linear light luminance picks its color from the stops,
+    /// which are positions in `0..=1` and straight sRGB colors, just like
+    /// [`Image::radial_gradient_with`]. The color replaces the pixel's, and
+    /// its alpha multiplies the pixel's, so opaque stops keep alpha as is.
+    /// Luminance outside the stops takes the end stops. For
+    /// [`ColorSpace::AsIs`] the values are taken to be linear sRGB.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if there are fewer than two stops,
+    ///   or they are out of order or outside `0..=1`
+    pub fn gradient_map_with(
+        &mut self,
+        stops: &[(f32, WorkPixel)],
+        space: GradientSpace,
+    ) -> Result<(), ImageError> {
+        if stops.len() < 2 {
+            return Err(ImageError::InvalidArgument);
+        }
+        let ramp = Ramp::new(stops, space)?;
+        let (color, alpha) = (self.color, self.alpha);
+        self.to_alpha_mode(AlphaMode::Straight);
+        let mut linear = self.data.clone();
+        convert_rows(&mut linear, color, ColorSpace::sRGBLinear);
+        for (p, l) in self.data.iter_mut().zip(&linear) {
+            let c = ramp.sample(luminance([l[0], l[1], l[2]]));
+            *p = [c[0], c[1], c[2], p[3] * c[3]];
+        }
+        convert_rows(&mut self.data, ColorSpace::sRGB, color);
+        self.to_alpha_mode(alpha);
+        self.check();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: WorkPixel = [1., 0., 0., 1.];
+    const BLUE: WorkPixel = [0., 0., 1., 1.];
+
+    fn pixel(img: &Image, xy: crate::XY) -> WorkPixel {
+        img.get_pixel(xy).unwrap()
+    }
+
+    fn close(a: WorkPixel, b: WorkPixel, tolerance: f32) -> bool {
+        (0..4).all(|c| (a[c] - b[c]).abs() <= tolerance)
+    }
+
+    #[test]
+    fn radial_stops_are_exact() {
+        let stops = [(0., RED), (1., BLUE)];
+        for space in [GradientSpace::Linear, GradientSpace::Oklab] {
+            let img = Image::radial_gradient_with((11, 11), (5., 5.), 4., &stops, space).unwrap();
+            assert_eq!(pixel(&img, (5, 5)), RED);
+            assert_eq!(pixel(&img, (9, 5)), BLUE);
+            assert_eq!(pixel(&img, (5, 1)), BLUE);
+            // Past the radius clamps to the last stop
+            assert_eq!(pixel(&img, (10, 10)), BLUE);
+        }
+        // Halfway, half of each in linear light
+        let img = Image::radial_gradient((11, 11), (5., 5.), 4., &stops).unwrap();
+        let half = rgb_to_srgb(0.5);
+        assert!(close(pixel(&img, (7, 5)), [half, 0., half, 1.], 1e-5));
+    }
+
+    #[test]
+    fn multiple_stops() {
+        let green = [0., 1., 0., 1.];
+        let stops = [(0.25, RED), (0.5, green), (0.5, BLUE), (1., BLUE)];
+        let img = Image::radial_gradient((9, 1), (0., 0.), 8., &stops).unwrap();
+        // Before the first stop, on the stops, and the hard edge
+        assert_eq!(pixel(&img, (1, 0)), RED);
+        assert_eq!(pixel(&img, (2, 0)), RED);
+        assert_eq!(pixel(&img, (4, 0)), BLUE);
+        let p = pixel(&img, (3, 0));
+        assert!(p[0] > 0. && p[1] > 0. && p[2] == 0., "{p:?}");
+    }
+
+    #[test]
+    fn invalid_stops() {
+        for stops in [
+            &[][..],
+            &[(0.5, RED), (0.2, BLUE)],
+            &[(-0.1, RED), (1., BLUE)],
+            &[(0., RED), (1.5, BLUE)],
+            &[(0., RED), (f32::NAN, BLUE)],
+        ] {
+            assert_eq!(
+                Image::radial_gradient((4, 4), (2., 2.), 2., stops).err(),
+                Some(ImageError::InvalidArgument)
+            );
+            assert_eq!(
+                Image::conic_gradient((4, 4), (2., 2.), 0., stops).err(),
+                Some(ImageError::InvalidArgument)
+            );
+        }
+        let stops = [(0., RED), (1., BLUE)];
+        for radius in [0., -1., f32::NAN, f32::INFINITY] {
+            assert!(Image::radial_gradient((4, 4), (2., 2.), radius, &stops).is_err());
+        }
+        assert!(Image::conic_gradient((4, 4), (2., 2.), f32::NAN, &stops).is_err());
+    }
+
+    #[test]
+    fn conic_seam_is_seamless() {
+        let stops = [(0.25, RED), (0.75, BLUE)];
+        let img = Image::conic_gradient((21, 21), (10., 10.), 0., &stops).unwrap();
+        // A quarter turn clockwise is straight down
+        assert_eq!(pixel(&img, (10, 18)), RED);
+        assert_eq!(pixel(&img, (10, 2)), BLUE);
+        // On the start angle, halfway from the last stop round to the first
+        let half = rgb_to_srgb(0.5);
+        assert!(close(pixel(&img, (18, 10)), [half, 0., half, 1.], 1e-5));
+        // Either side of the seam is no further apart than anywhere else
+        let across = (0..4).map(|c| (pixel(&img, (18, 9))[c] - pixel(&img, (18, 11))[c]).abs());
+        let along = (0..4).map(|c| (pixel(&img, (2, 9))[c] - pixel(&img, (2, 11))[c]).abs());
+        let (across, along) = (across.fold(0., f32::max), along.fold(0., f32::max));
+        assert!(across <= along + 1e-4, "{across} {along}");
+    }
+
+    #[test]
+    fn conic_start_angle() {
+        let stops = [(0., RED), (0.5, BLUE), (1., RED)];
+        let turned = Image::conic_gradient((21, 21), (10., 10.), TAU / 4., &stops).unwrap();
+        // The first stop is now straight down
+        assert_eq!(pixel(&turned, (10, 18)), RED);
+        assert_eq!(pixel(&turned, (10, 2)), BLUE);
+    }
+
+    /// A gray ramp, `width` wide, from black to white in sRGB
+    fn grays(width: u32) -> Image {
+        let mut img = crate::fixtures::solid((width, 1), [0., 0., 0., 1.]);
+        img.map_pixels_indexed(|(x, _), _| {
+            let g = x as f32 / (width - 1) as f32;
+            [g, g, g, 1.]
+        });
+        img
+    }
+
+    #[test]
+    fn gradient_map_black_to_red() {
+        let mut img = grays(16);
+        img.gradient_map(&[(0., [0., 0., 0., 1.]), (1., RED)])
+            .unwrap();
+        for (x, p) in (0..).zip(img.pixels()) {
+            let g = x as f32 / 15.;
+            assert!(close(*p, [g, 0., 0., 1.], 1e-4), "{x} {p:?}");
+        }
+    }
+
+    #[test]
+    fn gradient_map_keeps_alpha() {
+        let mut img = grays(4);
+        img.map_pixels(|p| [p[0], p[1], p[2], 0.5]);
+        img.to_alpha_mode(AlphaMode::Premultiplied);
+        img.gradient_map(&[(0., BLUE), (1., RED)]).unwrap();
+        assert_eq!(img.alpha, AlphaMode::Premultiplied);
+        img.to_alpha_mode(AlphaMode::Straight);
+        assert!(close(pixel(&img, (0, 0)), [0., 0., 1., 0.5], 1e-5));
+        assert!(close(pixel(&img, (3, 0)), [1., 0., 0., 0.5], 1e-5));
+    }
+
+    #[test]
+    fn gradient_map_oklab_midpoint() {
+        // Half luminance in linear light
+        let mid = ColorSpace::sRGB.transfer().unwrap().1(0.5);
+        let mut linear = crate::fixtures::solid((1, 1), [mid, mid, mid, 1.]);
+        let mut oklab = linear.clone();
+        let stops = [(0., [0., 0., 0., 1.]), (1., [1., 1., 1., 1.])];
+        linear.gradient_map(&stops).unwrap();
+        oklab
+            .gradient_map_with(&stops, GradientSpace::Oklab)
+            .unwrap();
+        // Linear light blends to half, Oklab to half lightness, half cubed
+        let encode = ColorSpace::sRGB.transfer().unwrap().1;
+        assert!(close(pixel(&linear, (0, 0)), [mid, mid, mid, 1.], 1e-4));
+        let want = encode(0.125);
+        assert!(close(pixel(&oklab, (0, 0)), [want, want, want, 1.], 1e-3));
+        assert!(mid - want > 0.3);
+    }
+
+    #[test]
+    fn gradient_map_errors() {
+        let mut img = grays(4);
+        let before = img.pixels().to_vec();
+        for stops in [
+            &[][..],
+            &[(0.5, RED)],
+            &[(0.8, RED), (0.2, BLUE)],
+            &[(0., RED), (1.5, BLUE)],
+        ] {
+            assert_eq!(img.gradient_map(stops), Err(ImageError::InvalidArgument));
+        }
+        assert_eq!(img.pixels(), &before[..]);
+    }
+}
@@ -0,0 +1,227 @@
+//! Visualizing where two images differ
+use alloc::vec::Vec;
+
+use crate::{alpha_converter, AlphaMode, ColorSpace, Image, ImageError, WorkPixel, F32};
+
+/// How [`Image::difference_heatmap`] measures the error of each pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffMetric {
+    /// Distance in Oklab, plus the alpha difference, for how different
+    /// pixels look
+    #[default]
+    DeltaE,
+    /// Largest difference of any straight alpha channel, as stored
+    MaxChannel,
+}
+
+/// What [`Image::difference_heatmap`] measures, and the error mapped to
+/// the hottest color
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DiffScale {
+    pub metric: DiffMetric,
+    /// Error shown as the hottest color, or `None` for the largest one in
+    /// the images
+    ///
+    /// A fixed scale makes heatmaps comparable across runs, larger errors
+    /// are clamped to it.
+    pub max: Option<f32>,
+}
+
+/// Viridis, sampled evenly, as 8 bit sRGB
+const RAMP: [[u8; 3]; 9] = [
+    [68, 1, 84],
+    [71, 44, 122],
+    [59, 81, 139],
+    [44, 113, 142],
+    [33, 144, 141],
+    [39, 173, 129],
+    [92, 200, 99],
+    [170, 220, 50],
+    [253, 231, 37],
+];
+
+/// The ramp color at `t` in `0..=1`
+fn ramp(t: f32) -> WorkPixel {
+    let pos = t.clamp(0., 1.) * (RAMP.len() - 1) as f32;
+    let i = (pos as usize).min(RAMP.len() - 2);
+    let f = pos - i as f32;
+    let (a, b) = (RAMP[i], RAMP[i + 1]);
+    let c = |c: usize| (a[c] as f32 + (b[c] as f32 - a[c] as f32) * f) / 255.;
+    [c(0), c(1), c(2), 1.]
+}
+
+impl Image {
+    /// Opaque sRGB image of how much each pixel of this and `other` differ,
+    /// from dark purple for none to yellow for the most, like viridis
+    ///
+    /// [`DiffMetric::DeltaE`] compares images in any color spaces.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::DimensionMismatch`] if the images are different sizes
+    /// - [`ImageError::ColorSpaceMismatch`] for [`DiffMetric::MaxChannel`]
+    ///   if the color spaces differ
+    /// - [`ImageError::InvalidArgument`] if the fixed scale isn't positive
+    pub fn difference_heatmap(&self, other: &Image, scale: DiffScale) -> Result<Image, ImageError> {
+        if self.res != other.res {
+            return Err(ImageError::DimensionMismatch);
+        }
+        if scale.metric == DiffMetric::MaxChannel && self.color != other.color {
+            return Err(ImageError::ColorSpaceMismatch);
+        }
+        if scale.max.is_some_and(|m| !(m > 0. && m.is_finite())) {
+            return Err(ImageError::InvalidArgument);
+        }
+
+        let errors: Vec<f32> = match scale.metric {
+            DiffMetric::DeltaE => self
+                .data
+                .iter()
+                .zip(&other.data)
+                .map(|(a, b)| {
+                    let (a, b) = (self.to_oklab_alpha(*a), other.to_oklab_alpha(*b));
+                    let d = |c: usize| a[c] - b[c];
+                    (d(0) * d(0) + d(1) * d(1) + d(2) * d(2)).sqrt() + d(3).abs()
+                })
+                .collect(),
+            DiffMetric::MaxChannel => {
+                let (ca, cb) = (
+                    alpha_converter(self.alpha, AlphaMode::Straight),
+                    alpha_converter(other.alpha, AlphaMode::Straight),
+                );
+                self.data
+                    .iter()
+                    .zip(&other.data)
+                    .map(|(a, b)| {
+                        let (a, b) = (ca(*a), cb(*b));
+                        (0..4).fold(0f32, |m, c| m.max((a[c] - b[c]).abs()))
+                    })
+                    .collect()
+            }
+        };
+
+        let max = scale
+            .max
+            .unwrap_or_else(|| errors.iter().fold(0f32, |m, e| m.max(*e)));
+        let data = errors
+            .iter()
+            .map(|e| ramp(if max > 0. { e / max } else { 0. }))
+            .collect();
+        Ok(Image::from_parts(data, self.res, ColorSpace::sRGB))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{photo, solid};
+
+    const COLD: WorkPixel = [68. / 255., 1. / 255., 84. / 255., 1.];
+    const HOT: WorkPixel = [253. / 255., 231. / 255., 37. / 255., 1.];
+    const METRICS: [DiffMetric; 2] = [DiffMetric::DeltaE, DiffMetric::MaxChannel];
+
+    #[test]
+    fn identical_is_cold() {
+        let img = photo((12, 9));
+        for metric in METRICS {
+            for max in [None, Some(0.1)] {
+                let map = img
+                    .difference_heatmap(&img, DiffScale { metric, max })
+                    .unwrap();
+                assert_eq!((map.res, map.color), ((12, 9), ColorSpace::sRGB));
+                assert!(map.pixels().iter().all(|p| *p == COLD), "{metric:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn one_hot_pixel() {
+        let img = photo((12, 9));
+        let mut other = img.clone();
+        other.data[4 * 12 + 7] = [1., 0., 1., 1.];
+        for metric in METRICS {
+            let map = img
+                .difference_heatmap(&other, DiffScale { metric, max: None })
+                .unwrap();
+            for (i, p) in map.pixels().iter().enumerate() {
+                assert_eq!(
+                    *p,
+                    if i == 4 * 12 + 7 { HOT } else { COLD },
+                    "{metric:?} {i}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_scale_is_comparable() {
+        let scale = DiffScale {
+            metric: DiffMetric::MaxChannel,
+            max: Some(0.5),
+        };
+        // The same errors, over different images, exact in binary
+        let (a, b) = (solid((6, 1), [0.25; 4]), solid((6, 1), [0.5; 4]));
+        let shift = |img: &Image| {
+            let mut img = img.clone();
+            img.map_pixels_indexed(|(x, _), p| p.map(|c| c + x as f32 * 0.125));
+            img
+        };
+        let (ma, mb) = (
+            a.difference_heatmap(&shift(&a), scale).unwrap(),
+            b.difference_heatmap(&shift(&b), scale).unwrap(),
+        );
+        assert_eq!(ma.pixels(), mb.pixels());
+        // Errors past the scale clamp
+        assert_eq!(ma.pixels()[0], COLD);
+        assert_eq!(ma.pixels()[4], HOT);
+        assert_eq!(ma.pixels()[5], HOT);
+        // Halfway is the middle of the ramp
+        let mid = [33. / 255., 144. / 255., 141. / 255., 1.];
+        assert!((0..4).all(|c| (ma.pixels()[2][c] - mid[c]).abs() < 1e-5));
+        // Normalizing to the largest error differs
+        let auto = a
+            .difference_heatmap(&shift(&a), DiffScale { max: None, ..scale })
+            .unwrap();
+        assert_eq!(auto.pixels()[5], HOT);
+        assert_ne!(auto.pixels()[4], HOT);
+    }
+
+    #[test]
+    fn errors() {
+        let img = photo((4, 4));
+        assert_eq!(
+            img.difference_heatmap(&photo((4, 5)), DiffScale::default())
+                .err(),
+            Some(ImageError::DimensionMismatch)
+        );
+        let mut linear = img.clone();
+        linear.to_color(ColorSpace::sRGBLinear);
+        let max_channel = DiffScale {
+            metric: DiffMetric::MaxChannel,
+            max: None,
+        };
+        assert_eq!(
+            img.difference_heatmap(&linear, max_channel).err(),
+            Some(ImageError::ColorSpaceMismatch)
+        );
+        // The same colors in another space look the same
+        let map = img
+            .difference_heatmap(&linear, DiffScale::default())
+            .unwrap();
+        let worst = map
+            .pixels()
+            .iter()
+            .fold(0f32, |m, p| m.max((p[0] - COLD[0]).abs()));
+        assert!(worst < 0.05, "{worst}");
+        for max in [0., -1., f32::NAN, f32::INFINITY] {
+            let scale = DiffScale {
+                max: Some(max),
+                ..DiffScale::default()
+            };
+            assert_eq!(
+                img.difference_heatmap(&img, scale).err(),
+                Some(ImageError::InvalidArgument)
+            );
+        }
+    }
+}
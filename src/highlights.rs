@@ -0,0 +1,226 @@
+//! Rebuilding clipped highlights
+use alloc::vec::Vec;
+
+use crate::{
+    alpha_converter, blur::gaussian_blur_buffer, transforms::luminance, AlphaMode, Image, WorkPixel,
+};
+
+/// Neighborhood the channel ratios are taken from, as a fraction of the
+/// larger dimension
+const RATIO_SIGMA: f32 = 1. / 64.;
+
+/// Smallest neighborhood, in pixels
+const MIN_SIGMA: f32 = 2.;
+
+/// Least weight of unclipped neighbors to trust their ratios
+const MIN_WEIGHT: f32 = 1e-3;
+
+/// How far past `clip_point` a channel may be rebuilt
+const MAX_RECOVERY: f32 = 4.;
+
+impl Image {
+    /// Rebuild channels clipped at `clip_point`, in linear light, so clipped
+    /// highlights keep their hue instead of shifting to magenta or cyan
+    ///
+    /// Where only some channels are at or past `clip_point`, the clipped ones
+    /// are rebuilt from the unclipped ones, using the ratios between
+    /// channels of the nearest unclipped pixels around them. Rebuilt
+    /// channels never go down, or above four times `clip_point`. Where all
+    /// of them are clipped, or there are no unclipped pixels nearby, the
+    /// pixel is made neutral at its luminance, and at least `clip_point`.
+    ///
+    /// Pixels without clipped channels, or already neutral with all of them
+    /// clipped, aren't touched at all.
+    pub fn recover_highlights(&mut self, clip_point: f32) {
+        let transfer = self.color.transfer();
+        let (decode, encode) = (transfer.map(|t| t.0), transfer.map(|t| t.1));
+        let (to_straight, back) = (
+            alpha_converter(self.alpha, AlphaMode::Straight),
+            alpha_converter(AlphaMode::Straight, self.alpha),
+        );
+        let linear: Vec<WorkPixel> = self
+            .data
+            .iter()
+            .map(|p| {
+                let p = to_straight(*p);
+                let c = |c: usize| decode.map_or(p[c], |f| f(p[c]));
+                [c(0), c(1), c(2), p[3]]
+            })
+            .collect();
+        let clipped = |p: &WorkPixel| p[..3].iter().filter(|c| **c >= clip_point).count();
+        if !linear.iter().any(|p| clipped(p) > 0) {
+            return;
+        }
+
+        // Average color of the unclipped pixels around each one, with the
+        // weight in alpha
+        let weighted: Vec<WorkPixel> = linear
+            .iter()
+            .map(|p| match clipped(p) {
+                0 => [p[0], p[1], p[2], 1.],
+                _ => [0.; 4],
+            })
+            .collect();
+        // Widened for pixels too deep inside a clipped area to have any,
+        // up to a quarter of the image
+        let size = self.res.0.max(self.res.1) as f32;
+        let mut sigma = (size * RATIO_SIGMA).max(MIN_SIGMA);
+        let mut guide = gaussian_blur_buffer(&weighted, self.res, sigma);
+        let missing = |guide: &[WorkPixel]| {
+            linear
+                .iter()
+                .zip(guide)
+                .any(|(p, g)| (1..3).contains(&clipped(p)) && g[3] < MIN_WEIGHT)
+        };
+        while sigma * 2. <= size / 4. && missing(&guide) {
+            sigma *= 2.;
+            let wider = gaussian_blur_buffer(&weighted, self.res, sigma);
+            for (g, w) in guide.iter_mut().zip(wider) {
+                if g[3] < MIN_WEIGHT {
+                    *g = w;
+                }
+            }
+        }
+
+        let neutral = |p: &WorkPixel| [luminance([p[0], p[1], p[2]]).max(clip_point); 3];
+        for ((out, p), g) in self.data.iter_mut().zip(&linear).zip(&guide) {
+            let n = clipped(p);
+            if n == 0 || (n == 3 && p[0] == p[1] && p[1] == p[2]) {
+                continue;
+            }
+            let rgb = if n == 3 || g[3] < MIN_WEIGHT {
+                neutral(p)
+            } else {
+                let avg = [g[0] / g[3], g[1] / g[3], g[2] / g[3]];
+                let (mut have, mut like) = (0., 0.);
+                for c in (0..3).filter(|c| p[*c] < clip_point) {
+                    have += p[c];
+                    like += avg[c];
+                }
+                if like <= 0. {
+                    neutral(p)
+                } else {
+                    let k = have / like;
+                    core::array::from_fn(|c| {
+                        if p[c] < clip_point {
+                            p[c]
+                        } else {
+                            (avg[c] * k).min(clip_point * MAX_RECOVERY).max(p[c])
+                        }
+                    })
+                }
+            };
+            let rgb = rgb.map(|c| encode.map_or(c, |f| f(c)));
+            *out = back([rgb[0], rgb[1], rgb[2], p[3]]);
+        }
+        self.check();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{photo, solid},
+        ColorSpace,
+    };
+
+    const HUE: [f32; 3] = [1.5, 1., 0.6];
+
+    /// A linear light ramp of one hue, brighter to the right, and the same
+    /// clipped at 1
+    fn gradient() -> (Image, Image) {
+        let mut truth = solid((64, 16), [0.; 4]);
+        truth.color = ColorSpace::sRGBLinear;
+        truth.map_pixels_indexed(|(x, _), _| {
+            let v = 0.2 + x as f32 / 63. * 0.8;
+            [HUE[0] * v, HUE[1] * v, HUE[2] * v, 1.]
+        });
+        let mut clipped = truth.clone();
+        clipped.map_pixels(|p| [p[0].min(1.), p[1].min(1.), p[2].min(1.), p[3]]);
+        (truth, clipped)
+    }
+
+    #[test]
+    fn rebuilds_one_clipped_channel() {
+        let (truth, mut img) = gradient();
+        assert!(img.pixels().iter().filter(|p| p[0] == 1.).count() > 16 * 16);
+        img.recover_highlights(1.);
+        for (p, t) in img.pixels().iter().zip(truth.pixels()) {
+            for c in 0..4 {
+                assert!((p[c] - t[c]).abs() <= t[c] * 1e-3, "{p:?} {t:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn rebuilt_channels_are_bounded() {
+        // Unclipped neighbors far redder than the clipped pixel
+        let mut img = solid((16, 16), [0.; 4]);
+        img.color = ColorSpace::sRGBLinear;
+        img.map_pixels_indexed(|(x, _), _| {
+            if x < 8 {
+                [0.9, 0.01, 0.01, 1.]
+            } else {
+                [1., 0.5, 0.5, 1.]
+            }
+        });
+        img.recover_highlights(1.);
+        for p in img.pixels() {
+            assert!(p[0] <= 4. && p.iter().all(|c| c.is_finite()), "{p:?}");
+        }
+        assert_eq!(img.get_pixel((15, 0)).unwrap()[0], 4.);
+    }
+
+    #[test]
+    fn clipped_white_stays_neutral() {
+        let mut img = solid((8, 8), [1., 1., 1., 1.]);
+        img.color = ColorSpace::sRGBLinear;
+        img.data[9] = [1.3, 1., 1.1, 1.];
+        img.recover_highlights(1.);
+        for p in img.pixels() {
+            assert!(p[0] == p[1] && p[1] == p[2] && p[0] >= 1., "{p:?}");
+        }
+        assert_eq!(img.data[0], [1.; 4]);
+        // With no neighbors to go on, partly clipped pixels go neutral too
+        let mut lone = solid((4, 4), [1., 0.5, 0.2, 1.]);
+        lone.color = ColorSpace::sRGBLinear;
+        lone.recover_highlights(1.);
+        let p = lone.data[5];
+        assert!(p[0] == p[1] && p[1] == p[2] && p[0] == 1., "{p:?}");
+    }
+
+    #[test]
+    fn unclipped_is_untouched() {
+        let mut img = photo((32, 24));
+        img.map_pixels(|p| p.map(|c| c * 0.9));
+        let mut before = img.clone();
+        img.recover_highlights(0.99);
+        assert_eq!(img.pixels(), before.pixels());
+
+        before.data[3] = [1., 0.6, 0.3, 1.];
+        let mut clipped = before.clone();
+        clipped.recover_highlights(0.99);
+        for (i, (a, b)) in clipped.pixels().iter().zip(before.pixels()).enumerate() {
+            if i != 3 {
+                assert_eq!(a.map(f32::to_bits), b.map(f32::to_bits));
+            }
+        }
+        assert_ne!(clipped.data[3], before.data[3]);
+    }
+
+    #[test]
+    fn works_in_any_encoding() {
+        let (mut truth, mut img) = gradient();
+        img.to_color(ColorSpace::sRGB);
+        img.to_alpha_mode(AlphaMode::Premultiplied);
+        img.recover_highlights(1.);
+        assert_eq!(
+            (img.color, img.alpha),
+            (ColorSpace::sRGB, AlphaMode::Premultiplied)
+        );
+        img.to_color(ColorSpace::sRGBLinear);
+        truth.to_alpha_mode(AlphaMode::Premultiplied);
+        assert!(crate::fixtures::max_diff(img.pixels(), truth.pixels()) < 2e-3);
+    }
+}
@@ -0,0 +1,309 @@
+//! Matching color distributions between images
+use alloc::{vec, vec::Vec};
+
+use crate::{alpha_converter, AlphaMode, Image, WorkPixel};
+
+/// Histogram bins over `0..=1`
+const BINS: usize = 1024;
+
+/// Entries in each mapping table, past the first
+const STEPS: usize = 4096;
+
+/// Distribution of one channel
+struct Distribution {
+    /// Each populated bin, as its midpoint in the cumulative distribution
+    /// and the mean value in it, both increasing
+    bins: Vec<(f64, f32)>,
+    min: f32,
+    max: f32,
+}
+
+impl Distribution {
+    /// Of `values`, clamped to `0..=1`, or `None` if there aren't any
+    fn new(values: impl Iterator<Item = f32>) -> Option<Self> {
+        let mut count = vec![0u32; BINS];
+        let mut sum = vec![0f64; BINS];
+        let (mut min, mut max) = (f32::INFINITY, f32::NEG_INFINITY);
+        for v in values {
+            let v = v.clamp(0., 1.);
+            let i = ((v * BINS as f32) as usize).min(BINS - 1);
+            count[i] += 1;
+            sum[i] += v as f64;
+            min = min.min(v);
+            max = max.max(v);
+        }
+        let total: u64 = count.iter().map(|n| *n as u64).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut seen = 0;
+        let mut bins = Vec::new();
+        for (n, s) in count.into_iter().zip(sum).filter(|(n, _)| *n > 0) {
+            let q = (seen as f64 + n as f64 / 2.) / total as f64;
+            bins.push((q, (s / n as f64) as f32));
+            seen += n as u64;
+        }
+        Some(Self { bins, min, max })
+    }
+
+    /// The value at quantile `q`
+    fn value_at(&self, q: f64) -> f32 {
+        let bins = &self.bins;
+        let i = bins.partition_point(|p| p.0 <= q);
+        if i == 0 {
+            return bins[0].1;
+        }
+        if i == bins.len() {
+            return bins[i - 1].1;
+        }
+        let ((q0, v0), (q1, v1)) = (bins[i - 1], bins[i]);
+        v0 + (v1 - v0) * ((q - q0) / (q1 - q0)) as f32
+    }
+}
+
+/// Piecewise linear through `points`, sorted by `x`, and flat past
+/// either end
+fn interpolate(points: &[(f32, f32)], x: f32) -> f32 {
+    let i = points.partition_point(|p| p.0 <= x);
+    if i == 0 {
+        return points[0].1;
+    }
+    if i == points.len() {
+        return points[i - 1].1;
+    }
+    let ((x0, y0), (x1, y1)) = (points[i - 1], points[i]);
+    y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+}
+
+/// Mapping of one channel, sampled over `0..=1`
+struct Lut {
+    table: Vec<f32>,
+}
+
+impl Lut {
+    /// Each populated bin of `source` goes to the same quantile of
+    /// `reference`, and the ends of one to the ends of the other
+    fn new(source: &Distribution, reference: &Distribution) -> Self {
+        let mut points = vec![(source.min, reference.min)];
+        points.extend(
+            source
+                .bins
+                .iter()
+                .map(|(q, v)| (*v, reference.value_at(*q))),
+        );
+        points.push((source.max, reference.max));
+        if source.min == source.max {
+            // All one value, which goes to the middle
+            points = vec![(source.min, reference.value_at(0.5))];
+        }
+        // An end is the mean of its bin when that's all one value
+        points.dedup_by(|b, a| b.0 <= a.0);
+        let table = (0..=STEPS)
+            .map(|i| interpolate(&points, i as f32 / STEPS as f32))
+            .collect();
+        Self { table }
+    }
+
+    /// Values outside `0..=1` move with the nearest end
+    fn get(&self, c: f32) -> f32 {
+        if c.is_nan() {
+            return c;
+        }
+        if c <= 0. {
+            return c + self.table[0];
+        }
+        if c >= 1. {
+            return c - 1. + self.table[STEPS];
+        }
+        let t = c * STEPS as f32;
+        let i = (t as usize).min(STEPS - 1);
+        let f = t - i as f32;
+        self.table[i] + (self.table[i + 1] - self.table[i]) * f
+    }
+}
+
+impl Image {
+    /// Map the colors of this image so each channel has the distribution
+    /// it has in `reference`, like for evening out the exposure of tiles
+    /// before stitching
+    ///
+    /// This is done in linear light, on straight alpha, from 1024 bin
+    /// histograms, and the images don't need to be the same size. Each
+    /// value goes to where the same fraction of `reference` is below it,
+    /// so the mapping never reverses the order of values. Fully
+    /// transparent pixels aren't counted, and alpha is left as is.
+    ///
+    /// A channel that's all one value maps to the median of `reference`.
+    /// If either image has no visible pixels, nothing changes.
+    pub fn match_histogram(&mut self, reference: &Image) {
+        let source = linear_straight(self);
+        let target = linear_straight(reference);
+        let visible = |px: &[WorkPixel], c: usize| {
+            Distribution::new(px.iter().filter(|p| p[3] > 0.).map(move |p| p[c]))
+        };
+        let luts: [Option<Lut>; 3] = core::array::from_fn(|c| {
+            let (s, r) = (visible(&source, c)?, visible(&target, c)?);
+            Some(Lut::new(&s, &r))
+        });
+        if luts.iter().all(Option::is_none) {
+            return;
+        }
+
+        let transfer = self.color.transfer();
+        let (decode, encode) = (transfer.map(|t| t.0), transfer.map(|t| t.1));
+        let (to_straight, back) = (
+            alpha_converter(self.alpha, AlphaMode::Straight),
+            alpha_converter(AlphaMode::Straight, self.alpha),
+        );
+        for p in &mut self.data {
+            let mut s = to_straight(*p);
+            for (c, lut) in s.iter_mut().zip(&luts) {
+                if let Some(lut) = lut {
+                    let linear = lut.get(decode.map_or(*c, |f| f(*c)));
+                    *c = encode.map_or(linear, |f| f(linear));
+                }
+            }
+            *p = back(s);
+        }
+        self.check();
+    }
+}
+
+/// Pixels of `img` in linear light and straight alpha
+fn linear_straight(img: &Image) -> Vec<WorkPixel> {
+    let decode = img.color.transfer().map(|t| t.0);
+    let to_straight = alpha_converter(img.alpha, AlphaMode::Straight);
+    img.data
+        .iter()
+        .map(|p| {
+            let p = to_straight(*p);
+            let c = |c: usize| decode.map_or(p[c], |f| f(p[c]));
+            [c(0), c(1), c(2), p[3]]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{max_diff, noise, photo},
+        ColorSpace,
+    };
+
+    /// Fraction of the pixels of each channel in each of 32 bins, in linear
+    /// light
+    fn histogram(img: &Image) -> [[f32; 32]; 3] {
+        let mut h = [[0.; 32]; 3];
+        let px = linear_straight(img);
+        for p in &px {
+            for c in 0..3 {
+                h[c][((p[c].clamp(0., 1.) * 32.) as usize).min(31)] += 1. / px.len() as f32;
+            }
+        }
+        h
+    }
+
+    /// Largest difference of any bin
+    fn histogram_diff(a: &Image, b: &Image) -> f32 {
+        let (a, b) = (histogram(a), histogram(b));
+        a.iter()
+            .flatten()
+            .zip(b.iter().flatten())
+            .map(|(a, b)| (a - b).abs())
+            .fold(0., f32::max)
+    }
+
+    /// `img` scaled by `k` in linear light
+    fn scaled(img: &Image, k: f32) -> Image {
+        let mut img = img.clone();
+        let (decode, encode) = img.color.transfer().unwrap();
+        img.map_pixels(|p| {
+            [0, 1, 2, 3].map(|c| {
+                if c < 3 {
+                    encode(decode(p[c]) * k)
+                } else {
+                    p[c]
+                }
+            })
+        });
+        img
+    }
+
+    #[test]
+    fn self_is_a_no_op() {
+        let img = photo((48, 32));
+        let mut matched = img.clone();
+        matched.match_histogram(&img);
+        assert!(max_diff(matched.pixels(), img.pixels()) < 0.01);
+    }
+
+    #[test]
+    fn darkened_is_restored() {
+        let img = photo((48, 32));
+        let dark = scaled(&img, 0.4);
+        let before = histogram_diff(&dark, &img);
+        let mut matched = dark.clone();
+        matched.match_histogram(&img);
+        let after = histogram_diff(&matched, &img);
+        assert!(after < 0.01 && after < before / 10., "{before} {after}");
+        // And from a reference of a different size
+        let mut matched = dark.clone();
+        matched.match_histogram(&img.resize((24, 16)));
+        assert!(histogram_diff(&matched, &img) < 0.03);
+    }
+
+    #[test]
+    fn constant_channel_is_finite() {
+        let mut seed = 5;
+        let mut img = photo((16, 16));
+        img.map_pixels(|p| [0.25, p[1], p[2], p[3]]);
+        let mut flat = img.clone();
+        flat.match_histogram(&photo((20, 12)));
+        assert!(flat.pixels().iter().flatten().all(|c| c.is_finite()));
+        let red = flat.pixels()[0][0];
+        assert!(flat.pixels().iter().all(|p| p[0] == red));
+
+        // To a constant reference
+        let mut to_flat = photo((16, 16));
+        to_flat.match_histogram(&img);
+        for p in to_flat.pixels() {
+            assert!(p.iter().all(|c| c.is_finite()));
+            assert!((p[0] - 0.25).abs() < 1e-3, "{p:?}");
+        }
+
+        // One pixel, and noise
+        let one = Image::from_bytes(&[9, 99, 199, 255], (1, 1), ColorSpace::sRGB);
+        let mut noisy = img.clone();
+        noisy.map_pixels(|p| [noise(&mut seed), p[1], p[2], p[3]]);
+        noisy.match_histogram(&one);
+        assert!(noisy.pixels().iter().flatten().all(|c| c.is_finite()));
+    }
+
+    #[test]
+    fn alpha_is_kept() {
+        let mut seed = 9;
+        let mut img = photo((16, 16));
+        img.map_pixels(|p| [p[0], p[1], p[2], noise(&mut seed)]);
+        let alpha: Vec<f32> = img.pixels().iter().map(|p| p[3]).collect();
+        let mut matched = img.clone();
+        matched.to_alpha_mode(AlphaMode::Premultiplied);
+        matched.match_histogram(&scaled(&photo((16, 16)), 0.5));
+        let got: Vec<f32> = matched.pixels().iter().map(|p| p[3]).collect();
+        assert_eq!(got, alpha);
+        assert_eq!(matched.alpha, AlphaMode::Premultiplied);
+    }
+
+    #[test]
+    fn nothing_visible_is_unchanged() {
+        let img = photo((16, 16));
+        let mut clear = img.clone();
+        clear.map_pixels(|p| [p[0], p[1], p[2], 0.]);
+        let mut matched = img.clone();
+        matched.match_histogram(&clear);
+        assert_eq!(matched.pixels(), img.pixels());
+        let mut empty = Image::from_bytes(&[], (0, 0), ColorSpace::sRGB);
+        empty.match_histogram(&img);
+        assert_eq!(empty.res, (0, 0));
+    }
+}
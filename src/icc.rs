@@ -0,0 +1,537 @@
+//! Minimal ICC profile parsing
+//!
+//! This only reads enough of a profile to figure out which [`ColorSpace`] it
+//! describes, it does not implement color management. Nothing is allocated.
+use crate::{
+    transforms::{srgb_to_rgb, ADOBE_RGB_GAMMA},
+    ColorSpace, ImageError,
+};
+
+/// Tone response curve of a profile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trc {
+    /// A pure power function
+    Gamma(f32),
+
+    /// An ICC `para` curve, with its function type and parameters
+    /// `g, a, b, c, d, e, f`, unused ones being zero
+    Parametric { function: u16, params: [f32; 7] },
+
+    /// A sampled `curv` table, with its number of entries, an estimated
+    /// gamma from the midpoint, and the curve it follows, if any
+    ///
+    /// Tables aren't kept, so the curve is found while parsing.
+    Table {
+        len: u32,
+        gamma: f32,
+        curve: Option<Curve>,
+    },
+}
+
+/// The shape of a [`Trc`], as far as matching color spaces goes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    /// The piecewise sRGB curve, also used by Display P3
+    Srgb,
+
+    /// A pure power function, `1` being linear
+    Gamma(f32),
+}
+
+/// How far, in encoded values, a curve can stray from the one it matches
+///
+/// Under half an 8 bit step, and a quarter of the largest difference between
+/// the sRGB curve and a gamma of 2.2.
+const CURVE_TOLERANCE: f32 = 2e-3;
+
+/// Which [`Curve`] `f`, from encoded to linear, follows, if any, checking
+/// a pure power function of `gamma`
+fn fit(f: impl Fn(f32) -> f32, gamma: f32) -> Option<Curve> {
+    let follows = |g: fn(f32, f32) -> f32| {
+        (0..=64).all(|i| {
+            let x = i as f32 / 64.;
+            (f(x) - g(x, gamma)).abs() <= CURVE_TOLERANCE
+        })
+    };
+    if follows(|x, _| srgb_to_rgb(x)) {
+        Some(Curve::Srgb)
+    } else if follows(libm::powf) {
+        Some(Curve::Gamma(gamma))
+    } else {
+        None
+    }
+}
+
+impl Trc {
+    /// Which [`Curve`] this follows, if any
+    pub fn curve(&self) -> Option<Curve> {
+        match *self {
+            Trc::Gamma(g) => Some(Curve::Gamma(g)),
+            Trc::Parametric { function, params } => {
+                let [g, a, b, c, d, e, f] = params;
+                // ICC.1 10.18, unused parameters being zero lets types
+                // share a formula
+                let para = |x: f32| match function {
+                    0 => libm::powf(x, g),
+                    1 | 2 if x >= -b / a => libm::powf(a * x + b, g) + c,
+                    1 | 2 => c,
+                    _ if x >= d => libm::powf(a * x + b, g) + e,
+                    _ => c * x + f,
+                };
+                fit(para, g)
+            }
+            Trc::Table { curve, .. } => curve,
+        }
+    }
+}
+
+/// Primaries well known enough to be matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnownPrimaries {
+    Srgb,
+    DisplayP3,
+    AdobeRgb,
+}
+
+/// The interesting parts of an ICC profile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IccSummary {
+    /// Red, green and blue colorants, in PCS XYZ (D50)
+    pub primaries: [[f32; 3]; 3],
+
+    /// Media white point XYZ
+    pub white: [f32; 3],
+
+    /// The red tone response curve
+    ///
+    /// Green and blue are assumed to match, which is true for all the
+    /// profiles this cares about.
+    pub trc: Trc,
+}
+
+/// Colorants of the standard profiles, D50 adapted as stored in ICC
+const SRGB: [[f32; 3]; 3] = [
+    [0.4361, 0.2225, 0.0139],
+    [0.3851, 0.7169, 0.0971],
+    [0.1431, 0.0606, 0.7141],
+];
+
+const DISPLAY_P3: [[f32; 3]; 3] = [
+    [0.5151, 0.2412, -0.0011],
+    [0.2920, 0.6922, 0.0419],
+    [0.1571, 0.0666, 0.7841],
+];
+
+const ADOBE_RGB: [[f32; 3]; 3] = [
+    [0.6097, 0.3111, 0.0195],
+    [0.2053, 0.6257, 0.0609],
+    [0.1492, 0.0632, 0.7446],
+];
+
+/// How close colorants have to be to match
+const TOLERANCE: f32 = 0.01;
+
+impl IccSummary {
+    /// Which well known primaries these are, if any
+    pub fn known_primaries(&self) -> Option<KnownPrimaries> {
+        let close = |k: &[[f32; 3]; 3]| {
+            self.primaries
+                .iter()
+                .flatten()
+                .zip(k.iter().flatten())
+                .all(|(a, b)| (a - b).abs() <= TOLERANCE)
+        };
+        if close(&SRGB) {
+            Some(KnownPrimaries::Srgb)
+        } else if close(&DISPLAY_P3) {
+            Some(KnownPrimaries::DisplayP3)
+        } else if close(&ADOBE_RGB) {
+            Some(KnownPrimaries::AdobeRgb)
+        } else {
+            None
+        }
+    }
+
+    /// The [`ColorSpace`] this profile describes, if there is one
+    ///
+    /// Both the primaries and the tone response curve have to match, so
+    /// for example linear P3, which has no [`ColorSpace`], is `None`
+    /// rather than [`ColorSpace::DisplayP3`].
+    pub fn closest_color_space(&self) -> Option<ColorSpace> {
+        let gamma = |target: f32, tolerance: f32| move |c: Curve| matches!(c, Curve::Gamma(g) if (g - target).abs() < tolerance);
+        let (linear, simple, adobe) = (
+            gamma(1., 0.01),
+            gamma(2.2, 0.05),
+            gamma(ADOBE_RGB_GAMMA, 0.05),
+        );
+        let curve = self.trc.curve()?;
+        match self.known_primaries()? {
+            KnownPrimaries::Srgb if curve == Curve::Srgb => Some(ColorSpace::sRGB),
+            KnownPrimaries::Srgb if linear(curve) => Some(ColorSpace::sRGBLinear),
+            KnownPrimaries::Srgb if simple(curve) => Some(ColorSpace::SimplesRGB),
+            KnownPrimaries::DisplayP3 if curve == Curve::Srgb => Some(ColorSpace::DisplayP3),
+            KnownPrimaries::AdobeRgb if adobe(curve) => Some(ColorSpace::AdobeRgb),
+            _ => None,
+        }
+    }
+}
+
+fn be_u16(data: &[u8], at: usize) -> Result<u16, ImageError> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or(ImageError::InvalidData)
+}
+
+fn be_u32(data: &[u8], at: usize) -> Result<u32, ImageError> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(ImageError::InvalidData)
+}
+
+fn s15_fixed16(data: &[u8], at: usize) -> Result<f32, ImageError> {
+    Ok(be_u32(data, at)? as i32 as f32 / 65536.)
+}
+
+/// The data of the tag with signature `sig`, if present
+fn find_tag<'a>(data: &'a [u8], sig: &[u8; 4]) -> Result<Option<&'a [u8]>, ImageError> {
+    let count = be_u32(data, 128)? as usize;
+    // Don't trust the count beyond what could actually fit
+    if count > (data.len() - 132) / 12 {
+        return Err(ImageError::InvalidData);
+    }
+    for i in 0..count {
+        let entry = 132 + i * 12;
+        if &data[entry..entry + 4] == sig {
+            let offset = be_u32(data, entry + 4)? as usize;
+            let size = be_u32(data, entry + 8)? as usize;
+            let end = offset.checked_add(size).ok_or(ImageError::InvalidData)?;
+            return data
+                .get(offset..end)
+                .map(Some)
+                .ok_or(ImageError::InvalidData);
+        }
+    }
+    Ok(None)
+}
+
+fn read_xyz(data: &[u8], sig: &[u8; 4]) -> Result<[f32; 3], ImageError> {
+    let tag = find_tag(data, sig)?.ok_or(ImageError::InvalidData)?;
+    if tag.get(..4) != Some(b"XYZ ") {
+        return Err(ImageError::InvalidData);
+    }
+    Ok([
+        s15_fixed16(tag, 8)?,
+        s15_fixed16(tag, 12)?,
+        s15_fixed16(tag, 16)?,
+    ])
+}
+
+fn read_trc(data: &[u8]) -> Result<Trc, ImageError> {
+    let tag = find_tag(data, b"rTRC")?.ok_or(ImageError::InvalidData)?;
+    match tag.get(..4) {
+        Some(b"curv") => {
+            let len = be_u32(tag, 8)?;
+            match len {
+                0 => Ok(Trc::Gamma(1.)),
+                1 => Ok(Trc::Gamma(be_u16(tag, 12)? as f32 / 256.)),
+                _ => {
+                    // Make sure the whole table is actually there
+                    be_u16(tag, 12 + (len as usize - 1) * 2)?;
+                    let entry = |i: usize| {
+                        u16::from_be_bytes([tag[12 + i * 2], tag[13 + i * 2]]) as f32 / 65535.
+                    };
+                    let mid = len as usize / 2;
+                    let x = mid as f32 / (len - 1) as f32;
+                    let y = entry(mid);
+                    let gamma = if y > 0. && y < 1. {
+                        libm::logf(y) / libm::logf(x)
+                    } else {
+                        1.
+                    };
+                    // Interpolated between entries, like color management
+                    // would
+                    let table = |x: f32| {
+                        let at = x * (len - 1) as f32;
+                        let i = (at as usize).min(len as usize - 2);
+                        let t = at - i as f32;
+                        entry(i) * (1. - t) + entry(i + 1) * t
+                    };
+                    let curve = fit(table, gamma);
+                    Ok(Trc::Table { len, gamma, curve })
+                }
+            }
+        }
+        Some(b"para") => {
+            let function = be_u16(tag, 8)?;
+            let count = match function {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                _ => return Err(ImageError::InvalidData),
+            };
+            let mut params = [0.; 7];
+            for (i, p) in params.iter_mut().enumerate().take(count) {
+                *p = s15_fixed16(tag, 12 + i * 4)?;
+            }
+            Ok(Trc::Parametric { function, params })
+        }
+        _ => Err(ImageError::InvalidData),
+    }
+}
+
+/// Parse the primaries and tone response curve out of an ICC profile
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidData`] if `data` isn't a valid ICC profile, or is
+///   missing the RGB colorant, white point, or `rTRC` tags
+pub fn parse_icc(data: &[u8]) -> Result<IccSummary, ImageError> {
+    if data.len() < 132 || data.get(36..40) != Some(b"acsp") {
+        return Err(ImageError::InvalidData);
+    }
+    let size = be_u32(data, 0)? as usize;
+    let data = data.get(..size).ok_or(ImageError::InvalidData)?;
+    if data.len() < 132 {
+        return Err(ImageError::InvalidData);
+    }
+    Ok(IccSummary {
+        primaries: [
+            read_xyz(data, b"rXYZ")?,
+            read_xyz(data, b"gXYZ")?,
+            read_xyz(data, b"bXYZ")?,
+        ],
+        white: read_xyz(data, b"wtpt")?,
+        trc: read_trc(data)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{vec, vec::Vec};
+
+    use super::*;
+
+    // Written by testdata/icc.py
+    const SRGB_ICC: &[u8] = include_bytes!("testdata/srgb.icc");
+    const DISPLAY_P3_ICC: &[u8] = include_bytes!("testdata/display-p3.icc");
+    const ADOBE_RGB_ICC: &[u8] = include_bytes!("testdata/adobe-rgb.icc");
+
+    // The raw s15Fixed16 values of the XYZ tags of the HP/Microsoft
+    // "sRGB IEC61966-2.1" v2 profile, and Apple's v4 "Display P3"
+    const SRGB_V2_RXYZ: [u32; 3] = [0x6fa2, 0x38f5, 0x0390];
+    const SRGB_V2_GXYZ: [u32; 3] = [0x6299, 0xb785, 0x18da];
+    const SRGB_V2_BXYZ: [u32; 3] = [0x24a0, 0x0f84, 0xb6cf];
+    // The v2 profile's media white is D65, not the D50 of the PCS
+    const SRGB_V2_WTPT: [u32; 3] = [0xf351, 0x1_0000, 0x1_16cc];
+    const P3_RXYZ: [u32; 3] = [0x83df, 0x3dbe, 0xffff_ffbb];
+    const P3_GXYZ: [u32; 3] = [0x4abf, 0xb137, 0x0ab9];
+    const P3_BXYZ: [u32; 3] = [0x2838, 0x110b, 0xc8b9];
+    const P3_WTPT: [u32; 3] = [0xf6d6, 0x1_0000, 0xd32d];
+    // Its rTRC, a type 3 para with g, a, b, c and d
+    const P3_PARA: [u32; 5] = [0x2_6666, 0xf2a7, 0x0d59, 0x13d0, 0x0a5b];
+
+    fn xyz(raw: [u32; 3]) -> Vec<u8> {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        tag.extend(raw.iter().flat_map(|v| v.to_be_bytes()));
+        tag
+    }
+
+    fn para(function: u16, raw: &[u32]) -> Vec<u8> {
+        let mut tag = b"para\0\0\0\0".to_vec();
+        tag.extend(function.to_be_bytes());
+        tag.extend([0; 2]);
+        tag.extend(raw.iter().flat_map(|v| v.to_be_bytes()));
+        tag
+    }
+
+    fn curv(entries: &[u16]) -> Vec<u8> {
+        let mut tag = b"curv\0\0\0\0".to_vec();
+        tag.extend((entries.len() as u32).to_be_bytes());
+        tag.extend(entries.iter().flat_map(|v| v.to_be_bytes()));
+        tag
+    }
+
+    /// A `curv` table of `len` entries of `f`
+    fn table(len: usize, f: impl Fn(f32) -> f32) -> Vec<u8> {
+        let entries: Vec<u16> = (0..len)
+            .map(|i| libm::roundf(f(i as f32 / (len - 1) as f32) * 65535.) as u16)
+            .collect();
+        curv(&entries)
+    }
+
+    /// A profile with just the colorants of `primaries`, `white`, and `trc`
+    fn profile(primaries: [[u32; 3]; 3], white: [u32; 3], trc: Vec<u8>) -> Vec<u8> {
+        let tags = [
+            (b"rXYZ", xyz(primaries[0])),
+            (b"gXYZ", xyz(primaries[1])),
+            (b"bXYZ", xyz(primaries[2])),
+            (b"wtpt", xyz(white)),
+            (b"rTRC", trc),
+        ];
+        let mut icc = vec![0; 128];
+        icc[36..40].copy_from_slice(b"acsp");
+        icc.extend((tags.len() as u32).to_be_bytes());
+        let mut offset = 132 + tags.len() * 12;
+        for (sig, data) in &tags {
+            icc.extend(*sig);
+            icc.extend((offset as u32).to_be_bytes());
+            icc.extend((data.len() as u32).to_be_bytes());
+            offset += data.len().next_multiple_of(4);
+        }
+        for (_, data) in &tags {
+            icc.extend(data);
+            icc.resize(icc.len().next_multiple_of(4), 0);
+        }
+        let size = icc.len() as u32;
+        icc[..4].copy_from_slice(&size.to_be_bytes());
+        icc
+    }
+
+    const SRGB_V2: [[u32; 3]; 3] = [SRGB_V2_RXYZ, SRGB_V2_GXYZ, SRGB_V2_BXYZ];
+    const P3: [[u32; 3]; 3] = [P3_RXYZ, P3_GXYZ, P3_BXYZ];
+
+    fn closest(primaries: [[u32; 3]; 3], trc: Vec<u8>) -> Option<ColorSpace> {
+        let white = match primaries == SRGB_V2 {
+            true => SRGB_V2_WTPT,
+            false => P3_WTPT,
+        };
+        parse_icc(&profile(primaries, white, trc))
+            .unwrap()
+            .closest_color_space()
+    }
+
+    #[test]
+    fn real_profile_tags() {
+        // The real sRGB table is 1024 entries too, of the same curve
+        let srgb = parse_icc(&profile(SRGB_V2, SRGB_V2_WTPT, table(1024, srgb_to_rgb))).unwrap();
+        assert_eq!(srgb.known_primaries(), Some(KnownPrimaries::Srgb));
+        assert_eq!(srgb.closest_color_space(), Some(ColorSpace::sRGB));
+        assert!((srgb.white[2] - 1.0891).abs() < 1e-4, "{:?}", srgb.white);
+
+        let p3 = parse_icc(&profile(P3, P3_WTPT, para(3, &P3_PARA))).unwrap();
+        assert_eq!(p3.known_primaries(), Some(KnownPrimaries::DisplayP3));
+        assert_eq!(p3.trc.curve(), Some(Curve::Srgb));
+        assert_eq!(p3.closest_color_space(), Some(ColorSpace::DisplayP3));
+        assert!((p3.white[2] - 0.8249).abs() < 1e-4, "{:?}", p3.white);
+    }
+
+    #[test]
+    fn curves_have_to_match_too() {
+        let linear = |len| table(len, |x| x);
+        let gamma = |g: f32| table(256, move |x| libm::powf(x, g));
+        // Linear sRGB, however it's written
+        for trc in [
+            curv(&[]),
+            curv(&[0x100]),
+            linear(2),
+            linear(1024),
+            para(0, &[0x1_0000]),
+        ] {
+            assert_eq!(closest(SRGB_V2, trc), Some(ColorSpace::sRGBLinear));
+        }
+        assert_eq!(closest(SRGB_V2, gamma(2.2)), Some(ColorSpace::SimplesRGB));
+        assert_eq!(
+            closest(SRGB_V2, curv(&[0x233])),
+            Some(ColorSpace::SimplesRGB)
+        );
+
+        // Linear P3 isn't Display P3, and there's no color space for it
+        for trc in [curv(&[]), linear(1024), para(0, &[0x1_0000])] {
+            assert_eq!(closest(P3, trc), None);
+        }
+        assert_eq!(
+            closest(P3, table(1024, srgb_to_rgb)),
+            Some(ColorSpace::DisplayP3)
+        );
+
+        // Curves no color space has
+        let rec709 = [0x2_38e4, 0xe8f0, 0x1710, 0x38e4, 0x14bc];
+        assert_eq!(closest(SRGB_V2, para(3, &rec709)), None);
+        assert_eq!(closest(SRGB_V2, gamma(1.8)), None);
+        assert_eq!(closest(P3, gamma(2.2)), None);
+        // Nothing like any curve
+        assert_eq!(closest(SRGB_V2, curv(&[0xffff, 0, 0xffff])), None);
+    }
+
+    #[test]
+    fn srgb() {
+        let icc = parse_icc(SRGB_ICC).unwrap();
+        assert_eq!(icc.known_primaries(), Some(KnownPrimaries::Srgb));
+        assert_eq!(icc.closest_color_space(), Some(ColorSpace::sRGB));
+        let Trc::Table { len, gamma, curve } = icc.trc else {
+            panic!("{:?}", icc.trc);
+        };
+        assert_eq!((len, curve), (1024, Some(Curve::Srgb)));
+        // The sRGB curve is about 2.2 in the middle
+        assert!((gamma - 2.2).abs() < 0.1, "{gamma}");
+        assert!((icc.white[1] - 1.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn display_p3() {
+        let icc = parse_icc(DISPLAY_P3_ICC).unwrap();
+        assert_eq!(icc.known_primaries(), Some(KnownPrimaries::DisplayP3));
+        assert_eq!(icc.closest_color_space(), Some(ColorSpace::DisplayP3));
+        let Trc::Parametric { function, params } = icc.trc else {
+            panic!("{:?}", icc.trc);
+        };
+        assert_eq!(function, 3);
+        assert!((params[0] - 2.4).abs() < 1e-4 && (params[4] - 0.04045).abs() < 1e-4);
+        assert_eq!(params[5..], [0.; 2]);
+        // Negative s15Fixed16 values
+        assert!(icc.primaries[0][2] < 0.);
+    }
+
+    #[test]
+    fn adobe_rgb() {
+        let icc = parse_icc(ADOBE_RGB_ICC).unwrap();
+        assert_eq!(icc.known_primaries(), Some(KnownPrimaries::AdobeRgb));
+        assert_eq!(icc.trc, Trc::Gamma(563. / 256.));
+        assert_eq!(icc.closest_color_space(), Some(ColorSpace::AdobeRgb));
+    }
+
+    #[test]
+    fn simple_srgb_gammas() {
+        let mut icc = parse_icc(SRGB_ICC).unwrap();
+        icc.trc = Trc::Gamma(2.2);
+        assert_eq!(icc.closest_color_space(), Some(ColorSpace::SimplesRGB));
+        icc.trc = Trc::Gamma(1.);
+        assert_eq!(icc.closest_color_space(), Some(ColorSpace::sRGBLinear));
+    }
+
+    #[test]
+    fn truncated() {
+        for icc in [SRGB_ICC, DISPLAY_P3_ICC, ADOBE_RGB_ICC] {
+            for len in 0..icc.len() {
+                assert_eq!(
+                    parse_icc(&icc[..len]),
+                    Err(ImageError::InvalidData),
+                    "{len}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_tag_table() {
+        let set = |at: usize, v: u32| {
+            let mut icc: Vec<u8> = DISPLAY_P3_ICC.to_vec();
+            icc[at..at + 4].copy_from_slice(&v.to_be_bytes());
+            parse_icc(&icc)
+        };
+        // A huge tag count
+        assert_eq!(set(128, u32::MAX), Err(ImageError::InvalidData));
+        // The first tag, desc, isn't read, so corrupt the second: wtpt
+        assert_eq!(set(132 + 12 + 4, u32::MAX), Err(ImageError::InvalidData));
+        assert_eq!(set(132 + 12 + 8, u32::MAX), Err(ImageError::InvalidData));
+        // The size in the header past the end
+        assert_eq!(set(0, u32::MAX), Err(ImageError::InvalidData));
+        assert_eq!(set(36, 0), Err(ImageError::InvalidData));
+        // Garbage anywhere never panics
+        for at in 0..DISPLAY_P3_ICC.len() - 4 {
+            let _ = set(at, 0xdead_beef);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Icon set export
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+
+use crate::{transforms::*, Image, ImageError, ResXY, ScaleFilter};
+
+impl Image {
+    /// Render the image at each of `sizes`, for icon sets
+    ///
+    /// Sizes are generated largest first, each from the smallest already
+    /// generated level that is at least as large, rather than always from the
+    /// original, which is a lot less work for long ladders. Filtering is done
+    /// in linear light.
+    ///
+    /// The result is sorted from largest to smallest, duplicates removed.
+    /// A size equal to the original is an exact copy.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if any size is zero
+    pub fn export_sizes(
+        &self,
+        sizes: &[ResXY],
+        filter: ScaleFilter,
+    ) -> Result<Vec<Image>, ImageError> {
+        if sizes.iter().any(|(w, h)| *w == 0 || *h == 0) {
+            return Err(ImageError::InvalidArgument);
+        }
+        let mut sizes: Vec<ResXY> = sizes.to_vec();
+        // Descending by area, then by width, so the cascade is well defined
+        sizes.sort_unstable_by_key(|s| Reverse((s.0 as u64 * s.1 as u64, s.0)));
+        sizes.dedup();
+
+        // All levels are kept linear until the end
+        let transfer = self.color.transfer();
+        let mut linear = self.clone();
+        if let Some((decode, _)) = transfer {
+            apply_transfer(&mut linear.data, decode);
+        }
+
+        let mut levels: Vec<Image> = Vec::with_capacity(sizes.len());
+        for size in sizes {
+            let fits = |img: &&Image| img.width() >= size.0 && img.height() >= size.1;
+            // Smallest already generated level we can derive from
+            let mut level = levels.iter().rev().find(fits).unwrap_or(&linear).clone();
+            level.scale_with(size, filter);
+            levels.push(level);
+        }
+
+        for level in &mut levels {
+            if level.res == self.res {
+                *level = self.clone();
+            } else if let Some((_, encode)) = transfer {
+                apply_transfer(&mut level.data, encode);
+            }
+        }
+        Ok(levels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::photo, ColorSpace};
+
+    const LADDER: [ResXY; 8] = [
+        (16, 16),
+        (64, 64),
+        (32, 32),
+        (24, 24),
+        (64, 64),
+        (48, 48),
+        (16, 16),
+        (60, 60),
+    ];
+
+    #[test]
+    fn export_sizes_order_and_count() {
+        let img = photo((64, 64));
+        let out = img.export_sizes(&LADDER, ScaleFilter::Box).unwrap();
+        let res: Vec<ResXY> = out.iter().map(|i| i.res).collect();
+        assert_eq!(
+            res,
+            [(64, 64), (60, 60), (48, 48), (32, 32), (24, 24), (16, 16)]
+        );
+        // The source size is an exact copy
+        assert_eq!(out[0].pixels(), img.pixels());
+    }
+
+    #[test]
+    fn export_sizes_cascades() {
+        // No transfer function, so levels can be compared exactly
+        let mut img = photo((64, 64));
+        img.color = ColorSpace::sRGBLinear;
+        let out = img
+            .export_sizes(&[(16, 16), (32, 32), (48, 48)], ScaleFilter::Bilinear)
+            .unwrap();
+        let mut from_32 = out[1].clone();
+        from_32.scale_with((16, 16), ScaleFilter::Bilinear);
+        assert_eq!(out[2].pixels(), from_32.pixels());
+        let mut from_48 = out[0].clone();
+        from_48.scale_with((32, 32), ScaleFilter::Bilinear);
+        assert_eq!(out[1].pixels(), from_48.pixels());
+
+        let mut direct = img.clone();
+        direct.scale_with((16, 16), ScaleFilter::Bilinear);
+        assert_ne!(out[2].pixels(), direct.pixels());
+    }
+
+    #[test]
+    fn export_sizes_in_linear_light() {
+        let img = Image::from_bytes(
+            &[0, 0, 0, 255, 255, 255, 255, 255],
+            (2, 1),
+            ColorSpace::sRGB,
+        );
+        let out = img.export_sizes(&[(1, 1)], ScaleFilter::Box).unwrap();
+        let expected = ColorSpace::sRGB.transfer().unwrap().1(0.5);
+        assert!((out[0].pixels()[0][0] - expected).abs() < 1e-5);
+    }
+
+    #[test]
+    fn export_sizes_rejects_zero() {
+        let img = photo((8, 8));
+        for size in [(0, 4), (4, 0)] {
+            assert_eq!(
+                img.export_sizes(&[(4, 4), size], ScaleFilter::Box).err(),
+                Some(ImageError::InvalidArgument)
+            );
+        }
+    }
+
+    #[test]
+    fn export_empty() {
+        let img = Image::from_bytes(&[], (0, 5), ColorSpace::sRGB);
+        let sizes = img
+            .export_sizes(&[(3, 3), (2, 2)], ScaleFilter::Box)
+            .unwrap();
+        assert_eq!(sizes.len(), 2);
+    }
+}
@@ -0,0 +1,264 @@
+//! Adam7 interlacing, for progressive display over slow links
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    layout::{prepare, validate_exact},
+    ColorSpace, Image, ImageError, PixelFormat, ResXY, XY,
+};
+
+/// Adam7 passes, as `(origin, step, block)`, where `block` is the area each
+/// pixel covers in a preview after that pass
+const ADAM7: [(XY, XY, ResXY); 7] = [
+    ((0, 0), (8, 8), (8, 8)),
+    ((4, 0), (8, 8), (4, 8)),
+    ((0, 4), (4, 8), (4, 4)),
+    ((2, 0), (4, 4), (2, 4)),
+    ((0, 2), (2, 4), (2, 2)),
+    ((1, 0), (2, 2), (1, 2)),
+    ((0, 1), (1, 2), (1, 1)),
+];
+
+/// Geometry of one Adam7 pass over an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PassInfo {
+    /// Pass number, `0..7`
+    pub index: usize,
+    /// First source pixel in the pass
+    pub origin: XY,
+    /// Distance between source pixels in the pass
+    pub step: XY,
+    /// Size of the pass, which may be zero for small images
+    pub res: ResXY,
+}
+
+impl PassInfo {
+    /// Pass `index` of an image of `res`
+    ///
+    /// # Panics
+    ///
+    /// - If `index` is 7 or more
+    pub fn new(index: usize, (w, h): ResXY) -> Self {
+        let (origin, step, _) = ADAM7[index];
+        let len = |size: u32, o: u32, s: u32| size.saturating_sub(o).div_ceil(s);
+        Self {
+            index,
+            origin,
+            step,
+            res: (len(w, origin.0, step.0), len(h, origin.1, step.1)),
+        }
+    }
+
+    /// Source coordinates of pixel `xy` in the pass
+    fn source(&self, (x, y): XY) -> XY {
+        (
+            self.origin.0 + x * self.step.0,
+            self.origin.1 + y * self.step.1,
+        )
+    }
+}
+
+impl Image {
+    /// Split the image into the 7 Adam7 passes, exported in `format` with
+    /// straight alpha, like [`Image::to_raw`]
+    ///
+    /// Sending these in order to an [`InterlacedAssembler`] gives a coarse
+    /// preview after the first 1/64th of the data, refining from there. Each
+    /// pixel is in exactly one pass. Passes of small images may be empty,
+    /// but are still produced.
+    pub fn interlace_passes(
+        &self,
+        format: PixelFormat,
+    ) -> impl Iterator<Item = (PassInfo, Vec<u8>)> + '_ {
+        let bpp = format.bytes_per_pixel();
+        let transfer = self.color.transfer();
+        (0..ADAM7.len()).map(move |i| {
+            let info = PassInfo::new(i, self.res);
+            let mut out = vec![0; info.res.0 as usize * info.res.1 as usize * bpp];
+            let mut o = out.chunks_exact_mut(bpp);
+            for y in 0..info.res.1 {
+                for x in 0..info.res.0 {
+                    let (sx, sy) = info.source((x, y));
+                    let p = self.data[(sy * self.width() + sx) as usize];
+                    // Sized to match
+                    let o = o.next().unwrap();
+                    format.encode(prepare(p, format, self.alpha, transfer), o);
+                }
+            }
+            (info, out)
+        })
+    }
+}
+
+/// Receiving side of [`Image::interlace_passes`]
+#[derive(Debug, Clone)]
+pub struct InterlacedAssembler {
+    /// Raw pixels, tightly packed, as far as received
+    data: Vec<u8>,
+    res: ResXY,
+    format: PixelFormat,
+    color: ColorSpace,
+    passes: usize,
+}
+
+impl InterlacedAssembler {
+    /// Assemble an image of `res`, with passes in `format`
+    pub fn new(res: ResXY, format: PixelFormat, color: ColorSpace) -> Self {
+        let len = res.0 as usize * res.1 as usize * format.bytes_per_pixel();
+        Self {
+            data: vec![0; len],
+            res,
+            format,
+            color,
+            passes: 0,
+        }
+    }
+
+    /// Geometry of the next pass expected, if any
+    pub fn next_pass(&self) -> Option<PassInfo> {
+        (self.passes < ADAM7.len()).then(|| PassInfo::new(self.passes, self.res))
+    }
+
+    /// Number of passes received so far
+    pub fn passes(&self) -> usize {
+        self.passes
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.passes == ADAM7.len()
+    }
+
+    /// Add the next pass
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if every pass was already received
+    /// - [`ImageError::BufferSize`] if `data` is the wrong size for the pass
+    pub fn push(&mut self, data: &[u8]) -> Result<(), ImageError> {
+        let info = self.next_pass().ok_or(ImageError::InvalidArgument)?;
+        validate_exact(data, self.format, info.res)?;
+        let bpp = self.format.bytes_per_pixel();
+        let mut pixels = data.chunks_exact(bpp);
+        for y in 0..info.res.1 {
+            for x in 0..info.res.0 {
+                let (sx, sy) = info.source((x, y));
+                let i = (sy as usize * self.res.0 as usize + sx as usize) * bpp;
+                // Checked above
+                self.data[i..i + bpp].copy_from_slice(pixels.next().unwrap());
+            }
+        }
+        self.passes += 1;
+        Ok(())
+    }
+
+    /// The image so far, with missing pixels copied from the nearest
+    /// received one above and to the left
+    ///
+    /// Before any pass this is all zero, after the last it's exact.
+    pub fn preview(&self) -> Image {
+        let (w, h) = (self.res.0 as usize, self.res.1 as usize);
+        let bpp = self.format.bytes_per_pixel();
+        let data = match self.passes {
+            0 | 7.. => self.data.clone(),
+            n => {
+                let (bw, bh) = ADAM7[n - 1].2;
+                let (bw, bh) = (bw as usize, bh as usize);
+                let mut out = vec![0; self.data.len()];
+                for y in 0..h {
+                    for x in 0..w {
+                        let src = ((y - y % bh) * w + x - x % bw) * bpp;
+                        let i = (y * w + x) * bpp;
+                        out[i..i + bpp].copy_from_slice(&self.data[src..src + bpp]);
+                    }
+                }
+                out
+            }
+        };
+        // Sized in `new`
+        Image::from_raw(&data, self.res, self.format, self.color).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::ramp;
+
+    const SIZES: [ResXY; 6] = [(1, 1), (3, 2), (8, 8), (13, 7), (17, 9), (2, 16)];
+
+    #[test]
+    fn reassembles_exactly() {
+        for res in SIZES {
+            let img = ramp(res);
+            for format in [PixelFormat::Rgba8888, PixelFormat::Rgb565Le] {
+                let mut asm = InterlacedAssembler::new(res, format, ColorSpace::sRGB);
+                for (info, data) in img.interlace_passes(format) {
+                    assert_eq!(asm.next_pass(), Some(info));
+                    asm.push(&data).unwrap();
+                }
+                assert!(asm.is_complete());
+                assert_eq!(asm.next_pass(), None);
+                assert_eq!(
+                    asm.preview().to_raw(format),
+                    img.to_raw(format),
+                    "{res:?} {format:?}"
+                );
+                assert_eq!(asm.push(&[]), Err(ImageError::InvalidArgument));
+            }
+        }
+    }
+
+    #[test]
+    fn every_pixel_once() {
+        for res in SIZES {
+            let mut seen = vec![0; res.0 as usize * res.1 as usize];
+            for i in 0..7 {
+                let info = PassInfo::new(i, res);
+                for y in 0..info.res.1 {
+                    for x in 0..info.res.0 {
+                        let (sx, sy) = info.source((x, y));
+                        seen[(sy * res.0 + sx) as usize] += 1;
+                    }
+                }
+            }
+            assert!(seen.iter().all(|n| *n == 1), "{res:?}");
+        }
+        let sizes: Vec<ResXY> = (0..7).map(|i| PassInfo::new(i, (13, 7)).res).collect();
+        assert_eq!(
+            sizes,
+            [(2, 1), (2, 1), (4, 1), (3, 2), (7, 2), (6, 4), (13, 3)]
+        );
+        // Small images have empty passes, but still all of them
+        let img = ramp((1, 1));
+        let lens: Vec<usize> = img
+            .interlace_passes(PixelFormat::Gray8)
+            .map(|(_, d)| d.len())
+            .collect();
+        assert_eq!(lens, [1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn preview_after_first_pass() {
+        let img = ramp((13, 7));
+        let format = PixelFormat::Rgba8888;
+        let mut asm = InterlacedAssembler::new(img.res, format, img.color);
+        assert_eq!(asm.preview().res, (13, 7));
+        let mut passes = img.interlace_passes(format);
+        asm.push(&passes.next().unwrap().1).unwrap();
+        let preview = asm.preview();
+        assert_eq!(preview.res, (13, 7));
+        assert_eq!(asm.passes(), 1);
+        for y in 0..7 {
+            for x in 0..13 {
+                assert_eq!(preview.get_pixel((x, y)), img.get_pixel((x - x % 8, 0)));
+            }
+        }
+        assert_eq!(
+            asm.push(&[0; 3]),
+            Err(ImageError::BufferSize {
+                expected: 8,
+                actual: 3
+            })
+        );
+        assert_eq!(asm.passes(), 1);
+    }
+}
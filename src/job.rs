@@ -0,0 +1,128 @@
+//! Incremental processing
+//!
+//! Jobs split long operations into steps of a bounded number of rows, so
+//! callers can do other work, like feeding a watchdog, in between. All state
+//! lives in the job, any number of them can be interleaved.
+use crate::{convert::convert_image_rows, ColorSpace, Image};
+
+/// Progress of a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    /// There are rows left to process
+    InProgress,
+
+    /// Everything has been processed, call `finish`
+    Done,
+}
+
+/// Incremental [`Image::to_color`], see [`Image::color_job`]
+#[derive(Debug)]
+pub struct ColorJob<'a> {
+    img: &'a mut Image,
+    color: ColorSpace,
+    next_row: u32,
+}
+
+impl ColorJob<'_> {
+    /// Convert up to `max_rows` more rows
+    pub fn step(&mut self, max_rows: u32) -> JobStatus {
+        let (w, h) = (self.img.width() as usize, self.img.height());
+        let end = self.next_row.saturating_add(max_rows).min(h);
+        let rows = self.next_row as usize * w..end as usize * w;
+        let img = &mut *self.img;
+        convert_image_rows(&mut img.data[rows], img.alpha, img.color, self.color);
+        self.next_row = end;
+        self.status()
+    }
+
+    pub fn status(&self) -> JobStatus {
+        if self.next_row >= self.img.height() {
+            JobStatus::Done
+        } else {
+            JobStatus::InProgress
+        }
+    }
+
+    /// Convert any remaining rows and update the image's color space
+    pub fn finish(mut self) {
+        self.step(u32::MAX);
+        self.img.color = self.color;
+    }
+}
+
+impl Image {
+    /// Start converting the image to `color` incrementally, see
+    /// [`Image::to_color`]
+    ///
+    /// The image keeps its old color space until [`ColorJob::finish`], if the
+    /// job is dropped before that the image is left partially converted.
+    pub fn color_job(&mut self, color: ColorSpace) -> ColorJob<'_> {
+        ColorJob {
+            img: self,
+            color,
+            next_row: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::photo;
+
+    #[test]
+    fn color_job_matches_to_color() {
+        for rows in [1, 3, 100] {
+            let mut expected = photo((9, 7));
+            expected.to_color(ColorSpace::DisplayP3);
+            let mut img = photo((9, 7));
+            let mut job = img.color_job(ColorSpace::DisplayP3);
+            let mut steps = 0;
+            while job.step(rows) == JobStatus::InProgress {
+                steps += 1;
+            }
+            assert_eq!(steps, 7_u32.div_ceil(rows) - 1);
+            job.finish();
+            assert_eq!(img.color, ColorSpace::DisplayP3);
+            assert_eq!(img.pixels(), expected.pixels());
+        }
+    }
+
+    #[test]
+    fn color_job_step_zero() {
+        let mut img = photo((4, 4));
+        assert_eq!(
+            img.color_job(ColorSpace::sRGBLinear).step(0),
+            JobStatus::InProgress
+        );
+        assert_eq!(img.pixels(), photo((4, 4)).pixels());
+        assert_eq!(img.color, ColorSpace::sRGB);
+    }
+
+    #[test]
+    fn color_jobs_interleave() {
+        let (mut a, mut b) = (photo((6, 8)), photo((3, 5)));
+        let mut ja = a.color_job(ColorSpace::sRGBLinear);
+        let mut jb = b.color_job(ColorSpace::DisplayP3);
+        while (ja.step(2) == JobStatus::InProgress) | (jb.step(1) == JobStatus::InProgress) {}
+        ja.finish();
+        jb.finish();
+        let (mut ea, mut eb) = (photo((6, 8)), photo((3, 5)));
+        ea.to_color(ColorSpace::sRGBLinear);
+        eb.to_color(ColorSpace::DisplayP3);
+        assert_eq!(a.pixels(), ea.pixels());
+        assert_eq!(b.pixels(), eb.pixels());
+    }
+
+    #[test]
+    fn color_job_finish_early() {
+        // Finishing converts whatever is left
+        let mut img = photo((5, 6));
+        let mut job = img.color_job(ColorSpace::sRGBLinear);
+        job.step(2);
+        job.finish();
+        let mut expected = photo((5, 6));
+        expected.to_color(ColorSpace::sRGBLinear);
+        assert_eq!(img.pixels(), expected.pixels());
+    }
+}
@@ -0,0 +1,273 @@
+//! Connected component labeling
+use alloc::{vec, vec::Vec};
+
+use crate::{FloatXY, Image, ResXY, WorkPixel, XY};
+
+/// Which neighbors count as connected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Only horizontal and vertical neighbors
+    Four,
+
+    /// Horizontal, vertical, and diagonal neighbors
+    Eight,
+}
+
+/// Statistics of one connected component
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Component {
+    /// Number of pixels
+    pub count: u32,
+
+    /// Bounding box, as `(origin, size)`
+    pub bounds: (XY, ResXY),
+
+    /// Average pixel position
+    pub centroid: FloatXY,
+}
+
+/// Per pixel labels from [`Image::connected_components`]
+///
+/// Label `0` is background, components are labeled from `1` in the order
+/// they're first seen, top to bottom, left to right.
+#[derive(Debug, Clone)]
+pub struct Labels {
+    labels: Vec<u32>,
+    res: ResXY,
+    components: Vec<Component>,
+}
+
+impl Labels {
+    /// Per pixel labels, row major
+    pub fn labels(&self) -> &[u32] {
+        &self.labels
+    }
+
+    /// Label of the pixel at `xy`, or `None` if it's out of bounds
+    pub fn get(&self, (x, y): XY) -> Option<u32> {
+        if x < self.res.0 && y < self.res.1 {
+            Some(self.labels[(y * self.res.0 + x) as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Components, the component labeled `n` is at index `n - 1`
+    pub fn components(&self) -> &[Component] {
+        &self.components
+    }
+
+    /// Number of components
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    /// Bounding boxes of all components, largest pixel count first
+    pub fn bounding_boxes(&self) -> Vec<(XY, ResXY)> {
+        let mut c = self.components.clone();
+        c.sort_by_key(|c| core::cmp::Reverse(c.count));
+        c.into_iter().map(|c| c.bounds).collect()
+    }
+}
+
+fn find(parent: &mut [u32], mut i: u32) -> u32 {
+    while parent[i as usize] != i {
+        // Path halving
+        parent[i as usize] = parent[parent[i as usize] as usize];
+        i = parent[i as usize];
+    }
+    i
+}
+
+fn union(parent: &mut [u32], a: u32, b: u32) {
+    let (a, b) = (find(parent, a), find(parent, b));
+    // Keep the smaller, earlier, label as root
+    let (lo, hi) = (a.min(b), a.max(b));
+    parent[hi as usize] = lo;
+}
+
+impl Image {
+    /// Label connected regions of pixels matching `predicate`
+    ///
+    /// This is the classic two pass union-find algorithm.
+    pub fn connected_components(
+        &self,
+        predicate: impl Fn(WorkPixel) -> bool,
+        connectivity: Connectivity,
+    ) -> Labels {
+        let (w, h) = (self.width() as usize, self.height() as usize);
+        let mut labels = vec![0u32; w * h];
+        // Provisional label equivalences, index 0 is background
+        let mut parent = vec![0u32];
+
+        let offsets: &[(isize, isize)] = match connectivity {
+            Connectivity::Four => &[(-1, 0), (0, -1)],
+            Connectivity::Eight => &[(-1, 0), (-1, -1), (0, -1), (1, -1)],
+        };
+        for y in 0..h {
+            for x in 0..w {
+                if !predicate(self.data[y * w + x]) {
+                    continue;
+                }
+                let mut label = 0;
+                for &(dx, dy) in offsets {
+                    let (nx, ny) = (x as isize + dx, y as isize + dy);
+                    if nx < 0 || ny < 0 || nx as usize >= w {
+                        continue;
+                    }
+                    let n = labels[ny as usize * w + nx as usize];
+                    if n == 0 {
+                        continue;
+                    }
+                    if label == 0 {
+                        label = n;
+                    } else if n != label {
+                        union(&mut parent, label, n);
+                    }
+                }
+                if label == 0 {
+                    label = parent.len() as u32;
+                    parent.push(label);
+                }
+                labels[y * w + x] = label;
+            }
+        }
+
+        // Resolve to compact final labels and gather stats
+        let mut remap = vec![0u32; parent.len()];
+        let mut components: Vec<Component> = Vec::new();
+        let mut sums: Vec<(u64, u64)> = Vec::new();
+        for (i, l) in labels.iter_mut().enumerate() {
+            if *l == 0 {
+                continue;
+            }
+            let root = find(&mut parent, *l) as usize;
+            if remap[root] == 0 {
+                components.push(Component {
+                    count: 0,
+                    bounds: ((u32::MAX, u32::MAX), (0, 0)),
+                    centroid: (0., 0.),
+                });
+                sums.push((0, 0));
+                remap[root] = components.len() as u32;
+            }
+            *l = remap[root];
+
+            let (x, y) = ((i % w) as u32, (i / w) as u32);
+            let c = &mut components[*l as usize - 1];
+            let s = &mut sums[*l as usize - 1];
+            c.count += 1;
+            // Size holds the max corner until the end
+            let ((x0, y0), (x1, y1)) = &mut c.bounds;
+            *x0 = (*x0).min(x);
+            *y0 = (*y0).min(y);
+            *x1 = (*x1).max(x);
+            *y1 = (*y1).max(y);
+            s.0 += x as u64;
+            s.1 += y as u64;
+        }
+        for (c, s) in components.iter_mut().zip(sums) {
+            let ((x0, y0), (x1, y1)) = c.bounds;
+            c.bounds = ((x0, y0), (x1 - x0 + 1, y1 - y0 + 1));
+            let n = c.count as f32;
+            c.centroid = (s.0 as f32 / n, s.1 as f32 / n);
+        }
+
+        Labels {
+            labels,
+            res: self.res,
+            components,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::ColorSpace;
+
+    /// Image from rows of `#` for set and `.` for clear pixels
+    fn mask(rows: &[&str]) -> Image {
+        let res = (rows[0].len() as u32, rows.len() as u32);
+        let data: Vec<u8> = rows
+            .iter()
+            .flat_map(|r| r.bytes())
+            .flat_map(|b| if b == b'#' { [255; 4] } else { [0, 0, 0, 255] })
+            .collect();
+        Image::from_bytes(&data, res, ColorSpace::AsIs)
+    }
+
+    fn set(p: WorkPixel) -> bool {
+        p[0] > 0.5
+    }
+
+    #[test]
+    fn two_squares() {
+        let img = mask(&[
+            "##......", //
+            "##......", "....###.", "....###.", "....###.",
+        ]);
+        let labels = img.connected_components(set, Connectivity::Four);
+        assert_eq!(labels.len(), 2);
+        let c = labels.components();
+        assert_eq!((c[0].count, c[0].bounds), (4, ((0, 0), (2, 2))));
+        assert_eq!((c[1].count, c[1].bounds), (9, ((4, 2), (3, 3))));
+        assert_eq!(c[0].centroid, (0.5, 0.5));
+        assert_eq!(c[1].centroid, (5., 3.));
+        assert_eq!(
+            labels.bounding_boxes(),
+            [((4, 2), (3, 3)), ((0, 0), (2, 2))]
+        );
+        assert_eq!(labels.get((1, 1)), Some(1));
+        assert_eq!(labels.get((6, 4)), Some(2));
+        assert_eq!(labels.get((2, 0)), Some(0));
+        assert_eq!(labels.get((8, 0)), None);
+    }
+
+    #[test]
+    fn diagonals_only_join_with_eight() {
+        let img = mask(&[
+            "#..", //
+            ".#.", "..#",
+        ]);
+        assert_eq!(img.connected_components(set, Connectivity::Four).len(), 3);
+        let eight = img.connected_components(set, Connectivity::Eight);
+        assert_eq!(eight.len(), 1);
+        assert_eq!(eight.components()[0].bounds, ((0, 0), (3, 3)));
+        // And the other diagonal, which needs the up-right neighbor
+        let img = mask(&[
+            "..#", //
+            ".#.", "#..",
+        ]);
+        assert_eq!(img.connected_components(set, Connectivity::Four).len(), 3);
+        assert_eq!(img.connected_components(set, Connectivity::Eight).len(), 1);
+    }
+
+    #[test]
+    fn merges_provisional_labels() {
+        // Both arms get their own label on the first pass
+        let img = mask(&[
+            "#.#.#", //
+            "#.#.#", "#####",
+        ]);
+        let labels = img.connected_components(set, Connectivity::Four);
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels.components()[0].count, 11);
+        assert!(labels.labels().iter().all(|&l| l <= 1));
+    }
+
+    #[test]
+    fn nothing_set() {
+        let img = mask(&["###", "###"]);
+        let labels = img.connected_components(|_| false, Connectivity::Eight);
+        assert!(labels.is_empty());
+        assert!(labels.bounding_boxes().is_empty());
+        assert!(labels.labels().iter().all(|&l| l == 0));
+    }
+}
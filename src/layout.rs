@@ -0,0 +1,1041 @@
+//! Byte layouts of pixel data
+//!
+//! Everything that reads or writes pixel bytes checks buffers with
+//! [`validate_buffer`] or [`validate_exact`], so size errors are always
+//! [`ImageError::BufferSize`], with the numbers.
+use crate::{transforms::*, AlphaMode, ImageError, ResXY, Transfer, WorkPixel, F32};
+
+/// Byte layouts for importing and exporting pixel data
+///
+/// Multi-byte channels are little endian unless noted otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 8 bits per channel, RGBA byte order
+    Rgba8888,
+
+    /// 8 bits per channel, BGRA byte order
+    Bgra8888,
+
+    /// 8 bits per channel, ARGB byte order
+    Argb8888,
+
+    /// 8 bits per channel, RGB byte order, no alpha
+    Rgb888,
+
+    /// 8 bits per channel, BGR byte order, no alpha
+    Bgr888,
+
+    /// 16 bits per channel, RGBA order
+    Rgba16,
+
+    /// 8 bit luma, no alpha
+    Gray8,
+
+    /// 8 bit luma, then 8 bit alpha
+    GrayAlpha8,
+
+    /// 16 bit luma, then 16 bit alpha, both little endian
+    GrayAlpha16Le,
+
+    /// 16 bit luma, then 16 bit alpha, both big endian
+    GrayAlpha16Be,
+
+    /// 5 bits red, 6 bits green, 5 bits blue, little endian `u16`
+    Rgb565Le,
+
+    /// 5 bits red, 6 bits green, 5 bits blue, big endian `u16`
+    Rgb565Be,
+
+    /// 8 bit alpha only
+    ///
+    /// Color is black on import and ignored on export.
+    A8,
+}
+
+impl PixelFormat {
+    /// Size of one pixel in bytes
+    pub const fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgba16 => 8,
+            PixelFormat::Rgba8888
+            | PixelFormat::Bgra8888
+            | PixelFormat::Argb8888
+            | PixelFormat::GrayAlpha16Le
+            | PixelFormat::GrayAlpha16Be => 4,
+            PixelFormat::Rgb888 | PixelFormat::Bgr888 => 3,
+            PixelFormat::GrayAlpha8 | PixelFormat::Rgb565Le | PixelFormat::Rgb565Be => 2,
+            PixelFormat::Gray8 | PixelFormat::A8 => 1,
+        }
+    }
+
+    /// Size of one pixel in bits
+    ///
+    /// Every format is a whole number of bytes so far. Packed ones, like 1
+    /// and 4 bit gray, aren't supported yet, for those see
+    /// [`Image::to_eink_update`](crate::Image::to_eink_update).
+    pub const fn bits_per_pixel(self) -> usize {
+        self.bytes_per_pixel() * 8
+    }
+
+    /// Size of one tightly packed row of `width` pixels in bytes, if it
+    /// doesn't overflow
+    pub const fn row_bytes(self, width: u32) -> Option<usize> {
+        (width as usize).checked_mul(self.bytes_per_pixel())
+    }
+
+    /// Whether this format stores luma rather than RGB
+    pub const fn is_gray(self) -> bool {
+        matches!(
+            self,
+            PixelFormat::Gray8
+                | PixelFormat::GrayAlpha8
+                | PixelFormat::GrayAlpha16Le
+                | PixelFormat::GrayAlpha16Be
+        )
+    }
+
+    /// Decode one pixel from `b`, which must be
+    /// [`PixelFormat::bytes_per_pixel`] long
+    pub(crate) fn decode(self, b: &[u8]) -> WorkPixel {
+        let n = |c: u8| c as f32 / 255.;
+        let rgb565 = |v: u16| {
+            [
+                (v >> 11) as f32 / 31.,
+                ((v >> 5) & 0x3F) as f32 / 63.,
+                (v & 0x1F) as f32 / 31.,
+                1.,
+            ]
+        };
+        match self {
+            PixelFormat::Rgba8888 => [n(b[0]), n(b[1]), n(b[2]), n(b[3])],
+            PixelFormat::Bgra8888 => [n(b[2]), n(b[1]), n(b[0]), n(b[3])],
+            PixelFormat::Argb8888 => [n(b[1]), n(b[2]), n(b[3]), n(b[0])],
+            PixelFormat::Rgb888 => [n(b[0]), n(b[1]), n(b[2]), 1.],
+            PixelFormat::Bgr888 => [n(b[2]), n(b[1]), n(b[0]), 1.],
+            PixelFormat::Rgba16 => {
+                let c = |i: usize| u16::from_le_bytes([b[i * 2], b[i * 2 + 1]]) as f32 / 65535.;
+                [c(0), c(1), c(2), c(3)]
+            }
+            PixelFormat::Gray8 => [n(b[0]), n(b[0]), n(b[0]), 1.],
+            PixelFormat::GrayAlpha8 => [n(b[0]), n(b[0]), n(b[0]), n(b[1])],
+            PixelFormat::GrayAlpha16Le | PixelFormat::GrayAlpha16Be => {
+                let c = |i: usize| {
+                    let v = [b[i * 2], b[i * 2 + 1]];
+                    let v = match self {
+                        PixelFormat::GrayAlpha16Le => u16::from_le_bytes(v),
+                        _ => u16::from_be_bytes(v),
+                    };
+                    v as f32 / 65535.
+                };
+                [c(0), c(0), c(0), c(1)]
+            }
+            PixelFormat::Rgb565Le => rgb565(u16::from_le_bytes([b[0], b[1]])),
+            PixelFormat::Rgb565Be => rgb565(u16::from_be_bytes([b[0], b[1]])),
+            PixelFormat::A8 => [0., 0., 0., n(b[0])],
+        }
+    }
+
+    /// Encode `p` into `out`, which must be [`PixelFormat::bytes_per_pixel`]
+    /// long
+    ///
+    /// Gray formats use the red channel, see [`prepare`].
+    /// Values are clamped to `0..=1` and rounded, see [`quantize`].
+    pub(crate) fn encode(self, p: WorkPixel, out: &mut [u8]) {
+        let q = quantize;
+        let [r, g, b, a] = p.map(|c| q(c, 255.) as u8);
+        let rgb565 = || {
+            let r = q(p[0], 31.) as u16;
+            let g = q(p[1], 63.) as u16;
+            let b = q(p[2], 31.) as u16;
+            (r << 11) | (g << 5) | b
+        };
+        match self {
+            PixelFormat::Rgba8888 => out.copy_from_slice(&[r, g, b, a]),
+            PixelFormat::Bgra8888 => out.copy_from_slice(&[b, g, r, a]),
+            PixelFormat::Argb8888 => out.copy_from_slice(&[a, r, g, b]),
+            PixelFormat::Rgb888 => out.copy_from_slice(&[r, g, b]),
+            PixelFormat::Bgr888 => out.copy_from_slice(&[b, g, r]),
+            PixelFormat::Rgba16 => {
+                for (o, c) in out.chunks_exact_mut(2).zip(p) {
+                    o.copy_from_slice(&(q(c, 65535.) as u16).to_le_bytes());
+                }
+            }
+            PixelFormat::Gray8 => out[0] = r,
+            PixelFormat::GrayAlpha8 => out.copy_from_slice(&[r, a]),
+            PixelFormat::GrayAlpha16Le | PixelFormat::GrayAlpha16Be => {
+                for (o, c) in out.chunks_exact_mut(2).zip([p[0], p[3]]) {
+                    let v = q(c, 65535.) as u16;
+                    o.copy_from_slice(&match self {
+                        PixelFormat::GrayAlpha16Le => v.to_le_bytes(),
+                        _ => v.to_be_bytes(),
+                    });
+                }
+            }
+            PixelFormat::Rgb565Le => out.copy_from_slice(&rgb565().to_le_bytes()),
+            PixelFormat::Rgb565Be => out.copy_from_slice(&rgb565().to_be_bytes()),
+            PixelFormat::A8 => out[0] = a,
+        }
+    }
+}
+
+/// Byte order of multi-byte channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+/// The order rows are in a buffer
+///
+/// Images are always stored top down, this is only for importing and
+/// exporting, like BMP pixel arrays or OpenGL readbacks that start at the
+/// bottom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RowOrder {
+    /// The first row in the buffer is the top of the image
+    #[default]
+    TopDown,
+
+    /// The first row in the buffer is the bottom of the image
+    BottomUp,
+}
+
+impl RowOrder {
+    /// Index in the buffer of image row `y`, out of `height`
+    pub fn buffer_row(self, y: u32, height: u32) -> u32 {
+        match self {
+            RowOrder::TopDown => y,
+            RowOrder::BottomUp => height - 1 - y,
+        }
+    }
+}
+
+/// What to do with channels outside `0..=1` when exporting to integers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncodePolicy {
+    /// Clamp each channel, NaN becomes 0
+    #[default]
+    Clamp,
+
+    /// Refuse to export, with [`ImageError::OutOfRange`] for the first
+    /// channel in row order that's outside `0..=1` or NaN
+    Error,
+
+    /// Rescale the color channels of the whole image so its smallest value
+    /// becomes 0 and its largest 1, then clamp
+    ///
+    /// Alpha is only clamped. Non finite values don't count towards the
+    /// range, and a single valued image is only clamped.
+    NormalizeMinMax,
+}
+
+/// Bytes per row of `width` [`PixelFormat::Argb8888`] pixels, rounded up to
+/// a multiple of `align`
+///
+/// An `align` of 0 or 1 is the tightly packed row.
+///
+/// # Panics
+///
+/// - If the stride overflows
+pub const fn argb_stride(width: u32, align: usize) -> usize {
+    let row = width as usize * 4;
+    if align <= 1 {
+        return row;
+    }
+    match row.div_ceil(align).checked_mul(align) {
+        Some(stride) => stride,
+        None => panic!("Stride overflowed"),
+    }
+}
+
+/// Smallest buffer that holds `res` pixels of `format`, with rows `stride`
+/// bytes apart
+///
+/// The last row doesn't need its padding. Zero sized images need nothing.
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidArgument`] if `stride` is smaller than a row, or
+///   the size overflows `usize`
+pub fn required_size(format: PixelFormat, res: ResXY, stride: usize) -> Result<usize, ImageError> {
+    let row = format.row_bytes(res.0).ok_or(ImageError::InvalidArgument)?;
+    if stride < row {
+        return Err(ImageError::InvalidArgument);
+    }
+    if res.1 == 0 {
+        return Ok(0);
+    }
+    stride
+        .checked_mul(res.1 as usize - 1)
+        .and_then(|s| s.checked_add(row))
+        .ok_or(ImageError::InvalidArgument)
+}
+
+/// Check `buf` is big enough for `res` pixels of `format`, with rows
+/// `stride` bytes apart, see [`required_size`]
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidArgument`] if `stride` is smaller than a row, or
+///   the size overflows `usize`
+/// - [`ImageError::BufferSize`] if `buf` is too small
+pub fn validate_buffer(
+    buf: &[u8],
+    format: PixelFormat,
+    res: ResXY,
+    stride: usize,
+) -> Result<(), ImageError> {
+    let expected = required_size(format, res, stride)?;
+    if buf.len() < expected {
+        return Err(ImageError::BufferSize {
+            expected,
+            actual: buf.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Check `buf` is exactly `res` tightly packed pixels of `format`
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidArgument`] if the size overflows `usize`
+/// - [`ImageError::BufferSize`] if `buf` is the wrong size
+pub fn validate_exact(buf: &[u8], format: PixelFormat, res: ResXY) -> Result<(), ImageError> {
+    let row = format.row_bytes(res.0).ok_or(ImageError::InvalidArgument)?;
+    let expected = required_size(format, res, row)?;
+    if buf.len() != expected {
+        return Err(ImageError::BufferSize {
+            expected,
+            actual: buf.len(),
+        });
+    }
+    Ok(())
+}
+
+/// `c` clamped to `0..=1`, scaled to `0..=max`, and rounded
+///
+/// NaN becomes 0 here, explicitly, rather than through whatever `as` does.
+pub(crate) fn quantize(c: f32, max: f32) -> f32 {
+    if c.is_nan() {
+        0.
+    } else {
+        (c.clamp(0., 1.) * max).round()
+    }
+}
+
+/// Prepare `p` for [`PixelFormat::encode`], un-premultiplying it and for gray
+/// formats computing its luma in linear light.
+///
+/// `transfer` is the decode and encode transfer functions of the image.
+pub(crate) fn prepare(
+    p: WorkPixel,
+    format: PixelFormat,
+    alpha: AlphaMode,
+    transfer: Option<(Transfer, Transfer)>,
+) -> WorkPixel {
+    let p = match alpha {
+        AlphaMode::Straight => p,
+        AlphaMode::Premultiplied => unpremultiply(p),
+    };
+    if !format.is_gray() {
+        return p;
+    }
+    let mut rgb = [p[0], p[1], p[2]];
+    if let Some((decode, _)) = transfer {
+        rgb = rgb.map(decode);
+    }
+    let mut y = luminance(rgb);
+    if let Some((_, encode)) = transfer {
+        y = encode(y);
+    }
+    [y, y, y, p[3]]
+}
+
+/// Channels of a [`convert_format`] done in integers, straight RGBA, either
+/// 8 or 16 bits each
+#[derive(Clone, Copy)]
+struct Channels([u16; 4]);
+
+/// How [`convert_format`] converts between two formats
+#[derive(Clone, Copy)]
+enum Path {
+    /// Same bytes, rearranged, each output byte is the input byte at its
+    /// index
+    Shuffle(&'static [usize]),
+    /// Through [`Channels`], wide if they're 16 bits
+    Integer { wide: bool },
+    /// Through [`WorkPixel`], for everything else
+    Float,
+}
+
+impl Path {
+    fn new(src: PixelFormat, dst: PixelFormat) -> Self {
+        use PixelFormat::*;
+        let shuffle: Option<&'static [usize]> = match (src, dst) {
+            (Rgba8888, Bgra8888) | (Bgra8888, Rgba8888) => Some(&[2, 1, 0, 3]),
+            (Rgba8888, Argb8888) => Some(&[3, 0, 1, 2]),
+            (Argb8888, Rgba8888) => Some(&[1, 2, 3, 0]),
+            (Bgra8888, Argb8888) | (Argb8888, Bgra8888) => Some(&[3, 2, 1, 0]),
+            (Rgb888, Bgr888) | (Bgr888, Rgb888) => Some(&[2, 1, 0]),
+            (Rgb565Le, Rgb565Be) | (Rgb565Be, Rgb565Le) => Some(&[1, 0]),
+            (GrayAlpha16Le, GrayAlpha16Be) | (GrayAlpha16Be, GrayAlpha16Le) => Some(&[1, 0, 3, 2]),
+            _ => None,
+        };
+        if let Some(shuffle) = shuffle {
+            return Path::Shuffle(shuffle);
+        }
+        let wide = |f| matches!(f, Rgba16 | GrayAlpha16Le | GrayAlpha16Be);
+        let rgb565 = |f| matches!(f, Rgb565Le | Rgb565Be);
+        let wide = wide(src) || wide(dst);
+        // Luma needs the float path, unless every channel is already the same
+        let luma = dst.is_gray() && !(src.is_gray() || src == A8);
+        if luma || (wide && (rgb565(src) || rgb565(dst))) {
+            Path::Float
+        } else {
+            Path::Integer { wide }
+        }
+    }
+}
+
+impl PixelFormat {
+    /// Like [`PixelFormat::decode`], in integers, scaled to 16 bits if
+    /// `wide`, and from 5 and 6 bits by rounding
+    fn read(self, b: &[u8], wide: bool) -> Channels {
+        let n = |c: u8| if wide { c as u16 * 257 } else { c as u16 };
+        let le = |i: usize| u16::from_le_bytes([b[i * 2], b[i * 2 + 1]]);
+        let be = |i: usize| u16::from_be_bytes([b[i * 2], b[i * 2 + 1]]);
+        let rgb565 = |v: u16| {
+            let (r, g, b) = (
+                (v >> 11) as u32,
+                ((v >> 5) & 0x3F) as u32,
+                (v & 0x1F) as u32,
+            );
+            let up = |c: u32, max: u32| ((c * 255 + max / 2) / max) as u16;
+            [up(r, 31), up(g, 63), up(b, 31), 255]
+        };
+        Channels(match self {
+            PixelFormat::Rgba8888 => [n(b[0]), n(b[1]), n(b[2]), n(b[3])],
+            PixelFormat::Bgra8888 => [n(b[2]), n(b[1]), n(b[0]), n(b[3])],
+            PixelFormat::Argb8888 => [n(b[1]), n(b[2]), n(b[3]), n(b[0])],
+            PixelFormat::Rgb888 => [n(b[0]), n(b[1]), n(b[2]), n(255)],
+            PixelFormat::Bgr888 => [n(b[2]), n(b[1]), n(b[0]), n(255)],
+            PixelFormat::Rgba16 => [le(0), le(1), le(2), le(3)],
+            PixelFormat::Gray8 => [n(b[0]), n(b[0]), n(b[0]), n(255)],
+            PixelFormat::GrayAlpha8 => [n(b[0]), n(b[0]), n(b[0]), n(b[1])],
+            PixelFormat::GrayAlpha16Le => [le(0), le(0), le(0), le(1)],
+            PixelFormat::GrayAlpha16Be => [be(0), be(0), be(0), be(1)],
+            PixelFormat::Rgb565Le => rgb565(le(0)),
+            PixelFormat::Rgb565Be => rgb565(be(0)),
+            PixelFormat::A8 => [0, 0, 0, n(b[0])],
+        })
+    }
+
+    /// Like [`PixelFormat::encode`], from [`PixelFormat::read`], rounding
+    /// to fewer bits
+    fn write(self, Channels(c): Channels, wide: bool, out: &mut [u8]) {
+        let n = |v: u16| {
+            if wide {
+                ((v as u32 + 128) / 257) as u8
+            } else {
+                v as u8
+            }
+        };
+        let [r, g, b, a] = c.map(n);
+        let rgb565 = || {
+            let down = |c: u8, max: u32| (c as u32 * max + 127) / 255;
+            ((down(r, 31) << 11) | (down(g, 63) << 5) | down(b, 31)) as u16
+        };
+        let pair = |v: [u16; 2], be: bool| {
+            let v = v.map(|v| if be { v.to_be_bytes() } else { v.to_le_bytes() });
+            [v[0][0], v[0][1], v[1][0], v[1][1]]
+        };
+        match self {
+            PixelFormat::Rgba8888 => out.copy_from_slice(&[r, g, b, a]),
+            PixelFormat::Bgra8888 => out.copy_from_slice(&[b, g, r, a]),
+            PixelFormat::Argb8888 => out.copy_from_slice(&[a, r, g, b]),
+            PixelFormat::Rgb888 => out.copy_from_slice(&[r, g, b]),
+            PixelFormat::Bgr888 => out.copy_from_slice(&[b, g, r]),
+            PixelFormat::Rgba16 => {
+                for (o, c) in out.chunks_exact_mut(2).zip(c) {
+                    o.copy_from_slice(&c.to_le_bytes());
+                }
+            }
+            PixelFormat::Gray8 => out[0] = r,
+            PixelFormat::GrayAlpha8 => out.copy_from_slice(&[r, a]),
+            PixelFormat::GrayAlpha16Le => out.copy_from_slice(&pair([c[0], c[3]], false)),
+            PixelFormat::GrayAlpha16Be => out.copy_from_slice(&pair([c[0], c[3]], true)),
+            PixelFormat::Rgb565Le => out.copy_from_slice(&rgb565().to_le_bytes()),
+            PixelFormat::Rgb565Be => out.copy_from_slice(&rgb565().to_be_bytes()),
+            PixelFormat::A8 => out[0] = a,
+        }
+    }
+}
+
+/// Convert one pixel, `src` to `dst`, along `path`
+#[inline]
+fn convert_pixel(path: Path, (src, s): (PixelFormat, &[u8]), (dst, d): (PixelFormat, &mut [u8])) {
+    match path {
+        Path::Shuffle(shuffle) => {
+            for (o, i) in d.iter_mut().zip(shuffle) {
+                *o = s[*i];
+            }
+        }
+        Path::Integer { wide } => dst.write(src.read(s, wide), wide, d),
+        Path::Float => dst.encode(prepare(src.decode(s), dst, AlphaMode::Straight, None), d),
+    }
+}
+
+/// Bytes of `pixels` pixels of `format`, checking `buf` has them
+fn check_pixels(buf: &[u8], format: PixelFormat, pixels: usize) -> Result<usize, ImageError> {
+    let expected = pixels
+        .checked_mul(format.bytes_per_pixel())
+        .ok_or(ImageError::InvalidArgument)?;
+    if buf.len() < expected {
+        return Err(ImageError::BufferSize {
+            expected,
+            actual: buf.len(),
+        });
+    }
+    Ok(expected)
+}
+
+/// Convert `pixels` tightly packed pixels from `src`, in `src_fmt`, to
+/// `dst`, in `dst_fmt`, without an [`Image`][crate::Image]
+///
+/// The values are only moved between formats, with no color space, so
+/// the result is the same as importing and exporting a
+/// [`ColorSpace::AsIs`][crate::ColorSpace::AsIs] image, and alpha is
+/// straight. Reordering channels only moves bytes, and the rest is done in
+/// integers, rounding to nearest when dropping bits, like `c * 31 / 255`
+/// for 5 bit channels, and replicating bits when adding them, like
+/// `c * 257` for 16 bit channels. Gray formats take the luma of RGB, which
+/// is done in floating point, as is anything between 16 bit and
+/// [`PixelFormat::Rgb565Le`] or [`PixelFormat::Rgb565Be`].
+///
+/// Bytes past `pixels` in either buffer are left alone.
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidArgument`] if either size overflows `usize`
+/// - [`ImageError::BufferSize`] if either buffer is too small
+pub fn convert_format(
+    src: &[u8],
+    src_fmt: PixelFormat,
+    dst: &mut [u8],
+    dst_fmt: PixelFormat,
+    pixels: usize,
+) -> Result<(), ImageError> {
+    let src_len = check_pixels(src, src_fmt, pixels)?;
+    let dst_len = check_pixels(dst, dst_fmt, pixels)?;
+    let (src, dst) = (&src[..src_len], &mut dst[..dst_len]);
+    if src_fmt == dst_fmt {
+        dst.copy_from_slice(src);
+        return Ok(());
+    }
+    let path = Path::new(src_fmt, dst_fmt);
+    let (sbpp, dbpp) = (src_fmt.bytes_per_pixel(), dst_fmt.bytes_per_pixel());
+    for (s, d) in src.chunks_exact(sbpp).zip(dst.chunks_exact_mut(dbpp)) {
+        convert_pixel(path, (src_fmt, s), (dst_fmt, d));
+    }
+    Ok(())
+}
+
+/// [`convert_format`] in place, for formats the same size, like swapping
+/// [`PixelFormat::Bgra8888`] to [`PixelFormat::Rgba8888`]
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidArgument`] if the formats aren't the same size,
+///   or the size overflows `usize`
+/// - [`ImageError::BufferSize`] if `buf` is too small
+pub fn convert_format_in_place(
+    buf: &mut [u8],
+    src_fmt: PixelFormat,
+    dst_fmt: PixelFormat,
+    pixels: usize,
+) -> Result<(), ImageError> {
+    let bpp = src_fmt.bytes_per_pixel();
+    if bpp != dst_fmt.bytes_per_pixel() {
+        return Err(ImageError::InvalidArgument);
+    }
+    let len = check_pixels(buf, src_fmt, pixels)?;
+    if src_fmt == dst_fmt {
+        return Ok(());
+    }
+    let path = Path::new(src_fmt, dst_fmt);
+    let mut s = [0; 8];
+    for d in buf[..len].chunks_exact_mut(bpp) {
+        s[..bpp].copy_from_slice(d);
+        convert_pixel(path, (src_fmt, &s[..bpp]), (dst_fmt, d));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{vec, vec::Vec};
+
+    use super::*;
+    use crate::{ColorSpace, Image};
+
+    const FORMATS: [PixelFormat; 13] = [
+        PixelFormat::Rgba8888,
+        PixelFormat::Bgra8888,
+        PixelFormat::Argb8888,
+        PixelFormat::Rgb888,
+        PixelFormat::Bgr888,
+        PixelFormat::Rgba16,
+        PixelFormat::Gray8,
+        PixelFormat::GrayAlpha8,
+        PixelFormat::GrayAlpha16Le,
+        PixelFormat::GrayAlpha16Be,
+        PixelFormat::Rgb565Le,
+        PixelFormat::Rgb565Be,
+        PixelFormat::A8,
+    ];
+
+    /// Every byte counting up, wrapping, for `pixels` of `format`
+    fn bytes(format: PixelFormat, pixels: usize) -> Vec<u8> {
+        (0..pixels * format.bytes_per_pixel())
+            .map(|i| (i * 37 + 11) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn channel_placement() {
+        let n = |c: u8| c as f32 / 255.;
+        let (r, g, b, a) = (10, 20, 30, 40);
+        let wide = |c: u8| (c as u16 * 257).to_le_bytes();
+        let wide_be = |c: u8| (c as u16 * 257).to_be_bytes();
+        let cases: [(PixelFormat, Vec<u8>, WorkPixel); 11] = [
+            (
+                PixelFormat::Rgba8888,
+                [r, g, b, a].into(),
+                [n(r), n(g), n(b), n(a)],
+            ),
+            (
+                PixelFormat::Bgra8888,
+                [b, g, r, a].into(),
+                [n(r), n(g), n(b), n(a)],
+            ),
+            (
+                PixelFormat::Argb8888,
+                [a, r, g, b].into(),
+                [n(r), n(g), n(b), n(a)],
+            ),
+            (
+                PixelFormat::Rgb888,
+                [r, g, b].into(),
+                [n(r), n(g), n(b), 1.],
+            ),
+            (
+                PixelFormat::Bgr888,
+                [b, g, r].into(),
+                [n(r), n(g), n(b), 1.],
+            ),
+            (
+                PixelFormat::Rgba16,
+                [wide(r), wide(g), wide(b), wide(a)].concat(),
+                [n(r), n(g), n(b), n(a)],
+            ),
+            (PixelFormat::Gray8, [g].into(), [n(g), n(g), n(g), 1.]),
+            (
+                PixelFormat::GrayAlpha8,
+                [g, a].into(),
+                [n(g), n(g), n(g), n(a)],
+            ),
+            (
+                PixelFormat::GrayAlpha16Le,
+                [wide(g), wide(a)].concat(),
+                [n(g), n(g), n(g), n(a)],
+            ),
+            (
+                PixelFormat::GrayAlpha16Be,
+                [wide_be(g), wide_be(a)].concat(),
+                [n(g), n(g), n(g), n(a)],
+            ),
+            (PixelFormat::A8, [a].into(), [0., 0., 0., n(a)]),
+        ];
+        for (format, data, want) in cases {
+            let img = Image::from_raw(&data, (1, 1), format, ColorSpace::AsIs).unwrap();
+            let got = img.pixels()[0];
+            for c in 0..4 {
+                assert!((got[c] - want[c]).abs() < 1e-6, "{format:?}: {got:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn rgb565_scaling() {
+        for (value, want) in [
+            (0xFFFFu16, [1., 1., 1., 1.]),
+            (0xF800, [1., 0., 0., 1.]),
+            (0x07E0, [0., 1., 0., 1.]),
+            (0x001F, [0., 0., 1., 1.]),
+            (0x0000, [0., 0., 0., 1.]),
+        ] {
+            for (format, data) in [
+                (PixelFormat::Rgb565Le, value.to_le_bytes()),
+                (PixelFormat::Rgb565Be, value.to_be_bytes()),
+            ] {
+                let img = Image::from_raw(&data, (1, 1), format, ColorSpace::AsIs).unwrap();
+                // Exactly, not just close
+                assert_eq!(img.pixels()[0], want, "{format:?} {value:#06x}");
+                assert_eq!(img.to_raw(format), data);
+            }
+        }
+        // Every 5 and 6 bit level is distinct and lands back on itself
+        for v in 0..=u16::MAX {
+            let data = v.to_le_bytes();
+            let img =
+                Image::from_raw(&data, (1, 1), PixelFormat::Rgb565Le, ColorSpace::AsIs).unwrap();
+            assert_eq!(img.to_raw(PixelFormat::Rgb565Le), data);
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        for format in FORMATS {
+            let data = bytes(format, 5 * 3);
+            let img = Image::from_raw(&data, (5, 3), format, ColorSpace::AsIs).unwrap();
+            assert_eq!(img.to_raw(format), data, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn length_checks() {
+        for format in FORMATS {
+            let bpp = format.bytes_per_pixel();
+            let data = bytes(format, 4 * 3 + 1);
+            let exact = &data[..4 * 3 * bpp];
+            assert!(Image::from_raw(exact, (4, 3), format, ColorSpace::sRGB).is_ok());
+            for len in [exact.len() - 1, exact.len() + 1, 0] {
+                assert_eq!(
+                    Image::from_raw(&data[..len], (4, 3), format, ColorSpace::sRGB).err(),
+                    Some(ImageError::BufferSize {
+                        expected: exact.len(),
+                        actual: len
+                    }),
+                    "{format:?}"
+                );
+            }
+        }
+        assert_eq!(
+            validate_exact(&[], PixelFormat::Rgba16, (u32::MAX, u32::MAX)),
+            Err(ImageError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn sizes() {
+        for format in FORMATS {
+            let bpp = format.bytes_per_pixel();
+            assert_eq!(format.bits_per_pixel(), bpp * 8);
+            assert_eq!(format.row_bytes(7), Some(7 * bpp));
+            assert_eq!(format.row_bytes(0), Some(0));
+            // Tight, and padded where the last row needs no padding
+            assert_eq!(required_size(format, (7, 3), 7 * bpp), Ok(21 * bpp));
+            assert_eq!(
+                required_size(format, (7, 3), 7 * bpp + 5),
+                Ok(21 * bpp + 10)
+            );
+            assert_eq!(required_size(format, (7, 0), 7 * bpp), Ok(0));
+            assert_eq!(required_size(format, (0, 3), 0), Ok(0));
+            assert_eq!(
+                required_size(format, (7, 3), 7 * bpp - 1),
+                Err(ImageError::InvalidArgument),
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn size_overflow() {
+        for format in FORMATS {
+            assert_eq!(
+                required_size(format, (1, 3), usize::MAX),
+                Err(ImageError::InvalidArgument)
+            );
+            assert_eq!(
+                required_size(format, (1, u32::MAX), usize::MAX / 2),
+                Err(ImageError::InvalidArgument)
+            );
+            // One huge row is fine without more rows
+            assert_eq!(
+                required_size(format, (1, 1), usize::MAX),
+                Ok(format.bytes_per_pixel())
+            );
+        }
+    }
+
+    #[test]
+    fn buffer_checks() {
+        for format in FORMATS {
+            let bpp = format.bytes_per_pixel();
+            let stride = 5 * bpp + 3;
+            let need = stride * 3 + 5 * bpp;
+            let buf = bytes(format, 64);
+            assert_eq!(
+                validate_buffer(&buf[..need], format, (5, 4), stride),
+                Ok(())
+            );
+            assert_eq!(validate_buffer(&buf, format, (5, 4), stride), Ok(()));
+            assert_eq!(
+                validate_buffer(&buf[..need - 1], format, (5, 4), stride),
+                Err(ImageError::BufferSize {
+                    expected: need,
+                    actual: need - 1
+                })
+            );
+            assert_eq!(
+                validate_buffer(&buf, format, (5, 4), 5 * bpp - 1),
+                Err(ImageError::InvalidArgument)
+            );
+            assert_eq!(validate_exact(&buf[..20 * bpp], format, (5, 4)), Ok(()));
+            assert_eq!(
+                validate_exact(&buf[..20 * bpp + 1], format, (5, 4)),
+                Err(ImageError::BufferSize {
+                    expected: 20 * bpp,
+                    actual: 20 * bpp + 1
+                })
+            );
+        }
+    }
+
+    /// `pixels` of `src` converted to `dst_fmt` through an AsIs [`Image`]
+    fn through_image(src: &[u8], src_fmt: PixelFormat, dst_fmt: PixelFormat) -> Vec<u8> {
+        let pixels = src.len() / src_fmt.bytes_per_pixel();
+        Image::from_raw(src, (pixels as u32, 1), src_fmt, ColorSpace::AsIs)
+            .unwrap()
+            .to_raw(dst_fmt)
+    }
+
+    #[test]
+    fn convert_matches_the_image_path() {
+        for src_fmt in FORMATS {
+            let src = bytes(src_fmt, 64);
+            for dst_fmt in FORMATS {
+                let mut dst = vec![0; 64 * dst_fmt.bytes_per_pixel()];
+                convert_format(&src, src_fmt, &mut dst, dst_fmt, 64).unwrap();
+                let want = through_image(&src, src_fmt, dst_fmt);
+                assert_eq!(dst, want, "{src_fmt:?} to {dst_fmt:?}");
+                if src_fmt.bytes_per_pixel() == dst_fmt.bytes_per_pixel() {
+                    let mut buf = src.clone();
+                    convert_format_in_place(&mut buf, src_fmt, dst_fmt, 64).unwrap();
+                    assert_eq!(buf, want, "{src_fmt:?} to {dst_fmt:?} in place");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rgb888_to_rgb565_rounds() {
+        let src: Vec<u8> = (0..=255u8).flat_map(|c| [c, c, 255 - c]).collect();
+        for dst_fmt in [PixelFormat::Rgb565Le, PixelFormat::Rgb565Be] {
+            let mut dst = vec![0; 256 * 2];
+            convert_format(&src, PixelFormat::Rgb888, &mut dst, dst_fmt, 256).unwrap();
+            assert_eq!(dst, through_image(&src, PixelFormat::Rgb888, dst_fmt));
+        }
+        // Hand rounded, 8 is 0.98 of a 5 bit step and 4 is 1.98 of a 6 bit one
+        let mut dst = [0; 2];
+        convert_format(
+            &[8, 4, 4],
+            PixelFormat::Rgb888,
+            &mut dst,
+            PixelFormat::Rgb565Be,
+            1,
+        )
+        .unwrap();
+        assert_eq!(u16::from_be_bytes(dst), 1 << 11 | 1 << 5);
+        convert_format(
+            &[3, 1, 3],
+            PixelFormat::Rgb888,
+            &mut dst,
+            PixelFormat::Rgb565Be,
+            1,
+        )
+        .unwrap();
+        assert_eq!(dst, [0, 0]);
+        // And every RGB565 value survives a trip through RGB888
+        let all: Vec<u8> = (0..=u16::MAX).flat_map(u16::to_le_bytes).collect();
+        let mut rgb = vec![0; all.len() / 2 * 3];
+        let mut back = vec![0; all.len()];
+        convert_format(
+            &all,
+            PixelFormat::Rgb565Le,
+            &mut rgb,
+            PixelFormat::Rgb888,
+            65536,
+        )
+        .unwrap();
+        convert_format(
+            &rgb,
+            PixelFormat::Rgb888,
+            &mut back,
+            PixelFormat::Rgb565Le,
+            65536,
+        )
+        .unwrap();
+        assert_eq!(back, all);
+    }
+
+    #[test]
+    fn swizzles_round_trip() {
+        let src = bytes(PixelFormat::Rgba8888, 16);
+        let mut bgra = vec![0; src.len()];
+        convert_format(
+            &src,
+            PixelFormat::Rgba8888,
+            &mut bgra,
+            PixelFormat::Bgra8888,
+            16,
+        )
+        .unwrap();
+        assert_eq!(bgra[..4], [src[2], src[1], src[0], src[3]]);
+        let mut back = bgra.clone();
+        convert_format_in_place(&mut back, PixelFormat::Bgra8888, PixelFormat::Rgba8888, 16)
+            .unwrap();
+        assert_eq!(back, src);
+        for (a, b) in [
+            (PixelFormat::Rgba8888, PixelFormat::Argb8888),
+            (PixelFormat::Bgra8888, PixelFormat::Argb8888),
+            (PixelFormat::Rgb888, PixelFormat::Bgr888),
+            (PixelFormat::Rgb565Le, PixelFormat::Rgb565Be),
+            (PixelFormat::GrayAlpha16Le, PixelFormat::GrayAlpha16Be),
+        ] {
+            let src = bytes(a, 16);
+            let mut buf = src.clone();
+            convert_format_in_place(&mut buf, a, b, 16).unwrap();
+            assert_ne!(buf, src);
+            convert_format_in_place(&mut buf, b, a, 16).unwrap();
+            assert_eq!(buf, src, "{a:?} {b:?}");
+        }
+    }
+
+    #[test]
+    fn gray8_replicates() {
+        let gray: Vec<u8> = (0..=255).collect();
+        let mut rgb = vec![0; 256 * 3];
+        convert_format(
+            &gray,
+            PixelFormat::Gray8,
+            &mut rgb,
+            PixelFormat::Rgb888,
+            256,
+        )
+        .unwrap();
+        assert!(rgb.chunks_exact(3).zip(&gray).all(|(p, g)| p == [*g; 3]));
+        let mut rgba = vec![0; 256 * 4];
+        convert_format(
+            &gray,
+            PixelFormat::Gray8,
+            &mut rgba,
+            PixelFormat::Bgra8888,
+            256,
+        )
+        .unwrap();
+        assert!(rgba
+            .chunks_exact(4)
+            .zip(&gray)
+            .all(|(p, g)| p == [*g, *g, *g, 255]));
+        let (mut from_gray, mut from_rgb) = (vec![0; 512], vec![0; 512]);
+        convert_format(
+            &gray,
+            PixelFormat::Gray8,
+            &mut from_gray,
+            PixelFormat::Rgb565Le,
+            256,
+        )
+        .unwrap();
+        convert_format(
+            &rgb,
+            PixelFormat::Rgb888,
+            &mut from_rgb,
+            PixelFormat::Rgb565Le,
+            256,
+        )
+        .unwrap();
+        assert_eq!(from_gray, from_rgb);
+        let mut wide = vec![0; 256 * 4];
+        convert_format(
+            &gray,
+            PixelFormat::Gray8,
+            &mut wide,
+            PixelFormat::GrayAlpha16Le,
+            256,
+        )
+        .unwrap();
+        for (p, g) in wide.chunks_exact(4).zip(&gray) {
+            assert_eq!(p[..2], (*g as u16 * 257).to_le_bytes());
+            assert_eq!(p[2..], [255, 255]);
+        }
+    }
+
+    #[test]
+    fn convert_checks_lengths() {
+        let src = bytes(PixelFormat::Rgb888, 4);
+        let mut dst = vec![0xaa; 4 * 2 + 3];
+        assert_eq!(
+            convert_format(
+                &src[..11],
+                PixelFormat::Rgb888,
+                &mut dst,
+                PixelFormat::Rgb565Le,
+                4
+            ),
+            Err(ImageError::BufferSize {
+                expected: 12,
+                actual: 11
+            })
+        );
+        assert_eq!(
+            convert_format(
+                &src,
+                PixelFormat::Rgb888,
+                &mut dst[..7],
+                PixelFormat::Rgb565Le,
+                4
+            ),
+            Err(ImageError::BufferSize {
+                expected: 8,
+                actual: 7
+            })
+        );
+        assert!(dst.iter().all(|b| *b == 0xaa));
+        // Only `pixels` are written
+        convert_format(
+            &src,
+            PixelFormat::Rgb888,
+            &mut dst,
+            PixelFormat::Rgb565Le,
+            4,
+        )
+        .unwrap();
+        assert_eq!(dst[8..], [0xaa; 3]);
+        assert_eq!(
+            convert_format(
+                &src,
+                PixelFormat::Rgb888,
+                &mut dst,
+                PixelFormat::Rgb565Le,
+                usize::MAX
+            ),
+            Err(ImageError::InvalidArgument)
+        );
+        let mut buf = bytes(PixelFormat::Rgba8888, 4);
+        assert_eq!(
+            convert_format_in_place(&mut buf, PixelFormat::Rgba8888, PixelFormat::Rgb888, 4),
+            Err(ImageError::InvalidArgument)
+        );
+        assert_eq!(
+            convert_format_in_place(&mut buf, PixelFormat::Rgba8888, PixelFormat::Bgra8888, 5),
+            Err(ImageError::BufferSize {
+                expected: 20,
+                actual: 16
+            })
+        );
+        assert_eq!(buf, bytes(PixelFormat::Rgba8888, 4));
+    }
+}
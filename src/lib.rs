@@ -1,4 +1,13 @@
 //! Embedded image handling library
+//!
+//! The `validate` feature checks image invariants, like every value being
+//! finite, after operations in debug builds. The `testing` feature adds
+//! the `testing` module, helpers for golden image tests. The `profiling`
+//! feature times the heavy operations, see `take_profile`.
+//!
+//! Both `profiling` and the plan cache of `plan-cache` need atomic
+//! compare and swap. Without it, like on `thumbv6m`, `plan-cache` does
+//! nothing and `profiling` doesn't build.
 #![no_std]
 #![allow(unused_imports, dead_code, clippy::wrong_self_convention)]
 extern crate alloc;
@@ -9,9 +18,177 @@ use core::slice::from_raw_parts;
 use na::{Matrix3x1, Matrix4x1};
 use nalgebra as na;
 
-use crate::transforms::*;
+pub use crate::{
+    accumulate::{AccumulateMode, Accumulator},
+    adjust::IlluminantEstimator,
+    ascii::AsciiCharset,
+    average::StatsMask,
+    blend::BlendSpace,
+    blit::{convert_blit, ImageMutRaw, ImageRefRaw},
+    border::BorderStyle,
+    convert::{ConversionPlan, Converter},
+    cvd::CvdKind,
+    dither::{dither_rows_blocked, DitherAlgorithm, ThresholdMap, ACEP_PALETTE},
+    eink::EinkPacking,
+    embed::{ImageRef, StaticImage},
+    font::{BitmapFont, TextAlign, TextLayout, TextMetrics},
+    framebuffer::FramebufferTarget,
+    fusion::FusionBlend,
+    gamut::GamutReport,
+    gradient::GradientSpace,
+    heatmap::{DiffMetric, DiffScale},
+    interlace::{InterlacedAssembler, PassInfo},
+    job::{ColorJob, JobStatus},
+    label::{Component, Connectivity, Labels},
+    layout::{EncodePolicy, Endian, PixelFormat, RowOrder},
+    luma::LumaImage,
+    metadata::{CaptureInfo, Metadata},
+    mipmap::AlphaHandling,
+    morph::MorphChannel,
+    ops::Ops,
+    outline::OutlineMode,
+    pipeline::Pipeline,
+    pixel::Pixel,
+    planar::Plane,
+    precise::{Image64, WorkPixel64},
+    previews::ImageWithPreviews,
+    response::{estimate_response_curve, ChannelLut},
+    rle::RleImage,
+    rotate::{Orientation, Rotation},
+    scale::{thumbnail_from_raw, ScaleFilter, ScaleJob, ScaledRows},
+    scratch::Scratch,
+    sensor::{BayerPattern, SensorPipeline},
+    similarity::SIMILARITY_THRESHOLD,
+    stamp::StampPlacement,
+    subsample::SubsampledImage,
+    template::TemplateChannels,
+    texture::{TextureData, TextureFormat},
+    tiled::{TileOrder, TilePadding},
+    tonemap::ToneMap,
+    transforms::{GamutMap, TransferFunction},
+    transition::Transition,
+    yuv::{YuvRange, YuvStandard},
+};
+use crate::{convert::apply_plan, transforms::*};
 
-mod transforms;
+#[cfg(feature = "profiling")]
+pub use crate::profile::{
+    set_time_source, take_profile, ProfileEntry, ProfileOp, ProfileReport, TimeSource,
+    PROFILE_CAPACITY,
+};
+#[cfg(feature = "macros")]
+pub use embedded_image_macros::include_image;
+
+/// Time the rest of the block as the [`ProfileOp`] `$op`, with the
+/// `profiling` feature
+#[cfg(feature = "profiling")]
+macro_rules! profile {
+    ($op:ident) => {
+        let _timer = crate::profile::Timer::start(crate::profile::ProfileOp::$op);
+    };
+}
+
+#[cfg(not(feature = "profiling"))]
+macro_rules! profile {
+    ($op:ident) => {};
+}
+
+mod accumulate;
+mod adjust;
+mod aligned;
+mod alpha;
+mod ascii;
+mod average;
+mod blend;
+mod blit;
+mod blur;
+mod border;
+pub mod color;
+pub mod color_matrix;
+mod composite;
+mod content;
+mod convert;
+mod coverage;
+mod cvd;
+mod denoise;
+mod deskew;
+mod distort;
+mod dither;
+mod eink;
+mod embed;
+mod fallible;
+mod film;
+mod filmstrip;
+pub mod fixed;
+#[cfg(test)]
+mod fixtures;
+mod font;
+pub mod formats;
+mod framebuffer;
+mod fusion;
+mod gamma;
+mod gamut;
+mod gradient;
+mod heatmap;
+mod highlights;
+mod histmatch;
+pub mod icc;
+mod icons;
+mod interlace;
+mod job;
+mod label;
+pub mod layout;
+#[cfg(all(
+    target_has_atomic = "8",
+    any(feature = "profiling", feature = "plan-cache")
+))]
+mod lock;
+mod luma;
+mod metadata;
+mod mipmap;
+mod montage;
+mod morph;
+mod noise;
+mod ops;
+mod outline;
+mod partial;
+mod pipeline;
+mod pixel;
+mod planar;
+mod policy;
+mod polygon;
+mod precise;
+mod previews;
+#[cfg(feature = "profiling")]
+mod profile;
+mod pyramid;
+mod region;
+pub mod resample;
+mod response;
+mod rle;
+mod rotate;
+mod scale;
+mod scratch;
+mod sdf;
+mod sensor;
+mod shadow;
+#[cfg(feature = "simd")]
+mod simd;
+mod similarity;
+mod stamp;
+mod subsample;
+mod template;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod texture;
+mod tiled;
+mod tonemap;
+pub mod transforms;
+mod transition;
+mod validate;
+#[cfg(feature = "verify")]
+pub mod verify;
+mod yuv;
 
 pub type XY = (u32, u32);
 pub type ResXY = (u32, u32);
@@ -19,20 +196,26 @@ pub type FloatXY = (f32, f32);
 pub type RawPixel = [u8; 4];
 pub type WorkPixel = [f32; 4];
 
+type Transfer = fn(f32) -> f32;
+
+/// Color spaces
+///
+/// The discriminants, as given by `u8::from`, are stable and safe to store.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
+#[repr(u8)]
 pub enum ColorSpace {
     /// sRGB data
-    sRGB,
+    sRGB = 0,
 
     /// Linear sRGB data
-    sRGBLinear,
+    sRGBLinear = 1,
 
     /// So called "simple" sRGB, with a flat gamma of 2.2
-    SimplesRGB,
+    SimplesRGB = 2,
 
     /// Display P3 / P3-D65
-    DisplayP3,
+    DisplayP3 = 3,
 
     /// No color-space / "as-is"
     ///
@@ -41,14 +224,221 @@ pub enum ColorSpace {
     ///
     /// Converting TO or FROM this profile has NO EFFECT beyond changing
     /// the color profile
-    AsIs,
+    AsIs = 4,
+
+    /// Adobe RGB (1998), with a flat gamma of 563/256
+    AdobeRgb = 5,
 }
 
-#[derive(Debug)]
+impl ColorSpace {
+    /// Transfer functions to and from linear light, or `None` if the data is
+    /// already linear.
+    ///
+    /// Note that this is only the transfer function, the primaries are
+    /// unchanged, so Display P3 decodes to *linear P3*.
+    fn transfer(self) -> Option<(Transfer, Transfer)> {
+        match self {
+            ColorSpace::sRGB | ColorSpace::DisplayP3 => Some((srgb_to_rgb, rgb_to_srgb)),
+            ColorSpace::SimplesRGB => Some((gamma_to_rgb, rgb_to_gamma)),
+            ColorSpace::AdobeRgb => Some((adobe_rgb_to_rgb, rgb_to_adobe_rgb)),
+            ColorSpace::sRGBLinear | ColorSpace::AsIs => None,
+        }
+    }
+
+    /// The transfer function of this color space, for applying it to
+    /// buffers that aren't images
+    ///
+    /// [`ColorSpace::AsIs`] is [`TransferFunction::Linear`], it's never
+    /// converted.
+    pub const fn transfer_function(self) -> TransferFunction {
+        match self {
+            ColorSpace::sRGB | ColorSpace::DisplayP3 => TransferFunction::Srgb,
+            ColorSpace::SimplesRGB => TransferFunction::Gamma(2.2),
+            ColorSpace::AdobeRgb => TransferFunction::Gamma(ADOBE_RGB_GAMMA),
+            ColorSpace::sRGBLinear | ColorSpace::AsIs => TransferFunction::Linear,
+        }
+    }
+
+    /// The variant after this one, matched exhaustively so new variants
+    /// can't be forgotten by [`ColorSpace::all`]
+    const fn next(self) -> Option<Self> {
+        match self {
+            ColorSpace::sRGB => Some(ColorSpace::sRGBLinear),
+            ColorSpace::sRGBLinear => Some(ColorSpace::SimplesRGB),
+            ColorSpace::SimplesRGB => Some(ColorSpace::DisplayP3),
+            ColorSpace::DisplayP3 => Some(ColorSpace::AsIs),
+            ColorSpace::AsIs => Some(ColorSpace::AdobeRgb),
+            ColorSpace::AdobeRgb => None,
+        }
+    }
+
+    /// Every color space
+    fn all() -> impl Iterator<Item = Self> {
+        core::iter::successors(Some(ColorSpace::sRGB), |c| c.next())
+    }
+
+    /// Name of the color space, the same as the variant
+    ///
+    /// Parse it back with [`str::parse`], which ignores case.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            ColorSpace::sRGB => "sRGB",
+            ColorSpace::sRGBLinear => "sRGBLinear",
+            ColorSpace::SimplesRGB => "SimplesRGB",
+            ColorSpace::DisplayP3 => "DisplayP3",
+            ColorSpace::AsIs => "AsIs",
+            ColorSpace::AdobeRgb => "AdobeRgb",
+        }
+    }
+}
+
+impl From<ColorSpace> for u8 {
+    fn from(color: ColorSpace) -> Self {
+        color as u8
+    }
+}
+
+impl TryFrom<u8> for ColorSpace {
+    type Error = ImageError;
+
+    /// # Errors
+    ///
+    /// - [`ImageError::UnknownColorSpace`] if `id` isn't a color space
+    fn try_from(id: u8) -> Result<Self, Self::Error> {
+        ColorSpace::all()
+            .find(|c| *c as u8 == id)
+            .ok_or(ImageError::UnknownColorSpace(id))
+    }
+}
+
+impl core::str::FromStr for ColorSpace {
+    type Err = ImageError;
+
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `s` isn't the name of a color
+    ///   space, see [`ColorSpace::as_str`]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ColorSpace::all()
+            .find(|c| c.as_str().eq_ignore_ascii_case(s))
+            .ok_or(ImageError::InvalidArgument)
+    }
+}
+
+/// How the alpha channel relates to the color channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AlphaMode {
+    /// Color channels are independent of alpha
+    #[default]
+    Straight,
+
+    /// Color channels have already been multiplied by alpha
+    Premultiplied,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageError {
+    /// Coordinates or a region were outside the image
+    OutOfBounds,
+
+    /// Images or buffers had mismatched dimensions
+    DimensionMismatch,
+
+    /// An argument was outside its valid range
+    InvalidArgument,
+
+    /// Images had different color spaces
+    ColorSpaceMismatch,
+
+    /// Input data was malformed or corrupt
+    InvalidData,
+
+    /// A channel plane had the wrong length
+    PlaneMismatch(Plane),
+
+    /// The operation isn't supported for these arguments
+    Unsupported,
+
+    /// A stored color space id didn't match any [`ColorSpace`]
+    UnknownColorSpace(u8),
+
+    /// A pixel buffer was the wrong size, in bytes
+    BufferSize { expected: usize, actual: usize },
+
+    /// Data wasn't in any known image format
+    UnknownFormat,
+
+    /// A channel was outside `0..=1`, from [`EncodePolicy::Error`]
+    OutOfRange {
+        x: u32,
+        y: u32,
+        channel: u8,
+        value: f32,
+    },
+
+    /// An allocation failed, from the fallible variants like
+    /// [`Image::try_clone`]
+    OutOfMemory { requested_bytes: usize },
+
+    /// A string wasn't a color, from [`color::named::parse_color`]
+    InvalidColor(color::named::ColorParseError),
+}
+
+impl core::fmt::Display for ImageError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ImageError::OutOfBounds => write!(f, "coordinates out of bounds"),
+            ImageError::DimensionMismatch => write!(f, "mismatched dimensions"),
+            ImageError::InvalidArgument => write!(f, "invalid argument"),
+            ImageError::ColorSpaceMismatch => write!(f, "mismatched color spaces"),
+            ImageError::InvalidData => write!(f, "invalid or corrupt data"),
+            ImageError::PlaneMismatch(p) => write!(f, "{p:?} plane has the wrong length"),
+            ImageError::Unsupported => write!(f, "unsupported operation"),
+            ImageError::UnknownColorSpace(id) => write!(f, "unknown color space id {id}"),
+            ImageError::BufferSize { expected, actual } => {
+                write!(f, "buffer is {actual} bytes, expected {expected}")
+            }
+            ImageError::UnknownFormat => write!(f, "unknown image format"),
+            ImageError::OutOfRange {
+                x,
+                y,
+                channel,
+                value,
+            } => write!(
+                f,
+                "channel {channel} of pixel ({x}, {y}) is out of range at {value}"
+            ),
+            ImageError::OutOfMemory { requested_bytes } => {
+                write!(f, "failed to allocate {requested_bytes} bytes")
+            }
+            ImageError::InvalidColor(e) => write!(f, "invalid color, {e}"),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Image {
     data: Vec<WorkPixel>,
     res: ResXY,
     color: ColorSpace,
+    alpha: AlphaMode,
+    meta: Metadata,
+    /// Override for [`Image::blend_space`]
+    blend: Option<BlendSpace>,
+}
+
+impl core::fmt::Debug for Image {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // The pixel data can be many megabytes, don't dump it all
+        f.debug_struct("Image")
+            .field("res", &self.res)
+            .field("color", &self.color)
+            .field("alpha", &self.alpha)
+            .field("len", &self.data.len())
+            .field("first", &self.data.first())
+            .field("last", &self.data.last())
+            .finish()
+    }
 }
 
 impl Image {
@@ -62,11 +452,26 @@ impl Image {
     ///
     /// - If `data` is not exactly `width * height * 4` in size
     pub fn from_bytes(data: &[u8], res: ResXY, color: ColorSpace) -> Self {
+        Self::from_bytes_with_alpha(data, res, color, AlphaMode::Straight)
+    }
+
+    /// Like [`Image::from_bytes`], but with the [`AlphaMode`] of `data`
+    ///
+    /// # Panics
+    ///
+    /// - If `data` is not exactly `width * height * 4` in size
+    pub fn from_bytes_with_alpha(
+        data: &[u8],
+        res: ResXY,
+        color: ColorSpace,
+        alpha: AlphaMode,
+    ) -> Self {
         let (width, height) = res;
-        assert_eq!(data.len(), (width * height * 4) as usize);
+        let len = (width as usize).checked_mul(height as usize);
+        assert_eq!(Some(data.len()), len.and_then(|n| n.checked_mul(4)));
 
         let data = unsafe {
-            let len = (width * height) as usize;
+            let len = data.len() / 4;
             let data = data.as_ptr() as *const RawPixel;
 
             from_raw_parts(data, len)
@@ -74,7 +479,258 @@ impl Image {
                 .map(|f| f.map(|f| f as f32 / 255.))
                 .collect()
         };
-        Self { data, res, color }
+        let mut img = Self::from_parts(data, res, color);
+        img.alpha = alpha;
+        img
+    }
+
+    /// Read an Image from `data` in the pixel format `format`
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::BufferSize`] if `data` is not exactly
+    ///   `width * height * format.bytes_per_pixel()` in size
+    pub fn from_raw(
+        data: &[u8],
+        res: ResXY,
+        format: PixelFormat,
+        color: ColorSpace,
+    ) -> Result<Self, ImageError> {
+        layout::validate_exact(data, format, res)?;
+        let bpp = format.bytes_per_pixel();
+        let data = data.chunks_exact(bpp).map(|b| format.decode(b)).collect();
+        Ok(Self::from_parts(data, res, color))
+    }
+
+    /// Like [`Image::from_raw`], but with the [`AlphaMode`] of `data`
+    ///
+    /// For premultiplied sources, like framebuffer grabs, follow this with
+    /// [`Image::to_alpha_mode`] to store straight alpha. Fully transparent
+    /// pixels become transparent black, never NaN.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::BufferSize`] if `data` is not exactly
+    ///   `width * height * format.bytes_per_pixel()` in size
+    pub fn from_raw_with_alpha(
+        data: &[u8],
+        res: ResXY,
+        format: PixelFormat,
+        color: ColorSpace,
+        alpha: AlphaMode,
+    ) -> Result<Self, ImageError> {
+        let mut img = Self::from_raw(data, res, format, color)?;
+        img.alpha = alpha;
+        Ok(img)
+    }
+
+    /// Read an Image from `data` in the pixel format `format`, with rows
+    /// `stride` bytes apart in `order`
+    ///
+    /// Padding between rows is ignored, and bottom up rows are put right
+    /// side up as they're read.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `stride` is smaller than a row
+    /// - [`ImageError::BufferSize`] if `data` is too small
+    pub fn from_raw_with_stride(
+        data: &[u8],
+        res: ResXY,
+        format: PixelFormat,
+        color: ColorSpace,
+        stride: usize,
+        order: RowOrder,
+    ) -> Result<Self, ImageError> {
+        layout::validate_buffer(data, format, res, stride)?;
+        let (w, h) = res;
+        let bpp = format.bytes_per_pixel();
+        let mut pixels = Vec::with_capacity(w as usize * h as usize);
+        for y in 0..h {
+            let start = order.buffer_row(y, h) as usize * stride;
+            let row = &data[start..start + w as usize * bpp];
+            pixels.extend(row.chunks_exact(bpp).map(|b| format.decode(b)));
+        }
+        Ok(Self::from_parts(pixels, res, color))
+    }
+
+    /// Export the image in the pixel format `format`, with straight alpha
+    ///
+    /// Gray formats get the luma, computed in linear light.
+    pub fn to_raw(&self, format: PixelFormat) -> Vec<u8> {
+        profile!(Export);
+        let bpp = format.bytes_per_pixel();
+        let transfer = self.color.transfer();
+        let mut out = vec![0; self.data.len() * bpp];
+        for (p, o) in self.data.iter().zip(out.chunks_exact_mut(bpp)) {
+            format.encode(layout::prepare(*p, format, self.alpha, transfer), o);
+        }
+        out
+    }
+
+    /// Like [`Image::to_raw`], but exporting with the [`AlphaMode`] `alpha`
+    ///
+    /// Gray formats get the luma of the straight color, premultiplied after.
+    pub fn to_raw_with_alpha(&self, format: PixelFormat, alpha: AlphaMode) -> Vec<u8> {
+        profile!(Export);
+        let bpp = format.bytes_per_pixel();
+        let transfer = self.color.transfer();
+        let convert = alpha_converter(AlphaMode::Straight, alpha);
+        let mut out = vec![0; self.data.len() * bpp];
+        for (p, o) in self.data.iter().zip(out.chunks_exact_mut(bpp)) {
+            let p = layout::prepare(*p, format, self.alpha, transfer);
+            format.encode(convert(p), o);
+        }
+        out
+    }
+
+    /// Export the image into `out` in the pixel format `format`, with rows
+    /// `stride` bytes apart in `order`, and straight alpha
+    ///
+    /// Padding bytes are not written.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `stride` is smaller than a row
+    /// - [`ImageError::BufferSize`] if `out` is too small
+    pub fn write_raw(
+        &self,
+        out: &mut [u8],
+        format: PixelFormat,
+        stride: usize,
+        order: RowOrder,
+    ) -> Result<(), ImageError> {
+        profile!(Export);
+        layout::validate_buffer(out, format, self.res, stride)?;
+        let bpp = format.bytes_per_pixel();
+        let transfer = self.color.transfer();
+        let width = self.width() as usize;
+        for (y, row) in self.data.chunks_exact(width.max(1)).enumerate() {
+            let start = order.buffer_row(y as u32, self.height()) as usize * stride;
+            let dst = &mut out[start..start + width * bpp];
+            for (p, o) in row.iter().zip(dst.chunks_exact_mut(bpp)) {
+                format.encode(layout::prepare(*p, format, self.alpha, transfer), o);
+            }
+        }
+        Ok(())
+    }
+
+    /// Read an Image from 8-bit gray and alpha pairs, like font atlases
+    ///
+    /// Gray is replicated into RGB.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::BufferSize`] if `data` is not exactly
+    ///   `width * height * 2` in size
+    pub fn from_la_bytes(data: &[u8], res: ResXY, color: ColorSpace) -> Result<Self, ImageError> {
+        Self::from_raw(data, res, PixelFormat::GrayAlpha8, color)
+    }
+
+    /// Export the image as 8-bit gray and alpha pairs, with straight alpha
+    ///
+    /// Gray is the luma, computed in linear light.
+    pub fn to_la_bytes(&self) -> Vec<u8> {
+        self.to_raw(PixelFormat::GrayAlpha8)
+    }
+
+    /// Read an Image from 16-bit gray and alpha pairs, with multi-byte
+    /// channels in `endian` order
+    ///
+    /// Gray is replicated into RGB.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::BufferSize`] if `data` is not exactly
+    ///   `width * height * 4` in size
+    pub fn from_la16_bytes(
+        data: &[u8],
+        res: ResXY,
+        color: ColorSpace,
+        endian: Endian,
+    ) -> Result<Self, ImageError> {
+        Self::from_raw(data, res, la16_format(endian), color)
+    }
+
+    /// Export the image as 16-bit gray and alpha pairs, with straight alpha,
+    /// with multi-byte channels in `endian` order
+    ///
+    /// Gray is the luma, computed in linear light and encoded with the
+    /// image's transfer function, convert to [`ColorSpace::sRGBLinear`]
+    /// first for linear luma. Rounding is the same as every other exporter,
+    /// half away from zero.
+    pub fn to_la16_bytes(&self, endian: Endian) -> Vec<u8> {
+        self.to_raw(la16_format(endian))
+    }
+
+    fn from_parts(data: Vec<WorkPixel>, res: ResXY, color: ColorSpace) -> Self {
+        let img = Self::from_parts_unchecked(data, res, color);
+        img.check();
+        img
+    }
+
+    /// [`Image::from_parts`] for caller provided floats, which may not be
+    /// finite until [`Image::sanitize`]d
+    fn from_parts_unchecked(data: Vec<WorkPixel>, res: ResXY, color: ColorSpace) -> Self {
+        let img = Self {
+            data,
+            res,
+            color,
+            alpha: AlphaMode::Straight,
+            meta: Metadata::default(),
+            blend: None,
+        };
+        img.debug_assert_consistent();
+        img
+    }
+
+    /// A new image derived from this one, keeping its color space, alpha
+    /// mode, and metadata
+    fn derive(&self, data: Vec<WorkPixel>, res: ResXY) -> Self {
+        let img = Self {
+            data,
+            res,
+            color: self.color,
+            alpha: self.alpha,
+            meta: self.meta.clone(),
+            blend: self.blend,
+        };
+        img.check();
+        img
+    }
+
+    /// Export the image as RGBA, 8 bits per channel, with straight alpha
+    ///
+    /// Values are clamped to `0..=1`, multiplied by 255, and rounded. NaN
+    /// becomes 0.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with_alpha(AlphaMode::Straight)
+    }
+
+    /// Like [`Image::to_bytes`], but exporting with the [`AlphaMode`] `alpha`
+    pub fn to_bytes_with_alpha(&self, alpha: AlphaMode) -> Vec<u8> {
+        profile!(Export);
+        let convert = alpha_converter(self.alpha, alpha);
+        #[cfg(feature = "simd")]
+        {
+            let mut out = Vec::with_capacity(self.data.len() * 4);
+            if self.alpha == alpha {
+                simd::pixels_to_bytes(&self.data, &mut out);
+            } else {
+                let mut converted = Vec::with_capacity(256);
+                for run in self.data.chunks(256) {
+                    converted.clear();
+                    converted.extend(run.iter().map(|p| convert(*p)));
+                    simd::pixels_to_bytes(&converted, &mut out);
+                }
+            }
+            out
+        }
+        #[cfg(not(feature = "simd"))]
+        self.data
+            .iter()
+            .flat_map(|p| convert(*p).map(|c| layout::quantize(c, 255.) as u8))
+            .collect()
     }
 
     pub fn width(&self) -> u32 {
@@ -89,93 +745,345 @@ impl Image {
         self.color
     }
 
+    pub fn alpha_mode(&self) -> AlphaMode {
+        self.alpha
+    }
+
+    /// Convert the pixel data to the [`AlphaMode`] `alpha`
+    pub fn to_alpha_mode(&mut self, alpha: AlphaMode) {
+        let convert = alpha_converter(self.alpha, alpha);
+        for p in &mut self.data {
+            *p = convert(*p);
+        }
+        self.alpha = alpha;
+    }
+
+    /// A short one line description of the image, for logging
+    ///
+    /// Its length doesn't depend on the image size, like the [`Debug`]
+    /// output, so it's safe to send over slow links like RTT, with
+    /// `defmt::Display2Format` for defmt.
+    ///
+    /// [`Debug`]: core::fmt::Debug
+    pub fn summary(&self) -> impl core::fmt::Display + '_ {
+        struct Summary<'a>(&'a Image);
+
+        impl core::fmt::Display for Summary<'_> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                let img = self.0;
+                write!(
+                    f,
+                    "{}x{} {:?} {:?}",
+                    img.width(),
+                    img.height(),
+                    img.color,
+                    img.alpha
+                )
+            }
+        }
+
+        Summary(self)
+    }
+
     pub fn pixels(&self) -> &[WorkPixel] {
         &self.data
     }
 
+    /// Split the pixel data into `n` chunks of whole rows
+    ///
+    /// Each chunk has `height / n` rows, except the last which also gets the
+    /// remainder. `n` is clamped to `1..=height`, so an image with no rows
+    /// gives no chunks.
+    pub fn split_rows_mut(&mut self, n: usize) -> Vec<&mut [WorkPixel]> {
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        if height == 0 {
+            return Vec::new();
+        }
+        let n = n.clamp(1, height);
+        let rows = height / n;
+        let mut out = Vec::with_capacity(n);
+        let mut rest = &mut self.data[..];
+        for _ in 0..n - 1 {
+            let (chunk, tail) = rest.split_at_mut(rows * width);
+            out.push(chunk);
+            rest = tail;
+        }
+        out.push(rest);
+        out
+    }
+
+    /// Row `y`, or `None` if it's out of bounds
+    pub fn row(&self, y: u32) -> Option<&[WorkPixel]> {
+        let w = self.width() as usize;
+        let start = self.index((0, y))?;
+        Some(&self.data[start..start + w])
+    }
+
+    /// Mutable row `y`, or `None` if it's out of bounds
+    pub fn row_mut(&mut self, y: u32) -> Option<&mut [WorkPixel]> {
+        let w = self.width() as usize;
+        let start = self.index((0, y))?;
+        Some(&mut self.data[start..start + w])
+    }
+
+    /// Column `x`, top to bottom, empty if it's out of bounds
+    pub fn column(&self, x: u32) -> impl Iterator<Item = &WorkPixel> {
+        let (w, len) = self.column_bounds(x);
+        self.data[..len].iter().skip(x as usize).step_by(w)
+    }
+
+    /// Mutable column `x`, top to bottom, empty if it's out of bounds
+    pub fn column_mut(&mut self, x: u32) -> impl Iterator<Item = &mut WorkPixel> {
+        let (w, len) = self.column_bounds(x);
+        self.data[..len].iter_mut().skip(x as usize).step_by(w)
+    }
+
+    /// Stride and how much of the data to walk for column `x`
+    fn column_bounds(&self, x: u32) -> (usize, usize) {
+        let w = self.width() as usize;
+        if x < self.width() {
+            (w, self.data.len())
+        } else {
+            (w.max(1), 0)
+        }
+    }
+
+    /// Swap rows `a` and `b`
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::OutOfBounds`] if either row is outside the image
+    pub fn swap_rows(&mut self, a: u32, b: u32) -> Result<(), ImageError> {
+        let w = self.width() as usize;
+        let (a, b) = match (self.index((0, a)), self.index((0, b))) {
+            (Some(a), Some(b)) => (a.min(b), a.max(b)),
+            _ => return Err(ImageError::OutOfBounds),
+        };
+        if a != b {
+            let (head, tail) = self.data.split_at_mut(b);
+            head[a..a + w].swap_with_slice(&mut tail[..w]);
+        }
+        Ok(())
+    }
+
+    /// Swap columns `a` and `b`
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::OutOfBounds`] if either column is outside the image
+    pub fn swap_columns(&mut self, a: u32, b: u32) -> Result<(), ImageError> {
+        if a >= self.width() || b >= self.width() {
+            return Err(ImageError::OutOfBounds);
+        }
+        for row in self.data.chunks_exact_mut(self.res.0 as usize) {
+            row.swap(a as usize, b as usize);
+        }
+        Ok(())
+    }
+
+    /// Get the pixel at `xy`, or `None` if it's out of bounds
+    pub fn get_pixel(&self, xy: XY) -> Option<WorkPixel> {
+        self.index(xy).map(|i| self.data[i])
+    }
+
+    /// Apply `f` to every pixel
+    pub fn map_pixels(&mut self, mut f: impl FnMut(WorkPixel) -> WorkPixel) {
+        for p in &mut self.data {
+            *p = f(*p);
+        }
+        self.check();
+    }
+
+    /// Apply `f` to every pixel, along with its coordinates
+    pub fn map_pixels_indexed(&mut self, mut f: impl FnMut(XY, WorkPixel) -> WorkPixel) {
+        let width = self.width();
+        for (i, p) in self.data.iter_mut().enumerate() {
+            let xy = (i as u32 % width, i as u32 / width);
+            *p = f(xy, *p);
+        }
+        self.check();
+    }
+
+    /// Like [`Image::map_pixels`], but returns a new image
+    pub fn mapped(&self, f: impl FnMut(WorkPixel) -> WorkPixel) -> Self {
+        let mut img = self.clone();
+        img.map_pixels(f);
+        img
+    }
+
+    /// Combine every pixel with the pixel at the same position in `other`
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::DimensionMismatch`] if the images aren't the same size
+    pub fn zip_map(
+        &mut self,
+        other: &Image,
+        mut f: impl FnMut(WorkPixel, WorkPixel) -> WorkPixel,
+    ) -> Result<(), ImageError> {
+        if self.res != other.res {
+            return Err(ImageError::DimensionMismatch);
+        }
+        for (p, o) in self.data.iter_mut().zip(&other.data) {
+            *p = f(*p, *o);
+        }
+        self.check();
+        Ok(())
+    }
+
+    fn index(&self, (x, y): XY) -> Option<usize> {
+        if x < self.width() && y < self.height() {
+            Some((y as usize * self.width() as usize) + x as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Check the rectangle at `origin` of `size` is non-empty and fits in the
+    /// image
+    fn check_rect(&self, (x, y): XY, (w, h): ResXY) -> Result<(), ImageError> {
+        if w == 0 || h == 0 {
+            return Err(ImageError::InvalidArgument);
+        }
+        match (x.checked_add(w), y.checked_add(h)) {
+            (Some(r), Some(b)) if r <= self.width() && b <= self.height() => Ok(()),
+            _ => Err(ImageError::OutOfBounds),
+        }
+    }
+
+    /// Run `f` with the pixel data decoded to linear light, re-encoding it
+    /// afterwards.
+    ///
+    /// The color space tag is not changed.
+    fn in_linear<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let transfer = self.color.transfer();
+        if let Some((decode, _)) = transfer {
+            apply_transfer(&mut self.data, decode);
+        }
+        let r = f(self);
+        if let Some((_, encode)) = transfer {
+            apply_transfer(&mut self.data, encode);
+        }
+        self.check();
+        r
+    }
+
     // TODO: Rendering intents?
     // jfc it really set out to write a uefi stub
     // and is now learning about color and writing an no_std image library huh
     // insane
+    /// Convert the image to the color space `color`
+    ///
+    /// Converting to the current color space, or to or from
+    /// [`ColorSpace::AsIs`], doesn't touch the pixels at all. Premultiplied
+    /// images are converted on their straight color, and stay premultiplied.
     pub fn to_color(&mut self, color: ColorSpace) {
-        for p in &mut self.data {
-            let mut q = Matrix3x1::from_row_slice(&p[..3]);
-            // TODO: ugh this doesn't need to be in the loop but it doesn't feel like moving
-            // it right now
-            match (self.color, color) {
-                (ColorSpace::sRGB, ColorSpace::sRGBLinear) => q = q.map(srgb_to_rgb),
-                (ColorSpace::sRGB, ColorSpace::SimplesRGB) => {
-                    q = q.map(srgb_to_rgb).map(rgb_to_gamma)
-                }
-                (ColorSpace::sRGB, ColorSpace::DisplayP3) => todo!(),
-
-                (ColorSpace::sRGBLinear, ColorSpace::sRGB) => q = q.map(rgb_to_srgb),
-                (ColorSpace::sRGBLinear, ColorSpace::DisplayP3) => todo!(),
-                (ColorSpace::sRGBLinear, ColorSpace::SimplesRGB) => q = q.map(rgb_to_gamma),
+        Converter::new(self.color, color).apply(self)
+    }
 
-                (ColorSpace::SimplesRGB, ColorSpace::sRGBLinear) => todo!(),
-                (ColorSpace::SimplesRGB, ColorSpace::sRGB) => {
-                    q = q.map(gamma_to_rgb).map(rgb_to_srgb)
-                }
-                (ColorSpace::SimplesRGB, ColorSpace::DisplayP3) => todo!(),
+    /// Convert the image to the color space `color`, bringing out of gamut
+    /// colors back in with `gamut`
+    ///
+    /// Unlike [`Image::to_color`] the result is always within `0..=1`.
+    pub fn to_color_with_intent(&mut self, color: ColorSpace, gamut: GamutMap) {
+        apply_plan(
+            &ConversionPlan::cached(self.color, color, Some(gamut)),
+            self,
+        )
+    }
 
-                (ColorSpace::DisplayP3, ColorSpace::sRGB) => todo!(),
-                (ColorSpace::DisplayP3, ColorSpace::sRGBLinear) => todo!(),
-                (ColorSpace::DisplayP3, ColorSpace::SimplesRGB) => todo!(),
+    /// Heap memory used by the pixel data, in bytes
+    ///
+    /// This is the allocated capacity, not just what's in use, see
+    /// [`Image::shrink_to_fit`].
+    pub fn byte_size(&self) -> usize {
+        self.data.capacity() * size_of::<WorkPixel>()
+    }
 
-                (ColorSpace::sRGB, ColorSpace::sRGB) => (),
-                (ColorSpace::sRGBLinear, ColorSpace::sRGBLinear) => (),
-                (ColorSpace::SimplesRGB, ColorSpace::SimplesRGB) => todo!(),
-                (ColorSpace::DisplayP3, ColorSpace::DisplayP3) => (),
+    /// Release any excess capacity left over from shrinking operations
+    pub fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+    }
 
-                (_, ColorSpace::AsIs) => (),
-                (ColorSpace::AsIs, _) => (),
-            }
-            *p = [q[0], q[1], q[2], p[3]];
+    /// Crop the image to the rectangle at `origin` of `size`
+    ///
+    /// This is done in place, reusing the existing allocation.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `size` is zero
+    /// - [`ImageError::OutOfBounds`] if the rectangle doesn't fit in the image
+    pub fn crop(&mut self, origin: XY, size: ResXY) -> Result<(), ImageError> {
+        self.check_rect(origin, size)?;
+        let (x, y) = origin;
+        let (w, h) = size;
+        let width = self.width() as usize;
+        let (x, y, w, h) = (x as usize, y as usize, w as usize, h as usize);
+        for row in 0..h {
+            let src = ((y + row) * width) + x;
+            self.data.copy_within(src..src + w, row * w);
         }
-        self.color = color;
+        self.data.truncate(w * h);
+        self.res = size;
+        self.check();
+        Ok(())
     }
 
+    /// Scale the image to `new` using bilinear filtering
+    ///
+    /// See [`Image::scale_with`]
+    ///
+    /// # Panics
+    ///
+    /// - If `new` is zero in either dimension
     pub fn scale(&mut self, new: ResXY) {
-        let width = self.width();
-        let height = self.height();
-        let (new_width, new_height) = (new.0, new.1);
-        if (width, height) == (new_width, new_height) {
-            return;
-        }
-        let x_scale = (new_width - 1) as f32 / (width - 1) as f32;
-        let y_scale = (new_height - 1) as f32 / (height - 1) as f32;
-
-        let pixels = self.pixels();
-        let mut out: Vec<WorkPixel> = vec![Default::default(); (new_height * new_width) as usize];
+        self.scale_with(new, ScaleFilter::Bilinear)
+    }
+}
 
-        for y in 0..new_height {
-            for x in 0..new_width {
-                let res = bilinear((x, y), (x_scale, y_scale), (width, height), pixels);
+/// Convert pixels from the color space `from` to `to`
+///
+/// This is what [`Image::to_color`] uses, but works on any slice of pixels,
+/// such as the chunks from [`Image::split_rows_mut`], so they can be
+/// converted independently.
+pub fn convert_rows(rows: &mut [WorkPixel], from: ColorSpace, to: ColorSpace) {
+    Converter::new(from, to).convert_rows(rows)
+}
 
-                let index = ((y * new_width) + x) as usize;
-                out[index] = res;
-            }
-        }
-        self.data = out;
-        self.res = new;
+fn la16_format(endian: Endian) -> PixelFormat {
+    match endian {
+        Endian::Little => PixelFormat::GrayAlpha16Le,
+        Endian::Big => PixelFormat::GrayAlpha16Be,
     }
 }
 
-#[allow(unused_variables)]
-fn bilinear(xy: XY, scale: FloatXY, src: ResXY, pixels: &[WorkPixel]) -> WorkPixel {
-    todo!("fuck this")
+fn alpha_converter(from: AlphaMode, to: AlphaMode) -> fn(WorkPixel) -> WorkPixel {
+    match (from, to) {
+        (AlphaMode::Straight, AlphaMode::Premultiplied) => premultiply,
+        (AlphaMode::Premultiplied, AlphaMode::Straight) => unpremultiply,
+        _ => |p| p,
+    }
 }
 
 /// Helper for no_std float methods
 pub trait F32 {
     fn powf(self, n: f32) -> f32;
 
+    fn sqrt(self) -> f32;
+
     fn round(self) -> f32;
 
     fn floor(self) -> f32;
 
     fn ceil(self) -> f32;
+
+    fn exp(self) -> f32;
+
+    fn ln(self) -> f32;
+
+    fn sin(self) -> f32;
+
+    fn cos(self) -> f32;
 }
 
 impl F32 for f32 {
@@ -184,6 +1092,11 @@ impl F32 for f32 {
         libm::powf(self, n)
     }
 
+    #[inline]
+    fn sqrt(self) -> f32 {
+        libm::sqrtf(self)
+    }
+
     #[inline]
     fn round(self) -> f32 {
         libm::roundf(self)
@@ -198,4 +1111,636 @@ impl F32 for f32 {
     fn ceil(self) -> f32 {
         libm::ceilf(self)
     }
+
+    #[inline]
+    fn exp(self) -> f32 {
+        libm::expf(self)
+    }
+
+    #[inline]
+    fn ln(self) -> f32 {
+        libm::logf(self)
+    }
+
+    #[inline]
+    fn sin(self) -> f32 {
+        libm::sinf(self)
+    }
+
+    #[inline]
+    fn cos(self) -> f32 {
+        libm::cosf(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(res: ResXY) -> Image {
+        let data: Vec<u8> = (0..res.0 * res.1 * 4).map(|i| i as u8).collect();
+        Image::from_bytes(&data, res, ColorSpace::sRGB)
+    }
+
+    #[test]
+    fn split_rows_mut() {
+        let mut img = image((3, 7));
+        let chunks = img.split_rows_mut(3);
+        let lens: Vec<usize> = chunks.iter().map(|c| c.len()).collect();
+        assert_eq!(lens, [6, 6, 9]);
+        assert_eq!(img.split_rows_mut(0).len(), 1);
+        assert_eq!(img.split_rows_mut(100).len(), 7);
+    }
+
+    #[test]
+    fn split_rows_mut_empty() {
+        assert!(image((3, 0)).split_rows_mut(4).is_empty());
+        assert!(image((0, 0)).split_rows_mut(1).is_empty());
+        assert_eq!(image((0, 3)).split_rows_mut(2).len(), 2);
+    }
+
+    #[test]
+    fn split_rows_mut_covers_once() {
+        for height in 1..14 {
+            for n in 1..9 {
+                let mut img = image((5, height));
+                img.data.fill([0.; 4]);
+                let chunks = img.split_rows_mut(n);
+                assert_eq!(chunks.len(), n.min(height as usize));
+                for chunk in chunks {
+                    assert_eq!(chunk.len() % 5, 0, "{height} rows in {n}");
+                    assert!(!chunk.is_empty());
+                    chunk.iter_mut().for_each(|p| p[0] += 1.);
+                }
+                assert!(img.data.iter().all(|p| p[0] == 1.), "{height} rows in {n}");
+            }
+        }
+    }
+
+    #[test]
+    fn convert_rows_in_chunks() {
+        for (from, to) in [
+            (ColorSpace::sRGB, ColorSpace::DisplayP3),
+            (ColorSpace::DisplayP3, ColorSpace::sRGBLinear),
+        ] {
+            let mut whole = fixtures::photo((7, 11));
+            whole.color = from;
+            let mut split = whole.clone();
+            whole.to_color(to);
+            for chunk in split.split_rows_mut(3) {
+                convert_rows(chunk, from, to);
+            }
+            assert!(fixtures::max_diff(&whole.data, &split.data) < 1e-6);
+        }
+    }
+
+    const SPACES: [ColorSpace; 5] = [
+        ColorSpace::sRGB,
+        ColorSpace::sRGBLinear,
+        ColorSpace::SimplesRGB,
+        ColorSpace::DisplayP3,
+        ColorSpace::AsIs,
+    ];
+
+    /// An image with values just out of range both ways
+    fn overshoot(color: ColorSpace) -> Image {
+        let mut img = image((2, 2));
+        img.data = vec![
+            [-0.01, 0.5, 1.5, 1.],
+            [1.5, -0.01, 0., 0.5],
+            [0., 1., -0.5, 1.],
+            [2., 2., 2., 1.],
+        ];
+        img.color = color;
+        img
+    }
+
+    #[test]
+    fn to_color_out_of_range_is_never_nan() {
+        for from in SPACES {
+            for to in SPACES {
+                let mut img = overshoot(from);
+                img.to_color(to);
+                assert_eq!(img.color, to);
+                assert!(
+                    img.data.iter().flatten().all(|c| c.is_finite()),
+                    "{from:?} to {to:?}: {:?}",
+                    img.data
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn to_color_identity_is_bitwise() {
+        for color in SPACES {
+            let mut img = overshoot(color);
+            let before: Vec<u32> = img.data.iter().flatten().map(|c| c.to_bits()).collect();
+            img.to_color(color);
+            let after: Vec<u32> = img.data.iter().flatten().map(|c| c.to_bits()).collect();
+            assert_eq!(before, after, "{color:?}");
+        }
+    }
+
+    #[test]
+    fn map_pixels_indexed_coordinates() {
+        let mut img = image((5, 3));
+        img.map_pixels_indexed(|(x, y), p| [x as f32, y as f32, p[2], p[3]]);
+        for y in 0..3 {
+            for x in 0..5 {
+                let p = img.get_pixel((x, y)).unwrap();
+                assert_eq!((p[0], p[1]), (x as f32, y as f32));
+            }
+        }
+    }
+
+    #[test]
+    fn mapped_leaves_original() {
+        let img = image((2, 2));
+        let inverted = img.mapped(|p| [1. - p[0], p[1], p[2], p[3]]);
+        assert_eq!(img.pixels(), image((2, 2)).pixels());
+        for (a, b) in img.pixels().iter().zip(inverted.pixels()) {
+            assert_eq!(b[0], 1. - a[0]);
+        }
+    }
+
+    #[test]
+    fn zip_map() {
+        let mut a = image((3, 2));
+        let b = a.mapped(|p| p.map(|c| c * 0.5));
+        a.zip_map(&b, |a, b| [a[0] - b[0], a[1], a[2], a[3]])
+            .unwrap();
+        for (a, b) in a.pixels().iter().zip(b.pixels()) {
+            assert_eq!(a[0], b[0]);
+        }
+        assert_eq!(
+            a.zip_map(&image((2, 3)), |a, _| a),
+            Err(ImageError::DimensionMismatch)
+        );
+    }
+
+    #[test]
+    fn shrink_to_fit_after_downscale() {
+        let mut img = image((16, 12));
+        let full = img.byte_size();
+        assert_eq!(full, 16 * 12 * size_of::<WorkPixel>());
+        img.scale_with((5, 4), ScaleFilter::Box);
+        assert_eq!(img.byte_size(), full, "Shrinking reuses the allocation");
+        img.shrink_to_fit();
+        assert!(img.byte_size() < full);
+        assert_eq!(img.byte_size(), 5 * 4 * size_of::<WorkPixel>());
+    }
+
+    #[test]
+    fn crop_in_place() {
+        let img = image((6, 5));
+        let mut cropped = img.clone();
+        cropped.crop((1, 2), (4, 3)).unwrap();
+        for y in 0..3 {
+            for x in 0..4 {
+                assert_eq!(
+                    cropped.get_pixel((x, y)),
+                    img.get_pixel((x + 1, y + 2)),
+                    "{x}, {y}"
+                );
+            }
+        }
+        assert_eq!(cropped.byte_size(), img.byte_size());
+    }
+
+    #[test]
+    fn export_round_trips() {
+        // Premultiplied bytes have no color above their alpha
+        let bytes: Vec<u8> = (0..64u32)
+            .flat_map(|i| {
+                let a = (i * 4 + 3) as u8;
+                [a / 2, a / 3, a, a]
+            })
+            .collect();
+        for alpha in [AlphaMode::Straight, AlphaMode::Premultiplied] {
+            let img = Image::from_bytes_with_alpha(&bytes, (8, 8), ColorSpace::sRGB, alpha);
+            assert_eq!(img.alpha_mode(), alpha);
+            assert_eq!(img.to_bytes_with_alpha(alpha), bytes, "{alpha:?}");
+
+            let mut other = img.clone();
+            other.to_alpha_mode(match alpha {
+                AlphaMode::Straight => AlphaMode::Premultiplied,
+                AlphaMode::Premultiplied => AlphaMode::Straight,
+            });
+            assert_eq!(other.to_bytes_with_alpha(alpha), bytes, "{alpha:?}");
+        }
+        let img = image((4, 4));
+        assert_eq!(img.to_bytes(), img.to_bytes_with_alpha(AlphaMode::Straight));
+    }
+
+    #[test]
+    fn debug_is_bounded() {
+        let small = alloc::format!("{:?}", image((1, 1)));
+        let big = alloc::format!("{:?}", image((1000, 1000)));
+        assert!(big.len() < 300, "{big}");
+        assert!(big.len() < small.len() + 16, "{small}\n{big}");
+        assert!(big.contains("1000000"), "{big}");
+        assert!(alloc::format!("{:?}", image((0, 0))).len() < 300);
+
+        let summary = alloc::format!("{}", image((640, 480)).summary());
+        assert_eq!(summary, "640x480 sRGB Straight");
+    }
+
+    #[test]
+    fn to_color_with_intent_in_range() {
+        for gamut in [GamutMap::Clip, GamutMap::SoftClip, GamutMap::ChromaReduce] {
+            let mut img = image((8, 8));
+            img.color = ColorSpace::DisplayP3;
+            img.data[0] = [1., 0., 0., 1.];
+            img.data[1] = [0., 1., 0.2, 0.5];
+            img.to_color_with_intent(ColorSpace::sRGB, gamut);
+            assert_eq!(img.color, ColorSpace::sRGB);
+            assert!(
+                img.data.iter().flatten().all(|c| (0.0..=1.0).contains(c)),
+                "{gamut:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rows_and_columns() {
+        let img = image((5, 3));
+        for y in 0..3 {
+            let row = img.row(y).unwrap();
+            assert_eq!(row.len(), 5);
+            assert_eq!(row[4], img.get_pixel((4, y)).unwrap());
+        }
+        assert!(img.row(3).is_none());
+        for x in 0..5 {
+            let column: Vec<WorkPixel> = img.column(x).copied().collect();
+            let want: Vec<WorkPixel> = (0..3).map(|y| img.get_pixel((x, y)).unwrap()).collect();
+            assert_eq!(column, want);
+        }
+        assert_eq!(img.column(5).count(), 0);
+        assert_eq!(image((0, 3)).column(0).count(), 0);
+        assert_eq!(image((3, 0)).column(0).count(), 0);
+    }
+
+    #[test]
+    fn mutable_rows_and_columns() {
+        let mut img = image((4, 6));
+        for (y, p) in img.column_mut(2).enumerate() {
+            *p = [y as f32; 4];
+        }
+        for y in 0..6 {
+            assert_eq!(img.get_pixel((2, y)), Some([y as f32; 4]));
+            assert_ne!(img.get_pixel((1, y)), Some([y as f32; 4]));
+        }
+        img.row_mut(5).unwrap().fill([9.; 4]);
+        assert_eq!(img.get_pixel((0, 5)), Some([9.; 4]));
+        assert!(img.row_mut(6).is_none());
+        assert_eq!(img.column_mut(4).count(), 0);
+    }
+
+    #[test]
+    fn swaps() {
+        let original = image((4, 3));
+        let mut img = original.clone();
+        img.swap_rows(0, 2).unwrap();
+        assert_eq!(img.row(0), original.row(2));
+        assert_eq!(img.row(2), original.row(0));
+        img.swap_rows(1, 1).unwrap();
+        assert_eq!(img.row(1), original.row(1));
+        img.swap_columns(3, 0).unwrap();
+        assert_eq!(img.get_pixel((0, 0)), original.get_pixel((3, 2)));
+        assert_eq!(img.swap_rows(0, 3), Err(ImageError::OutOfBounds));
+        assert_eq!(img.swap_columns(4, 0), Err(ImageError::OutOfBounds));
+
+        let mut flipped = original.clone();
+        flipped.flip_horizontal();
+        assert_eq!(flipped.get_pixel((0, 1)), original.get_pixel((3, 1)));
+        assert_eq!(flipped.get_pixel((1, 1)), original.get_pixel((2, 1)));
+        flipped.flip_vertical();
+        assert_eq!(flipped.get_pixel((0, 0)), original.get_pixel((3, 2)));
+    }
+
+    #[test]
+    fn la_gray_round_trip() {
+        let data: Vec<u8> = (0..=255u8).flat_map(|v| [v, 255 - v]).collect();
+        for color in [
+            ColorSpace::sRGB,
+            ColorSpace::sRGBLinear,
+            ColorSpace::SimplesRGB,
+        ] {
+            let img = Image::from_la_bytes(&data, (16, 16), color).unwrap();
+            assert!(img.pixels().iter().all(|p| p[0] == p[1] && p[1] == p[2]));
+            assert_eq!(img.to_la_bytes(), data, "{color:?}");
+        }
+    }
+
+    #[test]
+    fn la_luma_weights() {
+        let rgba = [
+            255, 0, 0, 255, 0, 255, 0, 128, 0, 0, 255, 0, 255, 255, 255, 255,
+        ];
+        let img = Image::from_bytes(&rgba, (4, 1), ColorSpace::sRGBLinear);
+        // Rec.709 weights, in linear light
+        assert_eq!(img.to_la_bytes(), [54, 255, 182, 128, 18, 0, 255, 255]);
+
+        let img = Image::from_bytes(&[255, 0, 0, 255], (1, 1), ColorSpace::sRGB);
+        let want = (crate::transforms::rgb_to_srgb(0.2126) * 255.).round() as u8;
+        assert_eq!(img.to_la_bytes(), [want, 255]);
+        assert_ne!(want, 54);
+    }
+
+    #[test]
+    fn la_length_checks() {
+        let err = |len: usize| Image::from_la_bytes(&vec![0; len], (3, 2), ColorSpace::sRGB).err();
+        assert_eq!(err(12), None);
+        for len in [11, 13, 24] {
+            assert_eq!(
+                err(len),
+                Some(ImageError::BufferSize {
+                    expected: 12,
+                    actual: len
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn color_space_ids() {
+        let all: Vec<ColorSpace> = ColorSpace::all().collect();
+        assert_eq!(all.len(), 6);
+        for (id, color) in all.iter().enumerate() {
+            assert_eq!(u8::from(*color), id as u8);
+            assert_eq!(ColorSpace::try_from(id as u8), Ok(*color));
+        }
+        for id in [6, 42, 255] {
+            assert_eq!(
+                ColorSpace::try_from(id),
+                Err(ImageError::UnknownColorSpace(id))
+            );
+        }
+    }
+
+    #[test]
+    fn color_space_names() {
+        for color in ColorSpace::all() {
+            assert_eq!(color.as_str().parse(), Ok(color));
+            assert_eq!(color.as_str().to_ascii_uppercase().parse(), Ok(color));
+            assert_eq!(color.as_str().to_ascii_lowercase().parse(), Ok(color));
+        }
+        assert_eq!("DisplayP3".parse::<ColorSpace>(), Ok(ColorSpace::DisplayP3));
+        assert_eq!("srgb".parse::<ColorSpace>(), Ok(ColorSpace::sRGB));
+        for bad in ["", "sRGB ", "P3", "linear"] {
+            assert_eq!(bad.parse::<ColorSpace>(), Err(ImageError::InvalidArgument));
+        }
+    }
+
+    #[test]
+    fn premultiplied_import() {
+        // Straight colors, and the same premultiplied
+        let mut straight = Vec::new();
+        for a in [255u8, 200, 128, 77, 3] {
+            for c in [0u8, 1, 90, 180, 255] {
+                straight.extend([c, 255 - c, c / 2, a]);
+            }
+        }
+        let premul: Vec<u8> = straight
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let a = p[3] as f32 / 255.;
+                [p[0], p[1], p[2]]
+                    .map(|c| (c as f32 * a).round() as u8)
+                    .into_iter()
+                    .chain([p[3]])
+            })
+            .collect();
+        let res = (5, 5);
+        let mut img = Image::from_raw_with_alpha(
+            &premul,
+            res,
+            PixelFormat::Rgba8888,
+            ColorSpace::sRGB,
+            AlphaMode::Premultiplied,
+        )
+        .unwrap();
+        assert_eq!(
+            img.to_raw_with_alpha(PixelFormat::Rgba8888, AlphaMode::Premultiplied),
+            premul
+        );
+        img.to_alpha_mode(AlphaMode::Straight);
+        assert_eq!(
+            img.to_raw_with_alpha(PixelFormat::Rgba8888, AlphaMode::Premultiplied),
+            premul
+        );
+        for (got, want) in img
+            .to_raw(PixelFormat::Rgba8888)
+            .chunks_exact(4)
+            .zip(straight.chunks_exact(4))
+        {
+            // Premultiplying lost precision at low alpha
+            let tolerance = 255 / want[3] as u32;
+            assert_eq!(got[3], want[3]);
+            for c in 0..3 {
+                assert!(
+                    got[c].abs_diff(want[c]) as u32 <= tolerance,
+                    "{got:?} {want:?}"
+                );
+            }
+        }
+        let back =
+            Image::from_raw(&straight, res, PixelFormat::Rgba8888, ColorSpace::sRGB).unwrap();
+        assert_eq!(
+            back.to_raw_with_alpha(PixelFormat::Rgba8888, AlphaMode::Premultiplied),
+            premul
+        );
+    }
+
+    #[test]
+    fn premultiplied_import_transparent() {
+        // Not valid premultiplied data, but it happens
+        let data = [0, 0, 0, 0, 255, 40, 7, 0];
+        let mut img = Image::from_raw_with_alpha(
+            &data,
+            (2, 1),
+            PixelFormat::Rgba8888,
+            ColorSpace::sRGB,
+            AlphaMode::Premultiplied,
+        )
+        .unwrap();
+        img.to_alpha_mode(AlphaMode::Straight);
+        assert!(img.pixels().iter().flatten().all(|c| c.is_finite()));
+        assert_eq!(img.to_raw(PixelFormat::Rgba8888), [0; 8]);
+        assert_eq!(
+            img.to_raw_with_alpha(PixelFormat::Rgba8888, AlphaMode::Premultiplied),
+            [0; 8]
+        );
+    }
+
+    /// Tightly packed `data` with rows of `row` bytes laid out `stride`
+    /// apart in `order`, padded with `0xee`
+    fn restride(data: &[u8], row: usize, stride: usize, order: RowOrder) -> Vec<u8> {
+        let mut rows: Vec<&[u8]> = data.chunks_exact(row).collect();
+        if order == RowOrder::BottomUp {
+            rows.reverse();
+        }
+        let mut out = Vec::new();
+        for r in rows {
+            out.extend_from_slice(r);
+            out.resize(out.len() + stride - row, 0xee);
+        }
+        out.truncate(out.len() - (stride - row));
+        out
+    }
+
+    #[test]
+    fn bottom_up_import() {
+        let img = image((5, 4));
+        for format in [
+            PixelFormat::Rgba8888,
+            PixelFormat::Rgb565Le,
+            PixelFormat::Bgr888,
+        ] {
+            let top_down = img.to_raw(format);
+            let row = format.row_bytes(5).unwrap();
+            for stride in [row, row + 5] {
+                let bottom_up = restride(&top_down, row, stride, RowOrder::BottomUp);
+                let got = Image::from_raw_with_stride(
+                    &bottom_up,
+                    (5, 4),
+                    format,
+                    ColorSpace::sRGB,
+                    stride,
+                    RowOrder::BottomUp,
+                )
+                .unwrap();
+                let want = Image::from_raw(&top_down, (5, 4), format, ColorSpace::sRGB).unwrap();
+                assert_eq!(got.pixels(), want.pixels(), "{format:?} {stride}");
+            }
+        }
+        assert_eq!(RowOrder::BottomUp.buffer_row(0, 5), 4);
+        assert_eq!(RowOrder::TopDown.buffer_row(1, 5), 1);
+    }
+
+    #[test]
+    fn bottom_up_export() {
+        let img = image((5, 4));
+        let format = PixelFormat::Rgba8888;
+        let (row, stride) = (20, 27);
+        for order in [RowOrder::TopDown, RowOrder::BottomUp] {
+            let mut out = vec![0xee; stride * 3 + row];
+            img.write_raw(&mut out, format, stride, order).unwrap();
+            let want = restride(&img.to_raw(format), row, stride, order);
+            assert_eq!(out, want, "{order:?}");
+            let back =
+                Image::from_raw_with_stride(&out, (5, 4), format, ColorSpace::sRGB, stride, order)
+                    .unwrap();
+            assert_eq!(back.pixels(), img.pixels());
+        }
+    }
+
+    #[test]
+    fn stride_errors() {
+        let img = image((5, 4));
+        let data = vec![0; 100];
+        let read = |stride| {
+            Image::from_raw_with_stride(
+                &data,
+                (5, 4),
+                PixelFormat::Rgba8888,
+                ColorSpace::sRGB,
+                stride,
+                RowOrder::BottomUp,
+            )
+            .err()
+        };
+        assert_eq!(read(19), Some(ImageError::InvalidArgument));
+        assert_eq!(
+            read(30),
+            Some(ImageError::BufferSize {
+                expected: 110,
+                actual: 100
+            })
+        );
+        let mut out = vec![0; 79];
+        assert_eq!(
+            img.write_raw(&mut out, PixelFormat::Rgba8888, 20, RowOrder::BottomUp),
+            Err(ImageError::BufferSize {
+                expected: 80,
+                actual: 79
+            })
+        );
+        assert_eq!(
+            img.write_raw(&mut out, PixelFormat::Rgba8888, 16, RowOrder::TopDown),
+            Err(ImageError::InvalidArgument)
+        );
+    }
+
+    /// 16-bit gray and alpha little endian pairs, covering the extremes
+    fn la16_data(pixels: u32) -> Vec<u8> {
+        let mut seed = 5;
+        (0..pixels * 2)
+            .map(|i| match i {
+                0 => 0,
+                1 => 65535,
+                _ => (crate::fixtures::noise(&mut seed) * 65535.) as u16,
+            })
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    }
+
+    #[test]
+    fn la16_round_trips() {
+        let le = la16_data(35);
+        let be: Vec<u8> = le.chunks_exact(2).flat_map(|b| [b[1], b[0]]).collect();
+        for color in [ColorSpace::sRGB, ColorSpace::sRGBLinear] {
+            for (endian, bytes) in [(Endian::Little, &le), (Endian::Big, &be)] {
+                let img = Image::from_la16_bytes(bytes, (7, 5), color, endian).unwrap();
+                assert_eq!(img.to_la16_bytes(endian), *bytes, "{color:?} {endian:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn la16_endianness() {
+        let img = image((6, 3));
+        let le = img.to_la16_bytes(Endian::Little);
+        let be = img.to_la16_bytes(Endian::Big);
+        assert_eq!(le.len(), 6 * 3 * 4);
+        for (l, b) in le.chunks_exact(2).zip(be.chunks_exact(2)) {
+            assert_eq!([l[0], l[1]], [b[1], b[0]]);
+        }
+        // The same image either way
+        let a = Image::from_la16_bytes(&le, (6, 3), ColorSpace::sRGB, Endian::Little).unwrap();
+        let b = Image::from_la16_bytes(&be, (6, 3), ColorSpace::sRGB, Endian::Big).unwrap();
+        assert_eq!(a.pixels(), b.pixels());
+    }
+
+    #[test]
+    fn la16_luma() {
+        let img = crate::fixtures::photo((9, 7));
+        let (decode, encode) = img.color.transfer().unwrap();
+        let bytes = img.to_la16_bytes(Endian::Little);
+        for (p, b) in img.pixels().iter().zip(bytes.chunks_exact(4)) {
+            let y = encode(transforms::luminance([p[0], p[1], p[2]].map(decode)));
+            let want = [y, p[3]].map(|c| c.clamp(0., 1.) * 65535.);
+            let got = [
+                u16::from_le_bytes([b[0], b[1]]),
+                u16::from_le_bytes([b[2], b[3]]),
+            ];
+            for c in 0..2 {
+                assert!((got[c] as f32 - want[c]).abs() <= 1., "{got:?} {want:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn la16_size() {
+        for len in [0, 4 * 6 - 1, 4 * 6 + 2] {
+            assert_eq!(
+                Image::from_la16_bytes(&vec![0; len], (3, 2), ColorSpace::sRGB, Endian::Big).err(),
+                Some(ImageError::BufferSize {
+                    expected: 24,
+                    actual: len
+                })
+            );
+        }
+    }
 }
@@ -0,0 +1,88 @@
+//! A lock for the few globals, without std
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// `T` behind a lock that's tried, or spun on
+///
+/// Code that may run in interrupts should only ever try it, so it can't
+/// deadlock with the code it interrupted.
+pub(crate) struct TryLock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+// Safety: `value` is only touched while `locked` is held
+unsafe impl<T: Send> Sync for TryLock<T> {}
+
+/// Releases the lock when dropped, so it's released even if `f` panics
+struct Unlock<'a>(&'a AtomicBool);
+
+impl Drop for Unlock<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl<T> TryLock<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Run `f` with the value, or `None` if it's locked
+    pub(crate) fn try_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        let _unlock = Unlock(&self.locked);
+        // Safety: We hold the lock
+        Some(f(unsafe { &mut *self.value.get() }))
+    }
+
+    /// Run `f` with the value, spinning until it's unlocked
+    pub(crate) fn with<R>(&self, mut f: impl FnMut(&mut T) -> R) -> R {
+        loop {
+            if let Some(r) = self.try_with(&mut f) {
+                return r;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_while_held() {
+        let lock = TryLock::new(1);
+        let inner = lock.try_with(|v| {
+            *v += 1;
+            lock.try_with(|v| *v += 10)
+        });
+        assert_eq!(inner, Some(None));
+        assert_eq!(lock.with(|v| *v), 2);
+        assert_eq!(lock.try_with(|v| *v), Some(2));
+    }
+
+    #[test]
+    fn released_on_panic() {
+        extern crate std;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let lock = TryLock::new(1);
+        let r = catch_unwind(AssertUnwindSafe(|| {
+            lock.with(|v| {
+                *v += 1;
+                panic!("while locked")
+            })
+        }));
+        assert!(r.is_err());
+        assert_eq!(lock.try_with(|v| *v), Some(2));
+    }
+}
@@ -0,0 +1,495 @@
+//! Grayscale images
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    layout::{prepare, validate_exact},
+    scale::scale_buffer,
+    AlphaMode, ColorSpace, Image, ImageError, PixelFormat, ResXY, ScaleFilter, StatsMask, Transfer,
+    WorkPixel, F32,
+};
+
+/// Luma of `p`, computed in linear light and re-encoded with `transfer`
+pub(crate) fn pixel_luma(
+    p: WorkPixel,
+    alpha: AlphaMode,
+    transfer: Option<(Transfer, Transfer)>,
+) -> f32 {
+    prepare(p, PixelFormat::Gray8, alpha, transfer)[0]
+}
+
+/// Sobel gradient magnitude of `data`, with edges clamped
+pub(crate) fn sobel(data: &[f32], (width, height): ResXY) -> Vec<f32> {
+    let (w, h) = (width as i64, height as i64);
+    let v = |x: i64, y: i64| data[(y.clamp(0, h - 1) * w + x.clamp(0, w - 1)) as usize];
+    let mut out = Vec::with_capacity(data.len());
+    for y in 0..h {
+        for x in 0..w {
+            let gx = (v(x + 1, y - 1) + 2. * v(x + 1, y) + v(x + 1, y + 1))
+                - (v(x - 1, y - 1) + 2. * v(x - 1, y) + v(x - 1, y + 1));
+            let gy = (v(x - 1, y + 1) + 2. * v(x, y + 1) + v(x + 1, y + 1))
+                - (v(x - 1, y - 1) + 2. * v(x, y - 1) + v(x + 1, y - 1));
+            out.push((gx * gx + gy * gy).sqrt());
+        }
+    }
+    out
+}
+
+/// 256 bin histogram of `data`, clamped to `0..=1`
+pub(crate) fn histogram(data: impl Iterator<Item = f32>) -> [u32; 256] {
+    let mut bins = [0; 256];
+    for v in data {
+        bins[bin(v)] += 1;
+    }
+    bins
+}
+
+/// Bin of `v` in a [`histogram`]
+fn bin(v: f32) -> usize {
+    (v.clamp(0., 1.) * 255.).round() as usize
+}
+
+/// A grayscale image, without alpha
+///
+/// This takes a quarter of the memory of an [`Image`], for pipelines that
+/// are grayscale anyway.
+#[derive(Debug, Clone)]
+pub struct LumaImage {
+    data: Vec<f32>,
+    res: ResXY,
+    color: ColorSpace,
+}
+
+impl LumaImage {
+    /// Read a LumaImage from 8 bit grayscale data of length `width * height`
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::BufferSize`] if `data` is the wrong size
+    pub fn from_gray8(data: &[u8], res: ResXY, color: ColorSpace) -> Result<Self, ImageError> {
+        validate_exact(data, PixelFormat::Gray8, res)?;
+        let data = data.iter().map(|c| *c as f32 / 255.).collect();
+        Ok(Self { data, res, color })
+    }
+
+    /// Luma of `img`, computed in linear light and kept in its color space
+    ///
+    /// Alpha is dropped.
+    pub fn from_image(img: &Image) -> Self {
+        let transfer = img.color.transfer();
+        let data = img
+            .data
+            .iter()
+            .map(|p| pixel_luma(*p, img.alpha, transfer))
+            .collect();
+        Self {
+            data,
+            res: img.res,
+            color: img.color,
+        }
+    }
+
+    /// Convert back to a gray, opaque, [`Image`]
+    pub fn to_image(&self) -> Image {
+        let data = self.data.iter().map(|v| [*v, *v, *v, 1.]).collect();
+        Image::from_parts(data, self.res, self.color)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.res.0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.res.1
+    }
+
+    pub fn color(&self) -> ColorSpace {
+        self.color
+    }
+
+    pub fn pixels(&self) -> &[f32] {
+        &self.data
+    }
+
+    /// Heap memory used by the pixel data, in bytes
+    pub fn byte_size(&self) -> usize {
+        self.data.capacity() * size_of::<f32>()
+    }
+
+    /// Scale the image to `new` using `filter`, see [`Image::scale_with`]
+    ///
+    /// # Panics
+    ///
+    /// - If `new` is zero in either dimension
+    pub fn scale_with(&mut self, new: ResXY, filter: ScaleFilter) {
+        assert!(new.0 > 0 && new.1 > 0, "Cannot scale to zero");
+        scale_buffer(&mut self.data, self.res, new, filter);
+        self.res = new;
+    }
+
+    /// Binarize, values of at least `t` become `1`, others `0`
+    pub fn threshold(&mut self, t: f32) {
+        for v in &mut self.data {
+            *v = if *v >= t { 1. } else { 0. };
+        }
+    }
+
+    /// Sobel edge detection, returning the gradient magnitude
+    pub fn sobel(&self) -> LumaImage {
+        Self {
+            data: sobel(&self.data, self.res),
+            res: self.res,
+            color: ColorSpace::AsIs,
+        }
+    }
+
+    /// 256 bin histogram
+    pub fn histogram(&self) -> [u32; 256] {
+        histogram(self.data.iter().copied())
+    }
+}
+
+impl Image {
+    /// Binarize by luma, see [`LumaImage::threshold`]
+    ///
+    /// Color becomes black or white, alpha is untouched.
+    pub fn threshold(&mut self, t: f32) {
+        let transfer = self.color.transfer();
+        let alpha = self.alpha;
+        for p in &mut self.data {
+            let v = if pixel_luma(*p, alpha, transfer) >= t {
+                1.
+            } else {
+                0.
+            };
+            *p = match alpha {
+                AlphaMode::Straight => [v, v, v, p[3]],
+                AlphaMode::Premultiplied => [v * p[3], v * p[3], v * p[3], p[3]],
+            };
+        }
+    }
+
+    /// Sobel edge detection on luma, see [`LumaImage::sobel`]
+    pub fn sobel(&self) -> Image {
+        LumaImage::from_image(self).sobel().to_image()
+    }
+
+    /// 256 bin histogram of each channel, as stored
+    pub fn histogram(&self) -> [[u32; 256]; 4] {
+        let mut bins = [[0; 256]; 4];
+        for (c, bins) in bins.iter_mut().enumerate() {
+            *bins = histogram(self.data.iter().map(|p| p[c]));
+        }
+        bins
+    }
+
+    /// Like [`Image::histogram`], with each pixel adding its weight in
+    /// `mask` to its bins, rather than 1
+    ///
+    /// A mask that's zero everywhere gives empty bins.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if a rectangle is empty
+    /// - [`ImageError::OutOfBounds`] if a rectangle isn't inside the image
+    /// - [`ImageError::DimensionMismatch`] if a mask image is a different
+    ///   size
+    pub fn histogram_masked(&self, mask: StatsMask) -> Result<[[f32; 256]; 4], ImageError> {
+        let weights = self.mask_weights(mask)?;
+        let mut bins = [[0.; 256]; 4];
+        for (p, w) in self.data.iter().zip(weights).filter(|(_, w)| *w > 0.) {
+            for (bins, c) in bins.iter_mut().zip(p) {
+                bins[bin(*c)] += w;
+            }
+        }
+        Ok(bins)
+    }
+
+    /// Global threshold for [`Image::threshold`] by Otsu's method
+    ///
+    /// This picks the level that best splits a 256 bin histogram of linear
+    /// luminance into two classes, maximizing the variance between them. The
+    /// result is re-encoded to the same scale [`Image::threshold`] uses.
+    ///
+    /// If every pixel has the same luma there's nothing to split, and this
+    /// returns `0.5`.
+    pub fn otsu_threshold(&self) -> f32 {
+        let transfer = self.color.transfer();
+        let linear = self.data.iter().map(|p| {
+            let v = pixel_luma(*p, self.alpha, transfer);
+            transfer.map_or(v, |(decode, _)| decode(v))
+        });
+        let bins = histogram(linear);
+        let total: f64 = bins.iter().map(|n| *n as f64).sum();
+        let sum: f64 = bins
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (i as u64 * *n as u64) as f64)
+            .sum();
+
+        let (mut best, mut best_var) = (None, 0.);
+        let (mut n0, mut sum0) = (0., 0.);
+        for (k, n) in bins.iter().enumerate().take(255) {
+            n0 += *n as f64;
+            sum0 += (k as u64 * *n as u64) as f64;
+            let n1 = total - n0;
+            if n0 == 0. || n1 == 0. {
+                continue;
+            }
+            let (m0, m1) = (sum0 / n0, (sum - sum0) / n1);
+            let var = n0 * n1 * (m0 - m1) * (m0 - m1);
+            if var > best_var {
+                (best, best_var) = (Some((k, k)), var);
+            } else if var == best_var {
+                // Empty bins between the classes tie, take the middle
+                best = best.map(|(lo, _)| (lo, k));
+            }
+        }
+        match best {
+            // After bin `k`
+            Some((lo, hi)) => {
+                let t = ((lo + hi) as f32 / 2. + 0.5) / 255.;
+                transfer.map_or(t, |(_, encode)| encode(t))
+            }
+            None => 0.5,
+        }
+    }
+
+    /// Binarize with [`Image::otsu_threshold`]
+    pub fn threshold_otsu(&mut self) {
+        self.threshold(self.otsu_threshold())
+    }
+
+    /// Binarize each pixel against the mean luma of the `window` sized
+    /// square around it, minus `c`
+    ///
+    /// This copes with uneven lighting, like a phone photo of a page, where
+    /// one global threshold can't. Luma is on the same scale as
+    /// [`Image::threshold`], and the window is clipped at the edges. A
+    /// positive `c` keeps faint noise in flat areas white.
+    pub fn adaptive_threshold(&mut self, window: u32, c: f32) {
+        let (w, h) = (self.width() as usize, self.height() as usize);
+        let transfer = self.color.transfer();
+        let alpha = self.alpha;
+        let luma: Vec<f32> = self
+            .data
+            .iter()
+            .map(|p| pixel_luma(*p, alpha, transfer))
+            .collect();
+        // Summed area table, with a zero row and column in front
+        let mut sat = vec![0f64; (w + 1) * (h + 1)];
+        for y in 0..h {
+            let mut row = 0.;
+            for x in 0..w {
+                row += luma[y * w + x] as f64;
+                sat[(y + 1) * (w + 1) + x + 1] = sat[y * (w + 1) + x + 1] + row;
+            }
+        }
+        let r = window.max(1) as usize / 2;
+        for y in 0..h {
+            let (y0, y1) = (y.saturating_sub(r), (y + r + 1).min(h));
+            for x in 0..w {
+                let (x0, x1) = (x.saturating_sub(r), (x + r + 1).min(w));
+                let s = |x: usize, y: usize| sat[y * (w + 1) + x];
+                let area = ((x1 - x0) * (y1 - y0)) as f64;
+                let mean = (s(x1, y1) - s(x0, y1) - s(x1, y0) + s(x0, y0)) / area;
+                let v = if luma[y * w + x] >= mean as f32 - c {
+                    1.
+                } else {
+                    0.
+                };
+                let p = &mut self.data[y * w + x];
+                *p = match alpha {
+                    AlphaMode::Straight => [v, v, v, p[3]],
+                    AlphaMode::Premultiplied => [v * p[3], v * p[3], v * p[3], p[3]],
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{noise, photo, solid},
+        ColorSpace,
+    };
+
+    /// A gray document, smooth with some detail
+    fn gray8(res: ResXY) -> Vec<u8> {
+        photo(res).to_raw(PixelFormat::Gray8)
+    }
+
+    #[test]
+    fn quarter_the_memory() {
+        let data = gray8((32, 24));
+        let luma = LumaImage::from_gray8(&data, (32, 24), ColorSpace::sRGB).unwrap();
+        let rgba = Image::from_raw(&data, (32, 24), PixelFormat::Gray8, ColorSpace::sRGB).unwrap();
+        assert_eq!(luma.byte_size() * 4, rgba.byte_size());
+        assert_eq!(
+            LumaImage::from_gray8(&data[1..], (32, 24), ColorSpace::sRGB).err(),
+            Some(ImageError::BufferSize {
+                expected: 32 * 24,
+                actual: 32 * 24 - 1
+            })
+        );
+    }
+
+    #[test]
+    fn pipeline_matches_rgba() {
+        let data = gray8((40, 30));
+        for filter in [ScaleFilter::Box, ScaleFilter::Bilinear] {
+            let mut luma = LumaImage::from_gray8(&data, (40, 30), ColorSpace::sRGB).unwrap();
+            let mut rgba =
+                Image::from_raw(&data, (40, 30), PixelFormat::Gray8, ColorSpace::sRGB).unwrap();
+            luma.scale_with((13, 11), filter);
+            rgba.scale_with((13, 11), filter);
+            let back = LumaImage::from_image(&rgba);
+            for (a, b) in luma.pixels().iter().zip(back.pixels()) {
+                assert!((a - b).abs() < 1e-5, "{a} {b}");
+            }
+            // Away from the threshold, so epsilon can't flip a pixel
+            let t = 0.5;
+            assert!(luma.pixels().iter().all(|v| (v - t).abs() > 1e-4));
+            luma.threshold(t);
+            rgba.threshold(t);
+            for (a, p) in luma.pixels().iter().zip(rgba.pixels()) {
+                assert_eq!([*a; 3], [p[0], p[1], p[2]]);
+                assert!((p[3] - 1.).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn operations_match_rgba() {
+        let data = gray8((17, 9));
+        let luma = LumaImage::from_gray8(&data, (17, 9), ColorSpace::sRGB).unwrap();
+        let rgba = Image::from_raw(&data, (17, 9), PixelFormat::Gray8, ColorSpace::sRGB).unwrap();
+        for (a, p) in luma.sobel().pixels().iter().zip(rgba.sobel().pixels()) {
+            assert!((a - p[0]).abs() < 1e-4, "{a} {p:?}");
+        }
+        assert_eq!(luma.histogram(), rgba.histogram()[0]);
+        let image = luma.to_image();
+        assert_eq!(image.res, (17, 9));
+        for (a, p) in luma.pixels().iter().zip(image.pixels()) {
+            assert_eq!(*p, [*a, *a, *a, 1.]);
+        }
+    }
+
+    #[test]
+    fn scale_empty() {
+        let img = Image::from_bytes(&[], (0, 5), ColorSpace::sRGB);
+        let mut luma = LumaImage::from_image(&img);
+        luma.scale_with((3, 4), ScaleFilter::Box);
+        assert_eq!(luma.res, (3, 4));
+    }
+
+    /// Gray `v(x, y)`, in sRGB
+    fn gray(res: ResXY, mut v: impl FnMut(u32, u32) -> f32) -> Image {
+        let mut img = solid(res, [0.; 4]);
+        img.map_pixels_indexed(|(x, y), _| {
+            let v = v(x, y);
+            [v, v, v, 1.]
+        });
+        img
+    }
+
+    #[test]
+    fn otsu_splits_modes() {
+        let mut seed = 5;
+        let bright = |x: u32, y: u32| (x / 4 + y / 3).is_multiple_of(3);
+        let mut img = gray((32, 24), |x, y| {
+            let n = (noise(&mut seed) - 0.5) * 0.06;
+            if bright(x, y) {
+                0.7 + n
+            } else {
+                0.2 + n
+            }
+        });
+        let t = img.otsu_threshold();
+        assert!((0.24..0.66).contains(&t), "{t}");
+        img.threshold_otsu();
+        for (i, p) in img.pixels().iter().enumerate() {
+            let on = bright(i as u32 % 32, i as u32 / 32);
+            assert_eq!(p[0], if on { 1. } else { 0. });
+        }
+    }
+
+    #[test]
+    fn adaptive_handles_uneven_light() {
+        // Paper lit from the right, with dark text strokes
+        let (w, h) = (64, 32);
+        let ink = |x: u32, y: u32| x % 8 < 2 && y % 8 < 5;
+        let page = gray((w, h), |x, y| {
+            let light = 0.3 + 0.7 * x as f32 / (w - 1) as f32;
+            if ink(x, y) {
+                light * 0.4
+            } else {
+                light
+            }
+        });
+        let wrong = |img: &Image| {
+            img.pixels()
+                .iter()
+                .enumerate()
+                .filter(|(i, p)| (p[0] == 0.) != ink(*i as u32 % w, *i as u32 / w))
+                .count()
+        };
+
+        let mut adaptive = page.clone();
+        adaptive.adaptive_threshold(15, 0.05);
+        assert_eq!(wrong(&adaptive), 0);
+        let mut global = page.clone();
+        global.threshold_otsu();
+        assert!(wrong(&global) > (w * h / 10) as usize, "{}", wrong(&global));
+    }
+
+    #[test]
+    fn flat_images() {
+        for v in [0., 0.5, 1.] {
+            let mut img = gray((8, 8), |_, _| v);
+            assert_eq!(img.otsu_threshold(), 0.5);
+            img.threshold_otsu();
+            img.adaptive_threshold(5, 0.01);
+            assert!(img.pixels().iter().all(|p| *p == [1., 1., 1., 1.]));
+        }
+        let mut empty = solid((0, 0), [0.; 4]);
+        assert!(!empty.otsu_threshold().is_nan());
+        empty.threshold_otsu();
+        empty.adaptive_threshold(5, 0.);
+        let mut one = gray((1, 1), |_, _| 0.3);
+        one.adaptive_threshold(0, 0.);
+        assert_eq!(one.pixels(), [[1.; 4]]);
+    }
+
+    #[test]
+    fn histogram_masked_counts_weights() {
+        let img = photo((16, 12));
+        let mut crop = img.clone();
+        crop.crop((3, 2), (7, 5)).unwrap();
+        let masked = img
+            .histogram_masked(StatsMask::Rect((3, 2), (7, 5)))
+            .unwrap();
+        for (got, want) in masked.iter().zip(crop.histogram()) {
+            for (g, w) in got.iter().zip(want) {
+                assert_eq!(*g, w as f32);
+            }
+        }
+        // Half weight everywhere is half of every bin
+        let mut half = solid(img.res, [0.5, 0.5, 0.5, 1.]);
+        half.color = ColorSpace::sRGBLinear;
+        let masked = img.histogram_masked(StatsMask::Image(&half)).unwrap();
+        for (got, want) in masked.iter().zip(img.histogram()) {
+            for (g, w) in got.iter().zip(want) {
+                assert!((g - w as f32 / 2.).abs() < 1e-4, "{g} {w}");
+            }
+        }
+        let none = solid(img.res, [0.; 4]);
+        let masked = img.histogram_masked(StatsMask::Image(&none)).unwrap();
+        assert!(masked.iter().flatten().all(|b| *b == 0.));
+        assert_eq!(
+            img.histogram_masked(StatsMask::Rect((0, 0), (17, 1))).err(),
+            Some(ImageError::OutOfBounds)
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Image metadata
+use alloc::{string::String, vec::Vec};
+
+use crate::Image;
+
+/// Extra information that travels with an [`Image`]
+///
+/// This is carried through operations that modify an image or derive a new
+/// one from it, like cropping, scaling, and color conversion. Nothing here
+/// affects the pixels.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    /// Horizontal and vertical resolution, in dots per inch
+    pub dpi: Option<(f32, f32)>,
+
+    /// Encoding gamma, if the source recorded one
+    pub gamma: Option<f32>,
+
+    /// Arbitrary key value pairs, like a source asset identifier
+    pub tags: Vec<(String, String)>,
+
+    /// How the frame was captured, for images from a camera
+    pub capture: Option<CaptureInfo>,
+}
+
+/// How a camera frame was captured, see [`Metadata::capture`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CaptureInfo {
+    /// When the frame was captured, in microseconds since any fixed point,
+    /// like boot
+    pub timestamp_us: u64,
+
+    /// Exposure time, in microseconds
+    pub exposure_us: u32,
+
+    /// Sensor analog gain, 1 for none
+    pub analog_gain: f32,
+
+    /// Sensor temperature, in degrees Celsius, if known
+    pub temperature_c: Option<f32>,
+}
+
+impl Metadata {
+    /// Value of the first tag named `key`
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Set the tag `key` to `value`, replacing any existing one
+    pub fn set_tag(&mut self, key: &str, value: &str) {
+        match self.tags.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.into(),
+            None => self.tags.push((key.into(), value.into())),
+        }
+    }
+}
+
+impl Image {
+    pub fn metadata(&self) -> &Metadata {
+        &self.meta
+    }
+
+    pub fn metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.meta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::photo, ColorSpace, ScaleFilter};
+
+    fn tagged() -> Metadata {
+        let mut meta = Metadata {
+            dpi: Some((300., 150.)),
+            gamma: Some(2.2),
+            capture: Some(CaptureInfo {
+                timestamp_us: 1_234_567,
+                exposure_us: 8_000,
+                analog_gain: 2.5,
+                temperature_c: Some(41.5),
+            }),
+            ..Default::default()
+        };
+        meta.set_tag("source", "icons/wifi.svg");
+        meta.set_tag("build", "7");
+        meta
+    }
+
+    #[test]
+    fn tags() {
+        let mut meta = tagged();
+        assert_eq!(meta.tag("source"), Some("icons/wifi.svg"));
+        assert_eq!(meta.tag("missing"), None);
+        meta.set_tag("build", "8");
+        assert_eq!(meta.tag("build"), Some("8"));
+        assert_eq!(meta.tags.len(), 2);
+    }
+
+    #[test]
+    fn survives_pipeline() {
+        let mut img = photo((40, 30));
+        *img.metadata_mut() = tagged();
+        img.scale_with((20, 15), ScaleFilter::Box);
+        img.to_color(ColorSpace::DisplayP3);
+        img.crop((2, 2), (10, 10)).unwrap();
+        let img = img.resize((5, 5));
+        let mut linear = img.clone();
+        linear.to_color(ColorSpace::sRGBLinear);
+        let view = linear.viewport(((0.5, 0.5), (3., 3.)), (2, 2), ScaleFilter::Bilinear);
+        for img in [&img, &linear, &view] {
+            assert_eq!(img.metadata(), &tagged());
+        }
+    }
+}
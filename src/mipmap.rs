@@ -0,0 +1,194 @@
+//! Mipmap chains
+use alloc::{vec, vec::Vec};
+
+use crate::{Image, ResXY, F32};
+
+/// How [`Image::generate_mipmaps`] treats alpha
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AlphaHandling {
+    /// Filter premultiplied, like [`Image::resize`], so transparent pixels
+    /// add no color
+    #[default]
+    Premultiplied,
+
+    /// [`AlphaHandling::Premultiplied`], then scale each level's alpha so
+    /// the fraction of pixels above `threshold` matches the first level
+    ///
+    /// Alpha tested textures like foliage otherwise thin out and vanish in
+    /// the smaller levels, as averaging pulls alpha below the threshold.
+    PreserveCoverage { threshold: f32 },
+}
+
+/// Size of the mip level after `res`
+fn next_level((w, h): ResXY) -> ResXY {
+    ((w / 2).max(1), (h / 2).max(1))
+}
+
+impl Image {
+    /// Mipmap chain of this image, from itself down to 1x1
+    ///
+    /// Each level is half the size of the one before, rounding down but at
+    /// least 1, as GPUs expect, and is [`Image::resize`]d from it. Empty
+    /// images have only themselves.
+    pub fn generate_mipmaps(&self, alpha: AlphaHandling) -> Vec<Image> {
+        let mut levels = vec![self.clone()];
+        if self.data.is_empty() {
+            return levels;
+        }
+        // Every level comes from the one before it as filtered, so coverage
+        // corrections don't compound
+        let mut filtered = self.clone();
+        while filtered.res != (1, 1) {
+            filtered = filtered.resize(next_level(filtered.res));
+            let mut level = filtered.clone();
+            if let AlphaHandling::PreserveCoverage { threshold } = alpha {
+                level.match_coverage(threshold, self.alpha_coverage(threshold));
+            }
+            levels.push(level);
+        }
+        levels
+    }
+
+    /// Scale alpha so [`Image::alpha_coverage`] at `threshold` is as close
+    /// to `target` as it can be
+    fn match_coverage(&mut self, threshold: f32, target: f32) {
+        let mut alphas: Vec<f32> = self.data.iter().map(|p| p[3]).collect();
+        let n = alphas.len();
+        let k = (target * n as f32).round() as usize;
+        alphas.sort_unstable_by(|a, b| b.total_cmp(a));
+        // The threshold can go between any two different alphas, pick the
+        // split with the closest count above it
+        let splits = (0..=n).filter(|j| match *j {
+            0 => true,
+            j => alphas[j - 1] > 0. && alphas.get(j).is_none_or(|a| *a < alphas[j - 1]),
+        });
+        let Some(j) = splits.min_by_key(|j| j.abs_diff(k)) else {
+            return;
+        };
+        let s = match j {
+            0 => threshold / alphas[0],
+            _ => 2. * threshold / (alphas[j - 1] + alphas.get(j).copied().unwrap_or(0.)),
+        };
+        if s.is_finite() && s > 0. {
+            self.scale_alpha(s);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{noise, solid},
+        AlphaMode, WorkPixel,
+    };
+
+    const THRESHOLD: f32 = 0.5;
+
+    /// Thin anti-aliased leaves on nothing, like a foliage texture
+    fn foliage() -> Image {
+        let mut seed = 3;
+        let mut img = solid((64, 64), [0.; 4]);
+        img.map_pixels_indexed(|(x, y), _| {
+            let (u, v) = (x as f32, y as f32);
+            let leaf = (u * 0.9 + (v * 0.3).sin() * 2.).sin() * (v * 0.7).cos();
+            let a = ((leaf + noise(&mut seed) * 0.3 - 0.35) * 3.).clamp(0., 1.);
+            [0.1, 0.4 + u / 160., 0.1, a]
+        });
+        img
+    }
+
+    #[test]
+    fn levels_halve() {
+        let img = solid((13, 6), [1.; 4]);
+        let sizes: Vec<ResXY> = img
+            .generate_mipmaps(AlphaHandling::Premultiplied)
+            .iter()
+            .map(|l| l.res)
+            .collect();
+        assert_eq!(sizes, [(13, 6), (6, 3), (3, 1), (1, 1)]);
+        let empty = Image::from_bytes(&[], (0, 0), crate::ColorSpace::sRGB);
+        assert_eq!(
+            empty.generate_mipmaps(AlphaHandling::Premultiplied).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn coverage_is_preserved() {
+        let img = foliage();
+        let base = img.alpha_coverage(THRESHOLD);
+        assert!((0.2..0.6).contains(&base), "{base}");
+        let plain = img.generate_mipmaps(AlphaHandling::Premultiplied);
+        let kept = img.generate_mipmaps(AlphaHandling::PreserveCoverage {
+            threshold: THRESHOLD,
+        });
+        assert_eq!(kept.len(), 7);
+        for level in &kept[1..] {
+            // Small levels can only get to the nearest pixel
+            let n = level.data.len() as f32;
+            let coverage = level.alpha_coverage(THRESHOLD);
+            assert!(
+                (coverage - base).abs() <= (0.02f32).max(0.5 / n + 1e-6),
+                "{:?} {coverage} {base}",
+                level.res
+            );
+        }
+        // Without it, the leaves thin out
+        let thinned = plain[3].alpha_coverage(THRESHOLD);
+        assert!((thinned - base).abs() > 0.05, "{thinned} {base}");
+        // Colors are unchanged
+        for (a, b) in kept[2].pixels().iter().zip(plain[2].pixels()) {
+            if a[3] > 0. && a[3] < 1. {
+                assert!((0..3).all(|c| (a[c] - b[c]).abs() < 1e-5));
+            }
+        }
+    }
+
+    #[test]
+    fn shared_with_resize() {
+        let mut img = foliage();
+        for alpha in [AlphaMode::Straight, AlphaMode::Premultiplied] {
+            img.to_alpha_mode(alpha);
+            let levels = img.generate_mipmaps(AlphaHandling::Premultiplied);
+            let resized = img.resize((32, 32));
+            assert_eq!(levels[1].pixels(), resized.pixels());
+            assert_eq!(levels[1].alpha, alpha);
+        }
+    }
+
+    #[test]
+    fn transparent_adds_no_color() {
+        // Opaque red, next to fully transparent green
+        let mut img = solid((16, 16), [1., 0., 0., 1.]);
+        img.map_pixels_indexed(|(x, y), p| {
+            if (x + y) % 3 == 0 {
+                [0., 1., 0., 0.]
+            } else {
+                p
+            }
+        });
+        for alpha in [
+            AlphaHandling::Premultiplied,
+            AlphaHandling::PreserveCoverage { threshold: 0.5 },
+        ] {
+            for level in img.generate_mipmaps(alpha) {
+                for p in level.pixels().iter().filter(|p| p[3] > 0.) {
+                    assert!(p[0] > 0.999 && p[1] < 1e-6, "{p:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn alpha_coverage_counts() {
+        let mut img = solid((4, 1), [1.; 4]);
+        let alphas = [0., 0.5, 0.6, 1.];
+        img.map_pixels_indexed(|(x, _), p| [p[0], p[1], p[2], alphas[x as usize]]);
+        assert_eq!(img.alpha_coverage(0.5), 0.5);
+        assert_eq!(img.alpha_coverage(0.), 0.75);
+        assert_eq!(img.alpha_coverage(1.), 0.);
+        let empty: Image = solid((0, 0), WorkPixel::default());
+        assert_eq!(empty.alpha_coverage(0.5), 0.);
+    }
+}
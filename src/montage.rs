@@ -0,0 +1,266 @@
+//! Joining images side by side and in grids
+use alloc::{vec, vec::Vec};
+
+use crate::{alpha_converter, AlphaMode, ColorSpace, Image, ImageError, ResXY, WorkPixel, XY};
+
+/// The color space shared by all of `images`
+fn shared_color(images: &[&Image]) -> Result<ColorSpace, ImageError> {
+    let color = images.first().ok_or(ImageError::InvalidArgument)?.color;
+    if images.iter().any(|i| i.color != color) {
+        return Err(ImageError::ColorSpaceMismatch);
+    }
+    Ok(color)
+}
+
+/// Copy `img` into `data`, which is `width` pixels wide, at `(x0, y0)`, as
+/// straight alpha
+fn place(data: &mut [WorkPixel], width: u32, img: &Image, (x0, y0): XY) {
+    let convert = alpha_converter(img.alpha, AlphaMode::Straight);
+    for (y, row) in img
+        .data
+        .chunks_exact(img.width().max(1) as usize)
+        .enumerate()
+    {
+        let start = (y0 as usize + y) * width as usize + x0 as usize;
+        for (o, p) in data[start..start + row.len()].iter_mut().zip(row) {
+            *o = convert(*p);
+        }
+    }
+}
+
+/// `size` pixels, or an error if that's too many
+fn canvas(size: ResXY, background: WorkPixel) -> Result<Vec<WorkPixel>, ImageError> {
+    let n = (size.0 as usize)
+        .checked_mul(size.1 as usize)
+        .ok_or(ImageError::InvalidArgument)?;
+    Ok(vec![background; n])
+}
+
+impl Image {
+    /// Join `images` left to right
+    ///
+    /// The result has straight alpha, in the images' color space.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if there are no images, or the
+    ///   result is too large
+    /// - [`ImageError::DimensionMismatch`] if the heights differ
+    /// - [`ImageError::ColorSpaceMismatch`] if the color spaces differ
+    pub fn hconcat(images: &[&Image]) -> Result<Image, ImageError> {
+        let color = shared_color(images)?;
+        let height = images[0].height();
+        if images.iter().any(|i| i.height() != height) {
+            return Err(ImageError::DimensionMismatch);
+        }
+        let width = images
+            .iter()
+            .try_fold(0u32, |w, i| w.checked_add(i.width()))
+            .ok_or(ImageError::InvalidArgument)?;
+        let mut data = canvas((width, height), WorkPixel::default())?;
+        let mut x = 0;
+        for img in images {
+            place(&mut data, width, img, (x, 0));
+            x += img.width();
+        }
+        Ok(Image::from_parts(data, (width, height), color))
+    }
+
+    /// Join `images` top to bottom
+    ///
+    /// The result has straight alpha, in the images' color space.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if there are no images, or the
+    ///   result is too large
+    /// - [`ImageError::DimensionMismatch`] if the widths differ
+    /// - [`ImageError::ColorSpaceMismatch`] if the color spaces differ
+    pub fn vconcat(images: &[&Image]) -> Result<Image, ImageError> {
+        let color = shared_color(images)?;
+        let width = images[0].width();
+        if images.iter().any(|i| i.width() != width) {
+            return Err(ImageError::DimensionMismatch);
+        }
+        let height = images
+            .iter()
+            .try_fold(0u32, |h, i| h.checked_add(i.height()))
+            .ok_or(ImageError::InvalidArgument)?;
+        let mut data = canvas((width, height), WorkPixel::default())?;
+        let mut y = 0;
+        for img in images {
+            place(&mut data, width, img, (0, y));
+            y += img.height();
+        }
+        Ok(Image::from_parts(data, (width, height), color))
+    }
+
+    /// Lay `images` out in a grid `columns` wide, left to right then top to
+    /// bottom
+    ///
+    /// Every cell is the size of the largest image in each dimension, with
+    /// each image centered in its own, and `pad` pixels between and around
+    /// them. Everything else, including any unused cells in the last row, is
+    /// the straight alpha `background`. There are never more columns than
+    /// images. The result has straight alpha, in the images' color space.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if there are no images, `columns`
+    ///   is zero, or the result is too large
+    /// - [`ImageError::ColorSpaceMismatch`] if the color spaces differ
+    pub fn montage(
+        images: &[&Image],
+        columns: u32,
+        pad: u32,
+        background: impl Into<WorkPixel>,
+    ) -> Result<Image, ImageError> {
+        let background = background.into();
+        let color = shared_color(images)?;
+        if columns == 0 {
+            return Err(ImageError::InvalidArgument);
+        }
+        let n = u32::try_from(images.len()).map_err(|_| ImageError::InvalidArgument)?;
+        let cols = columns.min(n);
+        let rows = n.div_ceil(cols);
+        let cell = images
+            .iter()
+            .fold((0, 0), |(w, h), i| (w.max(i.width()), h.max(i.height())));
+        // `count` cells of `size` with `pad` between and around them
+        let span =
+            |count: u32, size: u32| count.checked_mul(size.checked_add(pad)?)?.checked_add(pad);
+        let res = span(cols, cell.0)
+            .zip(span(rows, cell.1))
+            .ok_or(ImageError::InvalidArgument)?;
+        let mut data = canvas(res, background)?;
+        for (i, img) in (0..).zip(images) {
+            let (col, row) = (i % cols, i / cols);
+            let x = pad + col * (cell.0 + pad) + (cell.0 - img.width()) / 2;
+            let y = pad + row * (cell.1 + pad) + (cell.1 - img.height()) / 2;
+            place(&mut data, res.0, img, (x, y));
+        }
+        Ok(Image::from_parts(data, res, color))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{ramp, solid};
+
+    const RED: WorkPixel = [1., 0., 0., 1.];
+    const BLUE: WorkPixel = [0., 0., 1., 1.];
+    const GRAY: WorkPixel = [0.5, 0.5, 0.5, 1.];
+
+    fn at(img: &Image, xy: XY) -> WorkPixel {
+        img.get_pixel(xy).unwrap()
+    }
+
+    #[test]
+    fn hconcat_places_pixels() {
+        let (a, b) = (ramp((3, 2)), ramp((2, 2)));
+        let joined = Image::hconcat(&[&a, &b, &a]).unwrap();
+        assert_eq!(joined.res, (8, 2));
+        for y in 0..2 {
+            for x in 0..3 {
+                assert_eq!(at(&joined, (x, y)), at(&a, (x, y)));
+                assert_eq!(at(&joined, (x + 5, y)), at(&a, (x, y)));
+            }
+            for x in 0..2 {
+                assert_eq!(at(&joined, (x + 3, y)), at(&b, (x, y)));
+            }
+        }
+    }
+
+    #[test]
+    fn vconcat_places_pixels() {
+        let (a, b) = (ramp((3, 1)), ramp((3, 2)));
+        let joined = Image::vconcat(&[&a, &b]).unwrap();
+        assert_eq!(joined.res, (3, 3));
+        for x in 0..3 {
+            assert_eq!(at(&joined, (x, 0)), at(&a, (x, 0)));
+            assert_eq!(at(&joined, (x, 1)), at(&b, (x, 0)));
+            assert_eq!(at(&joined, (x, 2)), at(&b, (x, 1)));
+        }
+    }
+
+    #[test]
+    fn concat_errors() {
+        let (a, b) = (solid((3, 2), RED), solid((2, 3), BLUE));
+        assert_eq!(
+            Image::hconcat(&[&a, &b]).err(),
+            Some(ImageError::DimensionMismatch)
+        );
+        assert_eq!(
+            Image::vconcat(&[&a, &b]).err(),
+            Some(ImageError::DimensionMismatch)
+        );
+        assert_eq!(Image::hconcat(&[]).err(), Some(ImageError::InvalidArgument));
+        assert_eq!(Image::vconcat(&[]).err(), Some(ImageError::InvalidArgument));
+        assert_eq!(
+            Image::montage(&[], 2, 0, GRAY).err(),
+            Some(ImageError::InvalidArgument)
+        );
+        assert_eq!(
+            Image::montage(&[&a], 0, 0, GRAY).err(),
+            Some(ImageError::InvalidArgument)
+        );
+        let mut linear = a.clone();
+        linear.to_color(ColorSpace::sRGBLinear);
+        assert_eq!(
+            Image::hconcat(&[&a, &linear]).err(),
+            Some(ImageError::ColorSpaceMismatch)
+        );
+    }
+
+    #[test]
+    fn concat_makes_straight_alpha() {
+        let mut a = solid((1, 1), [1., 0.5, 0., 0.5]);
+        a.to_alpha_mode(AlphaMode::Premultiplied);
+        let joined = Image::hconcat(&[&a, &a]).unwrap();
+        assert_eq!(joined.alpha, AlphaMode::Straight);
+        assert_eq!(at(&joined, (1, 0)), [1., 0.5, 0., 0.5]);
+    }
+
+    #[test]
+    fn montage_grid() {
+        let a = solid((4, 2), RED);
+        let b = solid((2, 4), BLUE);
+        let c = solid((2, 2), RED);
+        let grid = Image::montage(&[&a, &b, &c], 2, 1, GRAY).unwrap();
+        // 2x2 cells of 4x4, padded by 1
+        assert_eq!(grid.res, (11, 11));
+        let cell = |col: u32, row: u32| (1 + col * 5, 1 + row * 5);
+        for y in 0..11 {
+            for x in 0..11 {
+                let inside = |(cx, cy): XY, (w, h): ResXY| {
+                    let (x0, y0) = (cx + (4 - w) / 2, cy + (4 - h) / 2);
+                    (x0..x0 + w).contains(&x) && (y0..y0 + h).contains(&y)
+                };
+                let want = if inside(cell(0, 0), a.res) || inside(cell(0, 1), c.res) {
+                    RED
+                } else if inside(cell(1, 0), b.res) {
+                    BLUE
+                } else {
+                    GRAY
+                };
+                assert_eq!(at(&grid, (x, y)), want, "{x} {y}");
+            }
+        }
+        // The empty cell is all background
+        let (cx, cy) = cell(1, 1);
+        for y in cy..cy + 4 {
+            for x in cx..cx + 4 {
+                assert_eq!(at(&grid, (x, y)), GRAY);
+            }
+        }
+    }
+
+    #[test]
+    fn montage_columns_are_capped() {
+        let a = solid((2, 2), RED);
+        let grid = Image::montage(&[&a, &a], 5, 0, GRAY).unwrap();
+        assert_eq!(grid.res, (4, 2));
+        assert!(grid.pixels().iter().all(|p| *p == RED));
+    }
+}
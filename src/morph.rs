@@ -0,0 +1,244 @@
+//! Morphological operations
+use alloc::{vec, vec::Vec};
+
+use crate::{luma::pixel_luma, Image, ResXY, WorkPixel};
+
+/// What [`Image::erode`] and friends compare pixels by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MorphChannel {
+    /// Only the alpha channel, color is untouched
+    Alpha,
+
+    /// Luma, whole pixels are moved so RGB stays together
+    Luminance,
+}
+
+/// Min or max over a window of `2 * r + 1` along `line`, edges clamped
+///
+/// This is van Herk/Gil-Werman, a constant three `pick`s per element
+/// however big the window is.
+fn van_herk<T: Copy>(line: &[T], r: usize, pick: &impl Fn(T, T) -> T, out: &mut [T]) {
+    let (n, k) = (line.len(), 2 * r + 1);
+    let len = n + 2 * r;
+    let p = |i: usize| line[i.saturating_sub(r).min(n - 1)];
+    // Running pick from the start of each block, and from its end
+    let mut g = Vec::with_capacity(len);
+    for i in 0..len {
+        g.push(if i % k == 0 {
+            p(i)
+        } else {
+            pick(g[i - 1], p(i))
+        });
+    }
+    let mut h = vec![p(len - 1); len];
+    for i in (0..len - 1).rev() {
+        h[i] = if (i + 1) % k == 0 {
+            p(i)
+        } else {
+            pick(h[i + 1], p(i))
+        };
+    }
+    for (x, o) in out.iter_mut().enumerate() {
+        *o = pick(h[x], g[x + k - 1]);
+    }
+}
+
+/// Min or max over a `2 * r + 1` square of `data`, of `res`, in place
+fn min_max<T: Copy>(data: &mut [T], (w, h): ResXY, r: u32, pick: impl Fn(T, T) -> T) {
+    let (w, h, r) = (w as usize, h as usize, r as usize);
+    let mut line = Vec::with_capacity(w.max(h));
+    let mut out = Vec::with_capacity(h);
+    for row in data.chunks_exact_mut(w) {
+        line.clear();
+        line.extend_from_slice(row);
+        van_herk(&line, r, &pick, row);
+    }
+    for x in 0..w {
+        line.clear();
+        line.extend((0..h).map(|y| data[y * w + x]));
+        out.clear();
+        out.extend_from_slice(&line);
+        van_herk(&line, r, &pick, &mut out);
+        for (y, v) in out.iter().enumerate() {
+            data[y * w + x] = *v;
+        }
+    }
+}
+
+impl Image {
+    /// Min (`max` false) or max filter by `channel`
+    fn morph(&mut self, radius: u32, channel: MorphChannel, max: bool) {
+        if radius == 0 || self.data.is_empty() {
+            return;
+        }
+        let better = move |a: f32, b: f32| if max { a >= b } else { a <= b };
+        match channel {
+            MorphChannel::Alpha => {
+                let mut alpha: Vec<f32> = self.data.iter().map(|p| p[3]).collect();
+                min_max(&mut alpha, self.res, radius, |a, b| {
+                    if better(a, b) {
+                        a
+                    } else {
+                        b
+                    }
+                });
+                for (p, a) in self.data.iter_mut().zip(alpha) {
+                    p[3] = a;
+                }
+            }
+            MorphChannel::Luminance => {
+                let transfer = self.color.transfer();
+                let mut keyed: Vec<(f32, WorkPixel)> = self
+                    .data
+                    .iter()
+                    .map(|p| (pixel_luma(*p, self.alpha, transfer), *p))
+                    .collect();
+                min_max(&mut keyed, self.res, radius, |a, b| {
+                    if better(a.0, b.0) {
+                        a
+                    } else {
+                        b
+                    }
+                });
+                for (p, (_, k)) in self.data.iter_mut().zip(keyed) {
+                    *p = k;
+                }
+            }
+        }
+    }
+
+    /// Shrink bright areas, each pixel becomes the darkest, or least opaque,
+    /// in the square of `radius` around it
+    ///
+    /// Edges are clamped, and a `radius` of zero does nothing. The cost per
+    /// pixel is constant, whatever the radius.
+    pub fn erode(&mut self, radius: u32, channel: MorphChannel) {
+        self.morph(radius, channel, false)
+    }
+
+    /// Grow bright areas, the opposite of [`Image::erode`]
+    pub fn dilate(&mut self, radius: u32, channel: MorphChannel) {
+        self.morph(radius, channel, true)
+    }
+
+    /// Erode then dilate, removing bright specks smaller than `radius`
+    pub fn open(&mut self, radius: u32, channel: MorphChannel) {
+        self.erode(radius, channel);
+        self.dilate(radius, channel);
+    }
+
+    /// Dilate then erode, filling dark specks smaller than `radius`
+    pub fn close(&mut self, radius: u32, channel: MorphChannel) {
+        self.dilate(radius, channel);
+        self.erode(radius, channel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{noise, solid};
+
+    const BLACK: WorkPixel = [0., 0., 0., 1.];
+    const WHITE: WorkPixel = [1., 1., 1., 1.];
+
+    /// Black with the `white` pixels
+    fn specks(res: ResXY, white: impl Fn(u32, u32) -> bool) -> Image {
+        let mut img = solid(res, BLACK);
+        for (i, p) in img.data.iter_mut().enumerate() {
+            if white(i as u32 % res.0, i as u32 / res.0) {
+                *p = WHITE;
+            }
+        }
+        img
+    }
+
+    /// Every pixel of `img`, noise in color and alpha
+    fn noisy(res: ResXY) -> Image {
+        let mut seed = 3;
+        let data = (0..res.0 * res.1)
+            .map(|_| [(); 4].map(|_| noise(&mut seed)))
+            .collect();
+        solid((0, 0), BLACK).derive(data, res)
+    }
+
+    /// Naive min or max over the clamped window around each pixel, picking
+    /// the first best by `key`
+    fn reference(
+        img: &Image,
+        r: i64,
+        max: bool,
+        key: impl Fn(&WorkPixel) -> f32,
+    ) -> Vec<WorkPixel> {
+        let (w, h) = (img.res.0 as i64, img.res.1 as i64);
+        let mut out = Vec::new();
+        for y in 0..h {
+            for x in 0..w {
+                let mut best = img.data[(y * w + x) as usize];
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        let (sx, sy) = ((x + dx).clamp(0, w - 1), (y + dy).clamp(0, h - 1));
+                        let p = img.data[(sy * w + sx) as usize];
+                        if (key(&p) > key(&best)) == max && key(&p) != key(&best) {
+                            best = p;
+                        }
+                    }
+                }
+                out.push(best);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn dilate_single_pixel() {
+        let mut img = specks((7, 6), |x, y| (x, y) == (3, 2));
+        img.dilate(1, MorphChannel::Luminance);
+        let expected = specks((7, 6), |x, y| (2..=4).contains(&x) && (1..=3).contains(&y));
+        assert_eq!(img.pixels(), expected.pixels());
+    }
+
+    #[test]
+    fn open_removes_specks() {
+        let block = |x: u32, y: u32| (4..14).contains(&x) && (3..11).contains(&y);
+        let mut img = specks((20, 16), |x, y| block(x, y) || (x, y) == (17, 1));
+        img.open(1, MorphChannel::Luminance);
+        assert_eq!(img.pixels(), specks((20, 16), block).pixels());
+    }
+
+    #[test]
+    fn radius_zero() {
+        let img = noisy((5, 5));
+        let mut out = img.clone();
+        out.erode(0, MorphChannel::Alpha);
+        out.dilate(0, MorphChannel::Luminance);
+        assert_eq!(out.pixels(), img.pixels());
+    }
+
+    #[test]
+    fn matches_naive() {
+        for res in [(13, 7), (1, 9), (5, 1)] {
+            let img = noisy(res);
+            let luma = |p: &WorkPixel| pixel_luma(*p, img.alpha, img.color.transfer());
+            for r in [1, 2, 4, 10] {
+                for max in [false, true] {
+                    let mut out = img.clone();
+                    out.morph(r, MorphChannel::Luminance, max);
+                    assert_eq!(
+                        out.data,
+                        reference(&img, r as i64, max, luma),
+                        "{res:?} {r} {max}"
+                    );
+
+                    let mut out = img.clone();
+                    out.morph(r, MorphChannel::Alpha, max);
+                    let expected = reference(&img, r as i64, max, |p| p[3]);
+                    for ((o, e), p) in out.data.iter().zip(expected).zip(&img.data) {
+                        assert_eq!(o[..3], p[..3]);
+                        assert_eq!(o[3], e[3]);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Deterministic noise
+use alloc::{vec, vec::Vec};
+use core::f32::consts::TAU;
+
+use crate::{ColorSpace, Image, ResXY, F32};
+
+/// SplitMix64, small and good enough for noise, not for anything secure
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    const GOLDEN: u64 = 0x9e3779b97f4a7c15;
+
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// The output for state `z`
+    pub(crate) fn mix(mut z: u64) -> u64 {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// The `i`th output for `seed`, without stepping through the others
+    pub(crate) fn nth(seed: u64, i: u64) -> u64 {
+        Self::mix(seed.wrapping_add((i + 1).wrapping_mul(Self::GOLDEN)))
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(Self::GOLDEN);
+        Self::mix(self.0)
+    }
+
+    /// Uniform in `0..1`
+    pub(crate) fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Standard normal, using Box-Muller
+    pub(crate) fn next_gaussian(&mut self) -> f32 {
+        // `1 - u` is never zero
+        let u = 1. - self.next_f32();
+        let v = self.next_f32();
+        (-2. * u.ln()).sqrt() * (TAU * v).cos()
+    }
+}
+
+/// Gaussian energy in a `RADIUS` window around `(x, y)`, wrapping around
+/// the edges, added to `energy` scaled by `sign`
+fn splat(energy: &mut [f32], (w, h): ResXY, i: usize, sign: f32) {
+    const SIGMA: f32 = 1.5;
+    const RADIUS: i64 = 5;
+    let (w, h) = (w as i64, h as i64);
+    let (x, y) = (i as i64 % w, i as i64 / w);
+    for dy in -RADIUS..=RADIUS {
+        for dx in -RADIUS..=RADIUS {
+            let (nx, ny) = ((x + dx).rem_euclid(w), (y + dy).rem_euclid(h));
+            let e = (-((dx * dx + dy * dy) as f32) / (2. * SIGMA * SIGMA)).exp();
+            energy[(ny * w + nx) as usize] += e * sign;
+        }
+    }
+}
+
+/// Index of the set pixel with the most energy, the tightest cluster, or
+/// the unset one with the least, the largest void
+fn extreme(bits: &[bool], energy: &[f32], set: bool) -> usize {
+    let mut best = (usize::MAX, 0.);
+    for (i, (b, e)) in bits.iter().zip(energy).enumerate() {
+        if *b != set {
+            continue;
+        }
+        let better = if set { *e > best.1 } else { *e < best.1 };
+        if best.0 == usize::MAX || better {
+            best = (i, *e);
+        }
+    }
+    best.0
+}
+
+impl Image {
+    /// Gray image from per pixel values
+    fn gray_from(res: ResXY, f: impl FnMut(usize) -> f32) -> Image {
+        let len = res.0 as usize * res.1 as usize;
+        let data = (0..len).map(f).map(|v| [v, v, v, 1.]).collect();
+        Image::from_parts(data, res, ColorSpace::AsIs)
+    }
+
+    /// Gray uniform noise in `0..1`
+    ///
+    /// The same `seed` always gives the same image. Tagged
+    /// [`ColorSpace::AsIs`].
+    pub fn uniform_noise(res: ResXY, seed: u64) -> Image {
+        let mut rng = Rng::new(seed);
+        Self::gray_from(res, |_| rng.next_f32())
+    }
+
+    /// Gray Gaussian noise with `mean` and standard deviation `sigma`
+    ///
+    /// Values aren't clamped. The same `seed` always gives the same image.
+    /// Tagged [`ColorSpace::AsIs`].
+    pub fn gaussian_noise(res: ResXY, mean: f32, sigma: f32, seed: u64) -> Image {
+        let mut rng = Rng::new(seed);
+        Self::gray_from(res, |_| mean + rng.next_gaussian() * sigma)
+    }
+
+    /// Blue noise threshold mask, using Ulichney's void-and-cluster algorithm
+    ///
+    /// Every pixel gets a distinct rank, scaled to `0..=1`, so a 16x16 mask
+    /// has each 8 bit level exactly once. The mask tiles seamlessly. The
+    /// same `seed` always gives the same image. Tagged [`ColorSpace::AsIs`].
+    ///
+    /// This is slow for large sizes, generate a small tile once and repeat it.
+    pub fn blue_noise_mask(res: ResXY, seed: u64) -> Image {
+        let n = res.0 as usize * res.1 as usize;
+        if n == 0 {
+            return Self::gray_from(res, |_| 0.);
+        }
+        let mut rng = Rng::new(seed);
+        let mut bits = vec![false; n];
+        let mut energy = vec![0f32; n];
+        let toggle = |bits: &mut [bool], energy: &mut [f32], i: usize| {
+            bits[i] = !bits[i];
+            splat(energy, res, i, if bits[i] { 1. } else { -1. });
+        };
+
+        // Random initial pattern, then even it out by moving the tightest
+        // cluster to the largest void until that's a no-op
+        let ones = (n / 10).max(1);
+        let mut placed = 0;
+        while placed < ones {
+            let i = (rng.next_u64() % n as u64) as usize;
+            if !bits[i] {
+                toggle(&mut bits, &mut energy, i);
+                placed += 1;
+            }
+        }
+        if ones < n {
+            loop {
+                let cluster = extreme(&bits, &energy, true);
+                toggle(&mut bits, &mut energy, cluster);
+                let void = extreme(&bits, &energy, false);
+                toggle(&mut bits, &mut energy, void);
+                if void == cluster {
+                    break;
+                }
+            }
+        }
+
+        let mut rank = vec![0usize; n];
+        // Ranks below the initial pattern, removing clusters
+        let (mut b, mut e) = (bits.clone(), energy.clone());
+        for r in (0..ones).rev() {
+            let i = extreme(&b, &e, true);
+            toggle(&mut b, &mut e, i);
+            rank[i] = r;
+        }
+        // And above, filling voids
+        for r in ones..n {
+            let i = extreme(&bits, &energy, false);
+            toggle(&mut bits, &mut energy, i);
+            rank[i] = r;
+        }
+
+        let scale = (n - 1).max(1) as f32;
+        Self::gray_from(res, |i| rank[i] as f32 / scale)
+    }
+
+    /// Add gray Gaussian film grain with standard deviation `sigma`
+    ///
+    /// This is added to the stored values, and clamped to `0..=1`. The same
+    /// `seed` always gives the same grain. Alpha is untouched.
+    pub fn add_noise(&mut self, sigma: f32, seed: u64) {
+        let mut rng = Rng::new(seed);
+        for p in &mut self.data {
+            let n = rng.next_gaussian() * sigma;
+            for c in &mut p[..3] {
+                *c = (*c + n).clamp(0., 1.);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::photo;
+
+    fn values(img: &Image) -> Vec<f32> {
+        img.pixels().iter().map(|p| p[0]).collect()
+    }
+
+    #[test]
+    fn deterministic() {
+        let res = (17, 9);
+        assert_eq!(
+            Image::uniform_noise(res, 1).pixels(),
+            Image::uniform_noise(res, 1).pixels()
+        );
+        assert_ne!(
+            Image::uniform_noise(res, 1).pixels(),
+            Image::uniform_noise(res, 2).pixels()
+        );
+        assert_eq!(
+            Image::gaussian_noise(res, 0.5, 0.1, 7).pixels(),
+            Image::gaussian_noise(res, 0.5, 0.1, 7).pixels()
+        );
+        assert_eq!(
+            Image::blue_noise_mask((8, 8), 3).pixels(),
+            Image::blue_noise_mask((8, 8), 3).pixels()
+        );
+        let mut a = photo(res);
+        let mut b = a.clone();
+        a.add_noise(0.05, 11);
+        b.add_noise(0.05, 11);
+        assert_eq!(a.pixels(), b.pixels());
+        for img in [
+            Image::uniform_noise(res, 1),
+            Image::gaussian_noise(res, 0.5, 0.1, 7),
+            Image::blue_noise_mask((8, 8), 3),
+        ] {
+            assert_eq!(img.color, ColorSpace::AsIs);
+            assert!(img
+                .pixels()
+                .iter()
+                .all(|p| p[0] == p[1] && p[1] == p[2] && p[3] == 1.));
+        }
+    }
+
+    #[test]
+    fn uniform_is_flat() {
+        let v = values(&Image::uniform_noise((64, 64), 5));
+        let mut bins = [0u32; 16];
+        for x in &v {
+            assert!((0.0..1.).contains(x));
+            bins[(x * 16.) as usize] += 1;
+        }
+        // 256 expected in each
+        for b in bins {
+            assert!((200..=312).contains(&b), "{bins:?}");
+        }
+    }
+
+    #[test]
+    fn gaussian_moments() {
+        let v = values(&Image::gaussian_noise((64, 64), 0.25, 0.5, 9));
+        let n = v.len() as f32;
+        let mean = v.iter().sum::<f32>() / n;
+        let var = v.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / n;
+        assert!((mean - 0.25).abs() < 0.03, "{mean}");
+        assert!((var.sqrt() - 0.5).abs() < 0.03, "{var}");
+        // Not clamped
+        assert!(v.iter().any(|&x| x < 0.) && v.iter().any(|&x| x > 1.));
+    }
+
+    #[test]
+    fn blue_noise_has_every_level() {
+        let v = values(&Image::blue_noise_mask((16, 16), 42));
+        let mut levels: Vec<u32> = v.iter().map(|x| (x * 255.).round() as u32).collect();
+        levels.sort_unstable();
+        assert_eq!(levels, (0..256).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn blue_noise_spreads_out() {
+        // The darkest tenth has no two pixels touching, even wrapped around
+        let v = values(&Image::blue_noise_mask((16, 16), 42));
+        let dark: Vec<(i32, i32)> = (0..256)
+            .filter(|&i| v[i] < 0.1)
+            .map(|i| (i as i32 % 16, i as i32 / 16))
+            .collect();
+        for (i, a) in dark.iter().enumerate() {
+            for b in &dark[i + 1..] {
+                let d = |a: i32, b: i32| (a - b).rem_euclid(16).min((b - a).rem_euclid(16));
+                assert!(d(a.0, b.0).max(d(a.1, b.1)) > 1, "{a:?} {b:?}");
+            }
+        }
+        assert!(Image::blue_noise_mask((0, 4), 1).pixels().is_empty());
+        assert_eq!(values(&Image::blue_noise_mask((1, 1), 1)), [0.]);
+    }
+
+    #[test]
+    fn add_noise() {
+        let img = photo((32, 32));
+        let mut same = img.clone();
+        same.add_noise(0., 1);
+        assert_eq!(same.pixels(), img.pixels());
+
+        let mut grain = img.clone();
+        grain.add_noise(0.2, 1);
+        assert_ne!(grain.pixels(), img.pixels());
+        for (g, p) in grain.pixels().iter().zip(img.pixels()) {
+            assert_eq!(g[3], p[3]);
+            assert!(g[..3].iter().all(|c| (0.0..=1.).contains(c)));
+            // One gray offset for all the channels, unless clamped
+            let d: Vec<f32> = (0..3).map(|c| g[c] - p[c]).collect();
+            if g[..3].iter().all(|c| *c > 0. && *c < 1.) {
+                assert!((d[0] - d[1]).abs() < 1e-5 && (d[1] - d[2]).abs() < 1e-5);
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Fusing per pixel adjustments into one pass, see [`Ops`]
+use alloc::vec::Vec;
+
+use crate::{
+    color_matrix::{compose, saturation, transform, ColorMatrix},
+    transforms::{premultiply, unpremultiply},
+    AlphaMode, Image, WorkPixel, F32,
+};
+
+/// One per pixel step
+#[derive(Clone, Copy)]
+enum Op {
+    Invert,
+    Matrix(ColorMatrix),
+    Levels { black: f32, white: f32, gamma: f32 },
+    Map(fn(WorkPixel) -> WorkPixel),
+}
+
+impl Op {
+    #[inline]
+    fn apply(&self, p: WorkPixel, alpha: AlphaMode) -> WorkPixel {
+        let premul = alpha == AlphaMode::Premultiplied;
+        match self {
+            // Premultiplied colors invert against their alpha
+            Op::Invert => {
+                let one = if premul { p[3] } else { 1. };
+                [one - p[0], one - p[1], one - p[2], p[3]]
+            }
+            Op::Matrix(m) => transform(m, p),
+            Op::Levels {
+                black,
+                white,
+                gamma,
+            } => {
+                let s = if premul { unpremultiply(p) } else { p };
+                let range = (white - black).max(f32::EPSILON);
+                let level = |c: f32| ((c - black) / range).clamp(0., 1.).powf(1. / gamma);
+                let s = [level(s[0]), level(s[1]), level(s[2]), s[3]];
+                if premul {
+                    premultiply(s)
+                } else {
+                    s
+                }
+            }
+            Op::Map(f) => f(p),
+        }
+    }
+}
+
+/// Per pixel adjustments recorded to run in a single pass, from
+/// [`Image::ops`]
+///
+/// Each step gives the same result as the [`Image`] method of the same name,
+/// up to float rounding, but the buffer is only walked once, in
+/// [`Ops::commit`]. Consecutive color matrices are composed into one.
+/// Anything spatial, like blurs or scaling, can't be fused, so commit before
+/// it. Nothing happens if this is dropped without committing.
+#[must_use = "nothing happens until `commit`"]
+pub struct Ops<'a> {
+    img: &'a mut Image,
+    ops: Vec<Op>,
+}
+
+impl Ops<'_> {
+    fn push(mut self, op: Op) -> Self {
+        match (self.ops.last_mut(), op) {
+            (Some(Op::Matrix(first)), Op::Matrix(then)) => *first = compose(first, &then),
+            _ => self.ops.push(op),
+        }
+        self
+    }
+
+    /// See [`Image::invert`]
+    pub fn invert(self) -> Self {
+        self.push(Op::Invert)
+    }
+
+    /// See [`Image::adjust_saturation`]
+    pub fn adjust_saturation(self, s: f32) -> Self {
+        self.push(Op::Matrix(saturation(s)))
+    }
+
+    /// See [`Image::apply_levels`]
+    pub fn apply_levels(self, black: f32, white: f32, gamma: f32) -> Self {
+        self.push(Op::Levels {
+            black,
+            white,
+            gamma: gamma.max(f32::EPSILON),
+        })
+    }
+
+    /// See [`Image::apply_color_matrix`]
+    pub fn apply_color_matrix(self, m: &ColorMatrix) -> Self {
+        self.push(Op::Matrix(*m))
+    }
+
+    /// Replace each stored pixel with `f` of it, as is
+    pub fn map(self, f: fn(WorkPixel) -> WorkPixel) -> Self {
+        self.push(Op::Map(f))
+    }
+
+    /// Run every step, in order, in one pass over the image
+    pub fn commit(self) {
+        let alpha = self.img.alpha;
+        if !self.ops.is_empty() {
+            for p in &mut self.img.data {
+                *p = self.ops.iter().fold(*p, |p, op| op.apply(p, alpha));
+            }
+        }
+        self.img.check();
+    }
+}
+
+impl Image {
+    /// Start recording per pixel adjustments to fuse into one pass, see
+    /// [`Ops`]
+    pub fn ops(&mut self) -> Ops<'_> {
+        Ops {
+            img: self,
+            ops: Vec::new(),
+        }
+    }
+
+    /// Invert the color channels, leaving alpha
+    ///
+    /// This is on the stored values, whatever the color space. Premultiplied
+    /// colors are inverted against their alpha, the same as inverting them
+    /// straight.
+    pub fn invert(&mut self) {
+        self.ops().invert().commit()
+    }
+
+    /// Scale saturation by `s`, `0` is grayscale and `1` is unchanged, with
+    /// [`color_matrix::saturation`][crate::color_matrix::saturation]
+    ///
+    /// Like [`Image::apply_color_matrix`] this is on the stored values as is.
+    pub fn adjust_saturation(&mut self, s: f32) {
+        self.ops().adjust_saturation(s).commit()
+    }
+
+    /// Map `black..=white` in each color channel to `0..=1`, then apply
+    /// `gamma`, like the levels tool of image editors
+    ///
+    /// Values outside clamp. A `gamma` above 1 brightens midtones, and is
+    /// kept positive. This is on stored values, straight alpha color even if
+    /// premultiplied. Alpha is untouched.
+    pub fn apply_levels(&mut self, black: f32, white: f32, gamma: f32) {
+        self.ops().apply_levels(black, white, gamma).commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color_matrix::{hue_rotate, sepia},
+        fixtures::{max_diff, photo},
+    };
+
+    /// `photo` with some alpha
+    fn image(alpha: AlphaMode) -> Image {
+        let mut img = photo((16, 12));
+        img.map_pixels_indexed(|(x, _), p| [p[0], p[1], p[2], 0.25 + x as f32 / 20.]);
+        img.to_alpha_mode(alpha);
+        img
+    }
+
+    #[test]
+    fn fused_matches_sequential() {
+        for alpha in [AlphaMode::Straight, AlphaMode::Premultiplied] {
+            let src = image(alpha);
+
+            let mut fused = src.clone();
+            fused
+                .ops()
+                .invert()
+                .adjust_saturation(0.8)
+                .apply_levels(0.05, 0.95, 1.)
+                .apply_color_matrix(&sepia())
+                .commit();
+            let mut seq = src.clone();
+            seq.invert();
+            seq.adjust_saturation(0.8);
+            seq.apply_levels(0.05, 0.95, 1.);
+            seq.apply_color_matrix(&sepia());
+            assert!(max_diff(fused.pixels(), seq.pixels()) < 1e-5, "{alpha:?}");
+
+            let mut fused = src.clone();
+            fused
+                .ops()
+                .apply_levels(0.1, 0.8, 1.8)
+                .apply_color_matrix(&hue_rotate(40.))
+                .adjust_saturation(1.5)
+                .map(|p| [p[2], p[1], p[0], p[3]])
+                .invert()
+                .commit();
+            let mut seq = src.clone();
+            seq.apply_levels(0.1, 0.8, 1.8);
+            seq.apply_color_matrix(&hue_rotate(40.));
+            seq.adjust_saturation(1.5);
+            seq.map_pixels(|p| [p[2], p[1], p[0], p[3]]);
+            seq.invert();
+            assert!(max_diff(fused.pixels(), seq.pixels()) < 1e-5, "{alpha:?}");
+        }
+    }
+
+    #[test]
+    fn steps_match_by_hand() {
+        let src = image(AlphaMode::Straight);
+        let mut img = src.clone();
+        img.invert();
+        for (a, b) in img.pixels().iter().zip(src.pixels()) {
+            assert_eq!(*a, [1. - b[0], 1. - b[1], 1. - b[2], b[3]]);
+        }
+        let mut img = src.clone();
+        img.apply_levels(0.2, 0.7, 2.);
+        for (a, b) in img.pixels().iter().zip(src.pixels()) {
+            for c in 0..3 {
+                let want = ((b[c] - 0.2) / 0.5).clamp(0., 1.).powf(0.5);
+                assert!((a[c] - want).abs() < 1e-6);
+            }
+            assert_eq!(a[3], b[3]);
+        }
+        // Premultiplied inverts like straight
+        let mut premul = image(AlphaMode::Premultiplied);
+        premul.invert();
+        premul.to_alpha_mode(AlphaMode::Straight);
+        let mut straight = src.clone();
+        straight.invert();
+        assert!(max_diff(premul.pixels(), straight.pixels()) < 1e-5);
+    }
+
+    #[test]
+    fn matrices_compose() {
+        let mut img = image(AlphaMode::Straight);
+        let ops = img
+            .ops()
+            .adjust_saturation(0.5)
+            .apply_color_matrix(&sepia())
+            .invert()
+            .apply_color_matrix(&sepia())
+            .adjust_saturation(2.);
+        assert_eq!(ops.ops.len(), 3);
+        ops.commit();
+    }
+
+    #[test]
+    fn nothing_without_commit() {
+        let src = image(AlphaMode::Straight);
+        let mut img = src.clone();
+        let _ = img.ops().invert().adjust_saturation(0.);
+        assert_eq!(img.pixels(), src.pixels());
+        img.ops().commit();
+        assert_eq!(img.pixels(), src.pixels());
+    }
+}
@@ -0,0 +1,253 @@
+//! Outlines around content
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    composite::{from_linear_premul, to_linear_premul},
+    transforms::premultiply,
+    AlphaMode, BlendSpace, Image, ResXY, WorkPixel, F32, XY,
+};
+
+/// Where [`Image::outline`] draws
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutlineMode {
+    /// Behind the content, around it, growing the canvas to fit
+    Outside,
+
+    /// Over the content, just inside its edge
+    Inside,
+}
+
+/// Max of `alpha`, of `res`, over a disk of `radius`, with the disk's edge
+/// anti-aliased so round shapes stay round and smooth
+///
+/// Outside the image counts as `0`.
+fn dilate_disk(alpha: &[f32], (w, h): ResXY, radius: u32) -> Vec<f32> {
+    let r = radius as i64;
+    let disk: Vec<(i64, i64, f32)> = (-r..=r)
+        .flat_map(|dy| (-r..=r).map(move |dx| (dx, dy)))
+        .filter_map(|(dx, dy)| {
+            let d = ((dx * dx + dy * dy) as f32).sqrt();
+            let weight = (radius as f32 + 0.5 - d).clamp(0., 1.);
+            (weight > 0.).then_some((dx, dy, weight))
+        })
+        .collect();
+    let (w, h) = (w as i64, h as i64);
+    let mut out = Vec::with_capacity(alpha.len());
+    for y in 0..h {
+        for x in 0..w {
+            let mut m = 0f32;
+            for &(dx, dy, weight) in &disk {
+                let (sx, sy) = (x + dx, y + dy);
+                if (0..w).contains(&sx) && (0..h).contains(&sy) {
+                    m = m.max(alpha[(sy * w + sx) as usize] * weight);
+                }
+            }
+            out.push(m);
+        }
+    }
+    out
+}
+
+impl Image {
+    /// Draw a `thickness` pixel outline of `color` around the non
+    /// transparent content, returning where the original content is now
+    ///
+    /// `color` is straight alpha, in the image's color space. Partial alpha
+    /// gives a partial outline, so anti-aliased edges stay smooth, and round
+    /// shapes get round outlines.
+    ///
+    /// [`OutlineMode::Outside`] grows the canvas by `thickness` on every
+    /// side, so the content moves to `(thickness, thickness)`.
+    /// [`OutlineMode::Inside`] keeps the size and alpha, and returns
+    /// `(0, 0)`.
+    ///
+    /// Fully transparent images, and a `thickness` of zero, are left alone.
+    /// The cost per pixel grows with the square of `thickness`. Blending is
+    /// in the image's [`Image::blend_space`].
+    pub fn outline(
+        &mut self,
+        thickness: u32,
+        color: impl Into<WorkPixel>,
+        mode: OutlineMode,
+    ) -> XY {
+        let color = color.into();
+        self.outline_with(thickness, color, mode, self.blend_space())
+    }
+
+    /// [`Image::outline`], blending in `blend`
+    pub fn outline_with(
+        &mut self,
+        thickness: u32,
+        color: impl Into<WorkPixel>,
+        mode: OutlineMode,
+        blend: BlendSpace,
+    ) -> XY {
+        let color = color.into();
+        if thickness == 0 || self.data.iter().all(|p| p[3] <= 0.) {
+            return (0, 0);
+        }
+        match mode {
+            OutlineMode::Outside => self.outline_outside(thickness, color, blend),
+            OutlineMode::Inside => self.outline_inside(thickness, color, blend),
+        }
+    }
+
+    fn outline_outside(&mut self, thickness: u32, color: WorkPixel, blend: BlendSpace) -> XY {
+        let (w, h) = self.res;
+        let t = thickness;
+        let res = (w + 2 * t, h + 2 * t);
+        let mut alpha = vec![0.; res.0 as usize * res.1 as usize];
+        for (y, row) in self.data.chunks_exact(w as usize).enumerate() {
+            let i = (y + t as usize) * res.0 as usize + t as usize;
+            for (a, p) in alpha[i..i + w as usize].iter_mut().zip(row) {
+                *a = p[3].clamp(0., 1.);
+            }
+        }
+        let data = dilate_disk(&alpha, res, t)
+            .into_iter()
+            .map(|a| {
+                let p = [color[0], color[1], color[2], color[3] * a];
+                match self.alpha {
+                    AlphaMode::Straight => p,
+                    AlphaMode::Premultiplied => premultiply(p),
+                }
+            })
+            .collect();
+        let mut ring = self.derive(data, res);
+        // Same color space, can't fail
+        let _ = ring.overlay_weighted(self, (t, t), blend, |_| 1.);
+        *self = ring;
+        (t, t)
+    }
+
+    fn outline_inside(&mut self, thickness: u32, color: WorkPixel, blend: BlendSpace) -> XY {
+        // Eroded alpha is the dilated transparency, flipped back
+        let clear: Vec<f32> = self.data.iter().map(|p| 1. - p[3].clamp(0., 1.)).collect();
+        let eroded = dilate_disk(&clear, self.res, thickness);
+        let (decode, encode) = self.blend_transfer(blend);
+        let ink = decode.map_or(color, |f| [f(color[0]), f(color[1]), f(color[2]), color[3]]);
+        for (p, e) in self.data.iter_mut().zip(eroded) {
+            let a = p[3].clamp(0., 1.);
+            // How much of this pixel's content is near the edge
+            let ring = (a - (1. - e)).max(0.);
+            if ring <= 0. {
+                continue;
+            }
+            let k = ink[3] * ring / a;
+            let d = to_linear_premul(*p, decode, self.alpha);
+            // Source atop, keeping alpha
+            let mix = |c: usize| ink[c] * k * d[3] + d[c] * (1. - k);
+            let out = [mix(0), mix(1), mix(2), d[3]];
+            *p = from_linear_premul(out, encode, self.alpha);
+        }
+        self.check();
+        (0, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::solid;
+
+    const BLUE: WorkPixel = [0., 0., 1., 1.];
+    const RED: WorkPixel = [1., 0., 0., 1.];
+
+    /// Distance of pixel `(x, y)`'s center from `c`
+    fn dist((x, y): XY, c: f32) -> f32 {
+        let (dx, dy) = (x as f32 + 0.5 - c, y as f32 + 0.5 - c);
+        (dx * dx + dy * dy).sqrt()
+    }
+
+    fn assert_close(a: WorkPixel, b: WorkPixel, at: XY) {
+        assert!(
+            (0..4).all(|c| (a[c] - b[c]).abs() < 1e-5),
+            "{a:?} != {b:?} at {at:?}"
+        );
+    }
+
+    /// A blue disk of `radius` in the middle of a `size` square
+    fn disk(size: u32, radius: f32) -> Image {
+        let mut img = solid((size, size), BLUE);
+        let c = size as f32 / 2.;
+        img.map_pixels_indexed(|xy, p| if dist(xy, c) <= radius { p } else { [0.; 4] });
+        img
+    }
+
+    #[test]
+    fn outside_ring() {
+        let src = disk(20, 6.);
+        let mut img = src.clone();
+        assert_eq!(img.outline(3, RED, OutlineMode::Outside), (3, 3));
+        assert_eq!(img.res, (26, 26));
+        img.map_pixels_indexed(|(x, y), p| {
+            let d = dist((x, y), 13.);
+            if d <= 6. {
+                // The content is over the ring, where it was plus the offset
+                assert_close(p, src.get_pixel((x - 3, y - 3)).unwrap(), (x, y));
+            } else if d <= 8. {
+                assert_close(p, RED, (x, y));
+            } else if d >= 10.5 {
+                assert_eq!(p[3], 0., "{x} {y}");
+            }
+            p
+        });
+    }
+
+    #[test]
+    fn inside_ring() {
+        let src = disk(20, 8.);
+        let mut img = src.clone();
+        assert_eq!(img.outline(3, RED, OutlineMode::Inside), (0, 0));
+        assert_eq!(img.res, src.res);
+        img.map_pixels_indexed(|xy, p| {
+            let d = dist(xy, 10.);
+            let before = src.get_pixel(xy).unwrap();
+            // Alpha is kept
+            assert_eq!(p[3], before[3]);
+            if d <= 4. || d > 8. {
+                assert_eq!(p, before, "{xy:?}");
+            } else if d >= 6. {
+                assert_close(p, RED, xy);
+            }
+            p
+        });
+    }
+
+    #[test]
+    fn anti_aliased_edges() {
+        // A soft edged disk gets a soft edged ring
+        let mut img = disk(20, 6.);
+        img.map_pixels_indexed(|xy, p| {
+            let a = (7. - dist(xy, 10.)).clamp(0., 1.);
+            [p[0], p[1], p[2], a]
+        });
+        img.outline(3, RED, OutlineMode::Outside);
+        let partial = img
+            .pixels()
+            .iter()
+            .filter(|p| p[3] > 0.05 && p[3] < 0.95)
+            .count();
+        assert!(partial > 20, "{partial}");
+        // Alpha falls off smoothly going out from the ring
+        let row: alloc::vec::Vec<f32> = (13..26)
+            .map(|x| img.get_pixel((x, 13)).unwrap()[3])
+            .collect();
+        for w in row.windows(2) {
+            assert!(w[1] <= w[0] && w[0] - w[1] < 0.9, "{row:?}");
+        }
+    }
+
+    #[test]
+    fn transparent_is_unchanged() {
+        for mode in [OutlineMode::Outside, OutlineMode::Inside] {
+            let mut img = solid((5, 4), [0.3, 0.2, 0.1, 0.]);
+            assert_eq!(img.outline(2, RED, mode), (0, 0));
+            assert_eq!(img.res, (5, 4));
+            assert_eq!(img.pixels(), solid((5, 4), [0.3, 0.2, 0.1, 0.]).pixels());
+        }
+        let mut img = disk(10, 3.);
+        img.outline(0, RED, OutlineMode::Outside);
+        assert_eq!(img.pixels(), disk(10, 3.).pixels());
+    }
+}
@@ -0,0 +1,261 @@
+//! Partial updates, sending displays only what changed since the last frame
+use alloc::{vec, vec::Vec};
+
+use crate::{layout::prepare, Image, ImageError, PixelFormat, ResXY, XY};
+
+impl Image {
+    /// Rectangles of `tile` sized tiles that changed since `previous`
+    ///
+    /// A tile changed if any channel of any pixel differs by more than
+    /// `tolerance`. Adjacent changed tiles are merged, first along each row
+    /// of tiles and then down, into rectangles whose edges line up with
+    /// the tiles, clipped to the image. Results are in top to bottom order
+    /// and never overlap.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::DimensionMismatch`] if `previous` is a different size
+    /// - [`ImageError::ColorSpaceMismatch`] if `previous` is in a different
+    ///   color space
+    /// - [`ImageError::InvalidArgument`] if `tile` is zero or `tolerance`
+    ///   is negative or NaN
+    pub fn diff_regions(
+        &self,
+        previous: &Image,
+        tile: ResXY,
+        tolerance: f32,
+    ) -> Result<Vec<(XY, ResXY)>, ImageError> {
+        if previous.res != self.res {
+            return Err(ImageError::DimensionMismatch);
+        }
+        if previous.color != self.color {
+            return Err(ImageError::ColorSpaceMismatch);
+        }
+        if tile.0 == 0 || tile.1 == 0 || tolerance.is_nan() || tolerance < 0. {
+            return Err(ImageError::InvalidArgument);
+        }
+        let (w, h) = self.res;
+        let (tw, th) = (w.div_ceil(tile.0) as usize, h.div_ceil(tile.1) as usize);
+        let mut changed = vec![false; tw * th];
+        for (i, (a, b)) in self.data.iter().zip(&previous.data).enumerate() {
+            let (x, y) = (i as u32 % w, i as u32 / w);
+            let t = &mut changed[(y / tile.1) as usize * tw + (x / tile.0) as usize];
+            if !*t {
+                // NaN differences count as changed, unless the bits match
+                *t = a.iter().zip(b).any(|(a, b)| {
+                    let d = (a - b).abs();
+                    a.to_bits() != b.to_bits() && (d.is_nan() || d > tolerance)
+                });
+            }
+        }
+
+        // Runs of changed tiles in each row, `(x0, x1, y0, y1)` in tiles,
+        // extended down while the next row has the exact same run
+        let mut open: Vec<(usize, usize, usize, usize)> = Vec::new();
+        let mut done = Vec::new();
+        for ty in 0..th {
+            let row = &changed[ty * tw..][..tw];
+            let mut runs = Vec::new();
+            let mut x = 0;
+            while x < tw {
+                if row[x] {
+                    let start = x;
+                    while x < tw && row[x] {
+                        x += 1;
+                    }
+                    runs.push((start, x));
+                }
+                x += 1;
+            }
+            let mut next = Vec::with_capacity(runs.len());
+            for (x0, x1) in runs {
+                match open.iter().position(|r| (r.0, r.1) == (x0, x1)) {
+                    Some(i) => {
+                        let r = open.swap_remove(i);
+                        next.push((x0, x1, r.2, ty + 1));
+                    }
+                    None => next.push((x0, x1, ty, ty + 1)),
+                }
+            }
+            done.append(&mut open);
+            open = next;
+        }
+        done.append(&mut open);
+        done.sort_by_key(|r| (r.2, r.0));
+
+        Ok(done
+            .into_iter()
+            .map(|(x0, x1, y0, y1)| {
+                let origin = (x0 as u32 * tile.0, y0 as u32 * tile.1);
+                let end = (
+                    (x1 as u32).saturating_mul(tile.0).min(w),
+                    (y1 as u32).saturating_mul(tile.1).min(h),
+                );
+                (origin, (end.0 - origin.0, end.1 - origin.1))
+            })
+            .collect())
+    }
+
+    /// Export the regions that changed since `previous`, from
+    /// [`Image::diff_regions`], passing each to `send` with its origin and
+    /// size
+    ///
+    /// The bytes are tightly packed rows in `format`, like [`Image::to_raw`].
+    /// One buffer is reused for every region.
+    ///
+    /// # Errors
+    ///
+    /// - The errors of [`Image::diff_regions`]
+    pub fn blit_diff_to(
+        &self,
+        previous: &Image,
+        tile: ResXY,
+        tolerance: f32,
+        format: PixelFormat,
+        mut send: impl FnMut(XY, ResXY, &[u8]),
+    ) -> Result<(), ImageError> {
+        let regions = self.diff_regions(previous, tile, tolerance)?;
+        let bpp = format.bytes_per_pixel();
+        let transfer = self.color.transfer();
+        let mut buf = Vec::new();
+        for (origin, size) in regions {
+            buf.clear();
+            buf.resize(size.0 as usize * size.1 as usize * bpp, 0);
+            let row_bytes = size.0 as usize * bpp;
+            for (y, dst) in (origin.1..).zip(buf.chunks_exact_mut(row_bytes)) {
+                let start = y as usize * self.width() as usize + origin.0 as usize;
+                let src = &self.data[start..start + size.0 as usize];
+                for (p, o) in src.iter().zip(dst.chunks_exact_mut(bpp)) {
+                    format.encode(prepare(*p, format, self.alpha, transfer), o);
+                }
+            }
+            send(origin, size, &buf);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::photo, ColorSpace};
+
+    fn changed(img: &Image, at: &[XY]) -> Image {
+        let mut new = img.clone();
+        for &(x, y) in at {
+            let i = (y * img.width() + x) as usize;
+            new.data[i][1] += 0.5;
+        }
+        new
+    }
+
+    #[test]
+    fn identical_is_empty() {
+        let mut img = photo((20, 18));
+        img.data[7] = [f32::NAN; 4];
+        assert_eq!(img.diff_regions(&img.clone(), (8, 8), 0.), Ok(vec![]));
+    }
+
+    #[test]
+    fn single_pixel_is_its_tile() {
+        let img = photo((20, 18));
+        let new = changed(&img, &[(10, 3)]);
+        assert_eq!(
+            new.diff_regions(&img, (8, 8), 0.),
+            Ok(vec![((8, 0), (8, 8))])
+        );
+        // Clipped at the edges
+        let new = changed(&img, &[(19, 17)]);
+        assert_eq!(
+            new.diff_regions(&img, (8, 8), 0.),
+            Ok(vec![((16, 16), (4, 2))])
+        );
+    }
+
+    #[test]
+    fn neighbors_merge() {
+        let img = photo((20, 18));
+        let new = changed(&img, &[(7, 3), (8, 3)]);
+        assert_eq!(
+            new.diff_regions(&img, (8, 8), 0.),
+            Ok(vec![((0, 0), (16, 8))])
+        );
+        let new = changed(&img, &[(3, 7), (3, 8)]);
+        assert_eq!(
+            new.diff_regions(&img, (8, 8), 0.),
+            Ok(vec![((0, 0), (8, 16))])
+        );
+        // An L doesn't merge into a rectangle covering unchanged tiles
+        let new = changed(&img, &[(0, 0), (8, 0), (0, 8)]);
+        assert_eq!(
+            new.diff_regions(&img, (8, 8), 0.),
+            Ok(vec![((0, 0), (16, 8)), ((0, 8), (8, 8))])
+        );
+    }
+
+    #[test]
+    fn tolerance_hides_noise() {
+        let img = photo((20, 18));
+        let mut new = img.clone();
+        new.map_pixels(|p| p.map(|c| c + 0.01));
+        assert_eq!(new.diff_regions(&img, (8, 8), 0.02), Ok(vec![]));
+        assert_eq!(
+            new.diff_regions(&img, (8, 8), 0.005),
+            Ok(vec![((0, 0), (20, 18))])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let img = photo((20, 18));
+        let small = photo((20, 17));
+        let mut linear = img.clone();
+        linear.color = ColorSpace::sRGBLinear;
+        assert_eq!(
+            img.diff_regions(&small, (8, 8), 0.),
+            Err(ImageError::DimensionMismatch)
+        );
+        assert_eq!(
+            img.diff_regions(&linear, (8, 8), 0.),
+            Err(ImageError::ColorSpaceMismatch)
+        );
+        for (tile, tolerance) in [
+            ((0, 8), 0.),
+            ((8, 0), 0.),
+            ((8, 8), -1.),
+            ((8, 8), f32::NAN),
+        ] {
+            assert_eq!(
+                img.diff_regions(&img, tile, tolerance),
+                Err(ImageError::InvalidArgument)
+            );
+        }
+    }
+
+    #[test]
+    fn blit_sends_regions() {
+        let img = photo((20, 18));
+        let new = changed(&img, &[(10, 3), (19, 17)]);
+        let full = new.to_raw(PixelFormat::Rgb565Le);
+        let mut sent = Vec::new();
+        new.blit_diff_to(
+            &img,
+            (8, 8),
+            0.,
+            PixelFormat::Rgb565Le,
+            |at, size, bytes| {
+                // The same bytes as that part of a full export
+                let want: Vec<u8> = (at.1..at.1 + size.1)
+                    .flat_map(|y| {
+                        let start = (y * 20 + at.0) as usize * 2;
+                        full[start..start + size.0 as usize * 2].iter().copied()
+                    })
+                    .collect();
+                assert_eq!(bytes, want);
+                sent.push((at, size));
+            },
+        )
+        .unwrap();
+        assert_eq!(sent, [((8, 0), (8, 8)), ((16, 16), (4, 2))]);
+    }
+}
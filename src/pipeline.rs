@@ -0,0 +1,319 @@
+//! Fused convert, scale, and export
+use alloc::vec::Vec;
+
+use crate::{
+    convert_rows,
+    layout::{prepare, validate_exact},
+    resample::FilterWeights,
+    scale::{nearest, Sample},
+    AlphaMode, ColorSpace, ImageError, PixelFormat, ResXY, ScaleFilter, WorkPixel, F32,
+};
+
+/// Source taps for each destination index along one axis
+enum Taps {
+    Nearest(Vec<u32>),
+    Weighted(FilterWeights),
+}
+
+impl Taps {
+    /// Taps for scaling `src` to `dst` with `filter`, exactly like
+    /// [`Image::scale_with`][crate::Image::scale_with]
+    fn new(filter: ScaleFilter, src: u32, dst: u32) -> Self {
+        match filter {
+            ScaleFilter::Nearest => Taps::Nearest((0..dst).map(|d| nearest(d, src, dst)).collect()),
+            _ => Taps::Weighted(FilterWeights::compute(src, dst, filter)),
+        }
+    }
+
+    /// Destination sample `d`, reading source samples with `get`
+    #[inline]
+    fn sample(&self, d: usize, get: impl Fn(u32) -> WorkPixel) -> WorkPixel {
+        match self {
+            Taps::Nearest(i) => get(i[d]),
+            Taps::Weighted(w) => {
+                let c = &w.contribs[d];
+                let mut acc = WorkPixel::default();
+                for (k, w) in c.weights.iter().enumerate() {
+                    acc = acc.mul_add(get(c.start + k as u32), *w);
+                }
+                acc
+            }
+        }
+    }
+}
+
+/// Scratch space reused between [`Pipeline::run`] calls
+#[derive(Default)]
+struct Scratch {
+    /// Source size the taps are for
+    res: ResXY,
+    taps: Option<(Taps, Taps)>,
+    row: Vec<WorkPixel>,
+    /// Horizontally scaled source rows
+    tmp: Vec<WorkPixel>,
+}
+
+/// A fused `from_raw`, `to_color`, `scale_with`, `to_color`, `to_raw`
+///
+/// Built up from the source format, the steps are run in order on each
+/// pixel but with only one pass over the source rows and one over the
+/// destination rows, and no intermediate [`Image`][crate::Image]. Scratch
+/// buffers are kept between calls to [`Pipeline::run`], so once warmed up at
+/// a size it doesn't allocate.
+///
+/// For example `Pipeline::new(Rgba8888, sRGB).linearize().scale(target,
+/// ScaleFilter::Box).encode(sRGB).output(Rgb565Le)` for a display preview.
+pub struct Pipeline {
+    src_format: PixelFormat,
+    src_color: ColorSpace,
+    /// Color space scaling happens in
+    work: ColorSpace,
+    scale: Option<(ResXY, ScaleFilter)>,
+    dst_color: ColorSpace,
+    dst_format: PixelFormat,
+    scratch: Scratch,
+}
+
+impl Pipeline {
+    /// Read straight alpha `src_format` pixels in `src_color`
+    ///
+    /// With no other steps this just converts the pixel format.
+    pub fn new(src_format: PixelFormat, src_color: ColorSpace) -> Self {
+        Self {
+            src_format,
+            src_color,
+            work: src_color,
+            scale: None,
+            dst_color: src_color,
+            dst_format: src_format,
+            scratch: Scratch::default(),
+        }
+    }
+
+    /// Convert to [`ColorSpace::sRGBLinear`] before scaling
+    pub fn linearize(mut self) -> Self {
+        self.work = ColorSpace::sRGBLinear;
+        self.dst_color = ColorSpace::sRGBLinear;
+        self
+    }
+
+    /// Scale to `target` using `filter`
+    ///
+    /// # Panics
+    ///
+    /// - If `target` is zero in either dimension
+    pub fn scale(mut self, target: ResXY, filter: ScaleFilter) -> Self {
+        assert!(target.0 > 0 && target.1 > 0, "Cannot scale to zero");
+        self.scale = Some((target, filter));
+        self
+    }
+
+    /// Convert to `color` after scaling
+    pub fn encode(mut self, color: ColorSpace) -> Self {
+        self.dst_color = color;
+        self
+    }
+
+    /// Write `format` pixels
+    pub fn output(mut self, format: PixelFormat) -> Self {
+        self.dst_format = format;
+        self
+    }
+
+    /// Size of the output for a source of `src_res`
+    pub fn output_res(&self, src_res: ResXY) -> ResXY {
+        self.scale.map_or(src_res, |(target, _)| target)
+    }
+
+    /// Run the pipeline on `src`, of `src_res`, into `dst`, tightly packed
+    ///
+    /// The result is identical to doing each step on an
+    /// [`Image`][crate::Image].
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `src_res` is zero
+    /// - [`ImageError::BufferSize`] if `src` or `dst` are not exactly
+    ///   the size of the source and output
+    pub fn run(&mut self, src: &[u8], src_res: ResXY, dst: &mut [u8]) -> Result<(), ImageError> {
+        let (w, h) = src_res;
+        if w == 0 || h == 0 {
+            return Err(ImageError::InvalidArgument);
+        }
+        let (nw, nh) = self.output_res(src_res);
+        validate_exact(src, self.src_format, src_res)?;
+        validate_exact(dst, self.dst_format, (nw, nh))?;
+        let (sbpp, dbpp) = (
+            self.src_format.bytes_per_pixel(),
+            self.dst_format.bytes_per_pixel(),
+        );
+        let s = &mut self.scratch;
+        if let Some((_, filter)) = self.scale {
+            if s.taps.is_none() || s.res != src_res {
+                s.taps = Some((Taps::new(filter, w, nw), Taps::new(filter, h, nh)));
+                s.res = src_res;
+            }
+        }
+        let transfer = self.dst_color.transfer();
+        let (w, nw) = (w as usize, nw as usize);
+        s.row.resize(w.max(nw), WorkPixel::default());
+
+        // Source rows, scaled horizontally into `tmp` if needed
+        s.tmp.resize(nw * h as usize, WorkPixel::default());
+        for (y, bytes) in src.chunks_exact(w * sbpp).enumerate() {
+            let row = &mut s.row[..w];
+            for (p, b) in row.iter_mut().zip(bytes.chunks_exact(sbpp)) {
+                *p = self.src_format.decode(b);
+            }
+            convert_rows(row, self.src_color, self.work);
+            let out = &mut s.tmp[y * nw..][..nw];
+            match &s.taps {
+                Some((hx, _)) => {
+                    for (x, o) in out.iter_mut().enumerate() {
+                        *o = hx.sample(x, |sx| row[sx as usize]);
+                    }
+                }
+                None => out.copy_from_slice(row),
+            }
+        }
+
+        // Destination rows
+        for (y, bytes) in dst.chunks_exact_mut(nw * dbpp).enumerate() {
+            let row = &mut s.row[..nw];
+            match &s.taps {
+                Some((_, vy)) => {
+                    for (x, o) in row.iter_mut().enumerate() {
+                        *o = vy.sample(y, |sy| s.tmp[sy as usize * nw + x]);
+                    }
+                }
+                None => row.copy_from_slice(&s.tmp[y * nw..][..nw]),
+            }
+            convert_rows(row, self.work, self.dst_color);
+            for (p, b) in row.iter().zip(bytes.chunks_exact_mut(dbpp)) {
+                let p = prepare(*p, self.dst_format, AlphaMode::Straight, transfer);
+                self.dst_format.encode(p, b);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{fixtures::photo, Image};
+
+    /// The same steps one at a time on an [`Image`]
+    fn naive(
+        src: &[u8],
+        res: ResXY,
+        (src_format, dst_format): (PixelFormat, PixelFormat),
+        target: ResXY,
+        filter: ScaleFilter,
+    ) -> Vec<u8> {
+        let mut img = Image::from_raw(src, res, src_format, ColorSpace::sRGB).unwrap();
+        img.to_color(ColorSpace::sRGBLinear);
+        img.scale_with(target, filter);
+        img.to_color(ColorSpace::sRGB);
+        img.to_raw(dst_format)
+    }
+
+    #[test]
+    fn matches_separate_steps() {
+        let res = (37, 23);
+        for (src_format, dst_format) in [
+            (PixelFormat::Rgba8888, PixelFormat::Rgba8888),
+            (PixelFormat::Rgba8888, PixelFormat::Rgb565Le),
+            (PixelFormat::Bgra8888, PixelFormat::Gray8),
+        ] {
+            let src = photo(res).to_raw(src_format);
+            for target in [(37, 23), (12, 9), (50, 31), (1, 1)] {
+                for filter in [
+                    ScaleFilter::Nearest,
+                    ScaleFilter::Bilinear,
+                    ScaleFilter::Box,
+                ] {
+                    let mut p = Pipeline::new(src_format, ColorSpace::sRGB)
+                        .linearize()
+                        .scale(target, filter)
+                        .encode(ColorSpace::sRGB)
+                        .output(dst_format);
+                    assert_eq!(p.output_res(res), target);
+                    let mut dst =
+                        vec![
+                            0;
+                            target.0 as usize * target.1 as usize * dst_format.bytes_per_pixel()
+                        ];
+                    p.run(&src, res, &mut dst).unwrap();
+                    let want = naive(&src, res, (src_format, dst_format), target, filter);
+                    let worst = dst.iter().zip(&want).map(|(a, b)| a.abs_diff(*b)).max();
+                    assert!(worst <= Some(1), "{dst_format:?} {target:?} {filter:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn format_only() {
+        let src = photo((8, 6)).to_raw(PixelFormat::Rgba8888);
+        let mut p =
+            Pipeline::new(PixelFormat::Rgba8888, ColorSpace::sRGB).output(PixelFormat::Bgra8888);
+        let mut dst = vec![0; src.len()];
+        p.run(&src, (8, 6), &mut dst).unwrap();
+        let img = Image::from_raw(&src, (8, 6), PixelFormat::Rgba8888, ColorSpace::sRGB).unwrap();
+        assert_eq!(dst, img.to_raw(PixelFormat::Bgra8888));
+    }
+
+    #[test]
+    fn reuse_across_sizes() {
+        let mut p = Pipeline::new(PixelFormat::Rgba8888, ColorSpace::sRGB)
+            .linearize()
+            .scale((5, 4), ScaleFilter::Box)
+            .encode(ColorSpace::sRGB);
+        for res in [(20, 16), (9, 9), (20, 16)] {
+            let src = photo(res).to_raw(PixelFormat::Rgba8888);
+            let mut dst = vec![0; 5 * 4 * 4];
+            p.run(&src, res, &mut dst).unwrap();
+            let want = naive(
+                &src,
+                res,
+                (PixelFormat::Rgba8888, PixelFormat::Rgba8888),
+                (5, 4),
+                ScaleFilter::Box,
+            );
+            assert!(
+                dst.iter().zip(&want).all(|(a, b)| a.abs_diff(*b) <= 1),
+                "{res:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let mut p =
+            Pipeline::new(PixelFormat::Rgba8888, ColorSpace::sRGB).scale((2, 2), ScaleFilter::Box);
+        let src = [0; 4 * 4 * 4];
+        let mut dst = [0; 2 * 2 * 4];
+        assert_eq!(
+            p.run(&src, (0, 4), &mut dst),
+            Err(ImageError::InvalidArgument)
+        );
+        assert_eq!(
+            p.run(&src[1..], (4, 4), &mut dst),
+            Err(ImageError::BufferSize {
+                expected: 64,
+                actual: 63
+            })
+        );
+        assert_eq!(
+            p.run(&src, (4, 4), &mut dst[..15]),
+            Err(ImageError::BufferSize {
+                expected: 16,
+                actual: 15
+            })
+        );
+        assert_eq!(p.run(&src, (4, 4), &mut dst), Ok(()));
+    }
+}
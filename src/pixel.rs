@@ -0,0 +1,237 @@
+//! [`WorkPixel`] as a type with its own helpers and arithmetic
+use core::ops::{Add, Mul, Sub};
+
+use crate::{layout::quantize, transforms, ImageError, WorkPixel};
+
+/// A [`WorkPixel`], `r`, `g`, `b`, `a`, with helpers and arithmetic
+///
+/// Everything that takes a single color, like
+/// [`Image::outline`](crate::Image::outline), takes either. Arithmetic is
+/// on all four channels, so `a + (b - a) * t` interpolates alpha too.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[repr(transparent)]
+pub struct Pixel(pub WorkPixel);
+
+impl Pixel {
+    /// From 8 bit channels
+    pub fn from_rgba8(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self([r, g, b, a].map(|c| c as f32 / 255.))
+    }
+
+    /// From opaque `0xRRGGBB`, ignoring the top byte
+    pub fn from_hex(rgb: u32) -> Self {
+        Self::from_hex_rgba(rgb << 8 | 0xff)
+    }
+
+    /// From `0xRRGGBBAA`
+    pub fn from_hex_rgba(rgba: u32) -> Self {
+        let [r, g, b, a] = rgba.to_be_bytes();
+        Self::from_rgba8(r, g, b, a)
+    }
+
+    /// Quantized to 8 bit channels, clamped to `0..=1`
+    pub fn to_rgba8(self) -> [u8; 4] {
+        self.0.map(|c| quantize(c, 255.) as u8)
+    }
+
+    /// From `self` at `t = 0` to `other` at `t = 1`, exactly at both
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        if t == 1. {
+            return other;
+        }
+        Self(core::array::from_fn(|c| {
+            self.0[c] + (other.0[c] - self.0[c]) * t
+        }))
+    }
+
+    /// Every channel clamped to `0..=1`
+    pub fn clamp01(self) -> Self {
+        Self(self.0.map(|c| c.clamp(0., 1.)))
+    }
+
+    /// Relative luminance, treating the color as linear sRGB
+    pub fn luminance_linear(self) -> f32 {
+        transforms::luminance([self.0[0], self.0[1], self.0[2]])
+    }
+
+    /// See [`transforms::premultiply`]
+    pub fn premultiply(self) -> Self {
+        Self(transforms::premultiply(self.0))
+    }
+
+    /// See [`transforms::unpremultiply`]
+    pub fn unpremultiply(self) -> Self {
+        Self(transforms::unpremultiply(self.0))
+    }
+}
+
+impl From<WorkPixel> for Pixel {
+    fn from(p: WorkPixel) -> Self {
+        Self(p)
+    }
+}
+
+impl From<Pixel> for WorkPixel {
+    fn from(p: Pixel) -> Self {
+        p.0
+    }
+}
+
+impl core::str::FromStr for Pixel {
+    type Err = ImageError;
+
+    /// Parse hex like CSS, `RGB`, `RGBA`, `RRGGBB`, or `RRGGBBAA`, with or
+    /// without a leading `#`
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `s` isn't one of those
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix('#').unwrap_or(s);
+        if !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ImageError::InvalidArgument);
+        }
+        let v = u32::from_str_radix(s, 16).map_err(|_| ImageError::InvalidArgument)?;
+        // Short forms repeat each nibble, `f80` is `ff8800`
+        let nibbles = |n: u32| {
+            (0..n).rev().fold(0, |acc, i| {
+                let d = (v >> (i * 4)) & 0xf;
+                acc << 8 | d << 4 | d
+            })
+        };
+        match s.len() {
+            3 => Ok(Self::from_hex(nibbles(3))),
+            4 => Ok(Self::from_hex_rgba(nibbles(4))),
+            6 => Ok(Self::from_hex(v)),
+            8 => Ok(Self::from_hex_rgba(v)),
+            _ => Err(ImageError::InvalidArgument),
+        }
+    }
+}
+
+impl Add for Pixel {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self(core::array::from_fn(|c| self.0[c] + rhs.0[c]))
+    }
+}
+
+impl Sub for Pixel {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self(core::array::from_fn(|c| self.0[c] - rhs.0[c]))
+    }
+}
+
+impl Mul<f32> for Pixel {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self {
+        Self(self.0.map(|c| c * rhs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: Pixel = Pixel([0.25, -0.5, 1.5, 0.75]);
+    const B: Pixel = Pixel([0.125, 0.75, 0.375, 0.25]);
+
+    fn parse(s: &str) -> Result<Pixel, ImageError> {
+        s.parse()
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(
+            Pixel::from_hex(0x336699).to_rgba8(),
+            [0x33, 0x66, 0x99, 0xff]
+        );
+        assert_eq!(
+            Pixel::from_hex(0xab336699).to_rgba8(),
+            [0x33, 0x66, 0x99, 0xff]
+        );
+        assert_eq!(
+            Pixel::from_hex_rgba(0x33669980).to_rgba8(),
+            [0x33, 0x66, 0x99, 0x80]
+        );
+        assert_eq!(Pixel::from_hex(0xff0000), Pixel([1., 0., 0., 1.]));
+        for (s, rgba) in [
+            ("#336699", [0x33, 0x66, 0x99, 0xff]),
+            ("33669980", [0x33, 0x66, 0x99, 0x80]),
+            ("#f80", [0xff, 0x88, 0x00, 0xff]),
+            ("f808", [0xff, 0x88, 0x00, 0x88]),
+            ("#ABCDEF", [0xab, 0xcd, 0xef, 0xff]),
+        ] {
+            assert_eq!(parse(s).map(Pixel::to_rgba8), Ok(rgba), "{s}");
+        }
+        for s in [
+            "",
+            "#",
+            "12345",
+            "1234567",
+            "#12345g",
+            "+123",
+            "##123",
+            "123456789",
+        ] {
+            assert_eq!(parse(s), Err(ImageError::InvalidArgument), "{s}");
+        }
+    }
+
+    #[test]
+    fn rgba8_round_trips() {
+        for v in 0..=255 {
+            let p = Pixel::from_rgba8(v, 255 - v, v / 2, v);
+            assert_eq!(p.to_rgba8(), [v, 255 - v, v / 2, v]);
+        }
+        // Out of range clamps
+        assert_eq!(A.to_rgba8(), [64, 0, 255, 191]);
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(A + Pixel::default(), A);
+        assert_eq!(A - A, Pixel::default());
+        assert_eq!(A * 1., A);
+        assert_eq!(A * 0., Pixel::default());
+        assert_eq!(A + B, B + A);
+        assert_eq!(A * 2., A + A);
+        assert_eq!((A + B).0, [0.375, 0.25, 1.875, 1.]);
+        assert_eq!((A - B).0, [0.125, -1.25, 1.125, 0.5]);
+    }
+
+    #[test]
+    fn lerp_endpoints_are_exact() {
+        for (a, b) in [(A, B), (B, A), (Pixel([1e-8, 3., -7., 0.1]), A)] {
+            assert_eq!(a.lerp(b, 0.), a);
+            assert_eq!(b.lerp(a, 1.), a);
+            assert_eq!(a.lerp(b, 1.), b);
+        }
+        let mid = Pixel([0., 0., 0., 0.]).lerp(Pixel([1., 2., -2., 1.]), 0.5);
+        assert_eq!(mid, Pixel([0.5, 1., -1., 0.5]));
+    }
+
+    #[test]
+    fn helpers() {
+        assert_eq!(A.clamp01(), Pixel([0.25, 0., 1., 0.75]));
+        assert_eq!(Pixel([1., 1., 1., 1.]).luminance_linear(), 1.);
+        assert!((Pixel([0., 1., 0., 1.]).luminance_linear() - 0.7152).abs() < 1e-3);
+        let p = Pixel([0.5, 0.25, 1., 0.5]);
+        assert_eq!(p.premultiply(), Pixel([0.25, 0.125, 0.5, 0.5]));
+        assert_eq!(p.premultiply().unpremultiply(), p);
+        let w: WorkPixel = p.into();
+        assert_eq!(Pixel::from(w), p);
+    }
+
+    #[test]
+    fn images_take_either() {
+        let img = crate::fixtures::solid((2, 1), [0.; 4]);
+        let a = crate::Image::montage(&[&img, &img], 2, 1, Pixel::from_hex(0xff0000)).unwrap();
+        let b = crate::Image::montage(&[&img, &img], 2, 1, [1., 0., 0., 1.]).unwrap();
+        assert_eq!(a.pixels(), b.pixels());
+    }
+}
@@ -0,0 +1,167 @@
+//! Planar pixel data
+use alloc::vec::Vec;
+
+use crate::{
+    alpha_converter, layout::quantize, AlphaMode, ColorSpace, Image, ImageError, ResXY, WorkPixel,
+    F32,
+};
+
+/// A single channel plane, see [`ImageError::PlaneMismatch`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Plane {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+/// Check plane lengths and gather them into pixels, `a` of `None` is opaque
+fn from_planes<T: Copy>(
+    [r, g, b]: [&[T]; 3],
+    a: Option<&[T]>,
+    (w, h): ResXY,
+    f: impl Fn(T) -> f32,
+) -> Result<Vec<WorkPixel>, ImageError> {
+    let len = w as usize * h as usize;
+    for (plane, data) in [(Plane::Red, r), (Plane::Green, g), (Plane::Blue, b)] {
+        if data.len() != len {
+            return Err(ImageError::PlaneMismatch(plane));
+        }
+    }
+    if a.is_some_and(|a| a.len() != len) {
+        return Err(ImageError::PlaneMismatch(Plane::Alpha));
+    }
+    Ok((0..len)
+        .map(|i| [f(r[i]), f(g[i]), f(b[i]), a.map_or(1., |a| f(a[i]))])
+        .collect())
+}
+
+impl Image {
+    /// Read an Image from separate 8 bit planes, each `width * height` long
+    ///
+    /// A missing alpha plane means fully opaque.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::PlaneMismatch`] with the first plane of the wrong length
+    pub fn from_planar_bytes(
+        r: &[u8],
+        g: &[u8],
+        b: &[u8],
+        a: Option<&[u8]>,
+        res: ResXY,
+        color: ColorSpace,
+    ) -> Result<Self, ImageError> {
+        let data = from_planes([r, g, b], a, res, |c| c as f32 / 255.)?;
+        Ok(Self::from_parts(data, res, color))
+    }
+
+    /// Like [`Image::from_planar_bytes`], but with `f32` planes used as is
+    ///
+    /// See [`Image::sanitize`] for untrusted data.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::PlaneMismatch`] with the first plane of the wrong length
+    pub fn from_planar_f32(
+        r: &[f32],
+        g: &[f32],
+        b: &[f32],
+        a: Option<&[f32]>,
+        res: ResXY,
+        color: ColorSpace,
+    ) -> Result<Self, ImageError> {
+        let data = from_planes([r, g, b], a, res, |c| c)?;
+        Ok(Self::from_parts_unchecked(data, res, color))
+    }
+
+    /// Export the image as separate R, G, B, and A planes, with straight alpha
+    pub fn to_planar_f32(&self) -> [Vec<f32>; 4] {
+        let convert = alpha_converter(self.alpha, AlphaMode::Straight);
+        let mut planes: [Vec<f32>; 4] = Default::default();
+        planes
+            .iter_mut()
+            .for_each(|p| p.reserve_exact(self.data.len()));
+        for p in &self.data {
+            for (plane, c) in planes.iter_mut().zip(convert(*p)) {
+                plane.push(c);
+            }
+        }
+        planes
+    }
+
+    /// Export the image as separate 8 bit R, G, B, and A planes, with
+    /// straight alpha
+    ///
+    /// Values are converted like [`Image::to_bytes`].
+    pub fn to_planar_bytes(&self) -> [Vec<u8>; 4] {
+        self.to_planar_f32()
+            .map(|plane| plane.into_iter().map(|c| quantize(c, 255.) as u8).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::ramp;
+
+    #[test]
+    fn bytes_round_trip() {
+        let img = ramp((7, 5));
+        let [r, g, b, a] = img.to_planar_bytes();
+        assert_eq!(r.len(), 35);
+        let back =
+            Image::from_planar_bytes(&r, &g, &b, Some(&a), (7, 5), ColorSpace::sRGB).unwrap();
+        assert_eq!(back.pixels(), img.pixels());
+        // Interleaved order, plane by plane
+        let bytes = img.to_bytes();
+        for (c, plane) in [r, g, b, a].iter().enumerate() {
+            let want: Vec<u8> = bytes.iter().skip(c).step_by(4).copied().collect();
+            assert_eq!(*plane, want);
+        }
+    }
+
+    #[test]
+    fn f32_round_trip() {
+        let mut img = ramp((4, 3));
+        img.data[5] = [-0.25, 1.5, 0.123_456_79, 0.5];
+        let [r, g, b, a] = img.to_planar_f32();
+        let back = Image::from_planar_f32(&r, &g, &b, Some(&a), (4, 3), ColorSpace::sRGB).unwrap();
+        // Out of range and full precision values survive
+        assert_eq!(back.pixels(), img.pixels());
+    }
+
+    #[test]
+    fn missing_alpha_is_opaque() {
+        let plane = [10u8; 6];
+        let img = Image::from_planar_bytes(&plane, &plane, &plane, None, (3, 2), ColorSpace::sRGB)
+            .unwrap();
+        assert!(img.pixels().iter().all(|p| p[3] == 1.));
+        let plane = [0.25f32; 6];
+        let img =
+            Image::from_planar_f32(&plane, &plane, &plane, None, (3, 2), ColorSpace::sRGB).unwrap();
+        assert!(img.pixels().iter().all(|p| *p == [0.25, 0.25, 0.25, 1.]));
+    }
+
+    #[test]
+    fn plane_mismatch() {
+        let (ok, bad) = ([0u8; 6], [0u8; 5]);
+        let planar = |r: &[u8], g: &[u8], b: &[u8], a: Option<&[u8]>| {
+            Image::from_planar_bytes(r, g, b, a, (3, 2), ColorSpace::sRGB).err()
+        };
+        let wrong = |p| Some(ImageError::PlaneMismatch(p));
+        assert_eq!(planar(&bad, &ok, &ok, None), wrong(Plane::Red));
+        assert_eq!(planar(&ok, &bad, &ok, None), wrong(Plane::Green));
+        assert_eq!(planar(&ok, &ok, &bad, None), wrong(Plane::Blue));
+        assert_eq!(planar(&ok, &ok, &ok, Some(&bad)), wrong(Plane::Alpha));
+        // The first wrong one
+        assert_eq!(planar(&ok, &bad, &bad, Some(&bad)), wrong(Plane::Green));
+        assert_eq!(planar(&ok, &ok, &ok, Some(&ok)), None);
+
+        let (ok, long) = ([0f32; 6], [0f32; 7]);
+        assert_eq!(
+            Image::from_planar_f32(&ok, &ok, &long, None, (3, 2), ColorSpace::sRGB).err(),
+            wrong(Plane::Blue)
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Exporting out of range values, see [`EncodePolicy`]
+use alloc::{borrow::Cow, vec::Vec};
+
+use crate::{AlphaMode, EncodePolicy, Image, ImageError, PixelFormat, RowOrder};
+
+impl Image {
+    /// This image, ready to export with `policy`
+    fn with_policy(&self, policy: EncodePolicy) -> Result<Cow<'_, Image>, ImageError> {
+        match policy {
+            EncodePolicy::Clamp => Ok(Cow::Borrowed(self)),
+            EncodePolicy::Error => {
+                let w = self.res.0.max(1);
+                for (i, p) in self.data.iter().enumerate() {
+                    if let Some(channel) = p.iter().position(|c| !(0. ..=1.).contains(c)) {
+                        return Err(ImageError::OutOfRange {
+                            x: i as u32 % w,
+                            y: i as u32 / w,
+                            channel: channel as u8,
+                            value: p[channel],
+                        });
+                    }
+                }
+                Ok(Cow::Borrowed(self))
+            }
+            EncodePolicy::NormalizeMinMax => {
+                let mut img = self.clone();
+                img.to_alpha_mode(AlphaMode::Straight);
+                let (min, max) = img
+                    .data
+                    .iter()
+                    .flat_map(|p| &p[..3])
+                    .filter(|c| c.is_finite())
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), c| {
+                        (lo.min(*c), hi.max(*c))
+                    });
+                if min < max {
+                    let scale = 1. / (max - min);
+                    for p in &mut img.data {
+                        p[..3].iter_mut().for_each(|c| *c = (*c - min) * scale);
+                    }
+                }
+                Ok(Cow::Owned(img))
+            }
+        }
+    }
+
+    /// [`Image::to_bytes`], with out of range values handled by `policy`
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::OutOfRange`] with [`EncodePolicy::Error`]
+    pub fn to_bytes_with_policy(&self, policy: EncodePolicy) -> Result<Vec<u8>, ImageError> {
+        Ok(self.with_policy(policy)?.to_bytes())
+    }
+
+    /// [`Image::to_raw`], with out of range values handled by `policy`
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::OutOfRange`] with [`EncodePolicy::Error`]
+    pub fn to_raw_with_policy(
+        &self,
+        format: PixelFormat,
+        policy: EncodePolicy,
+    ) -> Result<Vec<u8>, ImageError> {
+        Ok(self.with_policy(policy)?.to_raw(format))
+    }
+
+    /// [`Image::write_raw`], with out of range values handled by `policy`
+    ///
+    /// Nothing is written on error.
+    ///
+    /// # Errors
+    ///
+    /// - The errors of [`Image::write_raw`]
+    /// - [`ImageError::OutOfRange`] with [`EncodePolicy::Error`]
+    pub fn write_raw_with_policy(
+        &self,
+        out: &mut [u8],
+        format: PixelFormat,
+        stride: usize,
+        order: RowOrder,
+        policy: EncodePolicy,
+    ) -> Result<(), ImageError> {
+        self.with_policy(policy)?
+            .write_raw(out, format, stride, order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::solid, ColorSpace};
+
+    /// Mid gray, with -0.5 in green at `(2, 0)` and 2.0 in red at `(1, 1)`
+    fn out_of_range() -> Image {
+        let mut img = solid((3, 2), [0.5, 0.5, 0.5, 1.]);
+        img.color = ColorSpace::sRGBLinear;
+        img.data[2][1] = -0.5;
+        img.data[4][0] = 2.;
+        img
+    }
+
+    #[test]
+    fn clamp() {
+        let img = out_of_range();
+        let bytes = img.to_bytes_with_policy(EncodePolicy::Clamp).unwrap();
+        assert_eq!(bytes, img.to_bytes());
+        assert_eq!(bytes[8..12], [128, 0, 128, 255]);
+        assert_eq!(bytes[16..20], [255, 128, 128, 255]);
+        assert_eq!(
+            img.to_raw_with_policy(PixelFormat::Rgb888, EncodePolicy::default()),
+            Ok(img.to_raw(PixelFormat::Rgb888))
+        );
+    }
+
+    #[test]
+    fn error_reports_first() {
+        let mut img = out_of_range();
+        let first = ImageError::OutOfRange {
+            x: 2,
+            y: 0,
+            channel: 1,
+            value: -0.5,
+        };
+        assert_eq!(img.to_bytes_with_policy(EncodePolicy::Error), Err(first));
+        assert_eq!(
+            img.to_raw_with_policy(PixelFormat::Gray8, EncodePolicy::Error),
+            Err(first)
+        );
+        let mut out = [7; 3 * 2 * 3];
+        assert_eq!(
+            img.write_raw_with_policy(
+                &mut out,
+                PixelFormat::Rgb888,
+                9,
+                RowOrder::TopDown,
+                EncodePolicy::Error
+            ),
+            Err(first)
+        );
+        assert_eq!(out, [7; 18]);
+
+        img.data[2][1] = 0.;
+        assert_eq!(
+            img.to_bytes_with_policy(EncodePolicy::Error),
+            Err(ImageError::OutOfRange {
+                x: 1,
+                y: 1,
+                channel: 0,
+                value: 2.
+            })
+        );
+        img.data[4][0] = 1.;
+        assert_eq!(
+            img.to_bytes_with_policy(EncodePolicy::Error),
+            Ok(img.to_bytes())
+        );
+    }
+
+    #[test]
+    fn normalize() {
+        let img = out_of_range();
+        let bytes = img
+            .to_bytes_with_policy(EncodePolicy::NormalizeMinMax)
+            .unwrap();
+        // -0.5 to 2 becomes 0 to 1, so 0.5 is 0.4
+        assert_eq!(bytes[..4], [102, 102, 102, 255]);
+        assert_eq!(bytes[8..12], [102, 0, 102, 255]);
+        assert_eq!(bytes[16..20], [255, 102, 102, 255]);
+        // Nothing to stretch
+        let flat = solid((2, 2), [0.25, 0.25, 0.25, 1.]);
+        assert_eq!(
+            flat.to_bytes_with_policy(EncodePolicy::NormalizeMinMax),
+            Ok(flat.to_bytes())
+        );
+    }
+}
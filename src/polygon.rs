@@ -0,0 +1,275 @@
+//! Polygon filling
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    composite::{from_linear_premul, over, to_linear_premul},
+    transforms::premultiply,
+    AlphaMode, BlendSpace, FloatXY, Image, WorkPixel, F32,
+};
+
+/// Scanlines per pixel row when anti-aliasing
+const SUBSAMPLES: u32 = 4;
+
+/// Where the edges of `points` cross the horizontal line at `y`, sorted,
+/// with their winding direction
+fn crossings(points: &[FloatXY], y: f32, out: &mut Vec<(f32, i32)>) {
+    out.clear();
+    let n = points.len();
+    for i in 0..n {
+        let (a, b) = (points[i], points[(i + 1) % n]);
+        // Half open, so vertices on the line count once
+        let (dir, lo, hi) = if a.1 < b.1 { (1, a, b) } else { (-1, b, a) };
+        if lo.1 <= y && y < hi.1 {
+            let t = (y - lo.1) / (hi.1 - lo.1);
+            out.push((lo.0 + (hi.0 - lo.0) * t, dir));
+        }
+    }
+    out.sort_by(|a, b| a.0.total_cmp(&b.0));
+}
+
+/// Spans of `crossings` inside the polygon, by the nonzero rule
+fn spans(crossings: &[(f32, i32)]) -> impl Iterator<Item = (f32, f32)> + '_ {
+    let mut winding = 0;
+    crossings.windows(2).filter_map(move |w| {
+        winding += w[0].1;
+        (winding != 0).then_some((w[0].0, w[1].0))
+    })
+}
+
+/// Whether all of `points` are on one line, so there's nothing to fill
+///
+/// Rounding could otherwise leave specks along it. Net area isn't enough,
+/// a bow tie has none.
+fn collinear(points: &[FloatXY]) -> bool {
+    let a = points[0];
+    let Some(b) = points.iter().find(|p| **p != a) else {
+        return true;
+    };
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    points.iter().all(|p| dx * (p.1 - a.1) == dy * (p.0 - a.0))
+}
+
+/// Add `weight` times the coverage of `x0..x1` to each pixel in `row`
+fn add_span(row: &mut [f32], x0: f32, x1: f32, weight: f32) {
+    let w = row.len() as f32;
+    let (x0, x1) = (x0.clamp(0., w), x1.clamp(0., w));
+    if x1 <= x0 {
+        return;
+    }
+    let (first, last) = (x0.floor() as usize, (x1.ceil() as usize).max(1) - 1);
+    if first == last {
+        row[first] += (x1 - x0) * weight;
+        return;
+    }
+    row[first] += ((first + 1) as f32 - x0) * weight;
+    for c in &mut row[first + 1..last] {
+        *c += weight;
+    }
+    row[last] += (x1 - last as f32) * weight;
+}
+
+impl Image {
+    /// Fill the polygon through `points` with the straight alpha `color`,
+    /// composited over the image in its [`Image::blend_space`]
+    ///
+    /// Pixel `(x, y)` covers `(x, y)` to `(x + 1, y + 1)`, and the last point
+    /// connects back to the first. Self intersecting polygons are filled by
+    /// the nonzero winding rule, so the middle of a five pointed star drawn
+    /// in one stroke is filled, and so are areas wound over twice.
+    ///
+    /// Without `aa`, pixels are filled if their center is inside. With it,
+    /// edge pixels get their fractional coverage, from four scanlines per
+    /// row. Either way fully covered pixels with an opaque `color` are set to
+    /// exactly `color`. Fewer than three points, zero area, and non finite
+    /// points draw nothing.
+    pub fn fill_polygon(&mut self, points: &[FloatXY], color: impl Into<WorkPixel>, aa: bool) {
+        let color = color.into();
+        self.fill_polygon_with(points, color, aa, self.blend_space())
+    }
+
+    /// [`Image::fill_polygon`], compositing in `blend`
+    pub fn fill_polygon_with(
+        &mut self,
+        points: &[FloatXY],
+        color: impl Into<WorkPixel>,
+        aa: bool,
+        blend: BlendSpace,
+    ) {
+        let color = color.into();
+        if points.len() < 3
+            || !points.iter().all(|p| p.0.is_finite() && p.1.is_finite())
+            || collinear(points)
+        {
+            return;
+        }
+        let (w, h) = self.res;
+        let (min_y, max_y) = points
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), p| {
+                (lo.min(p.1), hi.max(p.1))
+            });
+        let y0 = min_y.floor().clamp(0., h as f32) as u32;
+        let y1 = max_y.ceil().clamp(0., h as f32) as u32;
+
+        let (decode, encode) = self.blend_transfer(blend);
+        let src = to_linear_premul(color, decode, AlphaMode::Straight);
+        let exact = match self.alpha {
+            AlphaMode::Straight => color,
+            AlphaMode::Premultiplied => premultiply(color),
+        };
+
+        let mut row = vec![0f32; w as usize];
+        let mut hits = Vec::new();
+        for y in y0..y1 {
+            row.iter_mut().for_each(|c| *c = 0.);
+            if aa {
+                let weight = 1. / SUBSAMPLES as f32;
+                for s in 0..SUBSAMPLES {
+                    let sy = y as f32 + (s as f32 + 0.5) * weight;
+                    crossings(points, sy, &mut hits);
+                    for (a, b) in spans(&hits) {
+                        add_span(&mut row, a, b, weight);
+                    }
+                }
+            } else {
+                crossings(points, y as f32 + 0.5, &mut hits);
+                for (a, b) in spans(&hits) {
+                    // Pixels whose centers are in `a..b`
+                    let first = (a - 0.5).ceil().clamp(0., w as f32) as usize;
+                    let end = (b - 0.5).ceil().clamp(0., w as f32) as usize;
+                    row[first..end.max(first)].iter_mut().for_each(|c| *c = 1.);
+                }
+            }
+
+            let start = (y * w) as usize;
+            for (d, k) in self.data[start..start + w as usize].iter_mut().zip(&row) {
+                let k = k.min(1.);
+                if k <= 0. {
+                    continue;
+                }
+                if k >= 1. && color[3] >= 1. {
+                    *d = exact;
+                    continue;
+                }
+                let s = src.map(|c| c * k);
+                let dst = to_linear_premul(*d, decode, self.alpha);
+                *d = from_linear_premul(over(s, dst), encode, self.alpha);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::solid, XY};
+
+    const RED: WorkPixel = [1., 0., 0., 1.];
+    const BG: WorkPixel = [0., 0., 0., 0.];
+
+    fn canvas() -> Image {
+        solid((48, 40), BG)
+    }
+
+    fn filled(img: &Image) -> alloc::vec::Vec<XY> {
+        let w = img.width();
+        (0..img.data.len() as u32)
+            .map(|i| (i % w, i / w))
+            .filter(|&xy| img.get_pixel(xy).unwrap()[3] > 0.)
+            .collect()
+    }
+
+    #[test]
+    fn square_is_a_rect() {
+        let square = [(2., 3.), (8., 3.), (8., 7.), (2., 7.)];
+        for aa in [false, true] {
+            let mut img = canvas();
+            img.fill_polygon(&square, RED, aa);
+            img.map_pixels_indexed(|(x, y), p| {
+                let inside = (2..8).contains(&x) && (3..7).contains(&y);
+                assert_eq!(p, if inside { RED } else { BG }, "{x} {y} {aa}");
+                p
+            });
+        }
+    }
+
+    #[test]
+    fn triangle_area() {
+        let [a, b, c] = [(1.3, 2.1), (40.7, 5.6), (12.2, 35.9)];
+        let area = ((b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1)) / 2f32;
+        for points in [[a, b, c], [c, b, a]] {
+            let mut img = canvas();
+            img.fill_polygon(&points, RED, true);
+            let covered: f32 = img.pixels().iter().map(|p| p[3]).sum();
+            assert!(
+                (covered - area.abs()).abs() < area.abs() * 0.01,
+                "{covered} {area}"
+            );
+            // Edges are partial
+            assert!(img.pixels().iter().any(|p| p[3] > 0. && p[3] < 1.));
+        }
+    }
+
+    #[test]
+    fn nonzero_rule() {
+        // A five pointed star in one stroke, its middle is wound over twice
+        let star: alloc::vec::Vec<FloatXY> = (0..5)
+            .map(|i| {
+                let t = (i * 2) as f32 * core::f32::consts::TAU / 5.;
+                (24. + 18. * t.sin(), 20. - 18. * t.cos())
+            })
+            .collect();
+        let mut img = canvas();
+        img.fill_polygon(&star, RED, false);
+        assert_eq!(img.get_pixel((24, 20)), Some(RED));
+        // But not between the points
+        assert_eq!(img.get_pixel((24, 37)), Some(BG));
+
+        // A hole wound the other way is left out
+        let with_hole = [
+            (2., 2.),
+            (20., 2.),
+            (20., 20.),
+            (2., 20.),
+            (2., 2.),
+            (8., 8.),
+            (8., 14.),
+            (14., 14.),
+            (14., 8.),
+            (8., 8.),
+        ];
+        let mut img = canvas();
+        img.fill_polygon(&with_hole, RED, false);
+        assert_eq!(img.get_pixel((10, 10)), Some(BG));
+        assert_eq!(img.get_pixel((4, 10)), Some(RED));
+    }
+
+    #[test]
+    fn degenerate_draws_nothing() {
+        for points in [
+            &[][..],
+            &[(1., 1.), (10., 10.)],
+            &[(1., 1.), (10., 10.), (20., 20.)],
+            &[(1., 5.), (10., 5.), (20., 5.)],
+            &[(1., 1.), (f32::NAN, 10.), (20., 3.)],
+            &[(1., 1.), (f32::INFINITY, 10.), (20., 3.)],
+        ] {
+            for aa in [false, true] {
+                let mut img = canvas();
+                img.fill_polygon(points, RED, aa);
+                assert_eq!(filled(&img), [], "{points:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn clipped_to_the_image() {
+        let mut img = canvas();
+        img.fill_polygon(
+            &[(-10., -10.), (100., -10.), (100., 100.), (-10., 100.)],
+            RED,
+            true,
+        );
+        assert!(img.pixels().iter().all(|p| *p == RED));
+    }
+}
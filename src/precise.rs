@@ -0,0 +1,295 @@
+//! Double precision images
+//!
+//! For host side pipelines chaining many conversions, where `f32` error adds
+//! up to visible banding. Embedded code should stick to [`Image`].
+use alloc::vec::Vec;
+
+use nalgebra::Matrix3;
+
+use crate::{transforms::*, AlphaMode, ColorSpace, Image, ResXY};
+
+/// A single RGBA pixel in double precision
+pub type WorkPixel64 = [f64; 4];
+
+fn srgb_to_rgb64(c: f64) -> f64 {
+    let x = c.abs();
+    if x <= 0.04045 {
+        c / 12.92
+    } else {
+        libm::pow((x + 0.055) / 1.055, 2.4).copysign(c)
+    }
+}
+
+fn rgb_to_srgb64(c: f64) -> f64 {
+    let x = c.abs();
+    if x <= 0.0031308 {
+        12.92 * c
+    } else {
+        (1.055 * libm::pow(x, 1. / 2.4) - 0.055).copysign(c)
+    }
+}
+
+fn gamma_to_rgb64(c: f64) -> f64 {
+    libm::pow(c.abs(), 2.2).copysign(c)
+}
+
+fn rgb_to_gamma64(c: f64) -> f64 {
+    libm::pow(c.abs(), 1. / 2.2).copysign(c)
+}
+
+fn adobe_rgb_to_rgb64(c: f64) -> f64 {
+    libm::pow(c.abs(), 563. / 256.).copysign(c)
+}
+
+fn rgb_to_adobe_rgb64(c: f64) -> f64 {
+    libm::pow(c.abs(), 256. / 563.).copysign(c)
+}
+
+type Transfer64 = fn(f64) -> f64;
+
+/// Decoding and encoding transfer functions, see `ColorSpace::transfer`
+fn transfer64(color: ColorSpace) -> Option<(Transfer64, Transfer64)> {
+    match color {
+        ColorSpace::sRGB | ColorSpace::DisplayP3 => Some((srgb_to_rgb64, rgb_to_srgb64)),
+        ColorSpace::SimplesRGB => Some((gamma_to_rgb64, rgb_to_gamma64)),
+        ColorSpace::AdobeRgb => Some((adobe_rgb_to_rgb64, rgb_to_adobe_rgb64)),
+        ColorSpace::sRGBLinear | ColorSpace::AsIs => None,
+    }
+}
+
+/// An [`Image`] with `f64` channels
+#[derive(Debug, Clone)]
+pub struct Image64 {
+    data: Vec<WorkPixel64>,
+    res: ResXY,
+    color: ColorSpace,
+    alpha: AlphaMode,
+}
+
+impl Image64 {
+    /// Read an Image64 from RGBA 8888 data, see [`Image::from_bytes`]
+    ///
+    /// # Panics
+    ///
+    /// - If `data` is not exactly `width * height * 4` in size
+    pub fn from_bytes(data: &[u8], res: ResXY, color: ColorSpace) -> Self {
+        assert_eq!(data.len(), res.0 as usize * res.1 as usize * 4);
+        let data = data
+            .chunks_exact(4)
+            .map(|p| [p[0], p[1], p[2], p[3]].map(|c| c as f64 / 255.))
+            .collect();
+        Self {
+            data,
+            res,
+            color,
+            alpha: AlphaMode::Straight,
+        }
+    }
+
+    /// Export as RGBA 8888 with straight alpha, see [`Image::to_bytes`]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.data
+            .iter()
+            .flat_map(|p| {
+                let p = match self.alpha {
+                    AlphaMode::Straight => *p,
+                    AlphaMode::Premultiplied if p[3] <= 0. => [0.; 4],
+                    AlphaMode::Premultiplied => [p[0] / p[3], p[1] / p[3], p[2] / p[3], p[3]],
+                };
+                p.map(|c| {
+                    if c.is_nan() {
+                        0
+                    } else {
+                        libm::round(c.clamp(0., 1.) * 255.) as u8
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Convert to single precision
+    pub fn to_f32(&self) -> Image {
+        let data = self.data.iter().map(|p| p.map(|c| c as f32)).collect();
+        let mut img = Image::from_parts(data, self.res, self.color);
+        img.alpha = self.alpha;
+        img
+    }
+
+    pub fn width(&self) -> u32 {
+        self.res.0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.res.1
+    }
+
+    pub fn color(&self) -> ColorSpace {
+        self.color
+    }
+
+    pub fn alpha_mode(&self) -> AlphaMode {
+        self.alpha
+    }
+
+    pub fn pixels(&self) -> &[WorkPixel64] {
+        &self.data
+    }
+
+    /// Apply `f` to every pixel
+    pub fn map_pixels(&mut self, mut f: impl FnMut(WorkPixel64) -> WorkPixel64) {
+        for p in &mut self.data {
+            *p = f(*p);
+        }
+    }
+
+    /// Convert the image to the color space `color`, see [`Image::to_color`]
+    pub fn to_color(&mut self, color: ColorSpace) {
+        let from = self.color;
+        self.color = color;
+        if from == color || from == ColorSpace::AsIs || color == ColorSpace::AsIs {
+            return;
+        }
+        let p3 = |c| c == ColorSpace::DisplayP3;
+        // Inverted here rather than using `p3_to_srgb_matrix`, so round trips
+        // don't pick up the rounding of both `f32` matrices
+        let to_p3: Matrix3<f64> = srgb_to_p3_matrix().cast();
+        let matrix = match (p3(from), p3(color)) {
+            (false, true) => Some(to_p3),
+            (true, false) => to_p3.try_inverse(),
+            _ => None,
+        };
+        let decode = transfer64(from).map(|t| t.0);
+        let encode = transfer64(color).map(|t| t.1);
+        for p in &mut self.data {
+            let mut rgb = [p[0], p[1], p[2]];
+            if let Some(f) = decode {
+                rgb = rgb.map(f);
+            }
+            if let Some(m) = &matrix {
+                let [r, g, b] = rgb;
+                rgb = [0, 1, 2].map(|i| m[(i, 0)] * r + m[(i, 1)] * g + m[(i, 2)] * b);
+            }
+            if let Some(f) = encode {
+                rgb = rgb.map(f);
+            }
+            *p = [rgb[0], rgb[1], rgb[2], p[3]];
+        }
+    }
+}
+
+impl Image {
+    /// Convert to double precision, see [`Image64`]
+    pub fn to_f64(&self) -> Image64 {
+        Image64 {
+            data: self.data.iter().map(|p| p.map(|c| c as f64)).collect(),
+            res: self.res,
+            color: self.color,
+            alpha: self.alpha,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Eight conversions, ending back in sRGB
+    const CHAIN: [ColorSpace; 8] = [
+        ColorSpace::sRGBLinear,
+        ColorSpace::DisplayP3,
+        ColorSpace::SimplesRGB,
+        ColorSpace::sRGB,
+        ColorSpace::DisplayP3,
+        ColorSpace::sRGBLinear,
+        ColorSpace::SimplesRGB,
+        ColorSpace::sRGB,
+    ];
+
+    /// How far [`fade`] pulls towards gray, a very low contrast intermediate
+    const FADE: f64 = 1e-6;
+
+    /// Fade towards mid gray, or back out again if `undo`
+    fn fade(c: f64, undo: bool) -> f64 {
+        if undo {
+            (c - 0.5) / FADE + 0.5
+        } else {
+            (c - 0.5) * FADE + 0.5
+        }
+    }
+
+    /// A 32 level cube of every color
+    fn cube() -> Vec<u8> {
+        let mut out = Vec::new();
+        for r in 0..32u8 {
+            for g in 0..32u8 {
+                for b in 0..32u8 {
+                    out.extend([r * 8 + r / 4, g * 8 + g / 4, b * 8 + b / 4, 255]);
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn chain_is_exact() {
+        let bytes = cube();
+        let res = (1024, 32);
+        let mut wide = Image64::from_bytes(&bytes, res, ColorSpace::sRGB);
+        let mut narrow = Image::from_bytes(&bytes, res, ColorSpace::sRGB);
+        // Ten steps, with the fade in linear light in the middle
+        for (i, c) in CHAIN.into_iter().enumerate() {
+            wide.to_color(c);
+            narrow.to_color(c);
+            if c == ColorSpace::sRGBLinear {
+                let undo = i > 0;
+                wide.map_pixels(|p| [fade(p[0], undo), fade(p[1], undo), fade(p[2], undo), p[3]]);
+                narrow.map_pixels(|p| {
+                    let q = [p[0], p[1], p[2]].map(|c| fade(c as f64, undo) as f32);
+                    [q[0], q[1], q[2], p[3]]
+                });
+            }
+        }
+        assert_eq!(wide.to_bytes(), bytes);
+        // Rounded to f32 at each step, which the fade out then magnifies
+        let narrow = narrow.to_bytes();
+        let worst = narrow.iter().zip(&bytes).map(|(a, b)| a.abs_diff(*b)).max();
+        assert!(worst >= Some(1), "{worst:?}");
+    }
+
+    #[test]
+    fn single_step_matches_f32() {
+        let bytes = cube();
+        for to in [ColorSpace::DisplayP3, ColorSpace::sRGBLinear] {
+            let mut wide = Image64::from_bytes(&bytes, (1024, 32), ColorSpace::sRGB);
+            let mut narrow = Image::from_bytes(&bytes, (1024, 32), ColorSpace::sRGB);
+            wide.to_color(to);
+            narrow.to_color(to);
+            let (w, n) = (wide.to_bytes(), narrow.to_bytes());
+            assert!(w.iter().zip(&n).all(|(a, b)| a.abs_diff(*b) <= 1), "{to:?}");
+        }
+    }
+
+    #[test]
+    fn precision_conversions() {
+        let mut img = Image::from_bytes(&cube()[..64], (4, 4), ColorSpace::DisplayP3);
+        img.to_alpha_mode(AlphaMode::Premultiplied);
+        let wide = img.to_f64();
+        assert_eq!((wide.width(), wide.height()), (4, 4));
+        assert_eq!(wide.color(), ColorSpace::DisplayP3);
+        assert_eq!(wide.alpha_mode(), AlphaMode::Premultiplied);
+        let back = wide.to_f32();
+        assert_eq!(back.pixels(), img.pixels());
+        assert_eq!((back.color, back.alpha), (img.color, img.alpha));
+        assert_eq!(wide.to_bytes(), img.to_bytes());
+    }
+
+    #[test]
+    fn map_pixels_and_as_is() {
+        let mut img = Image64::from_bytes(&[255, 0, 51, 255], (1, 1), ColorSpace::sRGB);
+        img.map_pixels(|p| [p[2], p[1], p[0], 0.5]);
+        assert_eq!(img.to_bytes(), [51, 0, 255, 128]);
+        img.to_color(ColorSpace::AsIs);
+        assert_eq!(img.to_bytes(), [51, 0, 255, 128]);
+        assert_eq!(img.color(), ColorSpace::AsIs);
+    }
+}
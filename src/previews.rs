@@ -0,0 +1,173 @@
+//! Cached previews of an image
+use alloc::vec::Vec;
+
+use crate::{Image, ResXY};
+
+/// An [`Image`] with smaller previews of it, made on first use and cached,
+/// for galleries that switch between thumbnails and the full image
+///
+/// Previews are always made from the original with [`Image::resize`], so
+/// they're the same whatever order they're asked for in.
+#[derive(Debug, Clone)]
+pub struct ImageWithPreviews {
+    original: Image,
+    /// Previews, by the `max_dim` they were asked for with
+    previews: Vec<(u32, Image)>,
+}
+
+/// Size of `res` scaled to fit in `max_dim` by `max_dim`, keeping the
+/// aspect ratio, and at least 1
+fn fit((w, h): ResXY, max_dim: u32) -> ResXY {
+    let big = w.max(h) as u64;
+    let scale = |len: u32| ((len as u64 * max_dim as u64 + big / 2) / big).max(1) as u32;
+    (scale(w), scale(h))
+}
+
+impl ImageWithPreviews {
+    pub fn new(original: Image) -> Self {
+        Self {
+            original,
+            previews: Vec::new(),
+        }
+    }
+
+    pub fn original(&self) -> &Image {
+        &self.original
+    }
+
+    /// The original, to change it, which drops every preview
+    pub fn original_mut(&mut self) -> &mut Image {
+        self.previews.clear();
+        &mut self.original
+    }
+
+    pub fn into_original(self) -> Image {
+        self.original
+    }
+
+    /// The original scaled down to fit in `max_dim` by `max_dim`, keeping
+    /// its aspect ratio
+    ///
+    /// The first call for a `max_dim` makes the preview, later calls return
+    /// the same one. If the original already fits, or is empty, it's the
+    /// original.
+    ///
+    /// # Panics
+    ///
+    /// - If `max_dim` is zero
+    pub fn get_preview(&mut self, max_dim: u32) -> &Image {
+        assert!(max_dim > 0, "Cannot preview at zero size");
+        let res = self.original.res;
+        if res.0 <= max_dim && res.1 <= max_dim || res.0 == 0 || res.1 == 0 {
+            return &self.original;
+        }
+        let i = match self.previews.iter().position(|(d, _)| *d == max_dim) {
+            Some(i) => i,
+            None => {
+                let preview = self.original.resize(fit(res, max_dim));
+                self.previews.push((max_dim, preview));
+                self.previews.len() - 1
+            }
+        };
+        &self.previews[i].1
+    }
+
+    /// Number of cached previews
+    pub fn previews(&self) -> usize {
+        self.previews.len()
+    }
+
+    /// Heap memory used by the original and the previews, in bytes, see
+    /// [`Image::byte_size`]
+    pub fn memory_usage(&self) -> usize {
+        self.original.byte_size() + self.preview_memory()
+    }
+
+    /// Drop every preview, returning how many bytes of
+    /// [`ImageWithPreviews::memory_usage`] that freed
+    pub fn evict_previews(&mut self) -> usize {
+        let freed = self.preview_memory();
+        self.previews = Vec::new();
+        freed
+    }
+
+    fn preview_memory(&self) -> usize {
+        let list = self.previews.capacity() * size_of::<(u32, Image)>();
+        list + self
+            .previews
+            .iter()
+            .map(|(_, p)| p.byte_size())
+            .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::photo, ColorSpace};
+
+    #[test]
+    fn preview_empty() {
+        let mut previews =
+            ImageWithPreviews::new(Image::from_bytes(&[], (0, 50), ColorSpace::sRGB));
+        assert_eq!(previews.get_preview(8).res, (0, 50));
+        assert_eq!(previews.previews(), 0);
+    }
+
+    #[test]
+    fn previews_are_cached() {
+        let mut previews = ImageWithPreviews::new(photo((64, 32)));
+        let first = previews.get_preview(16).data.as_ptr();
+        assert_eq!(previews.get_preview(16).res, (16, 8));
+        assert_eq!(previews.get_preview(16).data.as_ptr(), first);
+        assert_eq!(previews.get_preview(8).res, (8, 4));
+        assert_eq!(previews.get_preview(16).data.as_ptr(), first);
+        assert_eq!(previews.previews(), 2);
+        // Same as resizing directly, whatever the order
+        let want = previews.original().resize((8, 4));
+        assert_eq!(previews.get_preview(8).pixels(), want.pixels());
+    }
+
+    #[test]
+    fn fitting_is_the_original() {
+        let mut previews = ImageWithPreviews::new(photo((64, 32)));
+        let original = previews.original().data.as_ptr();
+        assert_eq!(previews.get_preview(64).data.as_ptr(), original);
+        assert_eq!(previews.get_preview(100).data.as_ptr(), original);
+        assert_eq!(previews.previews(), 0);
+        // Thin images keep at least a pixel
+        let mut thin = ImageWithPreviews::new(photo((64, 1)));
+        assert_eq!(thin.get_preview(8).res, (8, 1));
+    }
+
+    #[test]
+    fn mutation_invalidates() {
+        let mut previews = ImageWithPreviews::new(photo((64, 32)));
+        let old = previews.get_preview(16).pixels().to_vec();
+        previews
+            .original_mut()
+            .map_pixels(|p| [1. - p[0], 1. - p[1], 1. - p[2], p[3]]);
+        assert_eq!(previews.previews(), 0);
+        let new = previews.get_preview(16);
+        assert_ne!(new.pixels(), &old[..]);
+        let want = previews.original().resize((16, 8));
+        assert_eq!(previews.get_preview(16).pixels(), want.pixels());
+    }
+
+    #[test]
+    fn eviction_frees_the_memory() {
+        let mut previews = ImageWithPreviews::new(photo((64, 32)));
+        let bare = previews.memory_usage();
+        assert_eq!(bare, previews.original().byte_size());
+        previews.get_preview(16);
+        previews.get_preview(8);
+        let full = previews.memory_usage();
+        let images = (16 * 8 + 8 * 4) * size_of::<crate::WorkPixel>();
+        assert!(full >= bare + images, "{full} {bare}");
+        assert_eq!(previews.evict_previews(), full - bare);
+        assert_eq!(previews.memory_usage(), bare);
+        assert_eq!(previews.previews(), 0);
+        // And they come back
+        assert_eq!(previews.get_preview(16).res, (16, 8));
+    }
+}
@@ -0,0 +1,214 @@
+//! Timing the heavy operations, with the `profiling` feature
+//!
+//! Nothing is recorded until there's a [`TimeSource`], see
+//! [`set_time_source`]. Recording never waits, if the report is busy, say
+//! because an interrupt is profiling too, the entry is dropped.
+//!
+//! The report is shared behind a lock, which needs atomic compare and swap.
+#[cfg(not(target_has_atomic = "8"))]
+compile_error!("the `profiling` feature needs atomic compare and swap");
+
+use crate::lock::TryLock;
+
+/// How many entries a [`ProfileReport`] keeps
+pub const PROFILE_CAPACITY: usize = 32;
+
+/// Something that reads a monotonic counter, like a cycle counter or timer
+///
+/// The units are up to you, durations are just differences of
+/// [`TimeSource::now`], wrapping around.
+pub trait TimeSource: Sync {
+    fn now(&self) -> u64;
+}
+
+impl<F: Fn() -> u64 + Sync> TimeSource for F {
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+/// The operations that get timed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileOp {
+    /// [`Image::to_color`](crate::Image::to_color) and friends
+    ToColor,
+
+    /// Scaling, like [`Image::scale_with`](crate::Image::scale_with)
+    Scale,
+
+    /// Convolution, like [`Image::gaussian_blur`](crate::Image::gaussian_blur)
+    Convolve,
+
+    /// Exporting, like [`Image::to_raw`](crate::Image::to_raw)
+    Export,
+}
+
+/// One timed operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfileEntry {
+    pub op: ProfileOp,
+    pub duration: u64,
+}
+
+/// The last [`PROFILE_CAPACITY`] timed operations
+///
+/// When full the oldest entries are dropped to make room.
+#[derive(Debug, Clone)]
+pub struct ProfileReport {
+    entries: [ProfileEntry; PROFILE_CAPACITY],
+    start: usize,
+    len: usize,
+    dropped: u32,
+}
+
+impl ProfileReport {
+    const fn new() -> Self {
+        Self {
+            entries: [ProfileEntry {
+                op: ProfileOp::ToColor,
+                duration: 0,
+            }; PROFILE_CAPACITY],
+            start: 0,
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    fn push(&mut self, entry: ProfileEntry) {
+        if self.len == PROFILE_CAPACITY {
+            self.start = (self.start + 1) % PROFILE_CAPACITY;
+            self.len -= 1;
+            self.dropped = self.dropped.saturating_add(1);
+        }
+        self.entries[(self.start + self.len) % PROFILE_CAPACITY] = entry;
+        self.len += 1;
+    }
+
+    /// Entries, oldest first
+    pub fn entries(&self) -> impl Iterator<Item = ProfileEntry> + '_ {
+        (0..self.len).map(|i| self.entries[(self.start + i) % PROFILE_CAPACITY])
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// How many old entries were dropped to make room
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Total duration of the kept entries for `op`
+    pub fn total(&self, op: ProfileOp) -> u64 {
+        self.entries()
+            .filter(|e| e.op == op)
+            .fold(0, |t, e| t.wrapping_add(e.duration))
+    }
+}
+
+impl Default for ProfileReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct State {
+    source: Option<&'static dyn TimeSource>,
+    report: ProfileReport,
+}
+
+static SHARED: TryLock<State> = TryLock::new(State {
+    source: None,
+    report: ProfileReport::new(),
+});
+
+/// Use `source` to time operations from now on
+pub fn set_time_source(source: &'static dyn TimeSource) {
+    SHARED.with(|s| s.source = Some(source));
+}
+
+/// Take the report so far, leaving an empty one
+pub fn take_profile() -> ProfileReport {
+    SHARED.with(|s| core::mem::take(&mut s.report))
+}
+
+/// Times an operation until it's dropped
+pub(crate) struct Timer {
+    op: ProfileOp,
+    source: &'static dyn TimeSource,
+    start: u64,
+}
+
+impl Timer {
+    pub(crate) fn start(op: ProfileOp) -> Option<Self> {
+        let source = SHARED.try_with(|s| s.source).flatten()?;
+        Some(Self {
+            op,
+            source,
+            start: source.now(),
+        })
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let duration = self.source.now().wrapping_sub(self.start);
+        let op = self.op;
+        SHARED.try_with(|s| s.report.push(ProfileEntry { op, duration }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(duration: u64) -> ProfileEntry {
+        let op = match duration % 2 {
+            0 => ProfileOp::Scale,
+            _ => ProfileOp::Export,
+        };
+        ProfileEntry { op, duration }
+    }
+
+    #[test]
+    fn report_keeps_the_newest() {
+        let mut report = ProfileReport::default();
+        assert!(report.is_empty());
+        for d in 0..5 {
+            report.push(entry(d));
+        }
+        assert_eq!(report.len(), 5);
+        assert_eq!(report.dropped(), 0);
+        assert_eq!(report.total(ProfileOp::Scale), 2 + 4);
+        assert_eq!(report.total(ProfileOp::ToColor), 0);
+
+        let extra = 9;
+        for d in 5..(PROFILE_CAPACITY + extra) as u64 {
+            report.push(entry(d));
+        }
+        assert_eq!(report.len(), PROFILE_CAPACITY);
+        assert_eq!(report.dropped(), extra as u32);
+        let kept: alloc::vec::Vec<u64> = report.entries().map(|e| e.duration).collect();
+        let want: alloc::vec::Vec<u64> =
+            (extra as u64..(PROFILE_CAPACITY + extra) as u64).collect();
+        assert_eq!(kept, want);
+    }
+
+    #[test]
+    fn totals_wrap() {
+        let mut report = ProfileReport::default();
+        report.push(ProfileEntry {
+            op: ProfileOp::Convolve,
+            duration: u64::MAX,
+        });
+        report.push(ProfileEntry {
+            op: ProfileOp::Convolve,
+            duration: 2,
+        });
+        assert_eq!(report.total(ProfileOp::Convolve), 1);
+    }
+}
@@ -0,0 +1,205 @@
+//! Gaussian and Laplacian pyramids
+//!
+//! Levels are built in linear light with premultiplied alpha. Each level is
+//! half the size of the one before, rounding up, down to 1x1.
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    blur::gaussian_blur_buffer,
+    composite::{from_linear_premul, to_linear_premul},
+    scale::sample_bilinear,
+    Image, ResXY, WorkPixel,
+};
+
+/// Blur before every downsample, enough to stop aliasing at half size
+const SIGMA: f32 = 1.;
+
+/// Blur and drop every other pixel
+fn down(data: &[WorkPixel], (w, h): ResXY) -> (Vec<WorkPixel>, ResXY) {
+    let blurred = gaussian_blur_buffer(data, (w, h), SIGMA);
+    let res = (w.div_ceil(2), h.div_ceil(2));
+    let mut out = Vec::with_capacity((res.0 * res.1) as usize);
+    for y in 0..res.1 {
+        for x in 0..res.0 {
+            out.push(blurred[(y * 2 * w + x * 2) as usize]);
+        }
+    }
+    (out, res)
+}
+
+/// Bilinearly upsample `data`, of `res`, to `out`, the inverse of [`down`]
+fn up(data: &[WorkPixel], res: ResXY, out: ResXY) -> Vec<WorkPixel> {
+    let mut v = Vec::with_capacity((out.0 * out.1) as usize);
+    for y in 0..out.1 {
+        for x in 0..out.0 {
+            v.push(sample_bilinear(data, res, (x as f32 / 2., y as f32 / 2.)));
+        }
+    }
+    v
+}
+
+impl Image {
+    /// Levels of decreasing size, all linear premultiplied
+    fn linear_pyramid(&self, levels: usize) -> Vec<(Vec<WorkPixel>, ResXY)> {
+        let decode = self.color.transfer().map(|t| t.0);
+        let base = self.data.iter();
+        let base = base.map(|p| to_linear_premul(*p, decode, self.alpha));
+        let mut out = vec![(base.collect::<Vec<_>>(), self.res)];
+        while out.len() < levels {
+            let (data, res) = out.last().unwrap();
+            if *res == (1, 1) {
+                break;
+            }
+            let next = down(data, *res);
+            out.push(next);
+        }
+        out
+    }
+
+    /// An image like this one from linear premultiplied `data`
+    fn from_linear(&self, data: Vec<WorkPixel>, res: ResXY) -> Image {
+        let encode = self.color.transfer().map(|t| t.1);
+        let data = data.into_iter();
+        let data = data.map(|p| from_linear_premul(p, encode, self.alpha));
+        self.derive(data.collect(), res)
+    }
+
+    /// Gaussian pyramid of up to `levels` levels, the first being this image
+    ///
+    /// Each level is the one before blurred and downsampled by 2, in linear
+    /// light. Levels stop at 1x1, so fewer may be returned. A `levels` of
+    /// zero is treated as one.
+    pub fn gaussian_pyramid(&self, levels: usize) -> Vec<Image> {
+        self.linear_pyramid(levels)
+            .into_iter()
+            .map(|(data, res)| self.from_linear(data, res))
+            .collect()
+    }
+
+    /// Laplacian pyramid of up to `levels` levels, see
+    /// [`Image::gaussian_pyramid`]
+    ///
+    /// Every level but the last is the difference between that level of the
+    /// Gaussian pyramid and the next one upsampled, which is a band of
+    /// detail. The last is the smallest Gaussian level.
+    ///
+    /// Unlike the Gaussian pyramid, the values are *linear light with
+    /// premultiplied alpha*, and can be negative, whatever the color space
+    /// and alpha mode tags say. The tags are kept so [`Image::collapse`]
+    /// can restore them.
+    pub fn laplacian_pyramid(&self, levels: usize) -> Vec<Image> {
+        let gauss = self.linear_pyramid(levels);
+        let mut out = Vec::with_capacity(gauss.len());
+        for pair in gauss.windows(2) {
+            let ((fine, res), (coarse, coarse_res)) = (&pair[0], &pair[1]);
+            let upsampled = up(coarse, *coarse_res, *res);
+            let data = fine.iter().zip(upsampled);
+            let data = data.map(|(f, u)| core::array::from_fn(|c| f[c] - u[c]));
+            out.push(self.derive(data.collect(), *res));
+        }
+        let (data, res) = gauss.into_iter().last().unwrap();
+        out.push(self.derive(data, res));
+        out
+    }
+
+    /// Rebuild an image from its [`Image::laplacian_pyramid`]
+    ///
+    /// The levels can be edited first, blending two pyramids level by level
+    /// is the classic multi-band blend. Unedited, this gives back the
+    /// source, except fully transparent pixels lose their color.
+    ///
+    /// # Panics
+    ///
+    /// - If `levels` is empty
+    pub fn collapse(levels: &[Image]) -> Image {
+        let (last, rest) = levels.split_last().expect("Cannot collapse no levels");
+        let mut data = last.data.clone();
+        let mut res = last.res;
+        for level in rest.iter().rev() {
+            let upsampled = up(&data, res, level.res);
+            data = core::iter::zip(&level.data, &upsampled)
+                .map(|(l, u)| core::array::from_fn(|c| l[c] + u[c]))
+                .collect();
+            res = level.res;
+        }
+        levels[0].from_linear(data, res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{max_diff, noise, photo},
+        AlphaMode, ColorSpace,
+    };
+
+    #[test]
+    fn level_sizes() {
+        let img = photo((37, 23));
+        let sizes: Vec<ResXY> = img.gaussian_pyramid(100).iter().map(|l| l.res).collect();
+        assert_eq!(
+            sizes,
+            [(37, 23), (19, 12), (10, 6), (5, 3), (3, 2), (2, 1), (1, 1)]
+        );
+        let laplace: Vec<ResXY> = img.laplacian_pyramid(100).iter().map(|l| l.res).collect();
+        assert_eq!(laplace, sizes);
+        assert_eq!(img.gaussian_pyramid(3).len(), 3);
+        assert_eq!(img.gaussian_pyramid(0).len(), 1);
+    }
+
+    #[test]
+    fn single_pixel() {
+        let img = photo((1, 1));
+        for levels in [0, 1, 5] {
+            let g = img.gaussian_pyramid(levels);
+            assert_eq!(g.len(), 1);
+            assert!(max_diff(g[0].pixels(), img.pixels()) < 1e-6);
+            let l = img.laplacian_pyramid(levels);
+            assert_eq!(l.len(), 1);
+            assert!(max_diff(Image::collapse(&l).pixels(), img.pixels()) < 1e-6);
+        }
+    }
+
+    #[test]
+    fn gaussian_levels_are_smooth() {
+        let img = photo((32, 32));
+        let g = img.gaussian_pyramid(3);
+        assert!(max_diff(g[0].pixels(), img.pixels()) < 1e-5);
+        assert_eq!((g[1].color, g[1].alpha), (img.color, img.alpha));
+        // A flat image stays flat
+        let flat = crate::fixtures::solid((9, 9), [0.25, 0.5, 0.75, 1.]);
+        for level in flat.gaussian_pyramid(4) {
+            assert!(max_diff(level.pixels(), &flat.pixels()[..level.pixels().len()]) < 1e-5);
+        }
+    }
+
+    #[test]
+    fn collapse_reproduces_source() {
+        for res in [(37, 23), (16, 16), (2, 9)] {
+            let img = photo(res);
+            let levels = img.laplacian_pyramid(5);
+            assert!(max_diff(Image::collapse(&levels).pixels(), img.pixels()) < 1e-5);
+        }
+        // With transparency, up to the color of fully transparent pixels
+        let mut seed = 8;
+        let data: Vec<WorkPixel> = (0..20 * 12)
+            .map(|i| {
+                let a = if i % 7 == 0 { 0. } else { noise(&mut seed) };
+                [noise(&mut seed), noise(&mut seed), noise(&mut seed), a]
+            })
+            .collect();
+        for alpha in [AlphaMode::Straight, AlphaMode::Premultiplied] {
+            let mut img = Image::from_parts(data.clone(), (20, 12), ColorSpace::sRGB);
+            img.alpha = alpha;
+            let back = Image::collapse(&img.laplacian_pyramid(4));
+            assert_eq!(back.alpha, alpha);
+            for (b, p) in back.pixels().iter().zip(img.pixels()) {
+                assert!((b[3] - p[3]).abs() < 1e-5);
+                if p[3] > 0.01 {
+                    assert!(max_diff(&[*b], &[*p]) < 1e-3, "{alpha:?} {b:?} {p:?}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Processing only part of an image
+//!
+//! The color space tag always describes the whole image, converting a region
+//! leaves it alone. Keeping the regions consistent is up to the caller,
+//! usually by converting the region back before anything else touches it.
+use core::ops::Range;
+
+use crate::{convert::convert_image_rows, ColorSpace, Image, ImageError, ResXY, WorkPixel, XY};
+
+/// Index ranges of each row in a rectangle, in an image `width` wide
+fn region_rows(width: u32, (x, y): XY, (w, h): ResXY) -> impl Iterator<Item = Range<usize>> {
+    let (width, x, y, w, h) = (
+        width as usize,
+        x as usize,
+        y as usize,
+        w as usize,
+        h as usize,
+    );
+    (y..y + h).map(move |row| {
+        let start = row * width + x;
+        start..start + w
+    })
+}
+
+impl Image {
+    /// Convert the rectangle at `origin` of `size` from the image's color
+    /// space to `color`
+    ///
+    /// Pixels outside the rectangle are untouched, and so is the image's color
+    /// space, which always describes the whole image. Keeping the region
+    /// consistent is up to the caller, usually by converting it back before
+    /// anything else touches it.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `size` is zero
+    /// - [`ImageError::OutOfBounds`] if the rectangle doesn't fit in the image
+    pub fn to_color_region(
+        &mut self,
+        color: ColorSpace,
+        origin: XY,
+        size: ResXY,
+    ) -> Result<(), ImageError> {
+        self.check_rect(origin, size)?;
+        let from = self.color;
+        for row in region_rows(self.width(), origin, size) {
+            convert_image_rows(&mut self.data[row], self.alpha, from, color);
+        }
+        self.check();
+        Ok(())
+    }
+
+    /// [`Image::map_pixels`], but only for the rectangle at `origin` of `size`
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `size` is zero
+    /// - [`ImageError::OutOfBounds`] if the rectangle doesn't fit in the image
+    pub fn map_pixels_region(
+        &mut self,
+        origin: XY,
+        size: ResXY,
+        mut f: impl FnMut(WorkPixel) -> WorkPixel,
+    ) -> Result<(), ImageError> {
+        self.check_rect(origin, size)?;
+        for row in region_rows(self.width(), origin, size) {
+            for p in &mut self.data[row] {
+                *p = f(*p);
+            }
+        }
+        self.check();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::photo, AlphaMode};
+
+    fn outside_untouched(before: &Image, after: &Image, (x, y): XY, (w, h): ResXY) {
+        for (i, (a, b)) in before.pixels().iter().zip(after.pixels()).enumerate() {
+            let (px, py) = (i as u32 % before.width(), i as u32 / before.width());
+            if !((x..x + w).contains(&px) && (y..y + h).contains(&py)) {
+                assert_eq!(a.map(f32::to_bits), b.map(f32::to_bits));
+            }
+        }
+    }
+
+    #[test]
+    fn to_color_region() {
+        for alpha in [AlphaMode::Straight, AlphaMode::Premultiplied] {
+            let mut img = photo((13, 9));
+            img.to_alpha_mode(alpha);
+            let before = img.clone();
+            let (origin, size) = ((3, 2), (6, 5));
+            img.to_color_region(ColorSpace::DisplayP3, origin, size)
+                .unwrap();
+            assert_eq!(img.color, ColorSpace::sRGB);
+            outside_untouched(&before, &img, origin, size);
+
+            let mut want = before.clone();
+            want.crop(origin, size).unwrap();
+            want.to_color(ColorSpace::DisplayP3);
+            let mut got = img.clone();
+            got.crop(origin, size).unwrap();
+            assert_eq!(got.pixels(), want.pixels());
+        }
+    }
+
+    #[test]
+    fn map_pixels_region() {
+        let mut img = photo((10, 10));
+        let before = img.clone();
+        img.map_pixels_region((4, 0), (6, 3), |p| [1. - p[0], p[1], p[2], p[3]])
+            .unwrap();
+        outside_untouched(&before, &img, (4, 0), (6, 3));
+        let i = 2 * 10 + 9;
+        assert_eq!(img.pixels()[i][0], 1. - before.pixels()[i][0]);
+    }
+
+    #[cfg(all(feature = "validate", debug_assertions))]
+    #[test]
+    #[should_panic = "non-finite pixel"]
+    fn regions_are_validated() {
+        let mut img = photo((4, 4));
+        let _ = img.map_pixels_region((1, 1), (2, 2), |_| [f32::NAN; 4]);
+    }
+
+    #[test]
+    fn bad_regions() {
+        let mut img = photo((10, 10));
+        let srgb = ColorSpace::sRGBLinear;
+        assert_eq!(
+            img.to_color_region(srgb, (5, 5), (6, 1)),
+            Err(ImageError::OutOfBounds)
+        );
+        assert_eq!(
+            img.to_color_region(srgb, (10, 0), (1, 1)),
+            Err(ImageError::OutOfBounds)
+        );
+        assert_eq!(
+            img.map_pixels_region((u32::MAX, 0), (2, 2), |p| p),
+            Err(ImageError::OutOfBounds)
+        );
+        assert_eq!(
+            img.to_color_region(srgb, (0, 0), (0, 3)),
+            Err(ImageError::InvalidArgument)
+        );
+        assert_eq!(img.to_bytes(), photo((10, 10)).to_bytes());
+    }
+}
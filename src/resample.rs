@@ -0,0 +1,302 @@
+//! Filter weights for resampling outside [`Image::scale_with`]
+//!
+//! These are exactly the weights [`Image::scale_with`] uses. It filters
+//! separably, each row with [`apply_row`], then down each column with the
+//! same kind of weights for the height.
+//!
+//! [`Image::scale_with`]: crate::Image::scale_with
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    scale::{corner_ratio, cubic_weight, nearest, Sample},
+    ScaleFilter, WorkPixel, F32,
+};
+
+/// Source contributions to one destination pixel along an axis
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Contrib {
+    pub(crate) start: u32,
+    pub(crate) weights: Vec<f32>,
+}
+
+/// The source pixels and weights for each destination index along one axis
+///
+/// Taps that would fall past the edges are clamped to the edge pixel, so
+/// each destination's weights are contiguous and, except in degenerate
+/// cases, sum to 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterWeights {
+    pub(crate) contribs: Vec<Contrib>,
+}
+
+impl FilterWeights {
+    /// Weights for scaling `src_len` pixels to `dst_len` with `filter`
+    ///
+    /// [`ScaleFilter::Nearest`] has a single weight of 1 per destination,
+    /// [`ScaleFilter::Bilinear`] up to two, mapping corners to corners.
+    /// From an empty source every destination has no weights at all, so
+    /// filtering gives zero.
+    pub fn compute(src_len: u32, dst_len: u32, filter: ScaleFilter) -> Self {
+        if src_len == 0 {
+            return Self::empty(dst_len);
+        }
+        let contribs = match filter {
+            ScaleFilter::Nearest => (0..dst_len)
+                .map(|d| Contrib {
+                    start: nearest(d, src_len, dst_len),
+                    weights: vec![1.],
+                })
+                .collect(),
+            ScaleFilter::Bilinear => {
+                let ratio = corner_ratio(src_len, dst_len);
+                (0..dst_len)
+                    .map(|d| {
+                        let s = d as f32 * ratio;
+                        let start = (s.floor() as u32).min(src_len - 1);
+                        let t = s - start as f32;
+                        let weights = if start + 1 < src_len && t > 0. {
+                            vec![1. - t, t]
+                        } else {
+                            vec![1.]
+                        };
+                        Contrib { start, weights }
+                    })
+                    .collect()
+            }
+            _ => return Self::compute_at(filter, 0., src_len as f32, src_len, dst_len),
+        };
+        Self { contribs }
+    }
+
+    /// [`ScaleFilter::Box`] or [`ScaleFilter::Cubic`] weights covering
+    /// `span` source pixels from `offset`, with indices limited to
+    /// `0..limit`
+    pub(crate) fn compute_at(
+        filter: ScaleFilter,
+        offset: f32,
+        span: f32,
+        limit: u32,
+        dst: u32,
+    ) -> Self {
+        if limit == 0 {
+            return Self::empty(dst);
+        }
+        let contribs = match filter {
+            ScaleFilter::Cubic { b, c } => cubic_contribs(offset, span, limit, dst, b, c),
+            _ => box_contribs(offset, span, limit, dst),
+        };
+        Self { contribs }
+    }
+
+    /// No weights for each of `dst` destination indices
+    fn empty(dst: u32) -> Self {
+        let contribs = (0..dst)
+            .map(|_| Contrib {
+                start: 0,
+                weights: Vec::new(),
+            })
+            .collect();
+        Self { contribs }
+    }
+
+    /// Number of destination indices
+    pub fn len(&self) -> usize {
+        self.contribs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contribs.is_empty()
+    }
+
+    /// The first source index and the weights from there for destination
+    /// index `d`
+    pub fn get(&self, d: usize) -> Option<(u32, &[f32])> {
+        self.contribs.get(d).map(|c| (c.start, &c.weights[..]))
+    }
+
+    /// [`FilterWeights::get`] for every destination index, in order
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &[f32])> + '_ {
+        self.contribs.iter().map(|c| (c.start, &c.weights[..]))
+    }
+}
+
+/// Box filter contributions, see [`FilterWeights::compute_at`]
+fn box_contribs(offset: f32, span: f32, limit: u32, dst: u32) -> Vec<Contrib> {
+    let r = span / dst as f32;
+    (0..dst)
+        .map(|d| {
+            let lo = offset + d as f32 * r;
+            let hi = (offset + (d + 1) as f32 * r).min(limit as f32);
+            let start = (lo.floor() as u32).min(limit - 1);
+            let end = (hi.ceil() as u32).clamp(start + 1, limit);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|s| (hi.min((s + 1) as f32) - lo.max(s as f32)).max(0.))
+                .collect();
+            let sum: f32 = weights.iter().sum();
+            if sum > 0. {
+                weights.iter_mut().for_each(|w| *w /= sum);
+            } else {
+                weights.iter_mut().for_each(|w| *w = 1.);
+            }
+            Contrib { start, weights }
+        })
+        .collect()
+}
+
+/// Cubic filter contributions, see [`FilterWeights::compute_at`]
+///
+/// Taps past the edges are folded into the edge pixel, and the weights are
+/// normalized so they always sum to 1.
+fn cubic_contribs(offset: f32, span: f32, limit: u32, dst: u32, b: f32, c: f32) -> Vec<Contrib> {
+    let r = span / dst as f32;
+    // Widen the kernel when shrinking
+    let scale = r.max(1.);
+    let support = 2. * scale;
+    let last = limit as i64 - 1;
+    (0..dst)
+        .map(|d| {
+            let center = offset + (d as f32 + 0.5) * r - 0.5;
+            let lo = (center - support).ceil() as i64;
+            let hi = (center + support).floor() as i64;
+            let start = lo.clamp(0, last);
+            let end = hi.clamp(start, last);
+            let mut weights = vec![0.; (end - start + 1) as usize];
+            for i in lo..=hi {
+                let w = cubic_weight(b, c, (i as f32 - center) / scale);
+                weights[(i.clamp(start, end) - start) as usize] += w;
+            }
+            let sum: f32 = weights.iter().sum();
+            if sum != 0. {
+                weights.iter_mut().for_each(|w| *w /= sum);
+            }
+            Contrib {
+                start: start as u32,
+                weights,
+            }
+        })
+        .collect()
+}
+
+/// Filter `src` into `dst` with `weights`, one output pixel per destination
+/// index
+///
+/// # Panics
+///
+/// - If `dst` isn't [`FilterWeights::len`] long, or `src` is too short for
+///   the weights
+pub fn apply_row(weights: &FilterWeights, src: &[WorkPixel], dst: &mut [WorkPixel]) {
+    assert_eq!(dst.len(), weights.len(), "Destination length mismatch");
+    filter_row(weights, src, dst)
+}
+
+/// [`apply_row`] for any [`Sample`]
+pub(crate) fn filter_row<T: Sample>(weights: &FilterWeights, src: &[T], dst: &mut [T]) {
+    for (d, c) in dst.iter_mut().zip(&weights.contribs) {
+        let mut acc = T::default();
+        for (k, w) in c.weights.iter().enumerate() {
+            acc = acc.mul_add(src[c.start as usize + k], *w);
+        }
+        *d = acc;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [ScaleFilter; 5] = [
+        ScaleFilter::Nearest,
+        ScaleFilter::Bilinear,
+        ScaleFilter::Box,
+        ScaleFilter::MITCHELL,
+        ScaleFilter::CATMULL_ROM,
+    ];
+
+    const SIZES: [(u32, u32); 8] = [
+        (1, 1),
+        (1, 5),
+        (5, 1),
+        (7, 3),
+        (3, 7),
+        (10, 10),
+        (16, 9),
+        (9, 31),
+    ];
+
+    #[test]
+    fn from_nothing() {
+        for filter in FILTERS {
+            let w = FilterWeights::compute(0, 3, filter);
+            assert_eq!(w.len(), 3);
+            assert!(w.iter().all(|(_, w)| w.is_empty()));
+            let mut out = [[1.; 4]; 3];
+            apply_row(&w, &[], &mut out);
+            assert_eq!(out, [[0.; 4]; 3]);
+        }
+    }
+
+    #[test]
+    fn weights_sum_to_one() {
+        for filter in FILTERS {
+            for (src, dst) in SIZES {
+                let w = FilterWeights::compute(src, dst, filter);
+                assert_eq!(w.len(), dst as usize);
+                for (d, (start, weights)) in w.iter().enumerate() {
+                    let sum: f32 = weights.iter().sum();
+                    assert!(
+                        (sum - 1.).abs() < 1e-5,
+                        "{filter:?} {src} to {dst} at {d}: {sum}"
+                    );
+                    // Every tap is in the source
+                    assert!(start as usize + weights.len() <= src as usize);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn edges_are_clamped() {
+        // The first and last destinations of a cubic reach past the edges,
+        // those taps land on the edge pixels rather than being dropped
+        let w = FilterWeights::compute(8, 8, ScaleFilter::CATMULL_ROM);
+        let (start, first) = w.get(0).unwrap();
+        assert_eq!((start, first.len()), (0, 3));
+        assert!(
+            (first[0] - 1.).abs() < 1e-5 && first[1].abs() < 1e-5,
+            "{first:?}"
+        );
+        let (start, last) = w.get(7).unwrap();
+        assert_eq!(start as usize + last.len(), 8);
+        // Shrinking a lot, the edge pixels still count
+        let w = FilterWeights::compute(12, 2, ScaleFilter::MITCHELL);
+        let (start, first) = w.get(0).unwrap();
+        assert_eq!(start, 0);
+        assert!(first[0] > 0., "{first:?}");
+        assert!(w.get(2).is_none());
+    }
+
+    #[test]
+    fn constant_rows_stay_constant() {
+        let c = [0.25, 0.5, 0.75, 1.];
+        for filter in FILTERS {
+            for (src, dst) in SIZES {
+                let w = FilterWeights::compute(src, dst, filter);
+                let mut out = vec![[0.; 4]; dst as usize];
+                apply_row(&w, &vec![c; src as usize], &mut out);
+                for p in out {
+                    assert!(
+                        (0..4).all(|i| (p[i] - c[i]).abs() < 1e-5),
+                        "{filter:?} {p:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic = "Destination length mismatch"]
+    fn apply_row_checks_length() {
+        let w = FilterWeights::compute(4, 3, ScaleFilter::Box);
+        apply_row(&w, &[[0.; 4]; 4], &mut [[0.; 4]; 2]);
+    }
+}
@@ -0,0 +1,355 @@
+//! Recovering camera response curves from bracketed exposures
+use alloc::vec::Vec;
+
+use nalgebra::{DMatrix, DVector};
+
+use crate::{
+    alpha_converter, layout::quantize, AlphaMode, ColorSpace, Image, ImageError, WorkPixel, F32,
+};
+
+/// Encoded levels the curve is solved for
+const LEVELS: usize = 256;
+
+/// Pixels sampled, at most
+const SAMPLES: usize = 400;
+
+/// Weight of the smoothness of the curve against fitting the samples
+const SMOOTHNESS: f64 = 16.;
+
+/// A curve per color channel, from encoded values to linear light, like a
+/// camera's response inverted
+///
+/// Each curve has an entry for each 8 bit level, and is interpolated
+/// between them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelLut {
+    curves: [[f32; LEVELS]; 3],
+}
+
+impl ChannelLut {
+    /// From a curve for each of R, G, and B, with an entry per 8 bit level
+    pub fn new(curves: [[f32; LEVELS]; 3]) -> Self {
+        Self { curves }
+    }
+
+    /// The curve of each of R, G, and B, with an entry per 8 bit level
+    pub fn curves(&self) -> &[[f32; LEVELS]; 3] {
+        &self.curves
+    }
+
+    /// Linear value of encoded `v`, clamped to `0..=1`, in `channel`, 0 to
+    /// 2 for R, G, and B
+    ///
+    /// # Panics
+    ///
+    /// - If `channel` isn't 0, 1, or 2
+    pub fn get(&self, channel: usize, v: f32) -> f32 {
+        let curve = &self.curves[channel];
+        let t = if v.is_nan() { 0. } else { v.clamp(0., 1.) } * (LEVELS - 1) as f32;
+        let i = (t as usize).min(LEVELS - 2);
+        let f = t - i as f32;
+        curve[i] + (curve[i + 1] - curve[i]) * f
+    }
+}
+
+/// Debevec weight of level `z`, trusting the middle over the ends
+fn weight(z: usize) -> f64 {
+    z.min(LEVELS - 1 - z) as f64
+}
+
+/// Pixels spread over a grid covering `res`
+fn sample_points((w, h): (u32, u32)) -> Vec<usize> {
+    let count = SAMPLES.min(w as usize * h as usize);
+    let aspect = w as f32 / h as f32;
+    let across = (((count as f32) * aspect).sqrt().round() as u32).clamp(1, w);
+    let down = ((count as u32).div_ceil(across)).clamp(1, h);
+    let mut points = Vec::with_capacity((across * down) as usize);
+    for j in 0..down {
+        for i in 0..across {
+            let x = (2 * i + 1) as u64 * w as u64 / (2 * across) as u64;
+            let y = (2 * j + 1) as u64 * h as u64 / (2 * down) as u64;
+            points.push((y * w as u64 + x) as usize);
+        }
+    }
+    points
+}
+
+/// The response curve of one channel, as `ln` of exposure for each level,
+/// from `levels` of each sample in each frame
+///
+/// This is Debevec and Malik's least squares system, solved through its
+/// normal equations, so each row only touches a few unknowns.
+///
+/// `None` if no sample is unclipped in two frames.
+fn solve(levels: &[Vec<usize>], log_times: &[f64]) -> Option<[f64; LEVELS]> {
+    // Samples that aren't clipped in at least two frames, as nothing else
+    // relates one exposure to another
+    let samples: Vec<&Vec<usize>> = levels
+        .iter()
+        .filter(|s| s.iter().filter(|z| weight(**z) > 0.).count() >= 2)
+        .collect();
+    if samples.is_empty() {
+        return None;
+    }
+    let n = LEVELS + samples.len();
+    let mut ata = DMatrix::<f64>::zeros(n, n);
+    let mut atb = DVector::<f64>::zeros(n);
+    let mut row = |entries: &[(usize, f64)], b: f64| {
+        for &(i, vi) in entries {
+            atb[i] += vi * b;
+            for &(j, vj) in entries {
+                ata[(i, j)] += vi * vj;
+            }
+        }
+    };
+
+    // g(z) - ln(E) = ln(t), for each sample in each frame
+    for (s, zs) in samples.iter().enumerate() {
+        for (z, t) in zs.iter().zip(log_times) {
+            let w = weight(*z);
+            row(&[(*z, w), (LEVELS + s, -w)], w * t);
+        }
+    }
+    // Middle gray is 1, as exposure is only known relatively
+    row(&[(LEVELS / 2, 1.)], 0.);
+    for z in 1..LEVELS - 1 {
+        let w = SMOOTHNESS * weight(z);
+        row(&[(z - 1, w), (z, -2. * w), (z + 1, w)], 0.);
+    }
+
+    let x = ata.cholesky()?.solve(&atb);
+    Some(core::array::from_fn(|z| x[z]))
+}
+
+/// Estimate the response curve of the camera that took `frames`, each with
+/// its exposure time, in any unit
+///
+/// This uses Debevec and Malik's method on a few hundred pixels spread over
+/// the frames, from their 8 bit levels as stored, straight alpha. Each
+/// curve is made increasing, and scaled so encoded 1 is linear 1, giving
+/// radiance relative to what just clips in an exposure of 1, and it's
+/// only as good as the spread of levels the frames have.
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidArgument`] if there are fewer than two frames, if
+///   any exposure time isn't positive and finite, if the frames are
+///   empty, or there isn't enough in them to solve for a curve
+/// - [`ImageError::DimensionMismatch`] if the frames are different sizes
+/// - [`ImageError::ColorSpaceMismatch`] if the frames have different
+///   color spaces
+pub fn estimate_response_curve(frames: &[(&Image, f32)]) -> Result<ChannelLut, ImageError> {
+    let [(first, _), rest @ ..] = frames else {
+        return Err(ImageError::InvalidArgument);
+    };
+    if rest.is_empty() || first.data.is_empty() {
+        return Err(ImageError::InvalidArgument);
+    }
+    for (f, _) in rest {
+        first.check_blend(f)?;
+    }
+    if frames.iter().any(|(_, t)| !(t.is_finite() && *t > 0.)) {
+        return Err(ImageError::InvalidArgument);
+    }
+    let log_times: Vec<f64> = frames.iter().map(|(_, t)| libm::log(*t as f64)).collect();
+    let points = sample_points(first.res);
+
+    let mut curves = [[0.; LEVELS]; 3];
+    for (c, curve) in curves.iter_mut().enumerate() {
+        let levels: Vec<Vec<usize>> = points
+            .iter()
+            .map(|i| {
+                frames
+                    .iter()
+                    .map(|(f, _)| {
+                        let p = alpha_converter(f.alpha, AlphaMode::Straight)(f.data[*i]);
+                        quantize(p[c], (LEVELS - 1) as f32) as usize
+                    })
+                    .collect()
+            })
+            .collect();
+        let g = solve(&levels, &log_times).ok_or(ImageError::InvalidArgument)?;
+        if g.iter().any(|g| !g.is_finite()) {
+            return Err(ImageError::InvalidArgument);
+        }
+        let top = g[LEVELS - 1];
+        let mut last = 0f32;
+        for (out, g) in curve.iter_mut().zip(g) {
+            last = last.max(libm::exp(g - top) as f32);
+            *out = last;
+        }
+    }
+    Ok(ChannelLut::new(curves))
+}
+
+impl Image {
+    /// Linearize this image with a response curve, like from
+    /// [`estimate_response_curve`]
+    ///
+    /// Each color channel, straight alpha, goes through its curve in `lut`.
+    /// The curve doesn't know the primaries, so the result is
+    /// [`ColorSpace::sRGBLinear`] for images with sRGB primaries, and
+    /// [`ColorSpace::AsIs`] for the rest.
+    pub fn apply_response_inverse(&mut self, lut: &ChannelLut) {
+        let (to_straight, back) = (
+            alpha_converter(self.alpha, AlphaMode::Straight),
+            alpha_converter(AlphaMode::Straight, self.alpha),
+        );
+        for p in &mut self.data {
+            let s = to_straight(*p);
+            let s: WorkPixel = [lut.get(0, s[0]), lut.get(1, s[1]), lut.get(2, s[2]), s[3]];
+            *p = back(s);
+        }
+        self.color = match self.color {
+            ColorSpace::sRGB | ColorSpace::sRGBLinear | ColorSpace::SimplesRGB => {
+                ColorSpace::sRGBLinear
+            }
+            ColorSpace::DisplayP3 | ColorSpace::AdobeRgb | ColorSpace::AsIs => ColorSpace::AsIs,
+        };
+        self.check();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::noise;
+
+    const GAMMA: f32 = 2.2;
+    const RES: (u32, u32) = (64, 48);
+
+    /// Linear radiance of a scene, over 12 stops, different in each channel
+    fn scene() -> Vec<[f32; 3]> {
+        let mut seed = 17;
+        (0..RES.0 * RES.1)
+            .map(|_| [(); 3].map(|_| libm::exp2f(-12. * noise(&mut seed))))
+            .collect()
+    }
+
+    /// `scene` exposed for `time` through a pure `GAMMA` response, clipping
+    /// at 1, in 8 bits
+    fn frame(scene: &[[f32; 3]], time: f32) -> Image {
+        let data = scene
+            .iter()
+            .map(|e| {
+                let [r, g, b] = e.map(|e| {
+                    let z = (e * time).min(1.).powf(1. / GAMMA);
+                    quantize(z, 255.) / 255.
+                });
+                [r, g, b, 1.]
+            })
+            .collect();
+        Image::from_parts(data, RES, ColorSpace::AsIs)
+    }
+
+    const TIMES: [f32; 4] = [1., 4., 16., 64.];
+
+    fn frames(scene: &[[f32; 3]]) -> Vec<(Image, f32)> {
+        TIMES.iter().map(|t| (frame(scene, *t), *t)).collect()
+    }
+
+    fn estimate(frames: &[(Image, f32)]) -> Result<ChannelLut, ImageError> {
+        let frames: Vec<(&Image, f32)> = frames.iter().map(|(f, t)| (f, *t)).collect();
+        estimate_response_curve(&frames)
+    }
+
+    #[test]
+    fn recovers_a_gamma() {
+        let lut = estimate(&frames(&scene())).unwrap();
+        for curve in lut.curves() {
+            assert!(curve.windows(2).all(|w| w[0] <= w[1]), "increasing");
+            assert_eq!(curve[LEVELS - 1], 1.);
+            for (z, g) in curve.iter().enumerate().skip(LEVELS / 8) {
+                let want = (z as f32 / 255.).powf(GAMMA);
+                let off = (g / want - 1.).abs();
+                assert!(off < 0.05, "{z} {g} {want}");
+            }
+        }
+    }
+
+    #[test]
+    fn any_time_unit() {
+        let scene = scene();
+        let frames = frames(&scene);
+        let scaled: Vec<(Image, f32)> =
+            frames.iter().map(|(f, t)| (f.clone(), t / 1000.)).collect();
+        let (a, b) = (estimate(&frames).unwrap(), estimate(&scaled).unwrap());
+        for (a, b) in a.curves().iter().flatten().zip(b.curves().iter().flatten()) {
+            assert!((a - b).abs() <= 1e-4 * a.max(1e-3), "{a} {b}");
+        }
+    }
+
+    #[test]
+    fn inverse_linearizes() {
+        let scene = scene();
+        let lut = estimate(&frames(&scene)).unwrap();
+        let mut img = frame(&scene, 4.);
+        let encoded = img.data.clone();
+        img.apply_response_inverse(&lut);
+        assert_eq!(img.color, ColorSpace::AsIs);
+        for (p, e) in img.data.iter().zip(&encoded) {
+            for c in 0..3 {
+                let want = e[c].powf(GAMMA);
+                assert!((p[c] - want).abs() < 0.05 * want + 2e-3, "{p:?} {e:?}");
+            }
+            assert_eq!(p[3], 1.);
+        }
+        let mut srgb = frame(&scene, 4.);
+        srgb.color = ColorSpace::sRGB;
+        srgb.apply_response_inverse(&lut);
+        assert_eq!(srgb.color, ColorSpace::sRGBLinear);
+        assert_eq!(srgb.data, img.data);
+    }
+
+    #[test]
+    fn lut_interpolates() {
+        let mut curve = [0.; LEVELS];
+        for (z, c) in curve.iter_mut().enumerate() {
+            *c = z as f32;
+        }
+        let lut = ChannelLut::new([curve; 3]);
+        assert_eq!(lut.get(0, 0.), 0.);
+        assert_eq!(lut.get(1, 1.), 255.);
+        assert_eq!(lut.get(2, 2.), 255.);
+        assert_eq!(lut.get(2, -1.), 0.);
+        assert_eq!(lut.get(2, f32::NAN), 0.);
+        assert!((lut.get(0, 0.5) - 127.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn insufficient_input() {
+        let scene = scene();
+        let frames = frames(&scene);
+        let (a, b) = (&frames[0].0, &frames[1].0);
+        let err = |f: &[(&Image, f32)]| estimate_response_curve(f).err();
+        assert_eq!(err(&[]), Some(ImageError::InvalidArgument));
+        assert_eq!(err(&[(a, 1.)]), Some(ImageError::InvalidArgument));
+        for t in [0., -1., f32::NAN, f32::INFINITY] {
+            assert_eq!(err(&[(a, 1.), (b, t)]), Some(ImageError::InvalidArgument));
+        }
+        let mut small = a.clone();
+        small.crop((0, 0), (8, 8)).unwrap();
+        assert_eq!(
+            err(&[(a, 1.), (&small, 4.)]),
+            Some(ImageError::DimensionMismatch)
+        );
+        let mut other = b.clone();
+        other.color = ColorSpace::sRGB;
+        assert_eq!(
+            err(&[(a, 1.), (&other, 4.)]),
+            Some(ImageError::ColorSpaceMismatch)
+        );
+        let empty = Image::from_bytes(&[], (0, 0), ColorSpace::AsIs);
+        assert_eq!(
+            err(&[(&empty, 1.), (&empty, 4.)]),
+            Some(ImageError::InvalidArgument)
+        );
+        // Nothing but clipped levels has no curve to find
+        let mut black = a.clone();
+        black.map_pixels(|_| [0., 0., 0., 1.]);
+        assert_eq!(
+            err(&[(&black, 1.), (&black, 4.)]),
+            Some(ImageError::InvalidArgument)
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Run-length encoded images
+use alloc::vec::Vec;
+
+use crate::{
+    layout::{prepare, validate_buffer},
+    AlphaMode, ColorSpace, Image, ImageError, Orientation, PixelFormat, ResXY, WorkPixel,
+};
+
+/// A run of `len` pixels of the same color
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Run {
+    len: u32,
+    pixel: WorkPixel,
+}
+
+/// A run-length encoded [`Image`], for mostly flat images like UI
+/// backgrounds
+///
+/// Runs never cross rows.
+#[derive(Debug, Clone)]
+pub struct RleImage {
+    runs: Vec<Run>,
+    res: ResXY,
+    color: ColorSpace,
+    alpha: AlphaMode,
+}
+
+impl RleImage {
+    /// Encode `img`, merging neighboring pixels whose channels are all within
+    /// `tolerance` of the first pixel of the run
+    ///
+    /// A `tolerance` of `0` is lossless.
+    pub fn encode(img: &Image, tolerance: f32) -> Self {
+        let mut runs = Vec::new();
+        for row in img.data.chunks_exact(img.width().max(1) as usize) {
+            let mut run = Run {
+                len: 0,
+                pixel: row[0],
+            };
+            for p in row {
+                let close = (0..4).all(|c| (p[c] - run.pixel[c]).abs() <= tolerance);
+                if !close {
+                    runs.push(run);
+                    run = Run { len: 0, pixel: *p };
+                }
+                run.len += 1;
+            }
+            runs.push(run);
+        }
+        runs.shrink_to_fit();
+        Self {
+            runs,
+            res: img.res,
+            color: img.color,
+            alpha: img.alpha,
+        }
+    }
+
+    /// Expand back into an [`Image`]
+    pub fn decode(&self) -> Image {
+        let mut data = Vec::with_capacity(self.res.0 as usize * self.res.1 as usize);
+        for run in &self.runs {
+            data.extend((0..run.len).map(|_| run.pixel));
+        }
+        let mut img = Image::from_parts(data, self.res, self.color);
+        img.alpha = self.alpha;
+        img
+    }
+
+    pub fn width(&self) -> u32 {
+        self.res.0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.res.1
+    }
+
+    pub fn color(&self) -> ColorSpace {
+        self.color
+    }
+
+    /// Number of runs
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Heap memory used by the runs, in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.runs.capacity() * size_of::<Run>()
+    }
+
+    /// Export into `out` in the pixel format `format`, in `orientation`,
+    /// like [`Image::write_bytes_rotated`], without decoding
+    ///
+    /// `out` is the size of the image in `orientation`. Each run is
+    /// converted once, and then copied.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `stride` is smaller than a row
+    /// - [`ImageError::BufferSize`] if `out` is too small
+    pub fn blit_to(
+        &self,
+        out: &mut [u8],
+        format: PixelFormat,
+        stride: usize,
+        orientation: Orientation,
+    ) -> Result<(), ImageError> {
+        let w = self.res.0;
+        let bpp = format.bytes_per_pixel();
+        validate_buffer(out, format, orientation.oriented_res(self.res), stride)?;
+        let transfer = self.color.transfer();
+        let mut bytes = [0; 8];
+        let bytes = &mut bytes[..bpp];
+
+        let (mut x, mut y) = (0, 0);
+        for run in &self.runs {
+            format.encode(prepare(run.pixel, format, self.alpha, transfer), bytes);
+            if orientation == Orientation::IDENTITY {
+                let start = y as usize * stride + x as usize * bpp;
+                for o in out[start..start + run.len as usize * bpp].chunks_exact_mut(bpp) {
+                    o.copy_from_slice(bytes);
+                }
+            } else {
+                for x in x..x + run.len {
+                    let (dx, dy) = orientation.dest((x, y), self.res);
+                    let i = dy as usize * stride + dx as usize * bpp;
+                    out[i..i + bpp].copy_from_slice(bytes);
+                }
+            }
+            x += run.len;
+            if x == w {
+                x = 0;
+                y += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{
+        fixtures::{max_diff, noise, photo, solid},
+        layout::required_size,
+        Rotation,
+    };
+
+    /// Flat bands with a noisy strip, like a UI background
+    fn background() -> Image {
+        let mut seed = 9;
+        let mut data = Vec::new();
+        for y in 0..24u32 {
+            for x in 0..20u32 {
+                let v = if (8..12).contains(&y) && x > 4 {
+                    [noise(&mut seed), noise(&mut seed), noise(&mut seed), 1.]
+                } else if x < 10 {
+                    [0.2, 0.4, 0.6, 1.]
+                } else {
+                    [0.9, 0.1, 0.1, 0.5]
+                };
+                data.push(v);
+            }
+        }
+        Image::from_parts(data, (20, 24), ColorSpace::sRGB)
+    }
+
+    #[test]
+    fn lossless_round_trip() {
+        let img = background();
+        let rle = RleImage::encode(&img, 0.);
+        let back = rle.decode();
+        assert_eq!(back.res, img.res);
+        assert_eq!((back.color, back.alpha), (img.color, img.alpha));
+        assert_eq!(back.pixels(), img.pixels());
+        // Two runs for each flat row, one for each noisy pixel after the first
+        // band
+        assert_eq!(rle.runs(), 20 * 2 + 4 * (1 + 15));
+    }
+
+    #[test]
+    fn solid_is_tiny() {
+        let img = solid((64, 64), [0.3, 0.3, 0.3, 1.]);
+        let rle = RleImage::encode(&img, 0.);
+        assert_eq!(rle.runs(), 64);
+        let full = 64 * 64 * size_of::<WorkPixel>();
+        assert!(rle.memory_usage() * 50 < full, "{}", rle.memory_usage());
+        assert_eq!(rle.decode().pixels(), img.pixels());
+    }
+
+    #[test]
+    fn lossy_within_tolerance() {
+        let img = photo((48, 32));
+        for tolerance in [0.01, 0.05, 0.2] {
+            let rle = RleImage::encode(&img, tolerance);
+            assert!(rle.runs() < 48 * 32);
+            assert!(max_diff(rle.decode().pixels(), img.pixels()) <= tolerance);
+        }
+        let tight = RleImage::encode(&img, 0.01).runs();
+        let loose = RleImage::encode(&img, 0.2).runs();
+        assert!(loose < tight);
+    }
+
+    #[test]
+    fn blit_matches_decode() {
+        let rle = RleImage::encode(&background(), 0.);
+        let img = rle.decode();
+        for format in [PixelFormat::Rgba8888, PixelFormat::Rgb565Le] {
+            for rotation in [Rotation::R0, Rotation::R90, Rotation::R180, Rotation::R270] {
+                for mirror in [false, true] {
+                    let (w, _) = rotation.rotated_res(img.res);
+                    // Padded, which neither side writes
+                    let stride = format.bytes_per_pixel() * w as usize + 3;
+                    let len = required_size(format, rotation.rotated_res(img.res), stride).unwrap();
+                    let (mut direct, mut decoded) = (vec![0xaa; len], vec![0xaa; len]);
+                    rle.blit_to(
+                        &mut direct,
+                        format,
+                        stride,
+                        Orientation::new(rotation, mirror),
+                    )
+                    .unwrap();
+                    let mut flipped = img.clone();
+                    if mirror {
+                        flipped.flip_horizontal();
+                    }
+                    flipped
+                        .write_bytes_rotated(&mut decoded, rotation, format, stride)
+                        .unwrap();
+                    assert_eq!(direct, decoded, "{format:?} {rotation:?} {mirror}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blit_errors() {
+        let rle = RleImage::encode(&background(), 0.);
+        let mut out = vec![0; 20 * 24 * 4 - 1];
+        assert!(matches!(
+            rle.blit_to(&mut out, PixelFormat::Rgba8888, 80, Orientation::IDENTITY),
+            Err(ImageError::BufferSize { .. })
+        ));
+        assert_eq!(
+            rle.blit_to(&mut out, PixelFormat::Rgba8888, 79, Orientation::IDENTITY),
+            Err(ImageError::InvalidArgument)
+        );
+    }
+}
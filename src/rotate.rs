@@ -0,0 +1,394 @@
+//! Rotation
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    layout::{prepare, validate_buffer},
+    AlphaMode, Image, ImageError, PixelFormat, ResXY, XY,
+};
+
+/// Clockwise rotations by multiples of 90 degrees
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    R0,
+    R90,
+    R180,
+    R270,
+}
+
+impl Rotation {
+    /// Size of an image of `res` after rotation
+    pub fn rotated_res(self, (w, h): ResXY) -> ResXY {
+        match self {
+            Rotation::R0 | Rotation::R180 => (w, h),
+            Rotation::R90 | Rotation::R270 => (h, w),
+        }
+    }
+
+    /// Source coordinates for the destination pixel `xy`, in a source image
+    /// of `res`
+    fn source(self, (x, y): XY, (w, h): ResXY) -> XY {
+        match self {
+            Rotation::R0 => (x, y),
+            Rotation::R90 => (y, h - 1 - x),
+            Rotation::R180 => (w - 1 - x, h - 1 - y),
+            Rotation::R270 => (w - 1 - y, x),
+        }
+    }
+
+    /// Destination coordinates for the source pixel `xy`, in a source image
+    /// of `res`, the inverse of [`Rotation::source`]
+    fn dest(self, (x, y): XY, (w, h): ResXY) -> XY {
+        match self {
+            Rotation::R0 => (x, y),
+            Rotation::R90 => (h - 1 - y, x),
+            Rotation::R180 => (w - 1 - x, h - 1 - y),
+            Rotation::R270 => (y, w - 1 - x),
+        }
+    }
+}
+
+/// One of the 8 ways a rectangular panel can be mounted, as a mirror
+/// followed by a rotation
+///
+/// The image is first mirrored left to right if `mirror`, and then rotated
+/// clockwise by `rotation`, like [`Image::flip_horizontal`] and then
+/// [`Image::rotate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Orientation {
+    pub rotation: Rotation,
+    pub mirror: bool,
+}
+
+impl Orientation {
+    /// As is
+    pub const IDENTITY: Self = Self::new(Rotation::R0, false);
+
+    pub const fn new(rotation: Rotation, mirror: bool) -> Self {
+        Self { rotation, mirror }
+    }
+
+    /// Size of an image of `res` in this orientation
+    pub fn oriented_res(self, res: ResXY) -> ResXY {
+        self.rotation.rotated_res(res)
+    }
+
+    /// Source coordinates for the destination pixel `xy`, which must be
+    /// inside [`Orientation::oriented_res`], in a source image of `res`
+    pub(crate) fn source(self, xy: XY, res: ResXY) -> XY {
+        let (x, y) = self.rotation.source(xy, res);
+        if self.mirror {
+            (res.0 - 1 - x, y)
+        } else {
+            (x, y)
+        }
+    }
+
+    /// Destination coordinates for the source pixel `xy`, in a source image
+    /// of `res`, the inverse of [`Orientation::source`]
+    pub(crate) fn dest(self, (x, y): XY, res: ResXY) -> XY {
+        let xy = if self.mirror {
+            (res.0 - 1 - x, y)
+        } else {
+            (x, y)
+        };
+        self.rotation.dest(xy, res)
+    }
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl From<Rotation> for Orientation {
+    fn from(rotation: Rotation) -> Self {
+        Self::new(rotation, false)
+    }
+}
+
+/// Tile size for blocked iteration, keeps both the source and destination
+/// accesses reasonably cache friendly.
+const TILE: u32 = 8;
+
+/// Transpose `data`, of `res`, swapping rows and columns, tile by tile
+pub(crate) fn transpose_buffer<T: Copy>(data: &[T], (w, h): ResXY) -> Vec<T> {
+    let Some(&first) = data.first() else {
+        return Vec::new();
+    };
+    let mut out = vec![first; data.len()];
+    for ty in (0..h).step_by(TILE as usize) {
+        for tx in (0..w).step_by(TILE as usize) {
+            for y in ty..(ty + TILE).min(h) {
+                for x in tx..(tx + TILE).min(w) {
+                    out[(x * h + y) as usize] = data[(y * w + x) as usize];
+                }
+            }
+        }
+    }
+    out
+}
+
+impl Image {
+    /// Rotate the image clockwise by `rotation`
+    pub fn rotate(&mut self, rotation: Rotation) {
+        if rotation == Rotation::R0 {
+            return;
+        }
+        let res = self.res;
+        let (w, h) = rotation.rotated_res(res);
+        let mut out = Vec::with_capacity(self.data.len());
+        for y in 0..h {
+            for x in 0..w {
+                let (sx, sy) = rotation.source((x, y), res);
+                out.push(self.data[(sy * res.0 + sx) as usize]);
+            }
+        }
+        self.data = out;
+        self.res = (w, h);
+        self.check();
+    }
+
+    /// Rotate the image 90 degrees clockwise
+    pub fn rotate90(&mut self) {
+        self.rotate(Rotation::R90)
+    }
+
+    /// Rotate the image 180 degrees
+    pub fn rotate180(&mut self) {
+        self.rotate(Rotation::R180)
+    }
+
+    /// Rotate the image 270 degrees clockwise, or 90 counter-clockwise
+    pub fn rotate270(&mut self) {
+        self.rotate(Rotation::R270)
+    }
+
+    /// Swap rows and columns, mirroring the image along its main diagonal
+    ///
+    /// The width and height are exchanged.
+    pub fn transpose(&mut self) {
+        self.data = transpose_buffer(&self.data, self.res);
+        self.res = (self.res.1, self.res.0);
+        self.check();
+    }
+
+    /// Mirror the image left to right
+    pub fn flip_horizontal(&mut self) {
+        let w = self.width();
+        for x in 0..w / 2 {
+            // Always in bounds
+            let _ = self.swap_columns(x, w - 1 - x);
+        }
+    }
+
+    /// Mirror the image top to bottom
+    pub fn flip_vertical(&mut self) {
+        let h = self.height();
+        for y in 0..h / 2 {
+            // Always in bounds
+            let _ = self.swap_rows(y, h - 1 - y);
+        }
+    }
+
+    /// Export the image rotated clockwise by `rotation` into `out`, without
+    /// creating a rotated copy
+    ///
+    /// `stride` is the size of one output row in bytes, which may include
+    /// padding. Padding bytes are not written. Alpha is exported straight.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `stride` is smaller than a row
+    /// - [`ImageError::BufferSize`] if `out` is too small
+    pub fn write_bytes_rotated(
+        &self,
+        out: &mut [u8],
+        rotation: Rotation,
+        format: PixelFormat,
+        stride: usize,
+    ) -> Result<(), ImageError> {
+        let (w, h) = rotation.rotated_res(self.res);
+        let bpp = format.bytes_per_pixel();
+        validate_buffer(out, format, (w, h), stride)?;
+        let transfer = self.color.transfer();
+
+        for ty in (0..h).step_by(TILE as usize) {
+            for tx in (0..w).step_by(TILE as usize) {
+                for y in ty..(ty + TILE).min(h) {
+                    for x in tx..(tx + TILE).min(w) {
+                        let (sx, sy) = rotation.source((x, y), self.res);
+                        let p = self.data[(sy * self.width() + sx) as usize];
+                        let p = prepare(p, format, self.alpha, transfer);
+                        let i = y as usize * stride + x as usize * bpp;
+                        format.encode(p, &mut out[i..i + bpp]);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::ramp;
+
+    const ROTATIONS: [Rotation; 4] = [Rotation::R0, Rotation::R90, Rotation::R180, Rotation::R270];
+
+    #[test]
+    fn write_bytes_rotated_matches_rotate() {
+        // Not a multiple of the tile size either way
+        let img = ramp((11, 19));
+        for rotation in ROTATIONS {
+            for format in [PixelFormat::Rgba8888, PixelFormat::Rgb565Le] {
+                let mut rotated = img.clone();
+                rotated.rotate(rotation);
+                let expected = rotated.to_raw(format);
+                let stride = format.row_bytes(rotated.width()).unwrap();
+                let mut out = vec![0; expected.len()];
+                img.write_bytes_rotated(&mut out, rotation, format, stride)
+                    .unwrap();
+                assert_eq!(out, expected, "{rotation:?} {format:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn write_bytes_rotated_stride() {
+        let img = ramp((5, 3));
+        let format = PixelFormat::Rgb888;
+        for rotation in ROTATIONS {
+            let mut rotated = img.clone();
+            rotated.rotate(rotation);
+            let expected = rotated.to_raw(format);
+            let (w, h) = rotated.res;
+            let (row, stride) = (w as usize * 3, w as usize * 3 + 5);
+            let mut out = vec![0xaa; stride * h as usize];
+            img.write_bytes_rotated(&mut out, rotation, format, stride)
+                .unwrap();
+            for (y, line) in out.chunks(stride).enumerate() {
+                assert_eq!(line[..row], expected[y * row..][..row], "{rotation:?}");
+                assert!(line[row..].iter().all(|b| *b == 0xaa), "{rotation:?}");
+            }
+
+            // The last row needn't have padding
+            let mut short = vec![0; stride * h as usize - 5];
+            assert!(img
+                .write_bytes_rotated(&mut short, rotation, format, stride)
+                .is_ok());
+            assert_eq!(
+                img.write_bytes_rotated(&mut short[1..], rotation, format, stride),
+                Err(ImageError::BufferSize {
+                    expected: short.len(),
+                    actual: short.len() - 1
+                })
+            );
+            assert_eq!(
+                img.write_bytes_rotated(&mut out, rotation, format, row - 1),
+                Err(ImageError::InvalidArgument)
+            );
+        }
+    }
+
+    #[test]
+    fn rotations_compose() {
+        let img = ramp((4, 7));
+        let mut r = img.clone();
+        r.rotate90();
+        assert_eq!(r.res, (7, 4));
+        assert_eq!(r.get_pixel((6, 0)), img.get_pixel((0, 0)));
+        r.rotate90();
+        let mut half = img.clone();
+        half.rotate180();
+        assert_eq!(r.pixels(), half.pixels());
+        r.rotate90();
+        let mut back = img.clone();
+        back.rotate270();
+        assert_eq!(r.pixels(), back.pixels());
+        r.rotate90();
+        assert_eq!(r.pixels(), img.pixels());
+
+        let mut t = img.clone();
+        t.transpose();
+        assert_eq!(t.res, (7, 4));
+        assert_eq!(t.get_pixel((5, 2)), img.get_pixel((2, 5)));
+    }
+
+    #[test]
+    fn orientation_dest_inverts_source() {
+        let res = (5, 3);
+        for rotation in ROTATIONS {
+            for mirror in [false, true] {
+                let o = Orientation::new(rotation, mirror);
+                let (w, h) = o.oriented_res(res);
+                for y in 0..h {
+                    for x in 0..w {
+                        assert_eq!(o.dest(o.source((x, y), res), res), (x, y), "{o:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn orientation_matches_flip_and_rotate() {
+        let img = ramp((5, 3));
+        for rotation in ROTATIONS {
+            for mirror in [false, true] {
+                let o = Orientation::new(rotation, mirror);
+                let mut copy = img.clone();
+                if mirror {
+                    copy.flip_horizontal();
+                }
+                copy.rotate(rotation);
+                assert_eq!(copy.res, o.oriented_res(img.res));
+                for y in 0..copy.height() {
+                    for x in 0..copy.width() {
+                        let src = o.source((x, y), img.res);
+                        assert_eq!(copy.get_pixel((x, y)), img.get_pixel(src), "{o:?}");
+                    }
+                }
+            }
+        }
+        assert_eq!(Orientation::default(), Orientation::IDENTITY);
+        assert_eq!(
+            Orientation::from(Rotation::R90),
+            Orientation::new(Rotation::R90, false)
+        );
+    }
+
+    #[test]
+    fn transpose_twice_is_identity() {
+        let img = ramp((13, 7));
+        let mut t = img.clone();
+        t.transpose();
+        assert_eq!(t.res, (7, 13));
+        t.transpose();
+        assert_eq!(t.pixels(), img.pixels());
+    }
+
+    #[test]
+    fn transpose_column() {
+        let mut img = ramp((1, 9));
+        let column = img.data.clone();
+        img.transpose();
+        assert_eq!(img.res, (9, 1));
+        assert_eq!(img.data, column);
+    }
+
+    #[test]
+    fn transpose_matches_naive() {
+        for (w, h) in [(13, 7), (8, 8), (17, 3), (1, 1)] {
+            let img = ramp((w, h));
+            let mut t = img.clone();
+            t.transpose();
+            for y in 0..w {
+                for x in 0..h {
+                    assert_eq!(t.data[(y * h + x) as usize], img.data[(x * w + y) as usize]);
+                }
+            }
+        }
+    }
+}
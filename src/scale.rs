@@ -0,0 +1,1487 @@
+//! Image scaling
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    composite::{from_linear_premul, to_linear_premul},
+    job::JobStatus,
+    layout::validate_exact,
+    resample::{filter_row, Contrib, FilterWeights},
+    AlphaMode, ColorSpace, FloatXY, Image, ImageError, PixelFormat, ResXY, WorkPixel, F32, XY,
+};
+
+/// Resampling filters for [`Image::scale_with`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScaleFilter {
+    /// Nearest neighbor, no filtering at all
+    Nearest,
+
+    /// Bilinear interpolation, mapping corners to corners
+    Bilinear,
+
+    /// Box / area averaging, each destination pixel is the average of the
+    /// source area it covers.
+    ///
+    /// This is what you want for shrinking.
+    Box,
+
+    /// Mitchell–Netravali family of cubic filters, with parameters `b` and
+    /// `c`
+    ///
+    /// See [`ScaleFilter::CATMULL_ROM`], [`ScaleFilter::MITCHELL`], and
+    /// [`ScaleFilter::B_SPLINE`] for the usual choices. Higher `b` blurs,
+    /// higher `c` sharpens and rings. The kernel is widened when shrinking,
+    /// so it also antialiases.
+    Cubic { b: f32, c: f32 },
+}
+
+impl ScaleFilter {
+    /// Sharp interpolating cubic, `b = 0, c = 0.5`
+    pub const CATMULL_ROM: Self = Self::Cubic { b: 0., c: 0.5 };
+
+    /// Balanced cubic, `b = c = 1/3`, a good default for photos
+    pub const MITCHELL: Self = Self::Cubic {
+        b: 1. / 3.,
+        c: 1. / 3.,
+    };
+
+    /// Smooth cubic without ringing, `b = 1, c = 0`, for noisy images
+    pub const B_SPLINE: Self = Self::Cubic { b: 1., c: 0. };
+}
+
+/// Mitchell–Netravali cubic kernel with parameters `b` and `c` at `x`
+///
+/// Zero outside `-2..2`. For any `b` and `c` the weights at integer spacing
+/// sum to 1.
+pub(crate) fn cubic_weight(b: f32, c: f32, x: f32) -> f32 {
+    let x = x.abs();
+    let (x2, x3) = (x * x, x * x * x);
+    let w = if x < 1. {
+        (12. - 9. * b - 6. * c) * x3 + (-18. + 12. * b + 6. * c) * x2 + (6. - 2. * b)
+    } else if x < 2. {
+        (-b - 6. * c) * x3 + (6. * b + 30. * c) * x2 + (-12. * b - 48. * c) * x + (8. * b + 24. * c)
+    } else {
+        0.
+    };
+    w / 6.
+}
+
+/// Something that can be filtered, pixels or single channels
+pub(crate) trait Sample: Copy + Default {
+    /// `self + p * w`
+    fn mul_add(self, p: Self, w: f32) -> Self;
+
+    /// Linear interpolation from `self` to `b` by `t`
+    fn lerp(self, b: Self, t: f32) -> Self;
+}
+
+impl Sample for f32 {
+    #[inline]
+    fn mul_add(self, p: Self, w: f32) -> Self {
+        self + p * w
+    }
+
+    #[inline]
+    fn lerp(self, b: Self, t: f32) -> Self {
+        self + (b - self) * t
+    }
+}
+
+impl Sample for WorkPixel {
+    #[inline]
+    fn mul_add(self, p: Self, w: f32) -> Self {
+        let mut out = self;
+        for i in 0..4 {
+            out[i] += p[i] * w;
+        }
+        out
+    }
+
+    #[inline]
+    fn lerp(self, b: Self, t: f32) -> Self {
+        let mut out = self;
+        for i in 0..4 {
+            out[i] = self[i] + (b[i] - self[i]) * t;
+        }
+        out
+    }
+}
+
+/// Filter `data`, of `res`, through `h` then `v`, into a new buffer
+pub(crate) fn separable<T: Sample>(
+    data: &[T],
+    (w, h): ResXY,
+    hc: &FilterWeights,
+    vc: &FilterWeights,
+) -> Vec<T> {
+    let (w, nw) = (w as usize, hc.len());
+    if data.is_empty() {
+        return vec![T::default(); nw * vc.len()];
+    }
+    let mut tmp = vec![T::default(); nw * h as usize];
+    for (src, dst) in data.chunks_exact(w).zip(tmp.chunks_exact_mut(nw)) {
+        filter_row(hc, src, dst);
+    }
+    let mut out = vec![T::default(); nw * vc.len()];
+    for (c, dst) in vc.contribs.iter().zip(out.chunks_exact_mut(nw)) {
+        for (k, wt) in c.weights.iter().enumerate() {
+            let src = &tmp[(c.start as usize + k) * nw..][..nw];
+            for (d, s) in dst.iter_mut().zip(src) {
+                *d = d.mul_add(*s, *wt);
+            }
+        }
+    }
+    out
+}
+
+/// Source pixels per destination pixel, mapping corners to corners
+pub(crate) fn corner_ratio(old: u32, new: u32) -> f32 {
+    if new > 1 {
+        (old - 1) as f32 / (new - 1) as f32
+    } else {
+        0.
+    }
+}
+
+/// Bilinearly sample `pixels`, of `res`, at the source position `xy`
+///
+/// Positions outside the image are clamped to the edge.
+pub(crate) fn sample_bilinear<T: Sample>(pixels: &[T], (width, height): ResXY, xy: FloatXY) -> T {
+    let sx = xy.0.clamp(0., (width - 1) as f32);
+    let sy = xy.1.clamp(0., (height - 1) as f32);
+    let x0 = sx.floor() as u32;
+    let y0 = sy.floor() as u32;
+    let x1 = (x0 + 1).min(width - 1);
+    let y1 = (y0 + 1).min(height - 1);
+    let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+
+    let p = |x: u32, y: u32| pixels[((y * width) + x) as usize];
+    let top = p(x0, y0).lerp(p(x1, y0), fx);
+    let bottom = p(x0, y1).lerp(p(x1, y1), fx);
+    top.lerp(bottom, fy)
+}
+
+/// Nearest source index for destination index `d`
+pub(crate) fn nearest(d: u32, src: u32, dst: u32) -> u32 {
+    let s = ((d as f32 + 0.5) * (src as f32 / dst as f32)).floor() as u32;
+    s.min(src.saturating_sub(1))
+}
+
+/// Largest size the integer fast paths of [`nearest_integer`] are known to
+/// match [`nearest`] exactly at, its `f32` math is exact below this
+const EXACT_NEAREST: u32 = 1 << 22;
+
+/// Nearest neighbor scaling of `data` by whole factors on both axes, with
+/// plain copies instead of per pixel coordinate math
+///
+/// Returns `false`, without touching `data`, if the factors aren't whole.
+/// Results are identical to the general path.
+fn nearest_integer<T: Sample>(data: &mut Vec<T>, (w, h): ResXY, (nw, nh): ResXY) -> bool {
+    if w == 0 || h == 0 || w.max(h).max(nw).max(nh) >= EXACT_NEAREST {
+        return false;
+    }
+    let (w, h, nw, nh) = (w as usize, h as usize, nw as usize, nh as usize);
+    if nw % w == 0 && nh % h == 0 {
+        // Replicate each pixel, then each row
+        let (kx, ky) = (nw / w, nh / h);
+        let mut out = Vec::with_capacity(nw * nh);
+        for row in data.chunks_exact(w) {
+            let start = out.len();
+            for p in row {
+                out.extend(core::iter::repeat_n(*p, kx));
+            }
+            for _ in 1..ky {
+                out.extend_from_within(start..start + nw);
+            }
+        }
+        *data = out;
+        true
+    } else if w % nw == 0 && h % nh == 0 {
+        // The center of each block, strided in place
+        let (kx, ky) = (w / nw, h / nh);
+        for y in 0..nh {
+            let src = (y * ky + ky / 2) * w + kx / 2;
+            for x in 0..nw {
+                data[y * nw + x] = data[src + x * kx];
+            }
+        }
+        data.truncate(nw * nh);
+        true
+    } else {
+        false
+    }
+}
+
+/// Scale `data`, of `res`, to `new` using `filter`
+///
+/// When shrinking in both dimensions this is done in place, reusing the
+/// existing allocation, except for [`ScaleFilter::Cubic`].
+pub(crate) fn scale_buffer<T: Sample>(
+    data: &mut Vec<T>,
+    res: ResXY,
+    new: ResXY,
+    filter: ScaleFilter,
+) {
+    profile!(Scale);
+    let (width, height) = res;
+    let (new_width, new_height) = new;
+    if (width, height) == (new_width, new_height) {
+        return;
+    }
+    if width == 0 || height == 0 {
+        // Nothing to sample, so nothing but zeros
+        *data = vec![T::default(); new_width as usize * new_height as usize];
+        return;
+    }
+    if filter == ScaleFilter::Nearest && nearest_integer(data, res, new) {
+        return;
+    }
+    // When shrinking, every source pixel read is at or after the
+    // destination index, so writing front to back never clobbers
+    // anything still needed. Cubic taps reach behind the destination.
+    let in_place =
+        new_width <= width && new_height <= height && !matches!(filter, ScaleFilter::Cubic { .. });
+    let mut out = if in_place {
+        Vec::new()
+    } else {
+        vec![T::default(); (new_height * new_width) as usize]
+    };
+
+    match filter {
+        ScaleFilter::Nearest => {
+            for y in 0..new_height {
+                let sy = nearest(y, height, new_height);
+                for x in 0..new_width {
+                    let sx = nearest(x, width, new_width);
+                    let res = data[(sy * width + sx) as usize];
+                    let index = ((y * new_width) + x) as usize;
+                    if in_place {
+                        data[index] = res;
+                    } else {
+                        out[index] = res;
+                    }
+                }
+            }
+        }
+        ScaleFilter::Bilinear | ScaleFilter::Box | ScaleFilter::Cubic { .. } => {
+            let h = FilterWeights::compute(width, new_width, filter);
+            let v = FilterWeights::compute(height, new_height, filter);
+            // Horizontal pass into a `new_width * height` buffer
+            let mut tmp = if in_place {
+                core::mem::take(data)
+            } else {
+                vec![T::default(); (new_width * height) as usize]
+            };
+            let (w, nw) = (width as usize, new_width as usize);
+            let mut row = vec![T::default(); nw];
+            for y in 0..height as usize {
+                let src = if in_place { &tmp } else { &*data };
+                filter_row(&h, &src[y * w..][..w], &mut row);
+                tmp[y * nw..][..nw].copy_from_slice(&row);
+            }
+            // Vertical pass
+            for (dy, c) in v.contribs.iter().enumerate() {
+                for x in 0..nw {
+                    let mut acc = T::default();
+                    for (k, wt) in c.weights.iter().enumerate() {
+                        acc = acc.mul_add(tmp[(c.start as usize + k) * nw + x], *wt);
+                    }
+                    if in_place {
+                        tmp[dy * nw + x] = acc;
+                    } else {
+                        out[dy * nw + x] = acc;
+                    }
+                }
+            }
+            if in_place {
+                *data = tmp;
+            }
+        }
+    }
+
+    if in_place {
+        data.truncate((new_height * new_width) as usize);
+    } else {
+        *data = out;
+    }
+}
+
+/// Incremental [`Image::scale_with`], see [`Image::scale_job`]
+pub struct ScaleJob<'a> {
+    src: &'a Image,
+    new: ResXY,
+    filter: ScaleFilter,
+    out: Vec<WorkPixel>,
+    next_row: u32,
+    /// Filter weights, horizontal and vertical
+    weights: Option<(FilterWeights, FilterWeights)>,
+    /// Scratch for one output row
+    row: Vec<WorkPixel>,
+}
+
+impl ScaleJob<'_> {
+    /// Produce up to `max_rows` more rows of output
+    pub fn step(&mut self, max_rows: u32) -> JobStatus {
+        let end = self.next_row.saturating_add(max_rows).min(self.new.1);
+        for y in self.next_row..end {
+            self.row(y);
+        }
+        self.next_row = end;
+        self.status()
+    }
+
+    pub fn status(&self) -> JobStatus {
+        if self.next_row >= self.new.1 {
+            JobStatus::Done
+        } else {
+            JobStatus::InProgress
+        }
+    }
+
+    /// Produce any remaining rows and return the scaled image
+    pub fn finish(mut self) -> Image {
+        self.step(u32::MAX);
+        self.src.derive(self.out, self.new)
+    }
+
+    /// Output row `y`, computed exactly like [`scale_buffer`]
+    fn row(&mut self, y: u32) {
+        let nw = self.new.0 as usize;
+        let out = &mut self.out[y as usize * nw..][..nw];
+        scale_row(
+            self.src,
+            (self.new, self.filter),
+            &mut self.weights,
+            y,
+            out,
+            &mut self.row,
+        );
+    }
+}
+
+/// Row `y` of `src` scaled to `new` with `filter`, into `out`, computed
+/// exactly like [`scale_buffer`]
+///
+/// `weights` are computed on first use, and `row` is scratch space.
+fn scale_row(
+    src: &Image,
+    (new, filter): (ResXY, ScaleFilter),
+    weights: &mut Option<(FilterWeights, FilterWeights)>,
+    y: u32,
+    out: &mut [WorkPixel],
+    row: &mut Vec<WorkPixel>,
+) {
+    let (width, height) = src.res;
+    let (new_width, new_height) = new;
+    let data = &src.data;
+    let nw = new_width as usize;
+    if src.res == new {
+        out.copy_from_slice(&data[y as usize * nw..][..nw]);
+        return;
+    }
+    if data.is_empty() {
+        out.fill(WorkPixel::default());
+        return;
+    }
+    match filter {
+        ScaleFilter::Nearest => {
+            let sy = nearest(y, height, new_height);
+            for (x, o) in out.iter_mut().enumerate() {
+                let sx = nearest(x as u32, width, new_width);
+                *o = data[(sy * width + sx) as usize];
+            }
+        }
+        ScaleFilter::Bilinear | ScaleFilter::Box | ScaleFilter::Cubic { .. } => {
+            let (h, v) = weights.get_or_insert_with(|| {
+                (
+                    FilterWeights::compute(width, new_width, filter),
+                    FilterWeights::compute(height, new_height, filter),
+                )
+            });
+            let c = &v.contribs[y as usize];
+            let w = width as usize;
+            row.resize(nw, WorkPixel::default());
+            out.fill(WorkPixel::default());
+            for (k, wt) in c.weights.iter().enumerate() {
+                let sy = c.start as usize + k;
+                filter_row(h, &data[sy * w..][..w], row);
+                for (o, p) in out.iter_mut().zip(&*row) {
+                    *o = o.mul_add(*p, *wt);
+                }
+            }
+        }
+    }
+}
+
+/// Rows of [`Image::scale_with`], computed one at a time as they're asked
+/// for, see [`Image::scale_rows`]
+#[derive(Debug)]
+pub struct ScaledRows<'a> {
+    src: &'a Image,
+    new: ResXY,
+    filter: ScaleFilter,
+    next_row: u32,
+    weights: Option<(FilterWeights, FilterWeights)>,
+    /// The last row returned
+    out: Vec<WorkPixel>,
+    row: Vec<WorkPixel>,
+}
+
+impl ScaledRows<'_> {
+    /// The next row of output, top to bottom, or `None` when done
+    ///
+    /// The row is only borrowed until the next call, its buffer is reused.
+    pub fn next_row(&mut self) -> Option<&[WorkPixel]> {
+        if self.next_row >= self.new.1 {
+            return None;
+        }
+        let y = self.next_row;
+        self.next_row += 1;
+        scale_row(
+            self.src,
+            (self.new, self.filter),
+            &mut self.weights,
+            y,
+            &mut self.out,
+            &mut self.row,
+        );
+        Some(&self.out)
+    }
+
+    /// Rows left to produce
+    pub fn remaining(&self) -> u32 {
+        self.new.1 - self.next_row
+    }
+}
+
+/// One axis of a viewport, in source pixels
+#[derive(Clone, Copy)]
+struct Axis {
+    /// Whole pixel part of the origin
+    base: i64,
+    /// Fractional part of the origin
+    frac: f32,
+    span: f32,
+    /// Number of source pixels touched, from `base`
+    limit: u32,
+    /// Source image size
+    size: u32,
+}
+
+impl Axis {
+    fn new(origin: f32, span: f32, size: u32) -> Self {
+        let base = origin.floor();
+        let frac = origin - base;
+        let span = span.max(0.);
+        let limit = ((frac + span).ceil() as u32).max(1);
+        Self {
+            base: base as i64,
+            frac,
+            span,
+            limit,
+            size,
+        }
+    }
+
+    /// Source index for `local`, a pixel offset from `base`, clamped to the
+    /// image, which must not be empty
+    fn index(self, local: u32) -> usize {
+        debug_assert!(self.size > 0, "No pixels to index");
+        (self.base + local as i64).clamp(0, self.size as i64 - 1) as usize
+    }
+
+    /// Local index for destination `d`, like `nearest`
+    fn nearest(self, d: u32, dst: u32) -> u32 {
+        let s = (self.frac + (d as f32 + 0.5) * (self.span / dst as f32)).floor() as u32;
+        s.min(self.limit - 1)
+    }
+
+    /// Local bilinear weights for `dst` destination pixels, like
+    /// [`FilterWeights::compute`]
+    fn bilinear(self, dst: u32) -> FilterWeights {
+        let ratio = if dst > 1 {
+            ((self.span - 1.) / (dst - 1) as f32).max(0.)
+        } else {
+            0.
+        };
+        let contribs = (0..dst)
+            .map(|d| {
+                let s = self.frac + d as f32 * ratio;
+                let start = (s.floor() as u32).min(self.limit - 1);
+                let t = s - start as f32;
+                let weights = if start + 1 < self.limit && t > 0. {
+                    vec![1. - t, t]
+                } else {
+                    vec![1.]
+                };
+                Contrib { start, weights }
+            })
+            .collect();
+        FilterWeights { contribs }
+    }
+}
+
+impl Image {
+    /// Extract the source rectangle `src_rect`, as `(origin, size)`, scaled to
+    /// `out` using `filter`, in one pass
+    ///
+    /// The rectangle can have fractional coordinates, for smooth panning.
+    /// Parts outside the image are clamped to its edge, and an empty image
+    /// gives transparent black. For whole pixel rectangles this is identical
+    /// to [`Image::crop`] then [`Image::scale_with`].
+    ///
+    /// # Panics
+    ///
+    /// - If `out` is zero in either dimension
+    pub fn viewport(&self, src_rect: (FloatXY, FloatXY), out: ResXY, filter: ScaleFilter) -> Image {
+        assert!(out.0 > 0 && out.1 > 0, "Cannot scale to zero");
+        let ((ox, oy), (sw, sh)) = src_rect;
+        let (w, h) = self.res;
+        let (nw, nh) = (out.0 as usize, out.1 as usize);
+        if w == 0 || h == 0 {
+            return self.derive(vec![WorkPixel::default(); nw * nh], out);
+        }
+        let (ax, ay) = (Axis::new(ox, sw, w), Axis::new(oy, sh, h));
+        let p = |x: u32, y: u32| self.data[ay.index(y) * w as usize + ax.index(x)];
+        let mut data = Vec::with_capacity(nw * nh);
+
+        // Whole pixels at the same size are a copy, like `scale_with`
+        let whole = |a: Axis| a.frac == 0. && a.span == a.limit as f32;
+        if whole(ax) && whole(ay) && (ax.limit, ay.limit) == out {
+            for y in 0..out.1 {
+                data.extend((0..out.0).map(|x| p(x, y)));
+            }
+            return self.derive(data, out);
+        }
+
+        match filter {
+            ScaleFilter::Nearest => {
+                for y in 0..out.1 {
+                    let sy = ay.nearest(y, out.1);
+                    data.extend((0..out.0).map(|x| p(ax.nearest(x, out.0), sy)));
+                }
+            }
+            ScaleFilter::Bilinear | ScaleFilter::Box | ScaleFilter::Cubic { .. } => {
+                let (hc, vc) = if filter == ScaleFilter::Bilinear {
+                    (ax.bilinear(out.0), ay.bilinear(out.1))
+                } else {
+                    (
+                        FilterWeights::compute_at(filter, ax.frac, ax.span, ax.limit, out.0),
+                        FilterWeights::compute_at(filter, ay.frac, ay.span, ay.limit, out.1),
+                    )
+                };
+                // Same order of operations as `scale_buffer`
+                let filter = |sy: u32, c: &Contrib| {
+                    let mut acc = WorkPixel::default();
+                    for (k, wt) in c.weights.iter().enumerate() {
+                        acc = acc.mul_add(p(c.start + k as u32, sy), *wt);
+                    }
+                    acc
+                };
+                for v in &vc.contribs {
+                    data.extend(hc.contribs.iter().map(|c| {
+                        let mut acc = WorkPixel::default();
+                        for (k, wt) in v.weights.iter().enumerate() {
+                            acc = acc.mul_add(filter(v.start + k as u32, c), *wt);
+                        }
+                        acc
+                    }));
+                }
+            }
+        }
+        self.derive(data, out)
+    }
+
+    /// Start scaling the image to `new` using `filter` incrementally, see
+    /// [`Image::scale_with`]
+    ///
+    /// The result is identical to [`Image::scale_with`], but never done in
+    /// place, the output is allocated up front.
+    ///
+    /// # Panics
+    ///
+    /// - If `new` is zero in either dimension
+    pub fn scale_job(&self, new: ResXY, filter: ScaleFilter) -> ScaleJob<'_> {
+        assert!(new.0 > 0 && new.1 > 0, "Cannot scale to zero");
+        let out = vec![WorkPixel::default(); new.0 as usize * new.1 as usize];
+        self.scale_job_into(new, filter, out)
+    }
+
+    /// [`Image::scale_job`] writing into `out`, which must be `new` in size
+    pub(crate) fn scale_job_into(
+        &self,
+        new: ResXY,
+        filter: ScaleFilter,
+        out: Vec<WorkPixel>,
+    ) -> ScaleJob<'_> {
+        debug_assert_eq!(out.len(), new.0 as usize * new.1 as usize);
+        ScaleJob {
+            src: self,
+            new,
+            filter,
+            out,
+            next_row: 0,
+            weights: None,
+            row: Vec::new(),
+        }
+    }
+
+    /// Scale the image to `new` using `filter` a row at a time, see
+    /// [`ScaledRows`]
+    ///
+    /// The rows are identical to [`Image::scale_with`]'s, but only one row
+    /// of output is ever kept, for sending a scaled image somewhere without
+    /// holding all of it. Each row is filtered from the source rows under
+    /// it, so stopping early does none of the remaining work.
+    ///
+    /// # Panics
+    ///
+    /// - If `new` is zero in either dimension
+    pub fn scale_rows(&self, new: ResXY, filter: ScaleFilter) -> ScaledRows<'_> {
+        assert!(new.0 > 0 && new.1 > 0, "Cannot scale to zero");
+        ScaledRows {
+            src: self,
+            new,
+            filter,
+            next_row: 0,
+            weights: None,
+            out: vec![WorkPixel::default(); new.0 as usize],
+            row: Vec::new(),
+        }
+    }
+
+    /// Scale the image to `new` using `filter`
+    ///
+    /// When shrinking in both dimensions this is done in place, reusing the
+    /// existing allocation, except for [`ScaleFilter::Cubic`]. An empty image
+    /// has nothing to sample, and scales to transparent black.
+    ///
+    /// # Panics
+    ///
+    /// - If `new` is zero in either dimension
+    pub fn scale_with(&mut self, new: ResXY, filter: ScaleFilter) {
+        assert!(new.0 > 0 && new.1 > 0, "Cannot scale to zero");
+        scale_buffer(&mut self.data, self.res, new, filter);
+        self.res = new;
+        self.check();
+    }
+
+    /// A copy scaled to `new`, the right way for most uses
+    ///
+    /// Filtering happens in linear light with premultiplied alpha, so colors
+    /// don't darken and transparent pixels don't fringe. Each axis picks its
+    /// own filter, [`ScaleFilter::Box`] when shrinking and
+    /// [`ScaleFilter::CATMULL_ROM`] when enlarging. The result is clamped, so
+    /// the cubic filter can't overshoot, and keeps the color space and alpha
+    /// mode of the image.
+    ///
+    /// [`Image::scale_with`] is the low level version, doing exactly what
+    /// it's told on the values as stored.
+    ///
+    /// # Panics
+    ///
+    /// - If `new` is zero in either dimension
+    pub fn resize(&self, new: ResXY) -> Image {
+        assert!(new.0 > 0 && new.1 > 0, "Cannot scale to zero");
+        if new == self.res {
+            return self.clone();
+        }
+        let axis = |src: u32, dst: u32| {
+            let filter = if dst < src {
+                ScaleFilter::Box
+            } else {
+                ScaleFilter::CATMULL_ROM
+            };
+            FilterWeights::compute(src, dst, filter)
+        };
+        let (hc, vc) = (axis(self.res.0, new.0), axis(self.res.1, new.1));
+        let transfer = self.color.transfer();
+        let (decode, encode) = (transfer.map(|t| t.0), transfer.map(|t| t.1));
+        let linear: Vec<WorkPixel> = self
+            .data
+            .iter()
+            .map(|p| to_linear_premul(*p, decode, self.alpha))
+            .collect();
+        let data = separable(&linear, self.res, &hc, &vc)
+            .into_iter()
+            .map(|p| {
+                let a = p[3].clamp(0., 1.);
+                let c = |c: f32| c.clamp(0., a);
+                from_linear_premul([c(p[0]), c(p[1]), c(p[2]), a], encode, self.alpha)
+            })
+            .collect();
+        self.derive(data, new)
+    }
+
+    /// Scale the image to `new` without making any new colors, for palette
+    /// images like UI screenshots
+    ///
+    /// When shrinking, each destination pixel takes the most common color in
+    /// the source area it covers, weighted by coverage like
+    /// [`ScaleFilter::Box`]. Ties go to the first color seen, in row order.
+    /// Axes that grow use [`ScaleFilter::Nearest`].
+    ///
+    /// # Panics
+    ///
+    /// - If `new` is zero in either dimension
+    pub fn scale_indexed(&mut self, new: ResXY) {
+        assert!(new.0 > 0 && new.1 > 0, "Cannot scale to zero");
+        if new == self.res {
+            return;
+        }
+        if self.data.is_empty() {
+            return self.scale_with(new, ScaleFilter::Nearest);
+        }
+        let axis = |src: u32, dst: u32| {
+            let filter = if dst >= src {
+                ScaleFilter::Nearest
+            } else {
+                ScaleFilter::Box
+            };
+            FilterWeights::compute(src, dst, filter)
+        };
+        let (h, v) = (axis(self.res.0, new.0), axis(self.res.1, new.1));
+        let w = self.res.0;
+        let mut counts: Vec<(WorkPixel, f32)> = Vec::new();
+        let mut data = Vec::with_capacity(new.0 as usize * new.1 as usize);
+        for cy in &v.contribs {
+            for cx in &h.contribs {
+                counts.clear();
+                for (ky, wy) in cy.weights.iter().enumerate() {
+                    let row = (cy.start as usize + ky) * w as usize;
+                    for (kx, wx) in cx.weights.iter().enumerate() {
+                        let p = self.data[row + cx.start as usize + kx];
+                        match counts.iter_mut().find(|(c, _)| *c == p) {
+                            Some((_, n)) => *n += wx * wy,
+                            None => counts.push((p, wx * wy)),
+                        }
+                    }
+                }
+                let mut best = counts[0];
+                for c in &counts[1..] {
+                    if c.1 > best.1 {
+                        best = *c;
+                    }
+                }
+                data.push(best.0);
+            }
+        }
+        self.data = data;
+        self.res = new;
+        self.check();
+    }
+
+    /// Read a box-filtered thumbnail of `res` sized `target` from RGBA 8888
+    /// data, like [`Image::from_bytes`] followed by [`ScaleFilter::Box`].
+    ///
+    /// The full size image is never created, rows are streamed into a
+    /// `target` sized accumulator. Peak memory is the output plus two rows,
+    /// one of `res` width and one of `target` width.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::BufferSize`] if `data` is not exactly
+    ///   `width * height * 4` in size
+    /// - [`ImageError::InvalidArgument`] if `res` or `target` are zero
+    pub fn from_bytes_scaled(
+        data: &[u8],
+        res: ResXY,
+        color: ColorSpace,
+        target: ResXY,
+    ) -> Result<Image, ImageError> {
+        let mut acc = BoxAccumulator::new(res, target)?;
+        validate_exact(data, PixelFormat::Rgba8888, res)?;
+        for bytes in data.chunks_exact(res.0 as usize * 4) {
+            acc.push_row(
+                bytes
+                    .chunks_exact(4)
+                    .map(|b| [b[0], b[1], b[2], b[3]].map(|f| f as f32 / 255.)),
+            );
+        }
+        Ok(Image::from_parts(acc.finish(), target, color))
+    }
+}
+
+/// Box filters rows streamed in from the top into a `target` sized image,
+/// holding only it and a row, for [`Image::from_bytes_scaled`] and
+/// [`thumbnail_from_raw`]
+struct BoxAccumulator {
+    h: FilterWeights,
+    v: FilterWeights,
+    out: Vec<WorkPixel>,
+    /// Source row `y`, scaled horizontally
+    row: Vec<WorkPixel>,
+    src: Vec<WorkPixel>,
+    /// Next source row
+    y: u32,
+    /// First destination row that may still use the next source row
+    first: usize,
+}
+
+impl BoxAccumulator {
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `res` or `target` are zero
+    fn new((width, height): ResXY, target: ResXY) -> Result<Self, ImageError> {
+        let (new_width, new_height) = target;
+        if width == 0 || height == 0 || new_width == 0 || new_height == 0 {
+            return Err(ImageError::InvalidArgument);
+        }
+        let nw = new_width as usize;
+        Ok(Self {
+            h: FilterWeights::compute(width, new_width, ScaleFilter::Box),
+            v: FilterWeights::compute(height, new_height, ScaleFilter::Box),
+            out: vec![[0.; 4]; nw * new_height as usize],
+            row: vec![Default::default(); nw],
+            src: Vec::with_capacity(width as usize),
+            y: 0,
+            first: 0,
+        })
+    }
+
+    /// Add the next source row, which must be the source width
+    fn push_row(&mut self, pixels: impl Iterator<Item = WorkPixel>) {
+        self.src.clear();
+        self.src.extend(pixels);
+        filter_row(&self.h, &self.src, &mut self.row);
+
+        let (sy, nw) = (self.y, self.row.len());
+        let v = &self.v.contribs;
+        while v[self.first].start + v[self.first].weights.len() as u32 <= sy {
+            self.first += 1;
+        }
+        for (dy, c) in v.iter().enumerate().skip(self.first) {
+            if c.start > sy {
+                break;
+            }
+            let wt = c.weights[(sy - c.start) as usize];
+            for (o, p) in self.out[dy * nw..][..nw].iter_mut().zip(&self.row) {
+                *o = o.mul_add(*p, wt);
+            }
+        }
+        self.y += 1;
+    }
+
+    /// The scaled image, once every source row is in
+    fn finish(self) -> Vec<WorkPixel> {
+        self.out
+    }
+}
+
+/// Read a thumbnail of `res` sized `target` from tightly packed `data` in
+/// `format`, without ever holding the full size image
+///
+/// Rows are decoded one at a time and box filtered in linear light with
+/// premultiplied alpha, like [`Image::from_raw`] followed by
+/// [`Image::resize`] when shrinking. Peak memory is the output plus a row
+/// of each, like [`Image::from_bytes_scaled`], which shares the filtering.
+/// The result has straight alpha.
+///
+/// # Errors
+///
+/// - [`ImageError::BufferSize`] if `data` is the wrong size, see
+///   [`validate_exact`]
+/// - [`ImageError::InvalidArgument`] if `res` or `target` are zero
+pub fn thumbnail_from_raw(
+    data: &[u8],
+    res: ResXY,
+    format: PixelFormat,
+    color: ColorSpace,
+    target: ResXY,
+) -> Result<Image, ImageError> {
+    let mut acc = BoxAccumulator::new(res, target)?;
+    validate_exact(data, format, res)?;
+    let transfer = color.transfer();
+    let (decode, encode) = (transfer.map(|t| t.0), transfer.map(|t| t.1));
+    let bpp = format.bytes_per_pixel();
+    for bytes in data.chunks_exact(res.0 as usize * bpp) {
+        let row = bytes
+            .chunks_exact(bpp)
+            .map(|b| to_linear_premul(format.decode(b), decode, AlphaMode::Straight));
+        acc.push_row(row);
+    }
+    let data = acc
+        .finish()
+        .into_iter()
+        .map(|p| {
+            let a = p[3].clamp(0., 1.);
+            let c = |c: f32| c.clamp(0., a);
+            from_linear_premul([c(p[0]), c(p[1]), c(p[2]), a], encode, AlphaMode::Straight)
+        })
+        .collect();
+    Ok(Image::from_parts(data, target, color))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [ScaleFilter; 4] = [
+        ScaleFilter::Nearest,
+        ScaleFilter::Bilinear,
+        ScaleFilter::Box,
+        ScaleFilter::MITCHELL,
+    ];
+
+    const EMPTY: [ResXY; 3] = [(0, 5), (5, 0), (0, 0)];
+
+    fn empty(res: ResXY) -> Image {
+        Image::from_bytes(&[], res, ColorSpace::sRGB)
+    }
+
+    #[test]
+    fn scale_empty() {
+        for res in EMPTY {
+            for filter in FILTERS {
+                let mut img = empty(res);
+                img.scale_with((3, 4), filter);
+                assert_eq!(img.res, (3, 4));
+                assert!(img.pixels().iter().all(|p| *p == [0.; 4]));
+
+                let src = empty(res);
+                let mut job = src.scale_job((3, 4), filter);
+                job.step(u32::MAX);
+                assert_eq!(job.finish().pixels(), img.pixels());
+
+                let mut rows = src.scale_rows((3, 4), filter);
+                while let Some(row) = rows.next_row() {
+                    assert_eq!(row, [[0.; 4]; 3]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resize_empty() {
+        for res in EMPTY {
+            let out = empty(res).resize((3, 4));
+            assert_eq!(out.res, (3, 4));
+            assert!(out.pixels().iter().all(|p| *p == [0.; 4]));
+            let mut out = empty(res);
+            out.scale_indexed((3, 4));
+            assert_eq!(out.res, (3, 4));
+        }
+    }
+
+    #[test]
+    fn viewport_empty() {
+        for res in EMPTY {
+            for filter in FILTERS {
+                let out = empty(res).viewport(((-0.5, 1.25), (2., 3.)), (3, 4), filter);
+                assert_eq!(out.res, (3, 4));
+                assert!(out.pixels().iter().all(|p| *p == [0.; 4]));
+            }
+        }
+    }
+
+    #[test]
+    fn in_place_matches_allocated() {
+        let src = crate::fixtures::photo((23, 17));
+        for new in [(23, 17), (11, 17), (23, 5), (7, 6), (1, 1)] {
+            for filter in FILTERS {
+                let mut img = src.clone();
+                img.scale_with(new, filter);
+                let job = src.scale_job(new, filter).finish();
+                assert_eq!(img.res, job.res);
+                assert_eq!(img.pixels(), job.pixels(), "{new:?} {filter:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn from_bytes_scaled_matches_scale() {
+        let src = crate::fixtures::photo((41, 29));
+        let bytes = src.to_bytes();
+        for target in [
+            (41, 29),
+            (20, 29),
+            (41, 3),
+            (13, 10),
+            (4, 4),
+            (1, 1),
+            (64, 31),
+        ] {
+            let thumb =
+                Image::from_bytes_scaled(&bytes, (41, 29), ColorSpace::sRGB, target).unwrap();
+            let mut full = Image::from_bytes(&bytes, (41, 29), ColorSpace::sRGB);
+            full.scale_with(target, ScaleFilter::Box);
+            assert_eq!(thumb.res, target);
+            let diff = crate::fixtures::max_diff(thumb.pixels(), full.pixels());
+            assert!(diff < 1e-5, "{target:?}: {diff}");
+        }
+    }
+
+    #[test]
+    fn thumbnail_from_raw_matches_resize() {
+        let src = crate::fixtures::photo((37, 22));
+        let bytes = src.to_bytes();
+        for target in [(18, 11), (5, 7), (1, 1)] {
+            let thumb = thumbnail_from_raw(
+                &bytes,
+                (37, 22),
+                PixelFormat::Rgba8888,
+                ColorSpace::sRGB,
+                target,
+            )
+            .unwrap();
+            let diff = crate::fixtures::max_diff(thumb.pixels(), src.resize(target).pixels());
+            assert!(diff < 1e-5, "{target:?}: {diff}");
+        }
+    }
+
+    #[test]
+    fn from_bytes_scaled_errors() {
+        let bytes = [0; 4 * 6];
+        let scaled = |data: &[u8], res, target| {
+            Image::from_bytes_scaled(data, res, ColorSpace::sRGB, target).err()
+        };
+        assert_eq!(scaled(&bytes, (3, 2), (1, 1)), None);
+        assert_eq!(
+            scaled(&bytes[1..], (3, 2), (1, 1)),
+            Some(ImageError::BufferSize {
+                expected: 24,
+                actual: 23
+            })
+        );
+        assert_eq!(
+            scaled(&bytes, (3, 2), (0, 1)),
+            Some(ImageError::InvalidArgument)
+        );
+        assert_eq!(
+            scaled(&[], (0, 2), (1, 1)),
+            Some(ImageError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn scale_jobs_interleave() {
+        let (a, b) = (
+            crate::fixtures::photo((30, 20)),
+            crate::fixtures::photo((9, 31)),
+        );
+        let mut ja = a.scale_job((13, 7), ScaleFilter::Bilinear);
+        let mut jb = b.scale_job((20, 40), ScaleFilter::MITCHELL);
+        // Nothing happens for no rows
+        assert_eq!(ja.step(0), JobStatus::InProgress);
+        assert_eq!(ja.next_row, 0);
+        while (ja.step(2) == JobStatus::InProgress) | (jb.step(3) == JobStatus::InProgress) {}
+        assert_eq!(ja.status(), JobStatus::Done);
+        let (ra, rb) = (ja.finish(), jb.finish());
+        let (mut ea, mut eb) = (a.clone(), b.clone());
+        ea.scale_with((13, 7), ScaleFilter::Bilinear);
+        eb.scale_with((20, 40), ScaleFilter::MITCHELL);
+        assert_eq!(ra.pixels(), ea.pixels());
+        assert_eq!(rb.pixels(), eb.pixels());
+    }
+
+    #[test]
+    fn scale_job_finish_early() {
+        let src = crate::fixtures::photo((16, 16));
+        let mut job = src.scale_job((5, 5), ScaleFilter::Box);
+        job.step(1);
+        let mut expected = src.clone();
+        expected.scale_with((5, 5), ScaleFilter::Box);
+        assert_eq!(job.finish().pixels(), expected.pixels());
+    }
+
+    #[test]
+    fn viewport_matches_crop_and_scale() {
+        let src = crate::fixtures::photo((31, 23));
+        for (origin, size) in [((3, 2), (12, 9)), ((0, 0), (31, 23)), ((30, 5), (1, 18))] {
+            for out in [size, (6, 4), (20, 15), (1, 1)] {
+                for filter in FILTERS {
+                    let rect = (
+                        (origin.0 as f32, origin.1 as f32),
+                        (size.0 as f32, size.1 as f32),
+                    );
+                    let view = src.viewport(rect, out, filter);
+                    let mut expected = src.clone();
+                    expected.crop(origin, size).unwrap();
+                    expected.scale_with(out, filter);
+                    assert_eq!(view.res, out);
+                    assert_eq!(
+                        view.pixels(),
+                        expected.pixels(),
+                        "{origin:?} {size:?} {out:?} {filter:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn viewport_pans_smoothly() {
+        // Horizontal ramp, so panning right brightens every pixel
+        let data = (0..64 * 8)
+            .map(|i| [(i % 64) as f32 / 64., 0., 0., 1.])
+            .collect();
+        let src = Image::from_parts(data, (64, 8), ColorSpace::AsIs);
+        let view = |x: f32, filter| src.viewport(((x, 2.), (16., 4.)), (16, 4), filter);
+        for filter in [
+            ScaleFilter::Bilinear,
+            ScaleFilter::Box,
+            ScaleFilter::MITCHELL,
+        ] {
+            let frames: Vec<Image> = (0..=8)
+                .map(|k| view(10. + k as f32 * 0.125, filter))
+                .collect();
+            for pair in frames.windows(2) {
+                for (a, b) in pair[0].pixels().iter().zip(pair[1].pixels()) {
+                    // Each step moves a fraction of a ramp step, never a jump
+                    let d = b[0] - a[0];
+                    assert!(d > 0. && d < 1. / 64., "{filter:?} {d}");
+                }
+            }
+            // A whole pixel of panning matches the whole pixel viewport
+            let diff = crate::fixtures::max_diff(frames[8].pixels(), view(11., filter).pixels());
+            assert!(diff < 1e-5, "{filter:?} {diff}");
+        }
+    }
+
+    #[test]
+    fn viewport_outside_clamps() {
+        let src = crate::fixtures::photo((20, 10));
+        for filter in FILTERS {
+            // Entirely above and left, only the corner is nearest
+            let out = src.viewport(((-50., -40.), (10., 10.)), (4, 4), filter);
+            let corner = src.pixels()[0];
+            for p in out.pixels() {
+                assert!(
+                    crate::fixtures::max_diff(&[*p], &[corner]) < 1e-6,
+                    "{filter:?}"
+                );
+            }
+            // Entirely right, the last column stretched
+            let out = src.viewport(((100., 0.), (5., 10.)), (3, 10), filter);
+            let mut edge = src.clone();
+            edge.crop((19, 0), (1, 10)).unwrap();
+            edge.scale_with((3, 10), filter);
+            let diff = crate::fixtures::max_diff(out.pixels(), edge.pixels());
+            assert!(diff < 1e-6, "{filter:?} {diff}");
+        }
+    }
+
+    const CUBICS: [(f32, f32); 6] = [
+        (0., 0.5),
+        (1. / 3., 1. / 3.),
+        (1., 0.),
+        (0., 0.),
+        (0.5, 0.25),
+        (0.2, 1.),
+    ];
+
+    #[test]
+    fn cubic_presets() {
+        let kernel = |f: ScaleFilter| match f {
+            ScaleFilter::Cubic { b, c } => move |x: f32| cubic_weight(b, c, x),
+            _ => unreachable!(),
+        };
+        // Values of each kernel at 0, 0.5, 1, 1.5, and 2
+        for (filter, want) in [
+            (ScaleFilter::CATMULL_ROM, [1., 0.5625, 0., -0.0625, 0.]),
+            (
+                ScaleFilter::MITCHELL,
+                [8. / 9., 77. / 144., 1. / 18., -5. / 144., 0.],
+            ),
+            (
+                ScaleFilter::B_SPLINE,
+                [2. / 3., 23. / 48., 1. / 6., 1. / 48., 0.],
+            ),
+        ] {
+            let k = kernel(filter);
+            for (i, want) in want.into_iter().enumerate() {
+                let x = i as f32 / 2.;
+                assert!((k(x) - want).abs() < 1e-6, "{filter:?} {x}");
+                assert_eq!(k(x), k(-x));
+            }
+            assert_eq!(k(2.5), 0.);
+        }
+    }
+
+    #[test]
+    fn cubic_partition_of_unity() {
+        for (b, c) in CUBICS {
+            for i in 0..=16 {
+                let t = i as f32 / 16.;
+                let sum: f32 = (-2..=2).map(|k| cubic_weight(b, c, t - k as f32)).sum();
+                assert!((sum - 1.).abs() < 1e-5, "{b} {c} {t}");
+            }
+            for (from, to) in [(13, 5), (5, 13), (7, 7), (1, 4)] {
+                let weights = FilterWeights::compute(from, to, ScaleFilter::Cubic { b, c });
+                for contrib in &weights.contribs {
+                    let sum: f32 = contrib.weights.iter().sum();
+                    assert!((sum - 1.).abs() < 1e-5, "{b} {c} {from} {to}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cubic_keeps_constants() {
+        let p = [0.25, 0.5, 0.75, 1.];
+        for (b, c) in CUBICS {
+            for to in [(5, 3), (31, 17), (12, 9)] {
+                let mut img = crate::fixtures::solid((12, 9), p);
+                img.scale_with(to, ScaleFilter::Cubic { b, c });
+                for q in img.pixels() {
+                    for (q, p) in q.iter().zip(p) {
+                        assert!((q - p).abs() < 1e-6, "{b} {c} {to:?} {q}");
+                    }
+                }
+            }
+        }
+    }
+
+    const PALETTE: [WorkPixel; 4] = [
+        [0., 0., 0., 1.],
+        [1., 0., 0., 1.],
+        [0., 0.5, 1., 1.],
+        [1., 1., 1., 0.],
+    ];
+
+    /// `PALETTE` colors at `index` of each pixel
+    fn indexed(res: ResXY, mut index: impl FnMut(u32, u32) -> usize) -> Image {
+        let data = (0..res.0 * res.1)
+            .map(|i| PALETTE[index(i % res.0, i / res.0)])
+            .collect();
+        Image::from_parts(data, res, ColorSpace::sRGB)
+    }
+
+    #[test]
+    fn scale_indexed_keeps_palette() {
+        let mut seed = 9;
+        let img = indexed((37, 23), |_, _| {
+            (crate::fixtures::noise(&mut seed) * 4.) as usize
+        });
+        for new in [(10, 7), (5, 40), (36, 22), (1, 1)] {
+            let mut out = img.clone();
+            out.scale_indexed(new);
+            assert_eq!(out.res, new);
+            assert!(out.pixels().iter().all(|p| PALETTE.contains(p)), "{new:?}");
+        }
+    }
+
+    #[test]
+    fn scale_indexed_majority_and_ties() {
+        let pick = |img: &Image| {
+            let mut img = img.clone();
+            img.scale_indexed((1, 1));
+            img.pixels()[0]
+        };
+        // Two to one, wherever the majority is
+        assert_eq!(pick(&indexed((3, 1), |x, _| (x > 0) as usize)), PALETTE[1]);
+        assert_eq!(pick(&indexed((1, 3), |_, y| (y == 2) as usize)), PALETTE[0]);
+        // Ties go to the first color, row by row
+        let tie = indexed((2, 2), |x, y| ((x + y) % 2) as usize * 2);
+        assert_eq!(pick(&tie), PALETTE[0]);
+        let tie = indexed((2, 2), |x, y| (x == y) as usize);
+        assert_eq!(pick(&tie), PALETTE[1]);
+        let tie = indexed((4, 1), |x, _| [3, 1, 1, 3][x as usize]);
+        assert_eq!(pick(&tie), PALETTE[3]);
+        assert_eq!(pick(&tie), pick(&tie));
+    }
+
+    #[test]
+    fn scale_indexed_grows_like_nearest() {
+        let img = indexed((5, 4), |x, y| ((x * 3 + y) % 4) as usize);
+        for new in [(5, 4), (13, 9), (5, 11), (17, 4)] {
+            let (mut a, mut b) = (img.clone(), img.clone());
+            a.scale_indexed(new);
+            b.scale_with(new, ScaleFilter::Nearest);
+            assert_eq!(a.pixels(), b.pixels(), "{new:?}");
+        }
+    }
+
+    /// `resize` spelled out, scaling x with `fx` then y with `fy`
+    fn resize_by_hand(img: &Image, new: ResXY, fx: ScaleFilter, fy: ScaleFilter) -> Image {
+        let mut img = img.clone();
+        img.to_color(ColorSpace::sRGBLinear);
+        img.to_alpha_mode(AlphaMode::Premultiplied);
+        img.scale_with((new.0, img.height()), fx);
+        img.scale_with(new, fy);
+        img.map_pixels(|p| {
+            let a = p[3].clamp(0., 1.);
+            [p[0].clamp(0., a), p[1].clamp(0., a), p[2].clamp(0., a), a]
+        });
+        img.to_alpha_mode(AlphaMode::Straight);
+        img.to_color(ColorSpace::sRGB);
+        img
+    }
+
+    #[test]
+    fn resize_filter_per_axis() {
+        let img = crate::fixtures::photo((24, 10));
+        let (shrink, grow) = (ScaleFilter::Box, ScaleFilter::CATMULL_ROM);
+        for (new, fx, fy) in [((9, 23), shrink, grow), ((40, 4), grow, shrink)] {
+            let got = img.resize(new);
+            let want = resize_by_hand(&img, new, fx, fy);
+            assert!(
+                crate::fixtures::max_diff(got.pixels(), want.pixels()) < 1e-4,
+                "{new:?}"
+            );
+            let wrong = resize_by_hand(&img, new, fy, fx);
+            assert!(
+                crate::fixtures::max_diff(got.pixels(), wrong.pixels()) > 0.01,
+                "{new:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn resize_does_not_fringe() {
+        // Red in the middle, the transparent pixels around it green
+        let mut img = crate::fixtures::solid((12, 12), [0., 1., 0., 0.]);
+        img.map_pixels_indexed(|(x, y), p| {
+            if (4..8).contains(&x) && (3..9).contains(&y) {
+                [1., 0., 0., 1.]
+            } else {
+                p
+            }
+        });
+        for new in [(5, 7), (31, 29), (5, 30)] {
+            let out = img.resize(new);
+            let mut covered = 0;
+            for p in out.pixels() {
+                assert!((0. ..=1.).contains(&p[3]));
+                if p[3] > 1e-3 {
+                    covered += 1;
+                    assert!(p[1] < 1e-3 && p[0] > 0.999, "{new:?} {p:?}");
+                }
+            }
+            assert!(covered > 0);
+        }
+    }
+
+    #[test]
+    fn resize_same_size() {
+        let img = crate::fixtures::photo((9, 7));
+        assert_eq!(img.resize((9, 7)).pixels(), img.pixels());
+        let once = img.resize((20, 5));
+        assert_eq!(once.resize((20, 5)).pixels(), once.pixels());
+        // Hard edges ring with Catmull-Rom, but are clamped
+        let edges = crate::fixtures::ramp((6, 6)).resize((25, 25));
+        assert!(edges
+            .pixels()
+            .iter()
+            .flatten()
+            .all(|c| (0. ..=1.).contains(c)));
+    }
+
+    /// Nearest neighbor the general way, a coordinate per pixel
+    fn nearest_by_hand(data: &[f32], (w, h): ResXY, (nw, nh): ResXY) -> Vec<f32> {
+        (0..nh)
+            .flat_map(|y| (0..nw).map(move |x| (x, y)))
+            .map(|(x, y)| data[(nearest(y, h, nh) * w + nearest(x, w, nw)) as usize])
+            .collect()
+    }
+
+    #[test]
+    fn nearest_integer_matches_general() {
+        let mut fast = 0;
+        for res in (1..=6u32).flat_map(|w| (1..=6u32).map(move |h| (w, h))) {
+            let src: Vec<f32> = (0..res.0 * res.1).map(|i| i as f32).collect();
+            for new in (1..=19u32).flat_map(|w| (1..=19u32).map(move |h| (w, h))) {
+                let up = new.0.is_multiple_of(res.0) && new.1.is_multiple_of(res.1);
+                let down = res.0.is_multiple_of(new.0) && res.1.is_multiple_of(new.1);
+                let mut data = src.clone();
+                assert_eq!(nearest_integer(&mut data, res, new), up || down);
+                if up || down {
+                    fast += 1;
+                } else {
+                    assert_eq!(data, src);
+                }
+
+                let mut data = src.clone();
+                scale_buffer(&mut data, res, new, ScaleFilter::Nearest);
+                assert_eq!(data, nearest_by_hand(&src, res, new), "{res:?} to {new:?}");
+            }
+        }
+        assert!(fast > 100);
+    }
+
+    #[test]
+    fn scale_rows_match_scale() {
+        let img = crate::fixtures::photo((13, 9));
+        for filter in [
+            ScaleFilter::Nearest,
+            ScaleFilter::Bilinear,
+            ScaleFilter::Box,
+            ScaleFilter::CATMULL_ROM,
+            ScaleFilter::MITCHELL,
+        ] {
+            for new in [(13, 9), (5, 4), (27, 20), (1, 1), (13, 3), (40, 9)] {
+                let mut want = img.clone();
+                want.scale_with(new, filter);
+                let mut rows = img.scale_rows(new, filter);
+                let mut got = Vec::new();
+                while let Some(row) = rows.next_row() {
+                    assert_eq!(row.len(), new.0 as usize);
+                    got.extend_from_slice(row);
+                }
+                assert_eq!(got, want.pixels(), "{filter:?} {new:?}");
+                assert_eq!(rows.remaining(), 0);
+                assert!(rows.next_row().is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn scale_rows_stop_early() {
+        let img = crate::fixtures::photo((13, 9));
+        let mut want = img.clone();
+        want.scale_with((7, 30), ScaleFilter::CATMULL_ROM);
+        let mut rows = img.scale_rows((7, 30), ScaleFilter::CATMULL_ROM);
+        assert_eq!(rows.remaining(), 30);
+        for y in 0..3 {
+            assert_eq!(rows.next_row().unwrap(), &want.pixels()[y * 7..][..7]);
+        }
+        assert_eq!(rows.remaining(), 27);
+        // Only a row of output is ever held
+        assert_eq!(rows.out.len(), 7);
+        drop(rows);
+    }
+
+    #[test]
+    fn thumbnail_matches_resize() {
+        let img = crate::fixtures::photo((61, 47));
+        let mut seed = 4;
+        let mut alpha = img.clone();
+        alpha.map_pixels(|p| [p[0], p[1], p[2], crate::fixtures::noise(&mut seed)]);
+        for (img, format) in [
+            (&img, PixelFormat::Rgba8888),
+            (&alpha, PixelFormat::Rgba8888),
+            (&img, PixelFormat::Rgb565Le),
+        ] {
+            let raw = img.to_raw(format);
+            for target in [(16, 16), (20, 9), (61, 1), (1, 1)] {
+                let thumb = thumbnail_from_raw(&raw, img.res, format, img.color, target).unwrap();
+                let want = Image::from_raw(&raw, img.res, format, img.color)
+                    .unwrap()
+                    .resize(target);
+                assert_eq!((thumb.res, thumb.color), (target, img.color));
+                assert_eq!(thumb.alpha, AlphaMode::Straight);
+                let (got, want) = (thumb.to_bytes(), want.to_bytes());
+                for (g, w) in got.iter().zip(&want) {
+                    assert!(g.abs_diff(*w) <= 1, "{format:?} {target:?} {g} {w}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn thumbnail_errors() {
+        let raw = [0; 4 * 4 * 2];
+        let thumb = |res, target| {
+            thumbnail_from_raw(&raw, res, PixelFormat::Rgb565Le, ColorSpace::sRGB, target).err()
+        };
+        assert_eq!(thumb((4, 4), (2, 2)), None);
+        assert_eq!(thumb((4, 4), (0, 2)), Some(ImageError::InvalidArgument));
+        assert_eq!(thumb((0, 4), (2, 2)), Some(ImageError::InvalidArgument));
+        assert_eq!(
+            thumb((4, 5), (2, 2)),
+            Some(ImageError::BufferSize {
+                expected: 40,
+                actual: 32
+            })
+        );
+    }
+}
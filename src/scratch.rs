@@ -0,0 +1,60 @@
+//! Caller provided memory for operations that would otherwise need a full
+//! size temporary
+use crate::{ImageError, WorkPixel};
+
+/// A byte buffer lent to an operation for its temporaries, like
+/// [`Image::gaussian_blur_tiled`][crate::Image::gaussian_blur_tiled], so it
+/// works through the image in tiles that fit instead of allocating a copy
+/// of it
+///
+/// Any alignment is fine, though a few bytes at the start may go unused.
+/// The contents are overwritten.
+#[derive(Debug)]
+pub struct Scratch<'a> {
+    buf: &'a mut [u8],
+}
+
+impl<'a> Scratch<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf }
+    }
+
+    /// Size of the buffer, in bytes
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Bytes before the first aligned [`WorkPixel`]
+    fn offset(&self) -> usize {
+        self.buf
+            .as_ptr()
+            .align_offset(align_of::<WorkPixel>())
+            .min(self.buf.len())
+    }
+
+    /// The buffer as at least `min` pixels
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::BufferSize`] if it's too small, with the smallest
+    ///   size it could be as `expected`
+    pub(crate) fn pixels(&mut self, min: usize) -> Result<&mut [WorkPixel], ImageError> {
+        let expected = min
+            .checked_mul(size_of::<WorkPixel>())
+            .and_then(|b| b.checked_add(self.offset()))
+            .ok_or(ImageError::InvalidArgument)?;
+        if self.buf.len() < expected {
+            return Err(ImageError::BufferSize {
+                expected,
+                actual: self.buf.len(),
+            });
+        }
+        // Safety: Every bit pattern is a valid `f32`
+        let (_, pixels, _) = unsafe { self.buf.align_to_mut::<WorkPixel>() };
+        Ok(pixels)
+    }
+}
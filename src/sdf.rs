@@ -0,0 +1,210 @@
+//! Signed distance fields
+use alloc::{vec, vec::Vec};
+
+use crate::{ColorSpace, Image, ScaleFilter, F32};
+
+const INF: f32 = 1e20;
+
+/// Felzenszwalb & Huttenlocher 1D squared distance transform of `f` into `d`
+fn edt_1d(f: &[f32], d: &mut [f32], v: &mut [usize], z: &mut [f32]) {
+    let n = f.len();
+    let mut k = 0;
+    v[0] = 0;
+    z[0] = -INF;
+    z[1] = INF;
+    for q in 1..n {
+        let intersect =
+            |p: usize| ((f[q] + (q * q) as f32) - (f[p] + (p * p) as f32)) / (2 * q - 2 * p) as f32;
+        let mut s = intersect(v[k]);
+        // z[0] is -INF, so this always stops at k == 0
+        while s <= z[k] {
+            k -= 1;
+            s = intersect(v[k]);
+        }
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = INF;
+    }
+    k = 0;
+    for (q, d) in d.iter_mut().enumerate() {
+        while z[k + 1] < q as f32 {
+            k += 1;
+        }
+        let p = v[k];
+        let dq = q as f32 - p as f32;
+        *d = dq * dq + f[p];
+    }
+}
+
+/// Exact 2D euclidean distance transform, distance from each pixel to the
+/// nearest pixel where `grid` is `0`
+fn edt_2d(grid: &mut [f32], width: usize, height: usize) {
+    let n = width.max(height);
+    let mut f = vec![0.; n];
+    let mut d = vec![0.; n];
+    let mut v = vec![0; n];
+    let mut z = vec![0.; n + 1];
+
+    for x in 0..width {
+        for y in 0..height {
+            f[y] = grid[y * width + x];
+        }
+        edt_1d(&f[..height], &mut d[..height], &mut v, &mut z);
+        for y in 0..height {
+            grid[y * width + x] = d[y];
+        }
+    }
+    for y in 0..height {
+        let row = &mut grid[y * width..][..width];
+        f[..width].copy_from_slice(row);
+        edt_1d(&f[..width], row, &mut v, &mut z);
+    }
+    for g in grid {
+        *g = g.sqrt();
+    }
+}
+
+impl Image {
+    /// Create a signed distance field from the alpha channel
+    ///
+    /// Pixels with alpha of at least `0.5` are inside. The distance to the
+    /// edge, in pixels, is mapped so the edge is at `0.5`, inside is above,
+    /// and `spread` pixels from the edge is `0` or `1`.
+    ///
+    /// The result is grayscale, [`ColorSpace::AsIs`], with opaque alpha.
+    pub fn to_sdf(&self, spread: f32) -> Image {
+        let (w, h) = (self.width() as usize, self.height() as usize);
+        let inside = |a: f32| a >= 0.5;
+        let mut outside_dist: Vec<f32> = self
+            .data
+            .iter()
+            .map(|p| if inside(p[3]) { 0. } else { INF })
+            .collect();
+        let mut inside_dist: Vec<f32> = self
+            .data
+            .iter()
+            .map(|p| if inside(p[3]) { INF } else { 0. })
+            .collect();
+        edt_2d(&mut outside_dist, w, h);
+        edt_2d(&mut inside_dist, w, h);
+
+        let spread = spread.max(f32::EPSILON);
+        let data = outside_dist
+            .iter()
+            .zip(&inside_dist)
+            .map(|(o, i)| {
+                // The edge is between pixels, so both sides are off by half
+                let d = if *o > 0. { o - 0.5 } else { 0.5 - i };
+                let v = (0.5 - d / (2. * spread)).clamp(0., 1.);
+                [v, v, v, 1.]
+            })
+            .collect();
+        Image::from_parts(data, self.res, ColorSpace::AsIs)
+    }
+
+    /// Render a signed distance field, as made by [`Image::to_sdf`], at
+    /// `scale` times its size
+    ///
+    /// Values above `threshold` are inside. Edges are anti-aliased over about
+    /// one output pixel. The result is white, with the shape in alpha.
+    pub fn render_sdf(&self, scale: f32, threshold: f32) -> Image {
+        let new = (
+            ((self.width() as f32 * scale).round() as u32).max(1),
+            ((self.height() as f32 * scale).round() as u32).max(1),
+        );
+        let mut field = self.clone();
+        field.scale_with(new, ScaleFilter::Bilinear);
+
+        let (w, h) = new;
+        let v = |x: u32, y: u32| field.data[(y * w + x) as usize][0];
+        let mut data = Vec::with_capacity(field.data.len());
+        for y in 0..h {
+            for x in 0..w {
+                let c = v(x, y);
+                // Screen space gradient, to get a one pixel wide edge
+                let dx = (v((x + 1).min(w - 1), y) - v(x.saturating_sub(1), y)) / 2.;
+                let dy = (v(x, (y + 1).min(h - 1)) - v(x, y.saturating_sub(1))) / 2.;
+                let width = (dx.abs() + dy.abs()).max(1e-6);
+                let a = ((c - threshold) / width + 0.5).clamp(0., 1.);
+                data.push([1., 1., 1., a]);
+            }
+        }
+        Image::from_parts(data, new, ColorSpace::AsIs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::{ColorSpace, Image, F32};
+
+    const R: f32 = 10.;
+
+    /// A filled circle of radius `R` in the middle of 32x32, in alpha
+    fn circle() -> Image {
+        let mut data = Vec::new();
+        for y in 0..32 {
+            for x in 0..32 {
+                let (dx, dy) = (x as f32 + 0.5 - 16., y as f32 + 0.5 - 16.);
+                let a = if dx * dx + dy * dy <= R * R { 255 } else { 0 };
+                data.extend([255, 255, 255, a]);
+            }
+        }
+        Image::from_bytes(&data, (32, 32), ColorSpace::AsIs)
+    }
+
+    /// Where `values`, sampled at pixel centers, first falls through `0.5`
+    fn crossing(values: impl Iterator<Item = f32>) -> f32 {
+        let values: Vec<f32> = values.collect();
+        let i = values
+            .windows(2)
+            .position(|w| w[0] >= 0.5 && w[1] < 0.5)
+            .unwrap();
+        let t = (values[i] - 0.5) / (values[i] - values[i + 1]);
+        i as f32 + 0.5 + t
+    }
+
+    #[test]
+    fn circle_edge_at_half() {
+        let sdf = circle().to_sdf(4.);
+        let v = |x: u32, y: u32| sdf.get_pixel((x, y)).unwrap()[0];
+        assert!(v(16, 16) > 0.99 && v(0, 0) < 0.01);
+        for y in 8..24 {
+            let dy = y as f32 + 0.5 - 16.;
+            let edge = 16. + (R * R - dy * dy).sqrt();
+            let x = crossing((16..32).map(|x| v(x, y))) + 16.;
+            assert!((x - edge).abs() < 1., "row {y}: {x} vs {edge}");
+            let y2 = crossing((16..32).map(|y2| v(y, y2))) + 16.;
+            assert!((y2 - edge).abs() < 1., "column {y}: {y2} vs {edge}");
+        }
+    }
+
+    #[test]
+    fn render_is_smooth() {
+        let out = circle().to_sdf(4.).render_sdf(2., 0.5);
+        assert_eq!(out.res, (64, 64));
+        let a = |x: u32, y: u32| out.get_pixel((x, y)).unwrap()[3];
+        let mut partial = 0;
+        for y in 14..50 {
+            // Outwards from the middle, alpha only falls
+            let row: Vec<f32> = (32..64).map(|x| a(x, y)).collect();
+            assert!(row.windows(2).all(|w| w[1] <= w[0] + 1e-6), "row {y}");
+            partial += row.iter().filter(|a| **a > 0.02 && **a < 0.98).count();
+        }
+        // Anti-aliased, at least one soft pixel on every row the edge crosses
+        assert!(partial >= 36, "{partial}");
+        // And where the edge actually is
+        let x = crossing((32..64).map(|x| a(x, 32))) + 32.;
+        assert!((x - 2. * (16. + R)).abs() < 1.5, "{x}");
+    }
+
+    #[test]
+    fn render_empty() {
+        for res in [(0, 5), (5, 0)] {
+            let sdf = Image::from_bytes(&[], res, ColorSpace::sRGB);
+            assert!(!sdf.render_sdf(2., 0.5).pixels().is_empty());
+        }
+    }
+}
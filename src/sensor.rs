@@ -0,0 +1,500 @@
+//! Raw Bayer sensor data, see [`Image::from_bayer`] and [`SensorPipeline`]
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    color_matrix::{from_rgb_matrix, identity, transform, ColorMatrix},
+    layout::prepare,
+    AlphaMode, ColorSpace, Converter, Image, ImageError, PixelFormat, ResXY, WorkPixel,
+};
+
+/// Color filter layout of a Bayer sensor, named by its top left 2x2 block
+/// in reading order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BayerPattern {
+    Rggb,
+    Bggr,
+    Grbg,
+    Gbrg,
+}
+
+impl BayerPattern {
+    /// Channel, `0..3`, of the sample at `(x, y)`
+    fn channel(self, x: usize, y: u32) -> usize {
+        let block = match self {
+            BayerPattern::Rggb => [0, 1, 1, 2],
+            BayerPattern::Bggr => [2, 1, 1, 0],
+            BayerPattern::Grbg => [1, 0, 2, 1],
+            BayerPattern::Gbrg => [1, 2, 0, 1],
+        };
+        block[(y as usize & 1) * 2 + (x & 1)]
+    }
+}
+
+/// Bytes per sample of `bits`, one up to 8 and two, little endian, above
+fn sample_bytes(bits: u8) -> Result<usize, ImageError> {
+    match bits {
+        1..=8 => Ok(1),
+        9..=16 => Ok(2),
+        _ => Err(ImageError::InvalidArgument),
+    }
+}
+
+/// Read one row of `bits` samples from `raw` into `out`, scaled so the
+/// largest value is `1`
+fn read_row(raw: &[u8], bits: u8, out: &mut [f32]) {
+    let max = ((1u32 << bits) - 1) as f32;
+    if bits <= 8 {
+        for (o, b) in out.iter_mut().zip(raw) {
+            *o = *b as f32 / max;
+        }
+    } else {
+        for (o, b) in out.iter_mut().zip(raw.chunks_exact(2)) {
+            *o = u16::from_le_bytes([b[0], b[1]]) as f32 / max;
+        }
+    }
+}
+
+/// Bilinear demosaic of row `y` into `out`, given the rows `above` and
+/// `below` it
+///
+/// Outside the frame mirrors around the edge samples, which keeps the
+/// filter pattern, so the row above the first one is the second. Rows must
+/// be at least two samples wide.
+fn demosaic_row(
+    above: &[f32],
+    row: &[f32],
+    below: &[f32],
+    y: u32,
+    pattern: BayerPattern,
+    out: &mut [WorkPixel],
+) {
+    let w = row.len();
+    for (x, o) in out.iter_mut().enumerate() {
+        let l = if x == 0 { 1 } else { x - 1 };
+        let r = if x + 1 == w { w - 2 } else { x + 1 };
+        let mut p = [0., 0., 0., 1.];
+        match pattern.channel(x, y) {
+            1 => {
+                // Red or blue to the sides, the other above and below
+                let side = pattern.channel(l, y);
+                p[1] = row[x];
+                p[side] = (row[l] + row[r]) / 2.;
+                p[2 - side] = (above[x] + below[x]) / 2.;
+            }
+            c => {
+                p[c] = row[x];
+                p[1] = (row[l] + row[r] + above[x] + below[x]) / 4.;
+                p[2 - c] = (above[l] + above[r] + below[l] + below[r]) / 4.;
+            }
+        }
+        *o = p;
+    }
+}
+
+impl Image {
+    /// Demosaic raw Bayer sensor samples of `bit_depth` bits, into a
+    /// [`ColorSpace::sRGBLinear`] image
+    ///
+    /// Samples are one byte each up to 8 bits, and two little endian bytes
+    /// up to 16, scaled so the largest value is `1`. Missing channels are
+    /// interpolated bilinearly, with the edges mirrored. No white balance or
+    /// color correction is done, see [`Image::white_balance_gains`] and
+    /// [`Image::apply_color_matrix`].
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `bit_depth` isn't `1..=16`, or
+    ///   `res` is smaller than 2x2
+    /// - [`ImageError::BufferSize`] if `raw` isn't exactly the size of `res`
+    pub fn from_bayer(
+        raw: &[u8],
+        res: ResXY,
+        pattern: BayerPattern,
+        bit_depth: u8,
+    ) -> Result<Image, ImageError> {
+        let bytes = sample_bytes(bit_depth)?;
+        let (w, h) = (res.0 as usize, res.1 as usize);
+        if w < 2 || h < 2 {
+            return Err(ImageError::InvalidArgument);
+        }
+        let expected = w
+            .checked_mul(h)
+            .and_then(|n| n.checked_mul(bytes))
+            .ok_or(ImageError::InvalidArgument)?;
+        if raw.len() != expected {
+            return Err(ImageError::BufferSize {
+                expected,
+                actual: raw.len(),
+            });
+        }
+        let mut samples = vec![0.; w * h];
+        for (row, raw) in samples.chunks_exact_mut(w).zip(raw.chunks_exact(w * bytes)) {
+            read_row(raw, bit_depth, row);
+        }
+
+        let row = |y: usize| &samples[y * w..][..w];
+        let mut data = vec![WorkPixel::default(); w * h];
+        for (y, out) in data.chunks_exact_mut(w).enumerate() {
+            let above = if y == 0 { 1 } else { y - 1 };
+            let below = if y + 1 == h { h - 2 } else { y + 1 };
+            demosaic_row(row(above), row(y), row(below), y as u32, pattern, out);
+        }
+        Ok(Image::from_parts(data, res, ColorSpace::sRGBLinear))
+    }
+}
+
+/// Streaming [`Image::from_bayer`], white balance, color correction, and
+/// export, for sensors that deliver frames a row at a time
+///
+/// Only three rows of samples are kept, plus any partly received row, so
+/// the frame never exists in `f32`. The output is identical to
+/// [`Image::from_bayer`], [`Image::white_balance_gains`],
+/// [`Image::apply_color_matrix`] with [`from_rgb_matrix`],
+/// [`Image::to_color`], and [`Image::to_raw`], in that order.
+///
+/// Each row is written once the row below it arrives, and the last one by
+/// [`SensorPipeline::finish`], which also gets ready for the next frame.
+///
+/// [`from_rgb_matrix`]: crate::color_matrix::from_rgb_matrix
+#[derive(Debug, Clone)]
+pub struct SensorPipeline {
+    width: usize,
+    pattern: BayerPattern,
+    bits: u8,
+    gains: [f32; 3],
+    ccm: ColorMatrix,
+    /// From [`ColorSpace::sRGBLinear`] to the output
+    encode: Converter,
+    format: PixelFormat,
+    /// Bytes of a partly received row
+    partial: Vec<u8>,
+    /// The last three rows received, oldest first
+    rows: [Vec<f32>; 3],
+    /// Rows received so far this frame
+    received: u32,
+    pixels: Vec<WorkPixel>,
+}
+
+impl SensorPipeline {
+    /// Read rows of `width` samples of `bit_depth` bits in `pattern`, and
+    /// write them as `format` pixels
+    ///
+    /// Without other steps, this is just demosaicing and encoding to
+    /// [`ColorSpace::sRGB`].
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `bit_depth` isn't `1..=16`, or
+    ///   `width` is less than 2
+    pub fn new(
+        width: u32,
+        pattern: BayerPattern,
+        bit_depth: u8,
+        format: PixelFormat,
+    ) -> Result<Self, ImageError> {
+        sample_bytes(bit_depth)?;
+        if width < 2 {
+            return Err(ImageError::InvalidArgument);
+        }
+        let width = width as usize;
+        Ok(Self {
+            width,
+            pattern,
+            bits: bit_depth,
+            gains: [1.; 3],
+            ccm: identity(),
+            encode: Converter::new(ColorSpace::sRGBLinear, ColorSpace::sRGB),
+            format,
+            partial: Vec::new(),
+            rows: [vec![0.; width], vec![0.; width], vec![0.; width]],
+            received: 0,
+            pixels: vec![WorkPixel::default(); width],
+        })
+    }
+
+    /// White balance with `gains`, like [`Image::white_balance_gains`]
+    pub fn white_balance(mut self, gains: [f32; 3]) -> Self {
+        self.gains = gains.map(|g| g.max(0.));
+        self
+    }
+
+    /// Color correct with the 3x3 RGB matrix `ccm`, after white balance
+    pub fn color_correction(mut self, ccm: [[f32; 3]; 3]) -> Self {
+        self.ccm = from_rgb_matrix(ccm);
+        self
+    }
+
+    /// Encode to `color` instead of [`ColorSpace::sRGB`]
+    pub fn encode(mut self, color: ColorSpace) -> Self {
+        self.encode = Converter::new(ColorSpace::sRGBLinear, color);
+        self
+    }
+
+    /// Bytes in a row of input
+    pub fn input_stride(&self) -> usize {
+        // Checked in `new`
+        self.width * if self.bits <= 8 { 1 } else { 2 }
+    }
+
+    /// Bytes in a row of output
+    pub fn output_stride(&self) -> usize {
+        self.width * self.format.bytes_per_pixel()
+    }
+
+    /// Feed the next `raw` bytes of the frame, writing any rows that
+    /// completes into `out`, and returning how many
+    ///
+    /// `raw` can end anywhere, even partway through a row, the rest of which
+    /// is expected next time. `out` needs room for one row for every row
+    /// `raw` completes, except the first of a frame.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::BufferSize`] if `out` is too small, before anything
+    ///   is read
+    pub fn push_rows(&mut self, mut raw: &[u8], out: &mut [u8]) -> Result<usize, ImageError> {
+        let (stride, out_stride) = (self.input_stride(), self.output_stride());
+        let complete = (self.partial.len() + raw.len()) / stride;
+        let ready = (self.received as usize + complete).saturating_sub(1)
+            - (self.received as usize).saturating_sub(1);
+        let expected = ready * out_stride;
+        if out.len() < expected {
+            return Err(ImageError::BufferSize {
+                expected,
+                actual: out.len(),
+            });
+        }
+
+        let mut written = 0;
+        while !raw.is_empty() {
+            self.rows.rotate_left(1);
+            if self.partial.is_empty() && raw.len() >= stride {
+                read_row(&raw[..stride], self.bits, &mut self.rows[2]);
+                raw = &raw[stride..];
+            } else {
+                let take = (stride - self.partial.len()).min(raw.len());
+                self.partial.extend_from_slice(&raw[..take]);
+                raw = &raw[take..];
+                if self.partial.len() < stride {
+                    // Not a whole row, undo the rotation
+                    self.rows.rotate_right(1);
+                    break;
+                }
+                read_row(&self.partial, self.bits, &mut self.rows[2]);
+                self.partial.clear();
+            }
+            self.received += 1;
+            if self.received >= 2 {
+                let above = if self.received == 2 { 2 } else { 0 };
+                let y = self.received - 2;
+                self.write_row(
+                    above,
+                    1,
+                    2,
+                    y,
+                    &mut out[written * out_stride..][..out_stride],
+                );
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Write the last row of the frame into `out`, and start a new frame
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidData`] if fewer than two rows were pushed, or
+    ///   the last one isn't complete
+    /// - [`ImageError::BufferSize`] if `out` is smaller than a row
+    pub fn finish(&mut self, out: &mut [u8]) -> Result<(), ImageError> {
+        let out_stride = self.output_stride();
+        if out.len() < out_stride {
+            return Err(ImageError::BufferSize {
+                expected: out_stride,
+                actual: out.len(),
+            });
+        }
+        let result = if self.received < 2 || !self.partial.is_empty() {
+            Err(ImageError::InvalidData)
+        } else {
+            let y = self.received - 1;
+            self.write_row(1, 2, 1, y, &mut out[..out_stride]);
+            Ok(())
+        };
+        self.received = 0;
+        self.partial.clear();
+        result
+    }
+
+    /// Demosaic, adjust, and encode row `y`, which is `self.rows[row]`, into
+    /// `out`
+    fn write_row(&mut self, above: usize, row: usize, below: usize, y: u32, out: &mut [u8]) {
+        let rows = &self.rows;
+        demosaic_row(
+            &rows[above],
+            &rows[row],
+            &rows[below],
+            y,
+            self.pattern,
+            &mut self.pixels,
+        );
+        for p in &mut self.pixels {
+            for (c, g) in p.iter_mut().zip(self.gains) {
+                *c = (*c * g).clamp(0., 1.);
+            }
+            *p = transform(&self.ccm, *p);
+        }
+        self.encode.convert_rows(&mut self.pixels);
+        let bpp = self.format.bytes_per_pixel();
+        let transfer = self.encode.to().transfer();
+        for (p, o) in self.pixels.iter().zip(out.chunks_exact_mut(bpp)) {
+            self.format
+                .encode(prepare(*p, self.format, AlphaMode::Straight, transfer), o);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::noise;
+
+    const RES: ResXY = (13, 9);
+    const GAINS: [f32; 3] = [1.8, 1., 1.4];
+    const CCM: [[f32; 3]; 3] = [[1.5, -0.4, -0.1], [-0.2, 1.4, -0.2], [0., -0.5, 1.5]];
+
+    /// A noisy frame of `bits` samples
+    fn frame(bits: u8) -> Vec<u8> {
+        let mut seed = bits as u64;
+        let max = ((1u32 << bits) - 1) as f32;
+        let n = RES.0 as usize * RES.1 as usize;
+        let samples = (0..n).map(|_| (noise(&mut seed) * max) as u16);
+        if bits <= 8 {
+            samples.map(|s| s as u8).collect()
+        } else {
+            samples.flat_map(|s| s.to_le_bytes()).collect()
+        }
+    }
+
+    fn one_shot(raw: &[u8], pattern: BayerPattern, bits: u8, format: PixelFormat) -> Vec<u8> {
+        let mut img = Image::from_bayer(raw, RES, pattern, bits).unwrap();
+        img.white_balance_gains(GAINS);
+        img.apply_color_matrix(&from_rgb_matrix(CCM));
+        img.to_color(ColorSpace::sRGB);
+        img.to_raw(format)
+    }
+
+    /// Stream `raw` through `pipe` in chunks of `chunks` bytes, cycling
+    fn streamed(pipe: &mut SensorPipeline, raw: &[u8], chunks: &[usize]) -> Vec<u8> {
+        let stride = pipe.output_stride();
+        let mut out = Vec::new();
+        let mut buf = vec![0; stride * RES.1 as usize];
+        let mut rest = raw;
+        for &n in chunks.iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (chunk, tail) = rest.split_at(n.min(rest.len()));
+            rest = tail;
+            let rows = pipe.push_rows(chunk, &mut buf).unwrap();
+            out.extend_from_slice(&buf[..rows * stride]);
+        }
+        pipe.finish(&mut buf).unwrap();
+        out.extend_from_slice(&buf[..stride]);
+        out
+    }
+
+    #[test]
+    fn streaming_matches_one_shot() {
+        let patterns = [
+            BayerPattern::Rggb,
+            BayerPattern::Bggr,
+            BayerPattern::Grbg,
+            BayerPattern::Gbrg,
+        ];
+        for bits in [8, 10, 12] {
+            let raw = frame(bits);
+            for pattern in patterns {
+                for format in [PixelFormat::Rgb565Le, PixelFormat::Rgb888] {
+                    let want = one_shot(&raw, pattern, bits, format);
+                    let mut pipe = SensorPipeline::new(RES.0, pattern, bits, format)
+                        .unwrap()
+                        .white_balance(GAINS)
+                        .color_correction(CCM);
+                    let stride = pipe.input_stride();
+                    for chunks in [
+                        &[raw.len()][..],
+                        &[stride],
+                        &[1],
+                        &[stride - 1, stride + 3, 5],
+                        &[stride * 3 + 1, 2],
+                    ] {
+                        let got = streamed(&mut pipe, &raw, chunks);
+                        assert_eq!(got, want, "{bits} {pattern:?} {format:?} {chunks:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn output_needs_room() {
+        let raw = frame(8);
+        let mut pipe =
+            SensorPipeline::new(RES.0, BayerPattern::Rggb, 8, PixelFormat::Rgb888).unwrap();
+        let stride = pipe.output_stride();
+        // The first row only completes once the second arrives
+        assert_eq!(pipe.push_rows(&raw[..13], &mut []), Ok(0));
+        let mut out = vec![0; stride];
+        assert_eq!(
+            pipe.push_rows(&raw[13..39], &mut out),
+            Err(ImageError::BufferSize {
+                expected: stride * 2,
+                actual: stride,
+            })
+        );
+        assert_eq!(pipe.push_rows(&raw[13..26], &mut out), Ok(1));
+    }
+
+    #[test]
+    fn finish_errors() {
+        let raw = frame(8);
+        let mut pipe =
+            SensorPipeline::new(RES.0, BayerPattern::Rggb, 8, PixelFormat::Rgb888).unwrap();
+        let mut out = vec![0; pipe.output_stride() * 2];
+        pipe.push_rows(&raw[..13], &mut out).unwrap();
+        assert_eq!(pipe.finish(&mut out), Err(ImageError::InvalidData));
+        pipe.push_rows(&raw[..30], &mut out).unwrap();
+        assert_eq!(pipe.finish(&mut out), Err(ImageError::InvalidData));
+        // Ready for a new frame after either
+        let stride = pipe.output_stride();
+        let got = streamed(&mut pipe, &raw, &[7]);
+        assert_eq!(got.len(), stride * RES.1 as usize);
+    }
+
+    #[test]
+    fn arguments() {
+        let rgb = PixelFormat::Rgb888;
+        assert!(SensorPipeline::new(1, BayerPattern::Rggb, 8, rgb).is_err());
+        assert!(SensorPipeline::new(4, BayerPattern::Rggb, 0, rgb).is_err());
+        assert!(SensorPipeline::new(4, BayerPattern::Rggb, 17, rgb).is_err());
+        assert!(Image::from_bayer(&[0; 4], (4, 1), BayerPattern::Rggb, 8).is_err());
+        assert_eq!(
+            Image::from_bayer(&[0; 7], (2, 2), BayerPattern::Rggb, 12).err(),
+            Some(ImageError::BufferSize {
+                expected: 8,
+                actual: 7
+            })
+        );
+    }
+
+    #[test]
+    fn flat_gray_stays_gray() {
+        let raw = [128u8; 16];
+        let img = Image::from_bayer(&raw, (4, 4), BayerPattern::Grbg, 8).unwrap();
+        for p in img.pixels() {
+            assert_eq!(*p, [128. / 255., 128. / 255., 128. / 255., 1.]);
+        }
+    }
+}
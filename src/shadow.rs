@@ -0,0 +1,269 @@
+//! Drop shadows
+use alloc::{vec, vec::Vec};
+
+use crate::{transforms::premultiply, AlphaMode, Image, ImageError, ResXY, WorkPixel, XY};
+
+/// Box blur passes, three is close enough to a Gaussian
+const PASSES: u32 = 3;
+
+/// Box blur `line` with `radius`, treating everything outside as zero
+///
+/// Uses a prefix sum, so the cost doesn't depend on `radius`.
+fn box_line(line: &mut [f32], sums: &mut Vec<f64>, radius: usize) {
+    let n = line.len();
+    sums.clear();
+    sums.push(0.);
+    let mut acc = 0.;
+    for v in line.iter() {
+        acc += *v as f64;
+        sums.push(acc);
+    }
+    let k = (2 * radius + 1) as f64;
+    for (i, v) in line.iter_mut().enumerate() {
+        let (lo, hi) = (i.saturating_sub(radius), (i + radius + 1).min(n));
+        *v = ((sums[hi] - sums[lo]) / k) as f32;
+    }
+}
+
+/// [`PASSES`] box blurs of `data`, of `res`, in both directions
+fn box_blur(data: &mut [f32], (w, h): ResXY, radius: u32) {
+    let (w, h, r) = (w as usize, h as usize, radius as usize);
+    let mut sums = Vec::with_capacity(w.max(h) + 1);
+    let mut column = vec![0.; h];
+    for _ in 0..PASSES {
+        for row in data.chunks_exact_mut(w) {
+            box_line(row, &mut sums, r);
+        }
+        for x in 0..w {
+            for (y, c) in column.iter_mut().enumerate() {
+                *c = data[y * w + x];
+            }
+            box_line(&mut column, &mut sums, r);
+            for (y, c) in column.iter().enumerate() {
+                data[y * w + x] = *c;
+            }
+        }
+    }
+}
+
+impl Image {
+    /// This image on top of its own drop shadow
+    ///
+    /// The shadow is the alpha of the image, moved by `offset`, blurred, and
+    /// tinted with `color`, a straight alpha pixel in the image color space.
+    /// The blur is three box blurs of `blur_radius`, which looks close to
+    /// a Gaussian but is much faster.
+    ///
+    /// The result is grown to fit both the image and the shadow, which
+    /// spreads `3 * blur_radius` pixels past the offset image. Where the
+    /// image ends up in it is [`Image::drop_shadow_origin`]. An empty image
+    /// casts no shadow, and comes back as it is.
+    pub fn drop_shadow(
+        &self,
+        offset: (i32, i32),
+        blur_radius: u32,
+        color: impl Into<WorkPixel>,
+    ) -> Image {
+        let color = color.into();
+        let (shadow, _) = self.shadow_canvas(offset, blur_radius, color);
+        shadow
+    }
+
+    /// Where [`Image::drop_shadow`] puts the image itself
+    pub fn drop_shadow_origin(&self, offset: (i32, i32), blur_radius: u32) -> XY {
+        let spread = (blur_radius * PASSES) as i64;
+        let x = -(offset.0 as i64 - spread).min(0);
+        let y = -(offset.1 as i64 - spread).min(0);
+        (x as u32, y as u32)
+    }
+
+    /// Composite `src` and its drop shadow over this image, with the top
+    /// left corner of `src` at `at`, see [`Image::drop_shadow`]
+    ///
+    /// Parts of the shadow outside this image are clipped.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::ColorSpaceMismatch`] if the images have different
+    ///   color spaces
+    pub fn overlay_with_shadow(
+        &mut self,
+        src: &Image,
+        at: XY,
+        offset: (i32, i32),
+        blur_radius: u32,
+        color: impl Into<WorkPixel>,
+    ) -> Result<(), ImageError> {
+        let color = color.into();
+        if src.color != self.color {
+            return Err(ImageError::ColorSpaceMismatch);
+        }
+        let (mut shadow, (ox, oy)) = src.shadow_canvas(offset, blur_radius, color);
+        // Clip the part left of or above this image
+        let (x, y) = (at.0 as i64 - ox as i64, at.1 as i64 - oy as i64);
+        let (cx, cy) = ((-x).max(0) as u32, (-y).max(0) as u32);
+        if cx >= shadow.width() || cy >= shadow.height() {
+            return Ok(());
+        }
+        if cx > 0 || cy > 0 {
+            let size = (shadow.width() - cx, shadow.height() - cy);
+            shadow.crop((cx, cy), size)?;
+        }
+        self.overlay(&shadow, (x.max(0) as u32, y.max(0) as u32))
+    }
+
+    /// [`Image::drop_shadow`], and where the image is in it
+    fn shadow_canvas(&self, offset: (i32, i32), blur_radius: u32, color: WorkPixel) -> (Image, XY) {
+        let (w, h) = self.res;
+        if w == 0 || h == 0 {
+            return (self.clone(), (0, 0));
+        }
+        let spread = blur_radius * PASSES;
+        let (ox, oy) = self.drop_shadow_origin(offset, blur_radius);
+        // Top left of the shadow, before spreading
+        let sx = (ox as i64 + offset.0 as i64) as u32;
+        let sy = (oy as i64 + offset.1 as i64) as u32;
+        let res = ((ox + w).max(sx + w + spread), (oy + h).max(sy + h + spread));
+
+        let mut alpha = vec![0.; res.0 as usize * res.1 as usize];
+        for (y, row) in self.data.chunks_exact(w as usize).enumerate() {
+            let i = (sy as usize + y) * res.0 as usize + sx as usize;
+            for (a, p) in alpha[i..i + w as usize].iter_mut().zip(row) {
+                *a = p[3];
+            }
+        }
+        if blur_radius > 0 {
+            box_blur(&mut alpha, res, blur_radius);
+        }
+
+        let data = alpha
+            .into_iter()
+            .map(|a| {
+                let p = [color[0], color[1], color[2], color[3] * a.clamp(0., 1.)];
+                match self.alpha {
+                    AlphaMode::Straight => p,
+                    AlphaMode::Premultiplied => premultiply(p),
+                }
+            })
+            .collect();
+        let mut shadow = self.derive(data, res);
+        // Same color space, can't fail
+        let _ = shadow.overlay(self, (ox, oy));
+        (shadow, (ox, oy))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColorSpace;
+
+    /// An opaque square in the middle of `res`, with a half transparent
+    /// pixel in its corner
+    fn card(res: ResXY) -> Image {
+        let mut data = alloc::vec![0u8; res.0 as usize * res.1 as usize * 4];
+        for y in 2..res.1 - 2 {
+            for x in 2..res.0 - 2 {
+                let i = ((y * res.0 + x) * 4) as usize;
+                data[i..i + 4].copy_from_slice(&[200, 150, 100, 255]);
+            }
+        }
+        let i = ((2 * res.0 + 2) * 4) as usize;
+        data[i + 3] = 128;
+        Image::from_bytes(&data, res, ColorSpace::sRGB)
+    }
+
+    #[test]
+    fn zero_blur_and_offset() {
+        let img = card((10, 8));
+        let tint = [0., 0., 0.2, 0.6];
+        let out = img.drop_shadow((0, 0), 0, tint);
+        assert_eq!(out.res, img.res);
+        assert_eq!(img.drop_shadow_origin((0, 0), 0), (0, 0));
+        for (o, p) in out.pixels().iter().zip(img.pixels()) {
+            match p[3] {
+                0. => assert_eq!(*o, [0., 0., 0., 0.]),
+                1. => assert_eq!(o, p),
+                a => {
+                    // Over its own silhouette, tinted
+                    let want = a + tint[3] * a * (1. - a);
+                    assert!((o[3] - want).abs() < 1e-5, "{o:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn size_covers_offset_and_blur() {
+        let img = card((10, 8));
+        let out = img.drop_shadow((5, -3), 2, [0., 0., 0., 1.]);
+        // Spread of 6, so 1 on the left, 9 on top, and 6 on the right and
+        // bottom past the shadow at (6, 6)
+        assert_eq!(out.res, (22, 20));
+        assert_eq!(img.drop_shadow_origin((5, -3), 2), (1, 9));
+        let out = img.drop_shadow((0, 0), 0, [0., 0., 0., 1.]);
+        assert_eq!(out.res, (10, 8));
+        // The shadow sticks out 4 + 3 on the left, but its right side is
+        // inside the image
+        let out = img.drop_shadow((-4, 0), 1, [0., 0., 0., 1.]);
+        assert_eq!(out.res, (4 + 3 + 10, 3 + 8 + 3));
+    }
+
+    #[test]
+    fn alpha_never_past_tint() {
+        let img = card((12, 12));
+        let tint = [0.1, 0.1, 0.1, 0.4];
+        let out = img.drop_shadow((30, 20), 3, tint);
+        let (ox, oy) = img.drop_shadow_origin((30, 20), 3);
+        for (i, p) in out.pixels().iter().enumerate() {
+            let (x, y) = (i as u32 % out.width(), i as u32 / out.width());
+            let inside = (ox..ox + 12).contains(&x) && (oy..oy + 12).contains(&y);
+            if !inside {
+                assert!(p[3] <= tint[3] + 1e-6, "{p:?} at {x}, {y}");
+            }
+        }
+        assert!(out.pixels().iter().any(|p| p[3] > 0. && p[3] < tint[3]));
+    }
+
+    #[test]
+    fn empty() {
+        for res in [(0, 5), (5, 0)] {
+            let img = Image::from_bytes(&[], res, ColorSpace::sRGB);
+            assert_eq!(img.drop_shadow((3, 3), 2, [0., 0., 0., 1.]).res, res);
+            let mut bg = card((8, 8));
+            bg.overlay_with_shadow(&img, (1, 1), (2, 2), 1, [0., 0., 0., 1.])
+                .unwrap();
+            assert_eq!(bg.to_bytes(), card((8, 8)).to_bytes());
+        }
+    }
+
+    #[test]
+    fn overlay_matches_drop_shadow() {
+        let img = card((8, 6));
+        let tint = [0.1, 0.0, 0.2, 0.7];
+        let bg = |res: ResXY| {
+            let data = alloc::vec![90; res.0 as usize * res.1 as usize * 4];
+            Image::from_bytes(&data, res, ColorSpace::sRGB)
+        };
+        for (at, offset) in [((10, 7), (3, 2)), ((12, 9), (-4, -2)), ((1, 0), (-3, -3))] {
+            let mut got = bg((30, 20));
+            got.overlay_with_shadow(&img, at, offset, 2, tint).unwrap();
+
+            // On a background big enough for nothing to clip, then cut out
+            let mut want = bg((50, 40));
+            let (ox, oy) = img.drop_shadow_origin(offset, 2);
+            let shadow = img.drop_shadow(offset, 2, tint);
+            want.overlay(&shadow, (at.0 + 10 - ox, at.1 + 10 - oy))
+                .unwrap();
+            want.crop((10, 10), (30, 20)).unwrap();
+            assert_eq!(got.pixels(), want.pixels(), "{at:?} {offset:?}");
+        }
+
+        let mut linear = bg((30, 20));
+        linear.color = ColorSpace::sRGBLinear;
+        assert_eq!(
+            linear.overlay_with_shadow(&img, (0, 0), (1, 1), 1, tint),
+            Err(ImageError::ColorSpaceMismatch)
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Comparing frames, for telling a new scene from more of the same
+use alloc::vec::Vec;
+
+use crate::{luma::pixel_luma, scale::scale_buffer, Image, ImageError, ScaleFilter, F32};
+
+/// Suggested [`Image::similarity_score`] above which two frames are the
+/// same scene
+///
+/// Small brightness changes and noise stay above this, cuts to a different
+/// photo, or a corrupted frame, usually fall well below.
+pub const SIMILARITY_THRESHOLD: f32 = 0.8;
+
+const BINS: usize = 32;
+
+/// Size of the thumbnails compared for layout
+const THUMB: u32 = 8;
+
+/// How far apart the thumbnails, on average, count as nothing in common
+const THUMB_SCALE: f32 = 0.25;
+
+impl Image {
+    /// Encoded luma of every pixel, ignoring alpha
+    pub(crate) fn lumas(&self) -> Vec<f32> {
+        let transfer = self.color.transfer();
+        self.data
+            .iter()
+            .map(|p| pixel_luma(*p, self.alpha, transfer))
+            .collect()
+    }
+
+    /// How alike this and `other` look, from 0 for nothing in common to 1
+    /// for identical
+    ///
+    /// This averages the intersection of the luma histograms and the mean
+    /// difference of 8x8 luma thumbnails, so it looks at both tones and
+    /// layout. Both are taken around the mean luma, so brightening or
+    /// darkening a frame a little barely changes the score. Images of
+    /// different sizes are compared as is, the histograms are normalized
+    /// and the thumbnails are the same size. See [`SIMILARITY_THRESHOLD`].
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if either image is empty
+    pub fn similarity_score(&self, other: &Image) -> Result<f32, ImageError> {
+        if self.data.is_empty() || other.data.is_empty() {
+            return Err(ImageError::InvalidArgument);
+        }
+        let (a, b) = (self.lumas(), other.lumas());
+        let mean = |v: &[f32]| v.iter().sum::<f32>() / v.len() as f32;
+        let (ma, mb) = (mean(&a), mean(&b));
+
+        let hist = |v: &[f32], m: f32| {
+            let mut bins = [0u64; BINS];
+            for l in v {
+                let centered = (l - m + 0.5).clamp(0., 1.);
+                bins[((centered * BINS as f32) as usize).min(BINS - 1)] += 1;
+            }
+            bins
+        };
+        let (ha, hb) = (hist(&a, ma), hist(&b, mb));
+        let (na, nb) = (a.len() as u64, b.len() as u64);
+        // Cross multiplied, so identical histograms intersect to exactly 1
+        let common: u64 = ha.iter().zip(&hb).map(|(x, y)| (x * nb).min(y * na)).sum();
+        let hist_score = (common as f64 / (na * nb) as f64) as f32;
+
+        let thumb = |mut v: Vec<f32>, img: &Image, m: f32| {
+            scale_buffer(&mut v, img.res, (THUMB, THUMB), ScaleFilter::Box);
+            v.iter_mut().for_each(|l| *l -= m);
+            v
+        };
+        let (ta, tb) = (thumb(a, self, ma), thumb(b, other, mb));
+        let diff = ta.iter().zip(&tb).map(|(x, y)| (x - y).abs()).sum::<f32>() / ta.len() as f32;
+        let thumb_score = (1. - diff / THUMB_SCALE).max(0.);
+
+        Ok(((hist_score + thumb_score) / 2.).clamp(0., 1.))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{photo, solid};
+
+    /// Something else entirely, alternating dark and bright bars
+    fn other(res: crate::ResXY) -> Image {
+        let mut img = solid(res, [0.; 4]);
+        img.map_pixels_indexed(|(x, _), _| {
+            let c = if (x * 4 / res.0).is_multiple_of(2) {
+                0.05
+            } else {
+                0.95
+            };
+            [c, c, c, 1.]
+        });
+        img
+    }
+
+    #[test]
+    fn identical_is_one() {
+        let img = photo((32, 24));
+        assert_eq!(img.similarity_score(&img.clone()), Ok(1.));
+    }
+
+    #[test]
+    fn brightness_shift_is_similar() {
+        let img = photo((32, 24));
+        for shift in [-0.05, 0.05] {
+            let mut brighter = img.clone();
+            brighter.map_pixels(|p| [p[0] + shift, p[1] + shift, p[2] + shift, p[3]]);
+            let score = img.similarity_score(&brighter).unwrap();
+            assert!(score > 0.9, "{score}");
+        }
+    }
+
+    #[test]
+    fn unrelated_is_different() {
+        let score = photo((32, 24)).similarity_score(&other((32, 24))).unwrap();
+        assert!(score < SIMILARITY_THRESHOLD - 0.2, "{score}");
+    }
+
+    #[test]
+    fn different_sizes() {
+        let small = photo((32, 24));
+        let score = small.similarity_score(&photo((80, 60))).unwrap();
+        assert!(score > SIMILARITY_THRESHOLD, "{score}");
+        let score = small.similarity_score(&other((80, 60))).unwrap();
+        assert!(score < SIMILARITY_THRESHOLD - 0.2, "{score}");
+    }
+
+    #[test]
+    fn empty() {
+        let img = photo((4, 4));
+        let empty = solid((0, 4), [0.; 4]);
+        assert_eq!(
+            img.similarity_score(&empty),
+            Err(ImageError::InvalidArgument)
+        );
+        assert_eq!(
+            empty.similarity_score(&img),
+            Err(ImageError::InvalidArgument)
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Watermarks
+use alloc::borrow::Cow;
+
+use crate::{
+    composite::{from_linear_premul, over, to_linear_premul},
+    Image, ResXY, F32,
+};
+
+/// Largest fraction of the image's width or height a mark may cover, bigger
+/// ones are scaled down to fit
+const MAX_FRACTION: f32 = 0.5;
+
+/// Where [`Image::stamp`] puts the mark
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StampPlacement {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+
+    /// In the middle, ignoring the margin
+    Center,
+
+    /// Repeated across the whole image, `spacing` pixels apart, on a grid
+    /// turned by `angle_degrees` clockwise and centered on the image
+    ///
+    /// The marks themselves stay upright.
+    Tile {
+        spacing: ResXY,
+        angle_degrees: f32,
+    },
+}
+
+impl Image {
+    /// Composite `mark` over the image at `placement`, with `opacity`, in
+    /// linear light
+    ///
+    /// Corners are `margin` pixels in from both edges. Marks bigger than
+    /// half the image's width or height are scaled down, keeping their
+    /// aspect ratio, to fit in half, and are converted to the image's color
+    /// space if needed. An `opacity` of zero does nothing.
+    pub fn stamp(&mut self, mark: &Image, placement: StampPlacement, opacity: f32, margin: u32) {
+        let opacity = opacity.clamp(0., 1.);
+        if opacity <= 0. || mark.data.is_empty() || self.data.is_empty() {
+            return;
+        }
+        let mut mark = Cow::Borrowed(mark);
+        if mark.color != self.color {
+            mark.to_mut().to_color(self.color);
+        }
+        let (w, h) = (self.width() as f32, self.height() as f32);
+        let (mw, mh) = (mark.width() as f32, mark.height() as f32);
+        let fit = (w * MAX_FRACTION / mw).min(h * MAX_FRACTION / mh);
+        if fit < 1. {
+            let size = |s: f32| ((s * fit).round() as u32).max(1);
+            mark = Cow::Owned(mark.resize((size(mw), size(mh))));
+        }
+
+        let (w, h) = (self.width() as i64, self.height() as i64);
+        let (mw, mh) = (mark.width() as i64, mark.height() as i64);
+        let m = margin as i64;
+        match placement {
+            StampPlacement::TopLeft => self.stamp_at(&mark, (m, m), opacity),
+            StampPlacement::TopRight => self.stamp_at(&mark, (w - mw - m, m), opacity),
+            StampPlacement::BottomLeft => self.stamp_at(&mark, (m, h - mh - m), opacity),
+            StampPlacement::BottomRight => self.stamp_at(&mark, (w - mw - m, h - mh - m), opacity),
+            StampPlacement::Center => self.stamp_at(&mark, ((w - mw) / 2, (h - mh) / 2), opacity),
+            StampPlacement::Tile {
+                spacing,
+                angle_degrees,
+            } => {
+                let (sin, cos) = (
+                    angle_degrees.to_radians().sin(),
+                    angle_degrees.to_radians().cos(),
+                );
+                let (du, dv) = (
+                    (mw + spacing.0 as i64) as f32,
+                    (mh + spacing.1 as i64) as f32,
+                );
+                // Enough cells to cover the image at any angle
+                let diagonal = ((w * w + h * h) as f32).sqrt();
+                let n = (diagonal / du.min(dv)).ceil() as i64 + 1;
+                let (cx, cy) = ((w - mw) as f32 / 2., (h - mh) as f32 / 2.);
+                for j in -n..=n {
+                    for i in -n..=n {
+                        let (u, v) = (i as f32 * du, j as f32 * dv);
+                        let x = (cx + u * cos - v * sin).round() as i64;
+                        let y = (cy + u * sin + v * cos).round() as i64;
+                        if x < w && y < h && x + mw > 0 && y + mh > 0 {
+                            self.stamp_at(&mark, (x, y), opacity);
+                        }
+                    }
+                }
+            }
+        }
+        self.check();
+    }
+
+    /// Composite `mark`, in the same color space, with its top left at
+    /// `at`, clipped to the image
+    fn stamp_at(&mut self, mark: &Image, (x0, y0): (i64, i64), opacity: f32) {
+        let transfer = self.color.transfer();
+        let (decode, encode) = (transfer.map(|t| t.0), transfer.map(|t| t.1));
+        let (w, h) = (self.width() as i64, self.height() as i64);
+        for my in 0..mark.height() as i64 {
+            let y = y0 + my;
+            if !(0..h).contains(&y) {
+                continue;
+            }
+            for mx in 0..mark.width() as i64 {
+                let x = x0 + mx;
+                if !(0..w).contains(&x) {
+                    continue;
+                }
+                let s = mark.data[(my * mark.width() as i64 + mx) as usize];
+                let i = (y * w + x) as usize;
+                let s = to_linear_premul(s, decode, mark.alpha).map(|c| c * opacity);
+                let d = to_linear_premul(self.data[i], decode, self.alpha);
+                self.data[i] = from_linear_premul(over(s, d), encode, self.alpha);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::solid, XY};
+
+    const BLACK: crate::WorkPixel = [0., 0., 0., 1.];
+    const WHITE: crate::WorkPixel = [1., 1., 1., 1.];
+
+    /// Where `img` isn't black
+    fn marked(img: &Image) -> alloc::vec::Vec<XY> {
+        let w = img.width();
+        (0..img.data.len() as u32)
+            .map(|i| (i % w, i / w))
+            .filter(|&xy| img.get_pixel(xy).unwrap()[0] > 0.5)
+            .collect()
+    }
+
+    /// Every pixel in `w` by `h` at `(x, y)`
+    fn rect(x: u32, y: u32, w: u32, h: u32) -> alloc::vec::Vec<XY> {
+        (y..y + h)
+            .flat_map(|y| (x..x + w).map(move |x| (x, y)))
+            .collect()
+    }
+
+    #[test]
+    fn corners_respect_margin() {
+        let mark = solid((4, 3), WHITE);
+        for (placement, at) in [
+            (StampPlacement::TopLeft, (2, 2)),
+            (StampPlacement::TopRight, (34, 2)),
+            (StampPlacement::BottomLeft, (2, 25)),
+            (StampPlacement::BottomRight, (34, 25)),
+            (StampPlacement::Center, (18, 13)),
+        ] {
+            let mut img = solid((40, 30), BLACK);
+            img.stamp(&mark, placement, 1., 2);
+            assert_eq!(marked(&img), rect(at.0, at.1, 4, 3), "{placement:?}");
+        }
+    }
+
+    #[test]
+    fn opacity() {
+        let mark = solid((4, 3), WHITE);
+        for opacity in [0., -1.] {
+            let mut img = solid((10, 10), BLACK);
+            img.stamp(&mark, StampPlacement::Center, opacity, 0);
+            assert_eq!(img.pixels(), solid((10, 10), BLACK).pixels());
+        }
+        // Half of white over black in linear light
+        let mut img = solid((10, 10), BLACK);
+        img.stamp(&mark, StampPlacement::TopLeft, 0.5, 0);
+        img.to_color(crate::ColorSpace::sRGBLinear);
+        let p = img.get_pixel((1, 1)).unwrap();
+        assert!((p[0] - 0.5).abs() < 1e-5 && p[3] == 1., "{p:?}");
+    }
+
+    #[test]
+    fn tiles_cover_with_spacing() {
+        let mark = solid((2, 2), WHITE);
+        let mut img = solid((22, 18), BLACK);
+        let tile = StampPlacement::Tile {
+            spacing: (3, 1),
+            angle_degrees: 0.,
+        };
+        img.stamp(&mark, tile, 1., 0);
+        // A mark every 5 across and 3 down, one at (10, 8) in the middle
+        let want: alloc::vec::Vec<XY> = rect(0, 0, 22, 18)
+            .into_iter()
+            .filter(|&(x, y)| x % 5 < 2 && (y + 1) % 3 < 2)
+            .collect();
+        assert_eq!(marked(&img), want);
+
+        // Turned, marks still reach everywhere
+        let mut img = solid((40, 40), BLACK);
+        let tile = StampPlacement::Tile {
+            spacing: (4, 4),
+            angle_degrees: 30.,
+        };
+        img.stamp(&mark, tile, 1., 0);
+        let got = marked(&img);
+        for cell in rect(0, 0, 4, 4) {
+            assert!(
+                got.iter()
+                    .any(|&(x, y)| x / 10 == cell.0 && y / 10 == cell.1),
+                "{cell:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn oversized_marks_are_scaled() {
+        let mark = solid((20, 20), WHITE);
+        let mut img = solid((16, 12), BLACK);
+        img.stamp(&mark, StampPlacement::TopLeft, 1., 0);
+        // Half the height, keeping the aspect ratio
+        assert_eq!(marked(&img), rect(0, 0, 6, 6));
+    }
+}
@@ -0,0 +1,377 @@
+//! Chroma subsampled storage
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    alpha_converter,
+    color::chromaticity::{rgb_xyz_matrix, WhitePoint, SRGB_PRIMARIES},
+    convert::primaries,
+    layout::{prepare, quantize},
+    resample::{Contrib, FilterWeights},
+    scale::{corner_ratio, scale_buffer, separable},
+    AlphaMode, ColorSpace, Image, PixelFormat, ResXY, ScaleFilter, WorkPixel,
+};
+
+/// Y'CbCr luma coefficients `(Kr, Kg, Kb)` for `color`
+///
+/// These are the luminances of its primaries, so BT.709's for sRGB.
+/// [`ColorSpace::AsIs`] uses sRGB's.
+fn coefficients(color: ColorSpace) -> [f32; 3] {
+    let m = rgb_xyz_matrix(primaries(color).unwrap_or(SRGB_PRIMARIES), WhitePoint::D65);
+    [m[(1, 0)], m[(1, 1)], m[(1, 2)]]
+}
+
+/// An [`Image`] stored as full resolution luma and half resolution chroma,
+/// for photos
+///
+/// Luma is Y', chroma is Cb and Cr, all `f32`, computed from the stored
+/// values with the coefficients of the image's primaries, so BT.709 for
+/// sRGB. Straight alpha is kept at full resolution, only if the image isn't
+/// opaque. That's 6 bytes a pixel opaque, and 10 with alpha, instead of 16.
+///
+/// Each chroma sample is the average of a 2x2 block, and is interpolated
+/// bilinearly from the block centers to get pixels back. Exports work a row
+/// at a time, never expanding the whole image.
+#[derive(Debug, Clone)]
+pub struct SubsampledImage {
+    luma: Vec<f32>,
+    cb: Vec<f32>,
+    cr: Vec<f32>,
+    alpha: Option<Vec<f32>>,
+    res: ResXY,
+    color: ColorSpace,
+    alpha_mode: AlphaMode,
+}
+
+/// Size of the chroma planes for `res`
+fn chroma_res((w, h): ResXY) -> ResXY {
+    (w.div_ceil(2), h.div_ceil(2))
+}
+
+/// Bilinear weights from the chroma of `old` pixels to the chroma of `new`,
+/// moving chroma samples the same way [`ScaleFilter::Bilinear`] moves luma
+///
+/// Scaling the chroma plane on its own maps its corners to corners, which
+/// is off by up to a pixel from where the luma goes.
+fn chroma_bilinear(old: u32, new: u32) -> FilterWeights {
+    let (from, to) = (old.div_ceil(2), new.div_ceil(2));
+    if from == 0 {
+        return FilterWeights::compute(0, to, ScaleFilter::Bilinear);
+    }
+    let ratio = corner_ratio(old, new);
+    let contribs = (0..to)
+        .map(|j| {
+            // Center of the new sample in new pixels, then old chroma
+            let luma = (2. * j as f32 + 0.5).min((new - 1) as f32) * ratio;
+            let c = ((luma - 0.5) / 2.).clamp(0., (from - 1) as f32);
+            let start = c as u32;
+            let t = c - start as f32;
+            let weights = if start + 1 < from && t > 0. {
+                vec![1. - t, t]
+            } else {
+                vec![1.]
+            };
+            Contrib { start, weights }
+        })
+        .collect();
+    FilterWeights { contribs }
+}
+
+impl Image {
+    /// Store this image with half resolution chroma, see [`SubsampledImage`]
+    pub fn to_subsampled(&self) -> SubsampledImage {
+        let (w, h) = (self.res.0 as usize, self.res.1 as usize);
+        let [kr, kg, kb] = coefficients(self.color);
+        let straight = alpha_converter(self.alpha, AlphaMode::Straight);
+        let chroma = |[r, _, b, _]: WorkPixel, y: f32| {
+            [(b - y) / (2. * (1. - kb)), (r - y) / (2. * (1. - kr))]
+        };
+        let mut opaque = true;
+        let luma: Vec<f32> = self
+            .data
+            .iter()
+            .map(|p| {
+                let [r, g, b, a] = straight(*p);
+                opaque &= a == 1.;
+                kr * r + kg * g + kb * b
+            })
+            .collect();
+
+        let (cw, ch) = chroma_res(self.res);
+        let n = cw as usize * ch as usize;
+        let (mut cb, mut cr) = (Vec::with_capacity(n), Vec::with_capacity(n));
+        for by in (0..h).step_by(2) {
+            for bx in (0..w).step_by(2) {
+                let (mut sum, mut n) = ([0.; 2], 0.);
+                for y in by..(by + 2).min(h) {
+                    for x in bx..(bx + 2).min(w) {
+                        let i = y * w + x;
+                        let c = chroma(straight(self.data[i]), luma[i]);
+                        sum = [sum[0] + c[0], sum[1] + c[1]];
+                        n += 1.;
+                    }
+                }
+                cb.push(sum[0] / n);
+                cr.push(sum[1] / n);
+            }
+        }
+        let alpha = (!opaque).then(|| self.data.iter().map(|p| straight(*p)[3]).collect());
+        SubsampledImage {
+            luma,
+            cb,
+            cr,
+            alpha,
+            res: self.res,
+            color: self.color,
+            alpha_mode: self.alpha,
+        }
+    }
+}
+
+impl SubsampledImage {
+    pub fn width(&self) -> u32 {
+        self.res.0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.res.1
+    }
+
+    pub fn color(&self) -> ColorSpace {
+        self.color
+    }
+
+    /// Heap memory used by the planes, in bytes
+    pub fn byte_size(&self) -> usize {
+        let alpha = self.alpha.as_ref().map_or(0, Vec::capacity);
+        (self.luma.capacity() + self.cb.capacity() + self.cr.capacity() + alpha) * size_of::<f32>()
+    }
+
+    /// Straight alpha pixels of row `y` into `out`
+    fn row(&self, y: u32, out: &mut [WorkPixel]) {
+        let [kr, kg, kb] = coefficients(self.color);
+        let (cw, ch) = chroma_res(self.res);
+        // Chroma sample `i` is centered on pixel `2 * i + 0.5`
+        let axis = |v: u32, len: u32| {
+            let c = ((v as f32 - 0.5) / 2.).clamp(0., (len - 1) as f32);
+            let i = c as u32;
+            (i, (i + 1).min(len - 1), c - i as f32)
+        };
+        let (y0, y1, fy) = axis(y, ch);
+        let at = |plane: &[f32], x: u32, y: u32| plane[(y * cw + x) as usize];
+        let start = (y * self.res.0) as usize;
+        for (x, o) in (0..self.res.0).zip(out) {
+            let (x0, x1, fx) = axis(x, cw);
+            let sample = |plane: &[f32]| {
+                let top = at(plane, x0, y0) + (at(plane, x1, y0) - at(plane, x0, y0)) * fx;
+                let bottom = at(plane, x0, y1) + (at(plane, x1, y1) - at(plane, x0, y1)) * fx;
+                top + (bottom - top) * fy
+            };
+            let (luma, cb, cr) = (
+                self.luma[start + x as usize],
+                sample(&self.cb),
+                sample(&self.cr),
+            );
+            let r = luma + 2. * (1. - kr) * cr;
+            let b = luma + 2. * (1. - kb) * cb;
+            let g = (luma - kr * r - kb * b) / kg;
+            let a = self.alpha.as_ref().map_or(1., |a| a[start + x as usize]);
+            *o = [r, g, b, a];
+        }
+    }
+
+    /// Call `f` with each row of straight alpha pixels, in order
+    fn rows(&self, mut f: impl FnMut(&[WorkPixel])) {
+        let mut row = vec![WorkPixel::default(); self.res.0 as usize];
+        for y in 0..self.res.1 {
+            self.row(y, &mut row);
+            f(&row);
+        }
+    }
+
+    /// Expand back into an [`Image`], in the alpha mode it was made from
+    pub fn to_image(&self) -> Image {
+        let mut data = Vec::with_capacity(self.luma.len());
+        self.rows(|row| data.extend_from_slice(row));
+        let mut img = Image::from_parts(data, self.res, self.color);
+        img.to_alpha_mode(self.alpha_mode);
+        img
+    }
+
+    /// Export as RGBA 8888 with straight alpha, like [`Image::to_bytes`]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.luma.len() * 4);
+        self.rows(|row| {
+            out.extend(row.iter().flat_map(|p| p.map(|c| quantize(c, 255.) as u8)));
+        });
+        out
+    }
+
+    /// Export in the pixel format `format` with straight alpha, like
+    /// [`Image::to_raw`]
+    pub fn to_raw(&self, format: PixelFormat) -> Vec<u8> {
+        let bpp = format.bytes_per_pixel();
+        let transfer = self.color.transfer();
+        let mut out = vec![0; self.luma.len() * bpp];
+        let mut rows = out.chunks_exact_mut((self.res.0 as usize * bpp).max(1));
+        self.rows(|row| {
+            let Some(dst) = rows.next() else { return };
+            for (p, o) in row.iter().zip(dst.chunks_exact_mut(bpp)) {
+                format.encode(prepare(*p, format, AlphaMode::Straight, transfer), o);
+            }
+        });
+        out
+    }
+
+    /// Scale to `new` using bilinear filtering, see
+    /// [`SubsampledImage::scale_with`]
+    ///
+    /// # Panics
+    ///
+    /// - If `new` is zero in either dimension
+    pub fn scale(&mut self, new: ResXY) {
+        self.scale_with(new, ScaleFilter::Bilinear)
+    }
+
+    /// Scale each plane to `new` using `filter`, like [`Image::scale_with`]
+    ///
+    /// Chroma is scaled to half of `new`, rounded up, without ever expanding
+    /// it, keeping it lined up with the luma.
+    ///
+    /// # Panics
+    ///
+    /// - If `new` is zero in either dimension
+    pub fn scale_with(&mut self, new: ResXY, filter: ScaleFilter) {
+        assert!(new.0 > 0 && new.1 > 0, "Cannot scale to zero");
+        let (from, to) = (chroma_res(self.res), chroma_res(new));
+        scale_buffer(&mut self.luma, self.res, new, filter);
+        if filter == ScaleFilter::Bilinear {
+            let hc = chroma_bilinear(self.res.0, new.0);
+            let vc = chroma_bilinear(self.res.1, new.1);
+            self.cb = separable(&self.cb, from, &hc, &vc);
+            self.cr = separable(&self.cr, from, &hc, &vc);
+        } else {
+            scale_buffer(&mut self.cb, from, to, filter);
+            scale_buffer(&mut self.cr, from, to, filter);
+        }
+        if let Some(alpha) = &mut self.alpha {
+            scale_buffer(alpha, self.res, new, filter);
+        }
+        self.res = new;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fixtures::{photo, psnr},
+        AlphaMode, ColorSpace, Image, PixelFormat, ScaleFilter,
+    };
+
+    /// `photo` with alpha fading in left to right
+    fn translucent(res: crate::ResXY) -> Image {
+        let mut img = photo(res);
+        let w = (res.0 - 1) as f32;
+        img.map_pixels_indexed(|(x, _), p| [p[0], p[1], p[2], 0.5 + x as f32 / w / 2.]);
+        img
+    }
+
+    fn max_code_diff(a: &[u8], b: &[u8]) -> u8 {
+        assert_eq!(a.len(), b.len());
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn footprint() {
+        let img = photo((64, 48));
+        let full = 64 * 48 * size_of::<crate::WorkPixel>();
+        let sub = img.to_subsampled();
+        assert_eq!(sub.byte_size(), 64 * 48 * 6);
+        assert!(sub.byte_size() * 2 <= full);
+        // Alpha takes its own full plane
+        assert_eq!(
+            translucent((64, 48)).to_subsampled().byte_size(),
+            64 * 48 * 10
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for img in [photo((64, 48)), translucent((63, 47))] {
+            let back = img.to_subsampled().to_image();
+            assert_eq!(back.res, img.res);
+            assert_eq!(back.color, img.color);
+            let db = psnr(&img, &back);
+            assert!(db > 40., "{db}");
+        }
+        // In the alpha mode it came from
+        let mut img = translucent((16, 8));
+        img.to_alpha_mode(AlphaMode::Premultiplied);
+        let back = img.to_subsampled().to_image();
+        assert_eq!(back.alpha, AlphaMode::Premultiplied);
+        assert!(psnr(&img, &back) > 40.);
+    }
+
+    #[test]
+    fn direct_export_matches() {
+        for img in [photo((33, 21)), translucent((20, 12))] {
+            let sub = img.to_subsampled();
+            let back = sub.to_image();
+            assert!(max_code_diff(&sub.to_bytes(), &back.to_bytes()) <= 1);
+            for format in [
+                PixelFormat::Rgb565Le,
+                PixelFormat::Bgra8888,
+                PixelFormat::Gray8,
+            ] {
+                let (direct, expanded) = (sub.to_raw(format), back.to_raw(format));
+                if format == PixelFormat::Rgb565Le {
+                    let channels = |b: &[u8]| -> alloc::vec::Vec<u8> {
+                        b.chunks_exact(2)
+                            .flat_map(|b| {
+                                let v = u16::from_le_bytes([b[0], b[1]]);
+                                [(v >> 11) as u8, (v >> 5 & 0x3f) as u8, (v & 0x1f) as u8]
+                            })
+                            .collect()
+                    };
+                    assert!(max_code_diff(&channels(&direct), &channels(&expanded)) <= 1);
+                } else {
+                    assert!(max_code_diff(&direct, &expanded) <= 1, "{format:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scale_matches_expanded() {
+        let img = photo((64, 48));
+        for (new, filter) in [
+            ((32, 24), ScaleFilter::Bilinear),
+            ((100, 70), ScaleFilter::Bilinear),
+            ((21, 15), ScaleFilter::Box),
+        ] {
+            let mut sub = img.to_subsampled();
+            sub.scale_with(new, filter);
+            assert_eq!((sub.width(), sub.height()), new);
+            let mut want = img.clone();
+            want.scale_with(new, filter);
+            let db = psnr(&sub.to_image(), &want);
+            assert!(db > 35., "{new:?} {filter:?}: {db}");
+        }
+    }
+
+    #[test]
+    fn scale_empty() {
+        for filter in [
+            ScaleFilter::Nearest,
+            ScaleFilter::Bilinear,
+            ScaleFilter::Box,
+        ] {
+            let mut sub = Image::from_bytes(&[], (0, 5), ColorSpace::sRGB).to_subsampled();
+            sub.scale_with((4, 4), filter);
+            assert_eq!(sub.to_image().res, (4, 4));
+        }
+    }
+}
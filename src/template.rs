@@ -0,0 +1,320 @@
+//! Locating a sub-image, see [`Image::find_template`]
+use alloc::{vec, vec::Vec};
+
+use crate::{alpha_converter, AlphaMode, Image, ImageError, XY};
+
+/// What [`Image::find_template_with`] correlates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TemplateChannels {
+    /// Encoded luma, for matches regardless of tint
+    #[default]
+    Luma,
+    /// Encoded red, green, and blue, correlated together
+    Color,
+}
+
+/// Straight alpha encoded planes of `img` to correlate
+fn planes(img: &Image, channels: TemplateChannels) -> Vec<Vec<f32>> {
+    match channels {
+        TemplateChannels::Luma => vec![img.lumas()],
+        TemplateChannels::Color => {
+            let straight = alpha_converter(img.alpha, AlphaMode::Straight);
+            (0..3)
+                .map(|c| img.data.iter().map(|p| straight(*p)[c]).collect())
+                .collect()
+        }
+    }
+}
+
+/// Summed area tables of `plane` and its square, `w + 1` wide
+fn integrals(plane: &[f32], w: usize) -> (Vec<f64>, Vec<f64>) {
+    let h = plane.len() / w.max(1);
+    let stride = w + 1;
+    let (mut sum, mut sq) = (vec![0.; stride * (h + 1)], vec![0.; stride * (h + 1)]);
+    for y in 0..h {
+        let (mut row, mut row_sq) = (0f64, 0f64);
+        for x in 0..w {
+            let v = plane[y * w + x] as f64;
+            row += v;
+            row_sq += v * v;
+            let i = (y + 1) * stride + x + 1;
+            sum[i] = sum[i - stride] + row;
+            sq[i] = sq[i - stride] + row_sq;
+        }
+    }
+    (sum, sq)
+}
+
+impl Image {
+    /// Where `template` appears in this image, by normalized cross
+    /// correlation of luma
+    ///
+    /// See [`Image::find_template_with`].
+    ///
+    /// # Errors
+    ///
+    /// - The errors of [`Image::find_template_with`]
+    pub fn find_template(
+        &self,
+        template: &Image,
+        threshold: f32,
+    ) -> Result<Vec<(XY, f32)>, ImageError> {
+        self.find_template_with(template, threshold, TemplateChannels::Luma)
+    }
+
+    /// Where `template` appears in this image, by normalized cross
+    /// correlation of `channels`, as the top left corner and score of each
+    /// match, best first
+    ///
+    /// Scores go from -1 to 1 for a perfect match, and only those above
+    /// `threshold` are kept. Correlation is around each window's mean and
+    /// scaled by its contrast, so brightened or darkened copies still match.
+    /// A flat window matches a flat template with a score of 1, and anything
+    /// else with 0. Overlapping matches collapse into the best one. Alpha is
+    /// ignored.
+    ///
+    /// When `template` appears exactly, as 8 bit RGBA, only those exact
+    /// matches are returned, with a score of 1, without correlating at all.
+    /// That's the common case of finding an icon in a screenshot, so it's
+    /// much faster.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `template` is empty
+    /// - [`ImageError::DimensionMismatch`] if `template` is larger than this
+    ///   image
+    /// - [`ImageError::ColorSpaceMismatch`] if the color spaces differ
+    pub fn find_template_with(
+        &self,
+        template: &Image,
+        threshold: f32,
+        channels: TemplateChannels,
+    ) -> Result<Vec<(XY, f32)>, ImageError> {
+        let (tw, th) = template.res;
+        let (w, h) = self.res;
+        if tw == 0 || th == 0 {
+            return Err(ImageError::InvalidArgument);
+        }
+        if tw > w || th > h {
+            return Err(ImageError::DimensionMismatch);
+        }
+        if template.color != self.color {
+            return Err(ImageError::ColorSpaceMismatch);
+        }
+        let nx = w - tw + 1;
+
+        let exact = self.find_exact(template);
+        let candidates = if !exact.is_empty() {
+            exact.into_iter().map(|xy| (xy, 1.)).collect()
+        } else {
+            self.correlate(template, channels)
+                .into_iter()
+                .enumerate()
+                .filter(|(_, s)| *s > threshold)
+                .map(|(i, s)| ((i as u32 % nx, i as u32 / nx), s))
+                .collect()
+        };
+        Ok(suppress(candidates, (tw, th)))
+    }
+
+    /// Every position `template` appears at exactly, as 8 bit RGBA
+    fn find_exact(&self, template: &Image) -> Vec<XY> {
+        let (w, tw) = (self.res.0 as usize * 4, template.res.0 as usize * 4);
+        let (hay, needle) = (self.to_bytes(), template.to_bytes());
+        let mut found = Vec::new();
+        for y in 0..=self.res.1 - template.res.1 {
+            for x in 0..=self.res.0 - template.res.0 {
+                let at = |ty: usize| {
+                    let start = (y as usize + ty) * w + x as usize * 4;
+                    &hay[start..start + tw]
+                };
+                if needle
+                    .chunks_exact(tw)
+                    .enumerate()
+                    .all(|(ty, row)| at(ty) == row)
+                {
+                    found.push((x, y));
+                }
+            }
+        }
+        found
+    }
+
+    /// Correlation score of `template` at every position, in row order
+    fn correlate(&self, template: &Image, channels: TemplateChannels) -> Vec<f32> {
+        let (w, h) = (self.res.0 as usize, self.res.1 as usize);
+        let (tw, th) = (template.res.0 as usize, template.res.1 as usize);
+        let (nx, ny) = (w - tw + 1, h - th + 1);
+        let n = (tw * th) as f64;
+        let mut cross = vec![0f64; nx * ny];
+        let mut var = vec![0f64; nx * ny];
+        let mut template_var = 0f64;
+
+        for (plane, tplane) in planes(self, channels)
+            .iter()
+            .zip(planes(template, channels))
+        {
+            // Zero mean template, so its products with a window are already
+            // around the window's mean
+            let mean = tplane.iter().map(|v| *v as f64).sum::<f64>() / n;
+            let t: Vec<f64> = tplane.iter().map(|v| *v as f64 - mean).collect();
+            template_var += t.iter().map(|v| v * v).sum::<f64>();
+
+            let (sum, sq) = integrals(plane, w);
+            let stride = w + 1;
+            let rect = |table: &[f64], x: usize, y: usize| {
+                table[(y + th) * stride + x + tw]
+                    - table[y * stride + x + tw]
+                    - table[(y + th) * stride + x]
+                    + table[y * stride + x]
+            };
+            for y in 0..ny {
+                for x in 0..nx {
+                    let i = y * nx + x;
+                    let s = rect(&sum, x, y);
+                    var[i] += rect(&sq, x, y) - s * s / n;
+                    let mut c = 0f64;
+                    for (ty, trow) in t.chunks_exact(tw).enumerate() {
+                        let row = &plane[(y + ty) * w + x..][..tw];
+                        c += row
+                            .iter()
+                            .zip(trow)
+                            .map(|(a, b)| *a as f64 * b)
+                            .sum::<f64>();
+                    }
+                    cross[i] += c;
+                }
+            }
+        }
+
+        // Below this a window or template counts as flat
+        let flat = 1e-9 * n;
+        cross
+            .iter()
+            .zip(&var)
+            .map(|(c, v)| match (*v <= flat, template_var <= flat) {
+                (true, true) => 1.,
+                (true, false) | (false, true) => 0.,
+                _ => (c / libm::sqrt(v * template_var)).clamp(-1., 1.) as f32,
+            })
+            .collect()
+    }
+}
+
+/// The best of `candidates` that don't overlap a better one, for a template
+/// of `size`
+fn suppress(mut candidates: Vec<(XY, f32)>, (tw, th): XY) -> Vec<(XY, f32)> {
+    // Stable, so ties go in row order
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let mut kept: Vec<(XY, f32)> = Vec::new();
+    for (xy, s) in candidates {
+        let overlaps = kept
+            .iter()
+            .any(|((x, y), _)| x.abs_diff(xy.0) < tw && y.abs_diff(xy.1) < th);
+        if !overlaps {
+            kept.push((xy, s));
+        }
+    }
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{noise, photo, solid},
+        ColorSpace,
+    };
+
+    fn cropped(img: &Image, origin: XY, size: XY) -> Image {
+        let mut img = img.clone();
+        img.crop(origin, size).unwrap();
+        img
+    }
+
+    #[test]
+    fn exact_crop_is_found() {
+        let img = photo((40, 30));
+        let template = cropped(&img, (17, 9), (8, 6));
+        let found = img.find_template(&template, 0.5).unwrap();
+        assert_eq!(found, [((17, 9), 1.)]);
+        // Correlating finds it too
+        let scores = img.correlate(&template, TemplateChannels::Luma);
+        assert!((scores[9 * 33 + 17] - 1.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn brightness_shift_is_found() {
+        let img = photo((40, 30));
+        let mut template = cropped(&img, (5, 20), (9, 7));
+        template.map_pixels(|p| [p[0] * 0.7 + 0.2, p[1] * 0.7 + 0.2, p[2] * 0.7 + 0.2, p[3]]);
+        for channels in [TemplateChannels::Luma, TemplateChannels::Color] {
+            let found = img.find_template_with(&template, 0.9, channels).unwrap();
+            assert_eq!(found[0].0, (5, 20), "{channels:?}");
+            assert!(found[0].1 > 0.999, "{channels:?} {}", found[0].1);
+        }
+    }
+
+    #[test]
+    fn absent_is_not_found() {
+        let img = photo((40, 30));
+        let mut seed = 7;
+        let mut template = solid((8, 8), [0., 0., 0., 1.]);
+        template.map_pixels(|_| {
+            let v = noise(&mut seed);
+            [v, 1. - v, v, 1.]
+        });
+        assert_eq!(img.find_template(&template, 0.8).unwrap(), []);
+    }
+
+    #[test]
+    fn copies_and_overlaps() {
+        let icon = cropped(&photo((40, 30)), (10, 10), (5, 5));
+        let mut screen = solid((30, 12), [0.2, 0.2, 0.2, 1.]);
+        screen.overlay(&icon, (2, 3)).unwrap();
+        screen.overlay(&icon, (20, 6)).unwrap();
+        let found = screen.find_template(&icon, 0.5).unwrap();
+        assert_eq!(found, [((2, 3), 1.), ((20, 6), 1.)]);
+
+        let kept = suppress(
+            alloc::vec![((0, 0), 0.5), ((3, 2), 0.9), ((5, 0), 0.8), ((10, 10), 0.7)],
+            (5, 5),
+        );
+        assert_eq!(kept, [((3, 2), 0.9), ((10, 10), 0.7)]);
+    }
+
+    #[test]
+    fn flat_windows() {
+        let img = solid((6, 6), [0.5, 0.5, 0.5, 1.]);
+        let flat = solid((2, 2), [0.25, 0.25, 0.25, 1.]);
+        assert!(img
+            .correlate(&flat, TemplateChannels::Luma)
+            .iter()
+            .all(|s| *s == 1.));
+        let mut edge = flat.clone();
+        edge.map_pixels_indexed(|(x, _), p| if x == 0 { [1., 1., 1., 1.] } else { p });
+        assert!(img
+            .correlate(&edge, TemplateChannels::Luma)
+            .iter()
+            .all(|s| *s == 0.));
+    }
+
+    #[test]
+    fn errors() {
+        let img = photo((10, 10));
+        assert_eq!(
+            img.find_template(&photo((11, 2)), 0.5),
+            Err(ImageError::DimensionMismatch)
+        );
+        assert_eq!(
+            img.find_template(&photo((0, 0)), 0.5),
+            Err(ImageError::InvalidArgument)
+        );
+        let mut linear = photo((2, 2));
+        linear.to_color(ColorSpace::sRGBLinear);
+        assert_eq!(
+            img.find_template(&linear, 0.5),
+            Err(ImageError::ColorSpaceMismatch)
+        );
+    }
+}
@@ -0,0 +1,118 @@
+#!/usr/bin/env python3
+"""Writes the ICC profiles for the icc tests
+
+Nothing but the standard library. These carry the same colorants, white
+points and curves as the well known profiles, in the same tag types:
+
+- srgb.icc, like the HP/Microsoft "sRGB IEC61966-2.1" v2 profile, with a
+  1024 entry `curv` table
+- display-p3.icc, like Apple's v4 "Display P3", with a type 3 `para` curve
+- adobe-rgb.icc, like "Adobe RGB (1998)", with a single gamma `curv`
+
+Only the tags the parser reads are there, plus a description.
+"""
+import os
+import struct
+
+D50 = (0.9642, 1.0, 0.8249)
+
+
+def s15(v):
+    return struct.pack(">i", round(v * 65536))
+
+
+def xyz(v):
+    return b"XYZ " + bytes(4) + b"".join(s15(c) for c in v)
+
+
+def desc_v2(text):
+    t = text.encode() + b"\0"
+    return b"desc" + bytes(4) + struct.pack(">I", len(t)) + t + bytes(4 + 4 + 2 + 1 + 67)
+
+
+def desc_v4(text):
+    t = text.encode("utf-16-be")
+    return b"mluc" + bytes(4) + struct.pack(">II", 1, 12) + b"enUS" + struct.pack(">II", len(t), 28) + t
+
+
+def curv_table(n):
+    def f(x):
+        return x / 12.92 if x <= 0.04045 else ((x + 0.055) / 1.055) ** 2.4
+
+    table = [round(f(i / (n - 1)) * 65535) for i in range(n)]
+    return b"curv" + bytes(4) + struct.pack(">I", n) + struct.pack(f">{n}H", *table)
+
+
+def curv_gamma(g):
+    return b"curv" + bytes(4) + struct.pack(">IH", 1, round(g * 256)) + bytes(2)
+
+
+def para_srgb():
+    params = [2.4, 1 / 1.055, 0.055 / 1.055, 1 / 12.92, 0.04045]
+    return b"para" + bytes(4) + struct.pack(">HH", 3, 0) + b"".join(s15(p) for p in params)
+
+
+def profile(version, tags):
+    # Tags sharing data, like the three TRCs, share an offset
+    table, data, seen = [], b"", {}
+    start = 128 + 4 + 12 * len(tags)
+    for sig, body in tags:
+        if body not in seen:
+            data += bytes(-len(data) % 4)
+            seen[body] = start + len(data)
+            data += body
+        table.append(struct.pack(">4sII", sig, seen[body], len(body)))
+    size = start + len(data)
+    header = struct.pack(">I4sI4s4s4s", size, b"none", version, b"mntr", b"RGB ", b"XYZ ")
+    header += bytes(12) + b"acsp" + b"APPL" + bytes(4) + b"none" + bytes(4 + 8)
+    header += struct.pack(">I", 0) + b"".join(s15(c) for c in D50) + b"none"
+    header += bytes(128 - len(header))
+    return header + struct.pack(">I", len(tags)) + b"".join(table) + data
+
+
+def rgb(version, desc, white, primaries, trc):
+    r, g, b = primaries
+    return profile(
+        version,
+        [
+            (b"desc", desc),
+            (b"wtpt", xyz(white)),
+            (b"rXYZ", xyz(r)),
+            (b"gXYZ", xyz(g)),
+            (b"bXYZ", xyz(b)),
+            (b"rTRC", trc),
+            (b"gTRC", trc),
+            (b"bTRC", trc),
+        ],
+    )
+
+
+PROFILES = {
+    "srgb.icc": rgb(
+        0x02100000,
+        desc_v2("sRGB IEC61966-2.1"),
+        (0.9505, 1.0, 1.0891),
+        [(0.4361, 0.2225, 0.0139), (0.3851, 0.7169, 0.0971), (0.1431, 0.0606, 0.7141)],
+        curv_table(1024),
+    ),
+    "display-p3.icc": rgb(
+        0x04000000,
+        desc_v4("Display P3"),
+        (0.96419, 1.0, 0.82489),
+        [(0.51512, 0.24120, -0.00105), (0.29198, 0.69225, 0.04189), (0.15710, 0.06657, 0.78407)],
+        para_srgb(),
+    ),
+    "adobe-rgb.icc": rgb(
+        0x02100000,
+        desc_v2("Adobe RGB (1998)"),
+        (0.95045, 1.0, 1.08905),
+        [(0.60974, 0.31111, 0.01947), (0.20528, 0.62567, 0.06087), (0.14919, 0.06322, 0.74457)],
+        curv_gamma(563 / 256),
+    ),
+}
+
+if __name__ == "__main__":
+    here = os.path.dirname(os.path.abspath(__file__))
+    for name, data in PROFILES.items():
+        with open(os.path.join(here, name), "wb") as f:
+            f.write(data)
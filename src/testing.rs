@@ -0,0 +1,424 @@
+//! Golden image test helpers, with the `testing` feature
+//!
+//! Images are compared as straight alpha values, in `0..=1` for encoded
+//! data, without any color conversion.
+use alloc::borrow::Cow;
+use core::fmt;
+
+use crate::{
+    alpha_converter, formats::ppm, noise::Rng, AlphaMode, ColorSpace, Image, ImageError, ImageRef,
+    OutlineMode, Rotation, ScaleFilter, StaticImage, XY,
+};
+
+/// How far apart each channel, `r`, `g`, `b`, `a`, may be and still match
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PerChannelTolerance(pub [f32; 4]);
+
+impl PerChannelTolerance {
+    /// No difference at all
+    pub const EXACT: Self = Self([0.; 4]);
+
+    /// The same tolerance `t` for every channel
+    pub const fn uniform(t: f32) -> Self {
+        Self([t; 4])
+    }
+
+    /// Up to `levels` 8 bit steps in every channel
+    pub fn levels(levels: u8) -> Self {
+        // A little slack so values that round to `levels` apart still match
+        Self::uniform((levels as f32 + 0.5) / 255.)
+    }
+}
+
+/// Anything that can be the expected side of [`compare`]
+pub trait Expected {
+    fn expected(&self) -> Cow<'_, Image>;
+}
+
+impl Expected for Image {
+    fn expected(&self) -> Cow<'_, Image> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Expected for ImageRef<'_> {
+    fn expected(&self) -> Cow<'_, Image> {
+        Cow::Owned(self.to_image())
+    }
+}
+
+impl Expected for StaticImage {
+    fn expected(&self) -> Cow<'_, Image> {
+        Cow::Owned(self.to_image())
+    }
+}
+
+/// Result of [`compare`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageDiff {
+    /// Pixels with a channel beyond the tolerance
+    pub differing: usize,
+    pub total: usize,
+    /// Largest difference in each channel
+    pub max_delta: [f32; 4],
+    /// Coordinates of the pixel with the largest difference in any channel,
+    /// and its actual and expected values
+    pub worst: Option<(XY, [f32; 4], [f32; 4])>,
+}
+
+impl ImageDiff {
+    pub fn matches(&self) -> bool {
+        self.differing == 0
+    }
+}
+
+impl fmt::Display for ImageDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} pixels differ, max delta {:?}",
+            self.differing, self.total, self.max_delta
+        )?;
+        if let Some(((x, y), actual, expected)) = self.worst {
+            write!(
+                f,
+                ", worst at ({x}, {y}): actual {actual:?}, expected {expected:?}"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare `actual` against `expected`, pixel by pixel
+///
+/// # Panics
+///
+/// - If the images have different sizes or color spaces, which is always a
+///   mismatch
+#[track_caller]
+pub fn compare(
+    actual: &Image,
+    expected: &impl Expected,
+    tolerance: PerChannelTolerance,
+) -> ImageDiff {
+    let expected = expected.expected();
+    assert_eq!(actual.res, expected.res, "images are different sizes");
+    assert_eq!(
+        actual.color, expected.color,
+        "images are in different color spaces"
+    );
+    let (a_conv, e_conv) = (
+        alpha_converter(actual.alpha, AlphaMode::Straight),
+        alpha_converter(expected.alpha, AlphaMode::Straight),
+    );
+    let mut diff = ImageDiff {
+        differing: 0,
+        total: actual.data.len(),
+        max_delta: [0.; 4],
+        worst: None,
+    };
+    let mut worst = 0.;
+    for (i, (a, e)) in actual.data.iter().zip(&expected.data).enumerate() {
+        let (a, e) = (a_conv(*a), e_conv(*e));
+        let mut beyond = false;
+        let mut pixel = 0f32;
+        for c in 0..4 {
+            let d = (a[c] - e[c]).abs();
+            // NaN never matches
+            let d = if d.is_nan() { f32::INFINITY } else { d };
+            beyond |= d > tolerance.0[c];
+            diff.max_delta[c] = diff.max_delta[c].max(d);
+            pixel = pixel.max(d);
+        }
+        if beyond {
+            diff.differing += 1;
+        }
+        if pixel > worst {
+            worst = pixel;
+            let xy = (i as u32 % actual.width(), i as u32 / actual.width());
+            diff.worst = Some((xy, a, e));
+        }
+    }
+    diff
+}
+
+/// Opaque image of the difference between two images, multiplied by
+/// `amplify` so small errors show up
+///
+/// Alpha differences are added to every channel.
+///
+/// # Panics
+///
+/// - Like [`compare`]
+#[track_caller]
+pub fn diff_image(actual: &Image, expected: &impl Expected, amplify: f32) -> Image {
+    let expected = expected.expected();
+    assert_eq!(actual.res, expected.res, "images are different sizes");
+    let (a_conv, e_conv) = (
+        alpha_converter(actual.alpha, AlphaMode::Straight),
+        alpha_converter(expected.alpha, AlphaMode::Straight),
+    );
+    let data = actual
+        .data
+        .iter()
+        .zip(&expected.data)
+        .map(|(a, e)| {
+            let (a, e) = (a_conv(*a), e_conv(*e));
+            let da = (a[3] - e[3]).abs();
+            let c = |i: usize| (((a[i] - e[i]).abs() + da) * amplify).clamp(0., 1.);
+            [c(0), c(1), c(2), 1.]
+        })
+        .collect();
+    Image::from_parts_unchecked(data, actual.res, actual.color)
+}
+
+/// Assert `actual` matches `expected` within `tolerance`
+///
+/// # Panics
+///
+/// - With a report from [`ImageDiff`] if they don't match
+#[track_caller]
+pub fn assert_images_match(
+    actual: &Image,
+    expected: &impl Expected,
+    tolerance: PerChannelTolerance,
+) {
+    assert_images_match_with(actual, expected, tolerance, |_| ())
+}
+
+/// Like [`assert_images_match`], but on failure first calling `on_failure`
+/// with a PPM of [`diff_image`], amplified 16 times
+///
+/// There's no filesystem here, so writing it out is up to the caller, like
+/// `|ppm| std::fs::write("diff.ppm", ppm).unwrap()`.
+#[track_caller]
+pub fn assert_images_match_with(
+    actual: &Image,
+    expected: &impl Expected,
+    tolerance: PerChannelTolerance,
+    on_failure: impl FnOnce(&[u8]),
+) {
+    let diff = compare(actual, expected, tolerance);
+    if !diff.matches() {
+        on_failure(&ppm::encode(&diff_image(actual, expected, 16.)));
+        panic!("images don't match: {diff}");
+    }
+}
+
+/// Biggest side [`fuzz_geometry`] lets images grow to
+const FUZZ_MAX: u32 = 48;
+
+/// Apply `steps` random geometry operations, with random parameters from
+/// `seed`, checking every result
+///
+/// This crops, scales, resizes, rotates, transposes, flips, takes
+/// viewports, undistorts, outlines, and builds pyramids, starting from a
+/// small noise image. After each step the image must be
+/// [`Image::is_consistent`] and the size the operation promised. Crops are
+/// also given invalid rectangles, which must fail with the right error.
+/// Call it from your own tests with lots of seeds, the same seed always
+/// runs the same operations.
+///
+/// # Panics
+///
+/// - With the seed, step, and operation if any check fails
+#[track_caller]
+pub fn fuzz_geometry(seed: u64, steps: u32) {
+    let mut rng = Rng::new(seed);
+    let mut below = |n: u32| (rng.next_u64() % n as u64) as u32;
+    let (w, h) = (below(FUZZ_MAX) + 1, below(FUZZ_MAX) + 1);
+    let noise = Image::uniform_noise((w, h), seed);
+    let mut img = Image::from_parts(noise.data, (w, h), ColorSpace::sRGB);
+
+    let mut rng = Rng::new(seed ^ 0x5eed);
+    for step in 0..steps {
+        let mut below = |n: u32| (rng.next_u64() % n as u64) as u32;
+        let (w, h) = img.res;
+        let size = (below(FUZZ_MAX) + 1, below(FUZZ_MAX) + 1);
+        let filter = match below(5) {
+            0 => ScaleFilter::Nearest,
+            1 => ScaleFilter::Bilinear,
+            2 => ScaleFilter::Box,
+            3 => ScaleFilter::CATMULL_ROM,
+            _ => ScaleFilter::MITCHELL,
+        };
+        let rotation =
+            [Rotation::R0, Rotation::R90, Rotation::R180, Rotation::R270][below(4) as usize];
+        let coord = |v: u32, max: u32| v as f32 - max as f32 + v as f32 / 7.;
+
+        let op = below(11);
+        let (name, expected) = match op {
+            0 => {
+                // Often past the edges, or empty
+                let origin = (below(w + 2), below(h + 2));
+                let crop = (below(w + 2), below(h + 2));
+                let fits = origin.0 + crop.0 <= w && origin.1 + crop.1 <= h;
+                let want = match (crop.0 == 0 || crop.1 == 0, fits) {
+                    (true, _) => Err(ImageError::InvalidArgument),
+                    (false, false) => Err(ImageError::OutOfBounds),
+                    (false, true) => Ok(()),
+                };
+                let got = img.crop(origin, crop);
+                assert_eq!(
+                    got, want,
+                    "seed {seed} step {step}: crop {origin:?} {crop:?} of {w}x{h}"
+                );
+                ("crop", if got.is_ok() { crop } else { (w, h) })
+            }
+            1 => {
+                img.scale_with(size, filter);
+                ("scale_with", size)
+            }
+            2 => {
+                img = img.resize(size);
+                ("resize", size)
+            }
+            3 => {
+                img.rotate(rotation);
+                ("rotate", rotation.rotated_res((w, h)))
+            }
+            4 => {
+                img.transpose();
+                ("transpose", (h, w))
+            }
+            5 => {
+                if below(2) == 0 {
+                    img.flip_horizontal();
+                } else {
+                    img.flip_vertical();
+                }
+                ("flip", (w, h))
+            }
+            6 => {
+                let origin = (coord(below(2 * w), w), coord(below(2 * h), h));
+                let span = (coord(below(3 * w), w), coord(below(3 * h), h));
+                img = img.viewport((origin, span), size, filter);
+                ("viewport", size)
+            }
+            7 => {
+                let k = |v: u32| v as f32 / 10. - 0.5;
+                let center = (coord(below(2 * w), w), coord(below(2 * h), h));
+                let out = if below(2) == 0 { Some(size) } else { None };
+                img = img.undistort(k(below(11)), k(below(11)), center, out);
+                ("undistort", out.unwrap_or((w, h)))
+            }
+            8 => {
+                img.scale_indexed(size);
+                ("scale_indexed", size)
+            }
+            9 => {
+                let thickness = below(4);
+                let mode = if below(2) == 0 {
+                    OutlineMode::Outside
+                } else {
+                    OutlineMode::Inside
+                };
+                let moved = img.outline(thickness, [1., 0., 0., 1.], mode);
+                let grow = 2 * moved.0;
+                ("outline", (w + grow, h + grow))
+            }
+            _ => {
+                let levels = below(5) as usize;
+                for (i, level) in img.gaussian_pyramid(levels).iter().enumerate() {
+                    assert!(
+                        level.is_consistent(),
+                        "seed {seed} step {step}: pyramid level {i} of {w}x{h} is inconsistent"
+                    );
+                }
+                ("gaussian_pyramid", (w, h))
+            }
+        };
+        assert!(
+            img.is_consistent(),
+            "seed {seed} step {step}: {name} of {w}x{h} left {} pixels for {:?}",
+            img.data.len(),
+            img.res
+        );
+        assert_eq!(
+            img.res, expected,
+            "seed {seed} step {step}: {name} of {w}x{h}"
+        );
+
+        // Keep it small, so long runs stay fast
+        if img.res.0 > FUZZ_MAX || img.res.1 > FUZZ_MAX {
+            img.scale_with(
+                (img.res.0.min(FUZZ_MAX), img.res.1.min(FUZZ_MAX)),
+                ScaleFilter::Box,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::photo, PixelFormat};
+
+    /// `photo` with the pixel at `(3, 1)` off by `delta` in green
+    fn nudged(delta: f32) -> Image {
+        let mut img = photo((6, 4));
+        img.data[6 + 3][1] += delta;
+        img
+    }
+
+    #[test]
+    fn matching_images_pass() {
+        let img = photo((6, 4));
+        assert_images_match(&img, &img, PerChannelTolerance::EXACT);
+        assert_images_match(&nudged(1. / 255.), &img, PerChannelTolerance::levels(1));
+        let diff = compare(&nudged(0.1), &img, PerChannelTolerance::uniform(0.2));
+        assert!(diff.matches());
+        assert_eq!(diff.total, 24);
+        assert!((diff.max_delta[1] - 0.1).abs() < 1e-6);
+        assert_eq!(diff.max_delta[0], 0.);
+    }
+
+    #[test]
+    fn image_ref_expected() {
+        let img = photo((6, 4));
+        let raw = img.to_raw(PixelFormat::Rgba8888);
+        let expected =
+            ImageRef::new(&raw, (6, 4), PixelFormat::Rgba8888, ColorSpace::sRGB).unwrap();
+        assert_images_match(&img, &expected, PerChannelTolerance::levels(0));
+    }
+
+    #[test]
+    fn reports_worst_pixel() {
+        let diff = compare(&nudged(0.3), &photo((6, 4)), PerChannelTolerance::levels(2));
+        assert_eq!(diff.differing, 1);
+        let ((x, y), actual, expected) = diff.worst.unwrap();
+        assert_eq!((x, y), (3, 1));
+        assert!((actual[1] - expected[1] - 0.3).abs() < 1e-6);
+        assert!(alloc::format!("{diff}").contains("worst at (3, 1)"));
+    }
+
+    #[test]
+    #[should_panic = "1 of 24 pixels differ"]
+    fn single_pixel_fails() {
+        assert_images_match_with(
+            &nudged(0.3),
+            &photo((6, 4)),
+            PerChannelTolerance::levels(2),
+            |file| {
+                let diff = ppm::decode(file).unwrap();
+                for (i, p) in diff.pixels().iter().enumerate() {
+                    let want = if i == 9 { 1. } else { 0. };
+                    assert_eq!(p[1], want, "diff pixel {i}");
+                }
+            },
+        );
+    }
+
+    #[test]
+    #[should_panic = "worst at (3, 1)"]
+    fn failure_names_coordinate() {
+        assert_images_match(&nudged(-0.05), &photo((6, 4)), PerChannelTolerance::EXACT);
+    }
+
+    #[test]
+    fn fuzz_geometry_seeds() {
+        for seed in 0..300 {
+            fuzz_geometry(seed, 25);
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Float export for texture uploads
+use alloc::vec::Vec;
+
+use crate::{layout::quantize, AlphaMode, ColorSpace, Image, ImageError, ResXY};
+
+/// GPU texture formats, for [`Image::to_texture_data`]
+///
+/// GPUs decode sRGB typed formats to linear light when sampling, and use
+/// everything else as is, so the bytes need to match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureFormat {
+    /// RGBA 8888, sRGB encoded
+    Rgba8Srgb,
+    /// RGBA 8888, linear
+    Rgba8Unorm,
+    /// RGBA little endian half floats, linear
+    Rgba16Float,
+    /// Red and green, 8 bits each, linear
+    Rg88,
+}
+
+impl TextureFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            TextureFormat::Rgba8Srgb | TextureFormat::Rgba8Unorm => 4,
+            TextureFormat::Rgba16Float => 8,
+            TextureFormat::Rg88 => 2,
+        }
+    }
+
+    /// The color space texels of this format are in
+    fn color(self) -> ColorSpace {
+        match self {
+            TextureFormat::Rgba8Srgb => ColorSpace::sRGB,
+            _ => ColorSpace::sRGBLinear,
+        }
+    }
+}
+
+/// Texels ready to upload, from [`Image::to_texture_data`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextureData {
+    /// Tightly packed rows, top to bottom
+    pub bytes: Vec<u8>,
+    pub res: ResXY,
+    pub format: TextureFormat,
+}
+
+/// Largest finite half float
+const F16_MAX: f32 = 65504.;
+
+/// Round `m >> shift` to nearest, ties to even
+fn round_shift(m: u32, shift: u32) -> u32 {
+    let q = m >> shift;
+    let rem = m & ((1 << shift) - 1);
+    let half = 1 << (shift - 1);
+    if rem > half || (rem == half && q & 1 == 1) {
+        q + 1
+    } else {
+        q
+    }
+}
+
+/// Convert `v` to an IEEE half float, rounding to nearest even
+///
+/// Values too large for a half clamp to the largest finite one, including
+/// infinities. NaN stays NaN.
+pub(crate) fn f32_to_f16(v: f32) -> u16 {
+    let sign = ((v.to_bits() >> 16) & 0x8000) as u16;
+    let x = v.abs();
+    if x.is_nan() {
+        return sign | 0x7e00;
+    }
+    if x >= F16_MAX {
+        return sign | 0x7bff;
+    }
+    let bits = x.to_bits();
+    let exp = (bits >> 23) as i32 - 127;
+    let man = bits & 0x7f_ffff;
+    if exp < -14 {
+        // Subnormal, in units of 2^-24
+        let shift = (-(exp + 1)) as u32;
+        if shift > 24 {
+            return sign;
+        }
+        return sign | round_shift(man | 0x80_0000, shift) as u16;
+    }
+    // A carry out of the mantissa correctly bumps the exponent
+    let h = (((exp + 15) as u32) << 10) + round_shift(man, 13);
+    sign | h as u16
+}
+
+impl Image {
+    /// The pixel data as interleaved RGBA `f32`, `width * height * 4` long
+    ///
+    /// Pixels are in row order with no padding, and values are as stored,
+    /// so in the images color space and [`AlphaMode`]. This is guaranteed,
+    /// and suitable for uploading directly as an RGBA32F texture.
+    ///
+    /// [`AlphaMode`]: crate::AlphaMode
+    pub fn as_f32_slice(&self) -> &[f32] {
+        self.data.as_flattened()
+    }
+
+    /// Owned copy of [`Image::as_f32_slice`]
+    pub fn to_f32_vec(&self) -> Vec<f32> {
+        self.as_f32_slice().to_vec()
+    }
+
+    /// Create an Image from interleaved RGBA `f32`, straight alpha
+    ///
+    /// The values are used as is, see [`Image::sanitize`] for untrusted data.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::DimensionMismatch`] if `data` is not exactly
+    ///   `width * height * 4` long
+    pub fn from_f32_vec(data: Vec<f32>, res: ResXY, color: ColorSpace) -> Result<Self, ImageError> {
+        let len = (res.0 as usize).checked_mul(res.1 as usize);
+        if Some(data.len()) != len.and_then(|n| n.checked_mul(4)) {
+            return Err(ImageError::DimensionMismatch);
+        }
+        let data = data
+            .chunks_exact(4)
+            .map(|p| [p[0], p[1], p[2], p[3]])
+            .collect();
+        Ok(Self::from_parts_unchecked(data, res, color))
+    }
+
+    /// Export as little endian RGBA half floats, for RGBA16F textures
+    ///
+    /// Values are as stored, like [`Image::as_f32_slice`], rounded to the
+    /// nearest half. Anything beyond `±65504` is clamped to it.
+    pub fn to_rgba16f_bytes(&self) -> Vec<u8> {
+        self.as_f32_slice()
+            .iter()
+            .flat_map(|v| f32_to_f16(*v).to_le_bytes())
+            .collect()
+    }
+
+    /// Export for a GPU texture of `target`, encoded how the GPU expects
+    ///
+    /// The image is converted to [`ColorSpace::sRGB`] for
+    /// [`TextureFormat::Rgba8Srgb`], and to [`ColorSpace::sRGBLinear`] for
+    /// everything else. Alpha is straight. [`ColorSpace::AsIs`] images
+    /// could be either, so are an error, see
+    /// [`Image::to_texture_data_with`].
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::ColorSpaceMismatch`] if the image is
+    ///   [`ColorSpace::AsIs`]
+    pub fn to_texture_data(&self, target: TextureFormat) -> Result<TextureData, ImageError> {
+        self.to_texture_data_with(target, false)
+    }
+
+    /// [`Image::to_texture_data`], exporting [`ColorSpace::AsIs`] values as
+    /// stored if `as_is`, whatever `target` expects
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::ColorSpaceMismatch`] if the image is
+    ///   [`ColorSpace::AsIs`] and `as_is` is false
+    pub fn to_texture_data_with(
+        &self,
+        target: TextureFormat,
+        as_is: bool,
+    ) -> Result<TextureData, ImageError> {
+        if self.color == ColorSpace::AsIs && !as_is {
+            return Err(ImageError::ColorSpaceMismatch);
+        }
+        let mut img = self.clone();
+        img.to_alpha_mode(AlphaMode::Straight);
+        if img.color != ColorSpace::AsIs {
+            img.to_color(target.color());
+        }
+        let mut bytes = Vec::with_capacity(img.data.len() * target.bytes_per_pixel());
+        for p in &img.data {
+            match target {
+                TextureFormat::Rgba8Srgb | TextureFormat::Rgba8Unorm => {
+                    bytes.extend(p.map(|c| quantize(c, 255.) as u8))
+                }
+                TextureFormat::Rgba16Float => {
+                    bytes.extend(p.iter().flat_map(|c| f32_to_f16(*c).to_le_bytes()))
+                }
+                TextureFormat::Rg88 => {
+                    bytes.extend(p[..2].iter().map(|c| quantize(*c, 255.) as u8))
+                }
+            }
+        }
+        Ok(TextureData {
+            bytes,
+            res: self.res,
+            format: target,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::photo;
+
+    #[test]
+    fn f32_slice_layout() {
+        let img = photo((7, 5));
+        let flat = img.as_f32_slice();
+        assert_eq!(flat.len(), 7 * 5 * 4);
+        for y in 0..5 {
+            for x in 0..7 {
+                let i = (y * 7 + x) as usize * 4;
+                assert_eq!(flat[i..i + 4], img.get_pixel((x, y)).unwrap());
+            }
+        }
+        assert_eq!(img.to_f32_vec(), flat);
+    }
+
+    #[test]
+    fn f32_vec_round_trip() {
+        let img = photo((6, 4));
+        let back = Image::from_f32_vec(img.to_f32_vec(), (6, 4), ColorSpace::sRGB).unwrap();
+        assert_eq!(back.pixels(), img.pixels());
+        assert_eq!(back.color, ColorSpace::sRGB);
+        for len in [0, 95, 97] {
+            assert_eq!(
+                Image::from_f32_vec(alloc::vec![0.; len], (6, 4), ColorSpace::sRGB).err(),
+                Some(ImageError::DimensionMismatch)
+            );
+        }
+        assert!(Image::from_f32_vec(Vec::new(), (0, 3), ColorSpace::sRGB).is_ok());
+    }
+
+    #[test]
+    fn f16_bit_patterns() {
+        let sub = |n: i32| libm::ldexpf(1., n);
+        for (v, bits) in [
+            (0., 0x0000),
+            (-0., 0x8000),
+            (1., 0x3c00),
+            (0.5, 0x3800),
+            (-2., 0xc000),
+            (1. / 3., 0x3555),
+            (0.1, 0x2e66),
+            (65504., 0x7bff),
+            (1e6, 0x7bff),
+            (-1e6, 0xfbff),
+            (f32::INFINITY, 0x7bff),
+            (f32::NEG_INFINITY, 0xfbff),
+            // Smallest normal, and subnormals
+            (sub(-14), 0x0400),
+            (sub(-15), 0x0200),
+            (sub(-24), 0x0001),
+            (3. * sub(-26), 0x0001),
+            // Ties to even
+            (sub(-25), 0x0000),
+            (3. * sub(-25), 0x0002),
+            (sub(-14) - sub(-25), 0x0400),
+            (2049., 0x6800),
+            (2051., 0x6802),
+            (1e-10, 0x0000),
+        ] {
+            assert_eq!(f32_to_f16(v), bits, "{v:e}");
+        }
+        assert_eq!(f32_to_f16(f32::NAN) & 0x7fff, 0x7e00);
+    }
+
+    #[test]
+    fn rgba16f_bytes() {
+        let img = Image::from_parts(
+            alloc::vec![[0., 0.5, 1., 70000.]],
+            (1, 1),
+            ColorSpace::sRGBLinear,
+        );
+        assert_eq!(
+            img.to_rgba16f_bytes(),
+            [0x00, 0x00, 0x00, 0x38, 0x00, 0x3c, 0xff, 0x7b]
+        );
+        assert_eq!(photo((3, 3)).to_rgba16f_bytes().len(), 3 * 3 * 8);
+    }
+
+    /// Decode an IEEE half float
+    fn f16_to_f32(h: u16) -> f32 {
+        let sign = if h & 0x8000 != 0 { -1. } else { 1. };
+        let (exp, man) = ((h >> 10) & 0x1f, (h & 0x3ff) as f32);
+        sign * match exp {
+            0 => libm::ldexpf(man, -24),
+            31 if man == 0. => f32::INFINITY,
+            31 => f32::NAN,
+            _ => libm::ldexpf(1. + man / 1024., exp as i32 - 15),
+        }
+    }
+
+    fn linear() -> Image {
+        let mut img = photo((8, 6));
+        img.to_color(ColorSpace::sRGBLinear);
+        img.map_pixels_indexed(|(x, _), p| [p[0], p[1], p[2], x as f32 / 7.]);
+        img
+    }
+
+    #[test]
+    fn srgb_and_unorm_differ_by_the_transfer() {
+        let img = linear();
+        let encode = ColorSpace::sRGB.transfer().unwrap().1;
+        let srgb = img.to_texture_data(TextureFormat::Rgba8Srgb).unwrap();
+        let unorm = img.to_texture_data(TextureFormat::Rgba8Unorm).unwrap();
+        assert_eq!((srgb.res, srgb.format), ((8, 6), TextureFormat::Rgba8Srgb));
+        assert_eq!(
+            (unorm.res, unorm.format),
+            ((8, 6), TextureFormat::Rgba8Unorm)
+        );
+        for ((p, s), u) in img
+            .pixels()
+            .iter()
+            .zip(srgb.bytes.chunks_exact(4))
+            .zip(unorm.bytes.chunks_exact(4))
+        {
+            for c in 0..3 {
+                assert_eq!(u[c], quantize(p[c], 255.) as u8);
+                assert_eq!(s[c], quantize(encode(p[c]), 255.) as u8);
+            }
+            assert_eq!(s[3], u[3]);
+            assert_eq!(u[3], quantize(p[3], 255.) as u8);
+        }
+        // From sRGB the other way around
+        let mut encoded = img.clone();
+        encoded.to_color(ColorSpace::sRGB);
+        let again = encoded.to_texture_data(TextureFormat::Rgba8Srgb).unwrap();
+        assert_eq!(again.bytes, srgb.bytes);
+    }
+
+    #[test]
+    fn straight_alpha() {
+        let mut img = linear();
+        let want = img.to_texture_data(TextureFormat::Rgba8Unorm).unwrap();
+        img.to_alpha_mode(AlphaMode::Premultiplied);
+        let got = img.to_texture_data(TextureFormat::Rgba8Unorm).unwrap();
+        // Transparent pixels lose their color when premultiplied
+        for (g, w) in got.bytes.chunks_exact(4).zip(want.bytes.chunks_exact(4)) {
+            if w[3] != 0 {
+                assert!(g.iter().zip(w).all(|(g, w)| g.abs_diff(*w) <= 1));
+            }
+        }
+    }
+
+    #[test]
+    fn as_is_is_ambiguous() {
+        let mut img = linear();
+        img.color = ColorSpace::AsIs;
+        for target in [TextureFormat::Rgba8Srgb, TextureFormat::Rg88] {
+            assert_eq!(
+                img.to_texture_data(target),
+                Err(ImageError::ColorSpaceMismatch)
+            );
+        }
+        // Exported as stored with the flag, whatever the target
+        let srgb = img
+            .to_texture_data_with(TextureFormat::Rgba8Srgb, true)
+            .unwrap();
+        let unorm = img
+            .to_texture_data_with(TextureFormat::Rgba8Unorm, true)
+            .unwrap();
+        assert_eq!(srgb.bytes, unorm.bytes);
+    }
+
+    #[test]
+    fn rgba16f_and_rg88_texels() {
+        let img = linear();
+        let half = img.to_texture_data(TextureFormat::Rgba16Float).unwrap();
+        assert_eq!(half.bytes.len(), 8 * 6 * 8);
+        for (p, t) in img.pixels().iter().zip(half.bytes.chunks_exact(8)) {
+            for c in 0..4 {
+                let v = f16_to_f32(u16::from_le_bytes([t[c * 2], t[c * 2 + 1]]));
+                assert!(
+                    (v - p[c]).abs() <= p[c].abs() / 1024. + 1e-7,
+                    "{v} {}",
+                    p[c]
+                );
+            }
+        }
+        let rg = img.to_texture_data(TextureFormat::Rg88).unwrap();
+        assert_eq!(rg.bytes.len(), 8 * 6 * 2);
+        for (p, t) in img.pixels().iter().zip(rg.bytes.chunks_exact(2)) {
+            assert_eq!(t, [quantize(p[0], 255.) as u8, quantize(p[1], 255.) as u8]);
+        }
+    }
+
+    #[test]
+    fn f16_round_trips() {
+        for h in (0..=0xffffu16).filter(|h| h & 0x7c00 != 0x7c00) {
+            assert_eq!(f32_to_f16(f16_to_f32(h)), h, "{h:#x}");
+        }
+    }
+}
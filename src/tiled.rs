@@ -0,0 +1,381 @@
+//! Tiled pixel layouts, for display controllers and GPUs that fetch
+//! blocks rather than rows
+use alloc::{vec, vec::Vec};
+
+use crate::{layout, ColorSpace, Image, ImageError, PixelFormat, ResXY};
+
+/// Order of the pixels inside each tile, tiles themselves are always in
+/// rows, left to right and top to bottom
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileOrder {
+    /// In rows, like a tiny image
+    RowMajor,
+    /// Z-order, with the bits of x and y interleaved, x first
+    ///
+    /// Tile sizes must be powers of two. When they aren't square, the
+    /// leftover bits of the larger side come last.
+    Morton,
+}
+
+/// What fills the part of edge tiles past the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TilePadding {
+    /// The nearest edge pixel
+    Replicate,
+    /// Zero bytes
+    Zero,
+}
+
+/// How tiles of `tile` size are laid out over an image
+struct Tiling {
+    tile: ResXY,
+    order: TileOrder,
+    /// Tiles in each row
+    across: u32,
+    /// Size of the image padded out to whole tiles
+    padded: ResXY,
+    /// Size of the whole buffer, in pixels
+    len: usize,
+}
+
+impl Tiling {
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `tile` is zero in either
+    ///   dimension, not powers of two for [`TileOrder::Morton`], or the
+    ///   buffer size would overflow
+    fn new(res: ResXY, tile: ResXY, order: TileOrder) -> Result<Self, ImageError> {
+        let (tw, th) = tile;
+        if tw == 0 || th == 0 {
+            return Err(ImageError::InvalidArgument);
+        }
+        if order == TileOrder::Morton && !(tw.is_power_of_two() && th.is_power_of_two()) {
+            return Err(ImageError::InvalidArgument);
+        }
+        let across = res.0.div_ceil(tw);
+        let padded = across
+            .checked_mul(tw)
+            .zip(res.1.div_ceil(th).checked_mul(th))
+            .ok_or(ImageError::InvalidArgument)?;
+        let len = (padded.0 as usize)
+            .checked_mul(padded.1 as usize)
+            .ok_or(ImageError::InvalidArgument)?;
+        Ok(Self {
+            tile,
+            order,
+            across,
+            padded,
+            len,
+        })
+    }
+
+    /// Index in the buffer of the pixel at `(x, y)`, which may be past the
+    /// image in an edge tile
+    fn index(&self, x: u32, y: u32) -> usize {
+        let (tw, th) = self.tile;
+        let tile = (y / th) as usize * self.across as usize + (x / tw) as usize;
+        let (x, y) = (x % tw, y % th);
+        let inside = match self.order {
+            TileOrder::RowMajor => (y * tw + x) as usize,
+            TileOrder::Morton => morton(x, y, self.tile),
+        };
+        tile * tw as usize * th as usize + inside
+    }
+}
+
+/// Z-order index of `(x, y)` in a power of two `tile`
+fn morton(x: u32, y: u32, (tw, th): ResXY) -> usize {
+    let (bw, bh) = (tw.trailing_zeros(), th.trailing_zeros());
+    let (mut out, mut shift) = (0, 0);
+    for bit in 0..bw.max(bh) {
+        if bit < bw {
+            out |= (((x >> bit) & 1) as usize) << shift;
+            shift += 1;
+        }
+        if bit < bh {
+            out |= (((y >> bit) & 1) as usize) << shift;
+            shift += 1;
+        }
+    }
+    out
+}
+
+impl Image {
+    /// Export the image in the pixel format `format`, with straight alpha,
+    /// in tiles of `tile` pixels, ordered inside by `order`
+    ///
+    /// Tiles go left to right, top to bottom, and each is
+    /// `tile.0 * tile.1` pixels, so an image that isn't a multiple of the
+    /// tile size has its right and bottom tiles filled out with `padding`.
+    /// Gray formats get the luma, like [`Image::to_raw`].
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `tile` is zero in either
+    ///   dimension, not powers of two for [`TileOrder::Morton`], or the
+    ///   size overflows `usize`
+    pub fn to_raw_tiled(
+        &self,
+        format: PixelFormat,
+        tile: ResXY,
+        order: TileOrder,
+        padding: TilePadding,
+    ) -> Result<Vec<u8>, ImageError> {
+        let tiling = Tiling::new(self.res, tile, order)?;
+        let bpp = format.bytes_per_pixel();
+        let size = tiling
+            .len
+            .checked_mul(bpp)
+            .ok_or(ImageError::InvalidArgument)?;
+        let mut out = vec![0; size];
+        if self.data.is_empty() {
+            return Ok(out);
+        }
+        let (w, h) = self.res;
+        let transfer = self.color.transfer();
+        let (x_end, y_end) = match padding {
+            TilePadding::Replicate => tiling.padded,
+            TilePadding::Zero => (w, h),
+        };
+        for y in 0..y_end {
+            for x in 0..x_end {
+                let p = self.data[(y.min(h - 1) * w + x.min(w - 1)) as usize];
+                let i = tiling.index(x, y) * bpp;
+                let p = layout::prepare(p, format, self.alpha, transfer);
+                format.encode(p, &mut out[i..i + bpp]);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Read an Image from `data` in the pixel format `format`, in tiles of
+    /// `tile` pixels, ordered inside by `order`, like
+    /// [`Image::to_raw_tiled`] writes
+    ///
+    /// Padding in edge tiles is ignored.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::InvalidArgument`] if `tile` is zero in either
+    ///   dimension, not powers of two for [`TileOrder::Morton`], or the
+    ///   size overflows `usize`
+    /// - [`ImageError::BufferSize`] if `data` isn't exactly the size of
+    ///   the tiles covering `res`
+    pub fn from_raw_tiled(
+        data: &[u8],
+        res: ResXY,
+        format: PixelFormat,
+        tile: ResXY,
+        order: TileOrder,
+        color: ColorSpace,
+    ) -> Result<Self, ImageError> {
+        let tiling = Tiling::new(res, tile, order)?;
+        let bpp = format.bytes_per_pixel();
+        let expected = tiling
+            .len
+            .checked_mul(bpp)
+            .ok_or(ImageError::InvalidArgument)?;
+        if data.len() != expected {
+            return Err(ImageError::BufferSize {
+                expected,
+                actual: data.len(),
+            });
+        }
+        let (w, h) = res;
+        let mut pixels = Vec::with_capacity(w as usize * h as usize);
+        for y in 0..h {
+            for x in 0..w {
+                let i = tiling.index(x, y) * bpp;
+                pixels.push(format.decode(&data[i..i + bpp]));
+            }
+        }
+        Ok(Self::from_parts(pixels, res, color))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RGBA: PixelFormat = PixelFormat::Rgba8888;
+
+    /// Every pixel different
+    fn image(res: ResXY) -> Image {
+        let data: Vec<u8> = (0..res.0 * res.1 * 4)
+            .map(|i| (i * 7 + i / 4) as u8)
+            .collect();
+        Image::from_bytes(&data, res, ColorSpace::sRGB)
+    }
+
+    /// `res` black, with the pixel at `at` red
+    fn marked(res: ResXY, at: ResXY) -> Image {
+        let mut img = Image::from_bytes(
+            &vec![0; (res.0 * res.1 * 4) as usize],
+            res,
+            ColorSpace::sRGB,
+        );
+        img.data[(at.1 * res.0 + at.0) as usize] = [1., 0., 0., 1.];
+        img
+    }
+
+    /// Pixel index of the only red pixel in `out`
+    fn red(out: &[u8]) -> usize {
+        let red: Vec<usize> = (0..out.len() / 4).filter(|i| out[i * 4] == 255).collect();
+        assert_eq!(red.len(), 1, "{red:?}");
+        red[0]
+    }
+
+    #[test]
+    fn round_trips() {
+        let cases = [
+            ((7, 5), (4, 4), TileOrder::RowMajor),
+            ((7, 5), (3, 2), TileOrder::RowMajor),
+            ((8, 8), (4, 4), TileOrder::Morton),
+            ((7, 5), (4, 4), TileOrder::Morton),
+            ((9, 3), (4, 2), TileOrder::Morton),
+            ((9, 3), (2, 8), TileOrder::Morton),
+            ((1, 1), (4, 4), TileOrder::Morton),
+        ];
+        for (res, tile, order) in cases {
+            let img = image(res);
+            for padding in [TilePadding::Replicate, TilePadding::Zero] {
+                let out = img.to_raw_tiled(RGBA, tile, order, padding).unwrap();
+                let padded = (res.0.div_ceil(tile.0) * tile.0) * (res.1.div_ceil(tile.1) * tile.1);
+                assert_eq!(out.len(), padded as usize * 4);
+                let back =
+                    Image::from_raw_tiled(&out, res, RGBA, tile, order, ColorSpace::sRGB).unwrap();
+                assert_eq!(
+                    back.to_bytes(),
+                    img.to_bytes(),
+                    "{res:?} {tile:?} {order:?}"
+                );
+            }
+        }
+        // Tiles of one pixel are rows
+        let img = image((5, 3));
+        let out = img
+            .to_raw_tiled(RGBA, (1, 1), TileOrder::Morton, TilePadding::Zero)
+            .unwrap();
+        assert_eq!(out, img.to_raw(RGBA));
+    }
+
+    #[test]
+    fn marked_pixels_land_by_hand() {
+        // 10x6 in 4x4 tiles is 3 tiles across, (5, 3) is in the second
+        // tile at (1, 3) in it
+        let tiled = |at, tile, order| {
+            let out = marked((10, 6), at)
+                .to_raw_tiled(RGBA, tile, order, TilePadding::Zero)
+                .unwrap();
+            red(&out)
+        };
+        assert_eq!(tiled((5, 3), (4, 4), TileOrder::RowMajor), 16 + 3 * 4 + 1);
+        // x0 y0 x1 y1 is 1 1 0 1
+        assert_eq!(tiled((5, 3), (4, 4), TileOrder::Morton), 16 + 0b1011);
+        // Second row of tiles, first tile, (2, 1) in it
+        assert_eq!(tiled((2, 5), (4, 4), TileOrder::RowMajor), 3 * 16 + 6);
+        assert_eq!(tiled((2, 5), (4, 4), TileOrder::Morton), 3 * 16 + 0b0110);
+        // 4x2 tiles, 3 across, (1, 1) in the fourth: x0 y0 x1 is 1 1 0
+        assert_eq!(tiled((1, 3), (4, 2), TileOrder::RowMajor), 3 * 8 + 5);
+        assert_eq!(tiled((1, 3), (4, 2), TileOrder::Morton), 3 * 8 + 0b011);
+        // 2x4, (1, 2) in the first: x0 y0 y1 is 1 0 1
+        assert_eq!(tiled((1, 2), (2, 4), TileOrder::Morton), 0b101);
+        // The marked pixel decodes back in place
+        let out = marked((10, 6), (5, 3))
+            .to_raw_tiled(RGBA, (4, 4), TileOrder::Morton, TilePadding::Zero)
+            .unwrap();
+        let img = Image::from_raw_tiled(
+            &out,
+            (10, 6),
+            RGBA,
+            (4, 4),
+            TileOrder::Morton,
+            ColorSpace::sRGB,
+        )
+        .unwrap();
+        assert_eq!(img.get_pixel((5, 3)), Some([1., 0., 0., 1.]));
+    }
+
+    #[test]
+    fn padding_follows_the_policy() {
+        // 5x3 in 4x4 tiles, the right tile has 1 of 4 columns and 3 of 4
+        // rows of the image
+        let img = image((5, 3));
+        let pixel = |out: &[u8], x, y| {
+            let tiling = Tiling::new((5, 3), (4, 4), TileOrder::RowMajor).unwrap();
+            let i = tiling.index(x, y) * 4;
+            [out[i], out[i + 1], out[i + 2], out[i + 3]]
+        };
+        let src = |x: u32, y: u32| {
+            let raw = img.to_raw(RGBA);
+            let i = (y * 5 + x) as usize * 4;
+            [raw[i], raw[i + 1], raw[i + 2], raw[i + 3]]
+        };
+        let rep = img
+            .to_raw_tiled(RGBA, (4, 4), TileOrder::RowMajor, TilePadding::Replicate)
+            .unwrap();
+        let zero = img
+            .to_raw_tiled(RGBA, (4, 4), TileOrder::RowMajor, TilePadding::Zero)
+            .unwrap();
+        for y in 0..4 {
+            for x in 0..8 {
+                let inside = x < 5 && y < 3;
+                assert_eq!(pixel(&rep, x, y), src(x.min(4), y.min(2)), "{x} {y}");
+                let want = if inside { src(x, y) } else { [0; 4] };
+                assert_eq!(pixel(&zero, x, y), want, "{x} {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let img = image((5, 3));
+        let pad = TilePadding::Zero;
+        for (tile, order) in [
+            ((0, 4), TileOrder::RowMajor),
+            ((4, 0), TileOrder::Morton),
+            ((3, 4), TileOrder::Morton),
+            ((4, 6), TileOrder::Morton),
+        ] {
+            assert_eq!(
+                img.to_raw_tiled(RGBA, tile, order, pad),
+                Err(ImageError::InvalidArgument)
+            );
+            assert_eq!(
+                Image::from_raw_tiled(&[], (5, 3), RGBA, tile, order, ColorSpace::sRGB).err(),
+                Some(ImageError::InvalidArgument)
+            );
+        }
+        // Too big for the tiles
+        assert_eq!(
+            img.to_raw_tiled(RGBA, (1 << 31, 1 << 31), TileOrder::RowMajor, pad),
+            Err(ImageError::InvalidArgument)
+        );
+        // One row of tiles short
+        let out = img
+            .to_raw_tiled(RGBA, (4, 2), TileOrder::RowMajor, pad)
+            .unwrap();
+        assert_eq!(out.len(), 8 * 4 * 4);
+        assert_eq!(
+            Image::from_raw_tiled(
+                &out[..8 * 2 * 4],
+                (5, 3),
+                RGBA,
+                (4, 2),
+                TileOrder::RowMajor,
+                ColorSpace::sRGB
+            )
+            .err(),
+            Some(ImageError::BufferSize {
+                expected: 8 * 4 * 4,
+                actual: 8 * 2 * 4
+            })
+        );
+        // Empty images have no tiles
+        let empty = Image::from_bytes(&[], (0, 3), ColorSpace::sRGB);
+        assert_eq!(
+            empty.to_raw_tiled(RGBA, (4, 4), TileOrder::Morton, pad),
+            Ok(Vec::new())
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! HDR to SDR tone mapping
+use crate::{transforms::*, Image};
+
+/// Tone mapping operators for [`Image::tone_map`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMap {
+    /// Extended Reinhard, applied per channel
+    ///
+    /// `white_point` is the smallest value that gets mapped to `1.0`
+    Reinhard { white_point: f32 },
+
+    /// Reinhard applied to luminance only, scaling RGB to match.
+    ///
+    /// This preserves hue, but very saturated highlights can still clip.
+    ReinhardLuminance,
+
+    /// Krzysztof Narkowicz's ACES filmic curve fit
+    Aces,
+}
+
+fn reinhard(c: f32, white: f32) -> f32 {
+    c * (1. + c / (white * white)) / (1. + c)
+}
+
+fn aces(c: f32) -> f32 {
+    const A: f32 = 2.51;
+    const B: f32 = 0.03;
+    const C: f32 = 2.43;
+    const D: f32 = 0.59;
+    const E: f32 = 0.14;
+    (c * (A * c + B)) / (c * (C * c + D) + E)
+}
+
+impl Image {
+    /// Map HDR values above `1.0` into `0..=1`
+    ///
+    /// This works in linear light, encoded images are converted to linear and
+    /// back automatically. Alpha is untouched.
+    pub fn tone_map(&mut self, operator: ToneMap) {
+        self.in_linear(|img| {
+            for p in &mut img.data {
+                let rgb = [p[0], p[1], p[2]].map(|c| c.max(0.));
+                let rgb = match operator {
+                    ToneMap::Reinhard { white_point } => {
+                        let white = white_point.max(f32::EPSILON);
+                        rgb.map(|c| reinhard(c, white))
+                    }
+                    ToneMap::ReinhardLuminance => {
+                        let l = luminance(rgb);
+                        if l <= 0. {
+                            rgb
+                        } else {
+                            let scale = (l / (1. + l)) / l;
+                            rgb.map(|c| c * scale)
+                        }
+                    }
+                    ToneMap::Aces => rgb.map(aces),
+                };
+                let [r, g, b] = rgb.map(|c| c.clamp(0., 1.));
+                *p = [r, g, b, p[3]];
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::solid, ColorSpace};
+
+    /// `operator` on a linear gray of `c` with alpha `0.25`
+    fn map(operator: ToneMap, c: f32) -> [f32; 4] {
+        let mut img = solid((2, 1), [c, c, c, 0.25]);
+        img.color = ColorSpace::sRGBLinear;
+        img.tone_map(operator);
+        img.pixels()[1]
+    }
+
+    #[test]
+    fn known_values() {
+        let reinhard = ToneMap::Reinhard { white_point: 4. };
+        for (operator, c, want) in [
+            (reinhard, 0., 0.),
+            (reinhard, 0.1, 0.091_477_27),
+            (reinhard, 1., 0.531_25),
+            (reinhard, 4., 1.),
+            (reinhard, 9., 1.),
+            (ToneMap::ReinhardLuminance, 1., 0.5),
+            (ToneMap::ReinhardLuminance, 3., 0.75),
+            (ToneMap::Aces, 1., 0.803_797_5),
+            (ToneMap::Aces, 10., 1.),
+            (ToneMap::Aces, -2., 0.),
+        ] {
+            let p = map(operator, c);
+            for v in &p[..3] {
+                assert!((v - want).abs() < 1e-5, "{operator:?} of {c} is {v}");
+            }
+            assert_eq!(p[3], 0.25);
+        }
+    }
+
+    #[test]
+    fn luminance_keeps_hue() {
+        let mut img = solid((1, 1), [1.6, 0.8, 0.4, 1.]);
+        img.color = ColorSpace::sRGBLinear;
+        img.tone_map(ToneMap::ReinhardLuminance);
+        let [r, g, b, _] = img.pixels()[0];
+        assert!((r / g - 2.).abs() < 1e-5 && (g / b - 2.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn encoded_round_trips() {
+        let mut img = solid((1, 1), [0.5, 0.5, 0.5, 1.]);
+        img.tone_map(ToneMap::Reinhard { white_point: 1e6 });
+        assert_eq!(img.color, ColorSpace::sRGB);
+        // 0.214 linear maps to 0.176
+        assert!((img.pixels()[0][0] - rgb_to_srgb(0.214_041_14 / 1.214_041_1)).abs() < 1e-5);
+    }
+}
@@ -1,5 +1,15 @@
 //! Image transformations
-use crate::F32;
+//!
+//! All the transfer functions here are sign-preserving, that is negative
+//! inputs are mirrored around zero, `f(-x) == -f(x)`, instead of producing
+//! NaN. This is what happens to extended-range sRGB, and means filters with
+//! negative lobes can overshoot without poisoning everything.
+use nalgebra::Matrix3;
+
+use crate::{
+    color::chromaticity::{rgb_xyz_matrix, WhitePoint, Xy, DISPLAY_P3_PRIMARIES, SRGB_PRIMARIES},
+    WorkPixel, F32,
+};
 
 /// Transform sRGB into linear RGB
 pub fn srgb_to_rgb(c: f32) -> f32 {
@@ -7,10 +17,11 @@ pub fn srgb_to_rgb(c: f32) -> f32 {
     const GAMMA: f32 = 2.4;
     const PHI: f32 = 12.92;
     const C: f32 = 0.04045;
-    if c <= C {
+    let x = c.abs();
+    if x <= C {
         c / PHI
     } else {
-        ((c + A) / (1. + A)).powf(GAMMA)
+        ((x + A) / (1. + A)).powf(GAMMA).copysign(c)
     }
 }
 
@@ -20,19 +31,644 @@ pub fn rgb_to_srgb(c: f32) -> f32 {
     const GAMMA: f32 = 2.4;
     const PHI: f32 = 12.92;
     const C: f32 = 0.0031308;
-    if c <= C {
+    let x = c.abs();
+    if x <= C {
         PHI * c
     } else {
-        ((1. + A) * c.powf(1. / GAMMA)) - A
+        (((1. + A) * x.powf(1. / GAMMA)) - A).copysign(c)
     }
 }
 
 /// Gamma / "simple" RGB to linear RGB
 pub fn gamma_to_rgb(c: f32) -> f32 {
-    c.powf(2.2)
+    c.abs().powf(2.2).copysign(c)
 }
 
 /// Gamma / "simple" RGB from linear RGB
 pub fn rgb_to_gamma(c: f32) -> f32 {
-    c.powf(1.0 / 2.2)
+    c.abs().powf(1.0 / 2.2).copysign(c)
+}
+
+/// Gamma of Adobe RGB (1998), 2.2 rounded to the u8.8 of its ICC profile
+pub const ADOBE_RGB_GAMMA: f32 = 563. / 256.;
+
+/// Adobe RGB to linear RGB
+pub fn adobe_rgb_to_rgb(c: f32) -> f32 {
+    c.abs().powf(ADOBE_RGB_GAMMA).copysign(c)
+}
+
+/// Adobe RGB from linear RGB
+pub fn rgb_to_adobe_rgb(c: f32) -> f32 {
+    c.abs().powf(1.0 / ADOBE_RGB_GAMMA).copysign(c)
+}
+
+/// A transfer function between encoded values and linear light, for
+/// buffers that aren't [`Image`](crate::Image)s
+///
+/// See [`ColorSpace::transfer_function`](crate::ColorSpace::transfer_function)
+/// for the one of each color space, which images use too.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferFunction {
+    /// The piecewise sRGB curve, see [`srgb_to_rgb`]
+    Srgb,
+    /// A flat power curve, encoded values are linear raised to this
+    Gamma(f32),
+    /// Already linear
+    Linear,
+}
+
+impl TransferFunction {
+    /// Encode linear `c`
+    pub fn encode(self, c: f32) -> f32 {
+        match self {
+            TransferFunction::Srgb => rgb_to_srgb(c),
+            TransferFunction::Gamma(g) => c.abs().powf(1.0 / g).copysign(c),
+            TransferFunction::Linear => c,
+        }
+    }
+
+    /// Decode `c` to linear light
+    pub fn decode(self, c: f32) -> f32 {
+        match self {
+            TransferFunction::Srgb => srgb_to_rgb(c),
+            TransferFunction::Gamma(g) => c.abs().powf(g).copysign(c),
+            TransferFunction::Linear => c,
+        }
+    }
+
+    /// [`TransferFunction::encode`] every value of `c`, in place
+    pub fn encode_slice(self, c: &mut [f32]) {
+        #[cfg(feature = "simd")]
+        return crate::simd::transfer_slice(c, self, true);
+        #[cfg(not(feature = "simd"))]
+        match self {
+            TransferFunction::Srgb => linear_to_srgb_slice(c),
+            TransferFunction::Linear => (),
+            _ => c.iter_mut().for_each(|c| *c = self.encode(*c)),
+        }
+    }
+
+    /// [`TransferFunction::decode`] every value of `c`, in place
+    pub fn decode_slice(self, c: &mut [f32]) {
+        #[cfg(feature = "simd")]
+        return crate::simd::transfer_slice(c, self, false);
+        #[cfg(not(feature = "simd"))]
+        match self {
+            TransferFunction::Linear => (),
+            _ => c.iter_mut().for_each(|c| *c = self.decode(*c)),
+        }
+    }
+}
+
+/// Relative luminance of linear Rec.709 / sRGB primaries
+pub fn luminance(rgb: [f32; 3]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+/// Multiply the color channels by alpha
+pub fn premultiply(p: WorkPixel) -> WorkPixel {
+    let a = p[3];
+    [p[0] * a, p[1] * a, p[2] * a, a]
+}
+
+/// Divide the color channels by alpha
+///
+/// Fully transparent pixels become transparent black.
+pub fn unpremultiply(p: WorkPixel) -> WorkPixel {
+    let a = p[3];
+    if a <= 0. {
+        [0., 0., 0., a]
+    } else {
+        [p[0] / a, p[1] / a, p[2] / a, a]
+    }
+}
+
+/// Transform a slice of sRGB values into linear RGB, in place
+pub fn srgb_to_linear_slice(c: &mut [f32]) {
+    for c in c {
+        *c = srgb_to_rgb(*c);
+    }
+}
+
+/// Transform a slice of linear RGB values into sRGB, in place
+pub fn linear_to_srgb_slice(c: &mut [f32]) {
+    #[cfg(feature = "simd")]
+    return crate::simd::transfer_slice(c, TransferFunction::Srgb, true);
+    #[cfg(not(feature = "simd"))]
+    for c in c {
+        *c = rgb_to_srgb(*c);
+    }
+}
+
+/// Apply the transfer function `f` to the color channels of `pixels`
+///
+/// Alpha is left untouched.
+pub fn apply_transfer(pixels: &mut [WorkPixel], f: fn(f32) -> f32) {
+    for p in pixels {
+        *p = [f(p[0]), f(p[1]), f(p[2]), p[3]];
+    }
+}
+
+/// Multiply the color channels of `pixels` by the matrix `m`
+///
+/// Alpha is left untouched.
+pub fn apply_matrix3(pixels: &mut [WorkPixel], m: &Matrix3<f32>) {
+    #[cfg(feature = "simd")]
+    return crate::simd::matrix(pixels, m);
+    #[cfg(not(feature = "simd"))]
+    for p in pixels {
+        let [r, g, b, a] = *p;
+        *p = [
+            m[(0, 0)] * r + m[(0, 1)] * g + m[(0, 2)] * b,
+            m[(1, 0)] * r + m[(1, 1)] * g + m[(1, 2)] * b,
+            m[(2, 0)] * r + m[(2, 1)] * g + m[(2, 2)] * b,
+            a,
+        ];
+    }
+}
+
+/// Hue in degrees, plus the max and min channel, for HSL/HSV
+///
+/// Achromatic colors have a hue of `0`.
+fn hue(rgb: [f32; 3]) -> (f32, f32, f32) {
+    let [r, g, b] = rgb;
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let d = max - min;
+    let h = if d <= 0. {
+        0.
+    } else if max == r {
+        60. * ((g - b) / d)
+    } else if max == g {
+        60. * ((b - r) / d + 2.)
+    } else {
+        60. * ((r - g) / d + 4.)
+    };
+    (wrap_hue(h), max, min)
+}
+
+/// Wrap `h` degrees into `0..360`
+pub(crate) fn wrap_hue(h: f32) -> f32 {
+    let h = h % 360.;
+    if h < 0. {
+        h + 360.
+    } else {
+        h
+    }
+}
+
+/// RGB from hue in degrees, chroma, and the offset to add to each channel
+fn from_hue(h: f32, c: f32, m: f32) -> [f32; 3] {
+    let h = wrap_hue(h) / 60.;
+    let x = c * (1. - ((h % 2.) - 1.).abs());
+    let [r, g, b] = match h as u32 {
+        0 => [c, x, 0.],
+        1 => [x, c, 0.],
+        2 => [0., c, x],
+        3 => [0., x, c],
+        4 => [x, 0., c],
+        _ => [c, 0., x],
+    };
+    [r + m, g + m, b + m]
+}
+
+/// RGB to HSL, with hue in degrees and saturation and lightness `0..=1`
+pub fn rgb_to_hsl(rgb: [f32; 3]) -> [f32; 3] {
+    let (h, max, min) = hue(rgb);
+    let l = (max + min) / 2.;
+    let d = max - min;
+    let s = if d <= 0. {
+        0.
+    } else {
+        d / (1. - (2. * l - 1.).abs())
+    };
+    [h, s, l]
+}
+
+/// HSL to RGB, see [`rgb_to_hsl`]
+pub fn hsl_to_rgb(hsl: [f32; 3]) -> [f32; 3] {
+    let [h, s, l] = hsl;
+    let c = (1. - (2. * l - 1.).abs()) * s;
+    from_hue(h, c, l - c / 2.)
+}
+
+/// RGB to HSV, with hue in degrees and saturation and value `0..=1`
+pub fn rgb_to_hsv(rgb: [f32; 3]) -> [f32; 3] {
+    let (h, max, min) = hue(rgb);
+    let s = if max <= 0. { 0. } else { (max - min) / max };
+    [h, s, max]
+}
+
+/// HSV to RGB, see [`rgb_to_hsv`]
+pub fn hsv_to_rgb(hsv: [f32; 3]) -> [f32; 3] {
+    let [h, s, v] = hsv;
+    let c = v * s;
+    from_hue(h, c, v - c)
+}
+
+/// Matrix from linear RGB with primaries `from` to linear RGB with
+/// primaries `to`, both D65, through XYZ
+pub(crate) fn primaries_matrix(from: [Xy; 3], to: [Xy; 3]) -> Matrix3<f32> {
+    let from = rgb_xyz_matrix(from, WhitePoint::D65);
+    let to = rgb_xyz_matrix(to, WhitePoint::D65);
+    to.try_inverse().unwrap_or_else(Matrix3::zeros) * from
+}
+
+/// Linear sRGB to linear Display P3
+pub fn srgb_to_p3_matrix() -> Matrix3<f32> {
+    primaries_matrix(SRGB_PRIMARIES, DISPLAY_P3_PRIMARIES)
+}
+
+/// Linear Display P3 to linear sRGB
+pub fn p3_to_srgb_matrix() -> Matrix3<f32> {
+    primaries_matrix(DISPLAY_P3_PRIMARIES, SRGB_PRIMARIES)
+}
+
+/// Linear sRGB to Oklab
+pub fn linear_srgb_to_oklab(rgb: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb;
+    let l = 0.41222147 * r + 0.53633254 * g + 0.051445993 * b;
+    let m = 0.2119035 * r + 0.6806996 * g + 0.10739696 * b;
+    let s = 0.08830246 * r + 0.28171884 * g + 0.6299787 * b;
+    let (l, m, s) = (libm::cbrtf(l), libm::cbrtf(m), libm::cbrtf(s));
+    [
+        0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+    ]
+}
+
+/// Oklab to linear sRGB
+pub fn oklab_to_linear_srgb(lab: [f32; 3]) -> [f32; 3] {
+    let [l, a, b] = lab;
+    let l_ = l + 0.39633778 * a + 0.21580376 * b;
+    let m_ = l - 0.105561346 * a - 0.06385417 * b;
+    let s_ = l - 0.08948418 * a - 1.2914855 * b;
+    let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+    [
+        4.0767417 * l - 3.3077116 * m + 0.23096994 * s,
+        -1.268438 * l + 2.6097574 * m - 0.34131938 * s,
+        -0.0041960864 * l - 0.7034186 * m + 1.7076147 * s,
+    ]
+}
+
+/// How to bring out of gamut colors back into `0..=1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GamutMap {
+    /// Clamp each channel
+    #[default]
+    Clip,
+
+    /// Smoothly compress the top 10% of each channel, so values above `1.0`
+    /// still have some gradation instead of all becoming `1.0`
+    ///
+    /// Values below `0.9` are untouched, negatives are clamped.
+    SoftClip,
+
+    /// Reduce chroma in Oklab, holding lightness and hue, until the color
+    /// fits
+    ///
+    /// Colors already in gamut are untouched.
+    ChromaReduce,
+}
+
+fn in_gamut(rgb: [f32; 3]) -> bool {
+    rgb.iter().all(|c| (0.0..=1.0).contains(c))
+}
+
+/// Bring the linear RGB color `rgb` into gamut according to `mode`
+///
+/// Gamut here is just `0..=1`, so this works for any RGB space, though
+/// [`GamutMap::ChromaReduce`] uses Oklab which is only really correct for
+/// sRGB primaries.
+pub fn map_to_gamut(rgb: [f32; 3], mode: GamutMap) -> [f32; 3] {
+    const KNEE: f32 = 0.9;
+    match mode {
+        GamutMap::Clip => rgb.map(|c| c.clamp(0., 1.)),
+        GamutMap::SoftClip => rgb.map(|c| {
+            if c <= KNEE {
+                c.max(0.)
+            } else {
+                let t = (c - KNEE) / (1. - KNEE);
+                KNEE + (1. - KNEE) * (t / (1. + t))
+            }
+        }),
+        GamutMap::ChromaReduce => {
+            if in_gamut(rgb) {
+                return rgb;
+            }
+            let [l, a, b] = linear_srgb_to_oklab(rgb);
+            let l = l.clamp(0., 1.);
+            // Binary search the largest chroma scale that's in gamut
+            let (mut lo, mut hi) = (0f32, 1f32);
+            for _ in 0..24 {
+                let mid = (lo + hi) / 2.;
+                if in_gamut(oklab_to_linear_srgb([l, a * mid, b * mid])) {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            }
+            oklab_to_linear_srgb([l, a * lo, b * lo]).map(|c| c.clamp(0., 1.))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Values across `-0.5..1.5`, negatives and out of range included
+    fn values() -> Vec<f32> {
+        (0..=400).map(|i| i as f32 / 200. - 0.5).collect()
+    }
+
+    fn pixels() -> Vec<WorkPixel> {
+        values()
+            .chunks_exact(4)
+            .map(|c| [c[0], c[1], c[2], c[3]])
+            .collect()
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-6
+    }
+
+    #[test]
+    fn slices_match_scalar() {
+        let src = values();
+        let mut decoded = src.clone();
+        srgb_to_linear_slice(&mut decoded);
+        let mut encoded = src.clone();
+        linear_to_srgb_slice(&mut encoded);
+        for ((x, d), e) in src.iter().zip(&decoded).zip(&encoded) {
+            assert_eq!(d.to_bits(), srgb_to_rgb(*x).to_bits());
+            assert!(close(*e, rgb_to_srgb(*x)), "{x}");
+        }
+    }
+
+    #[test]
+    fn apply_transfer_skips_alpha() {
+        let src = pixels();
+        let mut got = src.clone();
+        apply_transfer(&mut got, gamma_to_rgb);
+        for (s, g) in src.iter().zip(&got) {
+            for c in 0..3 {
+                assert_eq!(g[c].to_bits(), gamma_to_rgb(s[c]).to_bits());
+            }
+            assert_eq!(g[3].to_bits(), s[3].to_bits());
+        }
+    }
+
+    #[test]
+    fn apply_matrix3_skips_alpha() {
+        let m = srgb_to_p3_matrix();
+        let src = pixels();
+        let mut got = src.clone();
+        apply_matrix3(&mut got, &m);
+        for (s, g) in src.iter().zip(&got) {
+            let want = m * nalgebra::Vector3::new(s[0], s[1], s[2]);
+            for c in 0..3 {
+                assert!(close(g[c], want[c]), "{g:?} != {want:?}");
+            }
+            assert_eq!(g[3].to_bits(), s[3].to_bits());
+        }
+    }
+
+    #[test]
+    fn transfer_functions_are_odd() {
+        for f in [srgb_to_rgb, rgb_to_srgb, gamma_to_rgb, rgb_to_gamma] {
+            for x in values() {
+                assert!(f(x).is_finite());
+                assert_eq!(f(-x).to_bits(), (-f(x)).to_bits(), "{x}");
+            }
+        }
+    }
+
+    fn close3(a: [f32; 3], b: [f32; 3], eps: f32) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() <= eps)
+    }
+
+    #[test]
+    fn hsl_hsv_known_pairs() {
+        let pairs = [
+            ([1., 0., 0.], [0., 1., 0.5], [0., 1., 1.]),
+            ([0., 1., 0.], [120., 1., 0.5], [120., 1., 1.]),
+            ([0., 0., 1.], [240., 1., 0.5], [240., 1., 1.]),
+            ([1., 1., 0.], [60., 1., 0.5], [60., 1., 1.]),
+            ([1., 0., 1.], [300., 1., 0.5], [300., 1., 1.]),
+            ([0.5, 0.25, 0.25], [0., 1. / 3., 0.375], [0., 0.5, 0.5]),
+            ([1., 1., 1.], [0., 0., 1.], [0., 0., 1.]),
+            ([0., 0., 0.], [0., 0., 0.], [0., 0., 0.]),
+        ];
+        for (rgb, hsl, hsv) in pairs {
+            assert!(
+                close3(rgb_to_hsl(rgb), hsl, 1e-5),
+                "{rgb:?} {:?}",
+                rgb_to_hsl(rgb)
+            );
+            assert!(
+                close3(rgb_to_hsv(rgb), hsv, 1e-5),
+                "{rgb:?} {:?}",
+                rgb_to_hsv(rgb)
+            );
+            assert!(close3(hsl_to_rgb(hsl), rgb, 1e-5), "{hsl:?}");
+            assert!(close3(hsv_to_rgb(hsv), rgb, 1e-5), "{hsv:?}");
+        }
+    }
+
+    #[test]
+    fn hsl_hsv_round_trip() {
+        let v: Vec<f32> = (0..=10).map(|i| i as f32 / 10.).collect();
+        for r in &v {
+            for g in &v {
+                for b in &v {
+                    let rgb = [*r, *g, *b];
+                    assert!(close3(hsl_to_rgb(rgb_to_hsl(rgb)), rgb, 1e-5), "{rgb:?}");
+                    assert!(close3(hsv_to_rgb(rgb_to_hsv(rgb)), rgb, 1e-5), "{rgb:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hue_wraps() {
+        assert_eq!(wrap_hue(360.), 0.);
+        assert_eq!(wrap_hue(-30.), 330.);
+        assert_eq!(wrap_hue(725.), 5.);
+        // Just under red from the blue side, not 360
+        let h = rgb_to_hsl([1., 0., 0.01])[0];
+        assert!((0. ..360.).contains(&h) && h > 359., "{h}");
+        for h in [0., 360., 720., -360.] {
+            assert!(close3(hsl_to_rgb([h, 1., 0.5]), [1., 0., 0.], 1e-5), "{h}");
+            assert!(close3(hsv_to_rgb([h, 1., 1.]), [1., 0., 0.], 1e-5), "{h}");
+        }
+    }
+
+    #[test]
+    fn achromatic_hue_is_stable() {
+        for v in [0., 0.2, 0.5, 1.] {
+            let [h, s, l] = rgb_to_hsl([v; 3]);
+            assert_eq!((h, s), (0., 0.));
+            assert_eq!(l, v);
+            let [h, s, _] = rgb_to_hsv([v; 3]);
+            assert_eq!((h, s), (0., 0.));
+            // Any hue with no saturation is the same gray
+            for h in [0., 90., 200.] {
+                assert!(close3(hsl_to_rgb([h, 0., v]), [v; 3], 1e-6));
+            }
+        }
+    }
+
+    const MODES: [GamutMap; 3] = [GamutMap::Clip, GamutMap::SoftClip, GamutMap::ChromaReduce];
+
+    /// Saturated Display P3 colors, in linear sRGB so out of gamut
+    fn saturated() -> Vec<[f32; 3]> {
+        let m = p3_to_srgb_matrix();
+        [
+            [1., 0., 0.],
+            [0., 1., 0.],
+            [0., 0., 1.],
+            [1., 1., 0.],
+            [0., 1., 1.],
+            [1., 0., 1.],
+            [0.8, 0.1, 0.3],
+            [0.2, 0.9, 0.4],
+        ]
+        .into_iter()
+        .map(|[r, g, b]| {
+            let v = m * nalgebra::Vector3::new(r, g, b);
+            [v[0], v[1], v[2]]
+        })
+        .collect()
+    }
+
+    fn oklab_hue(rgb: [f32; 3]) -> f32 {
+        let [_, a, b] = linear_srgb_to_oklab(rgb);
+        libm::atan2f(b, a).to_degrees()
+    }
+
+    #[test]
+    fn in_gamut_untouched() {
+        let v: Vec<f32> = (0..=9).map(|i| i as f32 / 10.).collect();
+        for r in &v {
+            for g in &v {
+                for b in &v {
+                    let rgb = [*r, *g, *b];
+                    // Soft clipping compresses the top of the range, below
+                    // the knee it's untouched too
+                    for mode in MODES {
+                        assert_eq!(map_to_gamut(rgb, mode), rgb, "{mode:?}");
+                    }
+                }
+            }
+        }
+        for rgb in [[1., 1., 1.], [1., 0., 0.5], [0.95, 0.99, 0.]] {
+            assert_eq!(map_to_gamut(rgb, GamutMap::Clip), rgb);
+            assert_eq!(map_to_gamut(rgb, GamutMap::ChromaReduce), rgb);
+        }
+    }
+
+    #[test]
+    fn mapped_in_gamut() {
+        for rgb in saturated().into_iter().chain([[2., 0.5, -1.], [-0.5; 3]]) {
+            for mode in MODES {
+                let out = map_to_gamut(rgb, mode);
+                assert!(
+                    out.iter().all(|c| (0.0..=1.0).contains(c)),
+                    "{mode:?} {out:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn chroma_reduce_keeps_hue() {
+        for rgb in saturated() {
+            let out = map_to_gamut(rgb, GamutMap::ChromaReduce);
+            let diff = (oklab_hue(out) - oklab_hue(rgb) + 540.) % 360. - 180.;
+            assert!(diff.abs() < 1., "{rgb:?} -> {out:?}: {diff}");
+            let (l0, l1) = (linear_srgb_to_oklab(rgb)[0], linear_srgb_to_oklab(out)[0]);
+            assert!((l0.min(1.) - l1).abs() < 0.01, "{l0} {l1}");
+        }
+    }
+
+    #[test]
+    fn soft_clip_gradient_is_monotonic() {
+        // Gray to the most saturated P3 red, converted to sRGB
+        let m = p3_to_srgb_matrix();
+        let steps: Vec<[f32; 3]> = (0..=64)
+            .map(|i| {
+                let t = i as f32 / 64.;
+                let p3 = nalgebra::Vector3::new(0.5 + t / 2., 0.5 - t / 2., 0.5 - t / 2.);
+                let v = m * p3;
+                map_to_gamut([v[0], v[1], v[2]], GamutMap::SoftClip)
+            })
+            .collect();
+        for w in steps.windows(2) {
+            assert!(w[1][0] >= w[0][0], "{:?}", w);
+            assert!(w[1][1] <= w[0][1] && w[1][2] <= w[0][2], "{:?}", w);
+        }
+        // Red keeps rising where clipping would have gone flat
+        let over = steps.iter().filter(|p| p[0] > 0.9).count();
+        assert!(over > 4);
+        let top: Vec<f32> = steps.iter().map(|p| p[0]).filter(|r| *r > 0.9).collect();
+        assert!(top.windows(2).all(|w| w[1] > w[0]), "{top:?}");
+    }
+
+    const CURVES: [TransferFunction; 4] = [
+        TransferFunction::Srgb,
+        TransferFunction::Gamma(2.2),
+        TransferFunction::Gamma(1.8),
+        TransferFunction::Linear,
+    ];
+
+    #[test]
+    fn transfer_function_is_srgb() {
+        let srgb = crate::ColorSpace::sRGB.transfer_function();
+        assert_eq!(srgb, TransferFunction::Srgb);
+        for x in values() {
+            assert_eq!(srgb.decode(x).to_bits(), srgb_to_rgb(x).to_bits());
+            assert_eq!(srgb.encode(x).to_bits(), rgb_to_srgb(x).to_bits());
+        }
+        let gamma = crate::ColorSpace::SimplesRGB.transfer_function();
+        for x in values() {
+            assert_eq!(gamma.decode(x).to_bits(), gamma_to_rgb(x).to_bits());
+            assert_eq!(gamma.encode(x).to_bits(), rgb_to_gamma(x).to_bits());
+        }
+        assert_eq!(
+            crate::ColorSpace::AsIs.transfer_function(),
+            TransferFunction::Linear
+        );
+    }
+
+    #[test]
+    fn transfer_slices_match_scalar() {
+        for f in CURVES {
+            let src = values();
+            let (mut encoded, mut decoded) = (src.clone(), src.clone());
+            f.encode_slice(&mut encoded);
+            f.decode_slice(&mut decoded);
+            for ((x, e), d) in src.iter().zip(&encoded).zip(&decoded) {
+                assert!(close(*e, f.encode(*x)), "{f:?} {x}");
+                assert_eq!(d.to_bits(), f.decode(*x).to_bits(), "{f:?} {x}");
+            }
+        }
+    }
+
+    #[test]
+    fn transfer_round_trips() {
+        for f in CURVES {
+            for i in 0..=1000 {
+                let x = i as f32 / 1000.;
+                assert!((f.encode(f.decode(x)) - x).abs() < 1e-6, "{f:?} {x}");
+                assert!((f.decode(f.encode(x)) - x).abs() < 1e-6, "{f:?} {x}");
+            }
+            // Mirrored around zero
+            assert_eq!(f.encode(-0.25), -f.encode(0.25));
+            assert_eq!(f.decode(-0.25), -f.decode(0.25));
+        }
+    }
 }
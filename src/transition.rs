@@ -0,0 +1,369 @@
+//! Transitions between two images, a frame at a time
+use alloc::vec::Vec;
+
+use crate::{
+    alpha_converter,
+    composite::{from_linear_premul, to_linear_premul},
+    Image, ImageError, WorkPixel, F32,
+};
+
+/// How [`Image::transition_frame`] goes from one image to the other
+///
+/// Directions are the way the edge between the images moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transition {
+    /// The new image is uncovered by an edge moving left
+    WipeLeft,
+    WipeRight,
+    WipeUp,
+    WipeDown,
+    /// The new image slides in, pushing the old one out to the left
+    SlideLeft,
+    SlideRight,
+    SlideUp,
+    SlideDown,
+    /// The new image shows through a circle growing from the center, with
+    /// an anti-aliased edge
+    CircleOpen,
+    /// The old image breaks into blocks, which grow until halfway, then
+    /// shrink back into the new image
+    Pixelate,
+}
+
+/// Block size at the middle of a [`Transition::Pixelate`], as a fraction of
+/// the larger dimension
+const PIXELATE_PEAK: f32 = 1. / 16.;
+
+impl Image {
+    /// The frame `t` of the way from this image to `to`, by `kind`
+    ///
+    /// `t` is clamped to `0..=1`, `0` is exactly this image and `1` is
+    /// exactly `to`. Frames are in this image's alpha mode.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::DimensionMismatch`] if the images are different sizes
+    /// - [`ImageError::ColorSpaceMismatch`] if the images have different
+    ///   color spaces
+    pub fn transition_frame(
+        &self,
+        to: &Image,
+        kind: Transition,
+        t: f32,
+    ) -> Result<Image, ImageError> {
+        self.check_blend(to)?;
+        let t = if t.is_nan() { 0. } else { t.clamp(0., 1.) };
+        let convert = alpha_converter(to.alpha, self.alpha);
+        if t == 0. {
+            return Ok(self.clone());
+        }
+        if t == 1. {
+            return Ok(self.derive(to.data.iter().map(|p| convert(*p)).collect(), self.res));
+        }
+
+        let (w, h) = self.res;
+        let at = |img: &Image, x: u32, y: u32| img.data[(y * w + x) as usize];
+        let from = |x, y| at(self, x, y);
+        let to_at = |x, y| convert(at(to, x, y));
+        // How far the edge has moved across `len`, in whole pixels
+        let moved = |len: u32| (t * len as f32).round() as u32;
+        let (mx, my) = (moved(w), moved(h));
+
+        let pixel = |x: u32, y: u32| -> WorkPixel {
+            match kind {
+                Transition::WipeLeft if x >= w - mx => to_at(x, y),
+                Transition::WipeRight if x < mx => to_at(x, y),
+                Transition::WipeUp if y >= h - my => to_at(x, y),
+                Transition::WipeDown if y < my => to_at(x, y),
+                Transition::SlideLeft if x < w - mx => from(x + mx, y),
+                Transition::SlideLeft => to_at(x - (w - mx), y),
+                Transition::SlideRight if x >= mx => from(x - mx, y),
+                Transition::SlideRight => to_at(x + (w - mx), y),
+                Transition::SlideUp if y < h - my => from(x, y + my),
+                Transition::SlideUp => to_at(x, y - (h - my)),
+                Transition::SlideDown if y >= my => from(x, y - my),
+                Transition::SlideDown => to_at(x, y + (h - my)),
+                _ => from(x, y),
+            }
+        };
+
+        let data = match kind {
+            Transition::CircleOpen => self.circle_open(to, t),
+            Transition::Pixelate if t < 0.5 => self.pixelated(block_size(self, t)),
+            Transition::Pixelate => {
+                let mut img = to.clone();
+                img.to_alpha_mode(self.alpha);
+                img.pixelated(block_size(self, t))
+            }
+            _ => (0..h)
+                .flat_map(|y| (0..w).map(move |x| (x, y)))
+                .map(|(x, y)| pixel(x, y))
+                .collect(),
+        };
+        Ok(self.derive(data, self.res))
+    }
+
+    /// Pixels of [`Transition::CircleOpen`] at `t`
+    fn circle_open(&self, to: &Image, t: f32) -> Vec<WorkPixel> {
+        let (w, h) = (self.res.0 as f32, self.res.1 as f32);
+        // Just past the corners at 1, so they're fully covered
+        let radius = t * ((w * w + h * h).sqrt() / 2. + 1.);
+        let transfer = self.color.transfer();
+        let (decode, encode) = (transfer.map(|t| t.0), transfer.map(|t| t.1));
+        let convert = alpha_converter(to.alpha, self.alpha);
+        let mut data = Vec::with_capacity(self.data.len());
+        for (i, (a, b)) in self.data.iter().zip(&to.data).enumerate() {
+            let (x, y) = (
+                (i as u32 % self.res.0) as f32,
+                (i as u32 / self.res.0) as f32,
+            );
+            let (dx, dy) = (x + 0.5 - w / 2., y + 0.5 - h / 2.);
+            let k = (radius - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0., 1.);
+            data.push(if k == 0. {
+                *a
+            } else if k == 1. {
+                convert(*b)
+            } else {
+                let a = to_linear_premul(*a, decode, self.alpha);
+                let b = to_linear_premul(*b, decode, to.alpha);
+                let p = core::array::from_fn(|c| a[c] + (b[c] - a[c]) * k);
+                from_linear_premul(p, encode, self.alpha)
+            });
+        }
+        data
+    }
+
+    /// Pixels averaged over `size` square blocks, in linear light
+    fn pixelated(&self, size: u32) -> Vec<WorkPixel> {
+        let (w, h) = self.res;
+        let transfer = self.color.transfer();
+        let (decode, encode) = (transfer.map(|t| t.0), transfer.map(|t| t.1));
+        let mut data = self.data.clone();
+        if size <= 1 {
+            return data;
+        }
+        for by in (0..h).step_by(size as usize) {
+            for bx in (0..w).step_by(size as usize) {
+                let (xs, ys) = (bx..(bx + size).min(w), by..(by + size).min(h));
+                let mut sum = [0f32; 4];
+                for y in ys.clone() {
+                    for x in xs.clone() {
+                        let p =
+                            to_linear_premul(self.data[(y * w + x) as usize], decode, self.alpha);
+                        sum = core::array::from_fn(|c| sum[c] + p[c]);
+                    }
+                }
+                let n = (xs.len() * ys.len()) as f32;
+                let p = from_linear_premul(sum.map(|c| c / n), encode, self.alpha);
+                for y in ys.clone() {
+                    for x in xs.clone() {
+                        data[(y * w + x) as usize] = p;
+                    }
+                }
+            }
+        }
+        data
+    }
+}
+
+/// Block size of [`Transition::Pixelate`] at `t`, largest halfway
+fn block_size(img: &Image, t: f32) -> u32 {
+    let peak = (img.res.0.max(img.res.1) as f32 * PIXELATE_PEAK).max(1.);
+    let ramp = 1. - (2. * t - 1.).abs();
+    ((peak * ramp).round() as u32).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{photo, ramp, solid},
+        AlphaMode, ColorSpace, ResXY,
+    };
+
+    const RES: ResXY = (20, 10);
+    const RED: WorkPixel = [1., 0., 0., 1.];
+    const BLUE: WorkPixel = [0., 0., 1., 1.];
+    const KINDS: [Transition; 10] = [
+        Transition::WipeLeft,
+        Transition::WipeRight,
+        Transition::WipeUp,
+        Transition::WipeDown,
+        Transition::SlideLeft,
+        Transition::SlideRight,
+        Transition::SlideUp,
+        Transition::SlideDown,
+        Transition::CircleOpen,
+        Transition::Pixelate,
+    ];
+
+    fn at(img: &Image, x: u32, y: u32) -> WorkPixel {
+        img.get_pixel((x, y)).unwrap()
+    }
+
+    #[test]
+    fn endpoints_are_exact() {
+        let (a, b) = (photo(RES), ramp(RES));
+        let mut premul = b.clone();
+        premul.to_alpha_mode(AlphaMode::Premultiplied);
+        for kind in KINDS {
+            for (t, want) in [(0., &a), (-1., &a), (f32::NAN, &a), (1., &b), (3., &b)] {
+                let frame = a.transition_frame(&b, kind, t).unwrap();
+                assert_eq!(frame.pixels(), want.pixels(), "{kind:?} {t}");
+            }
+            // In this image's alpha mode
+            let frame = a.transition_frame(&premul, kind, 1.).unwrap();
+            assert_eq!(frame.alpha, AlphaMode::Straight);
+            let worst = crate::fixtures::max_diff(frame.pixels(), b.pixels());
+            assert!(worst < 1e-6, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn wipes_are_sharp() {
+        let (a, b) = (solid(RES, RED), solid(RES, BLUE));
+        // Six columns and three rows in
+        let t = 0.3;
+        for (kind, is_new) in [
+            (
+                Transition::WipeRight,
+                (|x, _| x < 6) as fn(u32, u32) -> bool,
+            ),
+            (Transition::WipeLeft, |x, _| x >= 14),
+            (Transition::WipeDown, |_, y| y < 3),
+            (Transition::WipeUp, |_, y| y >= 7),
+        ] {
+            let frame = a.transition_frame(&b, kind, t).unwrap();
+            for y in 0..RES.1 {
+                for x in 0..RES.0 {
+                    let want = if is_new(x, y) { BLUE } else { RED };
+                    assert_eq!(at(&frame, x, y), want, "{kind:?} {x} {y}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn slides_move_both() {
+        let (a, b) = (photo(RES), ramp(RES));
+        let t = 0.3;
+        let frame = |kind| a.transition_frame(&b, kind, t).unwrap();
+        let (left, right) = (frame(Transition::SlideLeft), frame(Transition::SlideRight));
+        let (up, down) = (frame(Transition::SlideUp), frame(Transition::SlideDown));
+        for y in 0..RES.1 {
+            for x in 0..RES.0 {
+                let l = if x < 14 {
+                    at(&a, x + 6, y)
+                } else {
+                    at(&b, x - 14, y)
+                };
+                let r = if x >= 6 {
+                    at(&a, x - 6, y)
+                } else {
+                    at(&b, x + 14, y)
+                };
+                let u = if y < 7 {
+                    at(&a, x, y + 3)
+                } else {
+                    at(&b, x, y - 7)
+                };
+                let d = if y >= 3 {
+                    at(&a, x, y - 3)
+                } else {
+                    at(&b, x, y + 7)
+                };
+                assert_eq!(at(&left, x, y), l);
+                assert_eq!(at(&right, x, y), r);
+                assert_eq!(at(&up, x, y), u);
+                assert_eq!(at(&down, x, y), d);
+            }
+        }
+    }
+
+    #[test]
+    fn pixels_come_from_either() {
+        let (a, b) = (photo(RES), ramp(RES));
+        let sources: alloc::vec::Vec<WorkPixel> =
+            a.pixels().iter().chain(b.pixels()).copied().collect();
+        for kind in &KINDS[..8] {
+            for t in [0.1, 0.45, 0.5, 0.9] {
+                let frame = a.transition_frame(&b, *kind, t).unwrap();
+                assert!(
+                    frame.pixels().iter().all(|p| sources.contains(p)),
+                    "{kind:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn circle_has_an_anti_aliased_edge() {
+        let (a, b) = (solid(RES, RED), solid(RES, BLUE));
+        let t = 0.5;
+        let frame = a.transition_frame(&b, Transition::CircleOpen, t).unwrap();
+        let radius = t * ((20f32 * 20. + 10. * 10.).sqrt() / 2. + 1.);
+        let mut edge = 0;
+        for y in 0..RES.1 {
+            for x in 0..RES.0 {
+                let (dx, dy) = (x as f32 + 0.5 - 10., y as f32 + 0.5 - 5.);
+                let d = (dx * dx + dy * dy).sqrt();
+                let p = at(&frame, x, y);
+                if d < radius - 0.5 {
+                    assert_eq!(p, BLUE);
+                } else if d > radius + 0.5 {
+                    assert_eq!(p, RED);
+                } else {
+                    // A mix of the two, more blue further in
+                    assert!(p[1] == 0. && p[3] == 1.);
+                    edge += 1;
+                }
+            }
+        }
+        assert!(edge > 10);
+    }
+
+    #[test]
+    fn pixelate_peaks_halfway() {
+        let (a, b) = (photo((64, 32)), ramp((64, 32)));
+        assert_eq!(block_size(&a, 0.5), 4);
+        assert_eq!(block_size(&a, 0.25), 2);
+        assert_eq!(block_size(&a, 0.05), 1);
+        assert!(block_size(&a, 0.4) >= block_size(&a, 0.3));
+        for (t, source) in [(0.5, &b), (0.25, &a), (0.75, &b)] {
+            let size = block_size(&a, t);
+            let frame = a.transition_frame(&b, Transition::Pixelate, t).unwrap();
+            for y in 0..32 {
+                for x in 0..64 {
+                    assert_eq!(
+                        at(&frame, x, y),
+                        at(&frame, x / size * size, y / size * size)
+                    );
+                }
+            }
+            // Blocks average what they came from
+            let sum: f32 = (0..size * size)
+                .map(|i| at(source, i % size, i / size)[3])
+                .sum();
+            assert!((at(&frame, 0, 0)[3] - sum / (size * size) as f32).abs() < 1e-5);
+        }
+        // Small steps aren't pixelated at all
+        let frame = a.transition_frame(&b, Transition::Pixelate, 0.01).unwrap();
+        assert_eq!(frame.pixels(), a.pixels());
+    }
+
+    #[test]
+    fn errors() {
+        let a = photo(RES);
+        assert_eq!(
+            a.transition_frame(&photo((20, 11)), Transition::WipeLeft, 0.5)
+                .err(),
+            Some(ImageError::DimensionMismatch)
+        );
+        let mut linear = photo(RES);
+        linear.to_color(ColorSpace::sRGBLinear);
+        assert_eq!(
+            a.transition_frame(&linear, Transition::WipeLeft, 0.5).err(),
+            Some(ImageError::ColorSpaceMismatch)
+        );
+    }
+}
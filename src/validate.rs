@@ -0,0 +1,166 @@
+//! Guarding against non-finite pixel data
+use core::ops::RangeInclusive;
+
+use crate::Image;
+
+impl Image {
+    /// Replace non-finite values and clamp everything to `0..=1`, see
+    /// [`Image::sanitize_to`]
+    pub fn sanitize(&mut self) -> u32 {
+        self.sanitize_to(0.0..=1.)
+    }
+
+    /// Replace NaN with the start of `range`, infinities with its ends, and
+    /// clamp everything else to it
+    ///
+    /// Returns how many values were changed. Use this on data from outside,
+    /// like [`Image::from_f32_vec`], before it spreads through filters.
+    pub fn sanitize_to(&mut self, range: RangeInclusive<f32>) -> u32 {
+        let (lo, hi) = (*range.start(), *range.end());
+        let mut fixed = 0;
+        for c in self.data.as_flattened_mut() {
+            let v = if c.is_nan() { lo } else { c.clamp(lo, hi) };
+            if v.to_bits() != c.to_bits() {
+                *c = v;
+                fixed += 1;
+            }
+        }
+        fixed
+    }
+
+    /// Whether the number of pixels matches the resolution
+    ///
+    /// Every operation keeps this true, and checks it in debug builds. This
+    /// is for checking in release builds too, like after code that builds
+    /// images from untrusted sizes.
+    pub fn is_consistent(&self) -> bool {
+        (self.res.0 as usize).checked_mul(self.res.1 as usize) == Some(self.data.len())
+    }
+
+    /// Debug assert [`Image::is_consistent`]
+    #[track_caller]
+    #[inline]
+    pub(crate) fn debug_assert_consistent(&self) {
+        debug_assert!(
+            self.is_consistent(),
+            "{} pixels for a {}x{} image",
+            self.data.len(),
+            self.res.0,
+            self.res.1
+        );
+    }
+
+    /// Check the image invariants, the pixel count in debug builds, and
+    /// that pixels are finite with the `validate` feature too
+    ///
+    /// Operations call this on their result, so corrupt data is caught
+    /// where it's made rather than where it's exported.
+    #[track_caller]
+    #[inline]
+    pub(crate) fn check(&self) {
+        self.debug_assert_consistent();
+        #[cfg(all(feature = "validate", debug_assertions))]
+        {
+            if let Some(i) = self
+                .data
+                .iter()
+                .position(|p| !p.iter().all(|c| c.is_finite()))
+            {
+                let (x, y) = (i as u32 % self.res.0, i as u32 / self.res.0);
+                panic!("non-finite pixel {:?} at ({x}, {y})", self.data[i]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::solid, PixelFormat};
+
+    /// Every kind of bad value, and some fine ones to leave alone
+    fn poisoned() -> Image {
+        let mut img = solid((3, 1), [0.; 4]);
+        img.data = alloc::vec![
+            [f32::NAN, f32::INFINITY, f32::NEG_INFINITY, 0.5],
+            [1., f32::NAN, 0., f32::NAN],
+            [-0.5, 1.5, 0.25, -0.],
+        ];
+        img
+    }
+
+    #[test]
+    fn sanitize_counts() {
+        let mut img = poisoned();
+        assert_eq!(img.sanitize(), 7);
+        assert_eq!(
+            img.pixels(),
+            [[0., 1., 0., 0.5], [1., 0., 0., 0.], [0., 1., 0.25, -0.]]
+        );
+        assert_eq!(img.sanitize(), 0);
+
+        let mut img = poisoned();
+        assert_eq!(img.sanitize_to(-1.0..=2.), 5);
+        assert_eq!(
+            img.pixels(),
+            [
+                [-1., 2., -1., 0.5],
+                [1., -1., 0., -1.],
+                [-0.5, 1.5, 0.25, -0.]
+            ]
+        );
+    }
+
+    #[test]
+    fn export_is_defined() {
+        let img = poisoned();
+        assert_eq!(
+            img.to_raw(PixelFormat::Rgba8888),
+            [0, 255, 0, 128, 255, 0, 0, 0, 0, 255, 64, 0]
+        );
+        assert_eq!(img.to_bytes(), img.to_raw(PixelFormat::Rgba8888));
+        assert_eq!(
+            img.to_raw(PixelFormat::Rgba16)[..8],
+            [0, 0, 255, 255, 0, 0, 0, 128]
+        );
+        // Gray is NaN if any channel is
+        assert_eq!(img.to_raw(PixelFormat::Gray8), [0, 0, 255]);
+        assert_eq!(img.to_raw(PixelFormat::GrayAlpha8), [0, 128, 0, 0, 255, 0]);
+        assert_eq!(
+            img.to_raw(PixelFormat::Rgb565Le),
+            [0xe0, 0x07, 0x00, 0xf8, 0xe8, 0x07]
+        );
+    }
+
+    #[test]
+    fn consistency() {
+        let mut img = solid((3, 2), [0.; 4]);
+        assert!(img.is_consistent());
+        img.data.pop();
+        assert!(!img.is_consistent());
+    }
+
+    #[cfg(all(feature = "validate", debug_assertions))]
+    #[test]
+    #[should_panic = "non-finite pixel"]
+    fn validate_catches_nan() {
+        poisoned().transpose();
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic = "5 pixels for a 3x2 image"]
+    fn check_catches_inconsistency() {
+        let mut img = solid((3, 2), [0.; 4]);
+        img.data.pop();
+        img.check();
+    }
+
+    #[test]
+    fn consistency_of_empty_and_huge() {
+        assert!(solid((0, 5), [0.; 4]).is_consistent());
+        let mut img = solid((0, 0), [0.; 4]);
+        img.res = (u32::MAX, u32::MAX);
+        assert!(!img.is_consistent());
+    }
+}
@@ -0,0 +1,820 @@
+//! Checking color conversions against reference values, with the `verify`
+//! feature
+//!
+//! The reference values were computed offline in double precision, straight
+//! from the published transfer functions and primaries with the D65 white
+//! point, not with this crate. Conversions are run through
+//! [`Image::to_color`], so this checks the same code everything else uses.
+use alloc::vec::Vec;
+
+use crate::{convert::primaries, ColorSpace, Image, ImageError, TransferFunction};
+
+/// Largest error allowed in any channel of a conversion between different
+/// color spaces
+///
+/// About a quarter of a 16 bit step. Conversions to the same color space,
+/// or to or from [`ColorSpace::AsIs`], must be exact.
+pub const THRESHOLD: f32 = 4e-6;
+
+/// Largest error allowed when converting between primaries into a flat
+/// gamma, [`ColorSpace::SimplesRGB`] or [`ColorSpace::AdobeRgb`]
+///
+/// A flat gamma is infinitely steep at zero, so the rounding error of the
+/// matrix on channels that should be exactly zero, like the blue of Display
+/// P3 white, comes out around `1e-7.powf(1. / 2.2)`. That's still under a
+/// quarter of an 8 bit step.
+pub const GAMMA_THRESHOLD: f32 = 1e-3;
+
+/// How far one conversion was from the reference
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PairReport {
+    pub from: ColorSpace,
+    pub to: ColorSpace,
+    /// Largest difference of any channel of any color
+    pub max_error: f32,
+    /// Largest `max_error` that passes
+    pub threshold: f32,
+}
+
+impl PairReport {
+    pub fn passed(&self) -> bool {
+        self.max_error <= self.threshold
+    }
+}
+
+/// Results of [`run_conversion_checks`], one for every pair of color spaces
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VerifyReport {
+    pub pairs: Vec<PairReport>,
+}
+
+impl VerifyReport {
+    /// Whether every pair was within its threshold
+    pub fn passed(&self) -> bool {
+        self.pairs.iter().all(PairReport::passed)
+    }
+
+    /// The pairs that weren't
+    pub fn failures(&self) -> impl Iterator<Item = &PairReport> {
+        self.pairs.iter().filter(|p| !p.passed())
+    }
+}
+
+/// Convert the reference colors between every pair of color spaces and
+/// report how far each was from the reference values
+///
+/// # Errors
+///
+/// - [`ImageError::InvalidData`] if any pair was over its threshold, see
+///   [`conversion_report`] for the details
+pub fn run_conversion_checks() -> Result<VerifyReport, ImageError> {
+    let report = conversion_report();
+    if report.passed() {
+        Ok(report)
+    } else {
+        Err(ImageError::InvalidData)
+    }
+}
+
+/// [`run_conversion_checks`], returning the report whether or not it passed
+pub fn conversion_report() -> VerifyReport {
+    let pairs = ColorSpace::all()
+        .flat_map(|from| ColorSpace::all().map(move |to| (from, to)))
+        .map(|(from, to)| {
+            let (expected, threshold) = match reference(from, to) {
+                Some(values) => (values, threshold(from, to)),
+                None => (&INPUTS, 0.),
+            };
+            let data = INPUTS.iter().map(|[r, g, b]| [*r, *g, *b, 1.]).collect();
+            let mut img = Image::from_parts(data, (COLORS as u32, 1), from);
+            img.to_color(to);
+            let max_error = img
+                .data
+                .iter()
+                .zip(expected)
+                .flat_map(|(p, e)| {
+                    (0..3)
+                        .map(move |c| (p[c] - e[c]).abs())
+                        .chain([(p[3] - 1.).abs()])
+                })
+                .fold(0f32, f32::max);
+            PairReport {
+                from,
+                to,
+                max_error,
+                threshold,
+            }
+        })
+        .collect();
+    VerifyReport { pairs }
+}
+
+/// Threshold of converting from `from` to `to`, which are different
+fn threshold(from: ColorSpace, to: ColorSpace) -> f32 {
+    let gamma = matches!(to.transfer_function(), TransferFunction::Gamma(_));
+    if gamma && primaries(from) != primaries(to) {
+        GAMMA_THRESHOLD
+    } else {
+        THRESHOLD
+    }
+}
+
+/// Reference values of converting [`INPUTS`] from `from` to `to`, or `None`
+/// if that doesn't change them
+fn reference(from: ColorSpace, to: ColorSpace) -> Option<&'static [[f32; 3]; COLORS]> {
+    REFERENCE
+        .iter()
+        .find(|(f, t, _)| *f == from && *t == to)
+        .map(|(_, _, values)| values)
+}
+
+/// Colors per pair
+const COLORS: usize = 24;
+
+/// The colors converted, primaries, secondaries, grays, values on the linear
+/// segment of the sRGB curve, and a spread of others
+const INPUTS: [[f32; 3]; COLORS] = [
+    [0.0, 0.0, 0.0],
+    [1.0, 1.0, 1.0],
+    [0.5, 0.5, 0.5],
+    [0.18, 0.18, 0.18],
+    [1.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [1.0, 1.0, 0.0],
+    [0.0, 1.0, 1.0],
+    [1.0, 0.0, 1.0],
+    [0.01, 0.02, 0.03],
+    [0.003, 0.04, 0.05],
+    [0.2, 0.4, 0.8],
+    [0.9, 0.1, 0.3],
+    [0.25, 0.75, 0.5],
+    [0.6, 0.55, 0.1],
+    [0.33, 0.12, 0.66],
+    [0.05, 0.5, 0.95],
+    [0.8, 0.8, 0.2],
+    [0.45, 0.3, 0.15],
+    [0.7, 0.9, 0.95],
+    [0.15, 0.85, 0.35],
+    [0.95, 0.6, 0.75],
+    [0.4, 0.05, 0.2],
+];
+
+/// [`INPUTS`] converted between every pair of different color spaces,
+/// except [`ColorSpace::AsIs`]
+const REFERENCE: [(ColorSpace, ColorSpace, [[f32; 3]; COLORS]); 20] = [
+    (
+        ColorSpace::sRGB,
+        ColorSpace::sRGBLinear,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.21404114, 0.21404114, 0.21404114],
+            [0.027211782, 0.027211782, 0.027211782],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 1.0],
+            [1.0, 0.0, 1.0],
+            [0.0007739938, 0.0015479876, 0.0023219814],
+            [0.00023219814, 0.0030959751, 0.0039359396],
+            [0.033104766, 0.13286832, 0.60382736],
+            [0.7874123, 0.010022826, 0.073238954],
+            [0.05087609, 0.52252156, 0.21404114],
+            [0.31854677, 0.26327342, 0.010022826],
+            [0.088981524, 0.013411749, 0.39312312],
+            [0.0039359396, 0.21404114, 0.8900054],
+            [0.60382736, 0.60382736, 0.033104766],
+            [0.17064494, 0.073238954, 0.019606648],
+            [0.44798842, 0.7874123, 0.8900054],
+            [0.019606648, 0.6920711, 0.10048151],
+            [0.8900054, 0.31854677, 0.52252156],
+            [0.13286832, 0.0039359396, 0.033104766],
+        ],
+    ),
+    (
+        ColorSpace::sRGB,
+        ColorSpace::SimplesRGB,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.4962272, 0.4962272, 0.4962272],
+            [0.19432375, 0.19432375, 0.19432375],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 1.0],
+            [1.0, 0.0, 1.0],
+            [0.03852918, 0.0527985, 0.06348382],
+            [0.022290386, 0.07235248, 0.080693804],
+            [0.21243349, 0.39953536, 0.79508746],
+            [0.8970552, 0.123412505, 0.30477104],
+            [0.25825676, 0.7445015, 0.4962272],
+            [0.5945243, 0.54519093, 0.123412505],
+            [0.332973, 0.14088249, 0.6541763],
+            [0.080693804, 0.4962272, 0.94841117],
+            [0.79508746, 0.79508746, 0.21243349],
+            [0.44766337, 0.30477104, 0.16742477],
+            [0.69420046, 0.8970552, 0.94841117],
+            [0.16742477, 0.8459432, 0.35188666],
+            [0.94841117, 0.5945243, 0.7445015],
+            [0.39953536, 0.080693804, 0.21243349],
+        ],
+    ),
+    (
+        ColorSpace::sRGB,
+        ColorSpace::DisplayP3,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.5, 0.5, 0.5],
+            [0.18, 0.18, 0.18],
+            [0.91748756, 0.2002868, 0.1385606],
+            [0.4584016, 0.9852646, 0.2982947],
+            [7.172041e-16, -3.137768e-16, 0.95958805],
+            [1.0, 1.0, 0.33089733],
+            [0.4584016, 0.9852646, 0.992453],
+            [0.91748756, 0.2002868, 0.96747637],
+            [0.01177538, 0.019668058, 0.028934373],
+            [0.009568907, 0.038771816, 0.04860788],
+            [0.24985133, 0.39524007, 0.7735618],
+            [0.82631564, 0.2085381, 0.31495297],
+            [0.40247723, 0.73986095, 0.52058053],
+            [0.5915158, 0.5517532, 0.20167139],
+            [0.30465502, 0.13295966, 0.6346016],
+            [0.22444181, 0.49239212, 0.9191945],
+            [0.8, 0.8, 0.3211906],
+            [0.42800987, 0.30644795, 0.1758387],
+            [0.74076414, 0.89428234, 0.94291776],
+            [0.4086266, 0.8377212, 0.4126807],
+            [0.90057456, 0.6159782, 0.7445243],
+            [0.36552945, 0.087677464, 0.19868731],
+        ],
+    ),
+    (
+        ColorSpace::sRGB,
+        ColorSpace::AdobeRgb,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.4961037, 0.4961037, 0.4961037],
+            [0.1942107, 0.1942107, 0.1942107],
+            [0.8585916, -3.982783e-08, 1.5769423e-08],
+            [0.5649723, 1.0, 0.2344238],
+            [-5.5633645e-08, -2.59876e-08, 0.98106873],
+            [1.0, 1.0, 0.2344238],
+            [0.5649723, 1.0, 1.0],
+            [0.8585916, -4.627687e-08, 0.98106873],
+            [0.04313076, 0.05274336, 0.063024506],
+            [0.044171326, 0.072285004, 0.08029888],
+            [0.28143162, 0.39940515, 0.7833135],
+            [0.7719476, 0.12332082, 0.2996723],
+            [0.46454677, 0.74442345, 0.5092759],
+            [0.58086944, 0.54507345, 0.17054252],
+            [0.29345438, 0.14078444, 0.6421224],
+            [0.28609568, 0.4961037, 0.9347947],
+            [0.79502267, 0.79502267, 0.27094796],
+            [0.4128406, 0.30464244, 0.1756361],
+            [0.75861645, 0.8970206, 0.9463445],
+            [0.49307144, 0.84589297, 0.38823432],
+            [0.8651595, 0.59441453, 0.7389604],
+            [0.34476006, 0.08062168, 0.20877995],
+        ],
+    ),
+    (
+        ColorSpace::sRGBLinear,
+        ColorSpace::sRGB,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.735357, 0.735357, 0.735357],
+            [0.46135613, 0.46135613, 0.46135613],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 1.0],
+            [1.0, 0.0, 1.0],
+            [0.09985282, 0.15170372, 0.18974829],
+            [0.03876, 0.22091636, 0.24780053],
+            [0.4845292, 0.6651851, 0.9063318],
+            [0.9546872, 0.3491902, 0.5838315],
+            [0.5370987, 0.88082504, 0.735357],
+            [0.7977377, 0.76737565, 0.3491902],
+            [0.6097116, 0.38109186, 0.83228356],
+            [0.24780053, 0.735357, 0.9776916],
+            [0.9063318, 0.9063318, 0.4845292],
+            [0.7014107, 0.5838315, 0.42358288],
+            [0.8543058, 0.9546872, 0.9776916],
+            [0.42358288, 0.93092453, 0.6262097],
+            [0.9776916, 0.7977377, 0.88082504],
+            [0.6651851, 0.24780053, 0.4845292],
+        ],
+    ),
+    (
+        ColorSpace::sRGBLinear,
+        ColorSpace::SimplesRGB,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.72974, 0.72974, 0.72974],
+            [0.45865646, 0.45865646, 0.45865646],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 1.0],
+            [1.0, 0.0, 1.0],
+            [0.123284675, 0.16894327, 0.2031339],
+            [0.07132421, 0.23151158, 0.25622573],
+            [0.4811565, 0.6593533, 0.90354544],
+            [0.95323753, 0.35111916, 0.57853264],
+            [0.53252053, 0.8774243, 0.72974],
+            [0.79279274, 0.76204926, 0.35111916],
+            [0.60414714, 0.3814574, 0.82789356],
+            [0.25622573, 0.72974, 0.9769546],
+            [0.90354544, 0.90354544, 0.4811565],
+            [0.6956156, 0.57853264, 0.42217842],
+            [0.85033494, 0.95323753, 0.9769546],
+            [0.42217842, 0.92879033, 0.62052345],
+            [0.9769546, 0.79279274, 0.8774243],
+            [0.6593533, 0.25622573, 0.4811565],
+        ],
+    ),
+    (
+        ColorSpace::sRGBLinear,
+        ColorSpace::DisplayP3,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.735357, 0.735357, 0.735357],
+            [0.46135613, 0.46135613, 0.46135613],
+            [0.91748756, 0.2002868, 0.1385606],
+            [0.4584016, 0.9852646, 0.2982947],
+            [7.172041e-16, -3.137768e-16, 0.95958805],
+            [1.0, 1.0, 0.33089733],
+            [0.4584016, 0.9852646, 0.992453],
+            [0.91748756, 0.2002868, 0.96747637],
+            [0.11076469, 0.15026729, 0.18608767],
+            [0.097035535, 0.21735427, 0.24391288],
+            [0.52254766, 0.66018033, 0.88641214],
+            [0.88495564, 0.39086428, 0.58006287],
+            [0.61701506, 0.8721398, 0.7443926],
+            [0.7924582, 0.7684088, 0.4115676],
+            [0.5773232, 0.39147353, 0.806713],
+            [0.3957253, 0.7254317, 0.9556433],
+            [0.9063318, 0.9063318, 0.54072267],
+            [0.6824267, 0.5882281, 0.4442068],
+            [0.8732475, 0.9515772, 0.974109],
+            [0.56040984, 0.9196038, 0.6521025],
+            [0.94899064, 0.8045792, 0.87694407],
+            [0.6162645, 0.27534205, 0.4760704],
+        ],
+    ),
+    (
+        ColorSpace::sRGBLinear,
+        ColorSpace::AdobeRgb,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.72965837, 0.72965837, 0.72965837],
+            [0.45852947, 0.45852947, 0.45852947],
+            [0.8585916, -3.982783e-08, 1.5769423e-08],
+            [0.5649723, 1.0, 0.2344238],
+            [-5.5633645e-08, -2.59876e-08, 0.98106873],
+            [1.0, 1.0, 0.2344238],
+            [0.5649723, 1.0, 1.0],
+            [0.8585916, -4.627687e-08, 0.98106873],
+            [0.13806571, 0.1688366, 0.20174752],
+            [0.14139667, 0.23139128, 0.255141],
+            [0.5391048, 0.65925574, 0.8950096],
+            [0.83470523, 0.35098866, 0.571148],
+            [0.6535585, 0.87738353, 0.7364488],
+            [0.7841141, 0.7619757, 0.37918526],
+            [0.5515262, 0.3813268, 0.8150424],
+            [0.45643118, 0.72965837, 0.9682386],
+            [0.9035129, 0.9035129, 0.50718564],
+            [0.6646764, 0.57842016, 0.42986163],
+            [0.8810844, 0.9532213, 0.9759835],
+            [0.61994416, 0.92876595, 0.63674885],
+            [0.92891204, 0.79272735, 0.8740918],
+            [0.5786778, 0.25610182, 0.47422126],
+        ],
+    ),
+    (
+        ColorSpace::SimplesRGB,
+        ColorSpace::sRGB,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.5038668, 0.5038668, 0.5038668],
+            [0.16407135, 0.16407135, 0.16407135],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 1.0],
+            [1.0, 0.0, 1.0],
+            [0.00051435444, 0.0023633526, 0.0057667256],
+            [3.6385576e-05, 0.010859117, 0.017741753],
+            [0.1862848, 0.40048504, 0.8048413],
+            [0.90287334, 0.07281617, 0.2949024],
+            [0.24104936, 0.7554482, 0.5038668],
+            [0.6055278, 0.5548901, 0.07281617],
+            [0.32684776, 0.096066654, 0.6658326],
+            [0.017741753, 0.5038668, 0.9515432],
+            [0.8048413, 0.8048413, 0.1862848],
+            [0.45241573, 0.2949024, 0.13035434],
+            [0.70577985, 0.90287334, 0.9515432],
+            [0.13035434, 0.8539775, 0.34800908],
+            [0.9515432, 0.6055278, 0.7554482],
+            [0.40048504, 0.017741753, 0.1862848],
+        ],
+    ),
+    (
+        ColorSpace::SimplesRGB,
+        ColorSpace::sRGBLinear,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.21763764, 0.21763764, 0.21763764],
+            [0.022993205, 0.022993205, 0.022993205],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 1.0],
+            [1.0, 0.0, 1.0],
+            [3.981072e-05, 0.00018292203, 0.00044634097],
+            [2.8162212e-06, 0.0008404889, 0.0013732007],
+            [0.028991187, 0.13320851, 0.6120656],
+            [0.7931102, 0.0063095735, 0.070740275],
+            [0.047366142, 0.53104925, 0.21763764],
+            [0.32503697, 0.26840952, 0.0063095735],
+            [0.08724301, 0.0094232075, 0.40086365],
+            [0.0013732007, 0.21763764, 0.8932889],
+            [0.6120656, 0.6120656, 0.028991187],
+            [0.17261063, 0.070740275, 0.0153957475],
+            [0.45626345, 0.7931102, 0.8932889],
+            [0.0153957475, 0.6993936, 0.0993001],
+            [0.8932889, 0.32503697, 0.53104925],
+            [0.13320851, 0.0013732007, 0.028991187],
+        ],
+    ),
+    (
+        ColorSpace::SimplesRGB,
+        ColorSpace::DisplayP3,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.5038668, 0.5038668, 0.5038668],
+            [0.16407135, 0.16407135, 0.16407135],
+            [0.91748756, 0.2002868, 0.1385606],
+            [0.4584016, 0.9852646, 0.2982947],
+            [7.172041e-16, -3.137768e-16, 0.95958805],
+            [1.0, 1.0, 0.33089733],
+            [0.4584016, 0.9852646, 0.992453],
+            [0.91748756, 0.2002868, 0.96747637],
+            [0.00084262196, 0.0023019766, 0.0054306057],
+            [0.001957832, 0.010499865, 0.016941013],
+            [0.24138136, 0.39551848, 0.77814037],
+            [0.8285887, 0.19781089, 0.3102472],
+            [0.40052745, 0.7451473, 0.5244963],
+            [0.59693706, 0.55666614, 0.19221117],
+            [0.30037957, 0.112111904, 0.6399372],
+            [0.22024949, 0.4961107, 0.9207685],
+            [0.8048413, 0.8048413, 0.315529],
+            [0.429546, 0.30177677, 0.16043647],
+            [0.7458414, 0.8972233, 0.9445996],
+            [0.40560624, 0.8415638, 0.4118318],
+            [0.9025172, 0.6212371, 0.7498722],
+            [0.3652502, 0.067959145, 0.1855231],
+        ],
+    ),
+    (
+        ColorSpace::SimplesRGB,
+        ColorSpace::AdobeRgb,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.4998769, 0.4998769, 0.4998769],
+            [0.17989038, 0.17989038, 0.17989038],
+            [0.8585916, -3.982783e-08, 1.5769423e-08],
+            [0.5649723, 1.0, 0.2344238],
+            [-5.5633645e-08, -2.59876e-08, 0.98106873],
+            [1.0, 1.0, 0.2344238],
+            [0.5649723, 1.0, 1.0],
+            [0.8585916, -4.627687e-08, 0.98106873],
+            [0.013757258, 0.019972226, 0.029629463],
+            [0.022659201, 0.039954286, 0.04958257],
+            [0.2754381, 0.39986983, 0.78811836],
+            [0.77381605, 0.09991824, 0.29470646],
+            [0.4644547, 0.74992335, 0.5131388],
+            [0.5861652, 0.5498832, 0.15722057],
+            [0.2887012, 0.11990965, 0.6477068],
+            [0.28444186, 0.4998769, 0.9364182],
+            [0.7999366, 0.7999366, 0.26295918],
+            [0.41375098, 0.2998717, 0.159606],
+            [0.7633742, 0.8999663, 0.9479862],
+            [0.49208784, 0.8499509, 0.38705647],
+            [0.86742634, 0.5998911, 0.7444544],
+            [0.34396523, 0.04994682, 0.19628282],
+        ],
+    ),
+    (
+        ColorSpace::DisplayP3,
+        ColorSpace::sRGB,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.5, 0.5, 0.5],
+            [0.18, 0.18, 0.18],
+            [1.0930663, -0.22674197, -0.15013458],
+            [-0.51160496, 1.0182656, -0.3106746],
+            [-1.4344082e-15, 2.6895152e-16, 1.0420216],
+            [1.0, 1.0, -0.34626797],
+            [-0.51160496, 1.0182656, 1.0085834],
+            [1.0930663, -0.22674197, 1.0338056],
+            [0.0077505982, 0.02042057, 0.031179111],
+            [-0.0053227865, 0.041528955, 0.05152708],
+            [0.1040565, 0.4059325, 0.8278255],
+            [0.9832321, -0.16279021, 0.281009],
+            [-0.26058227, 0.7625949, 0.47656503],
+            [0.61053336, 0.5477684, -0.13310827],
+            [0.3590913, 0.10134937, 0.68646485],
+            [-0.23025124, 0.50943434, 0.9824595],
+            [0.8, 0.8, -0.16402765],
+            [0.4760687, 0.2915872, 0.11450518],
+            [0.6434511, 0.90717614, 0.957837],
+            [-0.3982679, 0.8652299, 0.2613751],
+            [1.0081103, 0.5789368, 0.7556362],
+            [0.43901357, -0.019206405, 0.20107079],
+        ],
+    ),
+    (
+        ColorSpace::DisplayP3,
+        ColorSpace::sRGBLinear,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.21404114, 0.21404114, 0.21404114],
+            [0.027211782, 0.027211782, 0.027211782],
+            [1.2249402, -0.042056955, -0.019637555],
+            [-0.22494018, 1.0420569, -0.07863604],
+            [-1.110223e-16, 2.0816682e-17, 1.0982736],
+            [1.0, 1.0, -0.0982736],
+            [-0.22494018, 1.0420569, 1.0196376],
+            [1.2249402, -0.042056955, 1.078636],
+            [0.00059989153, 0.0015805394, 0.002413244],
+            [-0.00041198038, 0.003216417, 0.0040747235],
+            [0.010663935, 0.13706407, 0.6520693],
+            [0.9622784, -0.022671808, 0.0641854],
+            [-0.055215925, 0.5423575, 0.19298762],
+            [0.33097997, 0.26094878, -0.015950454],
+            [0.1059802, 0.010233514, 0.42895472],
+            [-0.04332516, 0.22287753, 0.9605608],
+            [0.60382736, 0.60382736, -0.022982195],
+            [0.19255546, 0.06914236, 0.012423193],
+            [0.37163836, 0.8016874, 0.90675306],
+            [-0.13165762, 0.7203529, 0.05554943],
+            [1.0185494, 0.29451296, 0.53134483],
+            [0.16187039, -0.0014865638, 0.033439375],
+        ],
+    ),
+    (
+        ColorSpace::DisplayP3,
+        ColorSpace::SimplesRGB,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.4962272, 0.4962272, 0.4962272],
+            [0.19432375, 0.19432375, 0.19432375],
+            [1.0966101, -0.2368491, -0.16754468],
+            [-0.5075572, 1.0189022, -0.31478196],
+            [-5.5964644e-08, 2.6149282e-08, 1.0435296],
+            [1.0, 1.0, -0.34835076],
+            [-0.5075572, 1.0189022, 1.0088788],
+            [1.0966101, -0.2368491, 1.0350066],
+            [0.034315284, 0.053300306, 0.06460606],
+            [-0.028927274, 0.073618576, 0.081974916],
+            [0.1269401, 0.40522158, 0.8233568],
+            [0.98267394, -0.17885196, 0.28702888],
+            [-0.26804706, 0.7572177, 0.47341353],
+            [0.60496193, 0.54299754, -0.15243289],
+            [0.36051252, 0.12458502, 0.68063504],
+            [-0.2400692, 0.50543636, 0.98187625],
+            [0.79508746, 0.79508746, -0.17996082],
+            [0.47293136, 0.29690054, 0.13606374],
+            [0.63767624, 0.9044112, 0.95648205],
+            [-0.39787638, 0.86148524, 0.26878178],
+            [1.0083894, 0.57369864, 0.7501897],
+            [0.4370495, -0.05183569, 0.2134068],
+        ],
+    ),
+    (
+        ColorSpace::DisplayP3,
+        ColorSpace::AdobeRgb,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.4961037, 0.4961037, 0.4961037],
+            [0.1942107, 0.1942107, 0.1942107],
+            [0.9356933, -0.23672794, -0.17097144],
+            [0.4036516, 1.018909, -0.21056217],
+            [-5.5633645e-08, -2.9619427e-08, 1.0237899],
+            [1.0, 1.0, -0.263128],
+            [0.4036516, 1.018909, 1.0092971],
+            [0.9356933, -0.23672794, 1.0146519],
+            [0.040781897, 0.05324482, 0.064124756],
+            [0.034834165, 0.07355038, 0.08157844],
+            [0.24820535, 0.40509155, 0.81101984],
+            [0.8401004, -0.17874263, 0.2795214],
+            [0.37404406, 0.7571429, 0.4890104],
+            [0.587995, 0.54287976, -0.08613915],
+            [0.31477714, 0.124492876, 0.66796935],
+            [0.2105711, 0.5053139, 0.96763295],
+            [0.79502267, 0.79502267, 0.06926375],
+            [0.4313902, 0.29677248, 0.1470423],
+            [0.72576296, 0.90437895, 0.9543899],
+            [0.36813366, 0.86143965, 0.32232404],
+            [0.9097981, 0.5735854, 0.7438238],
+            [0.37451208, -0.05178122, 0.20907022],
+        ],
+    ),
+    (
+        ColorSpace::AdobeRgb,
+        ColorSpace::sRGB,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.5039929, 0.5039929, 0.5039929],
+            [0.16419367, 0.16419367, 0.16419367],
+            [1.1581835, -3.8101466e-16, -4.4825255e-17],
+            [-0.6639501, 1.0, -0.2291615],
+            [0.0, -1.7930102e-16, 1.0186398],
+            [1.0, 1.0, -0.2291615],
+            [-0.6639501, 1.0, 1.0],
+            [1.1581835, -5.603157e-16, 1.0186398],
+            [-0.0002224939, 0.0023705866, 0.005929017],
+            [-0.004285572, 0.010886459, 0.0180794],
+            [-0.115005486, 0.4006209, 0.8168197],
+            [1.0454891, 0.07291201, 0.3006781],
+            [-0.4172598, 0.75552416, 0.48933366],
+            [0.6243623, 0.5550088, -0.060384065],
+            [0.37857184, 0.096170954, 0.67837125],
+            [-0.3223916, 0.5039929, 0.96504897],
+            [0.80490375, 0.80490375, 0.050640985],
+            [0.4992151, 0.29503956, 0.11796684],
+            [0.6031454, 0.9029062, 0.9535757],
+            [-0.5440307, 0.8540256, 0.30076814],
+            [1.0508698, 0.60563767, 0.7611198],
+            [0.46829265, 0.017783325, 0.1904766],
+        ],
+    ),
+    (
+        ColorSpace::AdobeRgb,
+        ColorSpace::sRGBLinear,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.21775553, 0.21775553, 0.21775553],
+            [0.02302403, 0.02302403, 0.02302403],
+            [1.3983557, -2.94903e-17, -3.469447e-18],
+            [-0.39835575, 1.0, -0.04292899],
+            [0.0, -1.3877788e-17, 1.0429289],
+            [1.0, 1.0, -0.04292899],
+            [-0.39835575, 1.0, 1.0],
+            [1.3983557, -4.3368087e-17, 1.0429289],
+            [-1.722089e-05, 0.00018348194, 0.00045890224],
+            [-0.0003317006, 0.0008426052, 0.0013993344],
+            [-0.012511378, 0.13330391, 0.63272965],
+            [1.1066235, 0.006320934, 0.07357516],
+            [-0.14528757, 0.5311686, 0.20430103],
+            [0.34772632, 0.2685349, -0.0049356474],
+            [0.118342474, 0.0094388295, 0.41780284],
+            [-0.08481944, 0.21775553, 0.9223262],
+            [0.6121723, 0.6121723, 0.0039938516],
+            [0.21331537, 0.070806846, 0.01304082],
+            [0.32223043, 0.79317546, 0.897624],
+            [-0.25708216, 0.6994824, 0.07361986],
+            [1.1196537, 0.3251667, 0.54001206],
+            [0.18585798, 0.0013764183, 0.030214703],
+        ],
+    ),
+    (
+        ColorSpace::AdobeRgb,
+        ColorSpace::SimplesRGB,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.5001231, 0.5001231, 0.5001231],
+            [0.18010965, 0.18010965, 0.18010965],
+            [1.1646351, -3.0634993e-08, -1.1581229e-08],
+            [-0.6581199, 1.0, -0.23906887],
+            [0.0, -2.1747947e-08, 1.0192896],
+            [1.0, 1.0, -0.23906887],
+            [-0.6581199, 1.0, 1.0],
+            [1.1646351, -3.650481e-08, 1.0192896],
+            [-0.00683236, 0.020027803, 0.030380862],
+            [-0.02621326, 0.04004575, 0.050430305],
+            [-0.13650191, 0.40013018, 0.8121657],
+            [1.0471284, 0.1000818, 0.30540618],
+            [-0.41609716, 0.75007665, 0.48583254],
+            [0.61868787, 0.5501168, -0.08943758],
+            [0.3790533, 0.12009039, 0.67253405],
+            [-0.32580104, 0.5001231, 0.96391433],
+            [0.8000634, 0.8000634, 0.08123134],
+            [0.49546167, 0.30012828, 0.13909785],
+            [0.59763956, 0.90003365, 0.9520928],
+            [-0.5393254, 0.8500491, 0.3054905],
+            [1.052715, 0.60010886, 0.75572747],
+            [0.46538207, 0.05005322, 0.2037934],
+        ],
+    ),
+    (
+        ColorSpace::AdobeRgb,
+        ColorSpace::DisplayP3,
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [0.5039929, 0.5039929, 0.5039929],
+            [0.16419367, 0.16419367, 0.16419367],
+            [1.0632994, 0.23856373, 0.16758248],
+            [-0.42370838, 0.97931254, 0.17743643],
+            [7.172041e-16, -3.5860204e-16, 0.97751385],
+            [1.0, 1.0, 0.24878836],
+            [-0.42370838, 0.97931254, 0.9894253],
+            [1.0632994, 0.23856373, 0.9882576],
+            [0.0002378765, 0.0022845115, 0.0055663115],
+            [-0.0015919595, 0.010382836, 0.017176596],
+            [0.11980756, 0.3936534, 0.7891199],
+            [0.9599399, 0.2289286, 0.32522038],
+            [-0.17256413, 0.74106747, 0.5085004],
+            [0.6127792, 0.5574898, 0.15547647],
+            [0.3475146, 0.118038535, 0.6523645],
+            [-0.19345118, 0.49310187, 0.93290925],
+            [0.80490375, 0.80490375, 0.2680727],
+            [0.47081426, 0.30460006, 0.15445201],
+            [0.6695485, 0.89499414, 0.94538724],
+            [-0.32687065, 0.8365988, 0.37074736],
+            [0.9905344, 0.6274562, 0.75754946],
+            [0.42768663, 0.08236136, 0.1923989],
+        ],
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions_match_the_reference() {
+        let report = run_conversion_checks()
+            .unwrap_or_else(|_| panic!("{:?}", conversion_report().failures().collect::<Vec<_>>()));
+        let n = ColorSpace::all().count();
+        assert_eq!(report.pairs.len(), n * n);
+        assert_eq!(report.failures().count(), 0);
+    }
+
+    #[test]
+    fn every_pair_has_vectors() {
+        for from in ColorSpace::all() {
+            for to in ColorSpace::all() {
+                let changes = from != to && from != ColorSpace::AsIs && to != ColorSpace::AsIs;
+                assert_eq!(reference(from, to).is_some(), changes, "{from:?} {to:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn unchanged_pairs_are_exact() {
+        for pair in conversion_report().pairs {
+            if reference(pair.from, pair.to).is_none() {
+                assert_eq!((pair.max_error, pair.threshold), (0., 0.), "{pair:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn failures_are_reported() {
+        let pair = PairReport {
+            from: ColorSpace::sRGB,
+            to: ColorSpace::DisplayP3,
+            max_error: THRESHOLD * 2.,
+            threshold: THRESHOLD,
+        };
+        let ok = PairReport {
+            max_error: THRESHOLD,
+            ..pair
+        };
+        let report = VerifyReport {
+            pairs: alloc::vec![ok, pair],
+        };
+        assert!(ok.passed() && !pair.passed());
+        assert!(!report.passed());
+        assert_eq!(report.failures().copied().collect::<Vec<_>>(), [pair]);
+        assert!(VerifyReport::default().passed());
+    }
+}
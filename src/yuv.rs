@@ -0,0 +1,191 @@
+//! Y'CbCr output
+use alloc::vec::Vec;
+
+use crate::{transforms::*, AlphaMode, ColorSpace, Image, ImageError, Transfer, WorkPixel, F32};
+
+/// Y'CbCr matrix coefficients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YuvStandard {
+    /// ITU-R BT.601, standard definition video and JPEG
+    Bt601,
+
+    /// ITU-R BT.709, high definition video
+    Bt709,
+}
+
+impl YuvStandard {
+    /// `(Kr, Kb)`
+    fn coefficients(self) -> (f32, f32) {
+        match self {
+            YuvStandard::Bt601 => (0.299, 0.114),
+            YuvStandard::Bt709 => (0.2126, 0.0722),
+        }
+    }
+}
+
+/// Y'CbCr code value range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YuvRange {
+    /// `0..=255` for all planes, as used by JPEG
+    Full,
+
+    /// `16..=235` for luma and `16..=240` for chroma, as used by most video
+    Limited,
+}
+
+/// Y, Cb, and Cr planes
+type I420 = (Vec<u8>, Vec<u8>, Vec<u8>);
+
+fn identity(c: f32) -> f32 {
+    c
+}
+
+impl Image {
+    /// Export the image as planar Y'CbCr 4:2:0, I420, returning the Y, Cb, and
+    /// Cr planes
+    ///
+    /// Y' is computed from the gamma encoded values, with
+    /// [`ColorSpace::sRGBLinear`] encoded to sRGB first. Chroma is sited in
+    /// the center of each 2x2 block, and computed from the block's average in
+    /// linear light, which avoids the dark fringes of averaging encoded values.
+    ///
+    /// Alpha is ignored.
+    ///
+    /// # Errors
+    ///
+    /// - [`ImageError::DimensionMismatch`] if either dimension is odd
+    pub fn to_i420(&self, standard: YuvStandard, range: YuvRange) -> Result<I420, ImageError> {
+        let (w, h) = (self.width() as usize, self.height() as usize);
+        if w % 2 != 0 || h % 2 != 0 {
+            return Err(ImageError::DimensionMismatch);
+        }
+        // Stored to linear, and linear to encoded
+        let (decode, encode): (Transfer, Transfer) = match self.color {
+            ColorSpace::sRGBLinear => (identity, rgb_to_srgb),
+            c => c.transfer().unwrap_or((identity, identity)),
+        };
+        let linear = self.color == ColorSpace::sRGBLinear;
+        let straight = |p: WorkPixel| match self.alpha {
+            AlphaMode::Straight => p,
+            AlphaMode::Premultiplied => unpremultiply(p),
+        };
+
+        let (kr, kb) = standard.coefficients();
+        let (y_scale, y_offset, c_scale) = match range {
+            YuvRange::Full => (255., 0., 255.),
+            YuvRange::Limited => (219., 16., 224.),
+        };
+        let code = |v: f32| v.round().clamp(0., 255.) as u8;
+        let luma = |[r, g, b]: [f32; 3]| kr * r + (1. - kr - kb) * g + kb * b;
+
+        let y_plane = self
+            .data
+            .iter()
+            .map(|p| {
+                let p = straight(*p);
+                let mut rgb = [p[0], p[1], p[2]];
+                if linear {
+                    rgb = rgb.map(encode);
+                }
+                code(luma(rgb) * y_scale + y_offset)
+            })
+            .collect();
+
+        let mut cb_plane = Vec::with_capacity(w * h / 4);
+        let mut cr_plane = Vec::with_capacity(w * h / 4);
+        for by in (0..h).step_by(2) {
+            for bx in (0..w).step_by(2) {
+                let mut sum = [0.; 3];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let p = straight(self.data[(by + dy) * w + bx + dx]);
+                    for c in 0..3 {
+                        sum[c] += decode(p[c]);
+                    }
+                }
+                let rgb = sum.map(|c| encode(c / 4.));
+                let y = luma(rgb);
+                let cb = (rgb[2] - y) / (2. * (1. - kb));
+                let cr = (rgb[0] - y) / (2. * (1. - kr));
+                cb_plane.push(code(cb * c_scale + 128.));
+                cr_plane.push(code(cr * c_scale + 128.));
+            }
+        }
+        Ok((y_plane, cb_plane, cr_plane))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::solid;
+
+    fn planes(p: WorkPixel, standard: YuvStandard, range: YuvRange) -> [u8; 3] {
+        let (y, cb, cr) = solid((4, 2), p).to_i420(standard, range).unwrap();
+        for plane in [&y, &cb, &cr] {
+            assert!(plane.iter().all(|c| *c == plane[0]), "{plane:?}");
+        }
+        [y[0], cb[0], cr[0]]
+    }
+
+    #[test]
+    fn published_code_values() {
+        use YuvRange::*;
+        use YuvStandard::*;
+        let (white, black) = ([1.; 4], [0., 0., 0., 1.]);
+        let (red, green, blue) = ([1., 0., 0., 1.], [0., 1., 0., 1.], [0., 0., 1., 1.]);
+        let cases = [
+            (white, Bt601, Full, [255, 128, 128]),
+            (black, Bt601, Full, [0, 128, 128]),
+            (red, Bt601, Full, [76, 85, 255]),
+            (white, Bt601, Limited, [235, 128, 128]),
+            (black, Bt601, Limited, [16, 128, 128]),
+            (red, Bt601, Limited, [81, 90, 240]),
+            (green, Bt601, Limited, [145, 54, 34]),
+            (blue, Bt601, Limited, [41, 240, 110]),
+            (red, Bt709, Limited, [63, 102, 240]),
+            (green, Bt709, Limited, [173, 42, 26]),
+            (blue, Bt709, Limited, [32, 240, 118]),
+            (white, Bt709, Limited, [235, 128, 128]),
+        ];
+        for (p, standard, range, want) in cases {
+            assert_eq!(
+                planes(p, standard, range),
+                want,
+                "{p:?} {standard:?} {range:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn plane_sizes() {
+        let (y, cb, cr) = solid((6, 4), [0.5; 4])
+            .to_i420(YuvStandard::Bt709, YuvRange::Full)
+            .unwrap();
+        assert_eq!((y.len(), cb.len(), cr.len()), (24, 6, 6));
+    }
+
+    #[test]
+    fn odd_dimensions() {
+        for res in [(5, 4), (4, 3), (1, 1)] {
+            assert_eq!(
+                solid(res, [0.5; 4])
+                    .to_i420(YuvStandard::Bt601, YuvRange::Full)
+                    .err(),
+                Some(ImageError::DimensionMismatch)
+            );
+        }
+    }
+
+    #[test]
+    fn chroma_averaged_in_linear_light() {
+        // Red and green, averaged to linear yellow
+        let mut img = solid((2, 2), [1., 0., 0., 1.]);
+        img.data[1] = [0., 1., 0., 1.];
+        img.data[2] = [0., 1., 0., 1.];
+        let (_, cb, _) = img.to_i420(YuvStandard::Bt601, YuvRange::Full).unwrap();
+        // Averaging the encoded values would give 64
+        let e = rgb_to_srgb(0.5);
+        assert_eq!(cb[0], (128. - e * 0.5 * 255.).round() as u8);
+        assert_eq!(cb[0], 34);
+    }
+}
@@ -0,0 +1,36 @@
+//! `include_image!` on the checked in assets, against the runtime decoders
+#![cfg(feature = "macros")]
+use embedded_image::{
+    formats::{bmp, qoi},
+    include_image, ColorSpace, StaticImage,
+};
+
+const QOI: StaticImage = include_image!("assets/corners.qoi");
+const BMP: StaticImage = include_image!("assets/corners.bmp");
+
+fn pixel(img: &StaticImage, (x, y): (u32, u32)) -> &[u8] {
+    let i = (y * img.width() + x) as usize * 4;
+    &img.data[i..i + 4]
+}
+
+#[test]
+fn dimensions_and_corners() {
+    for img in [QOI, BMP] {
+        assert_eq!(img.res, (7, 5));
+        assert_eq!(img.color, ColorSpace::sRGB);
+        assert_eq!(img.data.len(), 7 * 5 * 4);
+        assert_eq!(pixel(&img, (0, 0)), [255, 0, 0, 255]);
+        assert_eq!(pixel(&img, (6, 0)), [0, 255, 0, 255]);
+        assert_eq!(pixel(&img, (0, 4)), [0, 0, 255, 255]);
+        assert_eq!(pixel(&img, (6, 4)), [10, 20, 30, 128]);
+    }
+}
+
+#[test]
+fn matches_runtime_decoders() {
+    let qoi = qoi::decode(include_bytes!("../assets/corners.qoi")).unwrap();
+    let bmp = bmp::decode(include_bytes!("../assets/corners.bmp")).unwrap();
+    assert_eq!(QOI.to_image().to_bytes(), qoi.to_bytes());
+    assert_eq!(BMP.to_image().to_bytes(), bmp.to_bytes());
+    assert_eq!(QOI.as_image_ref().data(), QOI.data);
+}
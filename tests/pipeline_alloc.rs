@@ -0,0 +1,62 @@
+//! `Pipeline::run` doesn't allocate once warmed up
+//!
+//! This is its own test binary, so the counting allocator only sees this
+//! test.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use embedded_image::{ColorSpace, Image, Pipeline, PixelFormat, ScaleFilter};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        // Safety: Forwarded as is
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Safety: Forwarded as is
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        // Safety: Forwarded as is
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[test]
+fn run_does_not_allocate() {
+    let data: Vec<u8> = (0..64 * 48 * 4).map(|i| (i * 7) as u8).collect();
+    let img = Image::from_bytes(&data, (64, 48), ColorSpace::sRGB);
+    let src = img.to_raw(PixelFormat::Rgba8888);
+    let mut dst = vec![0; 20 * 15 * 2];
+    for filter in [
+        ScaleFilter::Nearest,
+        ScaleFilter::Box,
+        ScaleFilter::MITCHELL,
+    ] {
+        let mut p = Pipeline::new(PixelFormat::Rgba8888, ColorSpace::sRGB)
+            .linearize()
+            .scale((20, 15), filter)
+            .encode(ColorSpace::sRGB)
+            .output(PixelFormat::Rgb565Le);
+        // Warm up the scratch buffers
+        p.run(&src, (64, 48), &mut dst).unwrap();
+        let before = ALLOCATIONS.load(Ordering::SeqCst);
+        for _ in 0..5 {
+            p.run(&src, (64, 48), &mut dst).unwrap();
+        }
+        assert_eq!(ALLOCATIONS.load(Ordering::SeqCst), before, "{filter:?}");
+    }
+}
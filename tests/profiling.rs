@@ -0,0 +1,73 @@
+//! The heavy operations record how long they took, with the `profiling`
+//! feature
+//!
+//! This is its own test binary, as the time source and report are global.
+#![cfg(feature = "profiling")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use embedded_image::{
+    set_time_source, take_profile, ColorSpace, Image, PixelFormat, ProfileOp, ScaleFilter,
+    PROFILE_CAPACITY,
+};
+
+/// Ticks by 10 every time it's read
+static CLOCK: AtomicU64 = AtomicU64::new(1000);
+
+fn now() -> u64 {
+    CLOCK.fetch_add(10, Ordering::SeqCst)
+}
+
+fn image() -> Image {
+    let data: Vec<u8> = (0..32 * 24 * 4).map(|i| (i * 7) as u8).collect();
+    Image::from_bytes(&data, (32, 24), ColorSpace::sRGB)
+}
+
+#[test]
+fn operations_are_timed() {
+    // Nothing without a time source
+    image().to_color(ColorSpace::DisplayP3);
+    assert!(take_profile().is_empty());
+
+    set_time_source(&now);
+    let mut img = image();
+    img.to_color(ColorSpace::DisplayP3);
+    img.scale_with((16, 12), ScaleFilter::Bilinear);
+    img.gaussian_blur(1.5);
+    let _ = img.to_raw(PixelFormat::Rgb565Le);
+
+    let report = take_profile();
+    for op in [
+        ProfileOp::ToColor,
+        ProfileOp::Scale,
+        ProfileOp::Convolve,
+        ProfileOp::Export,
+    ] {
+        let entries: Vec<_> = report.entries().filter(|e| e.op == op).collect();
+        assert!(!entries.is_empty(), "{op:?}");
+        // At least its own two reads of the clock apart, and far from wrapped
+        assert!(
+            entries
+                .iter()
+                .all(|e| e.duration >= 10 && e.duration < 1 << 32),
+            "{op:?}"
+        );
+    }
+    // In the order they ran
+    let ops: Vec<ProfileOp> = report.entries().map(|e| e.op).collect();
+    let first = |op| ops.iter().position(|o| *o == op).unwrap();
+    assert!(first(ProfileOp::ToColor) < first(ProfileOp::Scale));
+    assert!(first(ProfileOp::Scale) < first(ProfileOp::Convolve));
+    assert!(first(ProfileOp::Convolve) < first(ProfileOp::Export));
+    // Taking it leaves an empty one
+    assert!(take_profile().is_empty());
+
+    // More than fit keeps the newest
+    let img = image();
+    for _ in 0..PROFILE_CAPACITY + 5 {
+        let _ = img.to_raw(PixelFormat::Rgba8888);
+    }
+    let report = take_profile();
+    assert_eq!(report.len(), PROFILE_CAPACITY);
+    assert!(report.dropped() >= 5);
+    assert!(report.entries().all(|e| e.op == ProfileOp::Export));
+}
@@ -0,0 +1,67 @@
+//! `thumbnail_from_raw` never holds the full size image
+//!
+//! This is its own test binary, so the counting allocator only sees this
+//! test.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use embedded_image::{thumbnail_from_raw, ColorSpace, PixelFormat};
+
+struct Counting;
+
+/// Bytes allocated now, and the most at any one time
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+fn grow(by: usize) {
+    let live = LIVE.fetch_add(by, Ordering::SeqCst) + by;
+    PEAK.fetch_max(live, Ordering::SeqCst);
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        grow(layout.size());
+        // Safety: Forwarded as is
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::SeqCst);
+        // Safety: Forwarded as is
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        LIVE.fetch_sub(layout.size(), Ordering::SeqCst);
+        grow(new_size);
+        // Safety: Forwarded as is
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[test]
+fn thumbnail_holds_a_row_and_the_output() {
+    let res = (1024, 768);
+    let target = (32, 32);
+    for format in [PixelFormat::Rgba8888, PixelFormat::Rgb565Le] {
+        let data: Vec<u8> = (0..res.0 * res.1 * format.bytes_per_pixel() as u32)
+            .map(|i| (i * 7) as u8)
+            .collect();
+        let base = LIVE.load(Ordering::SeqCst);
+        PEAK.store(base, Ordering::SeqCst);
+        let thumb = thumbnail_from_raw(&data, res, format, ColorSpace::sRGB, target).unwrap();
+        assert_eq!(thumb.width(), 32);
+        let used = PEAK.load(Ordering::SeqCst) - base;
+        // The output, the filter weights, and a row or two of floats
+        let pixel = 16;
+        let bound = 32 * 32 * pixel * 2 + res.0 as usize * pixel * 3;
+        let full = res.0 as usize * res.1 as usize * pixel;
+        assert!(used <= bound, "{format:?} {used} > {bound}");
+        assert!(used * 50 < full, "{format:?} {used}");
+    }
+}